@@ -0,0 +1,49 @@
+/*
+ * The C half of a mixed firmware: a tone under a coin and a jump, played
+ * through the ym2149 crate's `ffi` functions. `main.rs` beside this file
+ * has the Rust half and how to build the two together.
+ */
+
+#include <stdio.h>
+
+#include "ym2149.h"
+
+/* Installs the chip, on the Rust side. */
+void rust_audio_start(void);
+
+static int check(const char *call, int32_t status)
+{
+    if (status < 0) {
+        fprintf(stderr, "%s failed: %d\n", call, (int)status);
+        return 1;
+    }
+    return 0;
+}
+
+int main(void)
+{
+    if (ym_init() != YM_STATUS_NOT_INSTALLED) {
+        fprintf(stderr, "ym_init should fail before a chip is installed\n");
+        return 1;
+    }
+    rust_audio_start();
+
+    int failed = check("ym_init", ym_init());
+    failed |= check("ym_play_tone", ym_play_tone(0, 440, 12));
+    if (ym_play_tone(3, 440, 12) != YM_STATUS_INVALID_ARGUMENT) {
+        fprintf(stderr, "channel 3 should be turned away\n");
+        failed = 1;
+    }
+
+    int32_t coin = ym_trigger_sfx(YM_SFX_COIN, 1);
+    int32_t jump = ym_trigger_sfx(YM_SFX_JUMP, 1);
+    failed |= check("ym_trigger_sfx", coin) | check("ym_trigger_sfx", jump);
+    printf("coin on channel %d, jump on channel %d\n", (int)coin, (int)jump);
+
+    /* A second of ticks at 50 Hz, from the main loop. */
+    for (int tick = 0; tick < 50; tick++) {
+        failed |= check("ym_player_tick", ym_player_tick());
+    }
+    failed |= check("ym_stop_all", ym_stop_all());
+    return failed;
+}
@@ -0,0 +1,43 @@
+//! The Rust half of `jingle.c`: a stand-in chip that prints every register
+//! write, handed to the C functions when C asks for it.
+//!
+//! On hardware this is the firmware's Rust side, building a `Ym2149` on its
+//! pins and installing that instead. On the host:
+//!
+//! ```text
+//! cargo build --example ffi --features ffi
+//! cc -Iinclude examples/ffi/jingle.c target/debug/examples/libffi.a -lpthread -ldl -lm -o jingle
+//! ./jingle
+//! ```
+
+use ym2149::{ffi, Psg, Registers};
+
+struct PrintingPsg {
+    registers: Registers,
+}
+
+impl Psg for PrintingPsg {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        println!("R{address:X} <- {data:#04x}");
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        ym2149::tuning::DEFAULT_MASTER_CLOCK
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rust_audio_start() {
+    let chip = Box::leak(Box::new(PrintingPsg {
+        registers: Registers::new(),
+    }));
+    ffi::install(chip);
+}
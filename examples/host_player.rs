@@ -0,0 +1,84 @@
+//! A YM file played live through the emulator and the default audio
+//! device.
+//!
+//! Each frame is queued for the sample it belongs at, a few frames ahead
+//! of what the device is playing, so the timing comes from the audio clock
+//! rather than from how promptly this thread wakes. LHA-packed files, as
+//! most .ym files are, are unpacked first.
+//!
+//! `cargo run --example host_player --features cpal,lha,formats-ym -- song.ym`.
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use ym2149::audio::AudioOutput;
+use ym2149::emulator::{StereoMode, Ym2149Emulator};
+use ym2149::frame_player::PlayStatus;
+use ym2149::lha;
+use ym2149::ym_file::YmSong;
+use ym2149::Psg;
+
+/// Frames queued ahead of the device.
+const AHEAD: u64 = 4;
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .expect("usage: host_player <song.ym>");
+    let data = std::fs::read(&path).expect("couldn't read the song");
+    let data = match lha::entries(&data).next() {
+        Some(Ok(entry)) => {
+            let mut unpacked = vec![0; entry.size() as usize];
+            entry
+                .decompress_into(&mut unpacked)
+                .expect("couldn't unpack the song");
+            unpacked
+        }
+        _ => data,
+    };
+    let song = YmSong::new(&data).expect("not a YM song");
+    println!(
+        "{} by {}",
+        String::from_utf8_lossy(song.name()),
+        String::from_utf8_lossy(song.author())
+    );
+    let frame_rate = u64::from(song.frame_rate());
+    let mut player = song.into_player(song.frame_rate().into());
+
+    let mut emulator = Ym2149Emulator::new();
+    emulator.set_stereo_mode(StereoMode::StereoAbc);
+    emulator.set_dc_filter(true);
+    let mut output = AudioOutput::open(emulator, 64).expect("couldn't open the audio device");
+    let sender = output.sender();
+    let rate = u64::from(sender.sample_rate());
+    let frame_length = rate / frame_rate;
+    // Leave room for the device's buffer before the first frame is due.
+    sleep(Duration::from_millis(50));
+    let start = sender.position() + u64::from(sender.latency()) + frame_length;
+
+    for frame in 0.. {
+        let at = start + frame * rate / frame_rate;
+        while at > sender.position() + u64::from(sender.latency()) + AHEAD * frame_length {
+            sleep(Duration::from_millis(2));
+        }
+        sender.set_time(at);
+        match player.tick(sender) {
+            Ok(PlayStatus::Finished | PlayStatus::Failed(_)) => break,
+            Ok(_) => {}
+            Err(_) => eprintln!("queue full at frame {frame}"),
+        }
+    }
+
+    // Let the last frames and the silence play out.
+    let end = sender.time() + AHEAD * frame_length;
+    while sender.position() < end {
+        sleep(Duration::from_millis(10));
+    }
+    sender.silence().ok();
+    println!(
+        "{} late, {} turned away, {} underruns",
+        sender.late(),
+        sender.overruns(),
+        sender.underruns()
+    );
+}
@@ -0,0 +1,61 @@
+//! A bar of bass, lead and snare using the preset instruments.
+//!
+//! Runs on the host against a stand-in chip that prints every register
+//! write; on hardware the same `play` function takes a `Ym2149`.
+
+use ym2149::instrument::{presets, InstrumentPlayer};
+use ym2149::{Channel, Note, Pitch, Psg, Registers};
+
+struct PrintingPsg {
+    registers: Registers,
+}
+
+impl Psg for PrintingPsg {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        println!("R{address:X} <- {data:#04x}");
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        ym2149::tuning::DEFAULT_MASTER_CLOCK
+    }
+}
+
+/// Ticked at 50 Hz, twelve ticks to a beat.
+fn play<P: Psg>(psg: &mut P) -> Result<(), P::Error> {
+    let mut bass = InstrumentPlayer::new(&presets::BASS);
+    let mut lead = InstrumentPlayer::new(&presets::LEAD);
+    let mut snare = InstrumentPlayer::new(&presets::SNARE);
+    let melody = [Note::E, Note::G, Note::A, Note::G];
+    for (beat, note) in melody.into_iter().enumerate() {
+        bass.note_on(Pitch::new(Note::A, 2));
+        lead.note_on(Pitch::new(note, 5));
+        if beat % 2 == 1 {
+            snare.note_on(Pitch::new(Note::A, 3));
+        }
+        for tick in 0..12 {
+            if tick == 8 {
+                bass.note_off();
+                lead.note_off();
+            }
+            bass.tick(psg, Channel::A)?;
+            lead.tick(psg, Channel::B)?;
+            snare.tick(psg, Channel::C)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut psg = PrintingPsg {
+        registers: Registers::new(),
+    };
+    let Ok(()) = play(&mut psg);
+}
@@ -0,0 +1,52 @@
+//! Surf on a beach: a drifting noise period under a slow swell in volume.
+//!
+//! Runs on the host against a stand-in chip that prints every register
+//! write; on hardware the same `play` function takes a `Ym2149`.
+
+use ym2149::lfo::LfoWaveform;
+use ym2149::noise_lfo::{NoiseLfo, NoiseLfoMode};
+use ym2149::tremolo::Tremolo;
+use ym2149::{Channel, ChannelLevel, Psg, Registers};
+
+struct PrintingPsg {
+    registers: Registers,
+}
+
+impl Psg for PrintingPsg {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        println!("R{address:X} <- {data:#04x}");
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        ym2149::tuning::DEFAULT_MASTER_CLOCK
+    }
+}
+
+/// Ticked at 50 Hz: each wave rolls in and out over about four seconds.
+fn play<P: Psg>(psg: &mut P, ticks: u32) -> Result<(), P::Error> {
+    let mut hiss = NoiseLfo::new(NoiseLfoMode::RandomWalk { max_step: 1 }, 5, 6, 18);
+    let mut swell = Tremolo::new(Channel::C, 9, 200, LfoWaveform::Sine);
+    swell.set_base_level(ChannelLevel::Fixed(12));
+    psg.set_tone_enabled(Channel::C, false)?;
+    psg.set_noise_enabled(Channel::C, true)?;
+    for _ in 0..ticks {
+        hiss.tick(psg)?;
+        swell.tick(psg)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut psg = PrintingPsg {
+        registers: Registers::new(),
+    };
+    let Ok(()) = play(&mut psg, 400);
+}
@@ -0,0 +1,68 @@
+//! A pattern put together at run time, as music downloaded into the heap
+//! would be, played with the preset instruments.
+//!
+//! Needs the `alloc` feature:
+//! `cargo run --example runtime_pattern --features alloc,player`.
+
+use ym2149::instrument::{presets, Instrument};
+use ym2149::sequencer::{Cell, OrderEntry, Pattern, Row, Sequencer, Song};
+use ym2149::storage::Owned;
+use ym2149::{Note, Pitch, Psg, Registers};
+
+struct PrintingPsg {
+    registers: Registers,
+}
+
+impl Psg for PrintingPsg {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        println!("R{address:X} <- {data:#04x}");
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        ym2149::tuning::DEFAULT_MASTER_CLOCK
+    }
+}
+
+/// A rising arpeggio over `root`, one note a row, with the bass on the
+/// first row of every four.
+fn arpeggio(root: Pitch, rows: usize) -> Pattern<Owned> {
+    let steps = [0, 4, 7, 12];
+    let rows = (0..rows)
+        .map(|row| {
+            let lead = Cell::note(root.transpose_folded(steps[row % 4] + 12)).instrument(0);
+            let bass = match row % 4 {
+                0 => Cell::note(root).instrument(1),
+                _ => Cell::EMPTY,
+            };
+            Row::new(lead, bass, Cell::EMPTY)
+        })
+        .collect();
+    Pattern::from_vec(rows)
+}
+
+fn main() {
+    let song = Song::from_vecs(
+        vec![arpeggio(Pitch::new(Note::A, 2), 8)],
+        // The pattern as built, then down a fourth.
+        vec![OrderEntry::new(0), OrderEntry::new(0).transposed(-5)],
+    );
+    let bank: Vec<Instrument<Owned>> = vec![presets::LEAD.into(), presets::BASS.into()];
+    let mut sequencer = Sequencer::<(), Owned>::new_in(song, bank, 50);
+    let mut psg = PrintingPsg {
+        registers: Registers::new(),
+    };
+    loop {
+        let Ok(finished) = sequencer.tick(&mut psg);
+        if finished {
+            break;
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! A YM song streamed from a file: SD card to YM header to frame player.
+//!
+//! The card here is a file in memory that takes a while over each sector,
+//! as a real one does; on hardware it would be an `embedded-sdmmc` `File`,
+//! which reads ahead just the same. The main loop keeps the buffer
+//! topped up between ticks, and reports any tick that caught it short.
+//!
+//! `cargo run --example sd_ym --features sd`.
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use ym2149::frame_player::{Frame, FramePlayer, PlayStatus, SourceError};
+use ym2149::sd_stream::{ReadAhead, SeekableFile};
+use ym2149::ym_file::YmMetadata;
+use ym2149::{Psg, Registers};
+
+const SECTOR: usize = 512;
+
+/// A file on a slow card.
+struct SlowCard {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl SeekableFile for SlowCard {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, SourceError> {
+        sleep(Duration::from_millis(2));
+        let end = ((self.offset / SECTOR + 1) * SECTOR)
+            .min(self.offset + buffer.len())
+            .min(self.data.len());
+        let bytes = self.data.get(self.offset..end).unwrap_or(&[]);
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        self.offset += bytes.len();
+        Ok(bytes.len())
+    }
+
+    fn seek_from_start(&mut self, offset: u32) -> Result<(), SourceError> {
+        self.offset = offset as usize;
+        Ok(())
+    }
+}
+
+struct PrintingPsg {
+    registers: Registers,
+}
+
+impl Psg for PrintingPsg {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        println!("R{address:X} <- {data:#04x}");
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        ym2149::tuning::DEFAULT_MASTER_CLOCK
+    }
+}
+
+/// Two seconds of a YM5 file, frames one after another, sweeping channel
+/// A down and looping back to its second half.
+fn ym_file() -> Vec<u8> {
+    let frames: Vec<Frame> = (0..100u32)
+        .map(|n| {
+            let period = 100 + n as u16 * 4;
+            let [high, low] = period.to_be_bytes();
+            [low, high, 0, 0, 0, 0, 0, 0x3E, 15, 0, 0, 0, 0, 0xFF, 0, 0]
+        })
+        .collect();
+    let mut file = Vec::new();
+    file.extend_from_slice(b"YM5!LeOnArD!");
+    file.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    file.extend_from_slice(&0u32.to_be_bytes());
+    file.extend_from_slice(&0u16.to_be_bytes());
+    file.extend_from_slice(&2_000_000u32.to_be_bytes());
+    file.extend_from_slice(&50u16.to_be_bytes());
+    file.extend_from_slice(&50u32.to_be_bytes());
+    file.extend_from_slice(&0u16.to_be_bytes());
+    file.extend_from_slice(b"Sweep\0Nobody\0Streamed from a card\0");
+    for frame in &frames {
+        file.extend_from_slice(frame);
+    }
+    file.extend_from_slice(b"End!");
+    file
+}
+
+fn main() -> Result<(), SourceError> {
+    let card = SlowCard {
+        data: ym_file(),
+        offset: 0,
+    };
+    let mut bytes = ReadAhead::<_, 1024>::new(card);
+    bytes.fill()?;
+
+    // Everything before the frames fits in the first few hundred bytes.
+    let mut header = [0; 256];
+    let read = bytes.read(&mut header)?;
+    let metadata = YmMetadata::parse(&header[..read]).map_err(|_| SourceError::Corrupt)?;
+    if metadata.is_interleaved() {
+        eprintln!("interleaved songs can't be streamed; save it frame by frame");
+        return Err(SourceError::Corrupt);
+    }
+    println!("{}", String::from_utf8_lossy(metadata.name()));
+
+    let frames = bytes.frames(metadata.frames_at() as u32, Some(metadata.frame_count()));
+    let mut player = FramePlayer::new(frames)
+        .with_loop(metadata.loop_frame())
+        .with_rate(metadata.frame_millihertz(), 50);
+    let mut psg = PrintingPsg {
+        registers: Registers::new(),
+    };
+    player.play();
+    for _ in 0..250 {
+        // The main loop's time between 50 Hz ticks: top up the buffer.
+        player.source_mut().fill()?;
+        let Ok(status) = player.tick(&mut psg);
+        if status == PlayStatus::Underrun {
+            println!("underrun");
+        }
+    }
+    println!("{} underruns", player.source().bytes().underruns());
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Clocking the chip from a 48 MHz MCU timer, and picking the divider that
+//! plays a key most in tune.
+//!
+//! The timer toggles its output pin every `n` cycles of 48 MHz, so the chip
+//! gets 24 MHz / n: 2 MHz exactly at n = 12, and the nearest it can get to
+//! anything else. Runs on the host and prints what each choice costs; on
+//! hardware, the divider goes to the timer and the clock to the driver with
+//! `Ym2149::set_master_clock_from`.
+
+use ym2149::clock_source::{key_error_cents, ClockSource, FnClock, MasterClock};
+use ym2149::{Key, Note, Scale};
+
+const TIMER_HZ: u32 = 48_000_000;
+
+/// The divider making the clock nearest `hz`, one at least.
+fn divider(hz: u32) -> u32 {
+    let half = TIMER_HZ / 2;
+    ((half + hz / 2) / hz.max(1)).max(1)
+}
+
+fn main() {
+    // An AY-3-8910 takes 1 to 2 MHz. A YM2149 takes up to 4 MHz with SEL
+    // held low, which the fastest wins: its periods are the finest.
+    let timer = FnClock::new(1_000_000, 2_000_000, |hz| TIMER_HZ / 2 / divider(hz));
+
+    for requested in [2_000_000, 1_773_400, 1_000_000] {
+        let clock = MasterClock::from_source(&timer, requested);
+        println!(
+            "asked for {requested} Hz: n = {}, {} Hz, {} ppm",
+            divider(requested),
+            clock.hz,
+            clock.error_ppm()
+        );
+    }
+
+    for key in [
+        Key::new(Note::C, Scale::Major),
+        Key::new(Note::A, Scale::NaturalMinor),
+        Key::new(Note::E, Scale::MinorPentatonic),
+    ] {
+        let best = timer.best_clock_for_key(key);
+        println!(
+            "{:?} {:?}: n = {}, {best} Hz, {} cents out in all (2 MHz: {})",
+            key.root,
+            key.scale,
+            divider(best),
+            key_error_cents(best, key),
+            key_error_cents(2_000_000, key)
+        );
+    }
+}
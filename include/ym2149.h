@@ -0,0 +1,88 @@
+/* The C interface to the ym2149 crate's `ffi` feature. Made by cbindgen; do not edit. */
+
+#ifndef YM2149_H
+#define YM2149_H
+
+#include <stdint.h>
+
+// What a call returns: [`Status::Ok`], or why it failed, which is always
+// negative.
+enum YmStatus
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : int32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  YM_STATUS_OK = 0,
+  // No chip has been installed.
+  YM_STATUS_NOT_INSTALLED = -1,
+  // A channel, register, effect or level out of range.
+  YM_STATUS_INVALID_ARGUMENT = -2,
+  // The chip's driver failed to write.
+  YM_STATUS_CHIP_FAILED = -3,
+  // Every channel is playing a more important effect.
+  YM_STATUS_NO_CHANNEL = -4,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum YmStatus YmStatus;
+#else
+typedef int32_t YmStatus;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+// The built-in effects. Pitches and frequencies are given in musical or
+// hertz terms, so every effect sounds the same at any master clock.
+//
+// Each is numbered by its place here, as the C functions in `ffi` take
+// them.
+enum YmSfx
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  YM_SFX_COIN,
+  YM_SFX_JUMP,
+  YM_SFX_LASER_SHOT,
+  YM_SFX_EXPLOSION,
+  YM_SFX_POWER_UP,
+  YM_SFX_HIT,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum YmSfx YmSfx;
+#else
+typedef uint8_t YmSfx;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Silences the chip and forgets every tone and effect played on it.
+YmStatus ym_init(void);
+
+// Writes `value` to register `address`, 0 to 15, as the music: a channel
+// an effect holds takes it once the effect ends.
+YmStatus ym_write_register(uint8_t address, uint8_t value);
+
+// Plays a tone of `hz` on `channel`, 0 to 2 for A to C, at `level`, 0 to
+// 15. Level 0 stops it, leaving the channel free for effects.
+YmStatus ym_play_tone(uint8_t channel, uint32_t hz, uint8_t level);
+
+// Starts effect `sfx`, one of the `YM_SFX_*`, at `priority`, returning the
+// channel it plays on, 0 to 2, or a negative [`Status`].
+int32_t ym_trigger_sfx(uint8_t sfx, uint8_t priority);
+
+// Moves the effects on by a tick. Call it at the rate effects are
+// written for, usually 50 Hz.
+YmStatus ym_player_tick(void);
+
+// Stops every tone and effect, as [`ym_init`] does.
+YmStatus ym_stop_all(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* YM2149_H */
@@ -0,0 +1,239 @@
+//! Per-channel software volume envelopes.
+
+use crate::effect::{ChannelCtx, Effect};
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::{Channel, ChannelLevel};
+
+/// Loudest fixed channel level.
+const MAX_LEVEL: u8 = 15;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdsrStage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// Attack, decay, sustain and release for one voice, stepped once per tick.
+///
+/// Stage lengths are in ticks and sustain is a 4-bit level. Ramps are
+/// straight lines in the chip's level steps, which are logarithmic (about
+/// 3 dB each), so a decay or release fades out exponentially in amplitude the
+/// way a struck or plucked note does. A zero-length stage reaches its target
+/// in a single tick. With a sustain level of 0 the envelope finishes at the
+/// end of the decay without waiting for [`Adsr::note_off`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adsr {
+    attack: u16,
+    decay: u16,
+    sustain: u8,
+    release: u16,
+    cut: Option<u16>,
+    stage: AdsrStage,
+    from: u8,
+    elapsed: u16,
+    level: u8,
+}
+
+impl Adsr {
+    /// `sustain` is clamped to 15.
+    pub const fn new(attack: u16, decay: u16, sustain: u8, release: u16) -> Adsr {
+        Adsr {
+            attack,
+            decay,
+            sustain: if sustain > MAX_LEVEL {
+                MAX_LEVEL
+            } else {
+                sustain
+            },
+            release,
+            cut: None,
+            stage: AdsrStage::Idle,
+            from: 0,
+            elapsed: 0,
+            level: 0,
+        }
+    }
+
+    /// Starts the attack from the current level, so retriggering a note that
+    /// is still sounding (or releasing) doesn't click back to silence.
+    pub fn note_on(&mut self) {
+        self.enter(AdsrStage::Attack);
+    }
+
+    /// Starts the release from the current level. Does nothing once the
+    /// envelope has finished.
+    pub fn note_off(&mut self) {
+        if self.stage != AdsrStage::Idle {
+            self.enter(AdsrStage::Release);
+        }
+    }
+
+    /// Releases over `ticks` ticks instead of the configured release, for a
+    /// voice that is about to be reused for another note.
+    pub fn cut(&mut self, ticks: u16) {
+        if self.stage != AdsrStage::Idle {
+            self.enter(AdsrStage::Release);
+            self.cut = Some(ticks);
+        }
+    }
+
+    pub fn stage(&self) -> AdsrStage {
+        self.stage
+    }
+
+    /// Whether the envelope is still producing sound.
+    pub fn is_active(&self) -> bool {
+        self.stage != AdsrStage::Idle
+    }
+
+    /// The level produced by the last [`Adsr::tick`].
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Advances one tick and returns the level for it.
+    pub fn tick(&mut self) -> u8 {
+        match self.stage {
+            AdsrStage::Idle => self.level = 0,
+            AdsrStage::Sustain => self.level = self.sustain,
+            AdsrStage::Attack => {
+                if self.ramp(MAX_LEVEL, self.attack) {
+                    self.enter(AdsrStage::Decay);
+                }
+            }
+            AdsrStage::Decay => {
+                if self.ramp(self.sustain, self.decay) {
+                    if self.sustain == 0 {
+                        self.enter(AdsrStage::Idle);
+                    } else {
+                        self.enter(AdsrStage::Sustain);
+                    }
+                }
+            }
+            AdsrStage::Release => {
+                if self.ramp(0, self.cut.unwrap_or(self.release)) {
+                    self.enter(AdsrStage::Idle);
+                }
+            }
+        }
+        self.level
+    }
+
+    /// Advances one tick and writes the level to `channel`. Unchanged levels
+    /// are skipped by the register cache.
+    pub fn tick_channel<P: Psg>(&mut self, psg: &mut P, channel: Channel) -> Result<u8, P::Error> {
+        let level = self.tick();
+        psg.update_channel_level(channel, ChannelLevel::Fixed(level))?;
+        Ok(level)
+    }
+
+    fn enter(&mut self, stage: AdsrStage) {
+        self.stage = stage;
+        self.cut = None;
+        self.from = self.level;
+        self.elapsed = 0;
+    }
+
+    /// Moves one tick along the line from the stage's starting level to
+    /// `to`, returning whether it has arrived.
+    fn ramp(&mut self, to: u8, length: u16) -> bool {
+        let length = length.max(1) as i32;
+        self.elapsed += 1;
+        let delta = (to as i32 - self.from as i32) * self.elapsed as i32;
+        // Round half away from zero so short ramps still move every tick.
+        let offset = (2 * delta + delta.signum() * length) / (2 * length);
+        self.level = (self.from as i32 + offset) as u8;
+        self.elapsed as i32 >= length
+    }
+}
+
+/// Contributes the envelope as attenuation from full level, so it scales
+/// whatever base level the chain has.
+impl Effect for Adsr {
+    fn tick(&mut self, ctx: &mut ChannelCtx) {
+        let level = Adsr::tick(self);
+        ctx.add_level(level as i8 - MAX_LEVEL as i8);
+    }
+
+    fn note_on(&mut self, _pitch: Pitch) {
+        Adsr::note_on(self);
+    }
+
+    fn note_off(&mut self) {
+        Adsr::note_off(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+
+    fn run<const N: usize>(adsr: &mut Adsr) -> [u8; N] {
+        core::array::from_fn(|_| adsr.tick())
+    }
+
+    #[test]
+    fn full_cycle() {
+        let mut adsr = Adsr::new(3, 4, 8, 2);
+        adsr.note_on();
+        assert_eq!(run(&mut adsr), [5, 10, 15, 13, 11, 10, 8, 8, 8]);
+        adsr.note_off();
+        assert_eq!(run(&mut adsr), [4, 0, 0]);
+        assert!(!adsr.is_active());
+    }
+
+    #[test]
+    fn zero_length_stages_and_silent_sustain() {
+        const PLUCK: Adsr = Adsr::new(0, 5, 0, 0);
+        let mut adsr = PLUCK;
+        adsr.note_on();
+        assert_eq!(run(&mut adsr), [15, 12, 9, 6, 3, 0, 0]);
+        assert_eq!(adsr.stage(), AdsrStage::Idle);
+
+        let mut organ = Adsr::new(0, 0, 11, 0);
+        organ.note_on();
+        assert_eq!(run(&mut organ), [15, 11, 11]);
+        organ.note_off();
+        assert_eq!(run(&mut organ), [0, 0]);
+    }
+
+    #[test]
+    fn retrigger_during_release_attacks_from_the_current_level() {
+        let mut adsr = Adsr::new(2, 0, 15, 6);
+        adsr.note_on();
+        run::<3>(&mut adsr);
+        adsr.note_off();
+        assert_eq!(run(&mut adsr), [12, 10, 7]);
+        adsr.note_on();
+        assert_eq!(run(&mut adsr), [11, 15, 15]);
+    }
+
+    #[test]
+    fn cut_releases_quickly_once() {
+        let mut adsr = Adsr::new(0, 0, 15, 10);
+        adsr.note_on();
+        run::<2>(&mut adsr);
+        adsr.cut(2);
+        assert_eq!(run(&mut adsr), [7, 0, 0]);
+        adsr.note_on();
+        run::<2>(&mut adsr);
+        adsr.note_off();
+        assert_eq!(run(&mut adsr), [13, 12]);
+    }
+
+    #[test]
+    fn tick_channel_writes_only_changes() {
+        let mut psg = FakePsg::new();
+        let mut adsr = Adsr::new(0, 0, 9, 0);
+        adsr.note_on();
+        for _ in 0..3 {
+            adsr.tick_channel(&mut psg, Channel::C).unwrap();
+        }
+        assert_eq!(psg.take_writes(), [(0xA, 15), (0xA, 9)]);
+    }
+}
@@ -0,0 +1,177 @@
+//! What each channel is doing, read off the registers, for level meters and
+//! LED bars that move with the music.
+//!
+//! Everything comes from a [`Registers`] snapshot and the master clock,
+//! apart from the envelope: how loud a channel on the envelope is depends on
+//! how long ago R13 last restarted it, which the registers can't say. The
+//! caller gives that time, and gets the level the envelope's shape would
+//! have reached by then. The chip's counters don't run from the write, and
+//! the bus takes time, so this is an estimate, but the same inputs always
+//! give the same answer. [`FramePlayer::last_analysis`] keeps the time for
+//! the frames it plays.
+//!
+//! [`FramePlayer::last_analysis`]: crate::frame_player::FramePlayer::last_analysis
+
+use crate::registers::Registers;
+use crate::tuning;
+use crate::{Channel, EnvelopeShape};
+
+/// One channel's part of a [`FrameAnalysis`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelAnalysis {
+    /// How loud the channel is, 0 to 15, estimated when on the envelope. A
+    /// channel with neither tone nor noise plays a flat level, which is
+    /// heard only when it changes.
+    pub level: u8,
+    pub envelope: bool,
+    pub tone: bool,
+    pub noise: bool,
+    /// The frequency of the tone period, whether or not the tone is on.
+    pub tone_millihertz: u32,
+}
+
+/// Each channel's level and pitch, and the noise's rate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameAnalysis {
+    pub channels: [ChannelAnalysis; 3],
+    /// How often the noise generator picks a new value.
+    pub noise_hertz: u32,
+}
+
+impl FrameAnalysis {
+    /// The analysis of `registers` on a chip clocked at `clock`, with the
+    /// envelope restarted `envelope_micros` microseconds ago.
+    pub fn new(registers: &Registers, clock: u32, envelope_micros: u64) -> FrameAnalysis {
+        let mixer = registers.mixer();
+        let envelope = envelope_level(
+            EnvelopeShape::from_bits_truncate(registers.value(0xD)),
+            registers.value(0xB) as u16 | (registers.value(0xC) as u16) << 8,
+            clock,
+            envelope_micros,
+        );
+        let channels = Channel::ALL.map(|channel| {
+            let value = registers.value(channel.level_register());
+            let on_envelope = value & 0x10 != 0;
+            let bit = 1 << channel.index();
+            ChannelAnalysis {
+                level: if on_envelope { envelope } else { value & 0xF },
+                envelope: on_envelope,
+                tone: mixer & bit == 0,
+                noise: mixer & bit << 3 == 0,
+                tone_millihertz: tuning::period_to_millihertz(
+                    clock,
+                    registers.tone_period(channel),
+                ),
+            }
+        });
+        let noise_period = (registers.value(0x6) & 0x1F).max(1) as u32;
+        FrameAnalysis {
+            channels,
+            noise_hertz: clock / (16 * noise_period),
+        }
+    }
+
+    pub fn channel(&self, channel: Channel) -> &ChannelAnalysis {
+        &self.channels[channel.index()]
+    }
+}
+
+/// The level, 0 to 15, an envelope of `shape` and `period` has got to
+/// `micros` microseconds after it was started, at `clock`.
+///
+/// Each ramp is 32 steps of `8 * period` clock cycles, as on the YM2149,
+/// and its 5-bit steps are halved to the 4 bits of a fixed level.
+pub fn envelope_level(shape: EnvelopeShape, period: u16, clock: u32, micros: u64) -> u8 {
+    let step_cycles = 8 * period.max(1) as u64;
+    let step = micros.saturating_mul(clock as u64) / 1_000_000 / step_cycles;
+    let (ramp, within) = (step / 32, (step % 32) as u8);
+    let attack = shape.contains(EnvelopeShape::Att);
+    let rising = if !shape.contains(EnvelopeShape::cont) {
+        // One ramp, then silence.
+        if ramp > 0 {
+            return 0;
+        }
+        attack
+    } else if shape.contains(EnvelopeShape::Hold) {
+        // One ramp, then held at its end, or its start if alternating.
+        if ramp > 0 {
+            return if attack != shape.contains(EnvelopeShape::Alt) {
+                15
+            } else {
+                0
+            };
+        }
+        attack
+    } else {
+        attack != (shape.contains(EnvelopeShape::Alt) && ramp % 2 == 1)
+    };
+    let value = if rising { within } else { 31 - within };
+    value >> 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuning::DEFAULT_MASTER_CLOCK;
+
+    /// At the default clock and period 250, each 5-bit step takes 1 ms.
+    fn levels(shape: u8) -> [u8; 6] {
+        let shape = EnvelopeShape::from_bits_truncate(shape);
+        [0, 10, 31, 32, 50, 70]
+            .map(|millis| envelope_level(shape, 250, DEFAULT_MASTER_CLOCK, millis * 1000))
+    }
+
+    #[test]
+    fn envelope_shapes_reach_the_right_levels() {
+        // \___ and /___.
+        assert_eq!(levels(0x0), [15, 10, 0, 0, 0, 0]);
+        assert_eq!(levels(0x4), [0, 5, 15, 0, 0, 0]);
+        // Sawtooths, \|\| and /|/|.
+        assert_eq!(levels(0x8), [15, 10, 0, 15, 6, 12]);
+        assert_eq!(levels(0xC), [0, 5, 15, 0, 9, 3]);
+        // Triangles, \/\/ and /\/\.
+        assert_eq!(levels(0xA), [15, 10, 0, 0, 9, 12]);
+        assert_eq!(levels(0xE), [0, 5, 15, 15, 6, 3]);
+        // Held: \¯¯¯ and /¯¯¯, then \___ and /___ again.
+        assert_eq!(levels(0xB), [15, 10, 0, 15, 15, 15]);
+        assert_eq!(levels(0xD), [0, 5, 15, 15, 15, 15]);
+        assert_eq!(levels(0x9), [15, 10, 0, 0, 0, 0]);
+        assert_eq!(levels(0xF), [0, 5, 15, 0, 0, 0]);
+    }
+
+    #[test]
+    fn channels_are_read_off_the_registers() {
+        let mut values = [0; 16];
+        // A: A4 at level 12, tone only.
+        values[0x0] = 0x1C;
+        values[0x1] = 0x01;
+        values[0x8] = 12;
+        // B: on the envelope, tone and noise. C: noise only, at level 5.
+        values[0x9] = 0x10;
+        values[0xA] = 5;
+        values[0x6] = 25;
+        values[0x7] = 0b0000_1100;
+        values[0xB] = 250;
+        values[0xD] = 0x8;
+        let registers = Registers::from_values(values);
+        let analysis = FrameAnalysis::new(&registers, DEFAULT_MASTER_CLOCK, 10_000);
+        assert_eq!(
+            *analysis.channel(Channel::A),
+            ChannelAnalysis {
+                level: 12,
+                envelope: false,
+                tone: true,
+                noise: false,
+                tone_millihertz: 440_141,
+            }
+        );
+        let b = analysis.channel(Channel::B);
+        assert_eq!(
+            (b.level, b.envelope, b.tone, b.noise),
+            (10, true, true, true)
+        );
+        let c = analysis.channel(Channel::C);
+        assert_eq!((c.level, c.tone, c.noise), (5, false, true));
+        assert_eq!(analysis.noise_hertz, 5000);
+    }
+}
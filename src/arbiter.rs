@@ -0,0 +1,317 @@
+//! Lending channels from the music to sound effects and taking them back.
+//!
+//! Music code (frame players, sequencers, anything generic over [`Psg`])
+//! writes through [`SfxArbiter::music`] instead of the chip. While an effect
+//! holds a channel, the music's writes to that channel's period and level
+//! registers, and its mixer bits, only update the music's own shadow copy.
+//! When the effect ends the channel is put back to whatever the music last
+//! asked for, so music and effects never need to know about each other.
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::sfx::{Sfx, SfxPlayer};
+use crate::tuning::FrequencyPolicy;
+use crate::{Channel, ChipKind};
+
+/// Where a new effect may go.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SfxPolicy {
+    /// Only channels the music isn't using.
+    FreeOnly,
+    /// A free channel if there is one, otherwise the music channel with the
+    /// lowest priority, the quietest breaking ties.
+    StealMusic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slot {
+    player: SfxPlayer,
+    priority: u8,
+}
+
+/// Routes music and sound effects onto one chip.
+///
+/// A new effect interrupts a playing one only when every usable channel is
+/// busy with effects and its priority is at least that of the weakest of
+/// them, so repeated triggers of the same effect restart it.
+///
+/// The noise period is shared, so music writes to it are held back while
+/// any effect plays and replayed afterwards.
+pub struct SfxArbiter<P> {
+    psg: P,
+    policy: SfxPolicy,
+    music: Registers,
+    music_priority: [Option<u8>; 3],
+    slots: [Option<Slot>; 3],
+}
+
+impl<P: Psg> SfxArbiter<P> {
+    pub fn new(psg: P, policy: SfxPolicy) -> SfxArbiter<P> {
+        SfxArbiter {
+            psg,
+            policy,
+            music: Registers::new(),
+            music_priority: [None; 3],
+            slots: [None, None, None],
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.psg
+    }
+
+    /// Stops every effect and forgets what the music played, marking every
+    /// channel free, and silences the chip.
+    pub fn reset(&mut self) -> Result<(), P::Error> {
+        self.slots = [None, None, None];
+        self.music = Registers::new();
+        self.music_priority = [None; 3];
+        self.psg.silence()
+    }
+
+    /// The chip, bypassing the arbitration.
+    pub fn psg(&mut self) -> &mut P {
+        &mut self.psg
+    }
+
+    /// Marks `channel` as used by the music with `priority`, or as free with
+    /// `None`. Lower-priority music channels are stolen first.
+    pub fn set_music_channel(&mut self, channel: Channel, priority: Option<u8>) {
+        self.music_priority[channel.index()] = priority;
+    }
+
+    /// The interface for music to write through.
+    pub fn music(&mut self) -> MusicPort<'_, P> {
+        MusicPort { arbiter: self }
+    }
+
+    /// Priority of the effect playing on `channel`, if any.
+    pub fn sfx_priority(&self, channel: Channel) -> Option<u8> {
+        self.slots[channel.index()]
+            .as_ref()
+            .map(|slot| slot.priority)
+    }
+
+    fn stolen(&self, channel: Channel) -> bool {
+        self.slots[channel.index()].is_some()
+    }
+
+    fn any_sfx(&self) -> bool {
+        self.slots.iter().any(Option::is_some)
+    }
+
+    /// Starts `sfx`, returning the channel chosen, or `None` if every
+    /// usable channel is taken by a more important effect.
+    pub fn trigger_sfx(&mut self, sfx: Sfx, priority: u8) -> Option<Channel> {
+        let channel = self.pick_channel(priority)?;
+        let mut player = SfxPlayer::new();
+        player.trigger(sfx, channel);
+        self.slots[channel.index()] = Some(Slot { player, priority });
+        Some(channel)
+    }
+
+    fn pick_channel(&self, priority: u8) -> Option<Channel> {
+        let idle = |channel: &Channel| !self.stolen(*channel);
+        if let Some(free) = Channel::ALL
+            .into_iter()
+            .filter(idle)
+            .find(|channel| self.music_priority[channel.index()].is_none())
+        {
+            return Some(free);
+        }
+        if self.policy == SfxPolicy::StealMusic {
+            let quietness = |channel: &Channel| {
+                let level = self.music.value(channel.level_register());
+                (self.music_priority[channel.index()], level)
+            };
+            if let Some(music) = Channel::ALL.into_iter().filter(idle).min_by_key(quietness) {
+                return Some(music);
+            }
+        }
+        // Everything usable is playing an effect: replace the weakest.
+        let usable = |channel: &Channel| {
+            self.policy == SfxPolicy::StealMusic || self.music_priority[channel.index()].is_none()
+        };
+        Channel::ALL
+            .into_iter()
+            .filter(usable)
+            .filter_map(|channel| Some((channel, self.slots[channel.index()].as_ref()?.priority)))
+            .min_by_key(|&(_, playing)| playing)
+            .filter(|&(_, playing)| priority >= playing)
+            .map(|(channel, _)| channel)
+    }
+
+    /// Advances every effect, handing channels back to the music as their
+    /// effects end.
+    pub fn tick(&mut self) -> Result<(), P::Error> {
+        for channel in Channel::ALL {
+            let Some(slot) = self.slots[channel.index()].as_mut() else {
+                continue;
+            };
+            if slot.player.tick(&mut self.psg)? {
+                self.slots[channel.index()] = None;
+                self.restore(channel)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replays the music's view of `channel` onto the chip.
+    fn restore(&mut self, channel: Channel) -> Result<(), P::Error> {
+        let (fine, rough) = channel.period_registers();
+        for address in [fine, rough, channel.level_register()] {
+            if let Some(value) = self.music.get(address) {
+                self.psg.update_register(address, value)?;
+            }
+        }
+        if self.music.is_written(0x7) {
+            let bits = (1 | 8) << channel.index();
+            let mixer = (self.psg.registers().mixer() & !bits) | (self.music.mixer() & bits);
+            self.psg.update_register(0x7, mixer)?;
+        }
+        if !self.any_sfx() {
+            if let Some(noise) = self.music.get(0x6) {
+                self.psg.update_register(0x6, noise)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The music's side of an [`SfxArbiter`]: a [`Psg`] whose shadow registers
+/// are the music's, not the chip's.
+pub struct MusicPort<'a, P> {
+    arbiter: &'a mut SfxArbiter<P>,
+}
+
+impl<P: Psg> Psg for MusicPort<'_, P> {
+    type Error = P::Error;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), P::Error> {
+        let arbiter = &mut *self.arbiter;
+        arbiter.music.set(address, data);
+        match address {
+            0x6 if arbiter.any_sfx() => Ok(()),
+            0x7 => {
+                // Keep the effect channels' mixer bits as the effects left them.
+                let stolen = Channel::ALL
+                    .into_iter()
+                    .filter(|channel| arbiter.stolen(*channel))
+                    .fold(0, |bits, channel| bits | (1 | 8) << channel.index());
+                let mixer = (data & !stolen) | (arbiter.psg.registers().mixer() & stolen);
+                arbiter.psg.set_register_value(address, mixer)
+            }
+            _ => match Channel::for_register(address) {
+                Some(channel) if arbiter.stolen(channel) => Ok(()),
+                _ => arbiter.psg.set_register_value(address, data),
+            },
+        }
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.arbiter.music
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.arbiter.psg.master_clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.arbiter.psg.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.arbiter.psg.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::ChannelLevel;
+
+    fn play_out<P: Psg>(arbiter: &mut SfxArbiter<P>) {
+        for _ in 0..100 {
+            assert!(arbiter.tick().is_ok());
+        }
+    }
+
+    #[test]
+    fn free_channels_are_preferred() {
+        let mut arbiter = SfxArbiter::new(FakePsg::new(), SfxPolicy::StealMusic);
+        arbiter.set_music_channel(Channel::A, Some(1));
+        arbiter.set_music_channel(Channel::B, Some(1));
+        assert_eq!(arbiter.trigger_sfx(Sfx::Coin, 0), Some(Channel::C));
+    }
+
+    #[test]
+    fn stolen_channel_is_shadowed_then_restored() {
+        let mut arbiter = SfxArbiter::new(FakePsg::new(), SfxPolicy::StealMusic);
+        for channel in Channel::ALL {
+            arbiter.set_music_channel(channel, Some(1));
+            arbiter
+                .music()
+                .update_channel_level(channel, ChannelLevel::Fixed(12))
+                .unwrap();
+        }
+        arbiter.set_music_channel(Channel::B, Some(0));
+        arbiter.music().set_tone_enabled(Channel::B, true).unwrap();
+        assert_eq!(arbiter.trigger_sfx(Sfx::Jump, 5), Some(Channel::B));
+        arbiter.tick().unwrap();
+        arbiter.psg().take_writes();
+
+        // The music moves on while the effect plays: only channel A's write
+        // reaches the chip.
+        let mut music = arbiter.music();
+        music.set_channel_period(Channel::B, 0x345).unwrap();
+        music
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(9))
+            .unwrap();
+        music
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(10))
+            .unwrap();
+        assert_eq!(arbiter.psg().take_writes(), [(0x8, 10)]);
+
+        play_out(&mut arbiter);
+        let registers = *arbiter.psg().registers();
+        assert_eq!(registers.tone_period(Channel::B), 0x345);
+        assert_eq!(registers.value(0x9), 9);
+        assert_eq!(registers.mixer() & 0b0001_0010, 0b0001_0000);
+        assert_eq!(arbiter.sfx_priority(Channel::B), None);
+    }
+
+    #[test]
+    fn mixer_writes_keep_the_effect_bits() {
+        let mut arbiter = SfxArbiter::new(FakePsg::new(), SfxPolicy::FreeOnly);
+        arbiter.set_music_channel(Channel::A, Some(0));
+        arbiter.set_music_channel(Channel::B, Some(0));
+        arbiter.trigger_sfx(Sfx::Explosion, 0);
+        arbiter.tick().unwrap();
+        // The explosion has C on noise only.
+        assert_eq!(arbiter.psg().registers().mixer() & 0b0010_0100, 0b0000_0100);
+        arbiter
+            .music()
+            .set_register_value(0x7, 0b0011_1100)
+            .unwrap();
+        assert_eq!(arbiter.psg().registers().mixer(), 0b0001_1100);
+        // Noise period writes wait for the effect to finish.
+        arbiter.music().set_register_value(0x6, 0x1F).unwrap();
+        assert_ne!(arbiter.psg().registers().value(0x6), 0x1F);
+        play_out(&mut arbiter);
+        assert_eq!(arbiter.psg().registers().value(0x6), 0x1F);
+        assert_eq!(arbiter.psg().registers().mixer(), 0b0011_1100);
+    }
+
+    #[test]
+    fn priorities_decide_interruptions() {
+        let mut arbiter = SfxArbiter::new(FakePsg::new(), SfxPolicy::FreeOnly);
+        arbiter.set_music_channel(Channel::A, Some(0));
+        arbiter.set_music_channel(Channel::B, Some(0));
+        assert_eq!(arbiter.trigger_sfx(Sfx::Explosion, 5), Some(Channel::C));
+        // The music channels are off limits and the explosion matters more.
+        assert_eq!(arbiter.trigger_sfx(Sfx::Coin, 2), None);
+        assert_eq!(arbiter.trigger_sfx(Sfx::Hit, 5), Some(Channel::C));
+        assert_eq!(arbiter.sfx_priority(Channel::C), Some(5));
+    }
+}
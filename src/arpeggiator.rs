@@ -0,0 +1,326 @@
+//! Chords faked on a single channel by cycling through their notes.
+
+use crate::effect::{ChannelCtx, Effect};
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{FrequencyError, FrequencyPolicy};
+use crate::Channel;
+
+/// Longest pattern an [`ArpPattern`] holds.
+pub const MAX_ARP_STEPS: usize = 8;
+
+/// Up to [`MAX_ARP_STEPS`] semitone offsets from the held note.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ArpPattern {
+    offsets: [i8; MAX_ARP_STEPS],
+    sorted: [i8; MAX_ARP_STEPS],
+    len: u8,
+}
+
+impl ArpPattern {
+    /// Builds a pattern from the first [`MAX_ARP_STEPS`] offsets given.
+    pub const fn new(offsets: &[i8]) -> ArpPattern {
+        let len = if offsets.len() > MAX_ARP_STEPS {
+            MAX_ARP_STEPS
+        } else {
+            offsets.len()
+        };
+        let mut pattern = ArpPattern {
+            offsets: [0; MAX_ARP_STEPS],
+            sorted: [0; MAX_ARP_STEPS],
+            len: len as u8,
+        };
+        let mut i = 0;
+        while i < len {
+            pattern.offsets[i] = offsets[i];
+            pattern.sorted[i] = offsets[i];
+            i += 1;
+        }
+        // Insertion sort, since `sort` isn't usable in const fn.
+        let mut i = 1;
+        while i < len {
+            let mut j = i;
+            while j > 0 && pattern.sorted[j - 1] > pattern.sorted[j] {
+                let swap = pattern.sorted[j - 1];
+                pattern.sorted[j - 1] = pattern.sorted[j];
+                pattern.sorted[j] = swap;
+                j -= 1;
+            }
+            i += 1;
+        }
+        pattern
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The offsets in the order given.
+    pub fn offsets(&self) -> &[i8] {
+        &self.offsets[..self.len()]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArpDirection {
+    /// Lowest to highest offset.
+    Up,
+    /// Highest to lowest offset.
+    Down,
+    /// Up then back down, without repeating the top and bottom notes.
+    UpDown,
+    /// The offsets in the order given.
+    AsEntered,
+    /// A pseudo-random offset each step.
+    Random,
+}
+
+/// Cycles a held note through a pattern of offsets, changing step every
+/// `speed` ticks.
+///
+/// [`Arpeggiator::next_pitch`] just produces the pitch for each tick so it can
+/// feed vibrato, detune or an instrument; [`Arpeggiator::tick`] writes it
+/// straight to a channel. Only pitch is handled: level and mixer are up to
+/// the caller. Offsets reaching beyond the MIDI range move back by octaves,
+/// and the period write follows the chip's frequency policy, by default
+/// folding anything it can't reach at its clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arpeggiator {
+    pattern: ArpPattern,
+    direction: ArpDirection,
+    speed: u16,
+    base: Option<Pitch>,
+    step: u16,
+    countdown: u16,
+    index: usize,
+    seed: u32,
+}
+
+impl Arpeggiator {
+    /// A `speed` of 0 is treated as 1.
+    pub const fn new(pattern: ArpPattern, direction: ArpDirection, speed: u16) -> Arpeggiator {
+        Arpeggiator {
+            pattern,
+            direction,
+            speed,
+            base: None,
+            step: 0,
+            countdown: 0,
+            index: 0,
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    pub fn set_pattern(&mut self, pattern: ArpPattern) {
+        self.pattern = pattern;
+        self.step = 0;
+    }
+
+    pub fn set_direction(&mut self, direction: ArpDirection) {
+        self.direction = direction;
+    }
+
+    pub fn set_speed(&mut self, speed: u16) {
+        self.speed = speed;
+    }
+
+    /// Starts the pattern from its first step on `base`.
+    pub fn note_on(&mut self, base: Pitch) {
+        self.base = Some(base);
+        self.step = 0;
+        self.countdown = 0;
+    }
+
+    pub fn note_off(&mut self) {
+        self.base = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.base.is_some()
+    }
+
+    /// The pitch for this tick, or `None` while no note is held.
+    pub fn next_pitch(&mut self) -> Option<Pitch> {
+        let base = self.base?;
+        let len = self.pattern.len();
+        if len == 0 {
+            return Some(base);
+        }
+        if self.countdown == 0 {
+            self.index = self.index_for(self.step as usize, len);
+            self.step = self.step.wrapping_add(1);
+            self.countdown = self.speed.max(1);
+        }
+        self.countdown -= 1;
+        let offset = match self.direction {
+            ArpDirection::AsEntered | ArpDirection::Random => self.pattern.offsets[self.index],
+            _ => self.pattern.sorted[self.index],
+        };
+        Some(base.transpose_folded(offset))
+    }
+
+    fn index_for(&mut self, step: usize, len: usize) -> usize {
+        match self.direction {
+            ArpDirection::Up | ArpDirection::AsEntered => step % len,
+            ArpDirection::Down => len - 1 - step % len,
+            ArpDirection::UpDown => {
+                if len == 1 {
+                    return 0;
+                }
+                let position = step % (2 * len - 2);
+                if position < len {
+                    position
+                } else {
+                    2 * len - 2 - position
+                }
+            }
+            ArpDirection::Random => {
+                let mut x = self.seed;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.seed = x;
+                x as usize % len
+            }
+        }
+    }
+
+    /// Writes this tick's pitch to `channel` under the chip's
+    /// [`Psg::frequency_policy`].
+    pub fn tick<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+    ) -> Result<(), FrequencyError<P::Error>> {
+        let policy = psg.frequency_policy();
+        self.tick_with(psg, channel, policy)
+    }
+
+    /// [`Arpeggiator::tick`] under `policy`. A step the policy refuses
+    /// leaves the channel on the one before, and still counts as played.
+    pub fn tick_with<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+        policy: FrequencyPolicy,
+    ) -> Result<(), FrequencyError<P::Error>> {
+        if let Some(pitch) = self.next_pitch() {
+            psg.set_channel_pitch_with(channel, pitch, policy)?;
+        }
+        Ok(())
+    }
+}
+
+impl Effect for Arpeggiator {
+    fn tick(&mut self, ctx: &mut ChannelCtx) {
+        let Some(base) = self.base else {
+            return;
+        };
+        if let Some(pitch) = self.next_pitch() {
+            ctx.add_cents((pitch.midi() as i32 - base.midi() as i32) * 100);
+        }
+    }
+
+    fn note_on(&mut self, pitch: Pitch) {
+        Arpeggiator::note_on(self, pitch);
+    }
+
+    fn note_off(&mut self) {
+        Arpeggiator::note_off(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    const MAJOR_SEVENTH: ArpPattern = ArpPattern::new(&[0, 7, 4, 11]);
+
+    fn sequence(direction: ArpDirection, speed: u16, ticks: usize) -> Vec<u8> {
+        let mut arp = Arpeggiator::new(MAJOR_SEVENTH, direction, speed);
+        arp.note_on(Pitch::new(Note::C, 4));
+        (0..ticks)
+            .map(|_| arp.next_pitch().unwrap().midi())
+            .collect()
+    }
+
+    #[test]
+    fn directions() {
+        assert_eq!(sequence(ArpDirection::Up, 1, 6), [60, 64, 67, 71, 60, 64]);
+        assert_eq!(sequence(ArpDirection::Down, 1, 6), [71, 67, 64, 60, 71, 67]);
+        assert_eq!(
+            sequence(ArpDirection::UpDown, 1, 8),
+            [60, 64, 67, 71, 67, 64, 60, 64]
+        );
+        assert_eq!(
+            sequence(ArpDirection::AsEntered, 1, 5),
+            [60, 67, 64, 71, 60]
+        );
+    }
+
+    #[test]
+    fn random_stays_within_the_pattern() {
+        let notes = sequence(ArpDirection::Random, 1, 64);
+        assert!(notes.iter().all(|n| [60, 64, 67, 71].contains(n)));
+        assert!(notes.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn speed_holds_each_step() {
+        assert_eq!(sequence(ArpDirection::Up, 2, 6), [60, 60, 64, 64, 67, 67]);
+    }
+
+    #[test]
+    fn offsets_past_the_top_fold_down() {
+        let mut arp = Arpeggiator::new(ArpPattern::new(&[0, 12]), ArpDirection::Up, 1);
+        arp.note_on(Pitch::new(Note::C, 9));
+        assert_eq!(arp.next_pitch(), Some(Pitch::new(Note::C, 9)));
+        assert_eq!(arp.next_pitch(), Some(Pitch::new(Note::C, 9)));
+    }
+
+    #[test]
+    fn tick_writes_and_note_off_stops() {
+        let mut psg = FakePsg::new();
+        let mut arp = Arpeggiator::new(ArpPattern::new(&[0, 12]), ArpDirection::Up, 1);
+        arp.note_on(Pitch::new(Note::A, 4));
+        arp.tick(&mut psg, Channel::B).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::B), 284);
+        arp.tick(&mut psg, Channel::B).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::B), 142);
+        arp.note_off();
+        psg.take_writes();
+        arp.tick(&mut psg, Channel::B).unwrap();
+        assert!(psg.writes.is_empty());
+    }
+
+    #[test]
+    fn steps_the_policy_refuses_leave_the_last_one_playing() {
+        use crate::tuning::{FrequencyError, OutOfRange};
+
+        // B0 fits the registers at 2 MHz, and A#0 below it doesn't.
+        let mut psg = FakePsg::new();
+        let mut arp = Arpeggiator::new(ArpPattern::new(&[0, -1]), ArpDirection::AsEntered, 1);
+        arp.note_on(Pitch::new(Note::B, 0));
+        let refuse = FrequencyPolicy::default().with_out_of_range(OutOfRange::Error);
+        arp.tick_with(&mut psg, Channel::A, refuse).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::A), 4050);
+        assert_eq!(
+            arp.tick_with(&mut psg, Channel::A, refuse),
+            Err(FrequencyError::OutOfRange)
+        );
+        assert_eq!(psg.registers().tone_period(Channel::A), 4050);
+        // By default it is folded up an octave.
+        arp.tick(&mut psg, Channel::A).unwrap();
+        arp.tick(&mut psg, Channel::A).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::A), 2145);
+    }
+}
@@ -0,0 +1,549 @@
+//! Live playback through the emulator: register writes made on one thread,
+//! heard from an audio callback on another at the sample they were meant
+//! for.
+//!
+//! [`channel`] splits an emulator into an [`AudioSender`], a [`Psg`] for
+//! the thread doing the playing, and an [`AudioRenderer`] for the audio
+//! callback. Writes go through a lock-free queue, each stamped with the
+//! sample it is due at, set with [`AudioSender::set_time`], and the
+//! renderer renders up to that sample before making it. Neither side waits
+//! for the other: a full queue turns a write away and counts an overrun,
+//! and a write arriving after its sample has been rendered is made at the
+//! next one and counted as late, the sign the sender should schedule
+//! further ahead. [`AudioSender::latency`] says how far: the samples
+//! between a write being queued and its sample being rendered, and beyond
+//! that heard, as the backend last reported.
+//!
+//! With the `cpal` feature, `AudioOutput` runs the renderer from the
+//! default output device's callback; `examples/host_player.rs` plays a YM
+//! file through it.
+
+use std::boxed::Box;
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::vec::Vec;
+
+use crate::emulator::Ym2149Emulator;
+use crate::frame_player::Frame;
+use crate::frame_queue::QueueFull;
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::ChipKind;
+
+/// A write queued for the audio callback.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// A value for a register.
+    Write(u8, u8),
+    /// A whole frame, written as [`Psg::write_frame`] writes one.
+    Frame(Frame),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Timed {
+    at: u64,
+    command: Command,
+}
+
+/// What the two ends share.
+struct Shared {
+    slots: Box<[UnsafeCell<Timed>]>,
+    /// Commands taken so far, written only by the renderer.
+    head: AtomicUsize,
+    /// Commands queued so far, written only by the sender.
+    tail: AtomicUsize,
+    /// Samples rendered so far.
+    position: AtomicU64,
+    /// Samples from queueing to hearing, as last reported.
+    latency: AtomicU32,
+    overruns: AtomicU32,
+    late: AtomicU32,
+    underruns: AtomicU32,
+}
+
+// The sender only writes slots the renderer has finished with, and the
+// renderer only reads slots the sender has published, as `head` and `tail`
+// record. `channel` hands out one of each.
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+}
+
+/// Bumps a counter only one side writes.
+fn count(counter: &AtomicU32) {
+    counter.store(
+        counter.load(Ordering::Relaxed).wrapping_add(1),
+        Ordering::Relaxed,
+    );
+}
+
+/// Splits `emulator` into the two ends of a queue with room for `capacity`
+/// writes, rendering at `sample_rate`.
+pub fn channel(
+    emulator: Ym2149Emulator,
+    sample_rate: u32,
+    capacity: usize,
+) -> (AudioSender, AudioRenderer) {
+    let empty = Timed {
+        at: 0,
+        command: Command::Write(0, 0),
+    };
+    let shared = Arc::new(Shared {
+        slots: (0..capacity.max(1))
+            .map(|_| UnsafeCell::new(empty))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        position: AtomicU64::new(0),
+        latency: AtomicU32::new(0),
+        overruns: AtomicU32::new(0),
+        late: AtomicU32::new(0),
+        underruns: AtomicU32::new(0),
+    });
+    let sender = AudioSender {
+        shared: shared.clone(),
+        registers: *emulator.registers(),
+        master_clock: emulator.master_clock(),
+        chip: emulator.chip_kind(),
+        sample_rate,
+        time: 0,
+    };
+    let renderer = AudioRenderer {
+        shared,
+        emulator,
+        sample_rate,
+        mono: Vec::new(),
+        stereo: Vec::new(),
+    };
+    (sender, renderer)
+}
+
+/// The playing thread's end of a [`channel`], writing to the emulator as
+/// a [`Psg`] at the time set.
+pub struct AudioSender {
+    shared: Arc<Shared>,
+    /// What the emulator will have once the queue is through.
+    registers: Registers,
+    master_clock: u32,
+    chip: ChipKind,
+    sample_rate: u32,
+    time: u64,
+}
+
+impl AudioSender {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Stamps writes from now on as due at sample `at`.
+    pub fn set_time(&mut self, at: u64) {
+        self.time = at;
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Samples the renderer has rendered.
+    pub fn position(&self) -> u64 {
+        self.shared.position.load(Ordering::Acquire)
+    }
+
+    /// Samples between rendering and hearing, as the renderer's buffer and
+    /// the backend last had it. Writes due less than this far ahead of
+    /// [`AudioSender::position`] are likely to be late.
+    pub fn latency(&self) -> u32 {
+        self.shared.latency.load(Ordering::Relaxed)
+    }
+
+    /// Writes waiting to be made.
+    pub fn queued(&self) -> usize {
+        self.shared.len()
+    }
+
+    /// Queues `command` for sample `at`, or counts an overrun if the queue
+    /// is full. Times earlier than the last queued are made in the order
+    /// queued, as soon as they can be.
+    pub fn send(&mut self, at: u64, command: Command) -> Result<(), QueueFull> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let capacity = shared.slots.len();
+        if tail.wrapping_sub(shared.head.load(Ordering::Acquire)) >= capacity {
+            count(&shared.overruns);
+            return Err(QueueFull);
+        }
+        // SAFETY: the slot is outside head..tail, so the renderer isn't
+        // reading it, and only this sender writes.
+        unsafe {
+            *shared.slots[tail % capacity].get() = Timed { at, command };
+        }
+        shared.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Writes turned away by a full queue.
+    pub fn overruns(&self) -> u32 {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Writes that arrived after their sample had been rendered.
+    pub fn late(&self) -> u32 {
+        self.shared.late.load(Ordering::Relaxed)
+    }
+
+    /// Times the backend ran short of samples, as reported to
+    /// [`AudioRenderer::count_underrun`].
+    pub fn underruns(&self) -> u32 {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+}
+
+impl Psg for AudioSender {
+    type Error = QueueFull;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), QueueFull> {
+        self.send(self.time, Command::Write(address, data))?;
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.chip
+    }
+
+    /// Queues the whole frame as one write, so it can't be split by a full
+    /// queue or land across two samples.
+    fn write_frame(&mut self, frame: &Frame) -> Result<(), QueueFull> {
+        self.send(self.time, Command::Frame(*frame))?;
+        let mut shadow = Shadow(&mut self.registers);
+        match shadow.write_frame(frame) {
+            Ok(()) => Ok(()),
+            Err(never) => match never {},
+        }
+    }
+}
+
+/// Registers written as a frame would write them, to keep the sender's
+/// shadow in step with the emulator.
+struct Shadow<'a>(&'a mut Registers);
+
+impl Psg for Shadow<'_> {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        self.0.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        self.0
+    }
+
+    fn master_clock(&self) -> u32 {
+        0
+    }
+}
+
+/// The audio callback's end of a [`channel`], owning the emulator.
+pub struct AudioRenderer {
+    shared: Arc<Shared>,
+    emulator: Ym2149Emulator,
+    sample_rate: u32,
+    /// Rendered samples, kept to save allocating in the callback.
+    mono: Vec<i16>,
+    stereo: Vec<[i16; 2]>,
+}
+
+impl AudioRenderer {
+    pub fn emulator(&self) -> &Ym2149Emulator {
+        &self.emulator
+    }
+
+    /// Samples rendered so far.
+    pub fn position(&self) -> u64 {
+        self.shared.position.load(Ordering::Relaxed)
+    }
+
+    /// Fills `out`, `channels` interleaved samples to a frame, making the
+    /// writes queued for each sample as it comes. One channel is mono;
+    /// with more, the first two are the emulator's stereo, as its
+    /// [`StereoMode`](crate::emulator::StereoMode) has it, and the rest are left silent. `delay` is
+    /// how many samples the backend takes to play what is handed it, for
+    /// [`AudioSender::latency`].
+    pub fn fill(&mut self, out: &mut [i16], channels: usize, delay: u32) {
+        let channels = channels.max(1);
+        let frames = out.len() / channels;
+        if channels == 1 {
+            self.mono.resize(frames, 0);
+            let mut mono = core::mem::take(&mut self.mono);
+            self.render_with(frames, |emulator, from, to, rate| {
+                emulator.render(&mut mono[from..to], rate)
+            });
+            out[..frames].copy_from_slice(&mono);
+            self.mono = mono;
+        } else {
+            self.stereo.resize(frames, [0; 2]);
+            let mut stereo = core::mem::take(&mut self.stereo);
+            self.render_with(frames, |emulator, from, to, rate| {
+                emulator.render_stereo(&mut stereo[from..to], rate)
+            });
+            for (frame, sample) in out.chunks_exact_mut(channels).zip(&stereo) {
+                frame[..2].copy_from_slice(sample);
+                frame[2..].fill(0);
+            }
+            self.stereo = stereo;
+        }
+        let latency = (frames as u32).saturating_add(delay);
+        self.shared.latency.store(latency, Ordering::Relaxed);
+    }
+
+    /// Records that the backend ran short of samples.
+    pub fn count_underrun(&self) {
+        count(&self.shared.underruns);
+    }
+
+    /// Renders `frames` samples with `render`, a run at a time between the
+    /// samples writes are due at.
+    fn render_with(
+        &mut self,
+        frames: usize,
+        mut render: impl FnMut(&mut Ym2149Emulator, usize, usize, u32),
+    ) {
+        let start = self.position();
+        let mut done = 0;
+        while done < frames {
+            let now = start + done as u64;
+            let next = self.apply_due(now);
+            let until = next.map_or(frames, |at| ((at - start) as usize).min(frames));
+            render(&mut self.emulator, done, until, self.sample_rate);
+            done = until;
+            self.shared
+                .position
+                .store(start + done as u64, Ordering::Release);
+        }
+    }
+
+    /// Makes the writes due by sample `now`, saying when the next is due if
+    /// there is one waiting.
+    fn apply_due(&mut self, now: u64) -> Option<u64> {
+        let shared = &*self.shared;
+        let capacity = shared.slots.len();
+        loop {
+            let head = shared.head.load(Ordering::Relaxed);
+            if shared.tail.load(Ordering::Acquire) == head {
+                return None;
+            }
+            // SAFETY: the slot is inside head..tail, so the sender has
+            // published it and won't touch it until head moves past.
+            let timed = unsafe { *shared.slots[head % capacity].get() };
+            if timed.at > now {
+                return Some(timed.at);
+            }
+            if timed.at < now {
+                count(&shared.late);
+            }
+            let done = match timed.command {
+                Command::Write(address, data) => self.emulator.set_register_value(address, data),
+                Command::Frame(frame) => self.emulator.write_frame(&frame),
+            };
+            match done {
+                Ok(()) => {}
+                Err(never) => match never {},
+            }
+            shared.head.store(head.wrapping_add(1), Ordering::Release);
+        }
+    }
+}
+
+#[cfg(feature = "cpal")]
+pub use self::output::{AudioError, AudioOutput};
+
+#[cfg(feature = "cpal")]
+mod output {
+    use std::vec::Vec;
+
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{ErrorKind, OutputCallbackInfo, SampleFormat};
+
+    use super::{channel, count, AudioSender};
+    use crate::emulator::Ym2149Emulator;
+
+    /// Why [`AudioOutput::open`] couldn't start.
+    #[derive(Debug)]
+    pub enum AudioError {
+        /// There is no output device.
+        NoDevice,
+        /// The device only takes samples in a format not handled here.
+        Format(SampleFormat),
+        Cpal(cpal::Error),
+    }
+
+    impl From<cpal::Error> for AudioError {
+        fn from(error: cpal::Error) -> AudioError {
+            AudioError::Cpal(error)
+        }
+    }
+
+    /// The emulator, playing through the default output device.
+    pub struct AudioOutput {
+        // Dropping it stops the sound.
+        _stream: cpal::Stream,
+        sender: AudioSender,
+    }
+
+    impl AudioOutput {
+        /// Starts `emulator` playing, at the device's own rate and channel
+        /// count, with room for `capacity` writes queued.
+        pub fn open(emulator: Ym2149Emulator, capacity: usize) -> Result<AudioOutput, AudioError> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or(AudioError::NoDevice)?;
+            let supported = device.default_output_config()?;
+            let format = supported.sample_format();
+            let config = supported.config();
+            let (rate, channels) = (config.sample_rate, config.channels as usize);
+            let (sender, mut renderer) = channel(emulator, rate, capacity);
+            let shared = renderer.shared.clone();
+            let errors = move |error: cpal::Error| {
+                if error.kind() == ErrorKind::Xrun {
+                    count(&shared.underruns);
+                }
+            };
+            let stream = match format {
+                SampleFormat::I16 => device.build_output_stream(
+                    config,
+                    move |out: &mut [i16], info: &OutputCallbackInfo| {
+                        renderer.fill(out, channels, delay(info, rate))
+                    },
+                    errors,
+                    None,
+                )?,
+                SampleFormat::F32 => {
+                    let mut samples = Vec::new();
+                    device.build_output_stream(
+                        config,
+                        move |out: &mut [f32], info: &OutputCallbackInfo| {
+                            samples.resize(out.len(), 0);
+                            renderer.fill(&mut samples, channels, delay(info, rate));
+                            for (out, &sample) in out.iter_mut().zip(&samples) {
+                                *out = sample as f32 / 32768.0;
+                            }
+                        },
+                        errors,
+                        None,
+                    )?
+                }
+                format => return Err(AudioError::Format(format)),
+            };
+            stream.play()?;
+            Ok(AudioOutput {
+                _stream: stream,
+                sender,
+            })
+        }
+
+        /// The end to play through, as a [`Psg`](crate::psg::Psg).
+        pub fn sender(&mut self) -> &mut AudioSender {
+            &mut self.sender
+        }
+    }
+
+    /// Samples from the callback to the device playing what it hands over.
+    fn delay(info: &OutputCallbackInfo, rate: u32) -> u32 {
+        let timestamp = info.timestamp();
+        let delay = timestamp
+            .playback
+            .saturating_duration_since(timestamp.callback);
+        (delay.as_micros() as u64 * rate as u64 / 1_000_000) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::StereoMode;
+    use crate::{Channel, ChannelLevel};
+
+    #[test]
+    fn writes_land_on_the_sample_they_are_due_at() {
+        let (mut sender, mut renderer) = channel(Ym2149Emulator::new(), 8_000, 16);
+        sender.set_time(10);
+        sender
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        sender.set_time(25);
+        sender.silence().unwrap();
+        assert!(sender.queued() > 0);
+        let mut out = [1; 40];
+        renderer.fill(&mut out, 1, 0);
+        let loud = out[10];
+        assert!(loud > 0);
+        assert!(out[..10].iter().all(|&sample| sample == 0));
+        assert!(out[10..25].iter().all(|&sample| sample == loud));
+        assert!(out[25..].iter().all(|&sample| sample == 0));
+        assert_eq!(
+            (sender.position(), sender.queued(), sender.late()),
+            (40, 0, 0)
+        );
+        assert_eq!(sender.latency(), 40);
+        // The sender's shadow follows what was queued.
+        assert_eq!(sender.registers(), renderer.emulator().registers());
+    }
+
+    #[test]
+    fn late_and_overflowing_writes_are_counted() {
+        let (mut sender, mut renderer) = channel(Ym2149Emulator::new(), 8_000, 2);
+        let mut out = [0; 20];
+        renderer.fill(&mut out, 1, 100);
+        assert_eq!(sender.latency(), 120);
+        // Due at 5, long gone: made at the next sample.
+        sender.set_time(5);
+        sender
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(15))
+            .unwrap();
+        sender.set_register_value(0x8, 15).unwrap();
+        assert_eq!(sender.set_register_value(0x9, 0), Err(QueueFull));
+        assert_eq!(sender.overruns(), 1);
+        // A frame takes one slot however many registers it changes.
+        renderer.fill(&mut out, 1, 0);
+        assert_eq!(sender.late(), 2);
+        assert!(out.iter().all(|&sample| sample > 0));
+        sender.set_time(50);
+        sender.write_frame(&[0; 16]).unwrap();
+        assert_eq!(sender.queued(), 1);
+        renderer.fill(&mut out, 1, 0);
+        assert!(out[..10].iter().all(|&sample| sample > 0));
+        assert!(out[10..].iter().all(|&sample| sample == 0));
+        assert_eq!(sender.registers(), renderer.emulator().registers());
+    }
+
+    #[test]
+    fn interleaved_buffers_fill_two_sides_and_leave_the_rest() {
+        let mut emulator = Ym2149Emulator::new();
+        emulator.set_stereo_mode(StereoMode::StereoAbc);
+        let (mut sender, mut renderer) = channel(emulator, 8_000, 4);
+        sender
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        let mut out = [1; 12];
+        renderer.fill(&mut out, 3, 0);
+        for frame in out.chunks(3) {
+            assert!(frame[0] > 0);
+            assert_eq!(frame[1..], [0, 0]);
+        }
+        assert_eq!(sender.position(), 4);
+    }
+}
@@ -0,0 +1,602 @@
+//! The Microchip AY8930's expanded mode, over any [`Psg`].
+//!
+//! The AY8930 starts out as an AY-3-8910. Writing `101` to the top bits of
+//! R13 switches it to expanded mode, which adds a second bank of registers,
+//! selected by R13's bit 4: an envelope of its own for channels B and C, a
+//! duty cycle for each tone, and masks over the noise. Bank A's registers
+//! widen too: the tone periods take all sixteen bits, the noise period all
+//! eight, and levels five, with the envelope flag moving up to bit 5.
+//!
+//! [`Ay8930`] wraps the chip's [`Psg`], keeping a shadow of both banks and
+//! selecting whichever each write needs. Standard-mode writes go through it
+//! as before: in expanded mode a level is turned into its five-bit form and
+//! R13's mode bits are kept, so everything written for the YM2149 plays on
+//! unchanged. R13 is the same register in both banks, so selecting a bank
+//! rewrites channel A's envelope shape and restarts it; keep bank B's
+//! writes together.
+//!
+//! What documentation there is of the chip is scarce, so its layout is
+//! written down once, in [`REGISTER_MAP`], and everything here works from
+//! that.
+//!
+//! ```
+//! use ym2149::ay8930::{Ay8930, Ay8930Error, Duty};
+//! use ym2149::{Channel, Psg};
+//!
+//! fn thin_b<P: Psg>(ay: &mut Ay8930<P>) -> Result<(), Ay8930Error<P::Error>> {
+//!     ay.enter_expanded_mode().map_err(Ay8930Error::Psg)?;
+//!     ay.set_channel_duty(Channel::B, Duty::Quarter)
+//! }
+//! ```
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::FrequencyPolicy;
+use crate::{Channel, ChipKind, EnvelopeShape};
+
+/// R13's top bits in expanded mode. Bit 4 then selects the bank.
+const EXPANDED: u8 = 0b1010_0000;
+
+/// One of expanded mode's two banks of registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Bank {
+    /// The standard registers, and the only bank in standard mode.
+    #[default]
+    A,
+    B,
+}
+
+impl Bank {
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A register's name and the bits of it the chip uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterInfo {
+    pub name: &'static str,
+    pub mask: u8,
+}
+
+const fn info(name: &'static str, mask: u8) -> Option<RegisterInfo> {
+    Some(RegisterInfo { name, mask })
+}
+
+/// Expanded mode's registers, by bank and then address, with `None` where
+/// an address holds nothing. R13 is the same register in either bank.
+pub const REGISTER_MAP: [[Option<RegisterInfo>; 16]; 2] = [
+    [
+        info("A_FINE", 0xFF),
+        info("A_ROUGH", 0xFF),
+        info("B_FINE", 0xFF),
+        info("B_ROUGH", 0xFF),
+        info("C_FINE", 0xFF),
+        info("C_ROUGH", 0xFF),
+        info("NOISE", 0xFF),
+        info("MIXER", 0xFF),
+        info("A_LEVEL", 0x3F),
+        info("B_LEVEL", 0x3F),
+        info("C_LEVEL", 0x3F),
+        info("EA_FINE", 0xFF),
+        info("EA_ROUGH", 0xFF),
+        info("MODE_EA_SHAPE", 0xFF),
+        info("PORT_A", 0xFF),
+        info("PORT_B", 0xFF),
+    ],
+    [
+        info("EB_FINE", 0xFF),
+        info("EB_ROUGH", 0xFF),
+        info("EC_FINE", 0xFF),
+        info("EC_ROUGH", 0xFF),
+        info("EB_SHAPE", 0x0F),
+        info("EC_SHAPE", 0x0F),
+        info("A_DUTY", 0x0F),
+        info("B_DUTY", 0x0F),
+        info("C_DUTY", 0x0F),
+        info("NOISE_AND", 0xFF),
+        info("NOISE_OR", 0xFF),
+        None,
+        None,
+        info("MODE_EA_SHAPE", 0xFF),
+        None,
+        None,
+    ],
+];
+
+/// What [`REGISTER_MAP`] says of `address` in `bank`.
+pub const fn register_info(bank: Bank, address: u8) -> Option<RegisterInfo> {
+    if address > 0xF {
+        return None;
+    }
+    REGISTER_MAP[bank.index()][address as usize]
+}
+
+/// A channel's envelope period registers, fine and then rough, and its
+/// shape register, all in the bank given.
+pub const fn envelope_registers(channel: Channel) -> (Bank, u8, u8, u8) {
+    match channel {
+        Channel::A => (Bank::A, 0xB, 0xC, 0xD),
+        Channel::B => (Bank::B, 0x0, 0x1, 0x4),
+        Channel::C => (Bank::B, 0x2, 0x3, 0x5),
+    }
+}
+
+/// A channel's duty cycle register, in bank B.
+pub const fn duty_register(channel: Channel) -> u8 {
+    0x6 + channel.index() as u8
+}
+
+/// The share of each tone period a channel's square wave spends high, in
+/// expanded mode. The chip takes nine, 0 to 8 in its duty registers, and
+/// plays anything above 8 as 8; standard mode's wave is [`Duty::Half`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Duty {
+    ThirtySecond,
+    Sixteenth,
+    Eighth,
+    Quarter,
+    #[default]
+    Half,
+    ThreeQuarters,
+    SevenEighths,
+    FifteenSixteenths,
+    ThirtyOneThirtySeconds,
+}
+
+impl Duty {
+    pub const ALL: [Duty; 9] = [
+        Duty::ThirtySecond,
+        Duty::Sixteenth,
+        Duty::Eighth,
+        Duty::Quarter,
+        Duty::Half,
+        Duty::ThreeQuarters,
+        Duty::SevenEighths,
+        Duty::FifteenSixteenths,
+        Duty::ThirtyOneThirtySeconds,
+    ];
+
+    /// The value to write to a duty register.
+    pub const fn register_value(self) -> u8 {
+        self as u8
+    }
+
+    /// The time high in 32nds of the period.
+    pub const fn thirty_seconds(self) -> u8 {
+        match self {
+            Duty::ThirtySecond => 1,
+            Duty::Sixteenth => 2,
+            Duty::Eighth => 4,
+            Duty::Quarter => 8,
+            Duty::Half => 16,
+            Duty::ThreeQuarters => 24,
+            Duty::SevenEighths => 28,
+            Duty::FifteenSixteenths => 30,
+            Duty::ThirtyOneThirtySeconds => 31,
+        }
+    }
+}
+
+/// Why an [`Ay8930`] write failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ay8930Error<E> {
+    /// Bank B and what is in it are only there in expanded mode.
+    StandardMode,
+    /// [`REGISTER_MAP`] has nothing at that address in that bank.
+    NoRegister(Bank, u8),
+    /// Writing to the chip failed.
+    Psg(E),
+}
+
+/// A shadow of both banks, keeping the bits [`REGISTER_MAP`] says each
+/// register has.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct BankedRegisters {
+    values: [[u8; 16]; 2],
+    written: [u16; 2],
+}
+
+impl BankedRegisters {
+    pub const fn new() -> BankedRegisters {
+        BankedRegisters {
+            values: [[0; 16]; 2],
+            written: [0; 2],
+        }
+    }
+
+    /// Last value written to `address` in `bank`, or `None` if it is
+    /// unknown.
+    pub fn get(&self, bank: Bank, address: u8) -> Option<u8> {
+        let written = address < 16 && self.written[bank.index()] & 1 << address != 0;
+        written.then(|| self.values[bank.index()][address as usize])
+    }
+
+    /// Records a write, R13 in both banks. Addresses with no register are
+    /// ignored.
+    pub fn set(&mut self, bank: Bank, address: u8, value: u8) {
+        let Some(info) = register_info(bank, address) else {
+            return;
+        };
+        let banks: &[Bank] = match address {
+            0xD => &[Bank::A, Bank::B],
+            _ => &[bank],
+        };
+        for bank in banks {
+            self.values[bank.index()][address as usize] = value & info.mask;
+            self.written[bank.index()] |= 1 << address;
+        }
+    }
+
+    /// Forgets `bank`'s values.
+    pub fn invalidate(&mut self, bank: Bank) {
+        self.written[bank.index()] = 0;
+    }
+}
+
+/// An AY8930 on `P`, in standard mode until told otherwise.
+///
+/// Its [`Psg::registers`] are what standard-mode writes asked for, so the
+/// helpers' read-modify-writes work in either mode; [`Ay8930::banks`] has
+/// what the chip holds. `P`'s own shadow holds whichever bank was last
+/// written at each address, and is best left unread.
+#[derive(Debug, Clone)]
+pub struct Ay8930<P> {
+    psg: P,
+    expanded: bool,
+    bank: Bank,
+    banks: BankedRegisters,
+    registers: Registers,
+}
+
+impl<P: Psg> Ay8930<P> {
+    pub const fn new(psg: P) -> Ay8930<P> {
+        Ay8930 {
+            psg,
+            expanded: false,
+            bank: Bank::A,
+            banks: BankedRegisters::new(),
+            registers: Registers::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.psg
+    }
+
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.psg
+    }
+
+    pub fn into_inner(self) -> P {
+        self.psg
+    }
+
+    pub fn is_expanded(&self) -> bool {
+        self.expanded
+    }
+
+    /// The bank selected: always [`Bank::A`] in standard mode.
+    pub fn bank(&self) -> Bank {
+        self.bank
+    }
+
+    /// What the chip holds, bank by bank.
+    pub fn banks(&self) -> &BankedRegisters {
+        &self.banks
+    }
+
+    /// Switches to expanded mode with bank A selected, doing nothing if it
+    /// is already. Switching restarts channel A's envelope, and may clear
+    /// the chip's registers, so everything standard-mode writes have set
+    /// is written again, in expanded form; bank B starts unknown.
+    pub fn enter_expanded_mode(&mut self) -> Result<(), P::Error> {
+        if self.expanded {
+            return Ok(());
+        }
+        self.expanded = true;
+        self.bank = Bank::A;
+        self.banks.invalidate(Bank::B);
+        self.write_mode(self.registers.value(0xD))?;
+        self.rewrite()
+    }
+
+    /// Switches back to standard mode, writing everything standard-mode
+    /// writes have set again in standard form.
+    pub fn leave_expanded_mode(&mut self) -> Result<(), P::Error> {
+        if !self.expanded {
+            return Ok(());
+        }
+        self.expanded = false;
+        self.bank = Bank::A;
+        self.banks.invalidate(Bank::B);
+        self.write_mode(self.registers.value(0xD))?;
+        self.rewrite()
+    }
+
+    /// Writes `value` to `address` in `bank`, selecting the bank first if
+    /// it isn't. Bits the register doesn't have are dropped; R13's mode
+    /// bits are this wrapper's, so only its shape is taken from `value`.
+    pub fn write(
+        &mut self,
+        bank: Bank,
+        address: u8,
+        value: u8,
+    ) -> Result<(), Ay8930Error<P::Error>> {
+        if register_info(bank, address).is_none() {
+            return Err(Ay8930Error::NoRegister(bank, address));
+        }
+        if bank == Bank::B && !self.expanded {
+            return Err(Ay8930Error::StandardMode);
+        }
+        if address == 0xD {
+            self.write_mode(value).map_err(Ay8930Error::Psg)?;
+            self.registers.set(0xD, value);
+            return Ok(());
+        }
+        self.put(bank, address, value).map_err(Ay8930Error::Psg)
+    }
+
+    /// Sets `channel`'s tone duty cycle.
+    pub fn set_channel_duty(
+        &mut self,
+        channel: Channel,
+        duty: Duty,
+    ) -> Result<(), Ay8930Error<P::Error>> {
+        self.write(Bank::B, duty_register(channel), duty.register_value())
+    }
+
+    /// Sets `channel`'s own envelope period, all sixteen bits.
+    pub fn set_channel_envelope_period(
+        &mut self,
+        channel: Channel,
+        period: u16,
+    ) -> Result<(), Ay8930Error<P::Error>> {
+        self.expanded_only()?;
+        let (bank, fine, rough, _) = envelope_registers(channel);
+        self.write(bank, fine, period as u8)?;
+        self.write(bank, rough, (period >> 8) as u8)
+    }
+
+    /// Sets `channel`'s own envelope shape, restarting it.
+    pub fn set_channel_envelope_shape(
+        &mut self,
+        channel: Channel,
+        shape: EnvelopeShape,
+    ) -> Result<(), Ay8930Error<P::Error>> {
+        self.expanded_only()?;
+        let (bank, _, _, register) = envelope_registers(channel);
+        self.write(bank, register, shape.bits())
+    }
+
+    /// Sets `channel` to a fixed `level`, 0 to 31.
+    pub fn set_channel_level(
+        &mut self,
+        channel: Channel,
+        level: u8,
+    ) -> Result<(), Ay8930Error<P::Error>> {
+        self.expanded_only()?;
+        let register = channel.level_register();
+        self.put(Bank::A, register, level & 0x1F)
+            .map_err(Ay8930Error::Psg)?;
+        self.registers.set(register, level >> 1 & 0xF);
+        Ok(())
+    }
+
+    /// Sets the noise period, all eight bits.
+    pub fn set_noise_period(&mut self, period: u8) -> Result<(), Ay8930Error<P::Error>> {
+        self.expanded_only()?;
+        self.put(Bank::A, 0x6, period).map_err(Ay8930Error::Psg)?;
+        self.registers.set(0x6, period);
+        Ok(())
+    }
+
+    /// Sets the masks over the noise generator's output: it is ANDed with
+    /// `and` and then ORed with `or`, so `0xFF` and `0` leave it as it is.
+    pub fn set_noise_masks(&mut self, and: u8, or: u8) -> Result<(), Ay8930Error<P::Error>> {
+        self.write(Bank::B, 0x9, and)?;
+        self.write(Bank::B, 0xA, or)
+    }
+
+    fn expanded_only(&self) -> Result<(), Ay8930Error<P::Error>> {
+        match self.expanded {
+            true => Ok(()),
+            false => Err(Ay8930Error::StandardMode),
+        }
+    }
+
+    /// Writes `value` to `address` in `bank`, selecting it first.
+    fn put(&mut self, bank: Bank, address: u8, value: u8) -> Result<(), P::Error> {
+        if bank != self.bank {
+            self.bank = bank;
+            self.write_mode(self.registers.value(0xD))?;
+        }
+        let mask = register_info(bank, address).map_or(0xFF, |info| info.mask);
+        self.psg.set_register_value(address, value & mask)?;
+        self.banks.set(bank, address, value);
+        Ok(())
+    }
+
+    /// Writes R13: the mode and bank bits, and channel A's envelope `shape`.
+    fn write_mode(&mut self, shape: u8) -> Result<(), P::Error> {
+        let mode = match self.expanded {
+            true => EXPANDED | (self.bank as u8) << 4,
+            false => 0,
+        };
+        let value = mode | shape & 0xF;
+        self.psg.set_register_value(0xD, value)?;
+        self.banks.set(self.bank, 0xD, value);
+        Ok(())
+    }
+
+    /// Writes every register standard-mode writes have set, other than R13.
+    fn rewrite(&mut self) -> Result<(), P::Error> {
+        for address in (0..16).filter(|&address| address != 0xD) {
+            if let Some(value) = self.registers.get(address) {
+                self.set_register_value(address, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A standard-mode level in expanded form: the same loudness in five bits,
+/// with the envelope flag moved up.
+const fn expanded_level(value: u8) -> u8 {
+    match value {
+        0x10..=0xFF => 0x20,
+        0 => 0,
+        level => level << 1 | 1,
+    }
+}
+
+impl<P: Psg> Psg for Ay8930<P> {
+    type Error = P::Error;
+
+    /// Writes as to an AY-3-8910, in bank A, with the level in expanded
+    /// form in expanded mode. R13's mode bits are kept.
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), P::Error> {
+        match address {
+            0x8..=0xA if self.expanded => {
+                self.put(Bank::A, address, expanded_level(data & 0x1F))?
+            }
+            0xD => self.write_mode(data)?,
+            _ => self.put(Bank::A, address, data)?,
+        }
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.psg.master_clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.psg.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.psg.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording_bus::RecordingBus;
+    use crate::ChannelLevel;
+
+    fn ay() -> Ay8930<RecordingBus<64>> {
+        Ay8930::new(RecordingBus::new())
+    }
+
+    #[test]
+    fn the_map_names_what_the_setters_write() {
+        let name = |bank, address| register_info(bank, address).map(|info| info.name);
+        for channel in Channel::ALL {
+            let (bank, fine, rough, shape) = envelope_registers(channel);
+            let letter = ["A", "B", "C"][channel.index()];
+            assert_eq!(name(bank, fine).unwrap(), ["E", letter, "_FINE"].concat());
+            assert_eq!(name(bank, rough).unwrap(), ["E", letter, "_ROUGH"].concat());
+            assert!(name(bank, shape).unwrap().ends_with("SHAPE"));
+            let duty = name(Bank::B, duty_register(channel)).unwrap();
+            assert_eq!(duty, [letter, "_DUTY"].concat());
+        }
+        assert_eq!(REGISTER_MAP[0][0xD], REGISTER_MAP[1][0xD]);
+        assert_eq!(register_info(Bank::B, 0xB), None);
+        assert_eq!(register_info(Bank::A, 0x10), None);
+        assert!(Duty::ALL
+            .iter()
+            .enumerate()
+            .all(|(value, duty)| duty.register_value() == value as u8));
+    }
+
+    #[test]
+    fn standard_mode_writes_pass_through() {
+        let mut ay = ay();
+        ay.set_channel_period(Channel::A, 0x11C).unwrap();
+        ay.update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        ay.set_register_value(0xD, 0xAE).unwrap();
+        ay.inner()
+            .assert_register_sequence(&[(0x0, 0x1C), (0x1, 0x01), (0x8, 15), (0xD, 0x0E)]);
+        assert!(!ay.is_expanded());
+        assert_eq!(
+            ay.set_channel_duty(Channel::A, Duty::Eighth),
+            Err(Ay8930Error::StandardMode)
+        );
+        assert_eq!(
+            ay.set_channel_envelope_period(Channel::A, 1),
+            Err(Ay8930Error::StandardMode)
+        );
+    }
+
+    #[test]
+    fn entering_expanded_mode_writes_levels_in_their_new_form() {
+        let mut ay = ay();
+        ay.update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        ay.update_channel_level(Channel::B, ChannelLevel::Envelope)
+            .unwrap();
+        ay.set_register_value(0xD, 0x0A).unwrap();
+        ay.inner_mut().clear();
+        ay.enter_expanded_mode().unwrap();
+        ay.inner()
+            .assert_register_sequence(&[(0xD, 0xAA), (0x8, 31), (0x9, 0x20)]);
+        // Standard-mode code carries on as it was.
+        ay.inner_mut().clear();
+        ay.update_channel_level(Channel::A, ChannelLevel::Fixed(7))
+            .unwrap();
+        ay.set_register_value(0xD, 0x0E).unwrap();
+        ay.inner()
+            .assert_register_sequence(&[(0x8, 15), (0xD, 0xAE)]);
+        assert_eq!(ay.registers().value(0x8), 7);
+
+        ay.inner_mut().clear();
+        ay.leave_expanded_mode().unwrap();
+        ay.inner()
+            .assert_register_sequence(&[(0xD, 0x0E), (0x8, 7), (0x9, 0x10)]);
+    }
+
+    #[test]
+    fn bank_b_writes_select_the_bank_once() {
+        let mut ay = ay();
+        ay.set_register_value(0xD, 0x08).unwrap();
+        ay.enter_expanded_mode().unwrap();
+        ay.inner_mut().clear();
+        ay.set_channel_envelope_period(Channel::C, 0x1234).unwrap();
+        ay.set_channel_envelope_shape(Channel::C, EnvelopeShape::cont | EnvelopeShape::Att)
+            .unwrap();
+        ay.set_channel_duty(Channel::A, Duty::ThirtySecond).unwrap();
+        ay.set_noise_masks(0x0F, 0x01).unwrap();
+        // Channel A's envelope is in bank A, and so is anything standard.
+        ay.set_channel_envelope_period(Channel::A, 0xABCD).unwrap();
+        ay.set_channel_level(Channel::B, 20).unwrap();
+        ay.inner().assert_register_sequence(&[
+            (0xD, 0xB8),
+            (0x2, 0x34),
+            (0x3, 0x12),
+            (0x5, 0xC),
+            (0x6, 0x0),
+            (0x9, 0x0F),
+            (0xA, 0x01),
+            (0xD, 0xA8),
+            (0xB, 0xCD),
+            (0xC, 0xAB),
+            (0x9, 20),
+        ]);
+        assert_eq!(ay.bank(), Bank::A);
+        assert_eq!(ay.banks().get(Bank::B, 0x3), Some(0x12));
+        assert_eq!(ay.banks().get(Bank::B, 0xD), Some(0xA8));
+        assert_eq!(ay.banks().get(Bank::A, 0xC), Some(0xAB));
+        assert_eq!(ay.registers().value(0x9), 10);
+        assert_eq!(
+            ay.write(Bank::B, 0xE, 1),
+            Err(Ay8930Error::NoRegister(Bank::B, 0xE))
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! Sound effects in the AYFX format used by AYFXEdit and countless ZX
+//! Spectrum games.
+//!
+//! An effect is a run of frames, one per 50 Hz tick. Each starts with an
+//! info byte:
+//!
+//! - bits 0-3: volume
+//! - bit 4: tone off
+//! - bit 5: a new tone period follows (two bytes, little-endian)
+//! - bit 6: a new noise period follows (one byte); a value of `0x20` there
+//!   ends the effect instead
+//! - bit 7: noise off
+//!
+//! A `.afx` file is a single effect. A `.afb` bank starts with the number of
+//! effects, then a little-endian offset per effect, each counted from the
+//! byte after itself.
+//!
+//! Effects and banks read their bytes through a [`DataSource`], usually a
+//! slice, so they can also stay in AVR program memory.
+
+use crate::data_source::DataSource;
+use crate::parse_error::ParseError;
+use crate::psg::Psg;
+use crate::tuning::{MAX_NOISE_PERIOD, MAX_TONE_PERIOD};
+use crate::{Channel, ChannelLevel};
+
+const TONE_OFF: u8 = 0x10;
+const TONE_CHANGE: u8 = 0x20;
+const NOISE_CHANGE: u8 = 0x40;
+const NOISE_OFF: u8 = 0x80;
+const END_MARKER: u8 = 0x20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AyfxErrorKind {
+    /// The data ends in the middle of the offset table or a frame, or an
+    /// effect has no end marker.
+    Truncated,
+    /// This effect's offset points outside the bank.
+    BadOffset(u8),
+}
+
+/// What is wrong with an effect or bank, and the byte it was found at.
+pub type AyfxError = ParseError<AyfxErrorKind>;
+
+fn truncated(at: usize) -> AyfxError {
+    AyfxError::new(AyfxErrorKind::Truncated, at)
+}
+
+/// One decoded frame. Periods are `None` when the frame keeps the previous
+/// one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AyfxFrame {
+    pub volume: u8,
+    pub tone: bool,
+    pub noise: bool,
+    pub tone_period: Option<u16>,
+    pub noise_period: Option<u8>,
+}
+
+/// Decodes the frame at `start` in `data`, returning it and its length, or
+/// `None` at the end marker.
+fn decode<D: DataSource>(data: &D, start: usize) -> Result<Option<(AyfxFrame, usize)>, AyfxError> {
+    let info = data.byte(start).ok_or(truncated(start))?;
+    let mut length = 1;
+    let mut tone_period = None;
+    if info & TONE_CHANGE != 0 {
+        let bytes = data.array(start + 1).ok_or(truncated(start + 1))?;
+        let period = u16::from_le_bytes(bytes);
+        tone_period = Some(period & MAX_TONE_PERIOD);
+        length += 2;
+    }
+    let mut noise_period = None;
+    if info & NOISE_CHANGE != 0 {
+        let noise = data.byte(start + length).ok_or(truncated(start + length))?;
+        if noise == END_MARKER {
+            return Ok(None);
+        }
+        noise_period = Some(noise & MAX_NOISE_PERIOD);
+        length += 1;
+    }
+    let frame = AyfxFrame {
+        volume: info & 0x0F,
+        tone: info & TONE_OFF == 0,
+        noise: info & NOISE_OFF == 0,
+        tone_period,
+        noise_period,
+    };
+    Ok(Some((frame, length)))
+}
+
+/// A single effect, checked to decode cleanly up to its end marker.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Effect<D> {
+    data: D,
+    start: usize,
+}
+
+impl<D: DataSource + Copy> Effect<D> {
+    /// Parses a `.afx` file, or an effect starting at the front of `data`.
+    /// Anything after the end marker is ignored.
+    pub fn parse(data: D) -> Result<Effect<D>, AyfxError> {
+        Effect::parse_at(data, 0)
+    }
+
+    fn parse_at(data: D, start: usize) -> Result<Effect<D>, AyfxError> {
+        let mut position = start;
+        while let Some((_, length)) = decode(&data, position)? {
+            position += length;
+        }
+        Ok(Effect { data, start })
+    }
+
+    pub fn frames(&self) -> Frames<D> {
+        Frames {
+            data: self.data,
+            position: self.start,
+        }
+    }
+
+    /// Length in frames.
+    pub fn len(&self) -> usize {
+        self.frames().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames().next().is_none()
+    }
+}
+
+/// The frames of an [`Effect`], in order.
+#[derive(Debug, Clone)]
+pub struct Frames<D> {
+    data: D,
+    position: usize,
+}
+
+impl<D: DataSource> Iterator for Frames<D> {
+    type Item = AyfxFrame;
+
+    fn next(&mut self) -> Option<AyfxFrame> {
+        // Effects are validated when parsed, so errors can't happen here.
+        let (frame, length) = decode(&self.data, self.position).ok()??;
+        self.position += length;
+        Some(frame)
+    }
+}
+
+/// A `.afb` bank of effects, validated up front so playback never fails.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bank<D> {
+    data: D,
+    count: u8,
+}
+
+impl<D: DataSource + Copy> Bank<D> {
+    pub fn parse(data: D) -> Result<Bank<D>, AyfxError> {
+        let count = data.byte(0).ok_or(truncated(0))?;
+        let bank = Bank { data, count };
+        for n in 0..count {
+            bank.locate(n)?;
+        }
+        Ok(bank)
+    }
+
+    pub fn effect_count(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Effect number `n`, or `None` past the end of the bank.
+    pub fn effect(&self, n: usize) -> Option<Effect<D>> {
+        if n >= self.effect_count() {
+            return None;
+        }
+        self.locate(n as u8).ok()
+    }
+
+    fn locate(&self, n: u8) -> Result<Effect<D>, AyfxError> {
+        let entry = 1 + 2 * n as usize;
+        let bytes = self.data.array(entry).ok_or(truncated(entry))?;
+        let start = entry + 1 + u16::from_le_bytes(bytes) as usize;
+        if start >= self.data.len() {
+            return Err(AyfxError::new(AyfxErrorKind::BadOffset(n), entry));
+        }
+        Effect::parse_at(self.data, start)
+    }
+}
+
+/// What an effect overwrote, put back once it ends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Saved {
+    period: u16,
+    level: u8,
+    mixer: u8,
+    noise_period: u8,
+}
+
+/// Plays one AYFX effect on a channel, one frame per tick.
+///
+/// The channel's period, level and mixer bits, and the shared noise period,
+/// are saved on the first tick and restored once the effect ends, so an
+/// effect can interrupt music and hand the channel back.
+#[derive(Debug, Clone)]
+pub struct AyfxPlayer<D> {
+    channel: Channel,
+    frames: Option<Frames<D>>,
+    saved: Option<Saved>,
+}
+
+impl<D: DataSource + Copy> AyfxPlayer<D> {
+    pub const fn new(channel: Channel) -> AyfxPlayer<D> {
+        AyfxPlayer {
+            channel,
+            frames: None,
+            saved: None,
+        }
+    }
+
+    /// Starts `effect`, replacing any effect already playing. The channel
+    /// state saved for the earlier effect is kept, so it is still what gets
+    /// restored.
+    pub fn play(&mut self, effect: Effect<D>) {
+        self.frames = Some(effect.frames());
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.frames.is_some()
+    }
+
+    /// Plays the next frame. Returns `true` once the effect has ended, on the
+    /// tick that restores the channel.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        let Some(frames) = self.frames.as_mut() else {
+            return Ok(true);
+        };
+        let channel = self.channel;
+        let registers = psg.registers();
+        let saved = *self.saved.get_or_insert(Saved {
+            period: registers.tone_period(channel),
+            level: registers.value(channel.level_register()),
+            mixer: registers.mixer(),
+            noise_period: registers.value(0x6),
+        });
+        let Some(frame) = frames.next() else {
+            self.frames = None;
+            self.saved = None;
+            psg.set_channel_period(channel, saved.period)?;
+            psg.update_register(0x6, saved.noise_period)?;
+            let tone = saved.mixer & (1 << channel.index()) == 0;
+            let noise = saved.mixer & (8 << channel.index()) == 0;
+            psg.set_channel_mixer(channel, tone, noise)?;
+            psg.update_register(channel.level_register(), saved.level)?;
+            return Ok(true);
+        };
+        if let Some(period) = frame.tone_period {
+            psg.set_channel_period(channel, period)?;
+        }
+        if let Some(period) = frame.noise_period {
+            psg.update_register(0x6, period)?;
+        }
+        psg.set_channel_mixer(channel, frame.tone, frame.noise)?;
+        psg.update_channel_level(channel, ChannelLevel::Fixed(frame.volume))?;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::{mangled, FakePsg, Inverted};
+    use std::vec::Vec;
+
+    /// Two effects: a tone-only blip, then a noise-only frame followed by a
+    /// tone plus noise frame.
+    const BANK: [u8; 19] = [
+        2, // effect count
+        3, 0, // effect 0 at 2 + 3 = byte 5
+        7, 0, // effect 1 at 4 + 7 = byte 11
+        0xAF, 0x1C, 0x01, // volume 15, tone period 0x11C, noise off
+        0x8C, // volume 12, same period
+        0xD0, 0x20, // end
+        0x5A, 0x05, // volume 10, tone off, noise period 5
+        0x68, 0x00, 0x02, 0x0A, // volume 8, tone 0x200 and noise 10, both on
+        0xD0, 0x20, // end
+    ];
+
+    #[test]
+    fn bank_decodes_frames() {
+        let bank = Bank::parse(&BANK).unwrap();
+        assert_eq!(bank.effect_count(), 2);
+        assert!(bank.effect(2).is_none());
+        let frames: Vec<_> = bank.effect(1).unwrap().frames().collect();
+        assert_eq!(
+            frames,
+            [
+                AyfxFrame {
+                    volume: 10,
+                    tone: false,
+                    noise: true,
+                    tone_period: None,
+                    noise_period: Some(5),
+                },
+                AyfxFrame {
+                    volume: 8,
+                    tone: true,
+                    noise: true,
+                    tone_period: Some(0x200),
+                    noise_period: Some(10),
+                },
+            ]
+        );
+        assert_eq!(bank.effect(0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn single_afx_file() {
+        let effect = Effect::parse(&BANK[5..11]).unwrap();
+        assert_eq!(effect.frames().next().unwrap().tone_period, Some(0x11C));
+    }
+
+    #[test]
+    fn malformed_banks_are_rejected() {
+        assert_eq!(Bank::parse(&[]), Err(truncated(0)));
+        assert_eq!(Bank::parse(&[1, 5]), Err(truncated(1)));
+        let bad_offset = AyfxError::new(AyfxErrorKind::BadOffset(0), 1);
+        assert_eq!(Bank::parse(&[1, 0x40, 0]), Err(bad_offset));
+        // No end marker, and a tone change cut short.
+        assert_eq!(Bank::parse(&[1, 0, 0, 0x8F]), Err(truncated(4)));
+        assert_eq!(Effect::parse(&[0x2F, 0x1C]), Err(truncated(1)));
+    }
+
+    #[test]
+    fn damaged_banks_fail_cleanly() {
+        for data in mangled(&BANK) {
+            let bank = match Bank::parse(&data[..]) {
+                Ok(bank) => bank,
+                Err(error) => {
+                    assert!(error.offset <= data.len());
+                    continue;
+                }
+            };
+            let mut psg = FakePsg::new();
+            let mut player = AyfxPlayer::new(Channel::C);
+            for n in 0..bank.effect_count() {
+                player.play(bank.effect(n).unwrap());
+                // A frame is at least a byte long.
+                for _ in 0..=data.len() {
+                    player.tick(&mut psg).unwrap();
+                }
+                assert!(!player.is_playing());
+            }
+        }
+    }
+
+    #[test]
+    fn player_plays_then_restores_the_channel() {
+        let mut psg = FakePsg::new();
+        psg.set_channel_period(Channel::A, 0x123).unwrap();
+        psg.update_channel_level(Channel::A, ChannelLevel::Fixed(7))
+            .unwrap();
+        psg.set_tone_enabled(Channel::A, true).unwrap();
+        psg.take_writes();
+
+        let bank = Bank::parse(&BANK).unwrap();
+        let mut player = AyfxPlayer::new(Channel::A);
+        player.play(bank.effect(1).unwrap());
+        assert!(!player.tick(&mut psg).unwrap());
+        assert_eq!(psg.take_writes(), [(0x6, 5), (0x7, 0b0011_0111), (0x8, 10)]);
+        assert!(!player.tick(&mut psg).unwrap());
+        // Rough byte first, so 0x123 passes through 0x223 rather than 0x100.
+        assert_eq!(
+            psg.take_writes(),
+            [
+                (0x1, 0x02),
+                (0x0, 0x00),
+                (0x6, 10),
+                (0x7, 0b0011_0110),
+                (0x8, 8)
+            ]
+        );
+        assert!(player.tick(&mut psg).unwrap());
+        assert_eq!(psg.registers().tone_period(Channel::A), 0x123);
+        assert_eq!(psg.registers().value(0x8), 7);
+        assert_eq!(psg.registers().mixer(), 0b0011_1110);
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn data_sources_play_as_slices_do() {
+        let inverted = Inverted::new(&BANK);
+        let banks = (
+            Bank::parse(&BANK[..]).unwrap(),
+            Bank::parse(&inverted).unwrap(),
+        );
+        assert_eq!(banks.1.effect_count(), 2);
+        let (mut a, mut b) = (FakePsg::new(), FakePsg::new());
+        let mut players = (AyfxPlayer::new(Channel::B), AyfxPlayer::new(Channel::B));
+        for n in [1, 0] {
+            players.0.play(banks.0.effect(n).unwrap());
+            players.1.play(banks.1.effect(n).unwrap());
+            while !players.0.tick(&mut a).unwrap() {
+                assert!(!players.1.tick(&mut b).unwrap());
+            }
+            assert!(players.1.tick(&mut b).unwrap());
+        }
+        assert_eq!(a.writes, b.writes);
+        assert_eq!(
+            Bank::parse(&Inverted::new(&[1, 0x40, 0])).err(),
+            Some(AyfxError::new(AyfxErrorKind::BadOffset(0), 1))
+        );
+    }
+}
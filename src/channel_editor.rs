@@ -0,0 +1,263 @@
+//! Setting up one channel in a single statement, its writes landing
+//! together.
+//!
+//! [`Psg::channel`] hands out a [`ChannelEditor`], which holds the
+//! channel's tone period, level and mixer bits as they are set and writes
+//! them in one burst on [`ChannelEditor::commit`], or when it is dropped:
+//! the period first, then the mixer in one write, then the level, so the
+//! channel is never heard at its new level on its old note. Only what was
+//! set is written, and then only if it changes a register.
+//!
+//! ```
+//! use ym2149::psg_write::{PsgWrite, Shadowed};
+//! use ym2149::registers::Register;
+//! use ym2149::{Channel, Psg};
+//!
+//! /// Logs every write.
+//! struct Log(Vec<(u8, u8)>);
+//!
+//! impl PsgWrite for Log {
+//!     type Error = core::convert::Infallible;
+//!
+//!     fn write_register(&mut self, register: Register, value: u8) -> Result<(), Self::Error> {
+//!         self.0.push((register.address(), value));
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut psg = Shadowed::new(Log(Vec::new()));
+//! psg.set_register_value(0x7, 0x3F).unwrap();
+//! psg.channel(Channel::B)
+//!     .tone_hz(440)
+//!     .level(12)
+//!     .tone(true)
+//!     .noise(false)
+//!     .commit()
+//!     .unwrap();
+//! let burst = [(0x2, 0x1C), (0x3, 0x01), (0x7, 0x3D), (0x9, 12)];
+//! assert_eq!(psg.inner().0[1..], burst);
+//! ```
+//!
+//! A pitch or frequency the chip's
+//! [`FrequencyPolicy`](crate::FrequencyPolicy) refuses is kept as an error
+//! rather than failing the chain. [`ChannelEditor::commit`] returns it and
+//! writes nothing, so half a change is never heard;
+//! [`ChannelEditor::take_error`] takes it back first, to carry on with the
+//! rest.
+
+#[cfg(feature = "music")]
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{self, FrequencyError};
+use crate::{Channel, ChannelLevel};
+
+/// Changes to one channel, written together. See the [module](self) docs.
+pub struct ChannelEditor<'a, P: Psg> {
+    psg: &'a mut P,
+    channel: Channel,
+    changes: Changes,
+    /// Whether a pitch or frequency was refused since the last commit.
+    refused: bool,
+}
+
+impl<'a, P: Psg> ChannelEditor<'a, P> {
+    pub fn new(psg: &'a mut P, channel: Channel) -> ChannelEditor<'a, P> {
+        ChannelEditor {
+            psg,
+            channel,
+            changes: Changes::default(),
+            refused: false,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn period(&mut self, period: u16) -> &mut Self {
+        self.changes.period = Some(period);
+        self
+    }
+
+    /// Tunes the channel to `pitch` under the chip's frequency policy.
+    #[cfg(feature = "music")]
+    pub fn pitch(&mut self, pitch: Pitch) -> &mut Self {
+        let clock = self.psg.master_clock();
+        let tone = tuning::pitch_tone(clock, pitch, self.psg.frequency_policy());
+        self.tune(tone)
+    }
+
+    /// Tunes the channel to `hz` under the chip's frequency policy.
+    pub fn tone_hz(&mut self, hz: u32) -> &mut Self {
+        let millihertz = hz.saturating_mul(1000);
+        let clock = self.psg.master_clock();
+        let tone = tuning::tone_period(clock, millihertz, self.psg.frequency_policy());
+        self.tune(tone)
+    }
+
+    fn tune(&mut self, tone: Option<tuning::FoldedPeriod>) -> &mut Self {
+        match tone {
+            Some(tone) => self.changes.period = Some(tone.period),
+            None => self.refused = true,
+        }
+        self
+    }
+
+    /// A fixed level, 0 to 15.
+    pub fn level(&mut self, level: u8) -> &mut Self {
+        self.channel_level(ChannelLevel::Fixed(level))
+    }
+
+    pub fn channel_level(&mut self, level: ChannelLevel) -> &mut Self {
+        self.changes.level = Some(level);
+        self
+    }
+
+    pub fn tone(&mut self, enabled: bool) -> &mut Self {
+        self.changes.tone = Some(enabled);
+        self
+    }
+
+    pub fn noise(&mut self, enabled: bool) -> &mut Self {
+        self.changes.noise = Some(enabled);
+        self
+    }
+
+    /// Takes back the refusal of a pitch or frequency set since the last
+    /// commit, so the next commit writes the rest.
+    pub fn take_error(&mut self) -> Option<FrequencyError<P::Error>> {
+        core::mem::take(&mut self.refused).then_some(FrequencyError::OutOfRange)
+    }
+
+    /// Writes what has been set, and forgets it. If a pitch or frequency was
+    /// refused, nothing is written and that is the error.
+    pub fn commit(&mut self) -> Result<(), FrequencyError<P::Error>> {
+        let refused = self.take_error();
+        let changes = core::mem::take(&mut self.changes);
+        if let Some(error) = refused {
+            return Err(error);
+        }
+        changes
+            .write(self.psg, self.channel)
+            .map_err(FrequencyError::Psg)
+    }
+}
+
+/// What has been set since the last commit.
+#[derive(Debug, Default)]
+struct Changes {
+    period: Option<u16>,
+    level: Option<ChannelLevel>,
+    tone: Option<bool>,
+    noise: Option<bool>,
+}
+
+impl Changes {
+    fn write<P: Psg>(self, psg: &mut P, channel: Channel) -> Result<(), P::Error> {
+        if let Some(period) = self.period {
+            psg.set_channel_period(channel, period)?;
+        }
+        if self.tone.is_some() || self.noise.is_some() {
+            // Mixer bits are active-low; the other channels' are kept.
+            let mut mixer = psg.registers().mixer();
+            for (bit, enabled) in [(1, self.tone), (8, self.noise)] {
+                let bit = bit << channel.index();
+                match enabled {
+                    Some(true) => mixer &= !bit,
+                    Some(false) => mixer |= bit,
+                    None => {}
+                }
+            }
+            psg.update_register(0x7, mixer)?;
+        }
+        if let Some(level) = self.level {
+            psg.update_channel_level(channel, level)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes anything left uncommitted. An error doing so is lost: commit to
+/// see it.
+impl<P: Psg> Drop for ChannelEditor<'_, P> {
+    fn drop(&mut self) {
+        let _ = self.commit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::tuning::{FrequencyPolicy, OutOfRange};
+
+    #[test]
+    fn changes_land_in_one_burst_in_order() {
+        let mut psg = FakePsg::new();
+        psg.set_register_value(0x7, 0x3F).unwrap();
+        psg.take_writes();
+        let mut editor = psg.channel(Channel::C);
+        editor.level(9).noise(true).period(0x234).tone(true);
+        editor.commit().unwrap();
+        drop(editor);
+        // Period, mixer, level, whatever order they were set in, and nothing
+        // more on drop.
+        assert_eq!(
+            psg.take_writes(),
+            [(0x4, 0x34), (0x5, 0x02), (0x7, 0x1B), (0xA, 9)]
+        );
+        // Dropping without committing writes too.
+        psg.channel(Channel::C).level(3);
+        assert_eq!(psg.take_writes(), [(0xA, 3)]);
+        // And unchanged registers aren't rewritten.
+        psg.channel(Channel::C).period(0x234).level(3).tone(true);
+        assert!(psg.take_writes().is_empty());
+    }
+
+    #[test]
+    fn the_mixer_keeps_the_other_channels_bits() {
+        let mut psg = FakePsg::new();
+        psg.set_register_value(0x7, 0b10_110_010).unwrap();
+        psg.take_writes();
+        psg.channel(Channel::B).tone(true).noise(true);
+        assert_eq!(psg.take_writes(), [(0x7, 0b10_100_000)]);
+        // A's noise is on already, and its tone bit, left unset, stays.
+        psg.channel(Channel::A).noise(true);
+        assert!(psg.take_writes().is_empty());
+        psg.channel(Channel::A).tone(false);
+        assert_eq!(psg.take_writes(), [(0x7, 0b10_100_001)]);
+    }
+
+    #[test]
+    fn refused_frequencies_write_nothing_until_taken_back() {
+        // 2 MHz / (16 * 20 Hz) is 6250, too long for the registers.
+        let mut psg = FakePsg::new();
+        psg.frequency_policy = FrequencyPolicy::default().with_out_of_range(OutOfRange::Error);
+        let mut editor = psg.channel(Channel::A);
+        editor.tone_hz(20).level(15);
+        assert_eq!(editor.commit(), Err(FrequencyError::OutOfRange));
+        drop(editor);
+        assert!(psg.take_writes().is_empty());
+        // Taken back, the rest is written without it.
+        let mut editor = psg.channel(Channel::A);
+        editor.tone_hz(20).level(15);
+        assert_eq!(editor.take_error(), Some(FrequencyError::OutOfRange));
+        assert_eq!(editor.commit(), Ok(()));
+        drop(editor);
+        assert_eq!(psg.take_writes(), [(0x8, 15)]);
+        // Nor does a refusal write anything on drop.
+        psg.channel(Channel::A).tone_hz(20).level(3);
+        assert!(psg.take_writes().is_empty());
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn pitches_follow_the_policy() {
+        use crate::pitch::Note;
+
+        let mut psg = FakePsg::new();
+        psg.channel(Channel::A).pitch(Pitch::new(Note::E, 3));
+        // 2 MHz / (16 * 164.8 Hz) is 758.4.
+        assert_eq!(psg.take_writes(), [(0x0, 0xF6), (0x1, 0x02)]);
+    }
+}
@@ -0,0 +1,200 @@
+//! What sets each chip of the family apart, written down once.
+//!
+//! A [`ChipProfile`] has a chip's envelope resolution, level curve, clock
+//! pin, I/O ports and register banks, and the helpers, the emulator and
+//! the drivers ask it rather than matching on the chip themselves: a
+//! [`ChipKind`] has its profile from [`ChipKind::profile`], and the
+//! packages without both ports theirs through [`IoPorts`](crate::IoPorts).
+
+use crate::volume::{AY8910_DAC, YM2149_DAC};
+use crate::ChipKind;
+
+/// One chip's differences from the rest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChipProfile {
+    pub name: &'static str,
+    /// Steps in one envelope ramp: 32, or 16 held twice as long each.
+    pub envelope_steps: u8,
+    /// The output at each envelope step, full scale 0xFFFF, one entry a
+    /// step. The sixteen fixed levels are spread evenly over it, level 15
+    /// at the top.
+    pub dac: &'static [u16],
+    /// What the chip can divide the clock it is fed by, 1 first.
+    pub prescalers: &'static [u8],
+    /// Whether halving the clock is the YM2149's SEL pin.
+    pub sel_pin: bool,
+    /// I/O ports bonded out: port A, then port B.
+    pub io_ports: u8,
+    /// Whether it has the AY8930's second bank of registers.
+    pub banked: bool,
+}
+
+impl ChipProfile {
+    pub const YM2149: ChipProfile = ChipProfile {
+        name: "YM2149",
+        envelope_steps: 32,
+        dac: &YM2149_DAC,
+        prescalers: &[1, 2],
+        sel_pin: true,
+        io_ports: 2,
+        banked: false,
+    };
+
+    pub const AY8910: ChipProfile = ChipProfile {
+        name: "AY-3-8910",
+        envelope_steps: 16,
+        dac: &AY8910_DAC,
+        prescalers: &[1],
+        sel_pin: false,
+        io_ports: 2,
+        banked: false,
+    };
+
+    pub const AY8912: ChipProfile = ChipProfile {
+        name: "AY-3-8912",
+        io_ports: 1,
+        ..ChipProfile::AY8910
+    };
+
+    pub const AY8913: ChipProfile = ChipProfile {
+        name: "AY-3-8913",
+        io_ports: 0,
+        ..ChipProfile::AY8910
+    };
+
+    /// In standard mode, as it starts; [`ay8930`](crate::ay8930) has the
+    /// rest.
+    pub const AY8930: ChipProfile = ChipProfile {
+        name: "AY8930",
+        banked: true,
+        ..ChipProfile::AY8910
+    };
+
+    /// The YMZ294 and YMZ284, whose 4/6 pin can halve the clock.
+    pub const YMZ294: ChipProfile = ChipProfile {
+        name: "YMZ294",
+        sel_pin: false,
+        io_ports: 0,
+        ..ChipProfile::YM2149
+    };
+
+    pub const ALL: [ChipProfile; 6] = [
+        ChipProfile::YM2149,
+        ChipProfile::AY8910,
+        ChipProfile::AY8912,
+        ChipProfile::AY8913,
+        ChipProfile::AY8930,
+        ChipProfile::YMZ294,
+    ];
+
+    /// The output at fixed `level`, 0 to 15.
+    pub const fn fixed_output(&self, level: u8) -> u16 {
+        let per_level = self.dac.len() / 16;
+        self.dac[(level as usize & 0xF) * per_level + per_level - 1]
+    }
+
+    /// The level the envelope plays at `step`, 0 to 31 in the YM2149's
+    /// steps, counted in this chip's own.
+    pub const fn envelope_level(&self, step: u8) -> u8 {
+        (step & 0x1F) / (32 / self.envelope_steps)
+    }
+
+    /// The output with the envelope at `step`, 0 to 31 in the YM2149's
+    /// steps.
+    pub const fn envelope_output(&self, step: u8) -> u16 {
+        self.dac[self.envelope_level(step) as usize]
+    }
+
+    pub const fn register_count(&self) -> u8 {
+        register_count(self.io_ports)
+    }
+
+    /// Whether `address` is a register the chip has.
+    pub const fn has_register(&self, address: u8) -> bool {
+        address < self.register_count()
+    }
+
+    /// The mixer's direction bits for the ports there are.
+    pub const fn direction_bits(&self) -> u8 {
+        direction_bits(self.io_ports)
+    }
+
+    /// Whether the chip can divide its clock by `prescaler`.
+    pub const fn has_prescaler(&self, prescaler: u8) -> bool {
+        let mut index = 0;
+        while index < self.prescalers.len() {
+            if self.prescalers[index] == prescaler {
+                return true;
+            }
+            index += 1;
+        }
+        false
+    }
+}
+
+impl ChipKind {
+    pub const fn profile(self) -> &'static ChipProfile {
+        match self {
+            ChipKind::Ym2149 => &ChipProfile::YM2149,
+            ChipKind::Ay8910 => &ChipProfile::AY8910,
+            ChipKind::Ymz294 => &ChipProfile::YMZ294,
+        }
+    }
+}
+
+/// Registers on a chip with `io_ports` ports: R14 is port A's and R15
+/// port B's.
+pub(crate) const fn register_count(io_ports: u8) -> u8 {
+    14 + io_ports
+}
+
+/// The mixer's direction bits for `io_ports` ports.
+pub(crate) const fn direction_bits(io_ports: u8) -> u8 {
+    match io_ports {
+        0 => 0,
+        1 => 0b0100_0000,
+        _ => 0b1100_0000,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_ports::{BothPorts, IoPorts, NoPorts, PortAOnly};
+
+    #[test]
+    fn profiles_agree_with_themselves() {
+        for profile in ChipProfile::ALL {
+            assert_eq!(profile.dac.len(), profile.envelope_steps as usize);
+            assert!(matches!(profile.envelope_steps, 16 | 32));
+            assert_eq!(profile.prescalers.first(), Some(&1));
+            assert!(!profile.sel_pin || profile.has_prescaler(2));
+            assert!(profile.io_ports <= 2);
+            assert_eq!(profile.register_count(), 14 + profile.io_ports);
+            assert_eq!(
+                profile.direction_bits().count_ones(),
+                profile.io_ports as u32
+            );
+            assert!(profile.fixed_output(0) <= profile.fixed_output(1));
+            assert_eq!(profile.fixed_output(15), 0xFFFF);
+            assert_eq!(profile.envelope_output(31), 0xFFFF);
+            assert_eq!(profile.banked, profile.name == "AY8930");
+        }
+        for chip in [ChipKind::Ym2149, ChipKind::Ay8910, ChipKind::Ymz294] {
+            assert_eq!(chip.has_sel_pin(), chip.profile().sel_pin);
+        }
+        let ports = |profile: ChipProfile| (profile.register_count(), profile.direction_bits());
+        assert_eq!(
+            ports(ChipProfile::YM2149),
+            (BothPorts::REGISTERS, BothPorts::DIRECTION_BITS)
+        );
+        assert_eq!(
+            ports(ChipProfile::AY8912),
+            (PortAOnly::REGISTERS, PortAOnly::DIRECTION_BITS)
+        );
+        assert_eq!(
+            ports(ChipProfile::AY8913),
+            (NoPorts::REGISTERS, NoPorts::DIRECTION_BITS)
+        );
+    }
+}
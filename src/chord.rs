@@ -0,0 +1,312 @@
+//! Three-note chords spread across channels A, B and C.
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{self, FoldedPeriod, FrequencyError, FrequencyPolicy};
+use crate::{Channel, ChannelLevel};
+
+/// The notes of a chord as semitone offsets from its root.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChordType {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    /// Explicit offsets for channels A, B and C.
+    Intervals([u8; 3]),
+}
+
+impl ChordType {
+    /// Semitones above the root of each chord tone, in root position.
+    pub const fn intervals(self) -> [u8; 3] {
+        match self {
+            ChordType::Major => [0, 4, 7],
+            ChordType::Minor => [0, 3, 7],
+            ChordType::Diminished => [0, 3, 6],
+            ChordType::Augmented => [0, 4, 8],
+            ChordType::Sus2 => [0, 2, 7],
+            ChordType::Sus4 => [0, 5, 7],
+            ChordType::Intervals(offsets) => offsets,
+        }
+    }
+}
+
+/// Which chord tone sits at the bottom.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Inversion {
+    #[default]
+    Root,
+    /// The root moves up an octave.
+    First,
+    /// The root and second note move up an octave.
+    Second,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Voicing {
+    /// All three notes within an octave.
+    #[default]
+    Close,
+    /// The middle note moves up an octave.
+    Spread,
+}
+
+/// A chord type plus how to voice it. A bare [`ChordType`] converts into a
+/// close, root-position chord.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub kind: ChordType,
+    pub inversion: Inversion,
+    pub voicing: Voicing,
+}
+
+impl Chord {
+    pub const fn new(kind: ChordType) -> Chord {
+        Chord {
+            kind,
+            inversion: Inversion::Root,
+            voicing: Voicing::Close,
+        }
+    }
+
+    pub const fn inversion(mut self, inversion: Inversion) -> Chord {
+        self.inversion = inversion;
+        self
+    }
+
+    pub const fn voicing(mut self, voicing: Voicing) -> Chord {
+        self.voicing = voicing;
+        self
+    }
+
+    /// Semitone offsets from the root for channels A, B and C, lowest first.
+    /// Octaves moved up past 255 stop there.
+    pub fn offsets(&self) -> [u8; 3] {
+        let [first, second, third] = self.kind.intervals();
+        let up = |offset: u8| offset.saturating_add(12);
+        let mut offsets = match self.inversion {
+            Inversion::Root => [first, second, third],
+            Inversion::First => [second, third, up(first)],
+            Inversion::Second => [third, up(first), up(second)],
+        };
+        if self.voicing == Voicing::Spread {
+            offsets[1] = up(offsets[1]);
+            offsets.sort_unstable();
+        }
+        offsets
+    }
+}
+
+impl From<ChordType> for Chord {
+    fn from(kind: ChordType) -> Chord {
+        Chord::new(kind)
+    }
+}
+
+/// Periods written for each channel by [`Psg::play_chord`], with any octave
+/// moves made to keep notes in range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChordReport {
+    pub voices: [FoldedPeriod; 3],
+}
+
+impl ChordReport {
+    /// Whether any note had to be moved by an octave.
+    pub fn folded(&self) -> bool {
+        self.voices.iter().any(FoldedPeriod::folded)
+    }
+}
+
+/// Works out the periods for `chord` on `root` without touching the chip.
+/// Notes pushed above G9 by the voicing fall back an octave at a time.
+pub fn voice(clock: u32, root: Pitch, chord: Chord) -> ChordReport {
+    let voices = chord.offsets().map(|offset| {
+        let (pitch, shift) = chord_tone(root, offset);
+        let mut folded = tuning::fold_pitch_period(clock, pitch);
+        folded.octaves += shift;
+        folded
+    });
+    ChordReport { voices }
+}
+
+/// [`voice`] under `policy`, or `None` if it refuses any of the notes.
+pub fn voice_with(
+    clock: u32,
+    root: Pitch,
+    chord: Chord,
+    policy: FrequencyPolicy,
+) -> Option<ChordReport> {
+    let [a, b, c] = chord.offsets().map(|offset| {
+        let (pitch, shift) = chord_tone(root, offset);
+        let mut tone = tuning::pitch_tone(clock, pitch, policy)?;
+        tone.octaves += shift;
+        Some(tone)
+    });
+    Some(ChordReport {
+        voices: [a?, b?, c?],
+    })
+}
+
+/// The note `offset` semitones above `root`, brought back below G9 by
+/// octaves if need be, and how many octaves it moved.
+fn chord_tone(root: Pitch, offset: u8) -> (Pitch, i8) {
+    // Offsets only go up, so an octave below the top always fits.
+    let mut midi = root.midi() as i16 + offset as i16;
+    let mut shift = 0;
+    while midi > 127 {
+        midi -= 12;
+        shift -= 1;
+    }
+    (Pitch::from_midi(midi as u8).unwrap_or(root), shift)
+}
+
+pub(crate) fn play<P: Psg>(
+    psg: &mut P,
+    root: Pitch,
+    chord: Chord,
+    level: u8,
+    policy: FrequencyPolicy,
+) -> Result<ChordReport, FrequencyError<P::Error>> {
+    let report = voice_with(psg.master_clock(), root, chord, policy);
+    let report = report.ok_or(FrequencyError::OutOfRange)?;
+    write(psg, &report, level).map_err(FrequencyError::Psg)?;
+    Ok(report)
+}
+
+fn write<P: Psg>(psg: &mut P, report: &ChordReport, level: u8) -> Result<(), P::Error> {
+    for (channel, folded) in Channel::ALL.into_iter().zip(report.voices) {
+        psg.set_channel_period(channel, folded.period)?;
+        psg.update_channel_level(channel, ChannelLevel::Fixed(level))?;
+        psg.set_tone_enabled(channel, true)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn stop<P: Psg>(psg: &mut P) -> Result<(), P::Error> {
+    for channel in Channel::ALL {
+        psg.update_channel_level(channel, ChannelLevel::Fixed(0))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+
+    #[test]
+    fn inversions_and_voicings() {
+        let major = Chord::new(ChordType::Major);
+        assert_eq!(major.offsets(), [0, 4, 7]);
+        assert_eq!(major.inversion(Inversion::First).offsets(), [4, 7, 12]);
+        assert_eq!(major.inversion(Inversion::Second).offsets(), [7, 12, 16]);
+        assert_eq!(major.voicing(Voicing::Spread).offsets(), [0, 7, 16]);
+    }
+
+    #[test]
+    fn low_roots_fold_up_at_fast_clocks() {
+        // At 2 MHz the lowest reachable note is just below B0.
+        let root = Pitch::new(Note::A, 0);
+        let report = voice(2_000_000, root, ChordType::Minor.into());
+        assert!(report.folded());
+        assert_eq!(report.voices[0].octaves, 1);
+        assert_eq!(report.voices[1].octaves, 0);
+        assert_eq!(report.voices[2].octaves, 0);
+        assert!(report.voices.iter().all(|v| v.period <= 0x0FFF));
+
+        // At 1 MHz every period halves and the same chord fits.
+        let report = voice(1_000_000, root, ChordType::Minor.into());
+        assert!(!report.folded());
+    }
+
+    #[test]
+    fn high_voicings_fall_back_below_g9() {
+        let report = voice(2_000_000, Pitch::new(Note::G, 9), ChordType::Major.into());
+        assert_eq!(report.voices[0].octaves, 0);
+        assert_eq!(report.voices[1].octaves, -1);
+        assert_eq!(report.voices[2].octaves, -1);
+    }
+
+    #[test]
+    fn wide_explicit_intervals_fold_without_overflowing() {
+        let wide = ChordType::Intervals([0, 250, 255]);
+        assert_eq!(
+            Chord::new(wide).inversion(Inversion::Second).offsets(),
+            [255, 12, 255]
+        );
+        assert_eq!(
+            Chord::new(ChordType::Intervals([244, 10, 130]))
+                .inversion(Inversion::First)
+                .voicing(Voicing::Spread)
+                .offsets(),
+            [10, 142, 255]
+        );
+        let c_minus_1 = Pitch::from_midi(0).unwrap();
+        for root in [c_minus_1, Pitch::new(Note::G, 9)] {
+            for inversion in [Inversion::Root, Inversion::First, Inversion::Second] {
+                for voicing in [Voicing::Close, Voicing::Spread] {
+                    let chord = Chord::new(wide).inversion(inversion).voicing(voicing);
+                    for offset in chord.offsets() {
+                        // The same note, as many octaves down as it took.
+                        let (pitch, shift) = chord_tone(root, offset);
+                        let midi = root.midi() as i16 + offset as i16 + 12 * shift as i16;
+                        assert_eq!(pitch.midi() as i16, midi);
+                        assert!(midi > 127 - 12 || shift == 0);
+                    }
+                    voice(2_000_000, root, chord);
+                }
+            }
+        }
+        // 250 semitones over C-1 comes down eleven octaves, to 118.
+        let (pitch, shift) = chord_tone(c_minus_1, 250);
+        assert_eq!((pitch.midi(), shift), (118, -11));
+    }
+
+    #[test]
+    fn play_chord_enables_tone_on_all_channels() {
+        let mut psg = FakePsg::new();
+        psg.set_register_value(0x7, 0b0011_1111).unwrap();
+        let report = psg
+            .play_chord(Pitch::new(Note::C, 4), ChordType::Major, 12)
+            .unwrap();
+        let registers = psg.registers();
+        assert_eq!(registers.tone_period(Channel::A), report.voices[0].period);
+        assert_eq!(registers.tone_period(Channel::C), report.voices[2].period);
+        assert_eq!(registers.mixer(), 0b0011_1000);
+        assert_eq!(registers.value(0x9), 12);
+
+        psg.stop_chord().unwrap();
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(psg.registers().value(0xA), 0);
+    }
+
+    #[test]
+    fn policies_apply_to_every_note() {
+        use crate::tuning::{OutOfRange, Rounding};
+
+        // A0 is below the registers at 2 MHz, and C1 and E1 are in them.
+        let mut psg = FakePsg::new();
+        let root = Pitch::new(Note::A, 0);
+        let refuse = FrequencyPolicy::default().with_out_of_range(OutOfRange::Error);
+        assert_eq!(
+            psg.play_chord_with(root, ChordType::Minor, 12, refuse),
+            Err(FrequencyError::OutOfRange)
+        );
+        assert!(psg.writes.is_empty());
+        let clamp = FrequencyPolicy::new(Rounding::Floor, OutOfRange::Clamp);
+        let report = psg
+            .play_chord_with(root, ChordType::Minor, 12, clamp)
+            .unwrap();
+        assert!(!report.folded());
+        assert_eq!(report.voices[0].period, 0xFFF);
+        assert_eq!(report.voices[0].millihertz, 30_525);
+        assert_eq!(psg.registers().tone_period(Channel::A), 0xFFF);
+        // Rounded up, but the same note as by default.
+        let folded = voice(2_000_000, root, ChordType::Minor.into());
+        assert_eq!(report.voices[1].period, folded.voices[1].period + 1);
+        assert!(report.voices[1].millihertz < folded.voices[1].millihertz);
+    }
+}
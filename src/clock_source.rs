@@ -0,0 +1,236 @@
+//! Master clocks an MCU makes for the chip from one of its timers.
+//!
+//! A timer divides its own clock by whole numbers, so it can't make any
+//! frequency asked of it, only the nearest it has; every period the crate
+//! works out has to be from that one or the notes are out by as much. A
+//! [`ClockSource`] says what a timer can make, as a list or as a function
+//! from what is asked for to what it gives, and
+//! [`Ym2149::set_master_clock_from`](crate::Ym2149::set_master_clock_from)
+//! asks it for a clock and tells the driver the one it got, which it
+//! returns as a [`MasterClock`].
+//!
+//! With the `music` feature, [`ClockSource`]'s `best_clock_for_key` goes a
+//! step further, and picks the clock that plays a key most in tune.
+//!
+//! ```
+//! use ym2149::clock_source::{ClockSource, FnClock, MasterClock};
+//!
+//! // A 48 MHz timer toggling its pin every `n` cycles makes 24 MHz / n.
+//! let timer = FnClock::new(1_000_000, 4_000_000, |hz| {
+//!     let divider = (24_000_000 + hz / 2) / hz.max(1);
+//!     24_000_000 / divider.max(1)
+//! });
+//! // The ZX Spectrum 128's clock is out of reach, 24 MHz / 14 the nearest.
+//! let clock = MasterClock::from_source(&timer, 1_773_400);
+//! assert_eq!(clock.hz, 1_714_285);
+//! assert_eq!(clock.error_ppm(), -33_334);
+//! ```
+
+#[cfg(feature = "music")]
+use crate::pitch::Pitch;
+#[cfg(feature = "music")]
+use crate::theory::Key;
+#[cfg(feature = "music")]
+use crate::tuning;
+
+/// The frequencies something can clock the chip at.
+pub trait ClockSource {
+    /// What it makes when asked for `hz`: the frequency it can make nearest
+    /// to it.
+    fn nearest(&self, hz: u32) -> u32;
+
+    /// Calls `f` with every frequency it can make, in any order, for the
+    /// searches here. A frequency may come more than once.
+    fn for_each_clock(&self, f: &mut dyn FnMut(u32));
+
+    /// The clock it makes that plays `key` most in tune, by
+    /// [`key_error_cents`]. Ties go to the faster clock, whose finer periods
+    /// suit notes outside the ones scored.
+    #[cfg(feature = "music")]
+    fn best_clock_for_key(&self, key: Key) -> u32 {
+        let mut best = self.nearest(tuning::DEFAULT_MASTER_CLOCK);
+        let mut best_error = key_error_cents(best, key);
+        let mut last = None;
+        self.for_each_clock(&mut |clock| {
+            if last == Some(clock) {
+                return;
+            }
+            last = Some(clock);
+            let error = key_error_cents(clock, key);
+            if error < best_error || error == best_error && clock > best {
+                (best, best_error) = (clock, error);
+            }
+        });
+        best
+    }
+}
+
+/// A timer that can make the frequencies listed and nothing else. An empty
+/// list makes whatever is asked of it, as if the clock were a crystal cut
+/// to order.
+impl ClockSource for [u32] {
+    fn nearest(&self, hz: u32) -> u32 {
+        let nearest = self.iter().min_by_key(|&&clock| clock.abs_diff(hz));
+        nearest.copied().unwrap_or(hz)
+    }
+
+    fn for_each_clock(&self, f: &mut dyn FnMut(u32)) {
+        self.iter().for_each(|&clock| f(clock));
+    }
+}
+
+impl<const N: usize> ClockSource for [u32; N] {
+    fn nearest(&self, hz: u32) -> u32 {
+        self.as_slice().nearest(hz)
+    }
+
+    fn for_each_clock(&self, f: &mut dyn FnMut(u32)) {
+        self.as_slice().for_each_clock(f)
+    }
+}
+
+/// A timer whose frequencies a function works out, between two bounds.
+///
+/// The searches find its frequencies by asking for one every
+/// [`FnClock::with_step`] hertz, 1 kHz unless set otherwise, so any it
+/// makes closer together than that may be missed.
+#[derive(Debug, Copy, Clone)]
+pub struct FnClock<F> {
+    low: u32,
+    high: u32,
+    step: u32,
+    nearest: F,
+}
+
+impl<F: Fn(u32) -> u32> FnClock<F> {
+    /// A timer asked for clocks from `low` to `high`, in hertz, which makes
+    /// `nearest(hz)` when asked for `hz`.
+    pub const fn new(low: u32, high: u32, nearest: F) -> FnClock<F> {
+        FnClock {
+            low,
+            high,
+            step: 1000,
+            nearest,
+        }
+    }
+
+    /// How far apart the searches ask for frequencies; 0 is taken as 1.
+    pub const fn with_step(mut self, hz: u32) -> FnClock<F> {
+        self.step = hz;
+        self
+    }
+}
+
+impl<F: Fn(u32) -> u32> ClockSource for FnClock<F> {
+    fn nearest(&self, hz: u32) -> u32 {
+        (self.nearest)(hz)
+    }
+
+    fn for_each_clock(&self, f: &mut dyn FnMut(u32)) {
+        let mut hz = self.low;
+        while hz <= self.high {
+            f((self.nearest)(hz));
+            hz = match hz.checked_add(self.step.max(1)) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+    }
+}
+
+/// A clock asked of a [`ClockSource`], and the one it made, which is the
+/// one periods have to be worked out from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MasterClock {
+    pub requested: u32,
+    pub hz: u32,
+}
+
+impl MasterClock {
+    /// Asks `source` for `requested` hertz.
+    pub fn from_source<S: ClockSource + ?Sized>(source: &S, requested: u32) -> MasterClock {
+        MasterClock {
+            requested,
+            hz: source.nearest(requested),
+        }
+    }
+
+    /// How far the clock made is from the one asked for, in parts per
+    /// million of it: positive when it is fast, and every note sharp.
+    pub fn error_ppm(&self) -> i32 {
+        let requested = self.requested.max(1) as i64;
+        let error = (self.hz as i64 - requested) * 1_000_000;
+        (error / requested).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+    }
+}
+
+/// How far out of tune `key` plays at `clock`: the cents each of its notes
+/// from its root in octave 1 to the top of octave 7 is off by, summed.
+/// Notes out of the period range are played folded, as
+/// [`Psg::set_channel_pitch`](crate::Psg::set_channel_pitch) plays them.
+#[cfg(feature = "music")]
+pub fn key_error_cents(clock: u32, key: Key) -> u32 {
+    let notes = 7 * key.scale.notes_per_octave();
+    (0..notes)
+        .map(|n| note_error_cents(clock, key.degree(1, n)))
+        .sum()
+}
+
+#[cfg(feature = "music")]
+fn note_error_cents(clock: u32, pitch: Pitch) -> u32 {
+    let period = tuning::fold_pitch_period(clock, pitch).period;
+    match tuning::nearest_pitch(clock, period) {
+        Some((_, cents)) => cents.unsigned_abs() as u32,
+        None => 50,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 24 MHz over a whole divider, the nearest to `hz`.
+    fn divided(hz: u32) -> u32 {
+        let divider = (24_000_000 + hz / 2) / hz.max(1);
+        24_000_000 / divider.max(1)
+    }
+
+    #[test]
+    fn sources_make_their_nearest_clock() {
+        let listed = [1_000_000, 2_000_000, 1_773_400];
+        let clock = MasterClock::from_source(&listed, 1_800_000);
+        assert_eq!(clock.hz, 1_773_400);
+        assert_eq!(clock.error_ppm(), -14_777);
+        assert_eq!(listed[..0].nearest(1_234_567), 1_234_567);
+        let timer = FnClock::new(1_000_000, 4_000_000, divided);
+        assert_eq!(MasterClock::from_source(&timer, 2_000_000).error_ppm(), 0);
+        // 24 / 13 MHz is the nearest to 1.8 MHz.
+        assert_eq!(timer.nearest(1_800_000), 1_846_153);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn the_best_clock_is_the_least_out_of_tune() {
+        use crate::pitch::Note;
+        use crate::theory::Scale;
+
+        let a_minor = Key::new(Note::A, Scale::NaturalMinor);
+        let timer = FnClock::new(1_000_000, 4_000_000, divided);
+        // Every clock the timer makes in range, listed.
+        let listed: [u32; 19] = core::array::from_fn(|n| 24_000_000 / (n as u32 + 6));
+        let best = timer.best_clock_for_key(a_minor);
+        assert_eq!(listed.best_clock_for_key(a_minor), best);
+        // Nothing the timer makes is any closer.
+        let error = key_error_cents(best, a_minor);
+        for clock in listed {
+            assert!(key_error_cents(clock, a_minor) >= error, "{clock}");
+        }
+        assert!(error < key_error_cents(2_000_000, a_minor));
+        // Not just the fastest: 24 / 23 MHz is further out than 1 MHz.
+        let slow = [1_000_000, 24_000_000 / 23];
+        assert!(key_error_cents(slow[1], a_minor) > key_error_cents(slow[0], a_minor));
+        assert_eq!(slow.best_clock_for_key(a_minor), 1_000_000);
+        // With only one to choose from, that one.
+        assert_eq!([1_000_000].best_clock_for_key(a_minor), 1_000_000);
+    }
+}
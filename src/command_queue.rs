@@ -0,0 +1,472 @@
+//! A lock-free queue of commands from a main loop to the timer interrupt
+//! playing the music.
+//!
+//! Game code pushes [`Command`]s, to start effects, stop everything or
+//! steer the song, through a [`CommandProducer`]; at the top of each tick
+//! the interrupt applies whatever is waiting, oldest first, through a
+//! [`CommandConsumer`]. Neither side waits for the other: a full queue hands
+//! a push back to the producer, and counts it.
+//!
+//! The queue can sit in a `static`, from which each end is taken once:
+//!
+//! ```
+//! use ym2149::arbiter::{SfxArbiter, SfxPolicy};
+//! use ym2149::command_queue::{Command, CommandQueue};
+//! use ym2149::psg_write::{PsgWrite, Shadowed};
+//! use ym2149::registers::Register;
+//! use ym2149::sequencer::{Sequencer, Song};
+//! use ym2149::sfx::Sfx;
+//! use ym2149::Psg;
+//!
+//! /// Stands in for the chip's bus.
+//! struct Bus;
+//!
+//! impl PsgWrite for Bus {
+//!     type Error = core::convert::Infallible;
+//!
+//!     fn write_register(&mut self, _: Register, _: u8) -> Result<(), Self::Error> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! static COMMANDS: CommandQueue<8> = CommandQueue::new();
+//! static SONG: Song = Song::new(&[], &[]);
+//!
+//! // Main loop.
+//! let mut commands = COMMANDS.take_producer().unwrap();
+//! commands.push(Command::PlaySfx { sfx: Sfx::Coin, priority: 3 }).unwrap();
+//! commands.push(Command::SetSongVolume(64)).unwrap();
+//!
+//! // Timer interrupt, taking its end once and keeping it.
+//! let mut pending = COMMANDS.take_consumer().unwrap();
+//! let mut sequencer = Sequencer::new(&SONG, &[], 50);
+//! let mut arbiter = SfxArbiter::new(Shadowed::new(Bus), SfxPolicy::StealMusic);
+//! pending.apply(&mut sequencer, &mut arbiter, |_, _, _| Ok(())).unwrap();
+//! sequencer.tick(&mut arbiter.music()).unwrap();
+//! arbiter.tick().unwrap();
+//! assert_eq!(sequencer.volume(), 64);
+//! assert_ne!(arbiter.psg().registers().value(0x8), 0);
+//! ```
+//!
+//! Taking the ends from a `static` needs compare-and-swap, which cores such
+//! as the Cortex-M0 lack; there, [`CommandQueue::split`] a queue held in a
+//! `&'static mut` from a singleton instead.
+
+use core::cell::UnsafeCell;
+#[cfg(target_has_atomic = "8")]
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::arbiter::SfxArbiter;
+use crate::psg::Psg;
+use crate::sequencer::{Sequencer, SequencerHooks};
+use crate::sfx::Sfx;
+use crate::storage::Storage;
+
+/// Something game code asks of the music and effects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command<U = ()> {
+    /// Starts `sfx` as [`SfxArbiter::trigger_sfx`] does. One refused for
+    /// want of a channel is dropped.
+    PlaySfx { sfx: Sfx, priority: u8 },
+    /// Ends the song and every effect, and silences the chip.
+    StopAll,
+    /// Sets the song's volume, 0 to 127, as [`Sequencer::set_volume`] does.
+    SetSongVolume(u8),
+    /// Moves to an order entry as [`Sequencer::jump_to_order`] does. One
+    /// past the end of the song is dropped.
+    JumpToOrder(usize),
+    /// Transposes the song as [`Sequencer::set_transpose`] does.
+    SetTranspose(i8),
+    /// Anything else, for the game's own handler.
+    User(U),
+}
+
+impl<U> Command<U> {
+    /// Carries the command out on `sequencer` and `arbiter`, handing a
+    /// [`Command::User`] to `user` with both.
+    pub fn apply<H, S, P, F>(
+        self,
+        sequencer: &mut Sequencer<H, S>,
+        arbiter: &mut SfxArbiter<P>,
+        user: F,
+    ) -> Result<(), P::Error>
+    where
+        H: SequencerHooks,
+        S: Storage,
+        P: Psg,
+        F: FnOnce(U, &mut Sequencer<H, S>, &mut SfxArbiter<P>) -> Result<(), P::Error>,
+    {
+        match self {
+            Command::PlaySfx { sfx, priority } => {
+                arbiter.trigger_sfx(sfx, priority);
+            }
+            Command::StopAll => {
+                sequencer.stop(&mut arbiter.music())?;
+                arbiter.reset()?;
+            }
+            Command::SetSongVolume(volume) => sequencer.set_volume(volume),
+            Command::JumpToOrder(order) => {
+                sequencer.jump_to_order(order);
+            }
+            Command::SetTranspose(semitones) => sequencer.set_transpose(semitones),
+            Command::User(command) => user(command, sequencer, arbiter)?,
+        }
+        Ok(())
+    }
+}
+
+/// Returned by [`CommandProducer::push`] when the queue is full, with the
+/// command turned away.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QueueFull<U = ()>(pub Command<U>);
+
+/// Room for `N` commands, shared by one producer and one consumer.
+///
+/// Only loads and stores are used on the atomics, never read-modify-write,
+/// other than to take the ends from a shared queue.
+pub struct CommandQueue<const N: usize, U = ()> {
+    commands: UnsafeCell<[Option<Command<U>>; N]>,
+    /// Commands taken so far, written only by the consumer.
+    head: AtomicUsize,
+    /// Commands pushed so far, written only by the producer.
+    tail: AtomicUsize,
+    overruns: AtomicU32,
+    #[cfg(target_has_atomic = "8")]
+    producer_taken: AtomicBool,
+    #[cfg(target_has_atomic = "8")]
+    consumer_taken: AtomicBool,
+}
+
+// The producer only writes slots the consumer has finished with, and the
+// consumer only takes from slots the producer has published, as `head` and
+// `tail` record. Either `split` taking `&mut self` or the ends being taken
+// once makes sure there is one of each. Commands move between contexts, so
+// they have to be `Send`.
+unsafe impl<const N: usize, U: Send> Sync for CommandQueue<N, U> {}
+
+impl<const N: usize, U> Default for CommandQueue<N, U> {
+    fn default() -> CommandQueue<N, U> {
+        CommandQueue::new()
+    }
+}
+
+impl<const N: usize, U> CommandQueue<N, U> {
+    pub const fn new() -> CommandQueue<N, U> {
+        CommandQueue {
+            commands: UnsafeCell::new([const { None }; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overruns: AtomicU32::new(0),
+            #[cfg(target_has_atomic = "8")]
+            producer_taken: AtomicBool::new(false),
+            #[cfg(target_has_atomic = "8")]
+            consumer_taken: AtomicBool::new(false),
+        }
+    }
+
+    /// The two ends of the queue, emptied and with the counter cleared.
+    pub fn split(&mut self) -> (CommandProducer<'_, N, U>, CommandConsumer<'_, N, U>) {
+        for command in self.commands.get_mut() {
+            *command = None;
+        }
+        *self.head.get_mut() = 0;
+        *self.tail.get_mut() = 0;
+        *self.overruns.get_mut() = 0;
+        (
+            CommandProducer { queue: self },
+            CommandConsumer { queue: self },
+        )
+    }
+
+    /// The producing end, the first time it is asked for; `None` after
+    /// that.
+    #[cfg(target_has_atomic = "8")]
+    pub fn take_producer(&self) -> Option<CommandProducer<'_, N, U>> {
+        match self.producer_taken.swap(true, Ordering::AcqRel) {
+            true => None,
+            false => Some(CommandProducer { queue: self }),
+        }
+    }
+
+    /// The consuming end, the first time it is asked for; `None` after that.
+    #[cfg(target_has_atomic = "8")]
+    pub fn take_consumer(&self) -> Option<CommandConsumer<'_, N, U>> {
+        match self.consumer_taken.swap(true, Ordering::AcqRel) {
+            true => None,
+            false => Some(CommandConsumer { queue: self }),
+        }
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    /// Commands turned away because the queue was full.
+    pub fn overruns(&self) -> u32 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+}
+
+/// The main loop's end of a [`CommandQueue`].
+pub struct CommandProducer<'a, const N: usize, U = ()> {
+    queue: &'a CommandQueue<N, U>,
+}
+
+impl<const N: usize, U> CommandProducer<'_, N, U> {
+    pub fn has_room(&self) -> bool {
+        self.queue.len() < N
+    }
+
+    /// Commands waiting to be applied.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.len() == 0
+    }
+
+    /// Adds a command to the back of the queue, or hands it back and counts
+    /// an overrun if it is full.
+    pub fn push(&mut self, command: Command<U>) -> Result<(), QueueFull<U>> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.queue.head.load(Ordering::Acquire)) >= N {
+            let overruns = &self.queue.overruns;
+            overruns.store(
+                overruns.load(Ordering::Relaxed).wrapping_add(1),
+                Ordering::Relaxed,
+            );
+            return Err(QueueFull(command));
+        }
+        // SAFETY: the slot is outside head..tail, so the consumer isn't
+        // reading it, and only this producer writes.
+        unsafe {
+            (*self.queue.commands.get())[tail % N] = Some(command);
+        }
+        self.queue
+            .tail
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn overruns(&self) -> u32 {
+        self.queue.overruns()
+    }
+}
+
+/// The interrupt's end of a [`CommandQueue`].
+pub struct CommandConsumer<'a, const N: usize, U = ()> {
+    queue: &'a CommandQueue<N, U>,
+}
+
+impl<const N: usize, U> CommandConsumer<'_, N, U> {
+    /// The oldest command waiting, if any.
+    pub fn pop(&mut self) -> Option<Command<U>> {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if self.queue.tail.load(Ordering::Acquire) == head {
+            return None;
+        }
+        // SAFETY: the slot is inside head..tail, so the producer has
+        // published it and won't touch it until head moves past.
+        let command = unsafe { (*self.queue.commands.get())[head % N].take() };
+        self.queue
+            .head
+            .store(head.wrapping_add(1), Ordering::Release);
+        command
+    }
+
+    /// Applies every command waiting, oldest first, as [`Command::apply`]
+    /// does, returning how many. Commands pushed meanwhile wait for the
+    /// next call, so a busy producer can't hold the tick up. After an error
+    /// the rest wait too.
+    pub fn apply<H, S, P, F>(
+        &mut self,
+        sequencer: &mut Sequencer<H, S>,
+        arbiter: &mut SfxArbiter<P>,
+        mut user: F,
+    ) -> Result<usize, P::Error>
+    where
+        H: SequencerHooks,
+        S: Storage,
+        P: Psg,
+        F: FnMut(U, &mut Sequencer<H, S>, &mut SfxArbiter<P>) -> Result<(), P::Error>,
+    {
+        let waiting = self.queue.len();
+        for applied in 0..waiting {
+            let Some(command) = self.pop() else {
+                return Ok(applied);
+            };
+            command.apply(sequencer, arbiter, &mut user)?;
+        }
+        Ok(waiting)
+    }
+
+    pub fn overruns(&self) -> u32 {
+        self.queue.overruns()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::arbiter::SfxPolicy;
+    use crate::instrument::Instrument;
+    use crate::pitch::{Note, Pitch};
+    use crate::sequencer::{Cell, OrderEntry, Pattern, Row, Song};
+    use crate::test_support::FakePsg;
+    use crate::Channel;
+    use std::vec::Vec;
+
+    static PATTERNS: [Pattern; 1] = [Pattern::new(&[Row::new(
+        Cell::note(Pitch::new(Note::C, 4)),
+        Cell::EMPTY,
+        Cell::EMPTY,
+    )])];
+    static SONG: Song = Song::new(&PATTERNS, &[OrderEntry::new(0), OrderEntry::new(0)]);
+    static BANK: [Instrument; 0] = [];
+
+    #[test]
+    fn interleaved_contexts_keep_order_and_report_overflow() {
+        let mut queue = CommandQueue::<3, u32>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let (mut pushed, mut taken, mut rejected) = (0, 0, 0);
+        let mut seed = 0x2545_F491u32;
+        for _ in 0..5000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            if seed & 1 == 0 {
+                match producer.push(Command::User(pushed)) {
+                    Ok(()) => pushed += 1,
+                    Err(QueueFull(command)) => {
+                        // The producer gets its command back.
+                        assert_eq!(command, Command::User(pushed));
+                        rejected += 1;
+                    }
+                }
+            } else if let Some(command) = consumer.pop() {
+                assert_eq!(command, Command::User(taken));
+                taken += 1;
+            }
+        }
+        assert!(rejected > 0 && taken > 0);
+        assert_eq!(consumer.overruns(), rejected);
+        assert_eq!(pushed - taken, producer.len() as u32);
+    }
+
+    #[test]
+    fn overflow_turns_the_newest_away() {
+        let mut queue = CommandQueue::<2>::new();
+        let (mut producer, mut consumer) = queue.split();
+        producer.push(Command::SetTranspose(1)).unwrap();
+        producer.push(Command::SetTranspose(2)).unwrap();
+        assert!(!producer.has_room());
+        assert_eq!(
+            producer.push(Command::StopAll),
+            Err(QueueFull(Command::StopAll))
+        );
+        assert_eq!(producer.overruns(), 1);
+        assert_eq!(consumer.pop(), Some(Command::SetTranspose(1)));
+        producer.push(Command::SetTranspose(3)).unwrap();
+        assert_eq!(consumer.pop(), Some(Command::SetTranspose(2)));
+        assert_eq!(consumer.pop(), Some(Command::SetTranspose(3)));
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn commands_apply_in_order_at_the_top_of_the_tick() {
+        let mut queue = CommandQueue::<8, Channel>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut sequencer = Sequencer::new(&SONG, &BANK, 50);
+        let mut arbiter = SfxArbiter::new(FakePsg::new(), SfxPolicy::StealMusic);
+        for command in [
+            Command::SetTranspose(5),
+            Command::SetTranspose(-2),
+            Command::SetSongVolume(64),
+            Command::JumpToOrder(1),
+            Command::JumpToOrder(9),
+            Command::PlaySfx {
+                sfx: Sfx::Coin,
+                priority: 1,
+            },
+            Command::User(Channel::B),
+        ] {
+            producer.push(command).unwrap();
+        }
+        let mut users = Vec::new();
+        let applied = consumer.apply(&mut sequencer, &mut arbiter, |channel, _, arbiter| {
+            users.push(channel);
+            arbiter.set_music_channel(channel, Some(2));
+            Ok(())
+        });
+        assert_eq!(applied, Ok(7));
+        // The last transpose wins, and a jump off the end changes nothing.
+        assert_eq!(sequencer.transpose(), -2);
+        assert_eq!(sequencer.volume(), 64);
+        assert_eq!(users, [Channel::B]);
+        sequencer.tick(&mut arbiter.music()).unwrap();
+        arbiter.tick().unwrap();
+        assert!(!sequencer.is_finished());
+        // The coin took A, the first free channel.
+        assert_ne!(arbiter.psg().registers().value(0x8), 0);
+
+        producer.push(Command::StopAll).unwrap();
+        assert_eq!(
+            consumer.apply(&mut sequencer, &mut arbiter, |_, _, _| Ok(())),
+            Ok(1)
+        );
+        assert!(sequencer.is_finished());
+        arbiter.tick().unwrap();
+        assert_eq!(arbiter.psg().registers().value(0x8), 0);
+        // Nothing waiting, nothing done.
+        assert_eq!(
+            consumer.apply(&mut sequencer, &mut arbiter, |_, _, _| Ok(())),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn ends_are_taken_from_a_static_once() {
+        static QUEUE: CommandQueue<4> = CommandQueue::new();
+        let mut producer = QUEUE.take_producer().unwrap();
+        assert!(QUEUE.take_producer().is_none());
+        let mut consumer = QUEUE.take_consumer().unwrap();
+        assert!(QUEUE.take_consumer().is_none());
+        producer.push(Command::SetSongVolume(10)).unwrap();
+        assert_eq!(consumer.pop(), Some(Command::SetSongVolume(10)));
+    }
+
+    #[test]
+    fn threads_hammering_both_ends() {
+        const COMMANDS: u32 = 20_000;
+        static QUEUE: CommandQueue<8, u32> = CommandQueue::new();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut producer = QUEUE.take_producer().unwrap();
+                for n in 0..COMMANDS {
+                    let mut command = Command::User(n);
+                    while let Err(QueueFull(back)) = producer.push(command) {
+                        command = back;
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut consumer = QUEUE.take_consumer().unwrap();
+            let mut expected = 0;
+            while expected < COMMANDS {
+                match consumer.pop() {
+                    Some(command) => {
+                        assert_eq!(command, Command::User(expected));
+                        expected += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+    }
+}
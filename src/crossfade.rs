@@ -0,0 +1,265 @@
+//! Morphing from one sound to another over a number of ticks, for scene
+//! transitions: from a snapshot of the registers, usually what the chip is
+//! playing already, to another.
+//!
+//! Fixed levels move in equal steps along the way, even to the ear as the
+//! chip's levels are about 3 dB apart. A channel going between the envelope
+//! and a fixed level is on the envelope for its half of the fade, and fades
+//! from or to level 15, the envelope's loudest, for the other.
+//!
+//! Tone periods switch halfway through, as do the mixer, the noise period
+//! and the envelope period, unless the channel is set to glide with
+//! [`Crossfade::with_glide`], when its period slides there as a smooth
+//! [`Glissando`](crate::glissando::Glissando) does. R13 is written on the
+//! last tick, and only if the target has it, so the envelope restarts once
+//! the rest is in place. Registers the target doesn't have are left alone,
+//! as are the I/O ports.
+
+use crate::glissando::div_round;
+use crate::mute::ChannelMask;
+use crate::psg::Psg;
+use crate::registers::{self, Registers};
+use crate::{Channel, ChannelLevel};
+
+/// A fade from one set of registers to another over a number of ticks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crossfade {
+    /// `None` until the first tick, for a fade from the chip's registers.
+    from: Option<Registers>,
+    to: Registers,
+    ticks: u16,
+    elapsed: u16,
+    glide: ChannelMask,
+}
+
+impl Crossfade {
+    /// A fade from `from` to `to` over `ticks` calls to [`Crossfade::tick`],
+    /// the last writing `to`. Over 0 ticks it is over 1: `to` is written
+    /// whole on the first.
+    pub const fn new(from: Registers, to: Registers, ticks: u16) -> Crossfade {
+        Crossfade {
+            from: Some(from),
+            to,
+            ticks,
+            elapsed: 0,
+            glide: ChannelMask::empty(),
+        }
+    }
+
+    /// A fade to `to` from whatever the chip is playing on the first tick,
+    /// as its shadow registers have it.
+    pub const fn towards(to: Registers, ticks: u16) -> Crossfade {
+        Crossfade {
+            from: None,
+            ..Crossfade::new(Registers::new(), to, ticks)
+        }
+    }
+
+    /// Slides the tone periods of `channels` from one to the other rather
+    /// than switching them halfway.
+    pub const fn with_glide(mut self, channels: ChannelMask) -> Crossfade {
+        self.glide = channels;
+        self
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.elapsed >= self.ticks.max(1)
+    }
+
+    /// Writes the next step of the fade. Returns whether it has reached the
+    /// target; ticks after that write nothing.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        if self.is_done() {
+            return Ok(true);
+        }
+        let from = *self.from.get_or_insert(*psg.registers());
+        let to = &self.to;
+        self.elapsed += 1;
+        let step = Step {
+            step: self.elapsed as i32,
+            ticks: self.ticks.max(1) as i32,
+        };
+
+        for channel in Channel::ALL {
+            let (fine, rough) = channel.period_registers();
+            if to.is_written(fine) || to.is_written(rough) {
+                let (start, end) = (from.tone_period(channel), to.tone_period(channel));
+                let period = match self.glide.has(channel) {
+                    true => step.between(start, end),
+                    false => step.either(start, end),
+                };
+                psg.set_channel_period(channel, period)?;
+            }
+        }
+        for address in [0x6, 0x7, 0xB, 0xC] {
+            if !to.is_written(address) {
+                continue;
+            }
+            let value = match address {
+                0x7 => {
+                    let mixer = step.either(from.mixer(), to.mixer());
+                    registers::frame_mixer(mixer, psg.registers().mixer())
+                }
+                _ => step.either(from.value(address), to.value(address)),
+            };
+            psg.update_register(address, value)?;
+        }
+        for channel in Channel::ALL {
+            let address = channel.level_register();
+            if let Some(end) = to.get(address) {
+                let level = step.level(from.value(address), end);
+                psg.update_channel_level(channel, level)?;
+            }
+        }
+        if let (true, Some(shape)) = (self.is_done(), to.get(0xD)) {
+            psg.set_register_value(0xD, shape)?;
+        }
+        Ok(self.is_done())
+    }
+}
+
+/// How far through a fade a tick is.
+#[derive(Debug, Copy, Clone)]
+struct Step {
+    /// From 1 to `ticks`.
+    step: i32,
+    ticks: i32,
+}
+
+impl Step {
+    fn halfway(self) -> bool {
+        self.step * 2 >= self.ticks
+    }
+
+    /// `start` before halfway, `end` from then on.
+    fn either<T>(self, start: T, end: T) -> T {
+        match self.halfway() {
+            true => end,
+            false => start,
+        }
+    }
+
+    /// The value this far from `start` to `end`.
+    fn between(self, start: u16, end: u16) -> u16 {
+        let (start, end) = (start as i32, end as i32);
+        (start + div_round((end - start) * self.step, self.ticks)) as u16
+    }
+
+    /// The level this far from level register value `start` to `end`.
+    fn level(self, start: u8, end: u8) -> ChannelLevel {
+        if self.either(start, end) & 0x10 != 0 {
+            return ChannelLevel::Envelope;
+        }
+        let fixed = |value: u8| match value & 0x10 {
+            0 => value as u16 & 0xF,
+            _ => 15,
+        };
+        ChannelLevel::Fixed(self.between(fixed(start), fixed(end)) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    /// A on a tone at `period`, at level register value `level`.
+    fn playing(period: u16, level: u8, mixer: u8) -> Registers {
+        let mut registers = Registers::new();
+        registers.set(0x0, period as u8);
+        registers.set(0x1, (period >> 8) as u8);
+        registers.set(0x7, mixer);
+        registers.set(0x8, level);
+        registers
+    }
+
+    /// What A's period, its level and the mixer are after each tick.
+    fn run(fade: &mut Crossfade, psg: &mut FakePsg) -> Vec<(u16, u8, u8)> {
+        let mut states = Vec::new();
+        while !fade.is_done() {
+            fade.tick(psg).unwrap();
+            let registers = psg.registers();
+            states.push((
+                registers.tone_period(Channel::A),
+                registers.value(0x8),
+                registers.mixer(),
+            ));
+        }
+        states
+    }
+
+    #[test]
+    fn levels_fade_evenly_and_the_rest_switches_halfway() {
+        let mut psg = FakePsg::new();
+        psg.write_frame(playing(0x100, 15, 0x3E).values()).unwrap();
+        let mut fade = Crossfade::towards(playing(0x200, 3, 0x36), 4);
+        assert_eq!(
+            run(&mut fade, &mut psg),
+            [
+                (0x100, 12, 0x3E),
+                (0x200, 9, 0x36),
+                (0x200, 6, 0x36),
+                (0x200, 3, 0x36),
+            ]
+        );
+        psg.take_writes();
+        assert!(fade.tick(&mut psg).unwrap());
+        assert!(psg.writes.is_empty());
+    }
+
+    #[test]
+    fn gliding_channels_slide_their_periods() {
+        let mut psg = FakePsg::new();
+        let from = playing(0x100, 10, 0x3E);
+        let mut fade = Crossfade::new(from, playing(0x180, 10, 0x3E), 4)
+            .with_glide(ChannelMask::A | ChannelMask::C);
+        let periods: Vec<_> = run(&mut fade, &mut psg)
+            .into_iter()
+            .map(|(period, ..)| period)
+            .collect();
+        assert_eq!(periods, [0x120, 0x140, 0x160, 0x180]);
+    }
+
+    #[test]
+    fn the_envelope_holds_for_its_half_of_the_fade() {
+        let mut psg = FakePsg::new();
+        let mut fade = Crossfade::new(playing(0x100, 0x10, 0x3E), playing(0x100, 5, 0x3E), 4);
+        let levels: Vec<_> = run(&mut fade, &mut psg)
+            .into_iter()
+            .map(|(_, level, _)| level)
+            .collect();
+        // From 15, the envelope's loudest, once it lets go.
+        assert_eq!(levels, [0x10, 10, 7, 5]);
+
+        let mut fade = Crossfade::new(playing(0x100, 5, 0x3E), playing(0x100, 0x10, 0x3E), 3);
+        let levels: Vec<_> = run(&mut fade, &mut psg)
+            .into_iter()
+            .map(|(_, level, _)| level)
+            .collect();
+        assert_eq!(levels, [8, 0x10, 0x10]);
+    }
+
+    #[test]
+    fn no_ticks_writes_the_target_at_once() {
+        let mut psg = FakePsg::new();
+        psg.write_frame(playing(0x100, 15, 0x3E).values()).unwrap();
+        psg.take_writes();
+        let mut to = playing(0x200, 0, 0x3F);
+        to.set(0xB, 0x40);
+        to.set(0xD, 0x0E);
+        let mut fade = Crossfade::towards(to, 0);
+        assert!(fade.tick(&mut psg).unwrap());
+        assert_eq!(
+            psg.take_writes(),
+            [(0x1, 0x02), (0x7, 0x3F), (0xB, 0x40), (0x8, 0), (0xD, 0x0E)]
+        );
+        // R13 once, on the last tick, and only if the target has it.
+        let mut fade = Crossfade::towards(playing(0x200, 0, 0x3F), 2);
+        fade.tick(&mut psg).unwrap();
+        fade.tick(&mut psg).unwrap();
+        assert!(psg.take_writes().is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+//! Bytes read through a trait rather than sliced, so song data can stay
+//! somewhere a plain load can't reach.
+//!
+//! On AVR, `const` and `static` data is copied into SRAM at start-up unless
+//! it is placed in program memory, and program memory can only be read with
+//! the `lpm` instruction. The parsers and players of byte formats (raw
+//! frames through [`FrameData`](crate::frame_player::FrameData), `.psg`
+//! streams through `psg_file::DataBytes`, and AYFX effects and banks) read
+//! through [`DataSource`], so a song can sit in flash and take up no RAM.
+//! Slices are a [`DataSource`] costing nothing over slicing; with the
+//! `avr-progmem` feature, `ProgMem` reads program memory.
+//!
+//! Patterns, songs and instruments are Rust values, not bytes, and are read
+//! as such: on AVR, keep songs as frames or `.psg` data.
+
+/// Bytes read by offset.
+pub trait DataSource {
+    /// How many bytes there are.
+    fn len(&self) -> usize;
+
+    /// Fills `buffer` with the bytes from `offset` on. The bytes must all be
+    /// there: `offset + buffer.len()` is at most [`DataSource::len`].
+    fn read(&self, offset: usize, buffer: &mut [u8]);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The byte at `offset`, if there is one.
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.array::<1>(offset).map(|[byte]| byte)
+    }
+
+    /// The `N` bytes from `offset` on, if there are that many.
+    fn array<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        if offset.checked_add(N)? > self.len() {
+            return None;
+        }
+        let mut bytes = [0; N];
+        self.read(offset, &mut bytes);
+        Some(bytes)
+    }
+}
+
+impl DataSource for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self[offset..offset + buffer.len()]);
+    }
+}
+
+impl<const N: usize> DataSource for [u8; N] {
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        self.as_slice().read(offset, buffer);
+    }
+}
+
+impl<D: DataSource + ?Sized> DataSource for &D {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        (**self).read(offset, buffer);
+    }
+}
+
+/// Bytes in AVR program memory, read with `lpm`. Elsewhere, as in tests on
+/// the host, they are read as a slice.
+///
+/// Only the pointer and length are ever held in RAM: the bytes are never
+/// loaded directly, not even to compare or print them.
+#[cfg(feature = "avr-progmem")]
+#[derive(Copy, Clone)]
+pub struct ProgMem {
+    data: &'static [u8],
+}
+
+#[cfg(feature = "avr-progmem")]
+impl ProgMem {
+    /// The bytes of `data`, which [`progmem!`](crate::progmem) places in
+    /// program memory.
+    ///
+    /// # Safety
+    ///
+    /// On AVR, `data` must be in program memory, in a static with
+    /// `#[link_section = ".progmem.data"]`; anything else reads whatever
+    /// flash is at its address.
+    pub const unsafe fn new(data: &'static [u8]) -> ProgMem {
+        ProgMem { data }
+    }
+}
+
+#[cfg(feature = "avr-progmem")]
+impl core::fmt::Debug for ProgMem {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ProgMem")
+            .field("address", &self.data.as_ptr())
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "avr-progmem")]
+impl DataSource for ProgMem {
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        // Taking a subslice only does arithmetic on the pointer.
+        let bytes = &self.data[offset..offset + buffer.len()];
+        #[cfg(target_arch = "avr")]
+        for (n, byte) in buffer.iter_mut().enumerate() {
+            // Safety: `new`'s caller promised the bytes are in program
+            // memory, and the slice above checked they're in range.
+            *byte = unsafe { load_program_memory(bytes.as_ptr().add(n)) };
+        }
+        #[cfg(not(target_arch = "avr"))]
+        buffer.copy_from_slice(bytes);
+    }
+}
+
+/// The byte at `address` in program memory.
+#[cfg(all(feature = "avr-progmem", target_arch = "avr"))]
+unsafe fn load_program_memory(address: *const u8) -> u8 {
+    let byte;
+    core::arch::asm!("lpm {}, Z", out(reg) byte, in("Z") address);
+    byte
+}
+
+/// Declares a [`ProgMem`] static holding the bytes of an array expression,
+/// such as `include_bytes!`, placed in program memory.
+///
+/// ```
+/// ym2149::progmem! {
+///     static CHIME = b"\xAF\x1C\x01\x8C\xD0\x20";
+/// }
+/// ```
+#[cfg(feature = "avr-progmem")]
+#[macro_export]
+macro_rules! progmem {
+    ($(#[$attribute:meta])* $visibility:vis static $name:ident = $data:expr;) => {
+        $(#[$attribute])*
+        $visibility static $name: $crate::data_source::ProgMem = {
+            #[link_section = ".progmem.data"]
+            static DATA: [u8; <[u8]>::len($data)] = *$data;
+            // Safety: `DATA` is in program memory.
+            unsafe { $crate::data_source::ProgMem::new(&DATA) }
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Inverted;
+
+    #[test]
+    fn reads_stay_in_bounds() {
+        let data = [1, 2, 3, 4];
+        assert_eq!(data.byte(3), Some(4));
+        assert_eq!(data.byte(4), None);
+        assert_eq!(data[..].array::<2>(1), Some([2, 3]));
+        assert_eq!(data.array::<2>(3), None);
+        assert_eq!(data.array::<2>(usize::MAX), None);
+        assert_eq!(Inverted::new(&data).array::<4>(0), Some(data));
+        assert!(DataSource::is_empty(&Inverted::new(&[])));
+    }
+
+    #[cfg(feature = "avr-progmem")]
+    #[test]
+    fn progmem_reads_as_the_bytes_it_holds() {
+        crate::progmem! {
+            static DATA = b"\x01\x02\x03";
+        }
+        assert_eq!(DATA.len(), 3);
+        assert_eq!(DATA.array::<2>(1), Some([2, 3]));
+        assert_eq!(DATA.byte(3), None);
+    }
+}
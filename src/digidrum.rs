@@ -0,0 +1,341 @@
+//! Digidrums: 4-bit samples played by rewriting a channel's level register
+//! thousands of times a second.
+//!
+//! With tone and noise both disabled in the mixer a channel's output sits at
+//! its level, so the level register becomes a crude 4-bit DAC. Sample values
+//! are written as they are, so they are level register values on the chip's
+//! logarithmic scale, as in the Atari ST digidrum format, not linear PCM.
+//!
+//! The achievable sample rate is set by the cost of one register write.
+//! [`Ym2149::play_sample`] latches the level register's address once, so
+//! each sample costs a single data cycle: eight data pin updates, four
+//! control pin updates and the driver's two 1 µs settle delays. Measure that
+//! on the target (toggle a spare pin around a write, or time a long sample)
+//! and pass it to [`Ym2149::set_sample_write_cost`]. The highest usable rate is
+//! one sample per write time: a write costing 5 µs tops out at 200 kHz, and
+//! the classic 4 to 12 kHz drums then leave most of the CPU free. From a
+//! timer interrupt, [`DigidrumPlayer::tick_sample`] goes through
+//! [`Psg::update_register`], paying for the address cycle too and so
+//! roughly doubling the per-sample cost, but skipping repeated values.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+
+use crate::psg::Psg;
+use crate::{Channel, Error, IoPorts, Ym2149};
+
+/// How a [`Sample`]'s bytes hold its values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Packing {
+    Nibbles,
+    Levels,
+    Pcm,
+    SignedPcm,
+}
+
+/// The smallest 8-bit amplitude for each level from 1 up: the geometric
+/// midpoints between the levels' amplitudes, 3 dB apart below full scale.
+const PCM_THRESHOLDS: [u8; 15] = [2, 3, 4, 5, 7, 10, 14, 19, 27, 38, 54, 76, 108, 152, 215];
+
+/// The level nearest an unsigned 8-bit amplitude.
+pub const fn pcm_level(amplitude: u8) -> u8 {
+    let mut level = 0;
+    while level < 15 && amplitude >= PCM_THRESHOLDS[level as usize] {
+        level += 1;
+    }
+    level
+}
+
+/// Sample data in one of the layouts drums come in, read as levels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sample<'a> {
+    data: &'a [u8],
+    packing: Packing,
+}
+
+impl<'a> Sample<'a> {
+    /// Packed 4-bit levels, two per byte, high nibble first.
+    pub const fn new(data: &'a [u8]) -> Sample<'a> {
+        Sample {
+            data,
+            packing: Packing::Nibbles,
+        }
+    }
+
+    /// One level per byte, in the low nibble, as YM files store 4-bit drums.
+    pub const fn levels(data: &'a [u8]) -> Sample<'a> {
+        Sample {
+            data,
+            packing: Packing::Levels,
+        }
+    }
+
+    /// Unsigned 8-bit amplitudes, turned into the nearest level as they
+    /// play. YM files store most drums like this.
+    pub const fn pcm(data: &'a [u8]) -> Sample<'a> {
+        Sample {
+            data,
+            packing: Packing::Pcm,
+        }
+    }
+
+    /// Signed 8-bit amplitudes, from -128 for silence up.
+    pub const fn signed_pcm(data: &'a [u8]) -> Sample<'a> {
+        Sample {
+            data,
+            packing: Packing::SignedPcm,
+        }
+    }
+
+    /// Number of samples: two per byte when packed, otherwise one.
+    pub const fn len(&self) -> usize {
+        match self.packing {
+            Packing::Nibbles => self.data.len() * 2,
+            _ => self.data.len(),
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn get(&self, n: usize) -> Option<u8> {
+        Some(match self.packing {
+            Packing::Nibbles => {
+                let byte = *self.data.get(n / 2)?;
+                if n.is_multiple_of(2) {
+                    byte >> 4
+                } else {
+                    byte & 0x0F
+                }
+            }
+            Packing::Levels => *self.data.get(n)? & 0x0F,
+            Packing::Pcm => pcm_level(*self.data.get(n)?),
+            Packing::SignedPcm => pcm_level(*self.data.get(n)? ^ 0x80),
+        })
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = u8> + 'a {
+        let sample = *self;
+        (0..sample.len()).filter_map(move |n| sample.get(n))
+    }
+}
+
+/// Channel state a sample overwrites, put back when it ends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Saved {
+    period: u16,
+    level: u8,
+    mixer: u8,
+}
+
+/// Plays a [`Sample`] one value per call to [`DigidrumPlayer::tick_sample`],
+/// meant to be driven from a timer interrupt at the sample rate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigidrumPlayer<'a> {
+    playing: Option<(Channel, Sample<'a>)>,
+    position: usize,
+    saved: Saved,
+    dac_setup: bool,
+}
+
+impl<'a> DigidrumPlayer<'a> {
+    pub const fn new() -> DigidrumPlayer<'a> {
+        DigidrumPlayer {
+            playing: None,
+            position: 0,
+            saved: Saved {
+                period: 0,
+                level: 0,
+                mixer: 0,
+            },
+            dac_setup: true,
+        }
+    }
+
+    /// Whether [`DigidrumPlayer::start`] sets the channel up as a DAC, as by
+    /// default. Without, only the level is written and put back, leaving the
+    /// period and mixer to whatever else drives the channel, as YM songs
+    /// expect: their frames set the channel up for the drum themselves.
+    pub const fn with_dac_setup(mut self, dac_setup: bool) -> DigidrumPlayer<'a> {
+        self.dac_setup = dac_setup;
+        self
+    }
+
+    /// Saves `channel`'s state and sets it up as a DAC: tone and noise off,
+    /// and the period at 0 so any leakage from the tone generator is
+    /// ultrasonic. A sample already playing is stopped first.
+    pub fn start<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+        sample: Sample<'a>,
+    ) -> Result<(), P::Error> {
+        self.stop(psg)?;
+        let registers = psg.registers();
+        self.saved = Saved {
+            period: registers.tone_period(channel),
+            level: registers.value(channel.level_register()),
+            mixer: registers.mixer(),
+        };
+        self.playing = Some((channel, sample));
+        self.position = 0;
+        if !self.dac_setup {
+            return Ok(());
+        }
+        psg.set_channel_mixer(channel, false, false)?;
+        psg.set_channel_period(channel, 0)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// Writes the next sample value. Returns `true` once the sample has run
+    /// out, on the call that restores the channel.
+    pub fn tick_sample<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        let Some((channel, sample)) = self.playing else {
+            return Ok(true);
+        };
+        match sample.get(self.position) {
+            Some(value) => {
+                psg.update_register(channel.level_register(), value)?;
+                self.position += 1;
+                Ok(false)
+            }
+            None => {
+                self.stop(psg)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Stops playback and restores the channel's period, level and mixer
+    /// bits, or just its level without the DAC setup.
+    pub fn stop<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let Some((channel, _)) = self.playing.take() else {
+            return Ok(());
+        };
+        let saved = self.saved;
+        if !self.dac_setup {
+            return psg
+                .update_register(channel.level_register(), saved.level)
+                .map(drop);
+        }
+        psg.set_channel_period(channel, saved.period)?;
+        let tone = saved.mixer & (1 << channel.index()) == 0;
+        let noise = saved.mixer & (8 << channel.index()) == 0;
+        psg.set_channel_mixer(channel, tone, noise)?;
+        psg.update_register(channel.level_register(), saved.level)?;
+        Ok(())
+    }
+}
+
+impl<'a> Default for DigidrumPlayer<'a> {
+    fn default() -> DigidrumPlayer<'a> {
+        DigidrumPlayer::new()
+    }
+}
+
+impl<P, Delay, Io> Ym2149<P, Delay, Io>
+where
+    P: OutputPin,
+    Delay: DelayNs,
+    Io: IoPorts,
+{
+    /// How long one level write takes on this target, in nanoseconds, which
+    /// [`Ym2149::play_sample`] subtracts from its delay between samples.
+    /// Defaults to the 2 µs spent in the driver's own delays.
+    pub fn set_sample_write_cost(&mut self, nanoseconds: u32) {
+        self.sample_write_ns = nanoseconds;
+    }
+
+    /// Plays `sample` on `channel` at `rate_hz`, blocking until it ends, then
+    /// restores the channel.
+    pub fn play_sample(
+        &mut self,
+        channel: Channel,
+        sample: Sample<'_>,
+        rate_hz: u32,
+    ) -> Result<(), Error<P>> {
+        let mut player = DigidrumPlayer::new();
+        player.start(self, channel, sample)?;
+        let wait = (1_000_000_000 / rate_hz.max(1)).saturating_sub(self.sample_write_ns);
+        let level = channel.level_register();
+        // The address stays latched between data writes.
+        self.set_address(level)?;
+        for value in sample.values() {
+            self.set_data(value)?;
+            self.registers.set(level, value);
+            self.delay.delay_ns(wait);
+        }
+        player.stop(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::ChannelLevel;
+    use std::vec::Vec;
+
+    const KICK: Sample<'static> = Sample::new(&[0xF9, 0x94, 0x10]);
+
+    #[test]
+    fn nibbles_unpack_high_first() {
+        assert_eq!(KICK.len(), 6);
+        assert_eq!(KICK.values().collect::<Vec<_>>(), [15, 9, 9, 4, 1, 0]);
+        assert_eq!(KICK.get(6), None);
+    }
+
+    #[test]
+    fn other_layouts_read_as_levels() {
+        let levels = Sample::levels(&[0x0F, 0xF3, 0x00]);
+        assert_eq!(levels.values().collect::<Vec<_>>(), [15, 3, 0]);
+        let pcm = Sample::pcm(&[255, 128, 54, 53, 1, 0]);
+        assert_eq!(pcm.len(), 6);
+        assert_eq!(pcm.values().collect::<Vec<_>>(), [15, 13, 11, 10, 0, 0]);
+        let signed = Sample::signed_pcm(&[0x7F, 0x00, 0x80]);
+        assert_eq!(signed.values().collect::<Vec<_>>(), [15, 13, 0]);
+        for amplitude in 1..=255 {
+            assert!(pcm_level(amplitude) >= pcm_level(amplitude - 1));
+        }
+    }
+
+    #[test]
+    fn isr_playback_writes_levels_and_restores() {
+        let mut psg = FakePsg::new();
+        psg.set_channel_period(Channel::C, 0x1AB).unwrap();
+        psg.update_channel_level(Channel::C, ChannelLevel::Fixed(6))
+            .unwrap();
+        psg.set_channel_mixer(Channel::C, true, false).unwrap();
+        psg.take_writes();
+
+        let mut player = DigidrumPlayer::new();
+        player.start(&mut psg, Channel::C, KICK).unwrap();
+        assert_eq!(
+            psg.take_writes(),
+            [(0x7, 0b0011_1111), (0x4, 0x00), (0x5, 0x00)]
+        );
+        while !player.tick_sample(&mut psg).unwrap() {}
+        let writes = psg.take_writes();
+        // A repeated value costs nothing; then the channel is handed back.
+        assert_eq!(
+            writes,
+            [
+                (0xA, 15),
+                (0xA, 9),
+                (0xA, 4),
+                (0xA, 1),
+                (0xA, 0),
+                (0x4, 0xAB),
+                (0x5, 0x01),
+                (0x7, 0b0011_1011),
+                (0xA, 6),
+            ]
+        );
+    }
+}
@@ -0,0 +1,583 @@
+//! The hardware drivers' pin sequences, checked against `embedded-hal-mock`
+//! pins and delay.
+//!
+//! The mocks check each pin's own transitions and the delays; each pin is
+//! also wrapped to log into one shared list, so the order across pins is
+//! checked too: the bus mode set before the address, the data on the bus
+//! before the write strobe. [`Bus`] builds both from one description of the
+//! cycles expected.
+
+extern crate std;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{ErrorType, OutputPin};
+use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
+use embedded_hal_mock::eh1::digital::{Mock, State, Transaction};
+use embedded_hal_mock::eh1::MockError;
+
+use crate::clock_source::MasterClock;
+#[cfg(feature = "player")]
+use crate::frame_player::FramePlayer;
+use crate::ymz::YmzBus;
+use crate::{
+    Channel, ChannelLevel, ChipConfig, ChipKind, EnvelopeShape, Error, FrequencyError,
+    FrequencyPolicy, IoPort, MixerSettings, OutOfRange, Psg, Rounding, VariantError, Ym2149,
+};
+
+/// A pin of either bus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Pin {
+    Bdir,
+    Bc1,
+    /// D0 to D7.
+    Data(u8),
+    A0,
+    Wr,
+    Cs,
+}
+
+/// The YM2149's pins, in the order [`Ym2149::new`] takes them.
+const PINS: [Pin; 10] = [
+    Pin::Bdir,
+    Pin::Bc1,
+    Pin::Data(0),
+    Pin::Data(1),
+    Pin::Data(2),
+    Pin::Data(3),
+    Pin::Data(4),
+    Pin::Data(5),
+    Pin::Data(6),
+    Pin::Data(7),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Event {
+    Set(Pin, State),
+    DelayUs(u32),
+}
+
+type Log = Rc<RefCell<Vec<Event>>>;
+
+/// A mock pin that logs what it is set to.
+#[derive(Debug)]
+struct Probe {
+    pin: Pin,
+    mock: Mock,
+    log: Log,
+}
+
+impl ErrorType for Probe {
+    type Error = MockError;
+}
+
+impl OutputPin for Probe {
+    fn set_low(&mut self) -> Result<(), MockError> {
+        self.log.borrow_mut().push(Event::Set(self.pin, State::Low));
+        self.mock.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), MockError> {
+        self.log
+            .borrow_mut()
+            .push(Event::Set(self.pin, State::High));
+        self.mock.set_high()
+    }
+}
+
+/// A mock delay that logs what it is asked for.
+struct ProbeDelay {
+    mock: CheckedDelay,
+    log: Log,
+}
+
+impl DelayNs for ProbeDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.mock.delay_ns(ns);
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.log.borrow_mut().push(Event::DelayUs(us));
+        self.mock.delay_us(us);
+    }
+}
+
+/// The bus cycles a test expects, in order.
+struct Bus {
+    events: Vec<Event>,
+    /// A pin whose `n`th transition fails, counting from 0.
+    failure: Option<(Pin, usize)>,
+}
+
+impl Bus {
+    fn empty() -> Bus {
+        Bus {
+            events: Vec::new(),
+            failure: None,
+        }
+    }
+
+    /// Starts with the inactive mode [`Ym2149::new`] leaves the bus in.
+    fn new() -> Bus {
+        Bus::empty().inactive()
+    }
+
+    fn set(mut self, pin: Pin, state: State) -> Bus {
+        self.events.push(Event::Set(pin, state));
+        self
+    }
+
+    fn delay(mut self) -> Bus {
+        self.events.push(Event::DelayUs(1));
+        self
+    }
+
+    fn mode(self, bdir: State, bc1: State) -> Bus {
+        self.set(Pin::Bdir, bdir).set(Pin::Bc1, bc1)
+    }
+
+    fn inactive(self) -> Bus {
+        self.mode(State::Low, State::Low)
+    }
+
+    /// `byte` onto D0 to D7, lowest bit first.
+    fn byte(self, byte: u8) -> Bus {
+        (0..8).fold(self, |bus, bit| {
+            let state = match byte >> bit & 1 {
+                0 => State::Low,
+                _ => State::High,
+            };
+            bus.set(Pin::Data(bit), state)
+        })
+    }
+
+    /// Latching `address`: address mode, the address, then inactive.
+    fn latch(self, address: u8) -> Bus {
+        self.mode(State::High, State::High)
+            .byte(address)
+            .delay()
+            .inactive()
+            .delay()
+    }
+
+    /// Writing `data` to the latched register: the data, then write mode,
+    /// then inactive.
+    fn write(self, data: u8) -> Bus {
+        self.byte(data)
+            .mode(State::High, State::Low)
+            .delay()
+            .inactive()
+            .delay()
+    }
+
+    fn register(self, address: u8, data: u8) -> Bus {
+        self.latch(address).write(data)
+    }
+
+    /// Has `pin`'s `n`th transition fail.
+    fn failing(mut self, pin: Pin, n: usize) -> Bus {
+        self.failure = Some((pin, n));
+        self
+    }
+
+    /// A driver on pins expecting these cycles, and what checks they were
+    /// made.
+    fn start(self) -> (Ym2149<Probe, ProbeDelay>, Check) {
+        let (mut probes, delay, check) = self.expect(&PINS);
+        let mut pin = || probes.next().unwrap();
+        let ym = Ym2149::new(
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            pin(),
+            delay,
+        );
+        match ym {
+            Ok(ym) => (ym, check),
+            Err(_) => panic!("couldn't start the bus"),
+        }
+    }
+
+    /// Starts with what [`YmzBus::new`] leaves the bus in: /WR high, and
+    /// /CS if there is one.
+    fn ymz(cs: bool) -> Bus {
+        let bus = Bus::empty().set(Pin::Wr, State::High);
+        match cs {
+            true => bus.set(Pin::Cs, State::High),
+            false => bus,
+        }
+    }
+
+    /// `byte` on the bus with A0 at `a0`, then a strobe of /WR.
+    fn strobe(self, a0: State, byte: u8) -> Bus {
+        self.set(Pin::A0, a0)
+            .byte(byte)
+            .set(Pin::Wr, State::Low)
+            .delay()
+            .set(Pin::Wr, State::High)
+            .delay()
+    }
+
+    /// A YMZ294 register write, with /CS around it if `cs`.
+    fn ymz_register(self, cs: bool, address: u8, data: u8) -> Bus {
+        let bus = match cs {
+            true => self.set(Pin::Cs, State::Low),
+            false => self,
+        };
+        let bus = bus.strobe(State::Low, address).strobe(State::High, data);
+        match cs {
+            true => bus.set(Pin::Cs, State::High),
+            false => bus,
+        }
+    }
+
+    /// A YMZ294 driver on pins expecting these cycles, with a /CS pin if
+    /// `cs`.
+    fn start_ymz(self, cs: bool) -> (YmzBus<Probe, ProbeDelay>, Check) {
+        let mut pins = Vec::from([Pin::A0, Pin::Wr]);
+        if cs {
+            pins.push(Pin::Cs);
+        }
+        pins.extend((0..8).map(Pin::Data));
+        let (mut probes, delay, check) = self.expect(&pins);
+        let a0 = probes.next().unwrap();
+        let wr = probes.next().unwrap();
+        let cs = cs.then(|| probes.next().unwrap());
+        let data = core::array::from_fn(|_| probes.next().unwrap());
+        match YmzBus::new(a0, wr, cs, data, delay) {
+            Ok(ymz) => (ymz, check),
+            Err(_) => panic!("couldn't start the bus"),
+        }
+    }
+
+    /// Probes on `pins` and a delay, each expecting its part of these
+    /// cycles, and the check that they were all made.
+    fn expect(self, pins: &[Pin]) -> (std::vec::IntoIter<Probe>, ProbeDelay, Check) {
+        let log = Log::default();
+        let mocks: Vec<Mock> = (pins.iter())
+            .map(|&pin| {
+                let transactions: Vec<Transaction> = (self.events.iter())
+                    .filter_map(|event| match *event {
+                        Event::Set(set, state) if set == pin => Some(state),
+                        _ => None,
+                    })
+                    .enumerate()
+                    .map(|(n, state)| match self.failure {
+                        Some(failure) if failure == (pin, n) => Transaction::set(state)
+                            .with_error(MockError::Io(std::io::ErrorKind::NotConnected)),
+                        _ => Transaction::set(state),
+                    })
+                    .collect();
+                Mock::new(&transactions)
+            })
+            .collect();
+        let delays: Vec<DelayTransaction> = (self.events.iter())
+            .filter_map(|event| match *event {
+                Event::DelayUs(us) => Some(DelayTransaction::delay_us(us)),
+                _ => None,
+            })
+            .collect();
+        let delay = CheckedDelay::new(&delays);
+        let probes: Vec<Probe> = (pins.iter().zip(&mocks))
+            .map(|(&pin, mock)| Probe {
+                pin,
+                mock: mock.clone(),
+                log: log.clone(),
+            })
+            .collect();
+        let probe_delay = ProbeDelay {
+            mock: delay.clone(),
+            log: log.clone(),
+        };
+        let check = Check {
+            expected: self.events,
+            log,
+            mocks,
+            delay,
+        };
+        (probes.into_iter(), probe_delay, check)
+    }
+}
+
+/// What [`Bus::start`] expects to see.
+struct Check {
+    expected: Vec<Event>,
+    log: Log,
+    mocks: Vec<Mock>,
+    delay: CheckedDelay,
+}
+
+impl Check {
+    /// Panics unless every cycle expected was made, in order, and no
+    /// others.
+    fn done(mut self) {
+        assert_eq!(*self.log.borrow(), self.expected);
+        for mock in &mut self.mocks {
+            mock.done();
+        }
+        self.delay.done();
+    }
+}
+
+#[test]
+fn construction_leaves_the_bus_inactive() {
+    let (_ym, check) = Bus::new().start();
+    check.done();
+}
+
+#[test]
+fn register_writes_latch_the_address_then_strobe_the_data() {
+    let (mut ym, check) = Bus::new().register(0x7, 0b1010_0101).start();
+    ym.set_register_value(0x7, 0b1010_0101).unwrap();
+    assert_eq!(Psg::registers(&ym).value(0x7), 0b1010_0101);
+    check.done();
+}
+
+#[test]
+fn clear_all_registers_writes_each_in_turn() {
+    let bus = (0..16).fold(Bus::new(), |bus, address| bus.register(address, 0));
+    let (mut ym, check) = bus.start();
+    ym.clear_all_registers().unwrap();
+    check.done();
+}
+
+#[test]
+fn setters_write_their_registers() {
+    let bus = Bus::new()
+        .register(0x2, 0x34)
+        .register(0x3, 0x12)
+        .register(0x6, 0x1F)
+        .register(0x7, 0b0011_1110)
+        .register(0x9, 0x10)
+        .register(0xB, 0xCD)
+        .register(0xC, 0xAB)
+        .register(0xD, 0b1010)
+        .register(0xE, 0x55)
+        .register(0xF, 0xAA);
+    let (mut ym, check) = bus.start();
+    ym.set_channel_frequency(Channel::B, 0x1234).unwrap();
+    ym.set_noise(0x1F).unwrap();
+    let noise = MixerSettings::DisableNoiseA | MixerSettings::DisableNoiseB;
+    let settings = noise | MixerSettings::DisableNoiseC;
+    ym.set_mixer_settings(settings | MixerSettings::DisableToneB | MixerSettings::DisableToneC)
+        .unwrap();
+    ym.set_channel_level(Channel::B, ChannelLevel::Envelope)
+        .unwrap();
+    ym.set_envelope_frequency(0xABCD).unwrap();
+    ym.set_envelope_shape(EnvelopeShape::cont | EnvelopeShape::Alt)
+        .unwrap();
+    ym.set_io_port_data(IoPort::A, 0x55).unwrap();
+    ym.set_io_port_data(IoPort::B, 0xAA).unwrap();
+    check.done();
+}
+
+#[test]
+fn a_failing_pin_stops_the_write() {
+    // D3's second transition, putting the data on the bus after the
+    // address.
+    let bus = Bus::new().latch(0x8).byte(0x0F).failing(Pin::Data(3), 1);
+    // The cycle stops at D3: D4 to D7 stay where the address left them.
+    let mut expected = bus.events.clone();
+    expected.truncate(expected.len() - 4);
+    let bus = Bus {
+        events: expected,
+        ..bus
+    };
+    let (mut ym, check) = bus.start();
+    assert!(matches!(
+        ym.set_register_value(0x8, 0x0F),
+        Err(Error::PinError(MockError::Io(_)))
+    ));
+    assert!(!Psg::registers(&ym).is_written(0x8));
+    check.done();
+}
+
+#[test]
+fn sel_is_only_on_the_ym2149() {
+    let (mut ym, check) = Bus::new().start();
+    ym.set_master_clock(4_000_000);
+    ym.set_sel_low(true).unwrap();
+    assert_eq!(Psg::master_clock(&ym), 2_000_000);
+    // The AY-3-8910 has no SEL to hold low.
+    ym.set_chip_kind(ChipKind::Ay8910);
+    assert_eq!(Psg::chip_kind(&ym), ChipKind::Ay8910);
+    assert_eq!(Psg::master_clock(&ym), 4_000_000);
+    assert_eq!(
+        ym.set_sel_low(true),
+        Err(VariantError::NoSelPin(ChipKind::Ay8910))
+    );
+    check.done();
+}
+
+#[test]
+fn the_frequency_policy_is_the_drivers_until_a_call_overrides_it() {
+    // 2 MHz / (16 * 30 Hz) is 4166.7, out of range, and half that 2083.3.
+    let (mut ym, check) = Bus::new().register(0x0, 0x23).register(0x1, 0x08).start();
+    assert_eq!(Psg::frequency_policy(&ym), FrequencyPolicy::default());
+    let refuse = FrequencyPolicy::new(Rounding::Ceil, OutOfRange::Error);
+    ym.set_frequency_policy(refuse);
+    assert_eq!(Psg::frequency_policy(&ym), refuse);
+    assert!(matches!(
+        ym.set_channel_tone_hz(Channel::A, 30),
+        Err(FrequencyError::OutOfRange)
+    ));
+    let fold = refuse.with_out_of_range(OutOfRange::FoldOctave);
+    let tone = ym.set_channel_tone_hz_with(Channel::A, 30, fold).unwrap();
+    assert_eq!((tone.period, tone.octaves), (2083, 1));
+    check.done();
+}
+
+#[test]
+fn clocks_from_a_source_are_the_ones_it_makes() {
+    let (mut ym, check) = Bus::new().start();
+    let clock = ym.set_master_clock_from(&[1_000_000, 1_714_285, 2_000_000], 1_773_400);
+    assert_eq!(
+        clock,
+        MasterClock {
+            requested: 1_773_400,
+            hz: 1_714_285
+        }
+    );
+    assert_eq!(Psg::master_clock(&ym), 1_714_285);
+    check.done();
+}
+
+#[test]
+fn chip_configs_are_taken_whole_or_not_at_all() {
+    let (mut ym, check) = Bus::new().start();
+    assert_eq!(ym.chip_config(), ChipConfig::default());
+    let config = ChipConfig {
+        chip: ChipKind::Ym2149,
+        master_clock: 4_000_000,
+        sel_low: true,
+    };
+    ym.set_chip_config(config).unwrap();
+    assert_eq!(ym.chip_config(), config);
+    assert_eq!(Psg::master_clock(&ym), 2_000_000);
+    assert_eq!(
+        ym.set_chip_config(ChipConfig {
+            chip: ChipKind::Ay8910,
+            master_clock: 1_000_000,
+            sel_low: true,
+        }),
+        Err(VariantError::NoSelPin(ChipKind::Ay8910))
+    );
+    assert_eq!(ym.chip_config(), config);
+    check.done();
+}
+
+#[test]
+fn the_ay8912_never_writes_port_b() {
+    let bus = (0..15).fold(Bus::new(), |bus, address| bus.register(address, 0));
+    let bus = bus
+        .register(0x7, 0b0111_1111)
+        .register(0x7, 0b0111_1110)
+        .register(0xE, 0x55);
+    let (ym, check) = bus.start();
+    let mut ay = ym.into_ay8912();
+    assert_eq!(Psg::chip_kind(&ay), ChipKind::Ay8910);
+    ay.clear_all_registers().unwrap();
+    // Dropped, as the chip would.
+    ay.set_register_value(0xF, 0xAA).unwrap();
+    assert!(!Psg::registers(&ay).is_written(0xF));
+    assert!(matches!(
+        ay.set_mixer_settings(MixerSettings::OutputIOB),
+        Err(Error::Unsupported)
+    ));
+    // A dump's mixer keeps port B an input, and so do the helpers after.
+    ay.set_register_value(0x7, 0xFF).unwrap();
+    ay.set_tone_enabled(Channel::A, true).unwrap();
+    assert_eq!(Psg::registers(&ay).mixer() & 0x80, 0);
+    ay.set_port_a_data(0x55).unwrap();
+    check.done();
+}
+
+#[test]
+fn updates_skip_what_a_package_without_ports_already_holds() {
+    let (ym, check) = Bus::new().register(0x7, 0b0111_1111).start();
+    let mut ay = ym.into_ay8912();
+    // Port B's direction bit is kept clear, so a mixer setting it again is
+    // no change.
+    assert!(ay.update_register(0x7, 0xFF).unwrap());
+    assert!(!ay.update_register(0x7, 0xFF).unwrap());
+    assert!(!ay.update_register(0x7, 0x7F).unwrap());
+    // Nor is anything written to port B's register.
+    assert!(!ay.update_register(0xF, 0xAA).unwrap());
+    check.done();
+
+    let (ym, check) = Bus::new().register(0x7, 0b0011_1111).start();
+    let mut ay = ym.into_ay8913();
+    assert!(ay.update_register(0x7, 0xFF).unwrap());
+    assert!(!ay.update_register(0x7, 0xFF).unwrap());
+    assert!(!ay.update_register(0xE, 0x55).unwrap());
+    assert!(!ay.update_register(0xF, 0x55).unwrap());
+    check.done();
+}
+
+#[cfg(feature = "player")]
+#[test]
+fn the_ay8913_plays_a_dump_without_its_port_writes() {
+    static SONG: [u8; 16] = [
+        0x1C, 0x01, 0, 0, 0, 0, 0, 0xFF, 15, 0, 0, 0x40, 0, 0x0E, 0x12, 0x34,
+    ];
+    let bus = (0..14).fold(Bus::new(), |bus, address| bus.register(address, 0));
+    let bus = (0..0xD).fold(bus, |bus, address| match address {
+        0x0 => bus.register(0x0, 0x1C),
+        0x1 => bus.register(0x1, 0x01),
+        0x7 => bus.register(0x7, 0b0011_1111),
+        0x8 => bus.register(0x8, 15),
+        0xB => bus.register(0xB, 0x40),
+        _ => bus,
+    });
+    let (ym, check) = bus.register(0xD, 0x0E).start();
+    let mut ay = ym.into_ay8913();
+    ay.clear_all_registers().unwrap();
+    let mut player = FramePlayer::new(&SONG[..]);
+    player.play();
+    player.tick(&mut ay).unwrap();
+    assert!(matches!(
+        ay.set_mixer_settings(MixerSettings::OutputIOA),
+        Err(Error::Unsupported)
+    ));
+    check.done();
+}
+
+#[test]
+fn ymz_writes_latch_with_a0_low_then_write_with_it_high() {
+    for cs in [true, false] {
+        let bus = Bus::ymz(cs)
+            .ymz_register(cs, 0x8, 15)
+            .ymz_register(cs, 0x7, 0b0011_1110);
+        let (mut ymz, check) = bus.start_ymz(cs);
+        ymz.update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        // The YMZ294 has no ports: their writes and direction bits go.
+        ymz.set_register_value(0xE, 0x55).unwrap();
+        ymz.set_register_value(0x7, 0b1111_1110).unwrap();
+        // Which is also what the mixer holds, so updating it again is no
+        // change, and nor is updating a port.
+        assert!(!ymz.update_register(0x7, 0b1111_1110).unwrap());
+        assert!(!ymz.update_register(0xF, 0x55).unwrap());
+        check.done();
+    }
+}
+
+#[test]
+fn ymz_clocks_follow_the_4_6_pin() {
+    let (mut ymz, check) = Bus::ymz(false).start_ymz(false);
+    ymz.set_master_clock(4_000_000);
+    assert_eq!(Psg::master_clock(&ymz), 4_000_000);
+    ymz.set_clock_halved(true);
+    assert_eq!(Psg::master_clock(&ymz), 2_000_000);
+    assert_eq!(Psg::chip_kind(&ymz), ChipKind::Ymz294);
+    check.done();
+}
@@ -0,0 +1,461 @@
+//! A step-sequenced rhythm section built on the sound effect player.
+//!
+//! A [`DrumMachine`] has four drum slots, each a sound effect made of
+//! [`SfxPhase`]s with a priority, and plays a [`DrumPattern`] of sixteen
+//! sixteenth-note steps per slot in a loop. Patterns and kits are plain const
+//! data, so they can sit in flash.
+
+use crate::instrument::Table;
+use crate::psg::Psg;
+use crate::sfx::{NoiseSweep, SfxPhase, SfxPlayer};
+use crate::sweep::{Sweep, SweepCurve, SweepPoint};
+use crate::tempo_sync::TempoSync;
+use crate::Channel;
+
+/// Steps in a pattern: one bar of sixteenth notes.
+pub const STEPS: usize = 16;
+
+/// Drum slots in a kit.
+pub const SLOTS: usize = 4;
+
+/// One drum sound. When drums collide on a step, higher priorities get the
+/// channels first, earlier slots breaking ties.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Drum {
+    pub phases: &'static [SfxPhase],
+    pub priority: u8,
+}
+
+pub type DrumKit = [Drum; SLOTS];
+
+/// Which slots play on each step. Bit `n` of a slot's mask is step `n`, so
+/// `0x0001` plays on the downbeat only.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DrumPattern {
+    pub steps: [u16; SLOTS],
+}
+
+impl DrumPattern {
+    pub const fn new(steps: [u16; SLOTS]) -> DrumPattern {
+        DrumPattern { steps }
+    }
+
+    /// Whether `slot` plays on `step`.
+    pub const fn hits(&self, slot: usize, step: usize) -> bool {
+        self.steps[slot] & (1 << step) != 0
+    }
+}
+
+const fn noise_phase(hertz: u32, volume: &'static [u8]) -> SfxPhase {
+    SfxPhase {
+        sweep: Sweep::new(
+            SweepPoint::Hertz(1000),
+            SweepPoint::Hertz(1000),
+            volume.len() as u16,
+            SweepCurve::LinearPeriod,
+        ),
+        tone: false,
+        noise: Some(NoiseSweep {
+            from_hz: hertz,
+            to_hz: hertz,
+        }),
+        volume: Table::new(volume),
+    }
+}
+
+/// A drum kit made from sweeps and noise, in the order kick, snare, closed
+/// hi-hat, open hi-hat.
+pub mod kit {
+    use super::*;
+
+    pub const KICK: Drum = Drum {
+        phases: &[SfxPhase {
+            sweep: Sweep::new(
+                SweepPoint::Hertz(160),
+                SweepPoint::Hertz(50),
+                5,
+                SweepCurve::Exponential,
+            ),
+            tone: true,
+            noise: None,
+            volume: Table::new(&[15, 14, 12, 9, 5]),
+        }],
+        priority: 3,
+    };
+
+    pub const SNARE: Drum = Drum {
+        phases: &[SfxPhase {
+            sweep: Sweep::new(
+                SweepPoint::Hertz(220),
+                SweepPoint::Hertz(160),
+                6,
+                SweepCurve::LinearPeriod,
+            ),
+            tone: true,
+            noise: Some(NoiseSweep {
+                from_hz: 8_000,
+                to_hz: 6_000,
+            }),
+            volume: Table::new(&[15, 13, 10, 7, 4, 2]),
+        }],
+        priority: 2,
+    };
+
+    pub const CLOSED_HAT: Drum = Drum {
+        phases: &[noise_phase(20_000, &[11, 7, 3])],
+        priority: 0,
+    };
+
+    pub const OPEN_HAT: Drum = Drum {
+        phases: &[noise_phase(20_000, &[12, 11, 10, 9, 8, 7, 6, 4, 3, 1])],
+        priority: 1,
+    };
+
+    pub const STANDARD: DrumKit = [KICK, SNARE, CLOSED_HAT, OPEN_HAT];
+}
+
+/// Patterns for [`kit::STANDARD`].
+pub mod patterns {
+    use super::DrumPattern;
+
+    /// Kick on the beats, snare on two and four, closed hats on the eighths.
+    pub const FOUR_ON_THE_FLOOR: DrumPattern = DrumPattern::new([0x1111, 0x1010, 0x5555, 0x0000]);
+
+    /// Kick on one and the "and" of three, snare on two and four, hats on
+    /// the eighths with an open hat closing the bar.
+    pub const BACK_BEAT: DrumPattern = DrumPattern::new([0x0401, 0x1010, 0x1555, 0x4000]);
+}
+
+/// Plays a [`DrumPattern`] on one channel, or two for overlapping hits.
+///
+/// Each tick of the caller's frame clock advances the step clock; steps fall
+/// on the nearest tick, with the fraction carried so the tempo doesn't
+/// drift. Swing delays every second step: at 50% steps are even, at 66%
+/// pairs are in a 2:1 triplet feel.
+///
+/// A new hit always cuts off whatever its channel was playing. With two
+/// channels, a hit goes to an idle channel if there is one, otherwise to the
+/// one playing the lower-priority drum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrumMachine {
+    kit: &'static DrumKit,
+    pattern: &'static DrumPattern,
+    channels: [Option<Channel>; 2],
+    players: [SfxPlayer; 2],
+    sounding: [Option<usize>; 2],
+    tick_rate: u32,
+    bpm: u16,
+    sync: Option<TempoSync>,
+    swing: u8,
+    step: usize,
+    elapsed: u32,
+    running: bool,
+}
+
+impl DrumMachine {
+    /// A machine ticked `tick_rate` times a second, playing on `channel`.
+    pub const fn new(
+        kit: &'static DrumKit,
+        pattern: &'static DrumPattern,
+        channel: Channel,
+        tick_rate: u32,
+        bpm: u16,
+    ) -> DrumMachine {
+        DrumMachine {
+            kit,
+            pattern,
+            channels: [Some(channel), None],
+            players: [SfxPlayer::new(), SfxPlayer::new()],
+            sounding: [None, None],
+            tick_rate,
+            bpm,
+            sync: None,
+            swing: 50,
+            step: 0,
+            elapsed: 0,
+            running: false,
+        }
+    }
+
+    /// Adds a second channel so two drums can sound at once.
+    pub const fn with_second_channel(mut self, channel: Channel) -> DrumMachine {
+        self.channels[1] = Some(channel);
+        self
+    }
+
+    /// Swing in percent of a step pair given to its first step, clamped to
+    /// 50..=75.
+    pub const fn with_swing(mut self, percent: u8) -> DrumMachine {
+        self.swing = if percent < 50 {
+            50
+        } else if percent > 75 {
+            75
+        } else {
+            percent
+        };
+        self
+    }
+
+    /// Plays at the tempo `sync` follows, from
+    /// [`DrumMachine::sync_pulse`], rather than its own, which plays until
+    /// the first pulses give one.
+    pub const fn with_tempo_sync(mut self, sync: TempoSync) -> DrumMachine {
+        self.sync = Some(sync);
+        self
+    }
+
+    /// A sync pulse came in at `micros`, as for [`TempoSync::pulse`]. Does
+    /// nothing without [`DrumMachine::with_tempo_sync`].
+    pub fn sync_pulse(&mut self, micros: u32) {
+        if let Some(sync) = &mut self.sync {
+            sync.pulse(micros);
+        }
+    }
+
+    pub fn tempo_sync(&self) -> Option<&TempoSync> {
+        self.sync.as_ref()
+    }
+
+    pub fn set_swing(&mut self, percent: u8) {
+        *self = self.clone().with_swing(percent);
+    }
+
+    pub fn set_tempo(&mut self, bpm: u16) {
+        self.bpm = bpm;
+    }
+
+    pub fn tempo(&self) -> u16 {
+        self.bpm
+    }
+
+    /// Switches pattern without losing the place in the bar.
+    pub fn set_pattern(&mut self, pattern: &'static DrumPattern) {
+        self.pattern = pattern;
+    }
+
+    /// Starts from the top of the bar on the next tick.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.step = 0;
+        self.elapsed = self.pair_length();
+    }
+
+    /// Stops the step clock, letting drums already sounding ring out.
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// The step that will play next.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    // Time is kept in units chosen so that a tick is `bpm * 4 * 100` of them
+    // and a pair of steps `tick_rate * 60 * 200`: the ratio is exact and
+    // swing percentages divide the pair without rounding.
+    fn pair_length(&self) -> u32 {
+        self.tick_rate * 60 * 200
+    }
+
+    /// A tick's time, from the sync's tempo once it has one, and the set
+    /// tempo otherwise.
+    fn tick_time(&self) -> u32 {
+        match self.sync.and_then(|sync| sync.millibpm()) {
+            Some(millibpm) => (millibpm as u64 * 2 / 5) as u32,
+            None => self.bpm as u32 * 4 * 100,
+        }
+    }
+
+    /// When the second step of a pair starts, from the start of the pair.
+    fn swung_offset(&self) -> u32 {
+        self.tick_rate * 60 * 2 * self.swing as u32
+    }
+
+    /// Plays any steps due and advances every sounding drum.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        if self.running {
+            loop {
+                let due = if self.step.is_multiple_of(2) {
+                    self.pair_length()
+                } else {
+                    self.swung_offset()
+                };
+                if self.elapsed < due {
+                    break;
+                }
+                if self.step.is_multiple_of(2) {
+                    self.elapsed -= self.pair_length();
+                }
+                self.play_step(self.step);
+                self.step = (self.step + 1) % STEPS;
+            }
+            self.elapsed += self.tick_time();
+        }
+        if let Some(sync) = &mut self.sync {
+            sync.advance(1_000_000 / self.tick_rate.max(1));
+        }
+        for (n, player) in self.players.iter_mut().enumerate() {
+            if self.sounding[n].is_some() && player.tick(psg)? {
+                self.sounding[n] = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn play_step(&mut self, step: usize) {
+        let lanes = self
+            .channels
+            .iter()
+            .filter(|channel| channel.is_some())
+            .count();
+        let mut taken = [false; 2];
+        for _ in 0..lanes {
+            // The most important drum on this step not yet placed.
+            let Some(slot) = (0..SLOTS)
+                .filter(|&slot| self.pattern.hits(slot, step))
+                .filter(|slot| !self.sounding_on_step(*slot, &taken))
+                .max_by_key(|&slot| (self.kit[slot].priority, core::cmp::Reverse(slot)))
+            else {
+                break;
+            };
+            let lane = (0..lanes)
+                .filter(|&lane| !taken[lane])
+                .min_by_key(|&lane| self.sounding[lane].map(|playing| self.kit[playing].priority))
+                .unwrap_or(0);
+            taken[lane] = true;
+            self.sounding[lane] = Some(slot);
+            if let Some(channel) = self.channels[lane] {
+                self.players[lane].play_phases(self.kit[slot].phases, channel);
+            }
+        }
+    }
+
+    /// Whether `slot` has already been given a lane this step.
+    fn sounding_on_step(&self, slot: usize, taken: &[bool; 2]) -> bool {
+        (0..2).any(|lane| taken[lane] && self.sounding[lane] == Some(slot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tempo_sync::MIDI_CLOCK_PPQN;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    /// Every slot on every step, to make collisions.
+    const ALL_HITS: DrumPattern = DrumPattern::new([0xFFFF; SLOTS]);
+
+    fn step_ticks(machine: &mut DrumMachine, ticks: usize) -> Vec<usize> {
+        let mut psg = FakePsg::new();
+        machine.start();
+        let mut starts = Vec::new();
+        for tick in 0..ticks {
+            let before = machine.step();
+            machine.tick(&mut psg).unwrap();
+            if machine.step() != before {
+                starts.push(tick);
+            }
+        }
+        starts
+    }
+
+    #[test]
+    fn tempo_maps_to_ticks() {
+        // 125 BPM at 50 Hz: a sixteenth is exactly 6 ticks.
+        let mut machine = DrumMachine::new(&kit::STANDARD, &ALL_HITS, Channel::C, 50, 125);
+        assert_eq!(step_ticks(&mut machine, 25), [0, 6, 12, 18, 24]);
+        // 120 BPM: 6.25 ticks, the remainder carried along.
+        machine.set_tempo(120);
+        assert_eq!(step_ticks(&mut machine, 26), [0, 7, 13, 19, 25]);
+    }
+
+    #[test]
+    fn synced_machines_follow_the_pulses_then_run_free() {
+        // A MIDI clock at 125 BPM is a pulse every 20 ms, a tick apart.
+        let mut machine = DrumMachine::new(&kit::STANDARD, &ALL_HITS, Channel::C, 50, 90)
+            .with_tempo_sync(TempoSync::new(MIDI_CLOCK_PPQN));
+        let mut psg = FakePsg::new();
+        machine.start();
+        let mut steps = 0u32;
+        let mut count_steps = |machine: &mut DrumMachine, ticks: u32, pulsing: bool| {
+            let start = steps;
+            for tick in 0..ticks {
+                if pulsing {
+                    // Up to a millisecond early or late.
+                    let jitter = (tick * 7919 % 2001) as i32 - 1000;
+                    machine.sync_pulse((tick * 20_000).wrapping_add_signed(jitter));
+                }
+                let before = machine.step();
+                machine.tick(&mut psg).unwrap();
+                if machine.step() != before {
+                    steps += 1;
+                }
+            }
+            steps - start
+        };
+        // A sixteenth every 6 ticks, rather than the 8.3 of 90 BPM.
+        let synced = count_steps(&mut machine, 600, true);
+        assert!(synced.abs_diff(100) <= 3, "{synced}");
+        assert!(machine.tempo_sync().unwrap().is_locked());
+        let free = count_steps(&mut machine, 600, false);
+        assert!(!machine.tempo_sync().unwrap().is_locked());
+        assert!(free.abs_diff(100) <= 2, "{free}");
+    }
+
+    #[test]
+    fn swing_delays_every_second_step() {
+        // 66% of a 12 tick pair is 7.92 ticks, landing on tick 8.
+        let mut machine =
+            DrumMachine::new(&kit::STANDARD, &ALL_HITS, Channel::C, 50, 125).with_swing(66);
+        assert_eq!(step_ticks(&mut machine, 25), [0, 8, 12, 20, 24]);
+        machine.set_swing(90);
+        assert_eq!(step_ticks(&mut machine, 13), [0, 9, 12]);
+    }
+
+    #[test]
+    fn colliding_drums_go_by_priority() {
+        let pattern = &patterns::FOUR_ON_THE_FLOOR;
+        let mut psg = FakePsg::new();
+        let mut machine = DrumMachine::new(&kit::STANDARD, pattern, Channel::A, 50, 125);
+        machine.start();
+        machine.tick(&mut psg).unwrap();
+        // Kick and hat on the downbeat, and only one channel: the kick wins.
+        assert_eq!(machine.sounding, [Some(0), None]);
+
+        let mut machine = DrumMachine::new(&kit::STANDARD, &ALL_HITS, Channel::A, 50, 125)
+            .with_second_channel(Channel::B);
+        machine.start();
+        machine.tick(&mut psg).unwrap();
+        assert_eq!(machine.sounding, [Some(0), Some(1)]);
+        // With a kick and an open hat still ringing, the next kick replaces
+        // the open hat, the less important of the two, and the snare takes
+        // the other channel.
+        machine.sounding = [Some(0), Some(3)];
+        machine.play_step(1);
+        assert_eq!(machine.sounding, [Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn drums_reach_the_chip_and_loop() {
+        let mut psg = FakePsg::new();
+        let mut machine =
+            DrumMachine::new(&kit::STANDARD, &patterns::BACK_BEAT, Channel::C, 50, 125);
+        machine.start();
+        machine.tick(&mut psg).unwrap();
+        // The kick's first step: 160 Hz, level 15, tone only.
+        assert_eq!(psg.registers().tone_period(Channel::C), 781);
+        assert_eq!(psg.registers().value(0xA), 15);
+        assert_eq!(psg.registers().mixer() & 0b0010_0100, 0b0010_0000);
+        for _ in 1..6 * STEPS {
+            machine.tick(&mut psg).unwrap();
+        }
+        assert_eq!(machine.step(), 0);
+    }
+}
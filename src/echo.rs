@@ -0,0 +1,230 @@
+//! A delayed, quieter copy of one channel's melody on another channel.
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning;
+use crate::{Channel, ChannelLevel};
+
+/// A note (or rest) played on the source channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EchoNote {
+    Note { pitch: Pitch, level: u8 },
+    Rest,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Event {
+    at: u32,
+    note: EchoNote,
+}
+
+/// Repeats what the source channel played `delay` ticks ago on the echo
+/// channel, `attenuation` levels quieter.
+///
+/// Notes meant for the source channel go through [`Echo::play`], which
+/// sounds them and remembers them; [`Echo::tick`] replays them later. Up to
+/// `N` notes can be waiting at once. If notes arrive faster than that, the
+/// oldest pending ones are dropped (see [`Echo::dropped`]), so the echo skips
+/// notes rather than falling behind.
+#[derive(Debug, Clone)]
+pub struct Echo<const N: usize> {
+    source: Channel,
+    echo: Channel,
+    delay: u16,
+    attenuation: u8,
+    events: [Event; N],
+    head: usize,
+    len: usize,
+    now: u32,
+    enabled: bool,
+    dropped: u32,
+}
+
+impl<const N: usize> Echo<N> {
+    pub const fn new(source: Channel, echo: Channel, delay: u16, attenuation: u8) -> Echo<N> {
+        Echo {
+            source,
+            echo,
+            delay,
+            attenuation,
+            events: [Event {
+                at: 0,
+                note: EchoNote::Rest,
+            }; N],
+            head: 0,
+            len: 0,
+            now: 0,
+            enabled: true,
+            dropped: 0,
+        }
+    }
+
+    /// Plays `note` on the source channel and queues its echo.
+    pub fn play<P: Psg>(&mut self, psg: &mut P, note: EchoNote) -> Result<(), P::Error> {
+        write_note(psg, self.source, note)?;
+        if self.enabled && N > 0 {
+            if self.len == N {
+                self.head = (self.head + 1) % N;
+                self.len -= 1;
+                self.dropped += 1;
+            }
+            self.events[(self.head + self.len) % N] = Event { at: self.now, note };
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Advances one tick, sounding any echoes that have come due.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        while self.len > 0 {
+            let event = self.events[self.head];
+            if self.now.wrapping_sub(event.at) < self.delay as u32 {
+                break;
+            }
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+            let note = match event.note {
+                EchoNote::Note { pitch, level } => EchoNote::Note {
+                    pitch,
+                    level: level.min(15).saturating_sub(self.attenuation),
+                },
+                EchoNote::Rest => EchoNote::Rest,
+            };
+            write_note(psg, self.echo, note)?;
+        }
+        self.now = self.now.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Turning the echo off forgets pending echoes and silences the echo
+    /// channel straight away; turning it back on only echoes new notes.
+    pub fn set_enabled<P: Psg>(&mut self, psg: &mut P, enabled: bool) -> Result<(), P::Error> {
+        if self.enabled && !enabled {
+            self.len = 0;
+            write_note(psg, self.echo, EchoNote::Rest)?;
+        }
+        self.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Notes whose echo was dropped because the buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Number of echoes waiting to sound.
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+}
+
+fn write_note<P: Psg>(psg: &mut P, channel: Channel, note: EchoNote) -> Result<(), P::Error> {
+    match note {
+        EchoNote::Note { pitch, level } => {
+            let period = tuning::fold_pitch_period(psg.master_clock(), pitch).period;
+            psg.set_channel_period(channel, period)?;
+            psg.update_channel_level(channel, ChannelLevel::Fixed(level))?;
+            psg.set_tone_enabled(channel, true)?;
+        }
+        EchoNote::Rest => psg.update_channel_level(channel, ChannelLevel::Fixed(0))?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+
+    const C4: Pitch = Pitch::new(Note::C, 4);
+    const E4: Pitch = Pitch::new(Note::E, 4);
+
+    fn level(psg: &FakePsg, channel: Channel) -> u8 {
+        psg.registers().value(channel.level_register())
+    }
+
+    #[test]
+    fn replays_notes_and_rests_after_the_delay() {
+        let mut psg = FakePsg::new();
+        let mut echo: Echo<4> = Echo::new(Channel::A, Channel::C, 2, 4);
+        echo.play(
+            &mut psg,
+            EchoNote::Note {
+                pitch: C4,
+                level: 12,
+            },
+        )
+        .unwrap();
+        echo.tick(&mut psg).unwrap();
+        echo.play(&mut psg, EchoNote::Rest).unwrap();
+        echo.tick(&mut psg).unwrap();
+        assert!(!psg.registers().is_written(Channel::C.level_register()));
+
+        echo.tick(&mut psg).unwrap();
+        assert_eq!(level(&psg, Channel::C), 8);
+        assert_eq!(
+            psg.registers().tone_period(Channel::C),
+            psg.registers().tone_period(Channel::A)
+        );
+        echo.tick(&mut psg).unwrap();
+        assert_eq!(level(&psg, Channel::C), 0);
+        assert_eq!(echo.pending(), 0);
+    }
+
+    #[test]
+    fn full_buffer_drops_oldest_echo() {
+        let mut psg = FakePsg::new();
+        let mut echo: Echo<2> = Echo::new(Channel::A, Channel::B, 10, 2);
+        for pitch in [C4, E4, C4] {
+            echo.play(&mut psg, EchoNote::Note { pitch, level: 10 })
+                .unwrap();
+            echo.tick(&mut psg).unwrap();
+        }
+        assert_eq!(echo.dropped(), 1);
+        assert_eq!(echo.pending(), 2);
+        for _ in 0..9 {
+            echo.tick(&mut psg).unwrap();
+        }
+        // The first echo to sound is the second note played.
+        assert_eq!(
+            psg.registers().tone_period(Channel::B),
+            crate::tuning::pitch_period(2_000_000, E4) as u16
+        );
+    }
+
+    #[test]
+    fn disabling_mid_echo_silences_and_forgets() {
+        let mut psg = FakePsg::new();
+        let mut echo: Echo<4> = Echo::new(Channel::A, Channel::C, 1, 0);
+        echo.play(
+            &mut psg,
+            EchoNote::Note {
+                pitch: C4,
+                level: 9,
+            },
+        )
+        .unwrap();
+        echo.tick(&mut psg).unwrap();
+        echo.tick(&mut psg).unwrap();
+        assert_eq!(level(&psg, Channel::C), 9);
+        echo.play(
+            &mut psg,
+            EchoNote::Note {
+                pitch: E4,
+                level: 9,
+            },
+        )
+        .unwrap();
+        echo.set_enabled(&mut psg, false).unwrap();
+        assert_eq!(level(&psg, Channel::C), 0);
+        echo.tick(&mut psg).unwrap();
+        echo.tick(&mut psg).unwrap();
+        assert_eq!(level(&psg, Channel::C), 0);
+        assert_eq!(level(&psg, Channel::A), 9);
+    }
+}
@@ -0,0 +1,282 @@
+//! A common interface for tick-driven modulators, and a chain that combines
+//! them into one set of register writes per channel.
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{self, MAX_TONE_PERIOD};
+use crate::{Channel, ChannelLevel};
+
+/// What one effect sees and contributes on one channel for one tick.
+///
+/// Effects never touch the chip themselves. They read the base pitch and
+/// level and add to the running totals:
+///
+/// - Pitch offsets are in cents and sum, so an arpeggio step of +12
+///   semitones and a vibrato swing of -30 cents come out as +1170 cents.
+/// - Level offsets are in 4-bit level steps and sum. The steps are
+///   logarithmic, so adding offsets multiplies gains: an envelope at 9 out
+///   of 15 contributes -6 and quietens whatever base level it rides on.
+/// - Tone and noise requests replace earlier ones: the last effect in the
+///   chain to ask wins. Without any request the mixer is left alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelCtx {
+    channel: Channel,
+    master_clock: u32,
+    base_pitch: Option<Pitch>,
+    base_level: ChannelLevel,
+    cents: i32,
+    level_offset: i16,
+    tone: Option<bool>,
+    noise: Option<bool>,
+}
+
+impl ChannelCtx {
+    pub fn new(
+        channel: Channel,
+        master_clock: u32,
+        base_pitch: Option<Pitch>,
+        base_level: ChannelLevel,
+    ) -> ChannelCtx {
+        ChannelCtx {
+            channel,
+            master_clock,
+            base_pitch,
+            base_level,
+            cents: 0,
+            level_offset: 0,
+            tone: None,
+            noise: None,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+
+    /// The note being played, if any.
+    pub fn base_pitch(&self) -> Option<Pitch> {
+        self.base_pitch
+    }
+
+    pub fn base_level(&self) -> ChannelLevel {
+        self.base_level
+    }
+
+    pub fn add_cents(&mut self, cents: i32) {
+        self.cents = self.cents.saturating_add(cents);
+    }
+
+    pub fn add_level(&mut self, steps: i8) {
+        self.level_offset = self.level_offset.saturating_add(steps as i16);
+    }
+
+    pub fn set_tone(&mut self, enabled: bool) {
+        self.tone = Some(enabled);
+    }
+
+    pub fn set_noise(&mut self, enabled: bool) {
+        self.noise = Some(enabled);
+    }
+
+    /// Pitch offset contributed so far.
+    pub fn cents(&self) -> i32 {
+        self.cents
+    }
+
+    /// Level offset contributed so far.
+    pub fn level_offset(&self) -> i16 {
+        self.level_offset
+    }
+
+    /// The tone period once every offset is applied. Whole semitones move
+    /// the note (folding by octaves at the ends of the range) and the
+    /// remaining cents detune the period.
+    pub fn period(&self) -> Option<u16> {
+        let base = self.base_pitch?;
+        let semitones = self
+            .cents
+            .div_euclid(100)
+            .clamp(i8::MIN as i32, i8::MAX as i32);
+        let pitch = base.transpose_folded(semitones as i8);
+        let period = tuning::fold_pitch_period(self.master_clock, pitch).period;
+        let period = tuning::detune_period(period as u32, self.cents.rem_euclid(100));
+        Some(period.clamp(1, MAX_TONE_PERIOD as u32) as u16)
+    }
+
+    /// The level once every offset is applied. Envelope-mode channels are
+    /// passed through untouched.
+    pub fn level(&self) -> ChannelLevel {
+        match self.base_level {
+            ChannelLevel::Fixed(base) => {
+                ChannelLevel::Fixed((base as i16 + self.level_offset).clamp(0, 15) as u8)
+            }
+            ChannelLevel::Envelope => ChannelLevel::Envelope,
+        }
+    }
+
+    pub fn tone(&self) -> Option<bool> {
+        self.tone
+    }
+
+    pub fn noise(&self) -> Option<bool> {
+        self.noise
+    }
+}
+
+/// A per-channel modulator run once per tick by an [`EffectChain`].
+pub trait Effect {
+    fn tick(&mut self, ctx: &mut ChannelCtx);
+
+    /// Called when the chain starts a note.
+    fn note_on(&mut self, _pitch: Pitch) {}
+
+    /// Called when the chain releases a note.
+    fn note_off(&mut self) {}
+}
+
+/// A fixed stack of effects on one channel, run in order every tick.
+///
+/// The chain owns the channel's note and level; the effects' own channel
+/// settings are ignored. Each tick makes at most one write per parameter
+/// (period, level, and each mixer bit), and the register cache drops any
+/// that haven't changed.
+pub struct EffectChain<'a, const N: usize> {
+    channel: Channel,
+    effects: [&'a mut dyn Effect; N],
+    base_pitch: Option<Pitch>,
+    base_level: ChannelLevel,
+}
+
+impl<'a, const N: usize> EffectChain<'a, N> {
+    pub fn new(channel: Channel, effects: [&'a mut dyn Effect; N]) -> EffectChain<'a, N> {
+        EffectChain {
+            channel,
+            effects,
+            base_pitch: None,
+            base_level: ChannelLevel::Fixed(15),
+        }
+    }
+
+    /// Starts `pitch` and tells every effect about it.
+    pub fn note_on(&mut self, pitch: Pitch) {
+        self.base_pitch = Some(pitch);
+        for effect in self.effects.iter_mut() {
+            effect.note_on(pitch);
+        }
+    }
+
+    /// Tells every effect the note was released. The note keeps sounding
+    /// until an effect (usually an envelope) brings the level down.
+    pub fn note_off(&mut self) {
+        for effect in self.effects.iter_mut() {
+            effect.note_off();
+        }
+    }
+
+    /// Changes the note without retriggering any effect.
+    pub fn set_base_pitch(&mut self, pitch: Option<Pitch>) {
+        self.base_pitch = pitch;
+    }
+
+    pub fn set_base_level(&mut self, level: ChannelLevel) {
+        self.base_level = level;
+    }
+
+    /// Runs every effect in order, then writes the combined result.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let mut ctx = ChannelCtx::new(
+            self.channel,
+            psg.master_clock(),
+            self.base_pitch,
+            self.base_level,
+        );
+        for effect in self.effects.iter_mut() {
+            effect.tick(&mut ctx);
+        }
+        if let Some(period) = ctx.period() {
+            psg.set_channel_period(self.channel, period)?;
+        }
+        psg.update_channel_level(self.channel, ctx.level())?;
+        if let Some(tone) = ctx.tone() {
+            psg.set_tone_enabled(self.channel, tone)?;
+        }
+        if let Some(noise) = ctx.noise() {
+            psg.set_noise_enabled(self.channel, noise)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adsr::Adsr;
+    use crate::arpeggiator::{ArpDirection, ArpPattern, Arpeggiator};
+    use crate::lfo::LfoWaveform;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use crate::vibrato::Vibrato;
+
+    struct NoiseBurst;
+
+    impl Effect for NoiseBurst {
+        fn tick(&mut self, ctx: &mut ChannelCtx) {
+            ctx.set_noise(true);
+            ctx.set_tone(false);
+        }
+    }
+
+    struct ToneOnly;
+
+    impl Effect for ToneOnly {
+        fn tick(&mut self, ctx: &mut ChannelCtx) {
+            ctx.set_tone(true);
+        }
+    }
+
+    #[test]
+    fn arpeggio_vibrato_and_adsr_combine() {
+        let mut psg = FakePsg::new();
+        let mut arp = Arpeggiator::new(ArpPattern::new(&[0, 12]), ArpDirection::Up, 2);
+        let mut vibrato = Vibrato::new(Channel::A, 100, 4, LfoWaveform::Triangle);
+        let mut adsr = Adsr::new(0, 2, 9, 0);
+        let mut chain = EffectChain::new(Channel::A, [&mut arp, &mut vibrato, &mut adsr]);
+        chain.note_on(Pitch::new(Note::A, 4));
+
+        chain.tick(&mut psg).unwrap();
+        // A4 at full level.
+        assert_eq!(psg.take_writes(), [(0x0, 0x1C), (0x1, 0x01), (0x8, 15)]);
+        chain.tick(&mut psg).unwrap();
+        // Vibrato peaks a semitone sharp, the decay starts.
+        assert_eq!(psg.take_writes(), [(0x0, 0x0C), (0x8, 12)]);
+        chain.tick(&mut psg).unwrap();
+        // The arpeggio jumps an octave, vibrato back at centre.
+        assert_eq!(psg.take_writes(), [(0x0, 0x8E), (0x1, 0x00), (0x8, 9)]);
+        chain.tick(&mut psg).unwrap();
+        // Vibrato a semitone flat of A5; sustain holds so only the period.
+        assert_eq!(psg.take_writes(), [(0x0, 0x96)]);
+    }
+
+    #[test]
+    fn last_mixer_request_wins() {
+        let mut psg = FakePsg::new();
+        let mut burst = NoiseBurst;
+        let mut tone = ToneOnly;
+        let mut chain = EffectChain::new(Channel::B, [&mut burst, &mut tone]);
+        chain.tick(&mut psg).unwrap();
+        // Noise on from the first effect, tone re-enabled by the second.
+        assert_eq!(psg.registers().mixer(), 0b0010_1101);
+    }
+
+    #[test]
+    fn envelope_level_is_not_offset() {
+        let mut ctx = ChannelCtx::new(Channel::C, 2_000_000, None, ChannelLevel::Envelope);
+        ctx.add_level(-4);
+        assert_eq!(ctx.level(), ChannelLevel::Envelope);
+        assert_eq!(ctx.period(), None);
+    }
+}
@@ -0,0 +1,740 @@
+//! A YM2149 in software, for trying things out with no chip to hand and for
+//! tests that listen to what gets played.
+//!
+//! [`Ym2149Emulator`] is a [`Psg`] like the hardware driver, so every helper
+//! and player runs against it unchanged, and [`Ym2149Emulator::render`]
+//! turns whatever has been written into mono samples. It keeps time in the
+//! chip's own steps, the master clock divided by 8: each tone generator
+//! flips after its period of them, the noise generator's 17-bit shift
+//! register moves on after twice its period, and the envelope moves on one
+//! of its 32 steps after its period. A period of 0 counts as 1 for all
+//! three, as on the chip. The generators themselves are
+//! [`math`](crate::math)'s, to be checked on their own.
+//!
+//! Output at any rate from 8 kHz to 96 kHz comes from those steps by
+//! averaging: each sample is the mean of the steps since the last, a
+//! window as long as the sample, with the leftover fraction of a step
+//! carried into the next so the rate holds exactly. It is all integer
+//! arithmetic, so the same writes always render the same samples, and it
+//! takes tones too high for the output rate down to a murmur rather than
+//! folding them back at full strength.
+//!
+//! Levels come out as measured on the chip, from
+//! [`YM2149_DAC`](crate::volume::YM2149_DAC), or from
+//! [`AY8910_DAC`](crate::volume::AY8910_DAC) once
+//! [`Ym2149Emulator::set_chip_kind`] says it is an AY-3-8910. The output
+//! never goes below 0, and nor do the samples: silence is 0 and all three
+//! channels at full level are 32766. A fixed level with tone and noise both
+//! off is a steady level, as digidrums rely on.
+//!
+//! [`Ym2149Emulator::set_dc_filter`] takes that offset out for listening,
+//! with a one-pole high-pass filter at about 20 Hz: a steady level then
+//! settles back to 0 and a tone swings either side of it.
+//!
+//! [`Ym2149Emulator::channel_levels`] and [`Ym2149Emulator::peak_levels`]
+//! say how loud each channel was in what was last rendered, envelope and
+//! all, for meters and visualisers.
+
+use core::convert::Infallible;
+
+use crate::math::{
+    fixed_level_step, EnvelopeGenerator, NoiseGenerator, SampleClock, ToneGenerator,
+};
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::DEFAULT_MASTER_CLOCK;
+use crate::volume::envelope_output;
+use crate::{Channel, ChipKind};
+
+/// What one channel at full level adds to a sample: a third of
+/// [`i16::MAX`], so all three together can't clip.
+const CHANNEL_MAX: i32 = i16::MAX as i32 / 3;
+
+/// A DAC output, full scale 0xFFFF, as one channel's part of a sample.
+const fn channel_sample(output: u16) -> i32 {
+    output as i32 * CHANNEL_MAX / 0xFFFF
+}
+
+/// How [`Ym2149Emulator::render_stereo`] places the three channels between
+/// left and right.
+///
+/// Both sides are scaled down by the weights of the more heavily weighted
+/// one, so however the channels are weighted, all three at full level come
+/// to [`i16::MAX`] on that side and never more there or on the other: each
+/// channel keeps its balance, and headroom is given up rather than clip.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum StereoMode {
+    /// All three channels in the middle.
+    #[default]
+    Mono,
+    /// A left, B in the middle and C right, in the order the chip's pins
+    /// come in.
+    StereoAbc,
+    /// A left, C in the middle and B right, as some Spectrum interfaces
+    /// wire them.
+    StereoAcb,
+    /// Each channel's weights, left then right, in Q8: 256 is all of it.
+    Custom([[u16; 2]; 3]),
+}
+
+impl StereoMode {
+    /// Each channel's left and right weights, in Q8.
+    pub const fn weights(self) -> [[u16; 2]; 3] {
+        const LEFT: [u16; 2] = [256, 0];
+        const MIDDLE: [u16; 2] = [128, 128];
+        const RIGHT: [u16; 2] = [0, 256];
+        match self {
+            StereoMode::Mono => [MIDDLE; 3],
+            StereoMode::StereoAbc => [LEFT, MIDDLE, RIGHT],
+            StereoMode::StereoAcb => [LEFT, RIGHT, MIDDLE],
+            StereoMode::Custom(weights) => weights,
+        }
+    }
+}
+
+/// The high-pass filter taking the offset out of the output, as set with
+/// [`Ym2149Emulator::set_dc_filter`]: `y = x - x' + r * y'`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+struct DcFilter {
+    /// The last input.
+    input: i32,
+    /// The last output, in Q8 so it decays smoothly all the way to 0.
+    output: i64,
+}
+
+impl DcFilter {
+    /// `r` in Q16 for a cutoff of about 20 Hz at `sample_rate`: 1 less
+    /// `2 * pi * 20 / sample_rate`.
+    fn pole(sample_rate: u32) -> i64 {
+        let fraction = 8_235_496 / sample_rate.max(1_000) as i64;
+        65_536 - fraction
+    }
+
+    fn filter(&mut self, input: i32, pole: i64) -> i16 {
+        let change = (input - self.input) as i64;
+        self.input = input;
+        // Dividing rounds towards 0, so the output can't get stuck just off it.
+        self.output = (change << 8) + self.output * pole / 65_536;
+        (self.output / 256).clamp(i16::MIN as i64, i16::MAX as i64) as i16
+    }
+}
+
+/// The loudest one channel has been lately, for
+/// [`Ym2149Emulator::peak_levels`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+struct Peak {
+    level: u8,
+    /// Samples rendered since.
+    age: u32,
+}
+
+impl Peak {
+    /// Takes in a sample whose loudest was `level`.
+    fn update(&mut self, level: u8) {
+        *self = match level >= self.level {
+            true => Peak { level, age: 0 },
+            false => Peak {
+                level: self.level,
+                age: self.age.saturating_add(1),
+            },
+        };
+    }
+}
+
+/// A software YM2149, written to through [`Psg`] and listened to with
+/// [`Ym2149Emulator::render`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ym2149Emulator {
+    registers: Registers,
+    master_clock: u32,
+    chip: ChipKind,
+    stereo: StereoMode,
+    tones: [ToneGenerator; 3],
+    noise: NoiseGenerator,
+    envelope: EnvelopeGenerator,
+    /// Each channel's level at the end of the last sample rendered.
+    levels: [u8; 3],
+    peaks: [Peak; 3],
+    samples: SampleClock,
+    /// One for each side, or `None` to leave the offset in.
+    dc_filter: Option<[DcFilter; 2]>,
+}
+
+impl Ym2149Emulator {
+    /// A chip just out of reset, at 2 MHz.
+    pub const fn new() -> Ym2149Emulator {
+        Ym2149Emulator {
+            registers: Registers::new(),
+            master_clock: DEFAULT_MASTER_CLOCK,
+            chip: ChipKind::Ym2149,
+            stereo: StereoMode::Mono,
+            tones: [ToneGenerator::new(); 3],
+            noise: NoiseGenerator::new(),
+            envelope: EnvelopeGenerator::new(),
+            levels: [0; 3],
+            peaks: [Peak { level: 0, age: 0 }; 3],
+            samples: SampleClock::new(),
+            dc_filter: None,
+        }
+    }
+
+    /// Sets the frequency of the clock the chip would be fed, which
+    /// [`Ym2149Emulator::render`] plays at and pitch conversions depend on.
+    pub fn set_master_clock(&mut self, hz: u32) {
+        self.master_clock = hz;
+    }
+
+    /// Sets which chip's levels to play: a YM2149 unless set otherwise.
+    pub fn set_chip_kind(&mut self, chip: ChipKind) {
+        self.chip = chip;
+    }
+
+    pub fn stereo_mode(&self) -> StereoMode {
+        self.stereo
+    }
+
+    /// Sets how [`Ym2149Emulator::render_stereo`] places the channels:
+    /// [`StereoMode::Mono`] unless set otherwise.
+    pub fn set_stereo_mode(&mut self, mode: StereoMode) {
+        self.stereo = mode;
+    }
+
+    /// Takes the steady offset out of rendered samples, or leaves it in,
+    /// as it is unless set otherwise. Turning it on starts the filter from
+    /// silence.
+    pub fn set_dc_filter(&mut self, enabled: bool) {
+        self.dc_filter = match (enabled, self.dc_filter) {
+            (false, _) => None,
+            (true, None) => Some([DcFilter::default(); 2]),
+            (true, filters) => filters,
+        };
+    }
+
+    /// Plays the chip into `out` at `sample_rate` samples a second, carrying
+    /// on from where the last call left off.
+    pub fn render(&mut self, out: &mut [i16], sample_rate: u32) {
+        for sample in out {
+            let outputs = self.next_outputs(sample_rate);
+            let mixed = outputs.into_iter().map(channel_sample).sum::<i32>();
+            *sample = match &mut self.dc_filter {
+                Some([filter, _]) => filter.filter(mixed, DcFilter::pole(sample_rate)),
+                None => mixed as i16,
+            };
+        }
+    }
+
+    /// As [`Ym2149Emulator::render`], into left and right samples placed as
+    /// the [`StereoMode`] says.
+    pub fn render_stereo(&mut self, out: &mut [[i16; 2]], sample_rate: u32) {
+        let weights = self.stereo.weights();
+        let total = |side: usize| {
+            weights
+                .iter()
+                .map(|weight| weight[side] as u64)
+                .sum::<u64>()
+        };
+        let total = total(0).max(total(1)).max(1) * 0xFFFF;
+        for sample in out {
+            let outputs = self.next_outputs(sample_rate);
+            for (side, value) in sample.iter_mut().enumerate() {
+                let sum: u64 = (outputs.into_iter().zip(weights))
+                    .map(|(output, weight)| output as u64 * weight[side] as u64)
+                    .sum();
+                let mixed = (sum * i16::MAX as u64 / total) as i32;
+                *value = match &mut self.dc_filter {
+                    Some(filters) => filters[side].filter(mixed, DcFilter::pole(sample_rate)),
+                    None => mixed as i16,
+                };
+            }
+        }
+    }
+
+    /// Each channel's level, 0 to 31 in the YM2149's steps, where the last
+    /// sample rendered ended: 0 while the mixer has it off, the envelope's
+    /// level if it follows that, or `2 * level + 1` for a fixed level above
+    /// 0, the step that sounds the same. An AY-3-8910 reports the same steps,
+    /// playing each pair as one.
+    pub fn channel_levels(&self) -> [u8; 3] {
+        self.levels
+    }
+
+    /// The loudest each channel has been, as [`Ym2149Emulator::channel_levels`]
+    /// has it, over the last `window` samples rendered, for meters. A
+    /// peak is held for `window` samples and then falls away over as many
+    /// again, never below the level now.
+    pub fn peak_levels(&self, window: u32) -> [u8; 3] {
+        let window = window.max(1) as u64;
+        core::array::from_fn(|index| {
+            let Peak { level, age } = self.peaks[index];
+            let fallen = (age as u64).saturating_sub(window) * 32 / window;
+            let level = level.saturating_sub(fallen.min(31) as u8);
+            level.max(self.levels[index])
+        })
+    }
+
+    /// Each channel's DAC output over the next sample, averaged across the
+    /// chip steps it covers.
+    fn next_outputs(&mut self, sample_rate: u32) -> [u16; 3] {
+        let steps = self.samples.steps(self.master_clock, sample_rate);
+        if steps == 0 {
+            self.levels = self.levels();
+        }
+        let mut loudest = match steps {
+            0 => self.levels,
+            _ => [0; 3],
+        };
+        let mut sums = [0u64; 3];
+        for _ in 0..steps {
+            self.step();
+            self.levels = self.levels();
+            for (channel, level) in self.levels.into_iter().enumerate() {
+                loudest[channel] = loudest[channel].max(level);
+                sums[channel] += envelope_output(self.chip, level) as u64;
+            }
+        }
+        for (peak, level) in self.peaks.iter_mut().zip(loudest) {
+            peak.update(level);
+        }
+        match steps {
+            0 => self.levels.map(|level| envelope_output(self.chip, level)),
+            _ => sums.map(|sum| (sum / steps) as u16),
+        }
+    }
+
+    /// Moves every generator on by one chip step.
+    fn step(&mut self) {
+        let values = self.registers.values();
+        for channel in Channel::ALL {
+            let (fine, rough) = channel.period_registers();
+            let period = u16::from_le_bytes([values[fine as usize], values[rough as usize]]);
+            self.tones[channel.index()].tick(period);
+        }
+        self.noise.tick(values[0x6]);
+        let period = u16::from_le_bytes([values[0xB], values[0xC]]);
+        self.envelope.tick(period, values[0xD]);
+    }
+
+    /// Each channel's level as it is now, as
+    /// [`Ym2149Emulator::channel_levels`] gives them.
+    fn levels(&self) -> [u8; 3] {
+        let mixer = self.registers.mixer();
+        let noise = self.noise.is_high();
+        let mut levels = [0; 3];
+        for channel in Channel::ALL {
+            let tone_off = mixer & 1 << channel.index() != 0;
+            let noise_off = mixer & 8 << channel.index() != 0;
+            let tone = self.tones[channel.index()].is_high();
+            if !((tone || tone_off) && (noise || noise_off)) {
+                continue;
+            }
+            let value = self.registers.value(channel.level_register());
+            levels[channel.index()] = match value & 0x10 {
+                0 => fixed_level_step(value),
+                _ => self.envelope.level(),
+            };
+        }
+        levels
+    }
+}
+
+impl Default for Ym2149Emulator {
+    fn default() -> Ym2149Emulator {
+        Ym2149Emulator::new()
+    }
+}
+
+impl Psg for Ym2149Emulator {
+    type Error = Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Infallible> {
+        self.registers.set(address, data);
+        if address == 0xD {
+            self.envelope.restart(data);
+        }
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.chip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(feature = "player")]
+    use crate::frame_player::{FramePlayer, PlayStatus, R13_UNCHANGED};
+    #[cfg(feature = "player")]
+    use crate::test_support::FakePsg;
+    use crate::ChannelLevel;
+    use std::vec;
+
+    /// A chip step a sample.
+    const STEP_RATE: u32 = DEFAULT_MASTER_CLOCK / 8;
+
+    /// The samples one channel gives at each envelope step on a YM2149.
+    fn levels() -> [i16; 32] {
+        core::array::from_fn(|step| {
+            channel_sample(envelope_output(ChipKind::Ym2149, step as u8)) as i16
+        })
+    }
+
+    #[test]
+    fn silent_until_written() {
+        let mut emulator = Ym2149Emulator::new();
+        let mut out = [1; 256];
+        emulator.render(&mut out, 44_100);
+        assert!(out.iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn tones_play_at_their_period() {
+        let mut emulator = Ym2149Emulator::new();
+        // 2 MHz / (16 * 250): 500 Hz.
+        emulator.set_channel_period(Channel::B, 250).unwrap();
+        emulator.set_tone_enabled(Channel::B, true).unwrap();
+        emulator
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(15))
+            .unwrap();
+        let mut out = vec![0; STEP_RATE as usize];
+        emulator.render(&mut out, STEP_RATE);
+        let rises = out.windows(2).filter(|pair| pair[0] < pair[1]).count();
+        assert_eq!(rises, 500);
+        assert!(out
+            .iter()
+            .all(|&sample| sample == 0 || sample == CHANNEL_MAX as i16));
+
+        // A steady level with tone and noise off, as digidrums play.
+        emulator.set_tone_enabled(Channel::B, false).unwrap();
+        emulator
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(7))
+            .unwrap();
+        emulator.render(&mut out[..64], 44_100);
+        assert!(out[..64].iter().all(|&sample| sample == levels()[15]));
+    }
+
+    #[test]
+    fn envelope_shapes_ramp_then_hold_or_repeat() {
+        let mut emulator = Ym2149Emulator::new();
+        emulator
+            .update_channel_level(Channel::A, ChannelLevel::Envelope)
+            .unwrap();
+        emulator.set_register_value(0xB, 1).unwrap();
+        // Up, then held at the top.
+        emulator.set_register_value(0xD, 0x0D).unwrap();
+        let mut out = [0; 40];
+        emulator.render(&mut out, STEP_RATE);
+        let levels = levels();
+        assert_eq!(out[..31], levels[1..]);
+        assert!(out[31..].iter().all(|&sample| sample == levels[31]));
+
+        // Down, over and over.
+        emulator.set_register_value(0xD, 0x08).unwrap();
+        let mut out = [0; 64];
+        emulator.render(&mut out, STEP_RATE);
+        let mut ramp = levels;
+        ramp.reverse();
+        assert_eq!(out[..31], ramp[1..]);
+        assert_eq!(out[31..63], ramp);
+
+        // Up once, then silent.
+        emulator.set_register_value(0xD, 0x04).unwrap();
+        emulator.render(&mut out, STEP_RATE);
+        assert_eq!(out[30], levels[31]);
+        assert!(out[31..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn chip_kinds_play_their_own_levels() {
+        let render = |chip: ChipKind, level: ChannelLevel| {
+            let mut emulator = Ym2149Emulator::new();
+            emulator.set_chip_kind(chip);
+            emulator.update_channel_level(Channel::A, level).unwrap();
+            emulator.set_register_value(0xB, 1).unwrap();
+            emulator.set_register_value(0xD, 0x0C).unwrap();
+            let mut out = [0; 64];
+            emulator.render(&mut out, STEP_RATE);
+            out
+        };
+        let (ym, ay) = (ChipKind::Ym2149, ChipKind::Ay8910);
+        // Full level is the same on both, quieter ones aren't.
+        let full = ChannelLevel::Fixed(15);
+        assert_eq!(render(ym, full), render(ay, full));
+        assert!(render(ym, full)
+            .iter()
+            .all(|&sample| sample == CHANNEL_MAX as i16));
+        let quiet = ChannelLevel::Fixed(7);
+        assert_eq!(render(ym, quiet)[0], 849);
+        assert_eq!(render(ay, quiet)[0], 1172);
+        // The AY's envelope holds each of its 16 levels for two steps. Each
+        // sample is a step on from the last, starting from step 1.
+        let (ym, ay) = (
+            render(ym, ChannelLevel::Envelope),
+            render(ay, ChannelLevel::Envelope),
+        );
+        assert!(ym[..31].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ay[1..31].chunks(2).all(|pair| pair[0] == pair[1]));
+        assert!(ay[..31].windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(ym[30], ay[30]);
+    }
+
+    #[test]
+    fn stereo_modes_place_the_channels() {
+        let render = |mode: StereoMode, channels: &[Channel]| {
+            let mut emulator = Ym2149Emulator::new();
+            emulator.set_stereo_mode(mode);
+            for &channel in channels {
+                emulator
+                    .update_channel_level(channel, ChannelLevel::Fixed(15))
+                    .unwrap();
+            }
+            let mut out = [[0; 2]; 16];
+            emulator.render_stereo(&mut out, 44_100);
+            assert!(out.iter().all(|&sample| sample == out[0]));
+            out[0]
+        };
+        const MAX: i16 = i16::MAX;
+        // A alone, left over right.
+        let a = [Channel::A];
+        assert_eq!(render(StereoMode::Mono, &a), [MAX / 3; 2]);
+        assert_eq!(render(StereoMode::StereoAbc, &a), [MAX / 3 * 2, 0]);
+        assert_eq!(render(StereoMode::StereoAcb, &a), [MAX / 3 * 2, 0]);
+        let quarter_right = StereoMode::Custom([[192, 64], [128, 128], [128, 128]]);
+        // Three to one, scaled by the left's weights.
+        assert_eq!(render(quarter_right, &a), [MAX / 7 * 3, MAX / 7]);
+        // B and C swap between the two layouts.
+        assert_eq!(
+            render(StereoMode::StereoAbc, &[Channel::C]),
+            render(StereoMode::StereoAcb, &[Channel::B])
+        );
+        // Nothing clips with everything at full level.
+        for mode in [
+            StereoMode::Mono,
+            StereoMode::StereoAbc,
+            StereoMode::StereoAcb,
+            quarter_right,
+            StereoMode::Custom([[256, 0]; 3]),
+        ] {
+            let full = render(mode, &Channel::ALL);
+            assert_eq!(full[0], MAX, "{mode:?}");
+        }
+        assert_eq!(
+            render(StereoMode::Custom([[256, 0]; 3]), &Channel::ALL)[1],
+            0
+        );
+    }
+
+    /// The size of `samples` at `bin` of a DFT as long as they are.
+    fn dft_magnitude(samples: &[i16], bin: usize) -> f64 {
+        let (mut real, mut imaginary) = (0.0, 0.0);
+        for (n, &sample) in samples.iter().enumerate() {
+            let angle = 2.0 * core::f64::consts::PI * (bin * n) as f64 / samples.len() as f64;
+            real += sample as f64 * angle.cos();
+            imaginary -= sample as f64 * angle.sin();
+        }
+        (real * real + imaginary * imaginary).sqrt()
+    }
+
+    #[test]
+    fn a_440_hz_square_peaks_at_440_hz_at_every_rate() {
+        for rate in [8_000, 22_050, 44_100, 48_000, 96_000] {
+            let mut emulator = Ym2149Emulator::new();
+            emulator.set_dc_filter(true);
+            // 2 MHz / (16 * 284): 440.1 Hz.
+            emulator.set_channel_period(Channel::A, 284).unwrap();
+            emulator.set_tone_enabled(Channel::A, true).unwrap();
+            emulator
+                .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+                .unwrap();
+            // Let the filter settle, then a tenth of a second: 10 Hz bins.
+            let mut out = vec![0; rate as usize / 10];
+            emulator.render(&mut out, rate);
+            emulator.render(&mut out, rate);
+            let peak = (1..200)
+                .max_by(|&a, &b| dft_magnitude(&out, a).total_cmp(&dft_magnitude(&out, b)))
+                .unwrap();
+            assert_eq!(peak, 44, "{rate} Hz");
+        }
+    }
+
+    #[test]
+    fn the_dc_filter_settles_to_no_offset() {
+        let mut emulator = Ym2149Emulator::new();
+        emulator.set_dc_filter(true);
+        emulator.set_channel_period(Channel::B, 284).unwrap();
+        emulator.set_tone_enabled(Channel::B, true).unwrap();
+        // A tone on B, and a steady level on C.
+        for channel in [Channel::B, Channel::C] {
+            emulator
+                .update_channel_level(channel, ChannelLevel::Fixed(15))
+                .unwrap();
+        }
+        let mut out = vec![0; 44_100];
+        for _ in 0..5 {
+            emulator.render(&mut out, 44_100);
+        }
+        let mean = out.iter().map(|&sample| sample as i64).sum::<i64>() / out.len() as i64;
+        assert!(mean.abs() < 50, "{mean}");
+        assert!(out.iter().any(|&sample| sample < -5000));
+        assert!(out.iter().any(|&sample| sample > 5000));
+
+        // Silenced, it comes back to exactly 0, on both sides in stereo.
+        emulator.silence().unwrap();
+        emulator.set_stereo_mode(StereoMode::StereoAbc);
+        let mut out = vec![[0; 2]; 44_100];
+        emulator.render_stereo(&mut out, 44_100);
+        assert_eq!(out[44_099], [0, 0]);
+        emulator.set_dc_filter(false);
+        emulator
+            .update_channel_level(Channel::C, ChannelLevel::Fixed(15))
+            .unwrap();
+        emulator.render_stereo(&mut out[..1], 44_100);
+        assert_eq!(out[0], [0, i16::MAX / 3 * 2]);
+    }
+
+    #[test]
+    fn periods_of_0_count_as_1() {
+        let render = |period: u8| {
+            let mut emulator = Ym2149Emulator::new();
+            emulator
+                .set_channel_period(Channel::A, period as u16)
+                .unwrap();
+            emulator.set_register_value(0x6, period).unwrap();
+            emulator.set_channel_mixer(Channel::A, true, true).unwrap();
+            emulator.set_channel_period(Channel::B, 3).unwrap();
+            emulator.set_tone_enabled(Channel::B, true).unwrap();
+            emulator
+                .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+                .unwrap();
+            emulator
+                .update_channel_level(Channel::B, ChannelLevel::Envelope)
+                .unwrap();
+            emulator.set_register_value(0xB, period).unwrap();
+            emulator.set_register_value(0xD, 0x0E).unwrap();
+            let mut out = [0; 256];
+            emulator.render(&mut out, STEP_RATE);
+            out
+        };
+        assert_eq!(render(0), render(1));
+        assert_ne!(render(1), render(2));
+    }
+
+    #[test]
+    fn noise_is_on_about_half_the_time() {
+        let mut emulator = Ym2149Emulator::new();
+        emulator.set_noise_enabled(Channel::C, true).unwrap();
+        emulator
+            .update_channel_level(Channel::C, ChannelLevel::Fixed(15))
+            .unwrap();
+        let mut out = vec![0; 10_000];
+        emulator.render(&mut out, STEP_RATE);
+        let on = out.iter().filter(|&&sample| sample != 0).count();
+        assert!((4000..6000).contains(&on), "{on}");
+    }
+
+    #[cfg(feature = "player")]
+    #[test]
+    fn players_run_against_it_as_against_the_chip() {
+        static SONG: [[u8; 16]; 2] = [
+            [100, 0, 0, 0, 0, 0, 0, 0x3E, 15, 0, 0, 0, 0, 0x0E, 0, 0],
+            [
+                90,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0x3E,
+                12,
+                0,
+                0,
+                0,
+                0,
+                R13_UNCHANGED,
+                0,
+                0,
+            ],
+        ];
+        let (mut emulator, mut psg) = (Ym2149Emulator::new(), FakePsg::new());
+        let mut player = FramePlayer::new(&SONG[..]);
+        let mut heard = FramePlayer::new(&SONG[..]);
+        player.play();
+        heard.play();
+        let mut out = [0; 882];
+        for _ in 0..2 {
+            assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Played));
+            assert_eq!(heard.tick(&mut emulator), Ok(PlayStatus::Played));
+            assert_eq!(emulator.registers(), psg.registers());
+            out.fill(0);
+            emulator.render(&mut out, 44_100);
+            assert!(out.iter().any(|&sample| sample > 0));
+        }
+    }
+
+    #[test]
+    fn channel_levels_follow_what_was_played() {
+        let mut emulator = Ym2149Emulator::new();
+        emulator
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        emulator
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(7))
+            .unwrap();
+        emulator
+            .update_channel_level(Channel::C, ChannelLevel::Fixed(15))
+            .unwrap();
+        emulator.set_channel_period(Channel::C, 4).unwrap();
+        emulator.set_tone_enabled(Channel::C, true).unwrap();
+        let mut out = [0; 4];
+        emulator.render(&mut out[..1], STEP_RATE);
+        // C's tone starts low, gating it off.
+        assert_eq!(emulator.channel_levels(), [31, 15, 0]);
+        emulator.render(&mut out[..3], STEP_RATE);
+        assert_eq!(emulator.channel_levels(), [31, 15, 31]);
+        emulator.render(&mut out, STEP_RATE);
+        assert_eq!(emulator.channel_levels(), [31, 15, 0]);
+
+        // The envelope's live level, up a step a sample and then held.
+        emulator
+            .update_channel_level(Channel::A, ChannelLevel::Envelope)
+            .unwrap();
+        emulator.set_register_value(0xB, 1).unwrap();
+        emulator.set_register_value(0xD, 0x0D).unwrap();
+        for step in 1..40 {
+            emulator.render(&mut out[..1], STEP_RATE);
+            assert_eq!(emulator.channel_levels()[0], step.min(31));
+        }
+    }
+
+    #[test]
+    fn peak_levels_hold_then_fall_to_the_level_now() {
+        let mut emulator = Ym2149Emulator::new();
+        emulator
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        let mut out = [0; 100];
+        emulator.render(&mut out[..10], 44_100);
+        emulator
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(3))
+            .unwrap();
+        emulator.render(&mut out, 44_100);
+        assert_eq!(emulator.channel_levels(), [7, 0, 0]);
+        assert_eq!(emulator.peak_levels(100), [31, 0, 0]);
+        emulator.render(&mut out[..50], 44_100);
+        assert_eq!(emulator.peak_levels(100), [15, 0, 0]);
+        assert_eq!(emulator.peak_levels(10), [7, 0, 0]);
+        emulator.render(&mut out[..60], 44_100);
+        assert_eq!(emulator.peak_levels(100), [7, 0, 0]);
+    }
+}
@@ -0,0 +1,58 @@
+//! What the features leave out: with none, the crate is the driver and the
+//! `Psg` trait, building for the smallest Cortex-M with nothing but its two
+//! dependencies.
+//!
+//! These run cargo, so they are ignored by default; CI runs them with
+//! `cargo test -- --ignored`, with `thumbv6m-none-eabi` installed.
+
+extern crate std;
+
+use std::process::Command;
+use std::string::String;
+use std::vec::Vec;
+
+const TARGET: &str = "thumbv6m-none-eabi";
+
+/// Cargo in this crate, building into a directory of its own rather than
+/// waiting on the one the tests were built in.
+fn cargo(subcommand: &str) -> Command {
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg(subcommand)
+        .args(["--no-default-features", "--target", TARGET])
+        .env(
+            "CARGO_TARGET_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/target/core"),
+        );
+    command
+}
+
+#[test]
+#[ignore = "runs cargo"]
+fn the_core_builds_alone_for_thumbv6m() {
+    let build = cargo("build").arg("--release").output().unwrap();
+    assert!(
+        build.status.success(),
+        "{}",
+        String::from_utf8_lossy(&build.stderr)
+    );
+}
+
+#[test]
+#[ignore = "runs cargo"]
+fn the_core_depends_on_embedded_hal_and_bitflags_alone() {
+    let tree = cargo("tree")
+        .args(["--edges", "normal", "--prefix", "none", "--format", "{lib}"])
+        .output()
+        .unwrap();
+    assert!(tree.status.success());
+    let mut crates: Vec<_> = String::from_utf8(tree.stdout)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    crates.sort();
+    crates.dedup();
+    assert_eq!(crates, ["bitflags", "embedded_hal", "ym2149"]);
+}
@@ -0,0 +1,322 @@
+//! A C interface to one chip, for firmware that is part C: Rust builds the
+//! driver and hands it over with [`install`], after which C code plays
+//! tones and sound effects on it through the `ym_*` functions.
+//!
+//! The declarations are in `include/ym2149.h`, which `cbindgen` makes from
+//! this module with the `cbindgen.toml` beside it. Every function returns
+//! a [`Status`], `YM_STATUS_OK` or a negative error, apart from
+//! [`ym_trigger_sfx`], which returns the channel it chose in its place.
+//! Effects are named by their [`Sfx`] number, `YM_SFX_COIN` and so on.
+//! `examples/ffi` has both halves of a program using them.
+//!
+//! Effects go through an [`SfxArbiter`] stealing tones for them, so a tone
+//! played from C sounds again once an effect that took its channel ends.
+//! Registers written with [`ym_write_register`] count as the music's too.
+//!
+//! Each call takes a [`critical_section`] for as long as it runs, so any
+//! of them can be made from any thread or interrupt handler, and a call
+//! made while another is running waits for it rather than interleaving
+//! bus cycles with it. The cost is that interrupts are held off while the
+//! chip is written, at most a few dozen register writes for
+//! [`ym_player_tick`]; call that from the main loop, or from a timer
+//! interrupt of low priority. The firmware provides the critical section
+//! implementation, as it does for any crate using one.
+//!
+//! ```
+//! use ym2149::{ffi, Psg};
+//!
+//! // `chip` lives for good, in a `static` cell or a singleton.
+//! fn start_audio<P: Psg + Send>(chip: &'static mut P) {
+//!     ffi::install(chip);
+//!     // C takes it from here, starting with `ym_init()`.
+//! }
+//! ```
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use crate::arbiter::{SfxArbiter, SfxPolicy};
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::sfx::Sfx;
+use crate::tuning::{self, MAX_TONE_PERIOD};
+use crate::{Channel, ChannelLevel, ChipKind};
+
+/// What a call returns: [`Status::Ok`], or why it failed, which is always
+/// negative.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    /// No chip has been installed.
+    NotInstalled = -1,
+    /// A channel, register, effect or level out of range.
+    InvalidArgument = -2,
+    /// The chip's driver failed to write.
+    ChipFailed = -3,
+    /// Every channel is playing a more important effect.
+    NoChannel = -4,
+}
+
+/// What the functions here need of a chip, without its error type, so one
+/// of any kind can sit in the static.
+trait AnyChip: Send {
+    fn write(&mut self, address: u8, data: u8) -> Result<(), ChipFailed>;
+    fn shadow(&self) -> &Registers;
+    fn clock(&self) -> u32;
+    fn kind(&self) -> ChipKind;
+}
+
+impl<P: Psg + Send> AnyChip for P {
+    fn write(&mut self, address: u8, data: u8) -> Result<(), ChipFailed> {
+        self.set_register_value(address, data)
+            .map_err(|_| ChipFailed)
+    }
+
+    fn shadow(&self) -> &Registers {
+        self.registers()
+    }
+
+    fn clock(&self) -> u32 {
+        self.master_clock()
+    }
+
+    fn kind(&self) -> ChipKind {
+        self.chip_kind()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ChipFailed;
+
+/// The installed chip, as a [`Psg`].
+struct Installed(&'static mut dyn AnyChip);
+
+impl Psg for Installed {
+    type Error = ChipFailed;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), ChipFailed> {
+        self.0.write(address, data)
+    }
+
+    fn registers(&self) -> &Registers {
+        self.0.shadow()
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.0.clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.0.kind()
+    }
+}
+
+static CHIP: Mutex<RefCell<Option<SfxArbiter<Installed>>>> = Mutex::new(RefCell::new(None));
+
+/// Hands `chip` to the C functions, in place of any chip installed before.
+/// Nothing is written to it until C calls [`ym_init`].
+pub fn install<P: Psg + Send>(chip: &'static mut P) {
+    let arbiter = SfxArbiter::new(Installed(chip), SfxPolicy::StealMusic);
+    critical_section::with(|cs| CHIP.borrow_ref_mut(cs).replace(arbiter));
+}
+
+/// Runs `f` on the installed chip in a critical section.
+fn with_chip<T: From<Status>>(
+    f: impl FnOnce(&mut SfxArbiter<Installed>) -> Result<T, ChipFailed>,
+) -> T {
+    critical_section::with(|cs| match CHIP.borrow_ref_mut(cs).as_mut() {
+        Some(arbiter) => f(arbiter).unwrap_or(Status::ChipFailed.into()),
+        None => Status::NotInstalled.into(),
+    })
+}
+
+impl From<Status> for i32 {
+    fn from(status: Status) -> i32 {
+        status as i32
+    }
+}
+
+fn channel(index: u8) -> Option<Channel> {
+    Channel::ALL.get(index as usize).copied()
+}
+
+/// Silences the chip and forgets every tone and effect played on it.
+#[no_mangle]
+pub extern "C" fn ym_init() -> Status {
+    with_chip(|arbiter| {
+        arbiter.reset()?;
+        Ok(Status::Ok)
+    })
+}
+
+/// Writes `value` to register `address`, 0 to 15, as the music: a channel
+/// an effect holds takes it once the effect ends.
+#[no_mangle]
+pub extern "C" fn ym_write_register(address: u8, value: u8) -> Status {
+    if address > 0xF {
+        return Status::InvalidArgument;
+    }
+    with_chip(|arbiter| {
+        arbiter.music().set_register_value(address, value)?;
+        Ok(Status::Ok)
+    })
+}
+
+/// Plays a tone of `hz` on `channel`, 0 to 2 for A to C, at `level`, 0 to
+/// 15. Level 0 stops it, leaving the channel free for effects.
+#[no_mangle]
+pub extern "C" fn ym_play_tone(channel: u8, hz: u32, level: u8) -> Status {
+    let (Some(channel), true) = (self::channel(channel), level <= 15) else {
+        return Status::InvalidArgument;
+    };
+    with_chip(|arbiter| {
+        let playing = level > 0;
+        arbiter.set_music_channel(channel, playing.then_some(0));
+        let mut music = arbiter.music();
+        if playing {
+            let period =
+                tuning::millihertz_to_period(music.master_clock(), hz.saturating_mul(1000));
+            music.set_channel_period(channel, period.clamp(1, MAX_TONE_PERIOD as u32) as u16)?;
+        }
+        music.set_tone_enabled(channel, playing)?;
+        music.update_channel_level(channel, ChannelLevel::Fixed(level))?;
+        Ok(Status::Ok)
+    })
+}
+
+/// Starts effect `sfx`, one of the `YM_SFX_*`, at `priority`, returning the
+/// channel it plays on, 0 to 2, or a negative [`Status`].
+#[no_mangle]
+pub extern "C" fn ym_trigger_sfx(sfx: u8, priority: u8) -> i32 {
+    let Some(&sfx) = Sfx::ALL.get(sfx as usize) else {
+        return Status::InvalidArgument.into();
+    };
+    with_chip(|arbiter| {
+        Ok(match arbiter.trigger_sfx(sfx, priority) {
+            Some(channel) => channel.index() as i32,
+            None => Status::NoChannel.into(),
+        })
+    })
+}
+
+/// Moves the effects on by a tick. Call it at the rate effects are
+/// written for, usually 50 Hz.
+#[no_mangle]
+pub extern "C" fn ym_player_tick() -> Status {
+    with_chip(|arbiter| {
+        arbiter.tick()?;
+        Ok(Status::Ok)
+    })
+}
+
+/// Stops every tone and effect, as [`ym_init`] does.
+#[no_mangle]
+pub extern "C" fn ym_stop_all() -> Status {
+    ym_init()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::sync::Mutex as StdMutex;
+    use std::vec::Vec;
+
+    use crate::tuning::DEFAULT_MASTER_CLOCK;
+
+    /// Every write the installed chip has had.
+    static WRITES: StdMutex<Vec<(u8, u8)>> = StdMutex::new(Vec::new());
+
+    /// A chip logging to [`WRITES`], as the one installed can't be looked
+    /// at once it is handed over.
+    struct Logged(Registers);
+
+    impl Psg for Logged {
+        type Error = ();
+
+        fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), ()> {
+            WRITES.lock().unwrap().push((address, data));
+            self.0.set(address, data);
+            Ok(())
+        }
+
+        fn registers(&self) -> &Registers {
+            &self.0
+        }
+
+        fn master_clock(&self) -> u32 {
+            DEFAULT_MASTER_CLOCK
+        }
+    }
+
+    fn writes() -> Vec<(u8, u8)> {
+        core::mem::take(&mut *WRITES.lock().unwrap())
+    }
+
+    // One test, as there is one chip.
+    #[test]
+    fn c_calls_play_tones_and_effects() {
+        assert_eq!(ym_init(), Status::NotInstalled);
+        install(Box::leak(Box::new(Logged(Registers::new()))));
+        assert_eq!(ym_init(), Status::Ok);
+        assert_eq!(
+            writes(),
+            [(0x8, 0), (0x9, 0), (0xA, 0), (0x7, 0x3F)],
+            "silenced"
+        );
+
+        assert_eq!(ym_play_tone(0, 440, 12), Status::Ok);
+        // 2 MHz / 16 / 440 Hz is a period of 284.
+        assert_eq!(writes(), [(0x0, 0x1C), (0x1, 0x01), (0x7, 0x3E), (0x8, 12)]);
+        assert_eq!(ym_play_tone(3, 440, 12), Status::InvalidArgument);
+        assert_eq!(ym_play_tone(0, 440, 16), Status::InvalidArgument);
+        assert_eq!(ym_write_register(0x10, 0), Status::InvalidArgument);
+        assert_eq!(ym_trigger_sfx(6, 0), Status::InvalidArgument as i32);
+        assert!(writes().is_empty());
+
+        // Effects take the free channels first, then the tone's.
+        assert_eq!(ym_trigger_sfx(Sfx::Coin as u8, 1), 1);
+        assert_eq!(ym_trigger_sfx(Sfx::Coin as u8, 1), 2);
+        assert_eq!(ym_trigger_sfx(Sfx::Jump as u8, 1), 0);
+        assert_eq!(ym_trigger_sfx(Sfx::Hit as u8, 0), Status::NoChannel as i32);
+        for _ in 0..20 {
+            assert_eq!(ym_player_tick(), Status::Ok);
+        }
+        // Once the jump ends, the tone comes back.
+        let played = writes();
+        let restored = [(0x0, 0x1C), (0x1, 0x01), (0x8, 12)];
+        assert!(played.windows(3).any(|window| window == restored));
+        // And the effects' channels are turned off after them.
+        assert_eq!(played.last(), Some(&(0x7, 0x3E)));
+
+        assert_eq!(ym_write_register(0x6, 0x1F), Status::Ok);
+        assert_eq!(ym_stop_all(), Status::Ok);
+        assert_eq!(writes().last(), Some(&(0x7, 0x3F)));
+    }
+
+    #[test]
+    fn the_header_declares_everything() {
+        let header = include_str!("../include/ym2149.h");
+        for declaration in [
+            "YmStatus ym_init(void);",
+            "YmStatus ym_write_register(uint8_t address, uint8_t value);",
+            "YmStatus ym_play_tone(uint8_t channel, uint32_t hz, uint8_t level);",
+            "int32_t ym_trigger_sfx(uint8_t sfx, uint8_t priority);",
+            "YmStatus ym_player_tick(void);",
+            "YmStatus ym_stop_all(void);",
+            "YM_STATUS_OK = 0,",
+            "YM_STATUS_NOT_INSTALLED = -1,",
+            "YM_STATUS_INVALID_ARGUMENT = -2,",
+            "YM_STATUS_CHIP_FAILED = -3,",
+            "YM_STATUS_NO_CHANNEL = -4,",
+            "YM_SFX_COIN,\n  YM_SFX_JUMP,\n  YM_SFX_LASER_SHOT,\n  YM_SFX_EXPLOSION,\n  \
+             YM_SFX_POWER_UP,\n  YM_SFX_HIT,\n",
+        ] {
+            assert!(header.contains(declaration), "{declaration}");
+        }
+    }
+}
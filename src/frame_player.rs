@@ -0,0 +1,2408 @@
+//! Register dump playback: the chip's registers, one frame at a time, as
+//! every tracker-exported music format boils down to.
+
+use crate::analysis::FrameAnalysis;
+use crate::data_source::DataSource;
+use crate::loop_policy::{Fader, LoopAction, LoopPolicy};
+use crate::mute::{Mute, MutedPsg};
+use crate::player_snapshot::{PlayerSnapshot, ResumeError};
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::slew::Slew;
+use crate::tuning::{self, MAX_NOISE_PERIOD, MAX_TONE_PERIOD};
+use crate::{Channel, ChannelLevel};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+pub(crate) use crate::registers::frame_mixer;
+pub use crate::registers::{Frame, R13_UNCHANGED};
+
+/// The frame rate times are worked out at unless set with
+/// [`FramePlayer::with_rate`]: 50 Hz, as on the Atari ST.
+pub const DEFAULT_FRAME_MILLIHERTZ: u32 = 50_000;
+
+/// Frames a pause or resume fades over unless set otherwise.
+pub const DEFAULT_FADE_FRAMES: u8 = 4;
+
+/// Rewrites each frame of a song before it is played, as set with
+/// [`FramePlayer::set_frame_transform`].
+pub type FrameTransform = fn(&mut Frame);
+
+/// A [`FrameTransform`] compared by address, which is as good as comparing
+/// function pointers gets.
+#[derive(Debug, Copy, Clone)]
+struct Transform(FrameTransform);
+
+impl PartialEq for Transform {
+    fn eq(&self, other: &Transform) -> bool {
+        core::ptr::fn_addr_eq(self.0, other.0)
+    }
+}
+
+impl Eq for Transform {}
+
+/// How far, and which periods, [`FramePlayer::set_transpose`] moves.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Transposition {
+    semitones: i8,
+    noise: bool,
+    envelope: bool,
+}
+
+impl Transposition {
+    /// `period` moved by the semitones, rounded to nearest, so a held note
+    /// stays put, and clamped to 1..=`max` if it was at least 1.
+    fn scale(&self, period: u16, max: u16) -> u16 {
+        if period == 0 {
+            return 0;
+        }
+        let scaled = tuning::detune_period(period as u32, self.semitones as i32 * 100);
+        scaled.clamp(1, max as u32) as u16
+    }
+
+    fn apply(&self, frame: &mut Frame) {
+        if self.semitones == 0 {
+            return;
+        }
+        for channel in Channel::ALL {
+            let (fine, rough) = channel.period_registers();
+            let (fine, rough) = (fine as usize, rough as usize);
+            let period = u16::from_le_bytes([frame[fine], frame[rough] & 0xF]);
+            let mut scaled = tuning::detune_period(period as u32, self.semitones as i32 * 100);
+            // Too low to play: up an octave at a time until it fits.
+            while scaled > MAX_TONE_PERIOD as u32 {
+                scaled = scaled.div_ceil(2);
+            }
+            [frame[fine], frame[rough]] = (scaled as u16).to_le_bytes();
+        }
+        if self.noise {
+            frame[0x6] = self.scale(frame[0x6] as u16 & 0x1F, MAX_NOISE_PERIOD as u16) as u8;
+        }
+        if self.envelope {
+            let period = u16::from_le_bytes([frame[0xB], frame[0xC]]);
+            [frame[0xB], frame[0xC]] = self.scale(period, u16::MAX).to_le_bytes();
+        }
+    }
+}
+
+/// A channel's fixed level in `frame`, or `None` if it plays the envelope.
+fn fixed_level(frame: &Frame, channel: Channel) -> Option<u8> {
+    let value = frame[channel.level_register() as usize];
+    (value & 0x10 == 0).then_some(value & 0xF)
+}
+
+/// The frame that takes the chip from wherever it is to `registers`, with R13
+/// written only if `restart`.
+pub(crate) fn snapshot_frame(registers: &Registers, restart: bool) -> Frame {
+    let mut frame = *registers.values();
+    frame[0x7] = registers.mixer();
+    if !restart {
+        frame[0xD] = R13_UNCHANGED;
+    }
+    frame
+}
+
+/// Records `frame` in `registers` as [`Psg::write_frame`] would write it.
+fn apply_frame(registers: &mut Registers, frame: &Frame) {
+    for address in 0..0xD {
+        let value = match address {
+            0x7 => frame_mixer(frame[0x7], registers.mixer()),
+            _ => frame[address as usize],
+        };
+        registers.set(address, value);
+    }
+    if frame[0xD] != R13_UNCHANGED {
+        registers.set(0xD, frame[0xD]);
+    }
+}
+
+/// What a [`FrameSource`] had for a frame it was asked for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameStatus {
+    /// The frame has been filled in.
+    Ready,
+    /// The frame exists but hasn't been fetched yet; ask again next tick.
+    NotReady,
+    /// There is no such frame: the song is over.
+    End,
+}
+
+/// Why a [`FrameSource`] couldn't provide a frame at all.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SourceError {
+    /// The storage behind the source failed.
+    Read,
+    /// The data read isn't a valid frame.
+    Corrupt,
+}
+
+/// Where a [`FramePlayer`] gets its frames from.
+///
+/// [`FrameSource::frame`] is called from [`FramePlayer::tick`], once per
+/// frame and usually from a timer interrupt, so it has to come back well
+/// within a frame period: a few hundred microseconds at most, so the bus
+/// writes that follow still land on time. A source backed by slow storage
+/// should fetch ahead from the main loop and answer
+/// [`FrameStatus::NotReady`] when it falls behind, rather than waiting; the
+/// player then holds the last frame and asks again on the next tick.
+///
+/// Frames are asked for by index, in order, apart from jumps back to a loop
+/// start.
+pub trait FrameSource {
+    /// Fills `frame` with frame `index`.
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError>;
+
+    /// How many frames there are, where that's known up front.
+    fn frame_count(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Frames laid out one after another, 16 bytes each, read through a
+/// [`DataSource`], such as [`ProgMem`](crate::data_source). A partial frame
+/// at the end is ignored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameData<D>(pub D);
+
+impl<D: DataSource> FrameSource for FrameData<D> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        let start = index as usize * frame.len();
+        match self.0.array(start) {
+            Some(bytes) => {
+                *frame = bytes;
+                Ok(FrameStatus::Ready)
+            }
+            None => Ok(FrameStatus::End),
+        }
+    }
+
+    fn frame_count(&self) -> Option<u32> {
+        Some((self.0.len() / 16) as u32)
+    }
+}
+
+/// Frames laid out one after another, as [`FrameData`].
+impl FrameSource for &[u8] {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        FrameData(*self).frame(index, frame)
+    }
+
+    fn frame_count(&self) -> Option<u32> {
+        FrameData(*self).frame_count()
+    }
+}
+
+impl FrameSource for &[Frame] {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        match self.get(index as usize) {
+            Some(found) => {
+                *frame = *found;
+                Ok(FrameStatus::Ready)
+            }
+            None => Ok(FrameStatus::End),
+        }
+    }
+
+    fn frame_count(&self) -> Option<u32> {
+        Some(self.len() as u32)
+    }
+}
+
+/// A frame buffer built at run time, played as the slice it holds.
+#[cfg(feature = "alloc")]
+impl FrameSource for Vec<Frame> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        self.as_slice().frame(index, frame)
+    }
+
+    fn frame_count(&self) -> Option<u32> {
+        self.as_slice().frame_count()
+    }
+}
+
+/// Calls made by a [`FramePlayer`] as it plays, to keep displays or lights
+/// in time with the music.
+///
+/// Every hook is called after the frame it reports has been written to the
+/// chip, so the registers it is handed are what is sounding. Hooks can't
+/// fail or stop playback; anything they need to act on should be noted and
+/// dealt with once [`FramePlayer::tick`] returns. All of them do nothing by
+/// default, and `()` is the player's hooks when none are given, which costs
+/// nothing.
+pub trait PlayerHooks {
+    /// Frame `index` has just been written; `registers` is the chip's state.
+    fn on_frame(&mut self, index: u32, registers: &Registers) {
+        let _ = (index, registers);
+    }
+
+    /// The song has gone back to its loop start for the `count`th time, and
+    /// the loop-start frame has been written. Called before that frame's
+    /// [`PlayerHooks::on_frame`].
+    fn on_loop(&mut self, count: u32) {
+        let _ = count;
+    }
+
+    /// The song has run off its end, or faded out at the end of its last
+    /// loop, and the chip has been silenced. Not called when the source
+    /// fails, which [`FramePlayer::tick`] reports.
+    fn on_end(&mut self) {}
+}
+
+impl PlayerHooks for () {}
+
+/// What [`FramePlayer::tick`] does when more than one frame has come due at
+/// once, because the ticks fell behind the song's rate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CatchUpPolicy {
+    /// Writes every frame in turn. Nothing is lost, but on a slow bus the
+    /// writes take longer still, and the player falls further behind.
+    #[default]
+    PlayAll,
+    /// Writes the frames as one: each register gets its value in the last
+    /// of them, and R13 is written if any of them restarted the envelope.
+    Merge,
+    /// Writes only the last frame, as it stands; envelope restarts in the
+    /// frames before it are lost.
+    Drop,
+}
+
+/// Frames [`FramePlayer::tick`] has had to catch up on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CatchUpStats {
+    /// Ticks that found more than one frame due.
+    pub catch_ups: u32,
+    /// Frames folded into a later one by [`CatchUpPolicy::Merge`].
+    pub merged: u32,
+    /// Frames skipped by [`CatchUpPolicy::Drop`].
+    pub dropped: u32,
+}
+
+/// What [`FramePlayer::subtick`] has done.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct InterpolationStats {
+    /// Calls made.
+    pub subticks: u32,
+    /// Register writes made by them, on top of the frames'.
+    pub writes: u32,
+}
+
+/// Updates between frames, as set with [`FramePlayer::with_interpolation`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Interpolation {
+    /// Parts each frame's time is split into.
+    parts: u8,
+    tones: bool,
+    /// Subticks since `from` was written.
+    step: u8,
+    /// The frame last written, while it can be moved on from.
+    from: Option<Frame>,
+    /// The frame after, read early to move towards, and whether reading it
+    /// went back to the loop start.
+    ahead: Option<(Frame, bool)>,
+    /// Whether `ahead` has been read, or tried, since `from` was written.
+    read_ahead: bool,
+    stats: InterpolationStats,
+}
+
+impl Interpolation {
+    /// Starts moving on from `frame`, just written.
+    fn start(&mut self, frame: &Frame) {
+        self.from = Some(*frame);
+        self.step = 0;
+        self.read_ahead = false;
+    }
+
+    /// Forgets the frames, until the next is written.
+    fn reset(&mut self) {
+        self.from = None;
+        self.ahead = None;
+        self.read_ahead = false;
+    }
+
+    /// `step` parts of the way from `from` to `to`, rounded to nearest.
+    fn between(&self, from: u16, to: u16) -> u16 {
+        let (parts, step) = (self.parts as u32, self.step as u32);
+        ((from as u32 * (parts - step) + to as u32 * step + parts / 2) / parts) as u16
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayerState {
+    Stopped,
+    Playing,
+    /// Fading out for a pause, holding the position.
+    Pausing,
+    Paused,
+    /// Fading back in after a pause, before playing on.
+    Resuming,
+}
+
+/// What a call to [`FramePlayer::tick`] did.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayStatus {
+    /// Wrote the next frame.
+    Played,
+    /// Ran off the end and wrote the loop-start frame.
+    Looped,
+    /// The source hadn't got the next frame ready, so the last one holds.
+    Underrun,
+    /// Ran off the end of a song without a loop, or of its last loop, or
+    /// finished fading out, and silenced the chip.
+    Finished,
+    /// The source failed; the chip has been silenced and the player stopped.
+    Failed(SourceError),
+    /// Playing, but at a fractional rate no frame was due on this call.
+    Waiting,
+    /// Fading for a pause or resume; the position holds.
+    Fading,
+    /// Stopped or paused, so nothing was written.
+    Idle,
+}
+
+/// A time into a song, for display as "1:23".
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlayTime {
+    pub minutes: u32,
+    pub seconds: u8,
+    pub millis: u16,
+}
+
+impl PlayTime {
+    pub const fn from_millis(millis: u64) -> PlayTime {
+        let seconds = millis / 1000;
+        PlayTime {
+            minutes: (seconds / 60) as u32,
+            seconds: (seconds % 60) as u8,
+            millis: (millis % 1000) as u16,
+        }
+    }
+
+    /// How long `frames` frames last at `frame_millihertz`, rounded down to
+    /// the millisecond.
+    pub const fn from_frames(frames: u64, frame_millihertz: u32) -> PlayTime {
+        let rate = if frame_millihertz == 0 {
+            1
+        } else {
+            frame_millihertz
+        };
+        PlayTime::from_millis(frames * 1_000_000 / rate as u64)
+    }
+
+    pub const fn total_millis(&self) -> u64 {
+        (self.minutes as u64 * 60 + self.seconds as u64) * 1000 + self.millis as u64
+    }
+}
+
+/// The song's frame rate against the rate [`FramePlayer::tick`] is called
+/// at, as a phase accumulator.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Rate {
+    /// Added every tick.
+    step: u64,
+    /// What one frame costs: the tick rate in millihertz.
+    cost: u64,
+    phase: u64,
+}
+
+impl Rate {
+    /// Set so the first tick after starting plays a frame.
+    const fn start_phase(&self) -> u64 {
+        self.cost.saturating_sub(self.step)
+    }
+}
+
+/// Plays a register dump, one frame per call to [`FramePlayer::tick`], made
+/// at the song's frame rate.
+///
+/// Frames go out through [`Psg::write_frame`], so registers that don't change
+/// from one frame to the next cost nothing.
+///
+/// By default each tick plays one frame. [`FramePlayer::with_rate`] decouples
+/// the two, for a song at 50.08 Hz played from a 1 kHz timer, say: each tick
+/// then plays however many frames have come due, which is usually none or
+/// one, and more to catch up when the song runs faster than the ticks. The
+/// count is kept exactly in integers, so the song never drifts from its
+/// rate by more than the frame currently in progress, however long it
+/// plays.
+///
+/// [`FramePlayer::pause`] and [`FramePlayer::resume`] fade the levels rather
+/// than cutting them, which would click, counting the fade in frames at the
+/// song's rate.
+///
+/// A song with a loop goes round it for as long as it plays, unless
+/// [`FramePlayer::with_loop_policy`] says to finish, or fade out, after so
+/// many times.
+///
+/// [`FramePlayer::set_mute`] keeps channels quiet as the rest plays on, and
+/// [`FramePlayer::set_frame_transform`] rewrites the song's frames on the
+/// way to the chip.
+///
+/// [`FramePlayer::with_interpolation`] smooths slides and vibrato written a
+/// frame at a time by updating the chip between frames too.
+///
+/// [`FramePlayer::with_hooks`] adds [`PlayerHooks`] called as frames play.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FramePlayer<S, H = ()> {
+    source: S,
+    loop_start: Option<u32>,
+    loop_policy: LoopPolicy,
+    /// The fade out at the end of the last loop, once begun.
+    fader: Option<Fader>,
+    mute: Mute,
+    transform: Option<Transform>,
+    transposition: Transposition,
+    interpolation: Option<Interpolation>,
+    rate: Option<Rate>,
+    frame_millihertz: u32,
+    position: u32,
+    /// Frames played in loops gone round, on top of the position.
+    looped_frames: u64,
+    loops: u32,
+    /// Frames played since R13 last restarted the envelope.
+    envelope_frames: u32,
+    state: PlayerState,
+    fade_frames: u8,
+    /// Where the fade has got to, per channel.
+    levels: [Slew; 3],
+    /// The registers as paused, which a resume puts back.
+    snapshot: Frame,
+    catch_up: CatchUpPolicy,
+    stats: CatchUpStats,
+    hooks: H,
+}
+
+impl<S: FrameSource> FramePlayer<S> {
+    /// A stopped player at the first frame, playing through once.
+    pub const fn new(source: S) -> FramePlayer<S> {
+        FramePlayer {
+            source,
+            loop_start: None,
+            loop_policy: LoopPolicy::Infinite,
+            fader: None,
+            mute: Mute::NONE,
+            transform: None,
+            transposition: Transposition {
+                semitones: 0,
+                noise: false,
+                envelope: false,
+            },
+            interpolation: None,
+            rate: None,
+            frame_millihertz: DEFAULT_FRAME_MILLIHERTZ,
+            position: 0,
+            looped_frames: 0,
+            loops: 0,
+            envelope_frames: 0,
+            state: PlayerState::Stopped,
+            fade_frames: DEFAULT_FADE_FRAMES,
+            levels: [Slew::new(0); 3],
+            snapshot: [0; 16],
+            catch_up: CatchUpPolicy::PlayAll,
+            stats: CatchUpStats {
+                catch_ups: 0,
+                merged: 0,
+                dropped: 0,
+            },
+            hooks: (),
+        }
+    }
+}
+
+impl<S: FrameSource, H: PlayerHooks> FramePlayer<S, H> {
+    /// Calls `hooks` as the song plays, in place of any given before.
+    pub fn with_hooks<G: PlayerHooks>(self, hooks: G) -> FramePlayer<S, G> {
+        FramePlayer {
+            source: self.source,
+            loop_start: self.loop_start,
+            loop_policy: self.loop_policy,
+            fader: self.fader,
+            mute: self.mute,
+            transform: self.transform,
+            transposition: self.transposition,
+            interpolation: self.interpolation,
+            rate: self.rate,
+            frame_millihertz: self.frame_millihertz,
+            position: self.position,
+            looped_frames: self.looped_frames,
+            loops: self.loops,
+            envelope_frames: self.envelope_frames,
+            state: self.state,
+            fade_frames: self.fade_frames,
+            levels: self.levels,
+            snapshot: self.snapshot,
+            catch_up: self.catch_up,
+            stats: self.stats,
+            hooks,
+        }
+    }
+
+    /// Goes back to frame `start` after the last one, rather than finishing.
+    /// A start past the end is ignored.
+    pub const fn with_loop(mut self, start: u32) -> FramePlayer<S, H> {
+        self.loop_start = Some(start);
+        self
+    }
+
+    /// Goes round the loop as `policy` says rather than for ever.
+    pub const fn with_loop_policy(mut self, policy: LoopPolicy) -> FramePlayer<S, H> {
+        self.loop_policy = policy;
+        self
+    }
+
+    /// Starts with channels muted, as [`FramePlayer::set_mute`] does.
+    pub const fn with_mute(mut self, mute: Mute) -> FramePlayer<S, H> {
+        self.mute = mute;
+        self
+    }
+
+    /// Starts with frames rewritten, as [`FramePlayer::set_frame_transform`]
+    /// does.
+    pub const fn with_frame_transform(mut self, transform: FrameTransform) -> FramePlayer<S, H> {
+        self.transform = Some(Transform(transform));
+        self
+    }
+
+    /// Has [`FramePlayer::set_transpose`] move the noise period along with
+    /// the tones.
+    pub const fn with_transposed_noise(mut self) -> FramePlayer<S, H> {
+        self.transposition.noise = true;
+        self
+    }
+
+    /// Has [`FramePlayer::set_transpose`] move the envelope period along with
+    /// the tones, for songs playing the envelope as a bass. How that sounds
+    /// depends on the song: the envelope's pitch is a period 16 times the
+    /// tone's, and moves in coarser steps.
+    pub const fn with_transposed_envelope(mut self) -> FramePlayer<S, H> {
+        self.transposition.envelope = true;
+        self
+    }
+
+    /// Has [`FramePlayer::subtick`], called `subticks` times between each
+    /// tick and the next, move the fixed levels towards the next frame's in
+    /// even steps: with 3, a 50 Hz song is updated at 200 Hz. The next frame
+    /// is read from the source at the first subtick after a frame, rather
+    /// than at the tick that plays it. Registers that can't sensibly be
+    /// part way between two values, the mixer, R13 and the noise and
+    /// envelope periods, change with the frames only, as do levels going
+    /// onto or off the envelope. 0 turns interpolation off.
+    pub const fn with_interpolation(mut self, subticks: u8) -> FramePlayer<S, H> {
+        self.interpolation = match subticks {
+            0 => None,
+            _ => Some(Interpolation {
+                parts: subticks.saturating_add(1),
+                tones: false,
+                step: 0,
+                from: None,
+                ahead: None,
+                read_ahead: false,
+                stats: InterpolationStats {
+                    subticks: 0,
+                    writes: 0,
+                },
+            }),
+        };
+        self
+    }
+
+    /// Has [`FramePlayer::subtick`] move the tone periods between frames as
+    /// well, for vibrato and slides. Notes that change from one frame to the
+    /// next glide across the gap rather than jumping, so this suits some
+    /// songs and not others. Does nothing without
+    /// [`FramePlayer::with_interpolation`].
+    pub const fn with_interpolated_tones(mut self) -> FramePlayer<S, H> {
+        if let Some(interpolation) = &mut self.interpolation {
+            interpolation.tones = true;
+        }
+        self
+    }
+
+    /// Plays the song at `frame_millihertz` while [`FramePlayer::tick`] is
+    /// called `tick_hertz` times a second, rather than a frame a tick.
+    pub const fn with_rate(mut self, frame_millihertz: u32, tick_hertz: u32) -> FramePlayer<S, H> {
+        let mut rate = Rate {
+            step: frame_millihertz as u64,
+            cost: if tick_hertz == 0 { 1 } else { tick_hertz } as u64 * 1000,
+            phase: 0,
+        };
+        rate.phase = rate.start_phase();
+        self.rate = Some(rate);
+        self.frame_millihertz = frame_millihertz;
+        self
+    }
+
+    /// Fades over `frames` frames when pausing and resuming; 0 cuts
+    /// straight away.
+    pub const fn with_fade(mut self, frames: u8) -> FramePlayer<S, H> {
+        self.fade_frames = frames;
+        self
+    }
+
+    /// Handles frames that come due together as `policy` says, rather than
+    /// playing them all.
+    pub const fn with_catch_up(mut self, policy: CatchUpPolicy) -> FramePlayer<S, H> {
+        self.catch_up = policy;
+        self
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Counted since the player was made; stopping doesn't clear them.
+    pub fn catch_up_stats(&self) -> CatchUpStats {
+        self.stats
+    }
+
+    /// Counted since the player was made, and all 0 without
+    /// [`FramePlayer::with_interpolation`].
+    pub fn interpolation_stats(&self) -> InterpolationStats {
+        self.interpolation
+            .map_or(InterpolationStats::default(), |interpolation| {
+                interpolation.stats
+            })
+    }
+
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    pub fn hooks_mut(&mut self) -> &mut H {
+        &mut self.hooks
+    }
+
+    pub fn state(&self) -> PlayerState {
+        self.state
+    }
+
+    pub fn mute(&self) -> Mute {
+        self.mute
+    }
+
+    /// Mutes the channels `mute` says, and unmutes the rest, from the next
+    /// frame written on. The song plays on underneath, so unmuting picks it
+    /// up where it has got to.
+    pub fn set_mute(&mut self, mute: Mute) {
+        self.mute = mute;
+    }
+
+    /// Has `transform` rewrite every frame read from the song from now on,
+    /// after it is read and before anything else is done with it, or with
+    /// `None`, plays the frames as they are. Forcing the noise period,
+    /// capping the levels or swapping channels round to suit a board's
+    /// wiring all fit here, and the chip's registers end up holding what
+    /// was written, so everything after, [`PlayerHooks`] included, sees the
+    /// rewritten frames.
+    ///
+    /// Some things to bear in mind:
+    ///
+    /// - Every frame goes through, including those merged or dropped while
+    ///   catching up and those crossed by a seek, so the rewrite should
+    ///   depend on the frame alone, not count frames.
+    /// - R13 is written only when it isn't [`R13_UNCHANGED`], and writing it
+    ///   restarts the envelope. Setting it in every frame restarts the
+    ///   envelope every frame, and setting it to [`R13_UNCHANGED`] loses the
+    ///   song's restarts.
+    /// - The mixer's port direction bits are kept from the chip whatever
+    ///   the frame says.
+    /// - Fades, the loop policy's fade out and [`FramePlayer::set_mute`] work
+    ///   on the rewritten frame, so they go by the channels it ends up on.
+    pub fn set_frame_transform(&mut self, transform: Option<FrameTransform>) {
+        self.transform = transform.map(Transform);
+    }
+
+    pub fn transpose(&self) -> i8 {
+        self.transposition.semitones
+    }
+
+    /// Moves the song `semitones` up, or down if negative, from the next
+    /// frame written on. Every tone period in a frame is scaled by the
+    /// interval and rounded to nearest, so it lands within a period step of
+    /// the note the interval asks for; a note too low to play is moved up by
+    /// octaves. The noise and envelope periods stay as the song has them
+    /// unless [`FramePlayer::with_transposed_noise`] or
+    /// [`FramePlayer::with_transposed_envelope`] say otherwise. This comes
+    /// after any [`FramePlayer::set_frame_transform`].
+    pub fn set_transpose(&mut self, semitones: i8) {
+        self.transposition.semitones = semitones;
+    }
+
+    /// The index of the next frame to play.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// How far into the song playback has got, from the position and the
+    /// frame rate. Time keeps counting across loops, and a seek moves it
+    /// along with the position.
+    pub fn elapsed(&self) -> PlayTime {
+        let frames = self.looped_frames + self.position as u64;
+        PlayTime::from_frames(frames, self.frame_millihertz)
+    }
+
+    /// How long the song lasts once through, or `None` if the source can't
+    /// tell, as with a stream.
+    pub fn duration(&self) -> Option<PlayTime> {
+        let frames = self.source.frame_count()?;
+        Some(PlayTime::from_frames(frames as u64, self.frame_millihertz))
+    }
+
+    /// What each channel of `psg` is doing, as played last: its level and
+    /// pitch, for meters and lights. The envelope's level is estimated from
+    /// the frames played since the song last restarted it, at the song's
+    /// rate.
+    pub fn last_analysis<P: Psg>(&self, psg: &P) -> FrameAnalysis {
+        let micros =
+            self.envelope_frames as u64 * 1_000_000_000 / self.frame_millihertz.max(1) as u64;
+        FrameAnalysis::new(psg.registers(), psg.master_clock(), micros)
+    }
+
+    /// Times the song has gone back to its loop start since it was started.
+    pub fn loops_completed(&self) -> u32 {
+        self.loops
+    }
+
+    /// Starts, or carries on straight away from a pause or a fade, the next
+    /// frame putting the levels back.
+    pub fn play(&mut self) {
+        self.state = PlayerState::Playing;
+    }
+
+    /// Fades out and holds the position: the next few ticks run the fade
+    /// rather than playing frames, and the player is then paused.
+    ///
+    /// Pausing again, or while already fading out, does nothing, and pausing
+    /// while fading back in turns the fade round from where it has got to.
+    /// Channels on the envelope can't be faded, so they carry on until the
+    /// others are quiet and are cut then.
+    pub fn pause<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        match self.state {
+            PlayerState::Playing => {
+                let registers = psg.registers();
+                self.snapshot = *registers.values();
+                self.snapshot[0x7] = registers.mixer();
+                self.snapshot[0xD] = R13_UNCHANGED;
+                for channel in Channel::ALL {
+                    let level = fixed_level(&self.snapshot, channel).unwrap_or(0);
+                    self.levels[channel.index()] = Slew::new(level);
+                }
+            }
+            PlayerState::Resuming => {}
+            _ => return Ok(()),
+        }
+        if let Some(interpolation) = &mut self.interpolation {
+            interpolation.from = None;
+        }
+        self.state = PlayerState::Pausing;
+        self.start_fade(psg, |_| 0)
+    }
+
+    /// Carries on from a pause, fading back in. The registers are put back
+    /// as they were when paused, or as a seek while paused left them, without
+    /// restarting the envelope; the next few ticks fade the levels up, and
+    /// the song then plays on from the frame it paused at.
+    ///
+    /// Resuming while fading out turns the fade round. When stopped this is
+    /// [`FramePlayer::play`], and when playing or already fading in it does
+    /// nothing.
+    pub fn resume<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        match self.state {
+            PlayerState::Paused => {
+                let mut frame = self.snapshot;
+                for channel in Channel::ALL {
+                    if fixed_level(&frame, channel).is_some() {
+                        frame[channel.level_register() as usize] = 0;
+                    }
+                }
+                psg.write_frame(&frame)?;
+            }
+            PlayerState::Pausing => {}
+            PlayerState::Stopped => {
+                self.play();
+                return Ok(());
+            }
+            PlayerState::Playing | PlayerState::Resuming => return Ok(()),
+        }
+        self.state = PlayerState::Resuming;
+        let snapshot = self.snapshot;
+        self.start_fade(psg, |channel| fixed_level(&snapshot, channel).unwrap_or(0))
+    }
+
+    /// Points each channel's fade at its target, timed to finish together.
+    fn start_fade<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        target: impl Fn(Channel) -> u8,
+    ) -> Result<(), P::Error> {
+        for channel in Channel::ALL {
+            let slew = &mut self.levels[channel.index()];
+            slew.set_target(target(channel));
+            slew.reach_in(self.fade_frames);
+        }
+        if self.fade_frames == 0 {
+            self.fade(psg, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the fade on `steps` frames and writes the levels, finishing the
+    /// pause or resume once every channel is there.
+    fn fade<P: Psg>(&mut self, psg: &mut P, steps: u64) -> Result<PlayStatus, P::Error> {
+        for slew in &mut self.levels {
+            for _ in 0..steps.min(15) {
+                slew.tick();
+            }
+        }
+        let settled = self.levels.iter().all(Slew::is_settled);
+        for channel in Channel::ALL {
+            let level = match fixed_level(&self.snapshot, channel) {
+                Some(_) => ChannelLevel::Fixed(self.levels[channel.index()].level()),
+                // Switched while everything else is quiet.
+                None if settled && self.state == PlayerState::Pausing => ChannelLevel::Fixed(0),
+                None => ChannelLevel::Envelope,
+            };
+            psg.update_channel_level(channel, level)?;
+        }
+        if settled {
+            self.state = match self.state {
+                PlayerState::Pausing => PlayerState::Paused,
+                _ => PlayerState::Playing,
+            };
+        }
+        Ok(PlayStatus::Fading)
+    }
+
+    /// Silences the chip and goes back to the first frame.
+    pub fn stop<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        self.state = PlayerState::Stopped;
+        self.position = 0;
+        self.looped_frames = 0;
+        self.loops = 0;
+        self.fader = None;
+        if let Some(interpolation) = &mut self.interpolation {
+            interpolation.reset();
+        }
+        if let Some(rate) = &mut self.rate {
+            rate.phase = rate.start_phase();
+        }
+        psg.silence()
+    }
+
+    /// Moves to frame `target`, leaving the chip as if every frame before it
+    /// had been played, ready to play `target` on the next tick.
+    ///
+    /// The frames in between are read and applied to a register snapshot
+    /// without touching the bus, then the snapshot is written once, skipping
+    /// registers that already hold the right value. Seeking forward starts
+    /// from the current position and the chip's current state; seeking back
+    /// starts over from the first frame. Either way it costs one source read
+    /// per frame crossed, which is well under a millisecond per thousand
+    /// frames from flash but may be slow from storage, so seek long songs
+    /// from the main loop rather than the tick interrupt.
+    ///
+    /// R13 is only written if one of the frames crossed restarts the
+    /// envelope. The envelope then starts over from the seek rather than
+    /// from part way through; there is no way to set its phase. Unless
+    /// playing, the levels are left at 0, and a seek while paused or fading
+    /// leaves the player paused at the new position, for a resume to fade in.
+    ///
+    /// Returns [`PlayStatus::Played`] once there, or [`PlayStatus::Finished`]
+    /// if the song ended first, in which case the player is left at the end.
+    /// If the source underruns or fails, nothing is written and the position
+    /// doesn't change.
+    pub fn seek<P: Psg>(&mut self, psg: &mut P, target: u32) -> Result<PlayStatus, P::Error> {
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        let (registers, index) = if target >= self.position {
+            (*psg.registers(), self.position)
+        } else {
+            (Registers::new(), 0)
+        };
+        self.seek_from(psg, registers, index, target)
+    }
+
+    /// Seeks to `target` from frame `index`, with the chip as `registers`
+    /// says it would be there.
+    fn seek_from<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        mut registers: Registers,
+        mut index: u32,
+        target: u32,
+    ) -> Result<PlayStatus, P::Error> {
+        if let Some(interpolation) = &mut self.interpolation {
+            interpolation.reset();
+        }
+        let start = index;
+        let mut restart = false;
+        let mut status = PlayStatus::Played;
+        let mut frame = [0; 16];
+        while index < target {
+            match self.source.frame(index, &mut frame) {
+                Ok(FrameStatus::Ready) => {
+                    self.rewrite(&mut frame);
+                    apply_frame(&mut registers, &frame);
+                    restart |= frame[0xD] != R13_UNCHANGED;
+                    index += 1;
+                }
+                Ok(FrameStatus::NotReady) => return Ok(PlayStatus::Underrun),
+                Ok(FrameStatus::End) => {
+                    status = PlayStatus::Finished;
+                    break;
+                }
+                Err(error) => return Ok(PlayStatus::Failed(error)),
+            }
+        }
+        let mut snapshot = snapshot_frame(&registers, restart);
+        if self.state != PlayerState::Playing {
+            if self.state != PlayerState::Stopped {
+                self.snapshot = snapshot;
+                self.snapshot[0xD] = R13_UNCHANGED;
+                self.levels = [Slew::new(0); 3];
+                self.state = PlayerState::Paused;
+            }
+            for channel in Channel::ALL {
+                snapshot[channel.level_register() as usize] = 0;
+            }
+        }
+        psg.write_frame(&snapshot)?;
+        self.count_envelope(&snapshot, index - start);
+        self.position = index;
+        Ok(status)
+    }
+
+    /// Where the song has got to, for [`FramePlayer::resume_from`] to carry
+    /// on from once everything else is lost, as in a deep sleep.
+    /// `source_id` is anything that tells songs apart, to be checked when
+    /// resuming: a track number, or a hash of the file name.
+    pub fn snapshot(&self, source_id: u32) -> PlayerSnapshot {
+        PlayerSnapshot {
+            source_id,
+            frame_count: self.source.frame_count(),
+            position: self.position,
+            loops: self.loops,
+            looped_frames: self.looped_frames,
+            phase: self.rate.map_or(0, |rate| rate.phase),
+            mute: self.mute,
+        }
+    }
+
+    /// Carries on playing from `snapshot`, taken by [`FramePlayer::snapshot`]
+    /// with `source_id`, perhaps by another player over the same song before
+    /// the power went.
+    ///
+    /// The chip is brought to the snapshot's position as a seek from the
+    /// start leaves it, whatever it holds now, and the loop count, the time
+    /// played, the mute and the rate's part-way frame are put back, so the
+    /// next tick plays on as the snapshotted player would have. A fade out
+    /// at the end of the last loop that was under way starts over at the
+    /// next time round. The player's own settings, its loop, rate and
+    /// policies, are its own: give it the same as when the snapshot was
+    /// taken.
+    ///
+    /// A snapshot with another `source_id`, or of a song whose length has
+    /// changed since, such as a file rewritten on a card, is refused with
+    /// nothing changed. If the source underruns or fails, as with
+    /// [`FramePlayer::seek`], nothing is written and the player stays as it
+    /// was.
+    pub fn resume_from<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        snapshot: &PlayerSnapshot,
+        source_id: u32,
+    ) -> Result<PlayStatus, ResumeError<P::Error>> {
+        if snapshot.source_id != source_id {
+            return Err(ResumeError::OtherSong);
+        }
+        let frame_count = self.source.frame_count();
+        if snapshot.frame_count != frame_count {
+            return Err(ResumeError::LengthChanged {
+                was: snapshot.frame_count,
+                now: frame_count,
+            });
+        }
+        let (state, mute) = (self.state, self.mute);
+        self.state = PlayerState::Playing;
+        self.mute = snapshot.mute;
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        let status = self
+            .seek_from(psg, Registers::new(), 0, snapshot.position)
+            .map_err(ResumeError::Psg)?;
+        if let PlayStatus::Underrun | PlayStatus::Failed(_) = status {
+            (self.state, self.mute) = (state, mute);
+            return Ok(status);
+        }
+        self.loops = snapshot.loops;
+        self.looped_frames = snapshot.looped_frames;
+        self.fader = None;
+        if let Some(rate) = &mut self.rate {
+            rate.phase = snapshot.phase % rate.cost;
+        }
+        Ok(status)
+    }
+
+    /// Fast-forwards `frames` frames, as [`FramePlayer::seek`] does.
+    pub fn skip<P: Psg>(&mut self, psg: &mut P, frames: u32) -> Result<PlayStatus, P::Error> {
+        self.seek(psg, self.position.saturating_add(frames))
+    }
+
+    /// Writes the frames due, if playing: the next one, or as many as the
+    /// rate calls for. Frames are reported as [`PlayStatus::Looped`] if any
+    /// of them was the loop start. A frame the source isn't ready with is
+    /// put back to play later. More than one frame due is handled as the
+    /// [`CatchUpPolicy`] says. While pausing or resuming, the frames due run
+    /// the fade instead.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<PlayStatus, P::Error> {
+        self.tick_by(psg, 1)
+    }
+
+    /// As [`FramePlayer::tick`], for `ticks` ticks at once: for a caller that
+    /// timestamps its calls and finds it has missed some, on a bus too slow
+    /// to keep up, say. Without a rate each tick is a frame, and frames the
+    /// source isn't ready with are lost rather than put back.
+    pub fn tick_by<P: Psg>(&mut self, psg: &mut P, ticks: u32) -> Result<PlayStatus, P::Error> {
+        if matches!(self.state, PlayerState::Stopped | PlayerState::Paused) {
+            return Ok(PlayStatus::Idle);
+        }
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        let due = match &mut self.rate {
+            None => ticks as u64,
+            Some(rate) => {
+                rate.phase += rate.step * ticks as u64;
+                let due = rate.phase / rate.cost;
+                rate.phase -= due * rate.cost;
+                due
+            }
+        };
+        if due == 0 {
+            return Ok(PlayStatus::Waiting);
+        }
+        if self.state != PlayerState::Playing {
+            return self.fade(psg, due);
+        }
+        if due > 1 {
+            self.stats.catch_ups = self.stats.catch_ups.wrapping_add(1);
+            if self.catch_up != CatchUpPolicy::PlayAll {
+                return self.catch_up(psg, due);
+            }
+        }
+        let mut status = PlayStatus::Played;
+        for played in 0..due {
+            match self.next_frame(psg)? {
+                PlayStatus::Played => {}
+                PlayStatus::Looped => status = PlayStatus::Looped,
+                PlayStatus::Underrun => {
+                    self.put_back(due - played);
+                    return Ok(PlayStatus::Underrun);
+                }
+                other => return Ok(other),
+            }
+        }
+        Ok(status)
+    }
+
+    /// Moves the levels, and the tone periods if set, a step towards the
+    /// next frame's, as set with [`FramePlayer::with_interpolation`],
+    /// writing those that change. Call it between calls to
+    /// [`FramePlayer::tick`], as many times as set; calls beyond that hold
+    /// at the last step. Does nothing unless playing, or while the next
+    /// frame can't be read: at the end of a song, or with a source that
+    /// isn't ready.
+    pub fn subtick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        if self.state != PlayerState::Playing {
+            return Ok(());
+        }
+        let Some(lerp) = &mut self.interpolation else {
+            return Ok(());
+        };
+        lerp.stats.subticks = lerp.stats.subticks.wrapping_add(1);
+        if lerp.from.is_none() || lerp.step + 1 >= lerp.parts {
+            return Ok(());
+        }
+        lerp.step += 1;
+        if !core::mem::replace(&mut lerp.read_ahead, true) {
+            let mut next = [0; 16];
+            if let (Ok(FrameStatus::Ready), looped) = self.fetch(&mut next) {
+                if let Some(lerp) = &mut self.interpolation {
+                    lerp.ahead = Some((next, looped));
+                }
+            }
+        }
+        let Some(lerp) = self.interpolation else {
+            return Ok(());
+        };
+        let (Some(from), Some((mut to, _))) = (lerp.from, lerp.ahead) else {
+            return Ok(());
+        };
+        self.fade_scale(&mut to);
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        let mut writes = 0u32;
+        for channel in Channel::ALL {
+            if let (Some(level), Some(next)) =
+                (fixed_level(&from, channel), fixed_level(&to, channel))
+            {
+                let level = lerp.between(level as u16, next as u16) as u8;
+                writes += psg.update_register(channel.level_register(), level)? as u32;
+            }
+            if lerp.tones {
+                let (fine, rough) = channel.period_registers();
+                let period = |frame: &Frame| {
+                    u16::from_le_bytes([frame[fine as usize], frame[rough as usize] & 0xF])
+                };
+                let [low, high] = lerp.between(period(&from), period(&to)).to_le_bytes();
+                writes += psg.update_register(fine, low)? as u32;
+                writes += psg.update_register(rough, high)? as u32;
+            }
+        }
+        if let Some(lerp) = &mut self.interpolation {
+            lerp.stats.writes = lerp.stats.writes.wrapping_add(writes);
+        }
+        Ok(())
+    }
+
+    /// Leaves `frames` frames due for later ticks.
+    fn put_back(&mut self, frames: u64) {
+        if let Some(rate) = &mut self.rate {
+            rate.phase += frames * rate.cost;
+        }
+    }
+
+    /// Reads the `due` frames and writes them as one, merged or with all
+    /// but the last dropped.
+    fn catch_up<P: Psg>(&mut self, psg: &mut P, due: u64) -> Result<PlayStatus, P::Error> {
+        let merge = self.catch_up == CatchUpPolicy::Merge;
+        let mut registers = *psg.registers();
+        let (mut read, mut looped, mut restart) = (0, false, false);
+        let mut frame = [0; 16];
+        while read < due {
+            let (fetched, wrapped) = self.fetch(&mut frame);
+            match fetched {
+                Ok(FrameStatus::Ready) if self.fade_step() => return self.finish(psg),
+                Ok(FrameStatus::Ready) => {}
+                Ok(FrameStatus::NotReady) => {
+                    self.put_back(due - read);
+                    break;
+                }
+                Ok(FrameStatus::End) => return self.finish(psg),
+                Err(error) => {
+                    self.stop(psg)?;
+                    return Ok(PlayStatus::Failed(error));
+                }
+            }
+            looped |= wrapped;
+            if merge {
+                apply_frame(&mut registers, &frame);
+                restart |= frame[0xD] != R13_UNCHANGED;
+            }
+            self.position += 1;
+            read += 1;
+        }
+        if read == 0 {
+            return Ok(PlayStatus::Underrun);
+        }
+        let skipped = (read - 1) as u32;
+        if merge {
+            frame = snapshot_frame(&registers, restart);
+            self.stats.merged = self.stats.merged.wrapping_add(skipped);
+        } else {
+            self.stats.dropped = self.stats.dropped.wrapping_add(skipped);
+        }
+        self.fade_scale(&mut frame);
+        psg.write_frame(&frame)?;
+        self.count_envelope(&frame, read as u32);
+        if let Some(interpolation) = &mut self.interpolation {
+            interpolation.start(&frame);
+        }
+        if looped {
+            self.hooks.on_loop(self.loops);
+        }
+        self.hooks.on_frame(self.position - 1, psg.registers());
+        Ok(match (read < due, looped) {
+            (true, _) => PlayStatus::Underrun,
+            (false, true) => PlayStatus::Looped,
+            (false, false) => PlayStatus::Played,
+        })
+    }
+
+    /// Reads the frame at the position into `frame`, going back to the loop
+    /// start at the end, and says whether it did.
+    fn fetch(&mut self, frame: &mut Frame) -> (Result<FrameStatus, SourceError>, bool) {
+        let ahead = self
+            .interpolation
+            .as_mut()
+            .and_then(|lerp| lerp.ahead.take());
+        if let Some((ahead, looped)) = ahead {
+            *frame = ahead;
+            return (Ok(FrameStatus::Ready), looped);
+        }
+        let (fetched, looped) = self.fetch_raw(frame);
+        if fetched == Ok(FrameStatus::Ready) {
+            self.rewrite(frame);
+        }
+        (fetched, looped)
+    }
+
+    fn fetch_raw(&mut self, frame: &mut Frame) -> (Result<FrameStatus, SourceError>, bool) {
+        let fetched = self.source.frame(self.position, frame);
+        if fetched == Ok(FrameStatus::End) {
+            // Only a loop start before the end is any use.
+            if let Some(start) = self.loop_start.filter(|&start| start < self.position) {
+                match self.loop_policy.at_end(self.loops, self.fader.is_some()) {
+                    LoopAction::Loop => {}
+                    LoopAction::Fade(fader) => self.fader = Some(fader),
+                    LoopAction::Finish => return (fetched, false),
+                }
+                self.looped_frames += (self.position - start) as u64;
+                self.loops = self.loops.wrapping_add(1);
+                self.position = start;
+                return (self.source.frame(start, frame), true);
+            }
+        }
+        (fetched, false)
+    }
+
+    fn next_frame<P: Psg>(&mut self, psg: &mut P) -> Result<PlayStatus, P::Error> {
+        let mut frame = [0; 16];
+        let (fetched, looped) = self.fetch(&mut frame);
+        let status = if looped {
+            PlayStatus::Looped
+        } else {
+            PlayStatus::Played
+        };
+        match fetched {
+            Ok(FrameStatus::Ready) if self.fade_step() => self.finish(psg),
+            Ok(FrameStatus::Ready) => {
+                self.fade_scale(&mut frame);
+                psg.write_frame(&frame)?;
+                self.count_envelope(&frame, 1);
+                if let Some(interpolation) = &mut self.interpolation {
+                    interpolation.start(&frame);
+                }
+                if looped {
+                    self.hooks.on_loop(self.loops);
+                }
+                self.hooks.on_frame(self.position, psg.registers());
+                self.position += 1;
+                Ok(status)
+            }
+            Ok(FrameStatus::NotReady) => Ok(PlayStatus::Underrun),
+            Ok(FrameStatus::End) => self.finish(psg),
+            Err(error) => {
+                self.stop(psg)?;
+                Ok(PlayStatus::Failed(error))
+            }
+        }
+    }
+
+    /// Rewrites a frame read from the song as set: transformed, then
+    /// transposed.
+    fn rewrite(&self, frame: &mut Frame) {
+        if let Some(Transform(transform)) = self.transform {
+            transform(frame);
+        }
+        self.transposition.apply(frame);
+    }
+
+    /// Counts `frames` frames played towards [`FramePlayer::last_analysis`]'s
+    /// envelope, ending with `frame`, which may have restarted it.
+    fn count_envelope(&mut self, frame: &Frame, frames: u32) {
+        self.envelope_frames = match frame[0xD] {
+            R13_UNCHANGED => self.envelope_frames.saturating_add(frames),
+            _ => 0,
+        };
+    }
+
+    /// Moves any fade out on a frame, saying whether it is over.
+    fn fade_step(&mut self) -> bool {
+        self.fader.as_mut().is_some_and(Fader::step)
+    }
+
+    /// Turns the fixed levels in `frame` down as far as any fade out has
+    /// got. Channels on the envelope play on until the end silences them.
+    fn fade_scale(&self, frame: &mut Frame) {
+        let Some(fader) = &self.fader else {
+            return;
+        };
+        for channel in Channel::ALL {
+            if let Some(level) = fixed_level(frame, channel) {
+                frame[channel.level_register() as usize] = fader.scale(level);
+            }
+        }
+    }
+
+    /// Ends the song: silences the chip, stops and tells the hooks.
+    fn finish<P: Psg>(&mut self, psg: &mut P) -> Result<PlayStatus, P::Error> {
+        self.stop(psg)?;
+        self.hooks.on_end();
+        Ok(PlayStatus::Finished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(feature = "sfx")]
+    use crate::arbiter::{SfxArbiter, SfxPolicy};
+    use crate::mute::ChannelMask;
+    use crate::player_snapshot::ResumeError;
+    #[cfg(feature = "sfx")]
+    use crate::sfx::Sfx;
+    use crate::test_support::{FakePsg, Inverted};
+    use crate::Channel;
+    use std::vec::Vec;
+
+    /// A rising note on A, restarting the envelope on the first frame only.
+    const fn frame(period: u8, level: u8, r13: u8) -> Frame {
+        [
+            period, 0, 0, 0, 0, 0, 0, 0x3E, level, 0, 0, 0x40, 0, r13, 0, 0,
+        ]
+    }
+
+    static SONG: [Frame; 3] = [
+        frame(100, 15, 0x0E),
+        frame(90, 15, R13_UNCHANGED),
+        frame(80, 12, R13_UNCHANGED),
+    ];
+
+    fn periods<S: FrameSource>(
+        player: &mut FramePlayer<S>,
+        psg: &mut FakePsg,
+        ticks: usize,
+    ) -> Vec<(u8, PlayStatus)> {
+        (0..ticks)
+            .map(|_| {
+                let status = player.tick(psg).unwrap();
+                (psg.registers().value(0x0), status)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_through_then_silences() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]);
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Idle));
+        player.play();
+        assert_eq!(
+            periods(&mut player, &mut psg, 5),
+            [
+                (100, PlayStatus::Played),
+                (90, PlayStatus::Played),
+                (80, PlayStatus::Played),
+                (80, PlayStatus::Finished),
+                (80, PlayStatus::Idle),
+            ]
+        );
+        assert_eq!(player.state(), PlayerState::Stopped);
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(psg.registers().mixer(), 0x3F);
+    }
+
+    #[test]
+    fn loops_back_to_the_loop_start() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]).with_loop(1);
+        player.play();
+        assert_eq!(
+            periods(&mut player, &mut psg, 6),
+            [
+                (100, PlayStatus::Played),
+                (90, PlayStatus::Played),
+                (80, PlayStatus::Played),
+                (90, PlayStatus::Looped),
+                (80, PlayStatus::Played),
+                (90, PlayStatus::Looped),
+            ]
+        );
+    }
+
+    #[test]
+    fn r13_is_only_written_when_asked() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]).with_loop(0);
+        player.play();
+        for _ in 0..6 {
+            player.tick(&mut psg).unwrap();
+        }
+        let restarts = psg.writes.iter().filter(|&&(address, _)| address == 0xD);
+        // The first frame, each time round.
+        assert_eq!(restarts.count(), 2);
+        // Unchanged registers aren't written again, and the ports never are.
+        let level_writes = psg.writes.iter().filter(|&&(address, _)| address == 0x8);
+        assert_eq!(level_writes.count(), 4);
+        assert!(psg.writes.iter().all(|&(address, _)| address < 0xE));
+    }
+
+    #[test]
+    fn stop_and_pause_mid_song() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]).with_fade(0);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        player.pause(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Idle));
+        player.play();
+        player.tick(&mut psg).unwrap();
+        assert_eq!(
+            (psg.registers().value(0x0), psg.registers().value(0x8)),
+            (90, 15)
+        );
+
+        player.stop(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(psg.registers().mixer() & 0x3F, 0x3F);
+        player.play();
+        assert_eq!(
+            periods(&mut player, &mut psg, 1),
+            [(100, PlayStatus::Played)]
+        );
+    }
+
+    fn levels<S: FrameSource>(
+        player: &mut FramePlayer<S>,
+        psg: &mut FakePsg,
+        ticks: usize,
+    ) -> Vec<(u8, PlayStatus)> {
+        (0..ticks)
+            .map(|_| {
+                let status = player.tick(psg).unwrap();
+                (psg.registers().value(0x8), status)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pause_and_resume_fade_without_losing_the_place() {
+        use PlayStatus::{Fading, Idle, Played};
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        player.tick(&mut psg).unwrap();
+
+        // Pause, twice over, then resume.
+        player.pause(&mut psg).unwrap();
+        player.pause(&mut psg).unwrap();
+        assert_eq!(player.state(), PlayerState::Pausing);
+        assert_eq!(
+            levels(&mut player, &mut psg, 5),
+            [
+                (11, Fading),
+                (7, Fading),
+                (3, Fading),
+                (0, Fading),
+                (0, Idle)
+            ]
+        );
+        assert_eq!(player.state(), PlayerState::Paused);
+        player.pause(&mut psg).unwrap();
+        // Something else borrows the chip meanwhile.
+        psg.update_register(0x0, 5).unwrap();
+        psg.take_writes();
+        player.resume(&mut psg).unwrap();
+        assert_eq!(psg.take_writes(), [(0x0, 100)]);
+        assert_eq!(
+            levels(&mut player, &mut psg, 5),
+            [
+                (4, Fading),
+                (8, Fading),
+                (12, Fading),
+                (15, Fading),
+                (15, Played)
+            ]
+        );
+        assert_eq!(player.position(), 2);
+
+        // Pausing part way into a resume, and back, turns the fade round.
+        player.pause(&mut psg).unwrap();
+        levels(&mut player, &mut psg, 4);
+        player.resume(&mut psg).unwrap();
+        levels(&mut player, &mut psg, 1);
+        player.pause(&mut psg).unwrap();
+        assert_eq!(
+            levels(&mut player, &mut psg, 4),
+            [(3, Fading), (2, Fading), (1, Fading), (0, Fading)]
+        );
+        player.resume(&mut psg).unwrap();
+        levels(&mut player, &mut psg, 1);
+        player.resume(&mut psg).unwrap();
+        assert_eq!(
+            levels(&mut player, &mut psg, 4),
+            [(8, Fading), (12, Fading), (15, Fading), (12, Played)]
+        );
+        assert_eq!(player.position(), 3);
+        // None of it restarted the envelope.
+        assert!(psg.writes.iter().all(|&(address, _)| address != 0xD));
+
+        // Pause, then seek: the resume fades in at the new place.
+        player.stop(&mut psg).unwrap();
+        player.play();
+        player.tick(&mut psg).unwrap();
+        player.pause(&mut psg).unwrap();
+        levels(&mut player, &mut psg, 2);
+        player.seek(&mut psg, 2).unwrap();
+        assert_eq!(player.state(), PlayerState::Paused);
+        assert_eq!(
+            (psg.registers().value(0x0), psg.registers().value(0x8)),
+            (90, 0)
+        );
+        player.resume(&mut psg).unwrap();
+        assert_eq!(
+            levels(&mut player, &mut psg, 5),
+            [
+                (4, Fading),
+                (8, Fading),
+                (12, Fading),
+                (15, Fading),
+                (12, Played)
+            ]
+        );
+        assert_eq!(psg.registers().value(0x0), 80);
+
+        // Pause, then stop: resuming starts over.
+        player.stop(&mut psg).unwrap();
+        player.play();
+        player.tick(&mut psg).unwrap();
+        player.pause(&mut psg).unwrap();
+        player.stop(&mut psg).unwrap();
+        assert_eq!(
+            (player.state(), player.position()),
+            (PlayerState::Stopped, 0)
+        );
+        assert_eq!(psg.registers().value(0x8), 0);
+        player.resume(&mut psg).unwrap();
+        assert_eq!(levels(&mut player, &mut psg, 1), [(15, Played)]);
+        assert_eq!(psg.registers().value(0x0), 100);
+    }
+
+    #[test]
+    fn envelope_channels_cut_once_the_rest_are_quiet() {
+        static BUZZ: [Frame; 1] = {
+            let mut frame = frame(100, 0x10, 0x0A);
+            frame[9] = 6;
+            [frame]
+        };
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&BUZZ[..]).with_fade(2);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        player.pause(&mut psg).unwrap();
+        let levels = |psg: &FakePsg| (psg.registers().value(0x8), psg.registers().value(0x9));
+        player.tick(&mut psg).unwrap();
+        assert_eq!(levels(&psg), (0x10, 3));
+        player.tick(&mut psg).unwrap();
+        assert_eq!(levels(&psg), (0, 0));
+        player.resume(&mut psg).unwrap();
+        assert_eq!(levels(&psg), (0x10, 0));
+        player.tick(&mut psg).unwrap();
+        player.tick(&mut psg).unwrap();
+        assert_eq!(levels(&psg), (0x10, 6));
+        assert_eq!(player.state(), PlayerState::Playing);
+    }
+
+    /// Has each frame ready only on every other request, like storage that
+    /// can't keep up.
+    struct SlowSource {
+        frames: &'static [u8],
+        asked: u32,
+    }
+
+    impl FrameSource for SlowSource {
+        fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+            self.asked += 1;
+            if self.asked % 2 == 1 {
+                return Ok(FrameStatus::NotReady);
+            }
+            if index == 2 {
+                return Err(SourceError::Read);
+            }
+            self.frames.frame(index, frame)
+        }
+    }
+
+    #[test]
+    fn slow_sources_underrun_without_losing_frames() {
+        static BYTES: [u8; 48] = {
+            let mut bytes = [0; 48];
+            let mut i = 0;
+            while i < 48 {
+                bytes[i] = SONG[i / 16][i % 16];
+                i += 1;
+            }
+            bytes
+        };
+        let flat = FramePlayer::new(&BYTES[..]);
+        assert_eq!(flat.source().frame_count(), Some(3));
+
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(SlowSource {
+            frames: &BYTES,
+            asked: 0,
+        });
+        player.play();
+        assert_eq!(
+            periods(&mut player, &mut psg, 5),
+            [
+                (0, PlayStatus::Underrun),
+                (100, PlayStatus::Played),
+                (100, PlayStatus::Underrun),
+                (90, PlayStatus::Played),
+                (90, PlayStatus::Underrun),
+            ]
+        );
+        assert_eq!(player.position(), 2);
+        // A failing source stops the song rather than playing on.
+        assert_eq!(
+            player.tick(&mut psg),
+            Ok(PlayStatus::Failed(SourceError::Read))
+        );
+        assert_eq!(player.state(), PlayerState::Stopped);
+        assert_eq!(psg.registers().value(0x8), 0);
+    }
+
+    /// Every frame the same, forever.
+    struct Endless;
+
+    impl FrameSource for Endless {
+        fn frame(&mut self, _: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+            *frame = SONG[0];
+            Ok(FrameStatus::Ready)
+        }
+    }
+
+    #[test]
+    fn fractional_rates_hold_over_an_hour() {
+        let mut psg = FakePsg::new();
+        // (song rate in mHz, tick rate, expected frames in an hour)
+        for (song, ticks, frames) in [
+            (50_080, 1000, 180_288),
+            (59_940, 1000, 215_784),
+            (50_000, 60, 180_000),
+        ] {
+            let mut player = FramePlayer::new(Endless).with_rate(song, ticks);
+            player.play();
+            for _ in 0..ticks * 3600 {
+                player.tick(&mut psg).unwrap();
+            }
+            assert!(
+                player.position().abs_diff(frames) <= 1,
+                "{song} mHz: {} frames",
+                player.position()
+            );
+        }
+    }
+
+    #[test]
+    fn catching_up_plays_several_frames_a_tick() {
+        let mut psg = FakePsg::new();
+        // 50 Hz from a 20 Hz tick: 2, 3, 2, 3...
+        let mut player = FramePlayer::new(Endless).with_rate(50_000, 20);
+        player.play();
+        let positions: Vec<u32> = (0..4)
+            .map(|_| {
+                player.tick(&mut psg).unwrap();
+                player.position()
+            })
+            .collect();
+        assert_eq!(positions, [2, 5, 7, 10]);
+
+        // 25 Hz from a 100 Hz tick: a frame every fourth call, starting
+        // straight away.
+        let mut player = FramePlayer::new(&SONG[..])
+            .with_rate(25_000, 100)
+            .with_loop(0);
+        player.play();
+        let statuses: Vec<PlayStatus> = (0..5).map(|_| player.tick(&mut psg).unwrap()).collect();
+        assert_eq!(
+            statuses,
+            [
+                PlayStatus::Played,
+                PlayStatus::Waiting,
+                PlayStatus::Waiting,
+                PlayStatus::Waiting,
+                PlayStatus::Played,
+            ]
+        );
+    }
+
+    /// Each frame changes different registers, and only some restart the
+    /// envelope.
+    fn varied_song() -> [Frame; 40] {
+        let mut seed = 0x9E37_79B9u32;
+        core::array::from_fn(|_| {
+            let mut frame = [0; 16];
+            for value in &mut frame {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                *value = seed as u8;
+            }
+            if seed & 0x300 != 0 {
+                frame[0xD] = R13_UNCHANGED;
+            }
+            frame
+        })
+    }
+
+    #[test]
+    fn seeking_matches_playing_through() {
+        let song = varied_song();
+        for target in [1, 2, 17, 39, 40] {
+            let mut played = FakePsg::new();
+            let mut player = FramePlayer::new(&song[..]);
+            player.play();
+            for _ in 0..target {
+                player.tick(&mut played).unwrap();
+            }
+
+            // Straight there on a fresh chip, R13 included.
+            let mut fresh = FakePsg::new();
+            let mut seeker = FramePlayer::new(&song[..]);
+            seeker.play();
+            seeker.seek(&mut fresh, target).unwrap();
+            assert_eq!(fresh.registers().values(), played.registers().values());
+
+            let mut sought = FakePsg::new();
+            let mut seeker = FramePlayer::new(&song[..]);
+            seeker.play();
+            seeker.tick(&mut sought).unwrap();
+            // Forward past the target, then back to it from scratch.
+            seeker.seek(&mut sought, 30).unwrap();
+            assert_eq!(seeker.seek(&mut sought, target), Ok(PlayStatus::Played));
+            assert_eq!(seeker.position(), target);
+            for address in 0..0xD {
+                assert_eq!(
+                    sought.registers().value(address),
+                    played.registers().value(address),
+                    "R{address} at frame {target}"
+                );
+            }
+            // And both carry on the same.
+            player.tick(&mut played).unwrap();
+            seeker.tick(&mut sought).unwrap();
+            assert_eq!(
+                sought.registers().values()[..0xD],
+                played.registers().values()[..0xD]
+            );
+        }
+    }
+
+    #[test]
+    fn snapshots_resume_where_they_left_off() {
+        let song = varied_song();
+        let player = || {
+            FramePlayer::new(&song[..])
+                .with_loop(10)
+                .with_rate(50_080, 200)
+        };
+        let mut played = FakePsg::new();
+        let mut before = player().with_mute(Mute::new(ChannelMask::C));
+        before.play();
+        for _ in 0..250 {
+            before.tick(&mut played).unwrap();
+        }
+        let bytes = before.snapshot(7).to_bytes();
+
+        // Waking with nothing but the bytes, on a chip that lost its state.
+        let snapshot = PlayerSnapshot::from_bytes(&bytes).unwrap();
+        let mut woken = FakePsg::new();
+        let mut after = player();
+        assert_eq!(
+            after.resume_from(&mut woken, &snapshot, 7),
+            Ok(PlayStatus::Played)
+        );
+        assert_eq!(after.state(), PlayerState::Playing);
+        assert_eq!(after.mute(), before.mute());
+        assert_eq!(after.loops_completed(), before.loops_completed());
+        assert_eq!(after.elapsed(), before.elapsed());
+        assert_eq!(
+            woken.registers().values()[..0xD],
+            played.registers().values()[..0xD]
+        );
+        for _ in 0..100 {
+            assert_eq!(after.tick(&mut woken), before.tick(&mut played));
+            assert_eq!(after.position(), before.position());
+            assert_eq!(
+                woken.registers().values()[..0xD],
+                played.registers().values()[..0xD]
+            );
+        }
+
+        // Another song, or this one changed, is refused untouched.
+        let mut other = player();
+        assert_eq!(
+            other.resume_from(&mut woken, &snapshot, 8),
+            Err(ResumeError::OtherSong)
+        );
+        let mut shorter = FramePlayer::new(&song[..39]);
+        assert_eq!(
+            shorter.resume_from(&mut woken, &snapshot, 7),
+            Err(ResumeError::LengthChanged {
+                was: Some(40),
+                now: Some(39)
+            })
+        );
+        assert_eq!(shorter.state(), PlayerState::Stopped);
+        assert_eq!(shorter.position(), 0);
+    }
+
+    #[test]
+    fn analysis_follows_the_envelope_from_its_restart() {
+        // A sawtooth down on B, a step every 1 ms, so 20 ms a frame at 50 Hz.
+        static RAMPS: [Frame; 3] = {
+            let mut frames = [[0; 16]; 3];
+            let mut n = 0;
+            while n < 3 {
+                frames[n] = [
+                    0x1C,
+                    0x01,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0x3C,
+                    9,
+                    0x10,
+                    0,
+                    250,
+                    0,
+                    R13_UNCHANGED,
+                    0,
+                    0,
+                ];
+                n += 1;
+            }
+            frames[0][0xD] = 0x8;
+            frames
+        };
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&RAMPS[..]);
+        player.play();
+        let mut levels = Vec::new();
+        for _ in 0..3 {
+            player.tick(&mut psg).unwrap();
+            let analysis = player.last_analysis(&psg);
+            assert_eq!(analysis.channel(Channel::A).level, 9);
+            assert_eq!(analysis.channel(Channel::A).tone_millihertz, 440_141);
+            assert!(analysis.channel(Channel::B).envelope);
+            levels.push(analysis.channel(Channel::B).level);
+        }
+        // Restarted, then 20 steps on, then 8 into the second ramp.
+        assert_eq!(levels, [15, 5, 11]);
+        // A seek back over the restart makes it again.
+        player.seek(&mut psg, 1).unwrap();
+        assert_eq!(player.last_analysis(&psg).channel(Channel::B).level, 15);
+    }
+
+    #[test]
+    fn transposed_periods_match_the_notes_asked_for() {
+        use crate::pitch::{Note, Pitch};
+        let clock = FakePsg::new().master_clock();
+        let pitches = [
+            Pitch::new(Note::A, 2),
+            Pitch::new(Note::E, 4),
+            Pitch::new(Note::B, 6),
+        ];
+        let mut song = [[0; 16]; 2];
+        for frame in &mut song {
+            for (channel, pitch) in Channel::ALL.into_iter().zip(pitches) {
+                let (fine, rough) = channel.period_registers();
+                let [low, high] = (tuning::pitch_period(clock, pitch) as u16).to_le_bytes();
+                (frame[fine as usize], frame[rough as usize]) = (low, high);
+            }
+            (frame[0x6], frame[0xB], frame[0xC], frame[0xD]) = (16, 0x00, 0x10, R13_UNCHANGED);
+        }
+        for semitones in [-12, -7, -1, 1, 5, 12] {
+            let mut psg = FakePsg::new();
+            let mut player = FramePlayer::new(&song[..]);
+            player.set_transpose(semitones);
+            player.play();
+            player.tick(&mut psg).unwrap();
+            let first = *psg.registers();
+            for (channel, pitch) in Channel::ALL.into_iter().zip(pitches) {
+                let wanted = tuning::pitch_period(clock, pitch.transpose(semitones).unwrap());
+                let got = first.tone_period(channel) as u32;
+                assert!(got.abs_diff(wanted) <= 1, "{semitones}: {got} for {wanted}");
+            }
+            assert_eq!((first.value(0x6), first.value(0xC)), (16, 0x10));
+            // Held notes hold still.
+            player.tick(&mut psg).unwrap();
+            assert_eq!(psg.registers().values(), first.values());
+        }
+
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&song[..])
+            .with_transposed_noise()
+            .with_transposed_envelope();
+        player.set_transpose(12);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x6), 8);
+        assert_eq!(
+            (psg.registers().value(0xB), psg.registers().value(0xC)),
+            (0x00, 0x08)
+        );
+        // A2 down three octaves is too low to play: it comes back up.
+        player.set_transpose(-36);
+        player.tick(&mut psg).unwrap();
+        assert!(psg.registers().tone_period(Channel::A) <= MAX_TONE_PERIOD);
+        assert_eq!(player.transpose(), -36);
+    }
+
+    #[test]
+    fn data_sources_play_as_slices_do() {
+        let song = varied_song();
+        let bytes = song.as_flattened();
+        let inverted = Inverted::new(bytes);
+        let mut players = (
+            FramePlayer::new(bytes).with_loop(3),
+            FramePlayer::new(FrameData(&inverted)).with_loop(3),
+        );
+        assert_eq!(players.1.source().frame_count(), Some(40));
+        players.0.play();
+        players.1.play();
+        let (mut a, mut b) = (FakePsg::new(), FakePsg::new());
+        for _ in 0..100 {
+            assert_eq!(players.0.tick(&mut a), players.1.tick(&mut b));
+        }
+        assert_eq!(a.writes, b.writes);
+    }
+
+    #[test]
+    fn skip_writes_only_what_changed_and_stays_muted_when_paused() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        psg.take_writes();
+        assert_eq!(player.skip(&mut psg, 1), Ok(PlayStatus::Played));
+        // Frame 1 only changes the period, and doesn't restart the envelope.
+        assert_eq!(psg.take_writes(), [(0x0, 90)]);
+
+        player.pause(&mut psg).unwrap();
+        assert_eq!(player.skip(&mut psg, 5), Ok(PlayStatus::Finished));
+        assert_eq!(player.position(), 3);
+        assert_eq!(psg.registers().value(0x0), 80);
+        assert_eq!(psg.registers().value(0x8), 0);
+    }
+
+    #[test]
+    fn times_follow_the_rate_loops_and_seeks() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]).with_loop(1);
+        assert_eq!(player.duration(), Some(PlayTime::from_millis(60)));
+        player.play();
+        for _ in 0..6 {
+            player.tick(&mut psg).unwrap();
+        }
+        // Six frames in, two of them second time round.
+        assert_eq!(player.elapsed().total_millis(), 120);
+        assert_eq!(player.loops_completed(), 2);
+        player.stop(&mut psg).unwrap();
+        assert_eq!(
+            (player.elapsed().total_millis(), player.loops_completed()),
+            (0, 0)
+        );
+
+        // A long song at 50.08 Hz from a 1 kHz timer.
+        let mut player = FramePlayer::new(Endless).with_rate(50_080, 1000);
+        assert_eq!(player.duration(), None);
+        player.play();
+        player.seek(&mut psg, 50_080).unwrap();
+        assert_eq!(
+            player.elapsed(),
+            PlayTime {
+                minutes: 16,
+                seconds: 40,
+                millis: 0,
+            }
+        );
+        // Ten seconds more is 501 whole frames, a shade over 10 s.
+        for _ in 0..10_000 {
+            player.tick(&mut psg).unwrap();
+        }
+        assert_eq!(player.position(), 50_581);
+        assert_eq!(
+            player.elapsed(),
+            PlayTime {
+                minutes: 16,
+                seconds: 50,
+                millis: 3,
+            }
+        );
+        player.seek(&mut psg, 3005).unwrap();
+        assert_eq!(player.elapsed().total_millis(), 60_003);
+    }
+
+    #[derive(Debug, Default, Clone, PartialEq, Eq)]
+    struct Log(Vec<(&'static str, u32, u8, u8)>);
+
+    impl PlayerHooks for Log {
+        fn on_frame(&mut self, index: u32, registers: &Registers) {
+            self.0
+                .push(("frame", index, registers.value(0x0), registers.value(0x8)));
+        }
+
+        fn on_loop(&mut self, count: u32) {
+            self.0.push(("loop", count, 0, 0));
+        }
+
+        fn on_end(&mut self) {
+            self.0.push(("end", 0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn hooks_see_each_frame_once_it_is_written() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..])
+            .with_loop(2)
+            .with_hooks(Log::default());
+        player.play();
+        for _ in 0..4 {
+            player.tick(&mut psg).unwrap();
+        }
+        assert_eq!(
+            player.hooks().0,
+            [
+                ("frame", 0, 100, 15),
+                ("frame", 1, 90, 15),
+                ("frame", 2, 80, 12),
+                ("loop", 1, 0, 0),
+                ("frame", 2, 80, 12),
+            ]
+        );
+
+        let mut player = FramePlayer::new(&SONG[..2]).with_hooks(Log::default());
+        player.play();
+        for _ in 0..4 {
+            player.tick(&mut psg).unwrap();
+        }
+        // Neither idle ticks nor the silencing are frames.
+        assert_eq!(player.hooks().0.len(), 3);
+        assert_eq!(player.hooks().0[2], ("end", 0, 0, 0));
+    }
+
+    #[test]
+    fn loop_policy_counts_loops_then_fades() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..])
+            .with_loop(1)
+            .with_loop_policy(LoopPolicy::Count(1));
+        player.play();
+        assert_eq!(
+            periods(&mut player, &mut psg, 6),
+            [
+                (100, PlayStatus::Played),
+                (90, PlayStatus::Played),
+                (80, PlayStatus::Played),
+                (90, PlayStatus::Looped),
+                (80, PlayStatus::Played),
+                (80, PlayStatus::Finished),
+            ]
+        );
+
+        // Going back once, then fading over six frames of a three-frame
+        // loop.
+        static STEADY: [Frame; 4] = [
+            frame(100, 15, 0x0E),
+            frame(90, 15, R13_UNCHANGED),
+            frame(80, 15, R13_UNCHANGED),
+            frame(70, 15, R13_UNCHANGED),
+        ];
+        let policy = LoopPolicy::CountThenFade {
+            loops: 1,
+            fade_frames: 6,
+        };
+        let mut player = FramePlayer::new(&STEADY[..])
+            .with_loop(1)
+            .with_loop_policy(policy)
+            .with_hooks(Log::default());
+        player.play();
+        let mut statuses = Vec::new();
+        while statuses.last() != Some(&PlayStatus::Finished) {
+            statuses.push(player.tick(&mut psg).unwrap());
+        }
+        assert_eq!(statuses.len(), 13);
+        let frames: Vec<_> = player
+            .hooks()
+            .0
+            .iter()
+            .filter(|entry| entry.0 == "frame")
+            .map(|&(_, index, _, level)| (index, level))
+            .collect();
+        // The fade starts on the second time back to the loop start.
+        assert_eq!(frames[..7].iter().filter(|frame| frame.1 < 15).count(), 0);
+        assert_eq!(frames[7..], [(1, 12), (2, 10), (3, 7), (1, 5), (2, 2)]);
+        assert!(frames.windows(2).all(|pair| pair[1].1 <= pair[0].1));
+        assert_eq!(player.hooks().0.last(), Some(&("end", 0, 0, 0)));
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(player.loops_completed(), 0);
+
+        // Without a loop to go round, the policy has nothing to do.
+        for mut player in [
+            FramePlayer::new(&SONG[..]).with_loop_policy(policy),
+            FramePlayer::new(&SONG[..])
+                .with_loop(3)
+                .with_loop_policy(policy),
+        ] {
+            player.play();
+            assert_eq!(periods(&mut player, &mut psg, 4)[3].1, PlayStatus::Finished);
+        }
+    }
+
+    #[test]
+    fn muted_channels_keep_their_periods_and_come_back_in_place() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..])
+            .with_loop(0)
+            .with_mute(Mute::new(ChannelMask::A));
+        player.play();
+        player.tick(&mut psg).unwrap();
+        player.tick(&mut psg).unwrap();
+        let writes = psg.take_writes();
+        // The level is written as 0, once.
+        let levels: Vec<_> = writes.iter().filter(|write| write.0 == 0x8).collect();
+        assert_eq!(levels, [&(0x8, 0)]);
+        assert!(writes.contains(&(0x0, 100)) && writes.contains(&(0x0, 90)));
+        assert!(writes.contains(&(0xB, 0x40)) && writes.contains(&(0xD, 0x0E)));
+        assert_eq!(psg.registers().mixer(), 0x3E);
+
+        // Unmuted mid-song, the level is back with the next frame.
+        player.set_mute(Mute::NONE);
+        player.tick(&mut psg).unwrap();
+        assert_eq!(psg.take_writes(), [(0x0, 80), (0x8, 12)]);
+
+        player.set_mute(Mute::new(ChannelMask::A).with_mixer_off());
+        player.tick(&mut psg).unwrap();
+        assert_eq!(
+            psg.take_writes(),
+            [(0x0, 100), (0x7, 0x3F), (0x8, 0), (0xD, 0x0E)]
+        );
+        assert!(player.mute().is_muted(Channel::A));
+    }
+
+    #[cfg(feature = "sfx")]
+    #[test]
+    fn muting_stacks_with_sound_effects() {
+        let mut arbiter = SfxArbiter::new(FakePsg::new(), SfxPolicy::StealMusic);
+        for channel in Channel::ALL {
+            arbiter.set_music_channel(channel, Some(1));
+        }
+        arbiter.set_music_channel(Channel::A, Some(0));
+        let mut player = FramePlayer::new(&SONG[..])
+            .with_loop(0)
+            .with_mute(Mute::new(ChannelMask::A));
+        player.play();
+        player.tick(&mut arbiter.music()).unwrap();
+        assert_eq!(arbiter.trigger_sfx(Sfx::Coin, 5), Some(Channel::A));
+        arbiter.tick().unwrap();
+        // The effect is heard on the muted channel...
+        assert_ne!(arbiter.psg().registers().value(0x8), 0);
+        for _ in 0..100 {
+            player.tick(&mut arbiter.music()).unwrap();
+            arbiter.tick().unwrap();
+        }
+        // ...and when it's over the channel is muted again, though the music
+        // has carried on.
+        assert_eq!(arbiter.sfx_priority(Channel::A), None);
+        assert_eq!(arbiter.psg().registers().value(0x8), 0);
+        assert_eq!(arbiter.psg().registers().value(0x0), 90);
+    }
+
+    /// Channel A's registers and mixer bits on C, and C's on A.
+    fn swap_a_and_c(frame: &mut Frame) {
+        frame.swap(0x0, 0x4);
+        frame.swap(0x1, 0x5);
+        frame.swap(0x8, 0xA);
+        let mixer = frame[0x7];
+        frame[0x7] = mixer & 0b1101_0010 | (mixer & 0b0000_1001) << 2 | (mixer & 0b0010_0100) >> 2;
+    }
+
+    #[test]
+    fn transforms_rewrite_frames_before_they_are_written() {
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&SONG[..]).with_frame_transform(swap_a_and_c);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        let writes = psg.take_writes();
+        assert!(writes.contains(&(0x4, 100)) && writes.contains(&(0xA, 15)));
+        assert!(writes.contains(&(0x0, 0)) && writes.contains(&(0x8, 0)));
+        // Tone on C alone.
+        assert_eq!(psg.registers().mixer(), 0x3B);
+        player.tick(&mut psg).unwrap();
+        assert_eq!(psg.take_writes(), [(0x4, 90)]);
+
+        // A seek crosses the rewritten frames, landing where playing does.
+        let mut seeking = FramePlayer::new(&SONG[..]).with_frame_transform(swap_a_and_c);
+        let mut sought = FakePsg::new();
+        seeking.play();
+        seeking.seek(&mut sought, 2).unwrap();
+        assert_eq!(sought.registers(), psg.registers());
+
+        player.set_frame_transform(None);
+        player.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x0), 80);
+        assert_eq!(psg.registers().mixer(), 0x3E);
+    }
+
+    #[test]
+    fn catching_up_merges_or_drops_the_frames_missed() {
+        static STEPS: [Frame; 5] = {
+            let mut steps = [
+                frame(100, 15, 0x0E),
+                frame(90, 15, R13_UNCHANGED),
+                frame(80, 12, 0x08),
+                frame(70, 12, R13_UNCHANGED),
+                frame(60, 12, R13_UNCHANGED),
+            ];
+            steps[1][0x1] = 1;
+            steps
+        };
+        for (policy, r13, merged, dropped) in [
+            (CatchUpPolicy::PlayAll, 0x08, 0, 0),
+            (CatchUpPolicy::Merge, 0x08, 2, 0),
+            (CatchUpPolicy::Drop, 0x0E, 0, 2),
+        ] {
+            let mut psg = FakePsg::new();
+            let mut player = FramePlayer::new(&STEPS[..])
+                .with_rate(50_000, 1000)
+                .with_catch_up(policy);
+            player.play();
+            player.tick(&mut psg).unwrap();
+            psg.take_writes();
+            // The bus held things up for 60 ms: three frames due.
+            assert_eq!(player.tick_by(&mut psg, 60), Ok(PlayStatus::Played));
+            assert_eq!(player.position(), 4);
+            assert_eq!(
+                (psg.registers().value(0x0), psg.registers().value(0x1)),
+                (70, 0),
+                "{policy:?}"
+            );
+            assert_eq!(psg.registers().value(0xD), r13, "{policy:?}");
+            let stats = player.catch_up_stats();
+            assert_eq!((stats.merged, stats.dropped), (merged, dropped));
+            assert_eq!(stats.catch_ups, 1);
+            let writes = psg.take_writes().len();
+            if policy == CatchUpPolicy::PlayAll {
+                assert!(writes > 4);
+            } else {
+                // The period and level only, and R13 if merged.
+                assert!(writes <= 3, "{policy:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn a_slow_bus_keeps_time_and_pitch() {
+        let song = varied_song();
+        for policy in [CatchUpPolicy::Merge, CatchUpPolicy::Drop] {
+            let mut psg = FakePsg::new();
+            let mut player = FramePlayer::new(&song[..])
+                .with_loop(0)
+                .with_rate(50_000, 1000)
+                .with_catch_up(policy);
+            player.play();
+            // Each register write takes 2 ms, so a busy frame takes longer
+            // than its 20 ms, and the millisecond timer is read before each
+            // call to see how much time has gone.
+            let (mut now, mut last) = (1, 0);
+            while now < 5000 {
+                player.tick_by(&mut psg, now - last).unwrap();
+                last = now;
+                now += 1 + 2 * psg.take_writes().len() as u32;
+            }
+            // Exactly on time, bar the frame in progress...
+            let frames = (last as u64 * 50_000 + 950_000) / 1_000_000;
+            assert_eq!(player.elapsed(), PlayTime::from_frames(frames, 50_000));
+            assert!(player.catch_up_stats().catch_ups > 0);
+            // ...with the current frame's pitches on the chip.
+            let current = &song[player.position() as usize - 1];
+            for address in 0..6 {
+                assert_eq!(
+                    psg.registers().value(address),
+                    current[address as usize] & crate::registers::REGISTER_MASKS[address as usize],
+                    "{policy:?} R{address}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn subticks_move_levels_and_tones_between_frames() {
+        static STEP: [Frame; 2] = {
+            let mut step = [frame(100, 3, 0x0E), frame(80, 11, R13_UNCHANGED)];
+            // B on the envelope, and a noise and mixer change.
+            step[0][0x9] = 0x10;
+            step[1][0x9] = 0x10;
+            step[1][0x6] = 9;
+            step[1][0x7] = 0x36;
+            step
+        };
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&STEP[..]).with_interpolation(3);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        psg.take_writes();
+        let mut subticks = Vec::new();
+        for _ in 0..4 {
+            player.subtick(&mut psg).unwrap();
+            subticks.push(psg.take_writes());
+        }
+        // A quarter of the way each time, then held.
+        assert_eq!(subticks, [&[(0x8, 5)][..], &[(0x8, 7)], &[(0x8, 9)], &[]]);
+        player.tick(&mut psg).unwrap();
+        assert_eq!(player.position(), 2);
+        let writes = psg.take_writes();
+        assert!(writes.contains(&(0x8, 11)) && writes.contains(&(0x0, 80)));
+        assert!(writes.contains(&(0x6, 9)) && writes.contains(&(0x7, 0x36)));
+        assert_eq!(
+            player.interpolation_stats(),
+            InterpolationStats {
+                subticks: 4,
+                writes: 3,
+            }
+        );
+        // Nothing to move towards at the end.
+        player.subtick(&mut psg).unwrap();
+        assert!(psg.take_writes().is_empty());
+
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(&STEP[..])
+            .with_interpolation(3)
+            .with_interpolated_tones();
+        player.play();
+        player.tick(&mut psg).unwrap();
+        psg.take_writes();
+        let tones: Vec<_> = (0..3)
+            .map(|_| {
+                player.subtick(&mut psg).unwrap();
+                (psg.registers().value(0x0), psg.registers().value(0x8))
+            })
+            .collect();
+        assert_eq!(tones, [(95, 5), (90, 7), (85, 9)]);
+        // Only the fine period and level change, never the mixer or noise.
+        assert!(psg
+            .take_writes()
+            .iter()
+            .all(|&(address, _)| address == 0x0 || address == 0x8));
+        assert_eq!(player.interpolation_stats().writes, 6);
+
+        // Without it, subticks write nothing.
+        let mut plain = FramePlayer::new(&STEP[..]);
+        plain.play();
+        plain.tick(&mut psg).unwrap();
+        psg.take_writes();
+        plain.subtick(&mut psg).unwrap();
+        assert!(psg.take_writes().is_empty());
+        assert_eq!(plain.interpolation_stats(), InterpolationStats::default());
+    }
+}
@@ -0,0 +1,308 @@
+//! A lock-free queue of frames between a main loop that loads them and a
+//! timer interrupt that plays them.
+//!
+//! The queue is split once into a [`FrameProducer`], kept by the main loop,
+//! and a [`FrameConsumer`], which is a [`FrameSource`] for the interrupt's
+//! [`FramePlayer`](crate::frame_player::FramePlayer). Neither side ever
+//! waits for the other: a full queue turns a push away and an empty one has
+//! the player hold its last frame, and both are counted.
+//!
+//! ```
+//! use ym2149::frame_player::{FramePlayer, FrameSource, FrameStatus};
+//! use ym2149::frame_queue::FrameQueue;
+//!
+//! // In firmware this lives in a `static`, split once at start-up.
+//! let mut queue = FrameQueue::<4>::new();
+//! let (mut producer, consumer) = queue.split();
+//! let mut player = FramePlayer::new(consumer);
+//!
+//! // Main loop: top the queue up whenever there is room.
+//! let mut next = 0u8;
+//! while producer.has_room() {
+//!     let frame = [next; 16]; // read from storage here
+//!     producer.push_frame(&frame).unwrap();
+//!     next += 1;
+//! }
+//!
+//! // Timer interrupt: `player.tick(&mut ym)` takes one frame per call.
+//! let mut frame = [0; 16];
+//! assert_eq!(player.source_mut().frame(0, &mut frame), Ok(FrameStatus::Ready));
+//! assert_eq!(frame[0], 0);
+//! ```
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use crate::frame_player::{Frame, FrameSource, FrameStatus, SourceError};
+
+/// Returned by [`FrameProducer::push_frame`] when the queue is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QueueFull;
+
+/// Room for `N` frames, shared by one producer and one consumer.
+///
+/// Only loads and stores are used on the atomics, never read-modify-write,
+/// so the queue works on cores without compare-and-swap such as the
+/// Cortex-M0.
+pub struct FrameQueue<const N: usize> {
+    frames: UnsafeCell<[Frame; N]>,
+    /// Frames taken so far, written only by the consumer.
+    head: AtomicUsize,
+    /// Frames pushed so far, written only by the producer.
+    tail: AtomicUsize,
+    finished: AtomicBool,
+    underruns: AtomicU32,
+    overruns: AtomicU32,
+}
+
+// The producer only writes slots the consumer has finished with, and the
+// consumer only reads slots the producer has published, as `head` and `tail`
+// record. `split` taking `&mut self` makes sure there is one of each.
+unsafe impl<const N: usize> Sync for FrameQueue<N> {}
+
+impl<const N: usize> Default for FrameQueue<N> {
+    fn default() -> FrameQueue<N> {
+        FrameQueue::new()
+    }
+}
+
+impl<const N: usize> FrameQueue<N> {
+    pub const fn new() -> FrameQueue<N> {
+        FrameQueue {
+            frames: UnsafeCell::new([[0; 16]; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            finished: AtomicBool::new(false),
+            underruns: AtomicU32::new(0),
+            overruns: AtomicU32::new(0),
+        }
+    }
+
+    /// The two ends of the queue, emptied and with the counters cleared.
+    pub fn split(&mut self) -> (FrameProducer<'_, N>, FrameConsumer<'_, N>) {
+        *self.head.get_mut() = 0;
+        *self.tail.get_mut() = 0;
+        *self.finished.get_mut() = false;
+        *self.underruns.get_mut() = 0;
+        *self.overruns.get_mut() = 0;
+        (FrameProducer { queue: self }, FrameConsumer { queue: self })
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    /// Times the consumer found the queue empty.
+    pub fn underruns(&self) -> u32 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Frames turned away because the queue was full.
+    pub fn overruns(&self) -> u32 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+}
+
+/// Bumps a counter only one side writes.
+fn count(counter: &AtomicU32) {
+    counter.store(
+        counter.load(Ordering::Relaxed).wrapping_add(1),
+        Ordering::Relaxed,
+    );
+}
+
+/// The main loop's end of a [`FrameQueue`].
+pub struct FrameProducer<'a, const N: usize> {
+    queue: &'a FrameQueue<N>,
+}
+
+impl<const N: usize> FrameProducer<'_, N> {
+    pub fn has_room(&self) -> bool {
+        self.queue.len() < N
+    }
+
+    /// Frames waiting to be played.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.len() == 0
+    }
+
+    /// Adds a frame to the back of the queue, or counts an overrun if it is
+    /// full.
+    pub fn push_frame(&mut self, frame: &Frame) -> Result<(), QueueFull> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.queue.head.load(Ordering::Acquire)) >= N {
+            count(&self.queue.overruns);
+            return Err(QueueFull);
+        }
+        // SAFETY: the slot is outside head..tail, so the consumer isn't
+        // reading it, and only this producer writes.
+        unsafe {
+            (*self.queue.frames.get())[tail % N] = *frame;
+        }
+        self.queue
+            .tail
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Marks the end of the song: once the queue has drained, the consumer
+    /// reports [`FrameStatus::End`] rather than an underrun.
+    pub fn finish(&mut self) {
+        self.queue.finished.store(true, Ordering::Release);
+    }
+
+    pub fn underruns(&self) -> u32 {
+        self.queue.underruns()
+    }
+
+    pub fn overruns(&self) -> u32 {
+        self.queue.overruns()
+    }
+}
+
+/// The interrupt's end of a [`FrameQueue`], played by a
+/// [`FramePlayer`](crate::frame_player::FramePlayer).
+pub struct FrameConsumer<'a, const N: usize> {
+    queue: &'a FrameQueue<N>,
+}
+
+impl<const N: usize> FrameConsumer<'_, N> {
+    pub fn underruns(&self) -> u32 {
+        self.queue.underruns()
+    }
+
+    pub fn overruns(&self) -> u32 {
+        self.queue.overruns()
+    }
+}
+
+/// Hands out frames in the order they were pushed. The index is ignored, so
+/// a loop is up to the producer, which pushes the loop again.
+impl<const N: usize> FrameSource for FrameConsumer<'_, N> {
+    fn frame(&mut self, _: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        // Read before the queue, so a last frame pushed just before
+        // finishing is never missed.
+        let finished = self.queue.finished.load(Ordering::Acquire);
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if self.queue.tail.load(Ordering::Acquire) == head {
+            if finished {
+                return Ok(FrameStatus::End);
+            }
+            count(&self.queue.underruns);
+            return Ok(FrameStatus::NotReady);
+        }
+        // SAFETY: the slot is inside head..tail, so the producer has
+        // published it and won't touch it until head moves past.
+        *frame = unsafe { (*self.queue.frames.get())[head % N] };
+        self.queue
+            .head
+            .store(head.wrapping_add(1), Ordering::Release);
+        Ok(FrameStatus::Ready)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::frame_player::{FramePlayer, PlayStatus};
+    use crate::psg::Psg;
+    use crate::test_support::FakePsg;
+
+    fn numbered(n: u32) -> Frame {
+        let mut frame = [0; 16];
+        frame[..4].copy_from_slice(&n.to_le_bytes());
+        frame
+    }
+
+    fn number(frame: &Frame) -> u32 {
+        u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]])
+    }
+
+    #[test]
+    fn interleaved_contexts_keep_order_and_count() {
+        let mut queue = FrameQueue::<3>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let (mut pushed, mut played, mut rejected, mut starved) = (0, 0, 0, 0);
+        let mut seed = 0x2545_F491u32;
+        for _ in 0..5000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            if seed & 1 == 0 {
+                match producer.push_frame(&numbered(pushed)) {
+                    Ok(()) => pushed += 1,
+                    Err(QueueFull) => rejected += 1,
+                }
+            } else {
+                let mut frame = [0; 16];
+                match consumer.frame(0, &mut frame) {
+                    Ok(FrameStatus::Ready) => {
+                        assert_eq!(number(&frame), played);
+                        played += 1;
+                    }
+                    Ok(FrameStatus::NotReady) => starved += 1,
+                    other => panic!("{other:?}"),
+                }
+            }
+        }
+        assert!(rejected > 0 && starved > 0);
+        assert_eq!(
+            (consumer.overruns(), consumer.underruns()),
+            (rejected, starved)
+        );
+        assert_eq!(pushed - played, producer.len() as u32);
+    }
+
+    #[test]
+    fn threads_hammering_both_ends() {
+        const FRAMES: u32 = 20_000;
+        let mut queue = FrameQueue::<8>::new();
+        let (mut producer, mut consumer) = queue.split();
+        std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for n in 0..FRAMES {
+                    while producer.push_frame(&numbered(n)).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+                producer.finish();
+            });
+            let mut expected = 0;
+            let mut frame = [0; 16];
+            loop {
+                match consumer.frame(0, &mut frame).unwrap() {
+                    FrameStatus::Ready => {
+                        assert_eq!(number(&frame), expected);
+                        expected += 1;
+                    }
+                    FrameStatus::NotReady => std::thread::yield_now(),
+                    FrameStatus::End => break,
+                }
+            }
+            assert_eq!(expected, FRAMES);
+        });
+    }
+
+    #[test]
+    fn player_holds_through_underruns_and_ends() {
+        let mut psg = FakePsg::new();
+        let mut queue = FrameQueue::<2>::new();
+        let (mut producer, consumer) = queue.split();
+        let mut player = FramePlayer::new(consumer);
+        player.play();
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Underrun));
+        producer.push_frame(&numbered(7)).unwrap();
+        producer.finish();
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Played));
+        assert_eq!(psg.registers().value(0x0), 7);
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Finished));
+        assert_eq!(producer.underruns(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+//! Recording what is written to the chip as register dump frames, the body
+//! of an uncompressed YM5 file.
+//!
+//! [`FrameRecorder`] wraps any [`Psg`], passing every write through, and
+//! on each [`FrameRecorder::capture_frame`], called from your frame tick,
+//! copies the registers into the next of a buffer of [`Frame`]s. R13 is
+//! recorded as [`R13_UNCHANGED`](crate::frame_player::R13_UNCHANGED) unless it was written since the last
+//! capture, so playback restarts the envelope only where the chip did.
+//! [`FrameRecorder::write_ym5_header`] writes the header to put in front;
+//! the frames follow it as they are, then `End!`.
+
+use crate::frame_player::{snapshot_frame, Frame};
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::FrequencyPolicy;
+use crate::ChipKind;
+
+/// What follows the frames in a YM file.
+pub const YM_END: &[u8; 4] = b"End!";
+
+/// Bytes of a YM5 header before its strings.
+const HEADER: usize = 34;
+
+/// The buffer ran out. The frame wasn't recorded; the chip still gets every
+/// write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FramesFull;
+
+/// A [`Psg`] that records the state of the chip once a frame.
+pub struct FrameRecorder<'b, P> {
+    psg: P,
+    buffer: &'b mut [Frame],
+    frame_count: usize,
+    loop_frame: Option<u32>,
+    envelope_written: bool,
+    full: bool,
+}
+
+impl<'b, P: Psg> FrameRecorder<'b, P> {
+    /// Starts recording into `buffer`. The first frame captured holds what
+    /// `psg` already knows, as well as anything written since.
+    pub fn new(psg: P, buffer: &'b mut [Frame]) -> FrameRecorder<'b, P> {
+        FrameRecorder {
+            psg,
+            buffer,
+            frame_count: 0,
+            loop_frame: None,
+            envelope_written: false,
+            full: false,
+        }
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.psg
+    }
+
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.psg
+    }
+
+    pub fn into_inner(self) -> P {
+        self.psg
+    }
+
+    /// Records the registers as they are now as the next frame.
+    pub fn capture_frame(&mut self) -> Result<(), FramesFull> {
+        let restart = core::mem::take(&mut self.envelope_written);
+        match self.buffer.get_mut(self.frame_count) {
+            Some(slot) => {
+                *slot = snapshot_frame(self.psg.registers(), restart);
+                self.frame_count += 1;
+                Ok(())
+            }
+            None => {
+                self.full = true;
+                Err(FramesFull)
+            }
+        }
+    }
+
+    /// Whether a capture has been turned away for want of room.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count as u32
+    }
+
+    /// The frames captured so far.
+    pub fn frames(&self) -> &[Frame] {
+        &self.buffer[..self.frame_count]
+    }
+
+    /// The frames captured so far as bytes, as they go in a file.
+    pub fn frame_bytes(&self) -> &[u8] {
+        self.frames().as_flattened()
+    }
+
+    /// Makes the next frame captured the one players go back to at the end.
+    pub fn mark_loop(&mut self) {
+        self.loop_frame = Some(self.frame_count as u32);
+    }
+
+    /// The frame marked with [`FrameRecorder::mark_loop`], if any.
+    pub fn loop_frame(&self) -> Option<u32> {
+        self.loop_frame
+    }
+
+    /// Writes a YM5 header for the frames so far into `buffer`, returning
+    /// its length, or `None` if it doesn't fit. The frames are uninterleaved
+    /// at `frame_hertz`, looping to the start unless a loop was marked. The
+    /// strings shouldn't hold a NUL.
+    pub fn write_ym5_header(
+        &self,
+        buffer: &mut [u8],
+        frame_hertz: u16,
+        name: &[u8],
+        author: &[u8],
+        comment: &[u8],
+    ) -> Option<usize> {
+        let length = HEADER + name.len() + author.len() + comment.len() + 3;
+        let header = buffer.get_mut(..length)?;
+        header[..4].copy_from_slice(b"YM5!");
+        header[4..12].copy_from_slice(b"LeOnArD!");
+        header[12..16].copy_from_slice(&self.frame_count().to_be_bytes());
+        // No attributes, no digidrums.
+        header[16..22].fill(0);
+        header[22..26].copy_from_slice(&self.psg.master_clock().to_be_bytes());
+        header[26..28].copy_from_slice(&frame_hertz.to_be_bytes());
+        header[28..32].copy_from_slice(&self.loop_frame.unwrap_or(0).to_be_bytes());
+        // No extra data.
+        header[32..34].fill(0);
+        let mut position = HEADER;
+        for text in [name, author, comment] {
+            header[position..position + text.len()].copy_from_slice(text);
+            header[position + text.len()] = 0;
+            position += text.len() + 1;
+        }
+        Some(length)
+    }
+}
+
+impl<P: Psg> Psg for FrameRecorder<'_, P> {
+    type Error = P::Error;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), P::Error> {
+        self.psg.set_register_value(address, data)?;
+        if address == 0xD {
+            self.envelope_written = true;
+        }
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        self.psg.registers()
+    }
+
+    fn register_mask(&self, address: u8) -> u8 {
+        self.psg.register_mask(address)
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.psg.master_clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.psg.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.psg.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(feature = "formats-ym")]
+    use crate::frame_player::{FramePlayer, FrameSource};
+    use crate::test_support::FakePsg;
+    #[cfg(feature = "formats-ym")]
+    use crate::ym_file::YmSong;
+    use std::vec::Vec;
+
+    /// The chip's registers after each frame, and whether the envelope was
+    /// restarted in it.
+    fn states(psg: &FakePsg, writes: &[(u8, u8)]) -> ([u8; 14], bool) {
+        let mut state = [0; 14];
+        for (address, value) in state.iter_mut().enumerate() {
+            *value = psg.registers().value(address as u8);
+        }
+        state[0x7] = psg.registers().mixer();
+        (state, writes.iter().any(|&(address, _)| address == 0xD))
+    }
+
+    /// Records `frames` frames of writes from a little xorshift, including
+    /// repeated writes to R13.
+    fn record(
+        buffer: &mut [Frame],
+        frames: usize,
+    ) -> (FrameRecorder<'_, FakePsg>, Vec<([u8; 14], bool)>) {
+        let mut recorder = FrameRecorder::new(FakePsg::new(), buffer);
+        let mut state = 0x1234_5678u32;
+        let mut expected = Vec::new();
+        for index in 0..frames {
+            if index == 3 {
+                recorder.mark_loop();
+            }
+            for _ in 0..4 {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let address = (state % 14) as u8;
+                let value = (state >> 8) as u8;
+                match address {
+                    // Same shape every time, so only the write restarts it.
+                    0xD => recorder.set_register_value(0xD, 0x0E).unwrap(),
+                    // Keep the ports as inputs.
+                    0x7 => recorder.set_register_value(0x7, value & 0x3F).unwrap(),
+                    _ => recorder.set_register_value(address, value).unwrap(),
+                }
+            }
+            let writes = recorder.inner_mut().take_writes();
+            expected.push(states(recorder.inner(), &writes));
+            if recorder.capture_frame().is_err() {
+                expected.pop();
+            }
+        }
+        (recorder, expected)
+    }
+
+    #[cfg(feature = "formats-ym")]
+    #[test]
+    fn plays_back_as_it_was_written() {
+        let mut buffer = [[0; 16]; 40];
+        let (recorder, expected) = record(&mut buffer, 40);
+        assert!(expected.iter().any(|&(_, restarted)| restarted));
+        assert!(expected.iter().any(|&(_, restarted)| !restarted));
+        assert_eq!(recorder.loop_frame(), Some(3));
+
+        let mut file = Vec::from([0; 64]);
+        let length = recorder
+            .write_ym5_header(&mut file, 50, b"Tune", b"Me", b"")
+            .unwrap();
+        file.truncate(length);
+        file.extend_from_slice(recorder.frame_bytes());
+        file.extend_from_slice(YM_END);
+        let song = YmSong::new(&file).unwrap();
+        assert_eq!(song.frame_count(), 40);
+        assert_eq!(song.master_clock(), recorder.master_clock());
+        assert_eq!(song.loop_frame(), 3);
+
+        check(FramePlayer::new(recorder.frames()), &expected);
+        check(song.into_player(50), &expected);
+    }
+
+    #[cfg(feature = "formats-ym")]
+    fn check<S: FrameSource>(mut player: FramePlayer<S>, expected: &[([u8; 14], bool)]) {
+        let mut psg = FakePsg::new();
+        player.play();
+        for state in expected {
+            player.tick(&mut psg).unwrap();
+            let writes = psg.take_writes();
+            assert_eq!(states(&psg, &writes), *state);
+        }
+    }
+
+    #[test]
+    fn a_full_buffer_is_reported() {
+        let mut buffer = [[0; 16]; 5];
+        let (mut recorder, expected) = record(&mut buffer, 8);
+        assert!(recorder.is_full());
+        assert_eq!(recorder.frame_count(), 5);
+        assert_eq!(expected.len(), 5);
+        assert_eq!(recorder.capture_frame(), Err(FramesFull));
+        // Writes still reach the chip.
+        recorder.set_register_value(0x0, 0x42).unwrap();
+        assert_eq!(recorder.inner().writes, [(0x0, 0x42)]);
+        assert_eq!(
+            recorder.write_ym5_header(&mut [0; 36], 50, b"", b"", b""),
+            None
+        );
+        assert_eq!(
+            recorder.write_ym5_header(&mut [0; 37], 50, b"", b"", b""),
+            Some(37)
+        );
+    }
+}
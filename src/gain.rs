@@ -0,0 +1,222 @@
+//! Turning channels down by a set amount, for evening out a board whose
+//! channels come out at different strengths, or keeping a channel left to
+//! sound effects below the music.
+//!
+//! A [`GainPsg`] sits between everything that writes levels and the chip,
+//! so setters, instruments, frame players and the sound effect arbiter
+//! above it are all turned down alike. Each channel has a gain in percent,
+//! scaled as [`volume::scale_level_by_gain`] does, on a curve where 50%
+//! sounds half as loud.
+//!
+//! Only fixed levels are scaled. A channel on the envelope has no level
+//! to scale, the envelope's own ramp setting it, and moving it to a fixed
+//! level would lose the envelope's shape, so it plays at full strength.
+//! Periods, the mixer and the envelope pass through untouched.
+//!
+//! Its shadow registers hold the levels as asked for, not as written, so
+//! anything reading them back, as fades and the arbiter do, isn't turned
+//! down twice. [`GainPsg::set_gain`] rewrites the channel's level at once.
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::FrequencyPolicy;
+use crate::volume;
+use crate::{Channel, ChipKind};
+
+/// A [`Psg`] writing to `P` with each channel's fixed levels turned down by
+/// its gain.
+#[derive(Debug, Clone)]
+pub struct GainPsg<P> {
+    psg: P,
+    /// Percent, 0 to 100, per channel.
+    gains: [u8; 3],
+    /// What was asked for.
+    registers: Registers,
+}
+
+impl<P: Psg> GainPsg<P> {
+    /// `psg` at full gain on every channel, taking what it holds as asked
+    /// for.
+    pub fn new(psg: P) -> GainPsg<P> {
+        GainPsg {
+            registers: *psg.registers(),
+            psg,
+            gains: [100; 3],
+        }
+    }
+
+    /// Starts with each channel's gain, A first, as [`GainPsg::set_gain`]
+    /// takes it.
+    pub fn with_gains(mut self, gains: [u8; 3]) -> GainPsg<P> {
+        self.gains = gains.map(|gain| gain.min(100));
+        self
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.psg
+    }
+
+    /// The chip, bypassing the gains. Levels written here are taken as
+    /// asked for until the next level write or gain change.
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.psg
+    }
+
+    pub fn into_inner(self) -> P {
+        self.psg
+    }
+
+    pub fn gain(&self, channel: Channel) -> u8 {
+        self.gains[channel.index()]
+    }
+
+    /// Sets `channel`'s gain to `percent`, 100 and over being full, and
+    /// rewrites its level if it has a fixed one.
+    pub fn set_gain(&mut self, channel: Channel, percent: u8) -> Result<(), P::Error> {
+        self.gains[channel.index()] = percent.min(100);
+        let address = channel.level_register();
+        match self.registers.get(address) {
+            Some(value) => {
+                let value = self.scaled(address, value);
+                self.psg.update_register(address, value).map(|_| ())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// What goes to the chip for `data` at `address`.
+    fn scaled(&self, address: u8, data: u8) -> u8 {
+        match Channel::for_register(address) {
+            Some(channel) if address >= 0x8 && data & 0x10 == 0 => {
+                let gain = self.gains[channel.index()];
+                volume::scale_level_by_gain(data & 0xF, gain)
+            }
+            _ => data,
+        }
+    }
+}
+
+impl<P: Psg> Psg for GainPsg<P> {
+    type Error = P::Error;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), P::Error> {
+        self.psg
+            .set_register_value(address, self.scaled(address, data))?;
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.psg.master_clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.psg.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.psg.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::ChannelLevel;
+
+    #[test]
+    fn only_fixed_levels_are_turned_down() {
+        let mut psg = GainPsg::new(FakePsg::new()).with_gains([100, 50, 0]);
+        psg.set_channel_period(Channel::B, 0x123).unwrap();
+        psg.update_channel_level(Channel::A, ChannelLevel::Fixed(12))
+            .unwrap();
+        psg.update_channel_level(Channel::B, ChannelLevel::Fixed(12))
+            .unwrap();
+        psg.update_channel_level(Channel::C, ChannelLevel::Envelope)
+            .unwrap();
+        psg.set_tone_enabled(Channel::B, true).unwrap();
+        assert_eq!(
+            psg.inner_mut().take_writes(),
+            [
+                (0x2, 0x23),
+                (0x3, 0x01),
+                (0x8, 12),
+                (0x9, 9),
+                (0xA, 0x10),
+                (0x7, 0b11_1101)
+            ]
+        );
+        // The shadow has what was asked for, so asking again writes nothing.
+        assert_eq!(psg.registers().value(0x9), 12);
+        psg.update_channel_level(Channel::B, ChannelLevel::Fixed(12))
+            .unwrap();
+        assert!(psg.inner().writes.is_empty());
+    }
+
+    #[test]
+    fn gain_changes_rewrite_levels_at_once() {
+        let mut psg = GainPsg::new(FakePsg::new());
+        psg.update_channel_level(Channel::C, ChannelLevel::Fixed(15))
+            .unwrap();
+        psg.update_channel_level(Channel::A, ChannelLevel::Envelope)
+            .unwrap();
+        psg.inner_mut().take_writes();
+        psg.set_gain(Channel::C, 75).unwrap();
+        assert_eq!(psg.inner_mut().take_writes(), [(0xA, 14)]);
+        psg.set_gain(Channel::C, 200).unwrap();
+        assert_eq!(psg.gain(Channel::C), 100);
+        assert_eq!(psg.inner_mut().take_writes(), [(0xA, 15)]);
+        // Nothing to rewrite on the envelope, nor on a channel never set.
+        psg.set_gain(Channel::A, 10).unwrap();
+        psg.set_gain(Channel::B, 10).unwrap();
+        assert!(psg.inner().writes.is_empty());
+    }
+
+    #[cfg(feature = "player")]
+    #[test]
+    fn frames_play_at_the_gain() {
+        use crate::frame_player::FramePlayer;
+        use crate::registers::Frame;
+
+        static SONG: [Frame; 1] = [[
+            0x1C, 0x01, 0x34, 0x02, 0xFF, 0x0F, 0x10, 0x38, 15, 8, 0x10, 0x40, 0x00, 0x0E, 0, 0,
+        ]];
+        let mut psg = GainPsg::new(FakePsg::new()).with_gains([50, 50, 50]);
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        player.tick(&mut psg).unwrap();
+        let chip = psg.inner().registers();
+        // 15 and 8 three steps down; C is on the envelope.
+        assert_eq!(
+            [0x8, 0x9, 0xA].map(|address| chip.value(address)),
+            [12, 5, 0x10]
+        );
+        for address in [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0xB, 0xC, 0xD] {
+            assert_eq!(chip.value(address), SONG[0][address as usize], "R{address}");
+        }
+        assert_eq!(psg.registers().value(0x8), 15);
+    }
+
+    #[cfg(feature = "sfx")]
+    #[test]
+    fn sound_effects_are_turned_down_too() {
+        use crate::arbiter::{SfxArbiter, SfxPolicy};
+        use crate::sfx::Sfx;
+
+        let gained = GainPsg::new(FakePsg::new()).with_gains([100, 100, 25]);
+        let mut arbiter = SfxArbiter::new(gained, SfxPolicy::StealMusic);
+        arbiter.set_music_channel(Channel::A, Some(1));
+        arbiter.set_music_channel(Channel::B, Some(1));
+        assert_eq!(arbiter.trigger_sfx(Sfx::Coin, 5), Some(Channel::C));
+        arbiter.tick().unwrap();
+        let asked = arbiter.psg().registers().value(0xA);
+        let written = arbiter.psg().inner().registers().value(0xA);
+        assert!(asked > 0);
+        assert_eq!(written, asked.saturating_sub(7));
+    }
+}
@@ -0,0 +1,272 @@
+//! GD3 tags: the track, game and author names in VGM files.
+//!
+//! A tag is `Gd3 `, a version and the byte length of the rest, little-endian,
+//! then eleven strings in UTF-16LE, each ending in a NUL. Most come in pairs,
+//! English then Japanese, either of which may be empty. Nothing is copied:
+//! each field is a [`Utf16`] borrowed from the file, which can be read
+//! [a character at a time](Utf16::chars) or
+//! [written out as UTF-8](Utf16::write_utf8) into a buffer of your own.
+
+const MAGIC: &[u8; 4] = b"Gd3 ";
+const HEADER: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Gd3Error {
+    /// The offset points past the end of the data.
+    BadOffset,
+    /// There is no `Gd3 ` header at the offset.
+    NotGd3,
+    /// The header runs past the end of the data.
+    Truncated,
+}
+
+/// The fields of a tag, in the order they are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Gd3Field {
+    TrackName,
+    TrackNameJapanese,
+    GameName,
+    GameNameJapanese,
+    SystemName,
+    SystemNameJapanese,
+    Author,
+    AuthorJapanese,
+    ReleaseDate,
+    /// Who made the VGM file.
+    Ripper,
+    Notes,
+}
+
+impl Gd3Field {
+    pub const ALL: [Gd3Field; 11] = [
+        Gd3Field::TrackName,
+        Gd3Field::TrackNameJapanese,
+        Gd3Field::GameName,
+        Gd3Field::GameNameJapanese,
+        Gd3Field::SystemName,
+        Gd3Field::SystemNameJapanese,
+        Gd3Field::Author,
+        Gd3Field::AuthorJapanese,
+        Gd3Field::ReleaseDate,
+        Gd3Field::Ripper,
+        Gd3Field::Notes,
+    ];
+}
+
+/// A UTF-16LE string borrowed from a tag, without its NUL.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Utf16<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Utf16<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Utf16<'a> {
+        Utf16 { bytes }
+    }
+
+    /// The raw little-endian bytes. A stray odd byte at the end is ignored
+    /// everywhere else.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.len() < 2
+    }
+
+    pub fn units(&self) -> impl Iterator<Item = u16> + 'a {
+        self.bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+    }
+
+    /// The characters, with any unpaired surrogate read as U+FFFD.
+    pub fn chars(&self) -> impl Iterator<Item = char> + 'a {
+        char::decode_utf16(self.units()).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    /// Writes the string into `buffer` as UTF-8. If it doesn't all fit, as
+    /// many whole characters as do are written and returned as the error.
+    pub fn write_utf8<'b>(&self, buffer: &'b mut [u8]) -> Result<&'b str, &'b str> {
+        let mut length = 0;
+        let mut truncated = false;
+        for c in self.chars() {
+            let Some(room) = buffer.get_mut(length..length + c.len_utf8()) else {
+                truncated = true;
+                break;
+            };
+            c.encode_utf8(room);
+            length += c.len_utf8();
+        }
+        // Only whole characters were written.
+        let text = core::str::from_utf8(&buffer[..length]).unwrap_or_default();
+        if truncated {
+            Err(text)
+        } else {
+            Ok(text)
+        }
+    }
+}
+
+/// A GD3 tag's fields.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Gd3<'a> {
+    version: u32,
+    fields: [Utf16<'a>; 11],
+}
+
+impl<'a> Gd3<'a> {
+    /// Reads the tag at `offset` into `data`, the start of the VGM file, as
+    /// [`VgmSong::gd3_offset`](crate::vgm::VgmSong::gd3_offset) gives it.
+    ///
+    /// A tag is taken as it is: a length running past the data is cut short,
+    /// a last string without its NUL runs to the end, and fields missing
+    /// from the end are empty.
+    pub fn parse(data: &'a [u8], offset: usize) -> Result<Gd3<'a>, Gd3Error> {
+        let tag = data.get(offset..).ok_or(Gd3Error::BadOffset)?;
+        let header = tag.get(..HEADER).ok_or(Gd3Error::Truncated)?;
+        if header[..4] != *MAGIC {
+            return Err(Gd3Error::NotGd3);
+        }
+        let number = |at: usize| {
+            u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+        };
+        let length = (number(8) as usize).min(tag.len() - HEADER);
+        let mut rest = &tag[HEADER..HEADER + length];
+        let mut fields = [Utf16::default(); 11];
+        for field in &mut fields {
+            let end = rest
+                .chunks_exact(2)
+                .position(|pair| pair == [0, 0])
+                .map_or(rest.len(), |units| units * 2);
+            *field = Utf16::new(&rest[..end]);
+            rest = &rest[(end + 2).min(rest.len())..];
+        }
+        Ok(Gd3 {
+            version: number(4),
+            fields,
+        })
+    }
+
+    /// The tag version in BCD: `0x100` for 1.00.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn field(&self, field: Gd3Field) -> Utf16<'a> {
+        self.fields[field as usize]
+    }
+
+    /// The English track name.
+    pub fn track_name(&self) -> Utf16<'a> {
+        self.field(Gd3Field::TrackName)
+    }
+
+    /// The English game or album name.
+    pub fn game_name(&self) -> Utf16<'a> {
+        self.field(Gd3Field::GameName)
+    }
+
+    /// The English name of the machine the music comes from.
+    pub fn system_name(&self) -> Utf16<'a> {
+        self.field(Gd3Field::SystemName)
+    }
+
+    /// The English author name.
+    pub fn author(&self) -> Utf16<'a> {
+        self.field(Gd3Field::Author)
+    }
+
+    pub fn release_date(&self) -> Utf16<'a> {
+        self.field(Gd3Field::ReleaseDate)
+    }
+
+    pub fn ripper(&self) -> Utf16<'a> {
+        self.field(Gd3Field::Ripper)
+    }
+
+    pub fn notes(&self) -> Utf16<'a> {
+        self.field(Gd3Field::Notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+    use std::vec::Vec;
+
+    fn tag(fields: &[&str]) -> Vec<u8> {
+        let mut strings = Vec::new();
+        for field in fields {
+            for unit in field.encode_utf16().chain([0]) {
+                strings.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+        let mut tag = Vec::from(*MAGIC);
+        tag.extend_from_slice(&0x100u32.to_le_bytes());
+        tag.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        tag.extend_from_slice(&strings);
+        tag
+    }
+
+    const FIELDS: [&str; 11] = [
+        "Café ☕",
+        "カフェ",
+        "",
+        "",
+        "ZX Spectrum 128",
+        "",
+        "Me 𝄞",
+        "",
+        "1987",
+        "ripper",
+        "",
+    ];
+
+    #[test]
+    fn reads_every_field_through_any_character() {
+        let mut data = Vec::from(&b"data before"[..]);
+        data.extend(tag(&FIELDS));
+        let gd3 = Gd3::parse(&data, 11).unwrap();
+        assert_eq!(gd3.version(), 0x100);
+        for (field, expected) in Gd3Field::ALL.into_iter().zip(FIELDS) {
+            assert_eq!(gd3.field(field).chars().collect::<String>(), expected);
+            assert_eq!(gd3.field(field).is_empty(), expected.is_empty());
+        }
+        assert_eq!(gd3.author().chars().count(), 4);
+        assert!(gd3.game_name().is_empty());
+
+        // UTF-8 is written whole characters at a time.
+        let mut buffer = [0; 16];
+        assert_eq!(gd3.track_name().write_utf8(&mut buffer), Ok("Café ☕"));
+        assert_eq!(gd3.track_name().write_utf8(&mut buffer[..7]), Err("Café "));
+        assert_eq!(gd3.author().write_utf8(&mut buffer[..6]), Err("Me "));
+        assert_eq!(gd3.author().write_utf8(&mut buffer[..7]), Ok("Me 𝄞"));
+        // A lone surrogate comes out as a replacement.
+        let lone = Utf16::new(&[0x3D, 0xD8, b'!', 0]);
+        assert_eq!(lone.chars().collect::<String>(), "\u{FFFD}!");
+    }
+
+    #[test]
+    fn takes_broken_tags_without_panicking() {
+        let data = tag(&FIELDS);
+        assert_eq!(Gd3::parse(&data, data.len() + 1), Err(Gd3Error::BadOffset));
+        assert_eq!(Gd3::parse(&data, data.len()), Err(Gd3Error::Truncated));
+        assert_eq!(Gd3::parse(&data, 1), Err(Gd3Error::NotGd3));
+        // Cut anywhere, the fields that are there still read.
+        for length in HEADER..data.len() {
+            let gd3 = Gd3::parse(&data[..length], 0).unwrap();
+            let name: String = gd3.track_name().chars().collect();
+            assert!(FIELDS[0].starts_with(name.as_str()));
+            assert!(gd3.notes().is_empty());
+        }
+        // A tag with fewer than eleven fields.
+        let data = tag(&["Only"]);
+        let gd3 = Gd3::parse(&data, 0).unwrap();
+        assert_eq!(gd3.track_name().chars().collect::<String>(), "Only");
+        assert!(gd3.ripper().is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! One-shot slides from one pitch to another.
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning;
+use crate::Channel;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GlissandoMode {
+    /// Step through each semitone in between, the classic arcade sweep.
+    Chromatic,
+    /// Interpolate the tone period directly for a continuous slide.
+    Smooth,
+}
+
+/// Slides a channel from `from` to `to` over `ticks` calls to
+/// [`Glissando::tick`]. The first tick sounds `from` and tick number `ticks`
+/// sounds `to`, after which the glissando is done and further ticks do
+/// nothing. Level and mixer are left to the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glissando {
+    channel: Channel,
+    from: Pitch,
+    to: Pitch,
+    ticks: u16,
+    elapsed: u16,
+    mode: GlissandoMode,
+    done: bool,
+}
+
+impl Glissando {
+    pub const fn new(
+        channel: Channel,
+        from: Pitch,
+        to: Pitch,
+        ticks: u16,
+        mode: GlissandoMode,
+    ) -> Glissando {
+        Glissando {
+            channel,
+            from,
+            to,
+            ticks,
+            elapsed: 0,
+            mode,
+            done: false,
+        }
+    }
+
+    /// Writes the next step of the slide. Returns whether the slide has
+    /// reached its target.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        if self.done {
+            return Ok(true);
+        }
+        let period = self.period_at(psg.master_clock(), self.elapsed);
+        psg.set_channel_period(self.channel, period)?;
+        if self.elapsed >= self.ticks {
+            self.done = true;
+        } else {
+            self.elapsed += 1;
+        }
+        Ok(self.done)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Tone period the slide sounds on tick `step`.
+    pub fn period_at(&self, clock: u32, step: u16) -> u16 {
+        let step = step.min(self.ticks) as i32;
+        let ticks = self.ticks.max(1) as i32;
+        match self.mode {
+            GlissandoMode::Chromatic => {
+                let from = self.from.midi() as i32;
+                let span = self.to.midi() as i32 - from;
+                let offset = div_round(span * step, ticks);
+                let pitch = Pitch::from_midi((from + offset) as u8).unwrap_or(self.to);
+                tuning::fold_pitch_period(clock, pitch).period
+            }
+            GlissandoMode::Smooth => {
+                let from = tuning::fold_pitch_period(clock, self.from).period as i32;
+                let to = tuning::fold_pitch_period(clock, self.to).period as i32;
+                (from + div_round((to - from) * step, ticks)) as u16
+            }
+        }
+    }
+}
+
+/// Division rounding half away from zero.
+pub(crate) fn div_round(numerator: i32, denominator: i32) -> i32 {
+    if numerator >= 0 {
+        (numerator + denominator / 2) / denominator
+    } else {
+        (numerator - denominator / 2) / denominator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    fn run(glissando: &mut Glissando, psg: &mut FakePsg) -> Vec<u16> {
+        let mut periods = Vec::new();
+        while !glissando.is_done() {
+            glissando.tick(psg).unwrap();
+            periods.push(psg.registers().tone_period(Channel::A));
+        }
+        periods
+    }
+
+    #[test]
+    fn chromatic_steps_through_semitones() {
+        let mut psg = FakePsg::new();
+        let from = Pitch::new(Note::C, 4);
+        let to = Pitch::new(Note::E, 4);
+        let mut glissando = Glissando::new(Channel::A, from, to, 4, GlissandoMode::Chromatic);
+        let expected: Vec<u16> = [60, 61, 62, 63, 64]
+            .map(|midi| tuning::pitch_period(2_000_000, Pitch::from_midi(midi).unwrap()) as u16)
+            .to_vec();
+        assert_eq!(run(&mut glissando, &mut psg), expected);
+        assert!(glissando.tick(&mut psg).unwrap());
+    }
+
+    #[test]
+    fn smooth_slide_across_rough_byte_boundaries() {
+        let mut psg = FakePsg::new();
+        // C2 is period 0x777 and C4 is 0x1DE at 2 MHz, so the rough byte
+        // passes through 7, 6, 5, 4, 3, 2 and 1.
+        let from = Pitch::new(Note::C, 2);
+        let to = Pitch::new(Note::C, 4);
+        let mut glissando = Glissando::new(Channel::A, from, to, 12, GlissandoMode::Smooth);
+        let mut previous = None;
+        for step in 0..=12u16 {
+            let done = glissando.tick(&mut psg).unwrap();
+            assert_eq!(done, step == 12);
+            let writes = psg.take_writes();
+            let period = psg.registers().tone_period(Channel::A);
+            let expected = 0x777 - (0x777 - 0x1DE) * step / 12;
+            assert!(period.abs_diff(expected) <= 1, "step {step}: {period:#x}");
+            if let Some(previous) = previous {
+                assert!(period < previous);
+                // Replay the individual byte writes: the transient between
+                // them must never be sharper than the new period.
+                let mut transient = previous;
+                for (register, value) in writes {
+                    transient = match register {
+                        0 => transient & 0xF00 | value as u16,
+                        1 => transient & 0x0FF | (value as u16) << 8,
+                        _ => transient,
+                    };
+                    assert!(transient >= period, "step {step}: {transient:#x}");
+                }
+            }
+            previous = Some(period);
+        }
+        assert_eq!(psg.registers().tone_period(Channel::A), 0x1DE);
+    }
+}
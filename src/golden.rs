@@ -0,0 +1,414 @@
+//! Golden tests: what a player writes over a run of ticks, as text, checked
+//! against a copy kept with the tests.
+//!
+//! [`record`] ticks something against a [`RecordingBus`] and makes a
+//! [`Trace`] of it, a line a tick naming each register written and the
+//! value, such as `  12: A_FINE=1C MIXER=3E`, and `-` for a tick with no
+//! writes. [`assert_golden`] compares that with the file kept for it and
+//! panics with the lines that differ; run with `YM2149_UPDATE_GOLDEN` set
+//! to write the file afresh instead, and review the change in the diff.
+//!
+//! ```no_run
+//! use ym2149::golden::{assert_golden, record};
+//! use ym2149::recording_bus::RecordingBus;
+//! use ym2149::{Channel, ChannelLevel, Psg};
+//!
+//! // A fade on A, a level a tick.
+//! let mut level = 15;
+//! let trace = record(&mut RecordingBus::<64>::new(), 100, |bus| {
+//!     level -= 1;
+//!     bus.update_channel_level(Channel::A, ChannelLevel::Fixed(level))
+//!         .unwrap();
+//!     level == 0
+//! });
+//! assert_golden("tests/golden/fade.trace", &trace);
+//! ```
+
+extern crate std;
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::string::String;
+
+use crate::recording_bus::RecordingBus;
+use crate::registers::REGISTER_NAMES;
+
+/// The environment variable that has [`assert_golden`] write its file.
+pub const UPDATE_VARIABLE: &str = "YM2149_UPDATE_GOLDEN";
+
+/// Differences [`diff`] lists before leaving the rest as a count.
+const MAX_LISTED: usize = 20;
+
+/// The writes made each tick, a line a tick.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Trace {
+    text: String,
+    ticks: u32,
+}
+
+impl Trace {
+    pub const fn new() -> Trace {
+        Trace {
+            text: String::new(),
+            ticks: 0,
+        }
+    }
+
+    /// Adds a line for the writes `bus` has logged since the last, and
+    /// empties its log for the next.
+    pub fn capture<const N: usize>(&mut self, bus: &mut RecordingBus<N>) {
+        let _ = write!(self.text, "{:4}:", self.ticks);
+        let mut any = false;
+        for (register, value) in bus.writes() {
+            let name = REGISTER_NAMES.get(register as usize).copied();
+            let _ = match name {
+                Some(name) => write!(self.text, " {name}={value:02X}"),
+                None => write!(self.text, " R{register}={value:02X}"),
+            };
+            any = true;
+        }
+        if !any {
+            self.text.push_str(" -");
+        }
+        self.text.push('\n');
+        self.ticks += 1;
+        bus.clear();
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Calls `tick` with `bus` up to `ticks` times, or until it returns `true`
+/// for having finished, capturing a line a call.
+pub fn record<const N: usize>(
+    bus: &mut RecordingBus<N>,
+    ticks: u32,
+    mut tick: impl FnMut(&mut RecordingBus<N>) -> bool,
+) -> Trace {
+    let mut trace = Trace::new();
+    bus.clear();
+    for _ in 0..ticks {
+        let finished = tick(bus);
+        trace.capture(bus);
+        if finished {
+            break;
+        }
+    }
+    trace
+}
+
+/// The lines of `actual` that differ from `expected`, each as the expected
+/// line after `-` and the actual one after `+`, or `None` if they match.
+/// Lines are compared in place, a tick being a line.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    let (mut expected, mut actual) = (expected.lines(), actual.lines());
+    let mut out = String::new();
+    let mut differences = 0;
+    loop {
+        let (old, new) = (expected.next(), actual.next());
+        if old.is_none() && new.is_none() {
+            break;
+        }
+        if old == new {
+            continue;
+        }
+        differences += 1;
+        if differences > MAX_LISTED {
+            continue;
+        }
+        if let Some(old) = old {
+            let _ = writeln!(out, "-{old}");
+        }
+        if let Some(new) = new {
+            let _ = writeln!(out, "+{new}");
+        }
+    }
+    if differences > MAX_LISTED {
+        let _ = writeln!(out, "... and {} more", differences - MAX_LISTED);
+    }
+    (differences > 0).then_some(out)
+}
+
+/// Panics unless `trace` matches the one kept at `path`, or with
+/// [`UPDATE_VARIABLE`] set, writes it there.
+#[track_caller]
+pub fn assert_golden(path: impl AsRef<Path>, trace: &Trace) {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_VARIABLE).is_some() {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory).expect("couldn't make the golden directory");
+        }
+        std::fs::write(path, trace.as_str()).expect("couldn't write the golden trace");
+        return;
+    }
+    let expected = std::fs::read_to_string(path).unwrap_or_default();
+    if let Some(diff) = diff(&expected, trace.as_str()) {
+        panic!(
+            "{} differs from the trace recorded; run with {UPDATE_VARIABLE}=1 to \
+             accept it:\n{diff}",
+            path.display()
+        );
+    }
+}
+
+#[cfg(all(test, feature = "player", feature = "sfx"))]
+mod tests {
+    use super::*;
+    use crate::crossfade::Crossfade;
+    use crate::frame_player::{Frame, FramePlayer, R13_UNCHANGED};
+    use crate::instrument::Instrument;
+    use crate::mute::ChannelMask;
+    use crate::psg::Psg;
+    use crate::registers::Registers;
+    use crate::sequencer::{Cell, Command, OrderEntry, Pattern, Row, Sequencer, Song};
+    use crate::sfx::{Sfx, SfxPlayer};
+    use crate::{Channel, Note, Pitch};
+
+    /// Where the traces kept for this crate's own tests live.
+    fn golden(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(name)
+    }
+
+    #[test]
+    fn traces_name_the_registers_written() {
+        let mut bus = RecordingBus::<16>::new();
+        let trace = record(&mut bus, 3, |bus| {
+            if bus.registers().is_written(0x8) {
+                return false;
+            }
+            bus.set_channel_period(Channel::A, 0x11C).unwrap();
+            bus.set_tone_enabled(Channel::A, true).unwrap();
+            bus.set_register_value(0x8, 15).unwrap();
+            false
+        });
+        assert_eq!(
+            trace.as_str(),
+            "   0: A_FINE=1C A_ROUGH=01 MIXER=3E A_LEVEL=0F\n   1: -\n   2: -\n"
+        );
+        assert_eq!(trace.ticks(), 3);
+    }
+
+    #[test]
+    fn diffs_list_the_ticks_that_changed() {
+        let expected = "   0: A_LEVEL=0F\n   1: -\n   2: -\n";
+        assert_eq!(diff(expected, expected), None);
+        assert_eq!(
+            diff(expected, "   0: A_LEVEL=0E\n   1: -\n").as_deref(),
+            Some("-   0: A_LEVEL=0F\n+   0: A_LEVEL=0E\n-   2: -\n")
+        );
+    }
+
+    #[test]
+    fn frame_player() {
+        const fn frame(period: u8, level: u8, shape: u8) -> Frame {
+            let mut frame = [0; 16];
+            frame[0x0] = period;
+            frame[0x2] = period / 2;
+            frame[0x7] = 0b0011_1100;
+            frame[0x8] = level;
+            frame[0x9] = 0x10;
+            frame[0xB] = 0x40;
+            frame[0xD] = shape;
+            frame
+        }
+        static SONG: [Frame; 4] = [
+            frame(0xA0, 15, 0x0E),
+            frame(0xA0, 12, R13_UNCHANGED),
+            frame(0x90, 9, R13_UNCHANGED),
+            frame(0x80, 6, 0x0A),
+        ];
+        let mut player = FramePlayer::new(&SONG[..]).with_loop(1);
+        player.play();
+        let trace = record(&mut RecordingBus::<64>::new(), 8, |bus| {
+            player.tick(bus).unwrap();
+            false
+        });
+        assert_golden(golden("frame_player.trace"), &trace);
+    }
+
+    #[test]
+    fn crossfade() {
+        // A and B on tones, B with noise too, and C on the envelope...
+        const FROM: Frame = [
+            0x1C,
+            0x01,
+            0xE0,
+            0x00,
+            0x40,
+            0x00,
+            0x08,
+            0b0011_0100,
+            15,
+            12,
+            0x10,
+            0x00,
+            0x02,
+            0x0E,
+            0,
+            0,
+        ];
+        // ...to A a fifth up and quieter, B silent, and C on a tone at a
+        // fixed level, the envelope restarted as a decay.
+        const TO: Frame = [
+            0xBE,
+            0x00,
+            0xE0,
+            0x00,
+            0x80,
+            0x00,
+            0x10,
+            0b0011_1010,
+            6,
+            0,
+            9,
+            0x00,
+            0x04,
+            0x09,
+            0,
+            0,
+        ];
+        let mut bus = RecordingBus::<64>::new();
+        bus.write_frame(&FROM).unwrap();
+        let mut fade = Crossfade::towards(Registers::from_values(TO), 8).with_glide(ChannelMask::A);
+        let trace = record(&mut bus, 10, |bus| fade.tick(bus).unwrap());
+        assert_golden(golden("crossfade.trace"), &trace);
+    }
+
+    #[test]
+    fn sequencer_effects() {
+        const C4: Pitch = Pitch::new(Note::C, 4);
+        const G4: Pitch = Pitch::new(Note::G, 4);
+        static EFFECTS: [Pattern; 1] = [Pattern::new(&[
+            Row::new(
+                Cell::note(C4).instrument(0).command(Command::Speed(4)),
+                Cell::note(G4)
+                    .instrument(0)
+                    .command(Command::Arpeggio(4, 7)),
+                Cell::note(C4)
+                    .instrument(0)
+                    .command(Command::Vibrato { speed: 8, depth: 4 }),
+            ),
+            Row::new(
+                Cell::EMPTY.command(Command::SlideUp(6)),
+                Cell::note(C4).command(Command::TonePortamento(20)),
+                Cell::EMPTY.command(Command::VolumeSlide { up: 0, down: 3 }),
+            ),
+            Row::new(Cell::OFF, Cell::OFF, Cell::OFF),
+        ])];
+        static SONG: Song = Song::new(&EFFECTS, &[OrderEntry::new(0)]);
+        static BANK: [Instrument; 1] = [Instrument::DEFAULT];
+        let mut sequencer = Sequencer::new(&SONG, &BANK, 50);
+        let trace = record(&mut RecordingBus::<128>::new(), 16, |bus| {
+            sequencer.tick(bus).unwrap()
+        });
+        assert_golden(golden("sequencer_effects.trace"), &trace);
+    }
+
+    #[test]
+    fn sfx_presets() {
+        for (sfx, name) in [
+            (Sfx::Coin, "sfx_coin.trace"),
+            (Sfx::Explosion, "sfx_explosion.trace"),
+        ] {
+            let mut player = SfxPlayer::new();
+            player.trigger(sfx, Channel::B);
+            let trace = record(&mut RecordingBus::<64>::new(), 200, |bus| {
+                player.tick(bus).unwrap()
+            });
+            assert_golden(golden(name), &trace);
+        }
+    }
+
+    #[cfg(feature = "effects")]
+    #[test]
+    fn metronome() {
+        use crate::metronome::Metronome;
+
+        // 97 beats a minute isn't a whole number of 50 Hz ticks.
+        let mut metronome = Metronome::new(97, 3);
+        metronome.start();
+        let trace = record(&mut RecordingBus::<64>::new(), 500, |bus| {
+            metronome.tick(bus).unwrap();
+            false
+        });
+        assert_golden(golden("metronome.trace"), &trace);
+    }
+
+    #[cfg(feature = "effects")]
+    #[test]
+    fn sid_voice() {
+        use crate::sid::SidVoice;
+
+        // 440.5 Hz at 30% from a 4 kHz timer, which it doesn't divide.
+        let mut voice = SidVoice::new(Channel::C, 440_500, 14, 3).with_duty(30);
+        voice.set_tick_rate(4_000);
+        let trace = record(&mut RecordingBus::<16>::new(), 120, |bus| {
+            voice.tick_hi_res(bus).unwrap();
+            false
+        });
+        assert_golden(golden("sid_voice.trace"), &trace);
+    }
+
+    /// `samples` as a trace, sixteen to a line, so an emulator's output can
+    /// be kept like the writes.
+    #[cfg(feature = "emulator")]
+    fn samples(samples: &[i16]) -> Trace {
+        let mut trace = Trace::new();
+        for line in samples.chunks(16) {
+            let _ = write!(trace.text, "{:4}:", trace.ticks);
+            for sample in line {
+                let _ = write!(trace.text, " {:04X}", *sample as u16);
+            }
+            trace.text.push('\n');
+            trace.ticks += 1;
+        }
+        trace
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn emulator() {
+        use crate::emulator::{StereoMode, Ym2149Emulator};
+        use crate::ChannelLevel;
+
+        // A tone on A, noise and a tone on B, and C on a repeating envelope
+        // it starts partway through.
+        let mut emulator = Ym2149Emulator::new();
+        emulator.set_channel_period(Channel::A, 0x11C).unwrap();
+        emulator.set_channel_period(Channel::B, 0x0D5).unwrap();
+        emulator.set_register_value(0x6, 0x05).unwrap();
+        emulator.set_register_value(0x7, 0b0010_1100).unwrap();
+        emulator
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        emulator
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(9))
+            .unwrap();
+        emulator
+            .update_channel_level(Channel::C, ChannelLevel::Envelope)
+            .unwrap();
+        emulator.set_register_value(0xB, 0x03).unwrap();
+        emulator.set_register_value(0xD, 0x0E).unwrap();
+        let mut mono = [0; 512];
+        emulator.render(&mut mono[..256], 44_100);
+        // A held decay, at a rate that isn't a whole number of steps.
+        emulator.set_register_value(0xD, 0x09).unwrap();
+        emulator.render(&mut mono[256..], 22_050);
+        let mut stereo = [[0; 2]; 128];
+        emulator.set_stereo_mode(StereoMode::StereoAcb);
+        emulator.set_dc_filter(true);
+        emulator.set_register_value(0xD, 0x0A).unwrap();
+        emulator.render_stereo(&mut stereo, 48_000);
+        let mut all = mono.to_vec();
+        all.extend(stereo.iter().flatten());
+        assert_golden(golden("emulator.trace"), &samples(&all));
+    }
+}
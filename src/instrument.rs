@@ -0,0 +1,491 @@
+//! Tracker-style instruments: short per-tick tables for volume, pitch and
+//! noise, played note by note on one channel.
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::storage::{Static, Storage};
+use crate::tuning::{self, MAX_NOISE_PERIOD};
+use crate::{Channel, ChannelLevel};
+
+#[cfg(feature = "alloc")]
+use crate::storage::Owned;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// One value per tick, with an optional point to loop back to once the end
+/// is reached.
+///
+/// Without a loop (or with a loop point past the end) the last value holds.
+/// An empty table has no value at all; each user of a table says what it
+/// falls back to.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "S::Slice<T>: serde::Serialize",
+        deserialize = "S::Slice<T>: serde::Deserialize<'de>"
+    ))
+)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Table<T: 'static, S: Storage = Static> {
+    values: S::Slice<T>,
+    loop_start: Option<u16>,
+}
+
+impl<T: Copy> Table<T> {
+    pub const EMPTY: Table<T> = Table::new(&[]);
+
+    pub const fn new(values: &'static [T]) -> Table<T> {
+        Table {
+            values,
+            loop_start: None,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Copy> Table<T, Owned> {
+    pub fn from_vec(values: Vec<T>) -> Table<T, Owned> {
+        Table {
+            values,
+            loop_start: None,
+        }
+    }
+
+    /// The table with its values moved out of the heap for good.
+    pub fn leak(self) -> Table<T> {
+        Table {
+            values: self.values.leak(),
+            loop_start: self.loop_start,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Copy> From<Table<T>> for Table<T, Owned> {
+    fn from(table: Table<T>) -> Table<T, Owned> {
+        Table {
+            values: table.values.to_vec(),
+            loop_start: table.loop_start,
+        }
+    }
+}
+
+impl<T: Copy, S: Storage> Table<T, S> {
+    /// Repeats from `values[start]` after the last value.
+    pub const fn looping(mut self, start: u16) -> Table<T, S> {
+        self.loop_start = Some(start);
+        self
+    }
+
+    pub fn values(&self) -> &[T] {
+        self.values.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values().is_empty()
+    }
+
+    fn loop_start(&self) -> Option<usize> {
+        self.loop_start
+            .map(usize::from)
+            .filter(|&start| start < self.values().len())
+    }
+
+    /// Whether `position` lies beyond a table that doesn't loop.
+    pub fn is_finished(&self, position: u16) -> bool {
+        self.loop_start().is_none() && position as usize >= self.values().len()
+    }
+
+    /// The value for tick `position`.
+    pub fn at(&self, position: u16) -> Option<T> {
+        let values = self.values();
+        let len = values.len();
+        let position = position as usize;
+        if position < len {
+            return Some(values[position]);
+        }
+        match self.loop_start() {
+            Some(start) => Some(values[start + (position - len) % (len - start)]),
+            None => values.last().copied(),
+        }
+    }
+}
+
+/// How a note sounds, tick by tick.
+///
+/// - `volume`: levels 0..=15 while the note is held; empty means a steady 15.
+/// - `release`: levels played after note-off. The note ends when a
+///   non-looping release runs out, or straight away if it's empty.
+/// - `pitch`: semitone offsets from the note, for arpeggios and drum sweeps;
+///   empty means no offset.
+/// - `noise`: 0 for no noise, otherwise the noise period to play; empty means
+///   no noise. The noise period register is shared by all channels.
+/// - `tone`: whether the tone generator is used at all.
+///
+/// Everything is `&'static` and const-constructible, so instruments can sit
+/// in flash as `const` items, unless `Owned`.
+///
+/// With the `serde` feature any instrument can be written out, its tables
+/// as their values, but only an `Owned` one read back, as there is nowhere
+/// for `&'static` tables to come from. A device without `alloc` keeps its
+/// instruments in a `&'static [Instrument]` of its own and sends an index
+/// into it instead, as `sfx::Sfx` stands for its tables.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "Table<u8, S>: serde::Serialize, Table<i8, S>: serde::Serialize",
+        deserialize = "Table<u8, S>: serde::Deserialize<'de>, Table<i8, S>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Instrument<S: Storage = Static> {
+    pub volume: Table<u8, S>,
+    pub release: Table<u8, S>,
+    pub pitch: Table<i8, S>,
+    pub noise: Table<u8, S>,
+    pub tone: bool,
+}
+
+// Written out, as derives would only ask for `S: Clone` and the like.
+impl<S: Storage> Clone for Instrument<S>
+where
+    Table<u8, S>: Clone,
+    Table<i8, S>: Clone,
+{
+    fn clone(&self) -> Instrument<S> {
+        Instrument {
+            volume: self.volume.clone(),
+            release: self.release.clone(),
+            pitch: self.pitch.clone(),
+            noise: self.noise.clone(),
+            tone: self.tone,
+        }
+    }
+}
+
+impl<S: Storage> Copy for Instrument<S>
+where
+    Table<u8, S>: Copy,
+    Table<i8, S>: Copy,
+{
+}
+
+impl<S: Storage> core::fmt::Debug for Instrument<S>
+where
+    Table<u8, S>: core::fmt::Debug,
+    Table<i8, S>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Instrument")
+            .field("volume", &self.volume)
+            .field("release", &self.release)
+            .field("pitch", &self.pitch)
+            .field("noise", &self.noise)
+            .field("tone", &self.tone)
+            .finish()
+    }
+}
+
+impl<S: Storage> PartialEq for Instrument<S>
+where
+    Table<u8, S>: PartialEq,
+    Table<i8, S>: PartialEq,
+{
+    fn eq(&self, other: &Instrument<S>) -> bool {
+        self.volume == other.volume
+            && self.release == other.release
+            && self.pitch == other.pitch
+            && self.noise == other.noise
+            && self.tone == other.tone
+    }
+}
+
+impl<S: Storage> Eq for Instrument<S>
+where
+    Table<u8, S>: Eq,
+    Table<i8, S>: Eq,
+{
+}
+
+impl Instrument {
+    /// A steady tone at full volume that stops at note-off.
+    pub const DEFAULT: Instrument = Instrument {
+        volume: Table::EMPTY,
+        release: Table::EMPTY,
+        pitch: Table::EMPTY,
+        noise: Table::EMPTY,
+        tone: true,
+    };
+}
+
+#[cfg(feature = "alloc")]
+impl Instrument<Owned> {
+    /// The instrument with its tables moved out of the heap for good.
+    pub fn leak(self) -> Instrument {
+        Instrument {
+            volume: self.volume.leak(),
+            release: self.release.leak(),
+            pitch: self.pitch.leak(),
+            noise: self.noise.leak(),
+            tone: self.tone,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<Instrument> for Instrument<Owned> {
+    fn from(instrument: Instrument) -> Instrument<Owned> {
+        Instrument {
+            volume: instrument.volume.into(),
+            release: instrument.release.into(),
+            pitch: instrument.pitch.into(),
+            noise: instrument.noise.into(),
+            tone: instrument.tone,
+        }
+    }
+}
+
+/// Example instruments, also handy as starting points.
+pub mod presets {
+    use super::{Instrument, Table};
+
+    /// A bright lead with a short swell and a quick fade after note-off.
+    pub const LEAD: Instrument = Instrument {
+        volume: Table::new(&[11, 13, 14, 13, 12]).looping(4),
+        release: Table::new(&[10, 8, 6, 4, 2, 0]),
+        ..Instrument::DEFAULT
+    };
+
+    /// A plucked bass that pops an octave up on the first tick.
+    pub const BASS: Instrument = Instrument {
+        volume: Table::new(&[15, 14, 13, 12, 11, 11, 10]),
+        release: Table::new(&[7, 3, 0]),
+        pitch: Table::new(&[12, 0]),
+        ..Instrument::DEFAULT
+    };
+
+    /// A snare: a falling tone thump under a burst of noise.
+    pub const SNARE: Instrument = Instrument {
+        volume: Table::new(&[15, 14, 12, 10, 8, 6, 4, 3, 2, 1, 0]),
+        release: Table::EMPTY,
+        pitch: Table::new(&[0, -4, -8]),
+        noise: Table::new(&[0, 5, 6, 7, 8]),
+        tone: true,
+    };
+}
+
+/// Everything an instrument asks of its channel for one tick.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InstrumentFrame {
+    pub pitch: Pitch,
+    pub level: u8,
+    pub tone: bool,
+    /// The noise period, if noise is on.
+    pub noise: Option<u8>,
+}
+
+/// Plays an [`Instrument`] on one channel.
+///
+/// [`InstrumentPlayer::next_frame`] works out each tick without touching the
+/// chip, so a sequencer or MIDI layer can adjust the frame (velocity,
+/// transposition, effects) before writing it; [`InstrumentPlayer::tick`]
+/// writes it directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrumentPlayer {
+    instrument: &'static Instrument,
+    note: Option<Pitch>,
+    position: u16,
+    released_at: Option<u16>,
+}
+
+impl InstrumentPlayer {
+    pub const fn new(instrument: &'static Instrument) -> InstrumentPlayer {
+        InstrumentPlayer {
+            instrument,
+            note: None,
+            position: 0,
+            released_at: None,
+        }
+    }
+
+    pub fn instrument(&self) -> &'static Instrument {
+        self.instrument
+    }
+
+    /// Takes effect from the next note.
+    pub fn set_instrument(&mut self, instrument: &'static Instrument) {
+        self.instrument = instrument;
+    }
+
+    /// Starts every table from the beginning on `pitch`.
+    pub fn note_on(&mut self, pitch: Pitch) {
+        self.note = Some(pitch);
+        self.position = 0;
+        self.released_at = None;
+    }
+
+    /// Switches to the release table.
+    pub fn note_off(&mut self) {
+        if self.note.is_some() && self.released_at.is_none() {
+            self.released_at = Some(self.position);
+        }
+    }
+
+    /// Ends the note at once, skipping its release.
+    pub fn stop(&mut self) {
+        self.note = None;
+    }
+
+    /// Whether a note is sounding, held or releasing.
+    pub fn is_playing(&self) -> bool {
+        self.note.is_some()
+    }
+
+    /// The frame for this tick, or `None` once the note has ended.
+    pub fn next_frame(&mut self) -> Option<InstrumentFrame> {
+        self.next_frame_with(self.instrument)
+    }
+
+    /// As [`InstrumentPlayer::next_frame`], playing `instrument` rather than
+    /// the player's own: for a player of instruments that aren't `'static`,
+    /// held in a bank it looks them up in.
+    pub fn next_frame_with<S: Storage>(
+        &mut self,
+        instrument: &Instrument<S>,
+    ) -> Option<InstrumentFrame> {
+        let pitch = self.note?;
+        let level = match self.released_at {
+            None => instrument.volume.at(self.position).unwrap_or(15),
+            Some(released_at) => {
+                let since = self.position - released_at;
+                if instrument.release.is_finished(since) {
+                    self.note = None;
+                    return None;
+                }
+                instrument.release.at(since).unwrap_or(0)
+            }
+        };
+        let offset = instrument.pitch.at(self.position).unwrap_or(0);
+        let noise = match instrument.noise.at(self.position).unwrap_or(0) {
+            0 => None,
+            period => Some(period.min(MAX_NOISE_PERIOD)),
+        };
+        self.position = self.position.saturating_add(1);
+        Some(InstrumentFrame {
+            pitch: pitch.transpose_folded(offset),
+            level: level.min(15),
+            tone: instrument.tone,
+            noise,
+        })
+    }
+
+    /// Writes this tick's frame to `channel`, or silences it once the note
+    /// has ended. Unchanged registers are skipped by the register cache.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P, channel: Channel) -> Result<(), P::Error> {
+        match self.next_frame() {
+            Some(frame) => apply_frame(psg, channel, &frame),
+            None => psg.update_channel_level(channel, ChannelLevel::Fixed(0)),
+        }
+    }
+}
+
+/// Writes an [`InstrumentFrame`] to `channel`.
+pub fn apply_frame<P: Psg>(
+    psg: &mut P,
+    channel: Channel,
+    frame: &InstrumentFrame,
+) -> Result<(), P::Error> {
+    if frame.tone {
+        let period = tuning::fold_pitch_period(psg.master_clock(), frame.pitch).period;
+        psg.set_channel_period(channel, period)?;
+    }
+    if let Some(period) = frame.noise {
+        psg.update_register(0x6, period)?;
+    }
+    psg.set_tone_enabled(channel, frame.tone)?;
+    psg.set_noise_enabled(channel, frame.noise.is_some())?;
+    psg.update_channel_level(channel, ChannelLevel::Fixed(frame.level))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    #[test]
+    fn table_loops_holds_and_handles_bad_loop_points() {
+        let looping = Table::new(&[1u8, 2, 3, 4]).looping(2);
+        let values: Vec<_> = (0..8).map(|i| looping.at(i).unwrap()).collect();
+        assert_eq!(values, [1, 2, 3, 4, 3, 4, 3, 4]);
+
+        let held = Table::new(&[1u8, 2]);
+        assert_eq!(held.at(5), Some(2));
+        assert!(held.is_finished(2));
+        assert_eq!(Table::new(&[1u8, 2]).looping(7).at(5), Some(2));
+        assert_eq!(Table::<u8>::EMPTY.at(0), None);
+    }
+
+    #[test]
+    fn release_runs_then_the_note_ends() {
+        static PLUCK: Instrument = Instrument {
+            volume: Table::new(&[15, 12]).looping(1),
+            release: Table::new(&[6, 3]),
+            ..Instrument::DEFAULT
+        };
+        let mut player = InstrumentPlayer::new(&PLUCK);
+        player.note_on(Pitch::new(Note::A, 4));
+        let mut levels = Vec::new();
+        for tick in 0..6 {
+            if tick == 3 {
+                player.note_off();
+            }
+            levels.push(player.next_frame().map(|frame| frame.level));
+        }
+        assert_eq!(
+            levels,
+            [Some(15), Some(12), Some(12), Some(6), Some(3), None]
+        );
+        assert!(!player.is_playing());
+    }
+
+    #[test]
+    fn empty_tables_fall_back() {
+        static PLAIN: Instrument = Instrument::DEFAULT;
+        let mut player = InstrumentPlayer::new(&PLAIN);
+        let c4 = Pitch::new(Note::C, 4);
+        player.note_on(c4);
+        let frame = player.next_frame().unwrap();
+        assert_eq!(
+            frame,
+            InstrumentFrame {
+                pitch: c4,
+                level: 15,
+                tone: true,
+                noise: None,
+            }
+        );
+        player.note_off();
+        assert_eq!(player.next_frame(), None);
+    }
+
+    #[test]
+    fn snare_drives_pitch_noise_and_mixer() {
+        let mut psg = FakePsg::new();
+        let mut player = InstrumentPlayer::new(&presets::SNARE);
+        player.note_on(Pitch::new(Note::A, 3));
+        player.tick(&mut psg, Channel::C).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::C), 568);
+        assert_eq!(psg.registers().mixer() & 0b0010_0100, 0b0010_0000);
+        player.tick(&mut psg, Channel::C).unwrap();
+        assert_eq!(psg.registers().value(0x6), 5);
+        assert_eq!(psg.registers().mixer() & 0b0010_0100, 0);
+        assert_eq!(psg.registers().value(0xA), 14);
+        // F3, four semitones down.
+        assert_eq!(psg.registers().tone_period(Channel::C), 716);
+    }
+}
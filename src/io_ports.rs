@@ -0,0 +1,105 @@
+//! Which I/O ports the chip's package bonds out, as a type, so that using a
+//! port the chip doesn't have is a compile error rather than a write to
+//! nothing.
+//!
+//! The 40-pin YM2149 and AY-3-8910 have both ports, [`BothPorts`]; the
+//! 28-pin AY-3-8912 only port A, [`PortAOnly`]; the 24-pin AY-3-8913
+//! neither, [`NoPorts`]. The registers of a port
+//! that isn't there, and its direction bit in the mixer, are never written:
+//! the driver drops writes to them, so a dump that writes them plays as it
+//! would on the chip.
+//!
+//! ```
+//! use embedded_hal::delay::DelayNs;
+//! use embedded_hal::digital::OutputPin;
+//! use ym2149::Ay8912;
+//!
+//! fn light<P: OutputPin, D: DelayNs>(ay: &mut Ay8912<P, D>) {
+//!     let _ = ay.set_port_a_data(0xFF);
+//! }
+//! ```
+//!
+//! Port B's setter isn't there to call:
+//!
+//! ```compile_fail
+//! use embedded_hal::delay::DelayNs;
+//! use embedded_hal::digital::OutputPin;
+//! use ym2149::{Ay8912, IoPort};
+//!
+//! fn light<P: OutputPin, D: DelayNs>(ay: &mut Ay8912<P, D>) {
+//!     let _ = ay.set_io_port_data(IoPort::B, 0xFF);
+//! }
+//! ```
+//!
+//! and on an AY-3-8913, neither is port A's:
+//!
+//! ```compile_fail
+//! use embedded_hal::delay::DelayNs;
+//! use embedded_hal::digital::OutputPin;
+//! use ym2149::Ay8913;
+//!
+//! fn light<P: OutputPin, D: DelayNs>(ay: &mut Ay8913<P, D>) {
+//!     let _ = ay.set_port_a_data(0xFF);
+//! }
+//! ```
+
+use crate::chip_profile;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// The ports a package has.
+pub trait IoPorts: sealed::Sealed {
+    /// How many: port A, then port B, as in its
+    /// [`ChipProfile`](crate::chip_profile::ChipProfile).
+    const PORTS: u8;
+    /// How many registers there are: R14 is port A's and R15 port B's, so
+    /// the registers are `0..REGISTERS`.
+    const REGISTERS: u8 = chip_profile::register_count(Self::PORTS);
+    /// The mixer's direction bits for the ports there are.
+    const DIRECTION_BITS: u8 = chip_profile::direction_bits(Self::PORTS);
+
+    /// Whether `address` is a register the package has.
+    fn has_register(address: u8) -> bool {
+        address < Self::REGISTERS
+    }
+}
+
+/// A package with port A, R14.
+pub trait HasPortA: IoPorts {}
+
+/// A package with port B, R15, as well.
+pub trait HasPortB: HasPortA {}
+
+/// Both ports, as on the YM2149 and the AY-3-8910.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct BothPorts;
+
+/// Port A alone, as on the AY-3-8912.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct PortAOnly;
+
+/// No ports, as on the AY-3-8913.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct NoPorts;
+
+impl sealed::Sealed for BothPorts {}
+impl sealed::Sealed for PortAOnly {}
+impl sealed::Sealed for NoPorts {}
+
+impl IoPorts for BothPorts {
+    const PORTS: u8 = 2;
+}
+
+impl IoPorts for PortAOnly {
+    const PORTS: u8 = 1;
+}
+
+impl IoPorts for NoPorts {
+    const PORTS: u8 = 0;
+}
+
+impl HasPortA for BothPorts {}
+impl HasPortB for BothPorts {}
+impl HasPortA for PortAOnly {}
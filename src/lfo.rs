@@ -0,0 +1,123 @@
+//! Integer low-frequency oscillators shared by the modulation effects.
+
+/// Full-scale LFO output; [`Lfo::value`] ranges over `-LFO_SCALE..=LFO_SCALE`.
+pub const LFO_SCALE: i32 = 1024;
+
+/// `sin` over a quarter turn in 16 steps, scaled to [`LFO_SCALE`].
+const QUARTER_SINE: [i32; 17] = [
+    0, 100, 200, 297, 392, 483, 569, 650, 724, 792, 851, 903, 946, 980, 1004, 1019, 1024,
+];
+
+/// Angle units per LFO cycle.
+const TURN: u32 = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LfoWaveform {
+    Triangle,
+    /// A sine approximated from a 17-entry quarter-wave table.
+    Sine,
+    /// High for the first half of the cycle, low for the second.
+    Square,
+}
+
+impl LfoWaveform {
+    /// Output at `angle` out of a 1024-unit turn. Every waveform starts at
+    /// (or, for the square, just after) zero heading upwards.
+    pub fn at(self, angle: u32) -> i32 {
+        let angle = angle % TURN;
+        let quarter = TURN / 4;
+        let within = angle % quarter;
+        // Distance into the rising quarter, folding the falling quarters back.
+        let rising = match angle / quarter {
+            0 | 2 => within,
+            _ => quarter - within,
+        };
+        let sign = if angle < TURN / 2 { 1 } else { -1 };
+        let magnitude = match self {
+            LfoWaveform::Triangle => (rising as i32 * LFO_SCALE) / quarter as i32,
+            LfoWaveform::Sine => {
+                let step = quarter / 16;
+                let index = (rising / step) as usize;
+                let fraction = (rising % step) as i32;
+                let low = QUARTER_SINE[index];
+                let high = QUARTER_SINE[(index + 1).min(16)];
+                low + (high - low) * fraction / step as i32
+            }
+            LfoWaveform::Square => LFO_SCALE,
+        };
+        sign * magnitude
+    }
+}
+
+/// An oscillator advanced one step per call to [`Lfo::step`], completing a
+/// cycle every `period` steps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Lfo {
+    waveform: LfoWaveform,
+    period: u16,
+    position: u16,
+}
+
+impl Lfo {
+    /// A `period` of 0 is treated as 1.
+    pub const fn new(waveform: LfoWaveform, period: u16) -> Lfo {
+        Lfo {
+            waveform,
+            period,
+            position: 0,
+        }
+    }
+
+    /// The output at the current position.
+    pub fn value(&self) -> i32 {
+        let period = self.period.max(1) as u32;
+        self.waveform.at(self.position as u32 * TURN / period)
+    }
+
+    /// Returns the current output, then moves on one step.
+    pub fn step(&mut self) -> i32 {
+        let value = self.value();
+        self.position = (self.position + 1) % self.period.max(1);
+        value
+    }
+
+    /// Back to the start of the cycle.
+    pub fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    pub fn set_period(&mut self, period: u16) {
+        self.period = period;
+        self.position %= period.max(1);
+    }
+
+    pub fn set_waveform(&mut self, waveform: LfoWaveform) {
+        self.waveform = waveform;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms_hit_their_extremes_at_quarter_turns() {
+        for waveform in [LfoWaveform::Triangle, LfoWaveform::Sine] {
+            assert_eq!(waveform.at(0), 0);
+            assert_eq!(waveform.at(256), LFO_SCALE);
+            assert_eq!(waveform.at(512), 0);
+            assert_eq!(waveform.at(768), -LFO_SCALE);
+        }
+        assert_eq!(LfoWaveform::Triangle.at(128), 512);
+        assert_eq!(LfoWaveform::Sine.at(128), 724);
+        assert_eq!(LfoWaveform::Square.at(511), LFO_SCALE);
+        assert_eq!(LfoWaveform::Square.at(512), -LFO_SCALE);
+    }
+
+    #[test]
+    fn lfo_wraps_every_period() {
+        let mut lfo = Lfo::new(LfoWaveform::Triangle, 4);
+        let cycle: [i32; 8] = core::array::from_fn(|_| lfo.step());
+        assert_eq!(cycle, [0, 1024, 0, -1024, 0, 1024, 0, -1024]);
+    }
+}
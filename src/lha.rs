@@ -0,0 +1,722 @@
+//! LHA archives, in which nearly every `.ym` file comes, unpacked on the
+//! device.
+//!
+//! An archive is a run of entries, each a header followed by the entry's
+//! packed data, ending with a zero byte. All three header levels are read.
+//! Entries packed with `-lh5-`, or the older `-lh4-`, are LZ77 with an 8 KiB
+//! window and Huffman coding in blocks; `-lh0-` entries are stored as they
+//! are. Other methods, such as `-lh6-` and `-lh7-` with their larger
+//! windows, are listed but can't be unpacked.
+//!
+//! [`Entry::decompress_into`] unpacks an entry into a buffer, which is what
+//! `ym_file::YmSong` needs. A [`Decoder`] instead unpacks on demand, a few
+//! bytes at a time, in about 9 KiB of its own, so data can be unpacked just
+//! ahead of where it is used. Either way the data is checked against its
+//! CRC once all of it has been unpacked.
+//!
+//! ```
+//! # fn main() -> Result<(), ym2149::lha::LhaError> {
+//! # let archive: &[u8] = &[0];
+//! let mut buffer = [0; 4096];
+//! for entry in ym2149::lha::entries(archive) {
+//!     let entry = entry?;
+//!     if entry.name().ends_with(b".ym") {
+//!         let length = entry.decompress_into(&mut buffer)?;
+//!         let _song = &buffer[..length];
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use core::fmt;
+
+/// The sliding window: 8 KiB for `-lh5-`, which also covers `-lh4-`'s 4 KiB.
+const WINDOW: usize = 1 << 13;
+/// Literal bytes, then match lengths 3 to 256.
+const CODES: usize = 510;
+/// Offset codes, by bit length: 0 to 13.
+const OFFSETS: usize = 14;
+/// Codes for the code lengths of the literal and length tree.
+const TEMP_CODES: usize = 19;
+const LONGEST: usize = 16;
+const MIN_MATCH: u16 = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LhaError {
+    /// The header runs past the end of the data, or an entry's packed data
+    /// does.
+    Truncated,
+    /// A header level other than 0, 1 or 2, or a header that doesn't add up.
+    BadHeader,
+    /// A level 0 or 1 header whose checksum is wrong.
+    BadChecksum,
+    /// Packed with a method that can't be unpacked here.
+    UnsupportedMethod([u8; 5]),
+    /// The packed data doesn't decode.
+    Corrupt,
+    /// The unpacked data doesn't match the CRC in the header.
+    BadCrc,
+    /// The buffer given is smaller than the unpacked entry.
+    BufferTooSmall,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Method {
+    Stored,
+    Lh4,
+    Lh5,
+}
+
+/// CRC-16 as LHA uses it: polynomial 0x8005, reflected, starting at 0.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn le16(bytes: &[u8], at: usize) -> Result<u16, LhaError> {
+    let bytes = bytes.get(at..at + 2).ok_or(LhaError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn le32(bytes: &[u8], at: usize) -> Result<u32, LhaError> {
+    let bytes = bytes.get(at..at + 4).ok_or(LhaError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// One file in an archive.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    name: &'a [u8],
+    method: [u8; 5],
+    level: u8,
+    packed: &'a [u8],
+    size: u32,
+    crc: u16,
+}
+
+impl fmt::Debug for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("name", &self.name)
+            .field("method", &self.method)
+            .field("level", &self.level)
+            .field("packed", &self.packed.len())
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// Walks extended headers starting with one of `next` bytes at `position`,
+/// taking the file name from any that has it. Returns the position after
+/// them.
+fn extended_headers<'a>(
+    data: &'a [u8],
+    mut position: usize,
+    mut next: u16,
+    name: &mut &'a [u8],
+) -> Result<usize, LhaError> {
+    while next != 0 {
+        let size = next as usize;
+        // A type byte, then the data, then the size of the next one.
+        if size < 3 {
+            return Err(LhaError::BadHeader);
+        }
+        let header = data
+            .get(position..position + size)
+            .ok_or(LhaError::Truncated)?;
+        if header[0] == 0x01 {
+            *name = &header[1..size - 2];
+        }
+        next = le16(header, size - 2)?;
+        position += size;
+    }
+    Ok(position)
+}
+
+impl<'a> Entry<'a> {
+    /// Reads the entry whose header starts at `start`, returning it and where
+    /// the next one starts.
+    fn read(data: &'a [u8], start: usize) -> Result<(Entry<'a>, usize), LhaError> {
+        let header = &data[start..];
+        let level = *header.get(20).ok_or(LhaError::Truncated)?;
+        let mut method = [0; 5];
+        method.copy_from_slice(&header[2..7]);
+        let mut packed = le32(header, 7)? as usize;
+        let size = le32(header, 11)?;
+        let (name, crc, data_start) = match level {
+            0 | 1 => {
+                let length = header[0] as usize + 2;
+                let base = header.get(..length).ok_or(LhaError::Truncated)?;
+                let sum = base[2..]
+                    .iter()
+                    .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+                if sum != base[1] {
+                    return Err(LhaError::BadChecksum);
+                }
+                let name_length = *base.get(21).ok_or(LhaError::BadHeader)? as usize;
+                let mut name = base.get(22..22 + name_length).ok_or(LhaError::BadHeader)?;
+                let crc = le16(base, 22 + name_length).map_err(|_| LhaError::BadHeader)?;
+                let mut data_start = start + length;
+                if level == 1 {
+                    // The packed size counts the extended headers too.
+                    let next = le16(base, length - 2)?;
+                    let end = extended_headers(data, data_start, next, &mut name)?;
+                    packed = packed
+                        .checked_sub(end - data_start)
+                        .ok_or(LhaError::BadHeader)?;
+                    data_start = end;
+                }
+                (name, crc, data_start)
+            }
+            2 => {
+                let length = le16(header, 0)? as usize;
+                let base = header.get(..length).ok_or(LhaError::Truncated)?;
+                let crc = le16(base, 21).map_err(|_| LhaError::BadHeader)?;
+                let next = le16(base, 24).map_err(|_| LhaError::BadHeader)?;
+                let mut name = &base[..0];
+                let end = extended_headers(base, 26, next, &mut name)?;
+                if end > length {
+                    return Err(LhaError::BadHeader);
+                }
+                (name, crc, start + length)
+            }
+            _ => return Err(LhaError::BadHeader),
+        };
+        let packed = data
+            .get(data_start..data_start + packed)
+            .ok_or(LhaError::Truncated)?;
+        let entry = Entry {
+            name,
+            method,
+            level,
+            packed,
+            size,
+            crc,
+        };
+        Ok((entry, data_start + packed.len()))
+    }
+
+    /// The file name as stored, usually ASCII.
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// The method as named in the header, such as `-lh5-`.
+    pub fn method_name(&self) -> [u8; 5] {
+        self.method
+    }
+
+    /// The method, if it can be unpacked.
+    pub fn method(&self) -> Result<Method, LhaError> {
+        match &self.method {
+            b"-lh0-" => Ok(Method::Stored),
+            b"-lh4-" => Ok(Method::Lh4),
+            b"-lh5-" => Ok(Method::Lh5),
+            _ => Err(LhaError::UnsupportedMethod(self.method)),
+        }
+    }
+
+    pub fn header_level(&self) -> u8 {
+        self.level
+    }
+
+    /// The unpacked size.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn packed_size(&self) -> u32 {
+        self.packed.len() as u32
+    }
+
+    /// A decoder unpacking the entry on demand.
+    pub fn decoder(&self) -> Result<Decoder<'a>, LhaError> {
+        Ok(Decoder {
+            method: self.method()?,
+            input: BitReader {
+                data: self.packed,
+                position: 0,
+                buffer: 0,
+                count: 0,
+            },
+            remaining: self.size,
+            crc: 0,
+            expected_crc: self.crc,
+            window: [b' '; WINDOW],
+            position: 0,
+            block_left: 0,
+            copy_left: 0,
+            copy_from: 0,
+            codes: Tree::EMPTY,
+            offsets: Tree::EMPTY,
+        })
+    }
+
+    /// Unpacks the whole entry to the start of `out` and checks it, returning
+    /// its length.
+    pub fn decompress_into(&self, out: &mut [u8]) -> Result<usize, LhaError> {
+        let out = out
+            .get_mut(..self.size as usize)
+            .ok_or(LhaError::BufferTooSmall)?;
+        let mut decoder = self.decoder()?;
+        let mut length = 0;
+        while length < out.len() {
+            length += decoder.read(&mut out[length..])?;
+        }
+        Ok(length)
+    }
+}
+
+/// The entries of an archive, in order, from [`entries`]. Stops after the
+/// first error.
+#[derive(Debug, Clone)]
+pub struct Entries<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+/// The entries of the archive in `data`.
+pub fn entries(data: &[u8]) -> Entries<'_> {
+    Entries { data, position: 0 }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, LhaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The archive ends at a zero byte, or just at the end of the data.
+        if self.data.get(self.position).is_none_or(|&byte| byte == 0) {
+            return None;
+        }
+        match Entry::read(self.data, self.position) {
+            Ok((entry, next)) => {
+                self.position = next;
+                Some(Ok(entry))
+            }
+            Err(error) => {
+                self.position = self.data.len();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Reads bits most significant first.
+#[derive(Debug, Clone)]
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u8) -> Result<u16, LhaError> {
+        while self.count < count {
+            let byte = *self.data.get(self.position).ok_or(LhaError::Corrupt)?;
+            self.position += 1;
+            self.buffer = self.buffer << 8 | byte as u32;
+            self.count += 8;
+        }
+        self.count -= count;
+        Ok((self.buffer >> self.count & ((1 << count) - 1)) as u16)
+    }
+
+    /// A code length: three bits, and if they are all set, one more for each
+    /// further set bit up to a clear one.
+    fn length(&mut self) -> Result<u8, LhaError> {
+        let mut length = self.bits(3)? as u8;
+        if length == 7 {
+            while self.bits(1)? == 1 {
+                length += 1;
+                if length as usize > LONGEST {
+                    return Err(LhaError::Corrupt);
+                }
+            }
+        }
+        Ok(length)
+    }
+}
+
+/// A canonical Huffman code over `N` symbols: shorter codes first, and in
+/// symbol order within a length.
+#[derive(Debug, Clone)]
+struct Tree<const N: usize> {
+    /// How many codes there are of each length.
+    counts: [u16; LONGEST + 1],
+    /// The symbols in code order.
+    symbols: [u16; N],
+    /// A tree of one symbol, which takes no bits.
+    single: Option<u16>,
+}
+
+impl<const N: usize> Tree<N> {
+    const EMPTY: Tree<N> = Tree {
+        counts: [0; LONGEST + 1],
+        symbols: [0; N],
+        single: None,
+    };
+
+    /// Reads the single symbol of a tree written as a zero count.
+    fn single(input: &mut BitReader<'_>, bits: u8) -> Result<Tree<N>, LhaError> {
+        let symbol = input.bits(bits)?;
+        if symbol as usize >= N {
+            return Err(LhaError::Corrupt);
+        }
+        Ok(Tree {
+            single: Some(symbol),
+            ..Tree::EMPTY
+        })
+    }
+
+    fn build(lengths: &[u8]) -> Result<Tree<N>, LhaError> {
+        let mut tree = Tree::EMPTY;
+        for &length in lengths {
+            tree.counts[length as usize] += 1;
+        }
+        tree.counts[0] = 0;
+        // More codes of a length than there is room for can't be decoded.
+        let mut room = 1i32;
+        let mut starts = [0; LONGEST + 1];
+        for length in 1..=LONGEST {
+            room = (room << 1) - tree.counts[length] as i32;
+            if room < 0 {
+                return Err(LhaError::Corrupt);
+            }
+            if length < LONGEST {
+                starts[length + 1] = starts[length] + tree.counts[length];
+            }
+        }
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                let start = &mut starts[length as usize];
+                tree.symbols[*start as usize] = symbol as u16;
+                *start += 1;
+            }
+        }
+        Ok(tree)
+    }
+
+    fn decode(&self, input: &mut BitReader<'_>) -> Result<u16, LhaError> {
+        if let Some(symbol) = self.single {
+            return Ok(symbol);
+        }
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..=LONGEST {
+            code |= input.bits(1)?;
+            let count = self.counts[length];
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(LhaError::Corrupt)
+    }
+}
+
+/// Unpacks an [`Entry`] a few bytes at a time, as asked.
+///
+/// About 9 KiB, most of it the window, so on a small device keep it in a
+/// `static` rather than on the stack.
+#[derive(Debug, Clone)]
+pub struct Decoder<'a> {
+    method: Method,
+    input: BitReader<'a>,
+    remaining: u32,
+    crc: u16,
+    expected_crc: u16,
+    window: [u8; WINDOW],
+    position: usize,
+    /// Codes left in the current block.
+    block_left: u16,
+    /// Bytes left to copy of the current match.
+    copy_left: u16,
+    copy_from: usize,
+    codes: Tree<CODES>,
+    offsets: Tree<OFFSETS>,
+}
+
+impl Decoder<'_> {
+    /// Bytes not yet unpacked.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Unpacks up to `out.len()` bytes into `out`, returning how many; 0 means
+    /// the entry is all unpacked. The call that unpacks the last byte fails
+    /// with [`LhaError::BadCrc`] if the data doesn't match its header.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, LhaError> {
+        let length = out.len().min(self.remaining as usize);
+        for byte in &mut out[..length] {
+            *byte = match self.method {
+                Method::Stored => self.input.bits(8)? as u8,
+                Method::Lh4 | Method::Lh5 => self.next_byte()?,
+            };
+        }
+        let out = &out[..length];
+        self.crc = crc16(self.crc, out);
+        self.remaining -= length as u32;
+        if length > 0 && self.remaining == 0 && self.crc != self.expected_crc {
+            return Err(LhaError::BadCrc);
+        }
+        Ok(length)
+    }
+
+    fn emit(&mut self, byte: u8) -> u8 {
+        self.window[self.position] = byte;
+        self.position = (self.position + 1) % WINDOW;
+        byte
+    }
+
+    fn next_byte(&mut self) -> Result<u8, LhaError> {
+        if self.copy_left == 0 {
+            while self.block_left == 0 {
+                self.read_block()?;
+            }
+            self.block_left -= 1;
+            let code = self.codes.decode(&mut self.input)?;
+            if code < 256 {
+                return Ok(self.emit(code as u8));
+            }
+            self.copy_left = code - 256 + MIN_MATCH;
+            let offset = match self.offsets.decode(&mut self.input)? {
+                bits @ 0..=1 => bits as usize,
+                bits => {
+                    let extra = self.input.bits(bits as u8 - 1)? as usize;
+                    (1 << (bits - 1)) + extra
+                }
+            };
+            self.copy_from = (self.position + WINDOW - offset - 1) % WINDOW;
+        }
+        self.copy_left -= 1;
+        let byte = self.window[self.copy_from];
+        self.copy_from = (self.copy_from + 1) % WINDOW;
+        Ok(self.emit(byte))
+    }
+
+    /// Reads a block's header: its length in codes, then its three trees.
+    fn read_block(&mut self) -> Result<(), LhaError> {
+        let input = &mut self.input;
+        self.block_left = input.bits(16)?;
+
+        // The code lengths of the main tree are themselves coded.
+        let count = input.bits(5)? as usize;
+        let temp = if count == 0 {
+            Tree::<TEMP_CODES>::single(input, 5)?
+        } else {
+            let mut lengths = [0; TEMP_CODES];
+            let slots = lengths.get_mut(..count).ok_or(LhaError::Corrupt)?;
+            let mut i = 0;
+            while i < slots.len() {
+                slots[i] = input.length()?;
+                i += 1;
+                // After the third, up to three more can be skipped as unused.
+                if i == 3 {
+                    i += input.bits(2)? as usize;
+                }
+            }
+            Tree::build(&lengths)?
+        };
+
+        let count = input.bits(9)? as usize;
+        self.codes = if count == 0 {
+            Tree::single(input, 9)?
+        } else {
+            let mut lengths = [0; CODES];
+            let slots = lengths.get_mut(..count).ok_or(LhaError::Corrupt)?;
+            let mut i = 0;
+            while i < slots.len() {
+                let unused = match temp.decode(input)? {
+                    0 => 1,
+                    1 => input.bits(4)? as usize + 3,
+                    2 => input.bits(9)? as usize + 20,
+                    length => {
+                        slots[i] = length as u8 - 2;
+                        0
+                    }
+                };
+                i += unused.max(1);
+                if i > slots.len() {
+                    return Err(LhaError::Corrupt);
+                }
+            }
+            Tree::build(&lengths)?
+        };
+
+        let count = input.bits(4)? as usize;
+        self.offsets = if count == 0 {
+            Tree::single(input, 4)?
+        } else {
+            let mut lengths = [0; OFFSETS];
+            let slots = lengths.get_mut(..count).ok_or(LhaError::Corrupt)?;
+            for slot in slots {
+                *slot = input.length()?;
+            }
+            Tree::build(&lengths)?
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(feature = "formats-ym")]
+    use crate::frame_player::{FrameSource, FrameStatus};
+    #[cfg(feature = "formats-ym")]
+    use crate::ym_file::YmSong;
+    use std::vec::Vec;
+
+    /// A small YM5 song packed with `-lh5-` under a level 0 header, a note
+    /// stored with `-lh0-` under level 1 with an extra extended header, and
+    /// a repetitive text packed under level 2.
+    #[rustfmt::skip]
+    static ARCHIVE: [u8; 230] = [
+    0x1D, 0xF7, 0x2D, 0x6C, 0x68, 0x35, 0x2D, 0x4E, 0x00, 0x00, 0x00, 0x6B,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x20, 0x00, 0x07, 0x74, 0x69,
+    0x6E, 0x79, 0x2E, 0x79, 0x6D, 0x51, 0x6A, 0x00, 0x46, 0x4C, 0x55, 0xA0,
+    0x33, 0x05, 0xF8, 0x98, 0x5D, 0xAD, 0x77, 0x25, 0x70, 0xE2, 0x36, 0xE2,
+    0x04, 0x24, 0xDB, 0x41, 0xA1, 0xDD, 0xBB, 0x46, 0x88, 0xF1, 0xA9, 0xB1,
+    0x99, 0x86, 0xCB, 0xE1, 0x36, 0xA3, 0x45, 0xEA, 0xDC, 0x1F, 0x92, 0x00,
+    0xA2, 0x16, 0x13, 0x15, 0xE3, 0x79, 0x67, 0xC3, 0x19, 0x3F, 0x9D, 0xEE,
+    0xC6, 0xF4, 0x25, 0x63, 0xD8, 0xE7, 0x2D, 0x42, 0xBF, 0xF1, 0x75, 0x71,
+    0xCD, 0x41, 0xCC, 0x9E, 0x04, 0x1E, 0x86, 0xD2, 0x7A, 0x64, 0xF9, 0xB5,
+    0x48, 0x22, 0x4E, 0x2D, 0x6C, 0x68, 0x30, 0x2D, 0x13, 0x00, 0x00, 0x00,
+    0x0E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x00, 0x20, 0x01, 0x09, 0x6E,
+    0x6F, 0x74, 0x65, 0x73, 0x2E, 0x74, 0x78, 0x74, 0xD7, 0x9C, 0x55, 0x05,
+    0x00, 0x40, 0x20, 0x00, 0x00, 0x00, 0x53, 0x74, 0x6F, 0x72, 0x65, 0x64,
+    0x20, 0x61, 0x73, 0x20, 0x69, 0x73, 0x2E, 0x0A, 0x26, 0x00, 0x2D, 0x6C,
+    0x68, 0x35, 0x2D, 0x1B, 0x00, 0x00, 0x00, 0x8A, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x20, 0x02, 0xFB, 0x75, 0x55, 0x0C, 0x00, 0x01, 0x6C,
+    0x6F, 0x6F, 0x70, 0x73, 0x2E, 0x74, 0x78, 0x74, 0x00, 0x00, 0x00, 0x0A,
+    0x3B, 0x4A, 0x4E, 0xD2, 0x83, 0x39, 0x1C, 0xB9, 0x08, 0xC3, 0xCA, 0x06,
+    0xB6, 0x92, 0x6C, 0xC0, 0x24, 0x92, 0x3C, 0xC7, 0xC6, 0x80, 0x58, 0xED,
+    0x80, 0x00,
+    ];
+
+    const LOOPS: &[u8] = b"la la la, la la la la; ";
+
+    fn entry(index: usize) -> Entry<'static> {
+        entries(&ARCHIVE).nth(index).unwrap().unwrap()
+    }
+
+    #[test]
+    fn lists_entries_at_every_header_level() {
+        let listed: Vec<_> = entries(&ARCHIVE)
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.name(),
+                    entry.method(),
+                    entry.header_level(),
+                    entry.size(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (&b"tiny.ym"[..], Ok(Method::Lh5), 0, 107),
+                (&b"notes.txt"[..], Ok(Method::Stored), 1, 14),
+                (&b"loops.txt"[..], Ok(Method::Lh5), 2, 138),
+            ]
+        );
+        assert!(entry(2).packed_size() < 30);
+    }
+
+    #[test]
+    fn unpacks_into_a_buffer() {
+        let mut buffer = [0; 200];
+        let length = entry(0).decompress_into(&mut buffer).unwrap();
+        #[cfg(feature = "formats-ym")]
+        {
+            let mut song = YmSong::new(&buffer[..length]).unwrap();
+            assert_eq!((song.frame_count(), song.name()), (3, &b"Tiny"[..]));
+            let mut frame = [0; 16];
+            assert_eq!(song.frame(2, &mut frame), Ok(FrameStatus::Ready));
+            assert_eq!((frame[0], frame[8]), (80, 13));
+        }
+        assert!(buffer[..length].starts_with(b"YM5!"));
+
+        let length = entry(1).decompress_into(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"Stored as is.\n");
+        let length = entry(2).decompress_into(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], LOOPS.repeat(6));
+        assert_eq!(
+            entry(2).decompress_into(&mut buffer[..100]),
+            Err(LhaError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn streams_in_small_pieces() {
+        for index in 0..3 {
+            let entry = entry(index);
+            let mut whole = [0; 200];
+            let length = entry.decompress_into(&mut whole).unwrap();
+            let mut decoder = entry.decoder().unwrap();
+            let mut streamed = Vec::new();
+            let mut piece = [0; 5];
+            loop {
+                let read = decoder.read(&mut piece).unwrap();
+                if read == 0 {
+                    break;
+                }
+                streamed.extend_from_slice(&piece[..read]);
+            }
+            assert_eq!(streamed, &whole[..length]);
+            assert_eq!(decoder.remaining(), 0);
+        }
+    }
+
+    #[test]
+    fn damage_is_caught() {
+        let mut buffer = [0; 200];
+        // A byte of stored data changed.
+        let mut archive = ARCHIVE;
+        let stored = ARCHIVE.windows(5).position(|w| w == b"Store").unwrap();
+        archive[stored] = b's';
+        let note = entries(&archive).nth(1).unwrap().unwrap();
+        assert_eq!(note.decompress_into(&mut buffer), Err(LhaError::BadCrc));
+        // Packed data changed: either it no longer decodes, or decodes wrong.
+        // The text's starts at 202 with its block length, which only needs
+        // to be long enough, and its last byte is partly padding.
+        for at in 204..ARCHIVE.len() - 2 {
+            let mut archive = ARCHIVE;
+            archive[at] ^= 0x10;
+            let loops = entries(&archive).nth(2).unwrap().unwrap();
+            assert!(loops.decompress_into(&mut buffer).is_err(), "{at}");
+        }
+        // A level 0 header with its checksum wrong.
+        let mut archive = ARCHIVE;
+        archive[1] ^= 1;
+        assert_eq!(entries(&archive).next(), Some(Err(LhaError::BadChecksum)));
+        // An unsupported method is listed, but can't be unpacked.
+        let mut archive = ARCHIVE;
+        archive[164 + 5] = b'7';
+        let loops = entries(&archive).nth(2).unwrap().unwrap();
+        assert_eq!(loops.method_name(), *b"-lh7-");
+        assert_eq!(
+            loops.decoder().map(|_| ()),
+            Err(LhaError::UnsupportedMethod(*b"-lh7-"))
+        );
+        // Cut short.
+        let cut: Vec<_> = entries(&ARCHIVE[..200]).collect();
+        assert_eq!(cut.len(), 3);
+        assert_eq!(cut[2], Err(LhaError::Truncated));
+    }
+}
@@ -1,12 +1,197 @@
 #![no_std]
+#![cfg_attr(
+    all(feature = "avr-progmem", target_arch = "avr"),
+    feature(asm_experimental_arch)
+)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "effects")]
+pub mod adsr;
+#[cfg(feature = "player")]
+pub mod analysis;
+#[cfg(feature = "sfx")]
+pub mod arbiter;
+#[cfg(feature = "effects")]
+pub mod arpeggiator;
+#[cfg(all(feature = "std", feature = "player"))]
+pub mod audio;
+pub mod ay8930;
+#[cfg(all(feature = "sfx", feature = "player"))]
+pub mod ayfx;
+pub mod channel_editor;
+pub mod chip_profile;
+#[cfg(feature = "music")]
+pub mod chord;
+pub mod clock_source;
+#[cfg(all(feature = "player", feature = "sfx"))]
+pub mod command_queue;
+#[cfg(feature = "player")]
+pub mod crossfade;
+#[cfg(feature = "player")]
+pub mod data_source;
+#[cfg(feature = "effects")]
+pub mod digidrum;
+#[cfg(all(feature = "player", feature = "sfx"))]
+pub mod drum_machine;
+#[cfg(feature = "effects")]
+pub mod echo;
+#[cfg(feature = "effects")]
+pub mod effect;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "player")]
+pub mod frame_player;
+#[cfg(feature = "player")]
+pub mod frame_queue;
+#[cfg(feature = "player")]
+pub mod frame_recorder;
+pub mod gain;
+#[cfg(feature = "formats-vgm")]
+pub mod gd3;
+#[cfg(feature = "music")]
+pub mod glissando;
+#[cfg(any(test, all(feature = "test-utils", feature = "std")))]
+pub mod golden;
+#[cfg(feature = "music")]
+pub mod instrument;
+pub mod io_ports;
+#[cfg(feature = "effects")]
+pub mod lfo;
+#[cfg(feature = "lha")]
+pub mod lha;
+#[cfg(feature = "player")]
+pub mod loop_policy;
+pub mod math;
+#[cfg(feature = "effects")]
+pub mod metronome;
+#[cfg(feature = "effects")]
+pub mod mfp;
+#[cfg(feature = "effects")]
+pub mod midi;
+#[cfg(feature = "effects")]
+pub mod midi_synth;
+#[cfg(feature = "player")]
+pub mod mml;
+#[cfg(feature = "player")]
+pub mod mute;
+#[cfg(feature = "effects")]
+pub mod noise_lfo;
+pub mod parse_error;
+#[cfg(feature = "player")]
+pub mod pattern_text;
+#[cfg(feature = "music")]
+pub mod pitch;
+#[cfg(feature = "player")]
+pub mod player_snapshot;
+#[cfg(feature = "player")]
+pub mod playlist;
+#[cfg(feature = "effects")]
+pub mod portamento;
+pub mod psg;
+#[cfg(feature = "formats-ym")]
+pub mod psg_file;
+pub mod psg_write;
+#[cfg(feature = "pt3")]
+pub mod pt3;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod recording_bus;
+pub mod registers;
+#[cfg(feature = "player")]
+pub mod rtttl;
+#[cfg(feature = "player")]
+pub mod scheduler;
+#[cfg(feature = "sd")]
+pub mod sd_stream;
+#[cfg(feature = "player")]
+pub mod sequencer;
+#[cfg(feature = "sfx")]
+pub mod sfx;
+#[cfg(feature = "effects")]
+pub mod sid;
+#[cfg(feature = "player")]
+pub mod slew;
+#[cfg(all(feature = "player", feature = "effects"))]
+pub mod smf;
+pub mod state;
+#[cfg(feature = "music")]
+pub mod storage;
+#[cfg(feature = "sfx")]
+pub mod sweep;
+#[cfg(feature = "effects")]
+pub mod sync_buzzer;
+#[cfg(feature = "player")]
+pub mod tee;
+#[cfg(feature = "player")]
+pub mod tempo_sync;
+#[cfg(feature = "music")]
+pub mod theory;
+#[cfg(feature = "music")]
+pub mod tone_code;
+#[cfg(feature = "effects")]
+pub mod tremolo;
+pub mod tuning;
+#[cfg(feature = "music")]
+pub mod unison;
+#[cfg(feature = "formats-vgm")]
+pub mod vgm;
+#[cfg(feature = "formats-vgm")]
+pub mod vgm_recorder;
+#[cfg(feature = "effects")]
+pub mod vibrato;
+#[cfg(feature = "effects")]
+pub mod voices;
+pub mod volume;
+#[cfg(all(feature = "std", feature = "player"))]
+pub mod wav;
+#[cfg(feature = "formats-ym")]
+pub mod ym_effects;
+#[cfg(all(feature = "std", feature = "formats-ym"))]
+pub mod ym_export;
+#[cfg(feature = "formats-ym")]
+pub mod ym_file;
+pub mod ymz;
+
+#[cfg(test)]
+mod driver_tests;
+#[cfg(test)]
+mod feature_tests;
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests;
+#[cfg(test)]
+mod test_support;
 
 use bitflags::bitflags;
+use core::marker::PhantomData;
+
+use clock_source::{ClockSource, MasterClock};
 use embedded_hal::{
     delay::DelayNs,
     digital::{OutputPin, PinState},
 };
 
+#[cfg(feature = "music")]
+pub use chord::{Chord, ChordType, Inversion, Voicing};
+pub use io_ports::{BothPorts, HasPortA, HasPortB, IoPorts, NoPorts, PortAOnly};
+#[cfg(feature = "music")]
+pub use pitch::{Note, Pitch};
+pub use psg::Psg;
+pub use psg_write::PsgWrite;
+pub use registers::{Register, Registers};
+#[cfg(feature = "music")]
+pub use theory::{Key, Scale};
+pub use tuning::{FrequencyError, FrequencyPolicy, OutOfRange, RangeError, Rounding};
+#[cfg(feature = "music")]
+pub use unison::Unison;
+
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct MixerSettings: u8 {
         const DisableToneA = 0b00000001;
         const DisableToneB = 0b00000010;
@@ -20,6 +205,8 @@ bitflags! {
 }
 
 bitflags! {
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
     pub struct EnvelopeShape: u8 {
         const Hold = 0b0001;
         const Alt = 0b0010;
@@ -28,17 +215,144 @@ bitflags! {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Channel {
     A,
     B,
     C,
 }
 
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::A, Channel::B, Channel::C];
+
+    /// Position of the channel, 0 for A through 2 for C.
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Fine and rough tone period registers.
+    pub const fn period_registers(self) -> (u8, u8) {
+        match self {
+            Channel::A => (0x0, 0x1),
+            Channel::B => (0x2, 0x3),
+            Channel::C => (0x4, 0x5),
+        }
+    }
+
+    pub const fn level_register(self) -> u8 {
+        match self {
+            Channel::A => 0x8,
+            Channel::B => 0x9,
+            Channel::C => 0xA,
+        }
+    }
+
+    /// The channel a tone period or level register belongs to, or `None`
+    /// for the shared registers.
+    pub const fn for_register(address: u8) -> Option<Channel> {
+        match address {
+            0x0 | 0x1 | 0x8 => Some(Channel::A),
+            0x2 | 0x3 | 0x9 => Some(Channel::B),
+            0x4 | 0x5 | 0xA => Some(Channel::C),
+            _ => None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ChannelLevel {
     Fixed(u8),
     Envelope,
 }
 
+impl ChannelLevel {
+    /// The value to write to a level register.
+    pub const fn register_value(self) -> u8 {
+        match self {
+            ChannelLevel::Fixed(level) => level & 0b1111,
+            ChannelLevel::Envelope => 0b10000,
+        }
+    }
+}
+
+/// Which of the family the chip is. They share a register layout, but
+/// differ in the details:
+///
+/// |               | YM2149                           | AY-3-8910                     | YMZ294 / YMZ284            |
+/// |---------------|----------------------------------|-------------------------------|----------------------------|
+/// | Envelope      | 32 steps                         | 16 steps, each twice as long  | 32 steps                   |
+/// | Fixed levels  | 16, on every other envelope step | 16, the same as the envelope's | as the YM2149              |
+/// | Level curve   | [`YM2149_DAC`](volume::YM2149_DAC) | [`AY8910_DAC`](volume::AY8910_DAC) | [`YM2149_DAC`](volume::YM2149_DAC) |
+/// | Clock pin     | SEL, halving the clock when low  | none                          | 4/6, halving it for the faster input |
+/// | Bus           | BDIR and BC1                     | BDIR and BC1                  | A0, /WR and /CS, in [`ymz`] |
+/// | Bus timing    | 1 µs a cycle is ample            | 1 µs a cycle is ample, with more to spare | 1 µs a strobe is ample |
+/// | I/O ports     | push-pull outputs                | open collector with pull-ups  | none                       |
+///
+/// An envelope ramp takes as long on each, 256 master clock cycles a
+/// period, so envelope periods carry over; only its resolution differs.
+///
+/// The AY-3-8912 and AY-3-8913 are AY-3-8910s in smaller packages, with
+/// port A alone and with no ports; which ports there are is part of the
+/// driver's type, in [`io_ports`].
+/// The AY8930 is an AY-3-8910 until told otherwise; its expanded mode is
+/// in [`ay8930`].
+///
+/// Each has its differences written down in a
+/// [`ChipProfile`](chip_profile::ChipProfile), from [`ChipKind::profile`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ChipKind {
+    #[default]
+    Ym2149,
+    Ay8910,
+    /// The YMZ294 and YMZ284, the YM2149's sound generator on another bus.
+    Ymz294,
+}
+
+impl ChipKind {
+    /// Steps in one envelope ramp.
+    pub const fn envelope_steps(self) -> u8 {
+        self.profile().envelope_steps
+    }
+
+    /// Whether the chip has the SEL pin, which halves the master clock it
+    /// is fed when held low.
+    pub const fn has_sel_pin(self) -> bool {
+        self.profile().sel_pin
+    }
+}
+
+/// How a chip is set up: which it is, the clock it is fed, and whether SEL
+/// halves that clock, all a configurator has to tell a board about it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChipConfig {
+    pub chip: ChipKind,
+    pub master_clock: u32,
+    pub sel_low: bool,
+}
+
+impl Default for ChipConfig {
+    /// A YM2149 fed 2 MHz with SEL high.
+    fn default() -> ChipConfig {
+        ChipConfig {
+            chip: ChipKind::Ym2149,
+            master_clock: tuning::DEFAULT_MASTER_CLOCK,
+            sel_low: false,
+        }
+    }
+}
+
+/// Returned when asked for something the chip in use doesn't have.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VariantError {
+    /// Only the YM2149 has a SEL pin.
+    NoSelPin(ChipKind),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum IoPort {
     A,
     B,
@@ -47,9 +361,14 @@ pub enum IoPort {
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Error<P: OutputPin> {
     PinError(P::Error),
+    /// Asked for something the chip's package doesn't have, such as the
+    /// direction bit of a port it has no pins for.
+    Unsupported,
 }
 
-pub struct Ym2149<P, Delay> {
+/// The driver, for a chip with the I/O ports `Io`: both, unless made
+/// otherwise, as for an [`Ay8912`].
+pub struct Ym2149<P, Delay, Io = BothPorts> {
     bdir: P,
     bc1: P,
     d0: P,
@@ -61,13 +380,28 @@ pub struct Ym2149<P, Delay> {
     d6: P,
     d7: P,
     delay: Delay,
+    registers: Registers,
+    master_clock: u32,
+    chip: ChipKind,
+    /// Whether SEL is held low, halving the master clock.
+    sel_low: bool,
+    frequency_policy: FrequencyPolicy,
+    sample_write_ns: u32,
+    io: PhantomData<Io>,
 }
 
+/// The driver for an AY-3-8912, which has port A and no port B.
+pub type Ay8912<P, Delay> = Ym2149<P, Delay, PortAOnly>;
+
+/// The driver for an AY-3-8913, which has neither port.
+pub type Ay8913<P, Delay> = Ym2149<P, Delay, NoPorts>;
+
 impl<P, Delay> Ym2149<P, Delay>
 where
     P: OutputPin,
     Delay: DelayNs,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         bdir: P,
         bc1: P,
@@ -94,11 +428,130 @@ where
             d6,
             d7,
             delay,
+            registers: Registers::new(),
+            master_clock: tuning::DEFAULT_MASTER_CLOCK,
+            chip: ChipKind::Ym2149,
+            sel_low: false,
+            frequency_policy: FrequencyPolicy::default(),
+            sample_write_ns: 2_000,
+            io: PhantomData,
         };
         output.inactive_mode()?;
         Ok(output)
     }
 
+    /// The driver for an AY-3-8912 on these pins: port B's register and
+    /// direction bit are no longer written, and its setters are gone.
+    pub fn into_ay8912(self) -> Ay8912<P, Delay> {
+        let mut ay = self.into_io_ports();
+        ay.set_chip_kind(ChipKind::Ay8910);
+        ay
+    }
+
+    /// The driver for an AY-3-8913 on these pins: neither port's register
+    /// nor direction bit is written, and their setters are gone. The
+    /// AY-3-8913 has no A8 either, which changes nothing here: the driver
+    /// leaves the chip select pins to the board.
+    pub fn into_ay8913(self) -> Ay8913<P, Delay> {
+        let mut ay = self.into_io_ports();
+        ay.set_chip_kind(ChipKind::Ay8910);
+        ay
+    }
+}
+
+impl<P, Delay, Io> Ym2149<P, Delay, Io>
+where
+    P: OutputPin,
+    Delay: DelayNs,
+    Io: IoPorts,
+{
+    fn into_io_ports<To: IoPorts>(self) -> Ym2149<P, Delay, To> {
+        Ym2149 {
+            bdir: self.bdir,
+            bc1: self.bc1,
+            d0: self.d0,
+            d1: self.d1,
+            d2: self.d2,
+            d3: self.d3,
+            d4: self.d4,
+            d5: self.d5,
+            d6: self.d6,
+            d7: self.d7,
+            delay: self.delay,
+            registers: self.registers,
+            master_clock: self.master_clock,
+            chip: self.chip,
+            sel_low: self.sel_low,
+            frequency_policy: self.frequency_policy,
+            sample_write_ns: self.sample_write_ns,
+            io: PhantomData,
+        }
+    }
+
+    /// Tells the driver the frequency of the clock feeding the chip, which
+    /// pitch conversions depend on. Defaults to 2 MHz.
+    pub fn set_master_clock(&mut self, hz: u32) {
+        self.master_clock = hz;
+    }
+
+    /// Asks `source` for a master clock of `hz` and tells the driver the one
+    /// it makes, which it returns.
+    pub fn set_master_clock_from<S: ClockSource + ?Sized>(
+        &mut self,
+        source: &S,
+        hz: u32,
+    ) -> MasterClock {
+        let clock = MasterClock::from_source(source, hz);
+        self.master_clock = clock.hz;
+        clock
+    }
+
+    /// Tells the driver which chip it is driving: a YM2149 unless set
+    /// otherwise. An AY-3-8910 has no SEL pin, so setting it forgets
+    /// [`Ym2149::set_sel_low`].
+    pub fn set_chip_kind(&mut self, chip: ChipKind) {
+        self.chip = chip;
+        self.sel_low &= chip.has_sel_pin();
+    }
+
+    /// Tells the driver the YM2149's SEL pin is held low, so the chip runs
+    /// at half the master clock it is fed, as boards feeding it 4 MHz do.
+    pub fn set_sel_low(&mut self, low: bool) -> Result<(), VariantError> {
+        if !self.chip.has_sel_pin() {
+            return Err(VariantError::NoSelPin(self.chip));
+        }
+        self.sel_low = low;
+        Ok(())
+    }
+
+    /// How the pitch and frequency setters turn what they are asked for into
+    /// periods, unless a call says otherwise: to the nearest period, folded
+    /// into range by octaves, until set.
+    pub fn set_frequency_policy(&mut self, policy: FrequencyPolicy) {
+        self.frequency_policy = policy;
+    }
+
+    /// The chip, clock and SEL the driver has been told about.
+    pub fn chip_config(&self) -> ChipConfig {
+        ChipConfig {
+            chip: self.chip,
+            master_clock: self.master_clock,
+            sel_low: self.sel_low,
+        }
+    }
+
+    /// Tells the driver all of `config` at once, or none of it if it holds
+    /// SEL low on a chip without one.
+    pub fn set_chip_config(&mut self, config: ChipConfig) -> Result<(), VariantError> {
+        if config.sel_low && !config.chip.has_sel_pin() {
+            return Err(VariantError::NoSelPin(config.chip));
+        }
+        self.chip = config.chip;
+        self.master_clock = config.master_clock;
+        self.sel_low = config.sel_low;
+        Ok(())
+    }
+
     fn write_mode(&mut self) -> Result<(), Error<P>> {
         self.bdir.set_high().map_err(Error::PinError)?;
         self.bc1.set_low().map_err(Error::PinError)?;
@@ -195,16 +648,29 @@ where
         Ok(())
     }
 
+    /// Writes 0 to every register the package has.
     pub fn clear_all_registers(&mut self) -> Result<(), Error<P>> {
-        for i in 0..16 {
+        for i in 0..Io::REGISTERS {
             self.set_register_value(i, 0)?;
         }
         Ok(())
     }
 
+    /// Writes `data` to `address`. Writes to the register of a port the
+    /// package doesn't have are dropped, and the direction bits of one are
+    /// kept clear in the mixer, so a dump that writes them plays as it
+    /// would on the chip.
     pub fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Error<P>> {
+        if !Io::has_register(address) {
+            return Ok(());
+        }
+        let data = match address {
+            0x7 => data & (0x3F | Io::DIRECTION_BITS),
+            _ => data,
+        };
         self.set_address(address)?;
         self.set_data(data)?;
+        self.registers.set(address, data);
         Ok(())
     }
 
@@ -213,11 +679,7 @@ where
         channel: Channel,
         frequency: u16,
     ) -> Result<(), Error<P>> {
-        let (fine_channel, rough_channel) = match channel {
-            Channel::A => (0x0, 0x1),
-            Channel::B => (0x2, 0x3),
-            Channel::C => (0x4, 0x5),
-        };
+        let (fine_channel, rough_channel) = channel.period_registers();
         let fine = frequency as u8;
         let rough = (frequency >> 8) as u8;
         self.set_register_value(fine_channel, fine)?;
@@ -230,7 +692,12 @@ where
         Ok(())
     }
 
+    /// Writes the mixer, or returns [`Error::Unsupported`] if `settings`
+    /// makes an output of a port the package doesn't have.
     pub fn set_mixer_settings(&mut self, settings: MixerSettings) -> Result<(), Error<P>> {
+        if settings.bits() & 0xC0 & !Io::DIRECTION_BITS != 0 {
+            return Err(Error::Unsupported);
+        }
         self.set_register_value(0x7, settings.bits())?;
         Ok(())
     }
@@ -240,16 +707,7 @@ where
         channel: Channel,
         level: ChannelLevel,
     ) -> Result<(), Error<P>> {
-        let data = match level {
-            ChannelLevel::Fixed(level) => level & 0b1111,
-            ChannelLevel::Envelope => 0b10000,
-        };
-        let register = match channel {
-            Channel::A => 0x8,
-            Channel::B => 0x9,
-            Channel::C => 0xA,
-        };
-        self.set_register_value(register, data)?;
+        self.set_register_value(channel.level_register(), level.register_value())?;
         Ok(())
     }
 
@@ -264,23 +722,218 @@ where
         Ok(())
     }
 
-    pub fn set_io_port_data(&mut self, port: IoPort, data: u8) -> Result<(), Error<P>> {
+    /// Writes port A's output latch, R14.
+    pub fn set_port_a_data(&mut self, data: u8) -> Result<(), Error<P>>
+    where
+        Io: HasPortA,
+    {
+        self.set_register_value(0xE, data)
+    }
+
+    /// Writes `port`'s output latch. Only for packages with both ports: on
+    /// an [`Ay8912`], use [`Ym2149::set_port_a_data`].
+    pub fn set_io_port_data(&mut self, port: IoPort, data: u8) -> Result<(), Error<P>>
+    where
+        Io: HasPortB,
+    {
         let register = match port {
             IoPort::A => 0xE,
-            IoPort::B => 0xD,
+            IoPort::B => 0xF,
         };
         self.set_register_value(register, data)?;
         Ok(())
     }
 }
 
+impl<P, Delay, Io> Psg for Ym2149<P, Delay, Io>
+where
+    P: OutputPin,
+    Delay: DelayNs,
+    Io: IoPorts,
+{
+    type Error = Error<P>;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Error<P>> {
+        Ym2149::set_register_value(self, address, data)
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// Without the port, its register is dropped and its direction bit
+    /// kept clear, as [`Ym2149::set_register_value`] does.
+    fn register_mask(&self, address: u8) -> u8 {
+        match address {
+            _ if !Io::has_register(address) => 0,
+            0x7 => 0x3F | Io::DIRECTION_BITS,
+            _ => registers::REGISTER_MASKS[address as usize],
+        }
+    }
+
+    /// The clock the chip runs at: halved while SEL is held low.
+    fn master_clock(&self) -> u32 {
+        match self.sel_low {
+            true => self.master_clock / 2,
+            false => self.master_clock,
+        }
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.chip
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.frequency_policy
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::recording_bus::RecordingBus;
 
     #[test]
-    fn it_works() {
-        //let result = add(2, 2);
-        assert_eq!(0, 4);
+    fn channel_register_layout() {
+        assert_eq!(Channel::B.period_registers(), (0x2, 0x3));
+        assert_eq!(Channel::C.level_register(), 0xA);
+        assert_eq!(ChannelLevel::Fixed(0x1F).register_value(), 0xF);
+        assert_eq!(ChannelLevel::Envelope.register_value(), 0x10);
+        for channel in Channel::ALL {
+            let (fine, rough) = channel.period_registers();
+            assert_eq!(Channel::for_register(fine), Some(channel));
+            assert_eq!(Channel::for_register(rough), Some(channel));
+            assert_eq!(
+                Channel::for_register(channel.level_register()),
+                Some(channel)
+            );
+        }
+        assert_eq!(Channel::for_register(0x7), None);
+    }
+
+    #[test]
+    fn period_setters_write_both_bytes_once() {
+        let mut bus = RecordingBus::<32>::new();
+        bus.set_channel_period(Channel::B, 0x123).unwrap();
+        bus.set_channel_period(Channel::B, 0x123).unwrap();
+        bus.assert_register_sequence(&[(0x2, 0x23), (0x3, 0x01)]);
+        bus.clear();
+        assert_eq!(bus.update_register(0x6, 0x10), Ok(true));
+        assert_eq!(bus.update_register(0x6, 0x10), Ok(false));
+        bus.assert_register_sequence(&[(0x6, 0x10)]);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn pitch_setters_write_the_folded_period() {
+        let mut bus = RecordingBus::<32>::new();
+        // 2 MHz / (16 * 440 Hz): 284.
+        let folded = bus
+            .set_channel_pitch(Channel::C, Pitch::new(Note::A, 4))
+            .unwrap();
+        assert_eq!(folded.period, 0x11C);
+        bus.assert_register_sequence(&[(0x4, 0x1C), (0x5, 0x01)]);
+    }
+
+    #[test]
+    fn frequency_setters_report_what_they_play() {
+        let mut bus = RecordingBus::<32>::new();
+        let tone = bus.set_channel_tone_hz(Channel::A, 440).unwrap();
+        // 284 plays 440.14 Hz.
+        assert_eq!((tone.period, tone.millihertz), (284, 440_141));
+        bus.assert_register_sequence(&[(0x0, 0x1C), (0x1, 0x01)]);
+        bus.clear();
+        // 2 MHz / (16 * 20 Hz) is 6250, an octave below the registers.
+        let refuse = FrequencyPolicy::default().with_out_of_range(OutOfRange::Error);
+        assert_eq!(
+            bus.set_channel_tone_hz_with(Channel::A, 20, refuse),
+            Err(FrequencyError::OutOfRange)
+        );
+        bus.assert_register_sequence(&[]);
+        let tone = bus.set_channel_tone_hz(Channel::A, 20).unwrap();
+        assert_eq!(
+            (tone.period, tone.octaves, tone.millihertz),
+            (3125, 1, 40_000)
+        );
+    }
+
+    #[test]
+    fn level_and_mixer_setters_touch_only_their_bits() {
+        let mut bus = RecordingBus::<32>::new();
+        bus.update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        bus.update_channel_level(Channel::A, ChannelLevel::Fixed(15))
+            .unwrap();
+        bus.update_channel_level(Channel::A, ChannelLevel::Envelope)
+            .unwrap();
+        bus.set_tone_enabled(Channel::B, true).unwrap();
+        bus.set_noise_enabled(Channel::C, true).unwrap();
+        bus.set_channel_mixer(Channel::A, true, false).unwrap();
+        bus.assert_register_sequence(&[
+            (0x8, 15),
+            (0x8, 0x10),
+            (0x7, 0b0011_1101),
+            (0x7, 0b0001_1101),
+            (0x7, 0b0001_1100),
+        ]);
+        assert!(bus.writes_to(0x8).eq([15, 0x10]));
+    }
+
+    #[test]
+    fn frames_and_silence_write_what_changed() {
+        let mut bus = RecordingBus::<64>::new();
+        let mut frame = [1, 2, 3, 4, 5, 6, 7, 0xFF, 9, 10, 11, 12, 13, 0xFF, 15, 16];
+        bus.write_frame(&frame).unwrap();
+        // R13 left alone, R14 and R15 never written, and the port bits of
+        // the mixer kept.
+        assert_eq!(bus.writes().count(), 13);
+        assert!(bus.writes_to(0x7).eq([0b0011_1111]));
+        bus.clear();
+        frame[0x0] = 0x40;
+        frame[0xD] = 0x0E;
+        bus.write_frame(&frame).unwrap();
+        bus.assert_register_sequence(&[(0x0, 0x40), (0xD, 0x0E)]);
+        bus.clear();
+        bus.silence().unwrap();
+        bus.assert_register_sequence(&[(0x8, 0), (0x9, 0), (0xA, 0)]);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn chords_and_unisons_tune_and_open_their_channels() {
+        let mut bus = RecordingBus::<64>::new();
+        bus.play_chord(Pitch::new(Note::C, 4), ChordType::Major, 12)
+            .unwrap();
+        bus.assert_register_sequence(&[
+            (0x0, 0xDE),
+            (0x1, 0x01),
+            (0x8, 12),
+            (0x7, 0b0011_1110),
+            (0x2, 0x7B),
+            (0x3, 0x01),
+            (0x9, 12),
+            (0x7, 0b0011_1100),
+            (0x4, 0x3F),
+            (0x5, 0x01),
+            (0xA, 12),
+            (0x7, 0b0011_1000),
+        ]);
+        bus.clear();
+        bus.stop_chord().unwrap();
+        bus.assert_register_sequence(&[(0x8, 0), (0x9, 0), (0xA, 0)]);
+
+        let mut bus = RecordingBus::<64>::new();
+        bus.play_unison(Channel::A, Channel::B, Pitch::new(Note::A, 4), 10, 10)
+            .unwrap();
+        bus.assert_register_sequence(&[
+            (0x0, 0x1C),
+            (0x1, 0x01),
+            (0x2, 0x1A),
+            (0x3, 0x01),
+            (0x8, 10),
+            (0x7, 0b0011_1110),
+            (0x9, 10),
+            (0x7, 0b0011_1100),
+        ]);
     }
 }
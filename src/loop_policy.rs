@@ -0,0 +1,113 @@
+//! How many times a looping song goes round, and how it ends once it has.
+//!
+//! [`FramePlayer`](crate::frame_player::FramePlayer) and
+//! [`Sequencer`](crate::sequencer::Sequencer) loop for as long as they are
+//! ticked unless given a [`LoopPolicy`]. Songs that never go back to a loop
+//! point end as they always do, whatever the policy says.
+
+/// What a song does each time it reaches the end of its loop.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum LoopPolicy {
+    /// Goes back every time.
+    #[default]
+    Infinite,
+    /// Goes back `n` times, then finishes at the end.
+    Count(u32),
+    /// Goes back `loops` times, then once more, fading out over the first
+    /// `fade_frames` frames of that pass and finishing once silent. The
+    /// fade carries on round the loop if the loop is shorter.
+    CountThenFade { loops: u32, fade_frames: u32 },
+}
+
+/// What to do at the end of the loop.
+pub(crate) enum LoopAction {
+    Loop,
+    /// Go back, and start fading.
+    Fade(Fader),
+    Finish,
+}
+
+impl LoopPolicy {
+    /// What to do at the end of the loop, having gone back `loops` times
+    /// already, and with a fade already under way or not.
+    pub(crate) fn at_end(self, loops: u32, fading: bool) -> LoopAction {
+        match self {
+            _ if fading => LoopAction::Loop,
+            LoopPolicy::Infinite => LoopAction::Loop,
+            LoopPolicy::Count(n) | LoopPolicy::CountThenFade { loops: n, .. } if loops < n => {
+                LoopAction::Loop
+            }
+            LoopPolicy::CountThenFade { fade_frames, .. } if fade_frames > 0 => {
+                LoopAction::Fade(Fader::new(fade_frames))
+            }
+            _ => LoopAction::Finish,
+        }
+    }
+}
+
+/// Scales the levels coming out of a song down to nothing, a frame at a
+/// time: at `n` frames from the end of the fade, levels are `n / frames` of
+/// what the song asks for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Fader {
+    frames: u32,
+    left: u32,
+}
+
+impl Fader {
+    pub(crate) const fn new(frames: u32) -> Fader {
+        Fader {
+            frames,
+            left: frames,
+        }
+    }
+
+    /// Moves on to the next frame, returning `true` once the fade is over
+    /// and the song should finish rather than play it.
+    pub(crate) fn step(&mut self) -> bool {
+        self.left = self.left.saturating_sub(1);
+        self.left == 0
+    }
+
+    pub(crate) fn scale(&self, level: u8) -> u8 {
+        (level as u32 * self.left / self.frames.max(1)) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_loops_then_fades_or_finishes() {
+        let fades = |policy: LoopPolicy| {
+            [0, 1, 2, 3].map(|loops| match policy.at_end(loops, false) {
+                LoopAction::Loop => 'L',
+                LoopAction::Fade(_) => 'F',
+                LoopAction::Finish => 'E',
+            })
+        };
+        assert_eq!(fades(LoopPolicy::Infinite), ['L'; 4]);
+        assert_eq!(fades(LoopPolicy::Count(2)), ['L', 'L', 'E', 'E']);
+        let policy = LoopPolicy::CountThenFade {
+            loops: 1,
+            fade_frames: 4,
+        };
+        assert_eq!(fades(policy), ['L', 'F', 'F', 'F']);
+        assert!(matches!(policy.at_end(3, true), LoopAction::Loop));
+        let policy = LoopPolicy::CountThenFade {
+            loops: 1,
+            fade_frames: 0,
+        };
+        assert_eq!(fades(policy), ['L', 'E', 'E', 'E']);
+
+        let mut fader = Fader::new(4);
+        let mut levels = [0; 3];
+        for level in &mut levels {
+            assert!(!fader.step());
+            *level = fader.scale(15);
+        }
+        assert_eq!(levels, [11, 7, 3]);
+        assert!(fader.step());
+    }
+}
@@ -0,0 +1,615 @@
+//! The chip's arithmetic on its own, with no pins or buses: plain integers
+//! in and out, so it can be checked on the host directly.
+//!
+//! [`tuning`] turns pitches and frequencies into periods and back, and
+//! [`volume`] has the level curves; the driver's setters and the emulator
+//! both go through them, and they are here too. The rest is how the chip's
+//! generators move on, as the emulator runs them: the counters dividing the
+//! clock down to each generator, the noise shift register, the envelope's
+//! shapes, and the fractions the emulator and the timer effects keep time
+//! with.
+
+pub use crate::{tuning, volume};
+
+/// What the noise shift register starts from.
+pub const NOISE_SEED: u32 = 1;
+
+/// Shifts the noise register takes to come back round to where it started:
+/// every 17-bit value but 0.
+pub const NOISE_CYCLE: u32 = (1 << 17) - 1;
+
+/// The 17-bit noise shift register moved on once: bits 0 and 3 are
+/// combined into bit 16 as the rest shift down. Bit 0 is the noise.
+pub const fn shift_noise(lfsr: u32) -> u32 {
+    let bit = (lfsr ^ lfsr >> 3) & 1;
+    lfsr >> 1 | bit << 16
+}
+
+/// The envelope step, 0 to 31, that fixed `level` sounds the same as: 0
+/// for 0, and `2 * level + 1` above it.
+pub const fn fixed_level_step(level: u8) -> u8 {
+    match level & 0xF {
+        0 => 0,
+        level => level * 2 + 1,
+    }
+}
+
+/// Counts chip steps up to a period, as each generator does to divide the
+/// clock down. A period of 0 counts as 1, as on the chip.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct PeriodCounter {
+    count: u32,
+}
+
+impl PeriodCounter {
+    pub const fn new() -> PeriodCounter {
+        PeriodCounter { count: 0 }
+    }
+
+    /// Counts a step, and says whether `period` of them have gone, starting
+    /// again if so. A period shortened below the count ends it at once.
+    pub fn tick(&mut self, period: u32) -> bool {
+        self.count += 1;
+        let ended = self.count >= period.max(1);
+        if ended {
+            self.count = 0;
+        }
+        ended
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+}
+
+/// One tone generator: high and low in turn for its period of chip steps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ToneGenerator {
+    counter: PeriodCounter,
+    high: bool,
+}
+
+impl ToneGenerator {
+    /// A generator just out of reset, low.
+    pub const fn new() -> ToneGenerator {
+        ToneGenerator {
+            counter: PeriodCounter::new(),
+            high: false,
+        }
+    }
+
+    /// Moves on a chip step with the 12-bit `period`.
+    pub fn tick(&mut self, period: u16) {
+        if self.counter.tick(period as u32) {
+            self.high ^= true;
+        }
+    }
+
+    pub const fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+/// The noise generator: the shift register, moved on every twice its
+/// period of chip steps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoiseGenerator {
+    counter: PeriodCounter,
+    lfsr: u32,
+}
+
+impl NoiseGenerator {
+    /// A generator just out of reset, at [`NOISE_SEED`].
+    pub const fn new() -> NoiseGenerator {
+        NoiseGenerator {
+            counter: PeriodCounter::new(),
+            lfsr: NOISE_SEED,
+        }
+    }
+
+    /// Moves on a chip step with the 5-bit `period`.
+    pub fn tick(&mut self, period: u8) {
+        if self.counter.tick(2 * period.max(1) as u32) {
+            self.lfsr = shift_noise(self.lfsr);
+        }
+    }
+
+    pub const fn is_high(&self) -> bool {
+        self.lfsr & 1 != 0
+    }
+
+    pub const fn lfsr(&self) -> u32 {
+        self.lfsr
+    }
+}
+
+impl Default for NoiseGenerator {
+    fn default() -> NoiseGenerator {
+        NoiseGenerator::new()
+    }
+}
+
+/// Where the envelope is in its shape, moving on a step every period of
+/// chip steps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EnvelopeGenerator {
+    counter: PeriodCounter,
+    /// 0 to 31 through the current ramp.
+    step: u8,
+    /// Whether the current ramp goes up.
+    attack: bool,
+    /// Whether the shape has ended, holding the level it ended on.
+    holding: bool,
+}
+
+impl EnvelopeGenerator {
+    /// An envelope just out of reset, held at 0.
+    pub const fn new() -> EnvelopeGenerator {
+        EnvelopeGenerator {
+            counter: PeriodCounter::new(),
+            step: 31,
+            attack: false,
+            holding: true,
+        }
+    }
+
+    /// Starts `shape` from the beginning, as a write to R13 does.
+    pub fn restart(&mut self, shape: u8) {
+        *self = EnvelopeGenerator {
+            counter: PeriodCounter::new(),
+            step: 0,
+            attack: shape & 0x4 != 0,
+            holding: false,
+        };
+    }
+
+    /// Moves on a chip step with `period` through `shape`.
+    pub fn tick(&mut self, period: u16, shape: u8) {
+        if self.counter.tick(period as u32) {
+            self.advance(shape);
+        }
+    }
+
+    /// Moves on a step through `shape`, at the end of a ramp starting the
+    /// next, or holding.
+    pub fn advance(&mut self, shape: u8) {
+        if self.holding {
+            return;
+        }
+        if self.step < 31 {
+            self.step += 1;
+            return;
+        }
+        let (cont, attack, alternate, hold) = (
+            shape & 0x8 != 0,
+            shape & 0x4 != 0,
+            shape & 0x2 != 0,
+            shape & 0x1 != 0,
+        );
+        if !cont || hold {
+            // Held at the top of a ramp that ends where the shape does.
+            self.holding = true;
+            self.attack = cont && attack != alternate;
+        } else {
+            self.step = 0;
+            self.attack ^= alternate;
+        }
+    }
+
+    /// The level, 0 to 31.
+    pub const fn level(&self) -> u8 {
+        match self.attack {
+            true => self.step,
+            false => 31 - self.step,
+        }
+    }
+
+    pub const fn is_holding(&self) -> bool {
+        self.holding
+    }
+}
+
+impl Default for EnvelopeGenerator {
+    fn default() -> EnvelopeGenerator {
+        EnvelopeGenerator::new()
+    }
+}
+
+/// Turns master clock cycles into the chip steps each output sample
+/// covers, the clock divided by 8, carrying the fraction of a step left
+/// over into the next sample so the rate holds exactly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct SampleClock {
+    /// Clock cycles left over, times 8 and the sample rate.
+    remainder: u64,
+}
+
+impl SampleClock {
+    pub const fn new() -> SampleClock {
+        SampleClock { remainder: 0 }
+    }
+
+    /// The chip steps in the next sample at `clock` and `sample_rate`.
+    pub fn steps(&mut self, clock: u32, sample_rate: u32) -> u64 {
+        let cost = 8 * sample_rate.max(1) as u64;
+        self.remainder += clock as u64;
+        let steps = self.remainder / cost;
+        self.remainder -= steps * cost;
+        steps
+    }
+}
+
+/// `numerator / denominator` in Q32, rounded down, and `u32::MAX` if it is
+/// 1 or more; 0 over 0.
+pub const fn q32_ratio(numerator: u64, denominator: u64) -> u32 {
+    q32(numerator, denominator, 0)
+}
+
+/// [`q32_ratio`] rounded to nearest.
+pub const fn q32_ratio_rounded(numerator: u64, denominator: u64) -> u32 {
+    q32(numerator, denominator, denominator / 2)
+}
+
+const fn q32(numerator: u64, denominator: u64, half: u64) -> u32 {
+    if denominator == 0 {
+        return 0;
+    }
+    let ratio = (((numerator as u128) << 32) + half as u128) / denominator as u128;
+    if ratio > u32::MAX as u128 {
+        u32::MAX
+    } else {
+        ratio as u32
+    }
+}
+
+/// A Q32 phase moved on by a fixed step each tick, which wraps round as
+/// often as a step of [`q32_ratio`]`(events, ticks)` says, for events that
+/// don't come a whole number of ticks apart. The rounding in the step is
+/// under one part in 2^32 of an event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct PhaseAccumulator {
+    step: u32,
+    phase: u32,
+}
+
+impl PhaseAccumulator {
+    /// Stopped, with nothing to step by.
+    pub const STOPPED: PhaseAccumulator = PhaseAccumulator::new(0);
+
+    pub const fn new(step: u32) -> PhaseAccumulator {
+        PhaseAccumulator { step, phase: 0 }
+    }
+
+    /// Steps by `step` instead, from where the phase is.
+    pub const fn with_step(mut self, step: u32) -> PhaseAccumulator {
+        self.step = step;
+        self
+    }
+
+    pub const fn step(&self) -> u32 {
+        self.step
+    }
+
+    /// Steps by `step` from the next tick, from where the phase is.
+    pub fn set_step(&mut self, step: u32) {
+        self.step = step;
+    }
+
+    pub const fn phase(&self) -> u32 {
+        self.phase
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+
+    /// Moves on a tick, and says whether the phase wrapped round.
+    pub fn advance(&mut self) -> bool {
+        let (phase, wrapped) = self.phase.overflowing_add(self.step);
+        self.phase = phase;
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tuning::*;
+    use super::volume::*;
+    use super::*;
+    use crate::ChipKind;
+
+    /// The clocks the machines with the chip feed it: the Amstrad CPC and
+    /// Oric, the Spectrum 128, the MSX and the Atari ST.
+    const CLOCKS: [u32; 5] = [1_000_000, 1_773_400, 1_789_772, 2_000_000, 4_000_000];
+
+    #[test]
+    fn every_tone_period_round_trips_at_every_clock() {
+        for clock in CLOCKS {
+            let mut last = u32::MAX;
+            for period in 1..=MAX_TONE_PERIOD {
+                let millihertz = period_to_millihertz(clock, period);
+                assert!(millihertz < last, "{period} at {clock}");
+                last = millihertz;
+                assert_eq!(millihertz_to_period(clock, millihertz), period as u32);
+                let policy = FrequencyPolicy::default().with_out_of_range(OutOfRange::Error);
+                let tone = tone_period(clock, millihertz, policy).unwrap();
+                assert_eq!((tone.period, tone.octaves), (period, 0));
+                // To the hertz, only the lowest can be out of range.
+                let hz = (millihertz + 500) / 1000;
+                let unchecked = millihertz_to_period(clock, hz * 1000);
+                match checked_hz_to_period(clock, hz) {
+                    Ok(checked) => assert_eq!(checked as u32, unchecked),
+                    Err(_) => assert!(unchecked > MAX_TONE_PERIOD as u32),
+                }
+            }
+            assert_eq!(
+                period_to_millihertz(clock, 0),
+                period_to_millihertz(clock, 1)
+            );
+        }
+    }
+
+    #[test]
+    fn every_noise_period_round_trips_at_every_clock() {
+        for clock in CLOCKS {
+            for period in 1..=MAX_NOISE_PERIOD {
+                let hertz = noise_hertz(clock, period);
+                assert_eq!(noise_period(clock, hertz), period, "{period} at {clock}");
+                assert_eq!(checked_noise_period(clock, hertz), Ok(period));
+                assert!(hertz > noise_hertz(clock, period + 1) || period == MAX_NOISE_PERIOD);
+            }
+            // Only the low five bits are a period, and 0 runs as 1.
+            assert_eq!(noise_hertz(clock, 0), noise_hertz(clock, 1));
+            assert_eq!(noise_hertz(clock, 0x21), noise_hertz(clock, 1));
+        }
+    }
+
+    #[test]
+    fn every_envelope_period_keeps_its_frequency_at_every_clock() {
+        for clock in CLOCKS {
+            let mut last = u32::MAX;
+            for period in 1..=u16::MAX {
+                let millihertz = envelope_millihertz(clock, period);
+                assert!(millihertz <= last, "{period} at {clock}");
+                last = millihertz;
+                // Long periods share a frequency to the millihertz, so only
+                // the frequency survives the trip.
+                let back = envelope_period(clock, millihertz);
+                assert_eq!(envelope_millihertz(clock, back), millihertz);
+                match checked_envelope_period(clock, millihertz) {
+                    Ok(checked) => assert_eq!(checked, back),
+                    // Only past the end, where it is clamped.
+                    Err(_) => assert_eq!(back, u16::MAX),
+                }
+                let steps = envelope_step_millihertz(ChipKind::Ym2149, clock, period);
+                let ay = envelope_step_millihertz(ChipKind::Ay8910, clock, period);
+                assert!(steps.abs_diff(32 * millihertz) <= 16);
+                assert!(steps.abs_diff(2 * ay) <= 1);
+            }
+        }
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn every_note_is_played_in_tune_at_every_clock() {
+        use crate::pitch::Pitch;
+
+        for clock in CLOCKS {
+            for midi in 0..=127 {
+                let pitch = Pitch::from_midi(midi).unwrap();
+                let folded = fold_pitch_period(clock, pitch);
+                let played = midi as i16 + 12 * folded.octaves as i16;
+                // Periods under 18 are more than a semitone's rounding apart,
+                // so the note may be nearer another, or none within 50 cents.
+                match (nearest_pitch(clock, folded.period), folded.period) {
+                    (Some((near, _)), 18..) => assert_eq!(near.midi() as i16, played),
+                    (Some((near, _)), _) => assert!(near.midi().abs_diff(played as u8) <= 1),
+                    (None, period) => assert!(period < 18, "{midi} at {clock}"),
+                }
+                match checked_note_to_period(clock, pitch) {
+                    Ok(period) => assert_eq!((period, folded.octaves), (folded.period, 0)),
+                    Err(error) => assert_ne!(folded.octaves, 0, "{error:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn level_curves_rise_with_every_step() {
+        for chip in [ChipKind::Ym2149, ChipKind::Ay8910] {
+            assert_eq!(envelope_output(chip, 0), 0);
+            assert_eq!(envelope_output(chip, 31), 0xFFFF);
+            for step in 1..32 {
+                assert!(envelope_output(chip, step) >= envelope_output(chip, step - 1));
+            }
+            for level in 0..16 {
+                let step = fixed_level_step(level);
+                assert_eq!(fixed_output(chip, level), envelope_output(chip, step));
+            }
+        }
+        // The AY-3-8910 plays each pair of the YM2149's steps as one.
+        for step in (0..32).step_by(2) {
+            let output = |step| envelope_output(ChipKind::Ay8910, step);
+            assert_eq!(output(step), output(step + 1));
+        }
+    }
+
+    #[test]
+    fn scaling_never_raises_a_level() {
+        for level in 0..16 {
+            let mut last = level;
+            for value in (0..=127).rev() {
+                let scaled = scale_level(level, value);
+                assert!(scaled <= last, "{level} at {value}");
+                last = scaled;
+            }
+            assert_eq!(scale_level(level, 127), level);
+            let mut last = 0;
+            for percent in 0..=100 {
+                let scaled = scale_level_by_gain(level, percent);
+                assert!(scaled >= last && scaled <= level, "{level} at {percent}%");
+                last = scaled;
+            }
+            assert_eq!(scale_level_by_gain(level, 0), 0);
+            assert_eq!(scale_level_by_gain(level, 100), level);
+        }
+    }
+
+    #[test]
+    fn generators_move_on_with_their_periods() {
+        for period in 0..=40u16 {
+            let mut counter = PeriodCounter::new();
+            let ends: u32 = (0..1000).map(|_| counter.tick(period as u32) as u32).sum();
+            assert_eq!(ends, 1000 / period.max(1) as u32);
+            // A tone flips after each period, so a cycle takes two.
+            let mut tone = ToneGenerator::new();
+            let flips = (0..1000)
+                .filter(|_| {
+                    let was = tone.is_high();
+                    tone.tick(period);
+                    tone.is_high() != was
+                })
+                .count();
+            assert_eq!(flips as u32, ends);
+        }
+        // Noise moves on every twice its period.
+        let mut noise = NoiseGenerator::new();
+        for _ in 0..20 {
+            noise.tick(5);
+        }
+        assert_eq!(noise.lfsr(), shift_noise(shift_noise(NOISE_SEED)));
+    }
+
+    #[test]
+    fn noise_follows_the_17_bit_shift_register() {
+        // The first 64 noise bits from the seed, bit 0 first.
+        const REFERENCE: u64 = 0x2404_1002_4001_0000;
+        let mut lfsr = NOISE_SEED;
+        for bit in 0..64 {
+            lfsr = shift_noise(lfsr);
+            assert_eq!(lfsr & 1, (REFERENCE >> bit) as u32 & 1, "bit {bit}");
+        }
+        // Every 17-bit value bar 0, once each.
+        let mut lfsr = NOISE_SEED;
+        for shift in 1..NOISE_CYCLE {
+            lfsr = shift_noise(lfsr);
+            assert_ne!(lfsr, NOISE_SEED, "shift {shift}");
+        }
+        assert_eq!(shift_noise(lfsr), NOISE_SEED);
+        // A maximal sequence has one more 1 than 0s.
+        let mut ones = 0;
+        for _ in 0..NOISE_CYCLE {
+            lfsr = shift_noise(lfsr);
+            ones += lfsr & 1;
+        }
+        assert_eq!(ones, 1 << 16);
+    }
+
+    #[test]
+    fn envelope_shapes_over_two_periods() {
+        #[derive(Copy, Clone)]
+        enum Ramp {
+            Up,
+            Down,
+            High,
+            Low,
+        }
+        use Ramp::*;
+        let shapes = [
+            [Down, Low],
+            [Down, Low],
+            [Down, Low],
+            [Down, Low],
+            [Up, Low],
+            [Up, Low],
+            [Up, Low],
+            [Up, Low],
+            [Down, Down],
+            [Down, Low],
+            [Down, Up],
+            [Down, High],
+            [Up, Up],
+            [Up, High],
+            [Up, Down],
+            [Up, Low],
+        ];
+        for (shape, ramps) in shapes.into_iter().enumerate() {
+            let expected = ramps.into_iter().flat_map(|ramp| {
+                (0..32).map(move |step| match ramp {
+                    Up => step,
+                    Down => 31 - step,
+                    High => 31,
+                    Low => 0,
+                })
+            });
+            let mut envelope = EnvelopeGenerator::new();
+            envelope.restart(shape as u8);
+            for (step, expected) in expected.enumerate() {
+                assert_eq!(envelope.level(), expected, "shape {shape:X} step {step}");
+                envelope.advance(shape as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn envelopes_hold_or_repeat_at_their_period() {
+        assert_eq!(EnvelopeGenerator::new().level(), 0);
+        for shape in 0..16 {
+            let mut envelope = EnvelopeGenerator::new();
+            envelope.restart(shape);
+            for _ in 0..64 {
+                envelope.advance(shape);
+            }
+            let repeats = shape & 0x8 != 0 && shape & 0x1 == 0;
+            assert_eq!(envelope.is_holding(), !repeats, "shape {shape:X}");
+        }
+        // A period of 3 moves on every third chip step.
+        let mut envelope = EnvelopeGenerator::new();
+        envelope.restart(0xC);
+        for _ in 0..9 {
+            envelope.tick(3, 0xC);
+        }
+        assert_eq!(envelope.level(), 3);
+    }
+
+    #[test]
+    fn sample_clocks_hold_their_rate_exactly() {
+        for clock in CLOCKS {
+            for rate in [8_000, 22_050, 44_100, 48_000, 96_000] {
+                let mut samples = SampleClock::new();
+                let steps: u64 = (0..rate).map(|_| samples.steps(clock, rate)).sum();
+                // A second of samples is a second of steps, to the step.
+                assert_eq!(steps, clock as u64 / 8, "{rate} Hz at {clock}");
+            }
+        }
+        // No rate is taken as 1 a second.
+        assert_eq!(SampleClock::new().steps(2_000_000, 0), 250_000);
+    }
+
+    #[test]
+    fn phases_wrap_as_often_as_their_ratio() {
+        assert_eq!(q32_ratio(1, 2), 1 << 31);
+        assert_eq!(q32_ratio(1, 3), 0x5555_5555);
+        assert_eq!(q32_ratio_rounded(2, 3), 0xAAAA_AAAB);
+        assert_eq!(q32_ratio(3, 3), u32::MAX);
+        assert_eq!((q32_ratio(5, 0), q32_ratio_rounded(5, 0)), (0, 0));
+        assert_eq!(q32_ratio(u32::MAX as u64, u64::MAX), 0);
+        for (events, ticks) in [(1, 50), (97, 3000), (440_500, 4_000_000), (7, 7 * 3 + 1)] {
+            let mut phase = PhaseAccumulator::new(q32_ratio(events, ticks));
+            let wraps = (0..ticks * 4).filter(|_| phase.advance()).count() as u64;
+            // Rounded down, the step can only lose the last.
+            assert!(
+                wraps == events * 4 || wraps == events * 4 - 1,
+                "{events}/{ticks}"
+            );
+        }
+        let mut phase = PhaseAccumulator::new(1 << 30);
+        assert!(!(phase.advance() || phase.advance() || phase.advance()));
+        assert!(phase.advance());
+        phase.set_step(1 << 31);
+        phase.advance();
+        phase.reset();
+        assert_eq!(phase.phase(), 0);
+        assert!(!PhaseAccumulator::STOPPED.clone().advance());
+    }
+}
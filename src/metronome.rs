@@ -0,0 +1,254 @@
+//! A click track with an accented first beat of each bar.
+
+use crate::math::{self, PhaseAccumulator};
+use crate::pitch::{Note, Pitch};
+use crate::psg::Psg;
+use crate::tuning;
+use crate::{Channel, ChannelLevel};
+
+/// One click: a tone blip shaped by the hardware envelope's single decay, so
+/// the chip fades it out with no further writes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Click {
+    pub pitch: Pitch,
+    /// How long the decay takes.
+    pub millis: u16,
+}
+
+pub const ACCENT_CLICK: Click = Click {
+    pitch: Pitch::new(Note::A, 6),
+    millis: 60,
+};
+
+pub const NORMAL_CLICK: Click = Click {
+    pitch: Pitch::new(Note::E, 6),
+    millis: 30,
+};
+
+/// The envelope shape that decays once and then holds at 0.
+const DECAY: u8 = 0x00;
+
+/// Beats per tick as a Q32 fraction, rounded to nearest.
+const fn beat_step(bpm: u16, tick_rate: u32) -> u32 {
+    math::q32_ratio_rounded(bpm as u64, tick_rate as u64 * 60)
+}
+
+/// Clicks `bpm` times a minute, accenting beat 0 of every bar.
+///
+/// The position within the beat is a Q32 phase advanced every tick, so a
+/// tempo that isn't a whole number of ticks per beat still averages out
+/// exactly: the rounding in the step is under one part in 2^32 of a beat,
+/// far below a tick even over hours. Changing the tempo keeps the phase, so
+/// the current beat just stretches or shrinks.
+///
+/// Clicks use the hardware envelope, which is shared, so other channels
+/// playing the envelope are retriggered too.
+#[derive(Debug, Clone)]
+pub struct Metronome {
+    bpm: u16,
+    beats_per_bar: u8,
+    tick_rate: u32,
+    channel: Channel,
+    accent: Click,
+    normal: Click,
+    phase: PhaseAccumulator,
+    beat_due: bool,
+    beat: u8,
+    running: bool,
+    on_beat: Option<fn(u8)>,
+}
+
+impl Metronome {
+    /// A metronome on channel A ticked at 50 Hz. Bars of 0 beats are taken
+    /// as 1.
+    pub const fn new(bpm: u16, beats_per_bar: u8) -> Metronome {
+        Metronome {
+            bpm,
+            beats_per_bar: if beats_per_bar == 0 { 1 } else { beats_per_bar },
+            tick_rate: 50,
+            channel: Channel::A,
+            accent: ACCENT_CLICK,
+            normal: NORMAL_CLICK,
+            phase: PhaseAccumulator::new(beat_step(bpm, 50)),
+            beat_due: false,
+            beat: 0,
+            running: false,
+            on_beat: None,
+        }
+    }
+
+    /// How many times a second [`Metronome::tick`] will be called.
+    pub const fn with_tick_rate(mut self, hertz: u32) -> Metronome {
+        self.tick_rate = hertz;
+        self.phase = self.phase.with_step(beat_step(self.bpm, hertz));
+        self
+    }
+
+    pub const fn with_channel(mut self, channel: Channel) -> Metronome {
+        self.channel = channel;
+        self
+    }
+
+    pub const fn with_clicks(mut self, accent: Click, normal: Click) -> Metronome {
+        self.accent = accent;
+        self.normal = normal;
+        self
+    }
+
+    /// Calls `callback` with the beat's index in the bar on every click, 0
+    /// being the accent.
+    pub fn on_beat(&mut self, callback: fn(u8)) {
+        self.on_beat = Some(callback);
+    }
+
+    /// Changes the tempo from the next tick without moving the phase.
+    pub fn set_tempo(&mut self, bpm: u16) {
+        self.bpm = bpm;
+        self.phase.set_step(beat_step(bpm, self.tick_rate));
+    }
+
+    pub fn tempo(&self) -> u16 {
+        self.bpm
+    }
+
+    pub fn set_beats_per_bar(&mut self, beats: u8) {
+        self.beats_per_bar = beats.max(1);
+        self.beat %= self.beats_per_bar;
+    }
+
+    /// Starts with an accent on the next tick.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.phase.reset();
+        self.beat = 0;
+        self.beat_due = true;
+    }
+
+    /// Stops clicking. A click already sounding decays on its own.
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Clicks if a beat has come round, returning its index in the bar.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<Option<u8>, P::Error> {
+        if !self.running {
+            return Ok(None);
+        }
+        let mut clicked = None;
+        if self.beat_due {
+            let beat = self.beat;
+            let click = if beat == 0 { self.accent } else { self.normal };
+            self.click(psg, click)?;
+            if let Some(callback) = self.on_beat {
+                callback(beat);
+            }
+            self.beat = (beat + 1) % self.beats_per_bar;
+            clicked = Some(beat);
+        }
+        self.beat_due = self.phase.advance();
+        Ok(clicked)
+    }
+
+    fn click<P: Psg>(&self, psg: &mut P, click: Click) -> Result<(), P::Error> {
+        let clock = psg.master_clock();
+        let period = tuning::fold_pitch_period(clock, click.pitch).period;
+        psg.set_channel_period(self.channel, period)?;
+        let millihertz = 1_000_000 / click.millis.max(1) as u32;
+        let [fine, rough] = tuning::envelope_period(clock, millihertz).to_le_bytes();
+        psg.update_register(0xB, fine)?;
+        psg.update_register(0xC, rough)?;
+        psg.set_channel_mixer(self.channel, true, false)?;
+        psg.update_channel_level(self.channel, ChannelLevel::Envelope)?;
+        // Always written: that's what restarts the envelope.
+        psg.set_register_value(0xD, DECAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    fn count_beats(metronome: &mut Metronome, ticks: u32) -> u32 {
+        let mut psg = FakePsg::new();
+        metronome.start();
+        (0..ticks)
+            .filter(|_| metronome.tick(&mut psg).unwrap().is_some())
+            .count() as u32
+    }
+
+    #[test]
+    fn whole_ticks_per_beat_land_exactly() {
+        let mut psg = FakePsg::new();
+        // 120 BPM at 50 Hz is 25 ticks a beat.
+        let mut metronome = Metronome::new(120, 3);
+        metronome.start();
+        let beats: [Option<u8>; 76] = core::array::from_fn(|_| metronome.tick(&mut psg).unwrap());
+        for (tick, beat) in beats.iter().enumerate() {
+            let expected = (tick % 25 == 0).then_some((tick / 25 % 3) as u8);
+            assert_eq!(*beat, expected, "tick {tick}");
+        }
+    }
+
+    #[test]
+    fn no_drift_over_minutes() {
+        // (bpm, tick rate, seconds)
+        for (bpm, rate, seconds) in [
+            (97, 50, 600),
+            (133, 60, 900),
+            (180, 200, 120),
+            (61, 50, 3600),
+        ] {
+            let ticks = rate * seconds;
+            let beats = count_beats(&mut Metronome::new(bpm, 4).with_tick_rate(rate), ticks);
+            // Beat 0 is at tick 0, and one beat lasts 60 * rate / bpm ticks.
+            let expected = (ticks as u64 * bpm as u64).div_ceil(60 * rate as u64) as u32;
+            assert!(
+                beats.abs_diff(expected) <= 1,
+                "{bpm} BPM: {beats} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn tempo_changes_keep_the_phase() {
+        let mut psg = FakePsg::new();
+        let mut metronome = Metronome::new(120, 4);
+        metronome.start();
+        for _ in 0..13 {
+            metronome.tick(&mut psg).unwrap();
+        }
+        // Halfway through beat 0, halve the tempo: the remaining half beat
+        // now takes 25 ticks rather than 13.
+        metronome.set_tempo(60);
+        let next = (1..=60)
+            .find(|_| metronome.tick(&mut psg).unwrap().is_some())
+            .unwrap();
+        assert_eq!(next, 25);
+    }
+
+    #[test]
+    fn accent_sounds_different_and_retriggers() {
+        static BEATS: AtomicU32 = AtomicU32::new(0);
+        let mut psg = FakePsg::new();
+        let mut metronome = Metronome::new(120, 2).with_channel(Channel::B);
+        metronome.on_beat(|beat| {
+            BEATS.fetch_add(1 + beat as u32 * 10, Ordering::Relaxed);
+        });
+        metronome.start();
+        metronome.tick(&mut psg).unwrap();
+        let accent = psg.registers().tone_period(Channel::B);
+        assert_eq!(psg.registers().value(0x9), 0x10);
+        assert_eq!(psg.writes.last(), Some(&(0xD, DECAY)));
+        for _ in 0..25 {
+            metronome.tick(&mut psg).unwrap();
+        }
+        assert!(psg.registers().tone_period(Channel::B) > accent);
+        assert_eq!(BEATS.load(Ordering::Relaxed), 1 + 11);
+    }
+}
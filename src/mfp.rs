@@ -0,0 +1,64 @@
+//! The Atari ST's MC68901 MFP timers, which YM files use to time their
+//! special effects.
+//!
+//! A YM file gives each effect's timer as the two values the ST player
+//! programmed: a 3-bit control value selecting the prescaler and an 8-bit
+//! count. [`MfpTimer`] keeps them as they are and turns them into a rate.
+
+/// The MFP's input clock on the ST.
+pub const MFP_CLOCK: u32 = 2_457_600;
+
+/// The prescaler for each control value. 0 stops the timer.
+pub const PRESCALERS: [u16; 8] = [0, 4, 10, 16, 50, 64, 100, 200];
+
+/// A timer setting as stored in YM files.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MfpTimer {
+    pub control: u8,
+    pub count: u8,
+}
+
+impl MfpTimer {
+    pub const fn new(control: u8, count: u8) -> MfpTimer {
+        MfpTimer { control, count }
+    }
+
+    pub const fn prescaler(self) -> u16 {
+        PRESCALERS[(self.control & 0b111) as usize]
+    }
+
+    pub const fn is_stopped(self) -> bool {
+        self.prescaler() == 0
+    }
+
+    /// Interrupts per second, in millihertz, or 0 for a stopped timer. A
+    /// count of 0 counts 256, as on the chip.
+    pub const fn millihertz(self) -> u32 {
+        let prescaler = self.prescaler() as u64;
+        if prescaler == 0 {
+            return 0;
+        }
+        let count = if self.count == 0 {
+            256
+        } else {
+            self.count as u64
+        };
+        let divisor = prescaler * count;
+        ((MFP_CLOCK as u64 * 1000 + divisor / 2) / divisor) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_follow_prescaler_and_count() {
+        assert_eq!(MfpTimer::new(1, 1).millihertz(), 614_400_000);
+        // 2457600 / (200 * 256)
+        assert_eq!(MfpTimer::new(7, 0).millihertz(), 48_000);
+        assert_eq!(MfpTimer::new(4, 3).millihertz(), 16_384_000);
+        assert!(MfpTimer::new(0, 10).is_stopped());
+        assert_eq!(MfpTimer::new(8, 10).millihertz(), 0);
+    }
+}
@@ -0,0 +1,336 @@
+//! A byte-at-a-time MIDI parser, for playing the chip from a DIN socket on a
+//! UART.
+
+use crate::pitch::Pitch;
+use crate::voices::{VoiceAllocator, VoiceEngine};
+
+/// The controller that silences every note.
+pub const ALL_NOTES_OFF: u8 = 123;
+
+/// Which MIDI channels to act on. Channels are numbered 0 to 15 here, which
+/// most gear labels 1 to 16.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Listen {
+    Omni,
+    Channel(u8),
+}
+
+impl Listen {
+    pub const fn accepts(self, channel: u8) -> bool {
+        match self {
+            Listen::Omni => true,
+            Listen::Channel(listening) => listening == channel,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOff {
+        key: u8,
+        velocity: u8,
+    },
+    /// Always has a non-zero velocity: a note-on at velocity 0 arrives as a
+    /// [`MidiMessage::NoteOff`].
+    NoteOn {
+        key: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        key: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange(u8),
+    ChannelPressure(u8),
+    /// 14 bits, centred on `0x2000`.
+    PitchBend(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MidiEvent {
+    pub channel: u8,
+    pub message: MidiMessage,
+}
+
+/// Data bytes that follow a channel voice status byte.
+pub(crate) const fn data_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
+
+/// The message a channel voice status byte and its data bytes make up. The
+/// second byte is ignored for one-byte messages.
+pub(crate) const fn decode(status: u8, first: u8, second: u8) -> MidiMessage {
+    match status & 0xF0 {
+        0x80 => MidiMessage::NoteOff {
+            key: first,
+            velocity: second,
+        },
+        0x90 if second == 0 => MidiMessage::NoteOff {
+            key: first,
+            velocity: 0,
+        },
+        0x90 => MidiMessage::NoteOn {
+            key: first,
+            velocity: second,
+        },
+        0xA0 => MidiMessage::PolyPressure {
+            key: first,
+            pressure: second,
+        },
+        0xB0 => MidiMessage::ControlChange {
+            controller: first,
+            value: second,
+        },
+        0xC0 => MidiMessage::ProgramChange(first),
+        0xD0 => MidiMessage::ChannelPressure(first),
+        _ => MidiMessage::PitchBend(first as u16 | (second as u16) << 7),
+    }
+}
+
+/// Turns a stream of MIDI bytes into [`MidiEvent`]s.
+///
+/// Running status is followed. Real-time bytes (clock, start, stop and so
+/// on) may arrive anywhere, even mid-message, and are dropped without
+/// disturbing it. System exclusive and system common messages are skipped:
+/// they cancel running status, and data bytes are ignored until the next
+/// channel status byte. A message cut short by a new status byte is
+/// dropped, so the parser always resynchronises on the next status byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiInput {
+    listen: Listen,
+    status: Option<u8>,
+    data: [u8; 2],
+    received: usize,
+}
+
+impl MidiInput {
+    pub const fn new(listen: Listen) -> MidiInput {
+        MidiInput {
+            listen,
+            status: None,
+            data: [0; 2],
+            received: 0,
+        }
+    }
+
+    pub fn set_listen(&mut self, listen: Listen) {
+        self.listen = listen;
+    }
+
+    /// Takes the next byte, returning an event if it completed one on a
+    /// channel being listened to.
+    pub fn push(&mut self, byte: u8) -> Option<MidiEvent> {
+        if byte >= 0xF8 {
+            return None;
+        }
+        if byte >= 0xF0 {
+            self.status = None;
+            return None;
+        }
+        if byte >= 0x80 {
+            self.status = Some(byte);
+            self.received = 0;
+            return None;
+        }
+        let status = self.status?;
+        self.data[self.received] = byte;
+        self.received += 1;
+        if self.received < data_length(status) {
+            return None;
+        }
+        self.received = 0;
+        let channel = status & 0x0F;
+        if !self.listen.accepts(channel) {
+            return None;
+        }
+        let [first, second] = self.data;
+        let message = decode(status, first, second);
+        Some(MidiEvent { channel, message })
+    }
+
+    /// Takes the next byte and plays any note event it completes on `voices`,
+    /// using the velocity as the note's priority. The event is returned as
+    /// well, for anything else the firmware wants to do with it.
+    pub fn feed<E: VoiceEngine + Clone>(
+        &mut self,
+        byte: u8,
+        voices: &mut VoiceAllocator<E>,
+    ) -> Option<MidiEvent> {
+        let event = self.push(byte)?;
+        apply(event, voices);
+        Some(event)
+    }
+}
+
+/// Plays `event` on `voices`: notes on and off, and all notes off.
+pub fn apply<E: VoiceEngine + Clone>(event: MidiEvent, voices: &mut VoiceAllocator<E>) {
+    match event.message {
+        MidiMessage::NoteOn { key, velocity } => {
+            if let Some(pitch) = Pitch::from_midi(key) {
+                voices.note_on(pitch, velocity);
+            }
+        }
+        MidiMessage::NoteOff { key, .. } => {
+            if let Some(pitch) = Pitch::from_midi(key) {
+                voices.note_off(pitch);
+            }
+        }
+        MidiMessage::ControlChange {
+            controller: ALL_NOTES_OFF,
+            ..
+        } => voices.all_notes_off(),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::adsr::Adsr;
+    use crate::voices::StealPolicy;
+    use crate::Channel;
+    use std::vec::Vec;
+
+    fn parse(input: &mut MidiInput, bytes: &[u8]) -> Vec<MidiEvent> {
+        bytes.iter().filter_map(|&byte| input.push(byte)).collect()
+    }
+
+    fn on(channel: u8, key: u8, velocity: u8) -> MidiEvent {
+        MidiEvent {
+            channel,
+            message: MidiMessage::NoteOn { key, velocity },
+        }
+    }
+
+    fn off(channel: u8, key: u8, velocity: u8) -> MidiEvent {
+        MidiEvent {
+            channel,
+            message: MidiMessage::NoteOff { key, velocity },
+        }
+    }
+
+    #[test]
+    fn running_status_and_zero_velocity() {
+        let mut input = MidiInput::new(Listen::Omni);
+        let events = parse(
+            &mut input,
+            &[0x91, 60, 100, 64, 90, 60, 0, 0xE1, 0x00, 0x40],
+        );
+        assert_eq!(
+            events,
+            [
+                on(1, 60, 100),
+                on(1, 64, 90),
+                off(1, 60, 0),
+                MidiEvent {
+                    channel: 1,
+                    message: MidiMessage::PitchBend(0x2000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_filter() {
+        let mut input = MidiInput::new(Listen::Channel(2));
+        let events = parse(
+            &mut input,
+            &[0x90, 60, 100, 0x92, 62, 100, 0xC2, 5, 0x80, 60, 0],
+        );
+        assert_eq!(
+            events,
+            [
+                on(2, 62, 100),
+                MidiEvent {
+                    channel: 2,
+                    message: MidiMessage::ProgramChange(5),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn resynchronises_through_garbage() {
+        let mut input = MidiInput::new(Listen::Omni);
+        #[rustfmt::skip]
+        let stream = [
+            0x12, 0x34,             // data with no status yet
+            0x90, 60, 0xF8, 100,    // a clock byte in the middle of a note
+            0x90, 61,               // cut short by...
+            0xF0, 0x7E, 0x90, 0xF7, // ...a sysex, with a stray data byte
+            62, 100,                // running status was cancelled
+            0xF2, 0x10, 0x20,       // song position, skipped
+            0x80, 60, 64,
+            0xFE, 0xB0, 0xFF, 123, 0, // all notes off, around real-time bytes
+            0x90, 64, 100,
+        ];
+        assert_eq!(
+            parse(&mut input, &stream),
+            [
+                on(0, 60, 100),
+                off(0, 60, 64),
+                MidiEvent {
+                    channel: 0,
+                    message: MidiMessage::ControlChange {
+                        controller: ALL_NOTES_OFF,
+                        value: 0,
+                    },
+                },
+                on(0, 64, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn random_bytes_never_wedge_the_parser() {
+        let mut input = MidiInput::new(Listen::Omni);
+        let mut seed = 0x1234_5678u32;
+        for _ in 0..2000 {
+            for _ in 0..(seed % 7) {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                input.push(seed as u8);
+            }
+            assert_eq!(parse(&mut input, &[0x93, 70, 1]), [on(3, 70, 1)]);
+        }
+    }
+
+    #[test]
+    fn feeds_the_voice_allocator() {
+        let mut voices = VoiceAllocator::new(Adsr::new(0, 0, 12, 0), StealPolicy::Oldest);
+        let mut input = MidiInput::new(Listen::Omni);
+        for byte in [0x90, 60, 100, 62, 100, 60, 0] {
+            input.feed(byte, &mut voices);
+        }
+        let c4 = Pitch::from_midi(60).unwrap();
+        let d4 = Pitch::from_midi(62).unwrap();
+        assert_eq!(voices.channel_of(d4), Some(Channel::B));
+        assert_eq!(voices.channel_of(c4), Some(Channel::A));
+        for byte in [0xB0, ALL_NOTES_OFF, 0] {
+            input.feed(byte, &mut voices);
+        }
+        // Both releasing now: once C is taken, the next note goes to the
+        // older A.
+        for byte in [0x90, 64, 100, 65, 100] {
+            input.feed(byte, &mut voices);
+        }
+        assert_eq!(
+            voices.channel_of(Pitch::from_midi(64).unwrap()),
+            Some(Channel::C)
+        );
+        assert_eq!(
+            voices.channel_of(Pitch::from_midi(65).unwrap()),
+            Some(Channel::A)
+        );
+    }
+}
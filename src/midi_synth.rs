@@ -0,0 +1,395 @@
+//! A MIDI-driven synth: programs choose instruments, velocity and channel
+//! volume set loudness, the mod wheel adds vibrato and pitch-bend bends.
+
+use crate::effect::ChannelCtx;
+use crate::glissando::div_round;
+use crate::instrument::{Instrument, InstrumentPlayer};
+use crate::lfo::LfoWaveform;
+use crate::midi::{Listen, MidiEvent, MidiInput, MidiMessage, ALL_NOTES_OFF};
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::vibrato::Vibrato;
+use crate::voices::{StealPolicy, VoiceAllocator, VoiceEngine};
+use crate::volume;
+use crate::{Channel, ChannelLevel};
+
+/// The mod wheel controller.
+pub const MODULATION: u8 = 1;
+/// The channel volume controller.
+pub const VOLUME: u8 = 7;
+/// The sustain pedal controller.
+pub const SUSTAIN: u8 = 64;
+
+/// Where pitch-bend rests.
+const BEND_CENTRE: i32 = 0x2000;
+
+/// How a [`MidiSynth`] turns MIDI into sound. Fill one in with references to
+/// your own instruments, starting from [`MidiMapping::DEFAULT`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MidiMapping {
+    /// Instruments by program number. Program changes past the end are
+    /// ignored; every channel starts on program 0, or on
+    /// [`Instrument::DEFAULT`] if the table is empty.
+    pub programs: &'static [&'static Instrument],
+    /// Semitones either way at full pitch-bend.
+    pub bend_range: u8,
+    /// Vibrato depth in cents with the mod wheel all the way up.
+    pub vibrato_depth: u16,
+    /// Ticks per vibrato cycle.
+    pub vibrato_rate: u16,
+    pub steal_policy: StealPolicy,
+}
+
+impl MidiMapping {
+    pub const DEFAULT: MidiMapping = MidiMapping {
+        programs: &[],
+        bend_range: 2,
+        vibrato_depth: 50,
+        vibrato_rate: 10,
+        steal_policy: StealPolicy::Oldest,
+    };
+
+    fn program(&self, program: u8) -> Option<&'static Instrument> {
+        match self.programs {
+            [] if program == 0 => Some(&Instrument::DEFAULT),
+            programs => programs.get(program as usize).copied(),
+        }
+    }
+}
+
+/// The controllers of one MIDI channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ChannelState {
+    instrument: &'static Instrument,
+    volume: u8,
+    modulation: u8,
+    bend: u16,
+}
+
+/// The settings a note is started with.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct NoteSettings {
+    instrument: &'static Instrument,
+    owner: u8,
+    velocity: u8,
+}
+
+/// Plays one note of a [`MidiSynth`] through an [`InstrumentPlayer`], with
+/// bend and vibrato on its pitch and velocity and channel volume on its
+/// level.
+///
+/// A note's settings are handed over when it is allocated but only taken up
+/// when it starts, so a note fading out to make way keeps sounding as it
+/// did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiVoice {
+    player: InstrumentPlayer,
+    next: NoteSettings,
+    settings: NoteSettings,
+    note: Option<Pitch>,
+    restart: bool,
+    volume: u8,
+    cents: i32,
+    vibrato: Vibrato,
+    fade: Option<(u16, u16)>,
+    level: u8,
+}
+
+impl MidiVoice {
+    fn new(instrument: &'static Instrument, mapping: &MidiMapping) -> MidiVoice {
+        let settings = NoteSettings {
+            instrument,
+            owner: 0,
+            velocity: 127,
+        };
+        MidiVoice {
+            player: InstrumentPlayer::new(instrument),
+            next: settings,
+            settings,
+            note: None,
+            restart: false,
+            volume: 127,
+            cents: 0,
+            vibrato: Vibrato::new(Channel::A, 0, mapping.vibrato_rate, LfoWaveform::Sine),
+            fade: None,
+            level: 0,
+        }
+    }
+
+    /// The MIDI channel the note came from.
+    pub fn owner(&self) -> u8 {
+        self.settings.owner
+    }
+
+    pub fn instrument(&self) -> &'static Instrument {
+        self.settings.instrument
+    }
+}
+
+impl VoiceEngine for MidiVoice {
+    fn start<P: Psg>(&mut self, _: &mut P, _: Channel, pitch: Pitch) -> Result<(), P::Error> {
+        self.note = Some(pitch);
+        self.restart = true;
+        Ok(())
+    }
+
+    fn retrigger(&mut self) {
+        self.restart = true;
+    }
+
+    fn note_off(&mut self) {
+        self.player.note_off();
+    }
+
+    fn cut(&mut self, ticks: u16) {
+        self.restart = false;
+        if ticks == 0 {
+            self.player.stop();
+        } else if self.player.is_playing() {
+            self.fade = Some((0, ticks));
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.restart || self.player.is_playing()
+    }
+
+    fn level(&self) -> u8 {
+        self.level
+    }
+
+    fn tick<P: Psg>(&mut self, psg: &mut P, channel: Channel) -> Result<(), P::Error> {
+        if self.restart {
+            self.restart = false;
+            self.settings = self.next;
+            self.fade = None;
+            self.vibrato.retrigger();
+            self.player.set_instrument(self.settings.instrument);
+            if let Some(pitch) = self.note {
+                self.player.note_on(pitch);
+            }
+        }
+        let Some(frame) = self.player.next_frame() else {
+            self.level = 0;
+            return psg.update_channel_level(channel, ChannelLevel::Fixed(0));
+        };
+        let mut quieter =
+            volume::attenuation(self.settings.velocity) + volume::attenuation(self.volume);
+        if let Some((done, length)) = &mut self.fade {
+            *done += 1;
+            quieter += (15 * *done / *length).min(15) as u8;
+            if *done >= *length {
+                self.player.stop();
+            }
+        }
+        self.level = frame.level.saturating_sub(quieter);
+
+        let mut ctx = ChannelCtx::new(
+            channel,
+            psg.master_clock(),
+            Some(frame.pitch),
+            ChannelLevel::Fixed(self.level),
+        );
+        ctx.add_cents(self.cents + self.vibrato.next_cents());
+        if let (true, Some(period)) = (frame.tone, ctx.period()) {
+            psg.set_channel_period(channel, period)?;
+        }
+        if let Some(period) = frame.noise {
+            psg.update_register(0x6, period)?;
+        }
+        psg.set_channel_mixer(channel, frame.tone, frame.noise.is_some())?;
+        psg.update_channel_level(channel, ChannelLevel::Fixed(self.level))
+    }
+}
+
+/// Plays a MIDI stream on the chip's three channels.
+///
+/// Notes are shared out by a [`VoiceAllocator`], using the velocity as the
+/// note's priority. Each of the 16 MIDI channels keeps its own program,
+/// volume (CC 7), mod wheel (CC 1) and pitch-bend, which apply to its notes
+/// straight away, even ones already sounding. The sustain pedal (CC 64) and
+/// all notes off (CC 123) act on every note. Anything else is ignored, as is
+/// a program change to a program the mapping doesn't have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiSynth {
+    mapping: MidiMapping,
+    input: MidiInput,
+    voices: VoiceAllocator<MidiVoice>,
+    channels: [ChannelState; 16],
+}
+
+impl MidiSynth {
+    pub fn new(mapping: MidiMapping, listen: Listen) -> MidiSynth {
+        let instrument = mapping.program(0).unwrap_or(&Instrument::DEFAULT);
+        let state = ChannelState {
+            instrument,
+            volume: 100,
+            modulation: 0,
+            bend: BEND_CENTRE as u16,
+        };
+        MidiSynth {
+            mapping,
+            input: MidiInput::new(listen),
+            voices: VoiceAllocator::new(MidiVoice::new(instrument, &mapping), mapping.steal_policy),
+            channels: [state; 16],
+        }
+    }
+
+    /// The allocator, for reserving channels or changing the policy.
+    pub fn voices(&self) -> &VoiceAllocator<MidiVoice> {
+        &self.voices
+    }
+
+    pub fn voices_mut(&mut self) -> &mut VoiceAllocator<MidiVoice> {
+        &mut self.voices
+    }
+
+    /// The instrument MIDI channel `channel` plays new notes on.
+    pub fn instrument(&self, channel: u8) -> &'static Instrument {
+        self.channels[channel as usize & 0xF].instrument
+    }
+
+    /// Takes the next MIDI byte and acts on any event it completes. The event
+    /// is returned as well, for anything else the firmware wants to do with
+    /// it.
+    pub fn feed(&mut self, byte: u8) -> Option<MidiEvent> {
+        let event = self.input.push(byte)?;
+        self.handle(event);
+        Some(event)
+    }
+
+    /// Acts on an event, parsed elsewhere.
+    pub fn handle(&mut self, event: MidiEvent) {
+        let state = &mut self.channels[event.channel as usize & 0xF];
+        match event.message {
+            MidiMessage::NoteOn { key, velocity } => {
+                let Some(pitch) = Pitch::from_midi(key) else {
+                    return;
+                };
+                if let Some(channel) = self.voices.note_on(pitch, velocity) {
+                    self.voices.engine_mut(channel).next = NoteSettings {
+                        instrument: state.instrument,
+                        owner: event.channel,
+                        velocity,
+                    };
+                }
+            }
+            MidiMessage::NoteOff { key, .. } => {
+                if let Some(pitch) = Pitch::from_midi(key) {
+                    self.voices.note_off(pitch);
+                }
+            }
+            MidiMessage::ControlChange { controller, value } => match controller {
+                MODULATION => state.modulation = value,
+                VOLUME => state.volume = value,
+                SUSTAIN => self.voices.set_sustain(value >= 64),
+                ALL_NOTES_OFF => self.voices.all_notes_off(),
+                _ => {}
+            },
+            MidiMessage::ProgramChange(program) => {
+                if let Some(instrument) = self.mapping.program(program) {
+                    state.instrument = instrument;
+                }
+            }
+            MidiMessage::PitchBend(bend) => state.bend = bend,
+            _ => {}
+        }
+    }
+
+    /// Advances every note and writes the channels.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        for channel in Channel::ALL {
+            let voice = self.voices.engine_mut(channel);
+            let state = &self.channels[voice.settings.owner as usize & 0xF];
+            let range = self.mapping.bend_range as i32 * 100;
+            voice.volume = state.volume;
+            voice.cents = div_round((state.bend as i32 - BEND_CENTRE) * range, BEND_CENTRE);
+            voice.vibrato.set_depth(div_round(
+                self.mapping.vibrato_depth as i32 * state.modulation as i32,
+                127,
+            ) as u16);
+        }
+        self.voices.tick(psg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::presets::{BASS, LEAD};
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use crate::tuning;
+
+    const PROGRAMS: &[&Instrument] = &[&LEAD, &BASS];
+
+    fn play(synth: &mut MidiSynth, psg: &mut FakePsg, bytes: &[u8], ticks: usize) {
+        for &byte in bytes {
+            synth.feed(byte);
+        }
+        for _ in 0..ticks {
+            synth.tick(psg).unwrap();
+        }
+    }
+
+    #[test]
+    fn scripted_performance() {
+        let mut psg = FakePsg::new();
+        let mut synth = MidiSynth::new(
+            MidiMapping {
+                programs: PROGRAMS,
+                ..MidiMapping::DEFAULT
+            },
+            Listen::Omni,
+        );
+        let clock = psg.master_clock();
+        let c4 = tuning::fold_pitch_period(clock, Pitch::new(Note::C, 4)).period;
+
+        // Bass on channel 1, forte: the octave pop, then the plain note with
+        // CC 7 at its default of 100 taking a step off.
+        play(&mut synth, &mut psg, &[0xC1, 1, 0x91, 60, 127], 1);
+        let c5 = tuning::fold_pitch_period(clock, Pitch::new(Note::C, 5)).period;
+        assert_eq!(psg.registers().tone_period(Channel::A), c5);
+        assert_eq!(psg.registers().value(0x8), 14);
+        play(&mut synth, &mut psg, &[], 1);
+        assert_eq!(psg.registers().tone_period(Channel::A), c4);
+        assert_eq!(psg.registers().value(0x8), 13);
+
+        // The lead on channel 0, played softly, at half velocity is four
+        // steps down.
+        play(&mut synth, &mut psg, &[0x90, 64, 64], 1);
+        assert_eq!(synth.voices().engine(Channel::B).instrument(), &LEAD);
+        assert_eq!(psg.registers().value(0x9), 11 - 1 - 4);
+
+        // Channel volume and pitch-bend only touch channel 1's note.
+        play(
+            &mut synth,
+            &mut psg,
+            &[0xB1, VOLUME, 64, 0xE1, 0x7F, 0x7F],
+            1,
+        );
+        assert_eq!(psg.registers().value(0x8), 12 - 4);
+        let d4 = tuning::fold_pitch_period(clock, Pitch::new(Note::D, 4)).period;
+        assert!(psg.registers().tone_period(Channel::A).abs_diff(d4) <= 1);
+        assert_eq!(psg.registers().value(0x9), 13 - 1 - 4);
+
+        // Unknown programs and controllers change nothing.
+        play(&mut synth, &mut psg, &[0xC0, 99, 0xB0, 20, 5], 0);
+        assert_eq!(synth.instrument(0), &LEAD);
+        assert_eq!(synth.instrument(1), &BASS);
+
+        // The mod wheel brings in vibrato on channel 0 only.
+        let e4 = psg.registers().tone_period(Channel::B);
+        let bent = psg.registers().tone_period(Channel::A);
+        play(&mut synth, &mut psg, &[0xB0, MODULATION, 127], 3);
+        assert_ne!(psg.registers().tone_period(Channel::B), e4);
+        assert_eq!(psg.registers().tone_period(Channel::A), bent);
+
+        // Releasing the bass plays its release table, scaled too.
+        play(&mut synth, &mut psg, &[0x81, 60, 0], 1);
+        assert_eq!(psg.registers().value(0x8), 7 - 4);
+        play(&mut synth, &mut psg, &[], 3);
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(synth.voices().channel_of(Pitch::new(Note::C, 4)), None);
+    }
+}
@@ -0,0 +1,714 @@
+//! MML, Music Macro Language: tunes as text, one string per channel, as in
+//! `t120 o4 l8 cdefgab>c`.
+//!
+//! The subset read here:
+//!
+//! | Command | Meaning |
+//! |---|---|
+//! | `c` to `b`, then `+`, `#` or `-`, a length and dots | A note, sharp or flat |
+//! | `r` with a length and dots | A rest |
+//! | `o`*n*, `>`, `<` | Set the octave (0 to 8), go up one, go down one |
+//! | `l`*n* and dots | The length of notes that don't give one |
+//! | `t`*n* | The tempo, in quarter notes a minute |
+//! | `v`*n* | The volume, 0 to 15 |
+//! | `[` ... `]`*n* | Play what's between *n* times, 2 if left out |
+//! | `&` | Tie the next note, which must be the same pitch, on |
+//!
+//! Lengths are fractions of a whole note, 1 to 64, and each dot adds half
+//! as much again as the last. Commands are case-insensitive and spaces
+//! between them are ignored. Octaves are scientific, so `o4 a` is concert
+//! A; `>` and `<` stop at the ends of the range rather than failing.
+//!
+//! A track starts at `o4 l4 t120 v15`. Tempo belongs to the track: a `t`
+//! changes only the track it is in, from where it is, so give every track
+//! the same tempo changes to keep them together.
+
+use crate::instrument::{apply_frame, Instrument, InstrumentPlayer};
+use crate::parse_error::ParseError;
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::{Channel, ChannelLevel};
+
+/// How deeply loops may nest.
+pub const MAX_LOOP_DEPTH: usize = 4;
+
+/// The fastest tempo accepted.
+pub const MAX_TEMPO: u16 = 900;
+
+/// How many commands a track may run with its loops unrolled, so that loops
+/// in loops can't make one that never ends, or spends a tick running
+/// commands without reaching a note.
+pub const MAX_COMMANDS: u32 = 1 << 16;
+
+const DEFAULT_OCTAVE: u8 = 4;
+const DEFAULT_LENGTH: u8 = 4;
+const DEFAULT_TEMPO: u16 = 120;
+const DEFAULT_VOLUME: u8 = 15;
+
+const MAX_OCTAVE: u8 = 8;
+const MAX_LENGTH: u16 = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MmlErrorKind {
+    /// A character that isn't a command.
+    UnknownCommand,
+    /// `o`, `l`, `t` or `v` without a number.
+    MissingNumber,
+    /// A length outside 1 to 64.
+    BadLength,
+    /// An octave above 8.
+    BadOctave,
+    /// A tempo of 0 or above [`MAX_TEMPO`].
+    BadTempo,
+    /// A volume above 15.
+    BadVolume,
+    /// A loop count of 0.
+    BadCount,
+    /// A `]` without a `[`, or a `[` without a `]`.
+    UnmatchedBracket,
+    /// More than [`MAX_LOOP_DEPTH`] loops inside each other.
+    LoopTooDeep,
+    /// More than [`MAX_COMMANDS`] commands once loops are unrolled.
+    TooLong,
+    /// A `&` not followed by a note of the same pitch, or by a rest after a
+    /// rest.
+    BadTie,
+}
+
+/// What is wrong with a track, and the byte it was found at.
+pub type MmlError = ParseError<MmlErrorKind>;
+
+/// A note or rest, with the volume it is played at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MmlNote {
+    /// `None` for a rest.
+    pub pitch: Option<Pitch>,
+    pub ticks: u32,
+    pub volume: u8,
+}
+
+/// One channel's part, checked and borrowing its text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MmlTrack<'a> {
+    text: &'a [u8],
+}
+
+impl<'a> MmlTrack<'a> {
+    /// Checks the whole track, so playing it can't fail.
+    pub fn parse(text: &'a str) -> Result<MmlTrack<'a>, MmlError> {
+        let track = MmlTrack {
+            text: text.as_bytes(),
+        };
+        let mut reader = MmlNotes::new(track.text, 1, false);
+        while reader.read()?.is_some() {}
+        Ok(track)
+    }
+
+    /// The notes and rests, loops unrolled and ties joined, timed in ticks
+    /// at `tick_hertz`. Lengths are rounded so they add up to the track's
+    /// true length rather than drifting.
+    pub fn notes(&self, tick_hertz: u32) -> MmlNotes<'a> {
+        MmlNotes::new(self.text, tick_hertz, true)
+    }
+
+    /// How many ticks the track lasts at `tick_hertz`.
+    pub fn ticks(&self, tick_hertz: u32) -> u64 {
+        self.notes(tick_hertz).map(|note| note.ticks as u64).sum()
+    }
+}
+
+/// A note or rest as written, before it is timed.
+struct Item {
+    /// Semitones above C in the current octave, or `None` for a rest.
+    semitone: Option<i8>,
+    micros: u64,
+}
+
+/// The notes of an [`MmlTrack`], from [`MmlTrack::notes`].
+#[derive(Debug, Clone)]
+pub struct MmlNotes<'a> {
+    text: &'a [u8],
+    position: usize,
+    /// Whether `]` jumps back; off while checking.
+    expand: bool,
+    octave: u8,
+    length: (u8, u8),
+    tempo: u16,
+    volume: u8,
+    /// Where each open loop starts, just past its `[`, and how many more
+    /// times it goes round once its count is known.
+    loops: [(usize, Option<u16>); MAX_LOOP_DEPTH],
+    depth: usize,
+    /// Commands run, loops unrolled, outside any loop and in the body of
+    /// each open one; only counted while checking.
+    commands: [u32; MAX_LOOP_DEPTH + 1],
+    tick_hertz: u32,
+    micros: u64,
+    ticks: u64,
+}
+
+impl<'a> MmlNotes<'a> {
+    fn new(text: &'a [u8], tick_hertz: u32, expand: bool) -> MmlNotes<'a> {
+        MmlNotes {
+            text,
+            position: 0,
+            expand,
+            octave: DEFAULT_OCTAVE,
+            length: (DEFAULT_LENGTH, 0),
+            tempo: DEFAULT_TEMPO,
+            volume: DEFAULT_VOLUME,
+            loops: [(0, None); MAX_LOOP_DEPTH],
+            depth: 0,
+            commands: [0; MAX_LOOP_DEPTH + 1],
+            tick_hertz,
+            micros: 0,
+            ticks: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text
+            .get(self.position)
+            .map(|byte| byte.to_ascii_lowercase())
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    /// The number here, if there is one, saturating at `u16::MAX`.
+    fn number(&mut self) -> Option<u16> {
+        let start = self.position;
+        let mut value: u16 = 0;
+        while let Some(digit) = self.peek().filter(u8::is_ascii_digit) {
+            value = value
+                .saturating_mul(10)
+                .saturating_add((digit - b'0') as u16);
+            self.position += 1;
+        }
+        (self.position > start).then_some(value)
+    }
+
+    fn required(
+        &mut self,
+        range: core::ops::RangeInclusive<u16>,
+        kind: MmlErrorKind,
+    ) -> Result<u16, MmlError> {
+        let at = self.position;
+        let value = self
+            .number()
+            .ok_or(MmlError::new(MmlErrorKind::MissingNumber, at))?;
+        if range.contains(&value) {
+            Ok(value)
+        } else {
+            Err(MmlError::new(kind, at))
+        }
+    }
+
+    /// A length and its dots, or `None` if neither is given.
+    fn length(&mut self) -> Result<Option<(u8, u8)>, MmlError> {
+        let at = self.position;
+        let length = match self.number() {
+            Some(value @ 1..=MAX_LENGTH) => Some(value as u8),
+            Some(_) => return Err(MmlError::new(MmlErrorKind::BadLength, at)),
+            None => None,
+        };
+        let mut dots: u8 = 0;
+        while self.peek() == Some(b'.') {
+            dots = dots.saturating_add(1);
+            self.position += 1;
+        }
+        Ok(match (length, dots) {
+            (None, 0) => None,
+            (None, dots) => Some((self.length.0, dots)),
+            (Some(length), dots) => Some((length, dots)),
+        })
+    }
+
+    fn micros(&self, (length, dots): (u8, u8)) -> u64 {
+        let mut part = 240_000_000 / self.tempo as u64 / length as u64;
+        let mut micros = part;
+        for _ in 0..dots {
+            part /= 2;
+            micros += part;
+        }
+        micros
+    }
+
+    /// The note or rest starting here, without any tie.
+    fn item(&mut self, command: u8) -> Result<Item, MmlError> {
+        let semitone = match command {
+            b'c' => Some(0),
+            b'd' => Some(2),
+            b'e' => Some(4),
+            b'f' => Some(5),
+            b'g' => Some(7),
+            b'a' => Some(9),
+            b'b' => Some(11),
+            _ => None,
+        };
+        let semitone = semitone.map(|semitone| match self.peek() {
+            Some(b'+' | b'#') => {
+                self.position += 1;
+                semitone + 1
+            }
+            Some(b'-') => {
+                self.position += 1;
+                semitone - 1
+            }
+            _ => semitone,
+        });
+        let length = self.length()?.unwrap_or(self.length);
+        Ok(Item {
+            semitone,
+            micros: self.micros(length),
+        })
+    }
+
+    /// Runs commands up to the next note or rest.
+    fn read(&mut self) -> Result<Option<MmlNote>, MmlError> {
+        loop {
+            self.skip_spaces();
+            let at = self.position;
+            let Some(command) = self.peek() else {
+                if self.depth > 0 {
+                    let start = self.loops[self.depth - 1].0;
+                    return Err(MmlError::new(MmlErrorKind::UnmatchedBracket, start - 1));
+                }
+                return Ok(None);
+            };
+            self.position += 1;
+            if !self.expand {
+                self.commands[self.depth] = self.commands[self.depth].saturating_add(1);
+                self.limit(at)?;
+            }
+            match command {
+                b'a'..=b'g' | b'r' => {
+                    let mut item = self.item(command)?;
+                    loop {
+                        self.skip_spaces();
+                        if self.peek() != Some(b'&') {
+                            break;
+                        }
+                        self.position += 1;
+                        self.skip_spaces();
+                        let tied_at = self.position;
+                        let tied = match self.peek() {
+                            Some(tied @ (b'a'..=b'g' | b'r')) => {
+                                self.position += 1;
+                                self.item(tied)?
+                            }
+                            _ => return Err(MmlError::new(MmlErrorKind::BadTie, tied_at)),
+                        };
+                        if tied.semitone != item.semitone {
+                            return Err(MmlError::new(MmlErrorKind::BadTie, tied_at));
+                        }
+                        item.micros += tied.micros;
+                    }
+                    return Ok(Some(self.time(item)));
+                }
+                b'o' => {
+                    self.octave =
+                        self.required(0..=MAX_OCTAVE as u16, MmlErrorKind::BadOctave)? as u8
+                }
+                b'>' => self.octave = (self.octave + 1).min(MAX_OCTAVE),
+                b'<' => self.octave = self.octave.saturating_sub(1),
+                b'l' => {
+                    self.length = self
+                        .length()?
+                        .ok_or(MmlError::new(MmlErrorKind::MissingNumber, at + 1))?;
+                }
+                b't' => self.tempo = self.required(1..=MAX_TEMPO, MmlErrorKind::BadTempo)?,
+                b'v' => self.volume = self.required(0..=15, MmlErrorKind::BadVolume)? as u8,
+                b'[' => {
+                    if self.depth == MAX_LOOP_DEPTH {
+                        return Err(MmlError::new(MmlErrorKind::LoopTooDeep, at));
+                    }
+                    self.loops[self.depth] = (self.position, None);
+                    self.depth += 1;
+                    self.commands[self.depth] = 0;
+                }
+                b']' => {
+                    if self.depth == 0 {
+                        return Err(MmlError::new(MmlErrorKind::UnmatchedBracket, at));
+                    }
+                    let count_at = self.position;
+                    let count = self.number().unwrap_or(2);
+                    if count == 0 {
+                        return Err(MmlError::new(MmlErrorKind::BadCount, count_at));
+                    }
+                    let (start, left) = &mut self.loops[self.depth - 1];
+                    let left = left.get_or_insert(count - 1);
+                    if self.expand && *left > 0 {
+                        *left -= 1;
+                        self.position = *start;
+                    } else {
+                        self.depth -= 1;
+                    }
+                    if !self.expand {
+                        // The body, `]` and all, runs `count` times.
+                        let body = self.commands[self.depth + 1].saturating_mul(count as u32);
+                        self.commands[self.depth + 1] = 0;
+                        self.commands[self.depth] = self.commands[self.depth].saturating_add(body);
+                        self.limit(at)?;
+                    }
+                }
+                _ => return Err(MmlError::new(MmlErrorKind::UnknownCommand, at)),
+            }
+        }
+    }
+
+    /// Fails at `at` once the commands counted pass [`MAX_COMMANDS`].
+    fn limit(&self, at: usize) -> Result<(), MmlError> {
+        let commands = self
+            .commands
+            .iter()
+            .fold(0u32, |sum, &n| sum.saturating_add(n));
+        match commands > MAX_COMMANDS {
+            true => Err(MmlError::new(MmlErrorKind::TooLong, at)),
+            false => Ok(()),
+        }
+    }
+
+    fn time(&mut self, item: Item) -> MmlNote {
+        self.micros += item.micros;
+        let end = (self.micros * self.tick_hertz as u64 + 500_000) / 1_000_000;
+        let ticks = end - self.ticks;
+        self.ticks = end;
+        // At most B#8, well within range.
+        let pitch = item
+            .semitone
+            .and_then(|semitone| Pitch::from_midi(((self.octave as i8 + 1) * 12 + semitone) as u8));
+        MmlNote {
+            pitch,
+            ticks: ticks.min(u32::MAX as u64) as u32,
+            volume: self.volume,
+        }
+    }
+}
+
+impl Iterator for MmlNotes<'_> {
+    type Item = MmlNote;
+
+    fn next(&mut self) -> Option<MmlNote> {
+        // Checked when the track was parsed.
+        self.read().ok()?
+    }
+}
+
+/// One channel of an [`MmlPlayer`].
+#[derive(Debug, Clone)]
+struct Part<'a> {
+    track: MmlTrack<'a>,
+    notes: MmlNotes<'a>,
+    voice: InstrumentPlayer,
+    volume: u8,
+    /// Ticks left of the current note.
+    left: u32,
+    finished: bool,
+}
+
+/// Plays up to three [`MmlTrack`]s together on channels A, B and C.
+///
+/// Each note is played with its channel's [`Instrument`], a steady tone by
+/// default, scaled to the track's volume. Its note-off comes a tick before
+/// the next note so repeated notes don't run together, unless it is only a
+/// tick long.
+///
+/// Tracks can be different lengths. Left alone, one that ends early rests
+/// until the longest is done; [`MmlPlayer::with_looping_tracks`] instead
+/// starts it again, cut off wherever it is when the longest ends.
+#[derive(Debug, Clone)]
+pub struct MmlPlayer<'a> {
+    parts: [Option<Part<'a>>; 3],
+    tick_hertz: u32,
+    /// The length of the longest track, once needed.
+    length: Option<u64>,
+    elapsed: u64,
+}
+
+impl<'a> MmlPlayer<'a> {
+    /// Plays `tracks` on channels A, B and C in that order; `None` leaves a
+    /// channel alone.
+    pub fn new(tracks: [Option<MmlTrack<'a>>; 3], tick_hertz: u32) -> MmlPlayer<'a> {
+        MmlPlayer {
+            parts: tracks.map(|track| {
+                track.map(|track| Part {
+                    track,
+                    notes: track.notes(tick_hertz),
+                    voice: InstrumentPlayer::new(&Instrument::DEFAULT),
+                    volume: DEFAULT_VOLUME,
+                    left: 0,
+                    finished: false,
+                })
+            }),
+            tick_hertz,
+            length: None,
+            elapsed: 0,
+        }
+    }
+
+    /// Plays the track on `channel`, if there is one, with `instrument`.
+    pub fn with_instrument(
+        mut self,
+        channel: Channel,
+        instrument: &'static Instrument,
+    ) -> MmlPlayer<'a> {
+        if let Some(part) = &mut self.parts[channel.index()] {
+            part.voice.set_instrument(instrument);
+        }
+        self
+    }
+
+    /// Starts tracks that end before the longest one again.
+    pub fn with_looping_tracks(mut self) -> MmlPlayer<'a> {
+        let tick_hertz = self.tick_hertz;
+        let longest = self
+            .parts
+            .iter()
+            .flatten()
+            .map(|part| part.track.ticks(tick_hertz))
+            .max();
+        self.length = Some(longest.unwrap_or(0));
+        self
+    }
+
+    /// Ticks played so far.
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.parts.iter().flatten().all(|part| part.finished)
+    }
+
+    /// Goes back to the start of every track.
+    pub fn restart(&mut self) {
+        let tick_hertz = self.tick_hertz;
+        for part in self.parts.iter_mut().flatten() {
+            part.notes = part.track.notes(tick_hertz);
+            part.voice.stop();
+            part.left = 0;
+            part.finished = false;
+        }
+        self.elapsed = 0;
+    }
+
+    /// Plays the next tick on every channel. Returns `true` once every track
+    /// has finished, from the tick that silences the last of them.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        if self.is_finished() {
+            return Ok(true);
+        }
+        for (channel, part) in Channel::ALL.into_iter().zip(&mut self.parts) {
+            if let Some(part) = part {
+                part.tick(psg, channel, self.length, self.elapsed, self.tick_hertz)?;
+            }
+        }
+        self.elapsed += 1;
+        Ok(self.is_finished())
+    }
+}
+
+impl Part<'_> {
+    fn tick<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+        length: Option<u64>,
+        elapsed: u64,
+        tick_hertz: u32,
+    ) -> Result<(), P::Error> {
+        if self.finished {
+            return Ok(());
+        }
+        let mut restarted = false;
+        while self.left == 0 || length.is_some_and(|length| elapsed >= length) {
+            let note = match self.notes.next() {
+                Some(note) if length.is_none_or(|length| elapsed < length) => note,
+                // A track with no ticks in it at all can't loop.
+                None if !restarted && length.is_some_and(|length| elapsed < length) => {
+                    self.notes = self.track.notes(tick_hertz);
+                    restarted = true;
+                    continue;
+                }
+                _ => {
+                    self.finished = true;
+                    self.voice.stop();
+                    return psg.update_channel_level(channel, ChannelLevel::Fixed(0));
+                }
+            };
+            self.left = note.ticks;
+            self.volume = note.volume;
+            match note.pitch {
+                Some(pitch) => self.voice.note_on(pitch),
+                None => self.voice.note_off(),
+            }
+        }
+        if self.left == 1 {
+            self.voice.note_off();
+        }
+        self.left -= 1;
+        match self.voice.next_frame() {
+            Some(mut frame) => {
+                frame.level = frame.level * self.volume / 15;
+                apply_frame(psg, channel, &frame)
+            }
+            None => psg.update_channel_level(channel, ChannelLevel::Fixed(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::instrument::Table;
+    use crate::test_support::{mangled, FakePsg};
+    use std::vec::Vec;
+
+    fn notes(text: &str, tick_hertz: u32) -> Vec<(Option<u8>, u32, u8)> {
+        let track = MmlTrack::parse(text).unwrap();
+        track
+            .notes(tick_hertz)
+            .map(|note| (note.pitch.map(Pitch::midi), note.ticks, note.volume))
+            .collect()
+    }
+
+    #[test]
+    fn compiles_a_score_to_notes() {
+        // Eighth notes at 120 BPM are a quarter of a second.
+        assert_eq!(
+            notes("t120 o4 l8 c D+ e- <b >> c4. r16 V8 c&c4 [e g]3", 100),
+            [
+                (Some(60), 25, 15),
+                (Some(63), 25, 15),
+                (Some(63), 25, 15),
+                (Some(59), 25, 15),
+                (Some(72), 75, 15),
+                (None, 13, 15),
+                (Some(72), 75, 8),
+                (Some(76), 25, 8),
+                (Some(79), 25, 8),
+                (Some(76), 25, 8),
+                (Some(79), 25, 8),
+                (Some(76), 25, 8),
+                (Some(79), 25, 8),
+            ]
+        );
+        // Dots each add half the last, `l` takes them too, and the octave
+        // stops at the ends.
+        assert_eq!(
+            notes("l4.. c o8 >>b o0 <c-", 10),
+            [(Some(60), 9, 15), (Some(119), 9, 15), (Some(11), 8, 15)]
+        );
+        // Loops inside loops, and a tempo change partway.
+        let track = MmlTrack::parse("[c[d]2]2 t60 e").unwrap();
+        let pitches: Vec<_> = track
+            .notes(10)
+            .map(|note| note.pitch.unwrap().midi())
+            .collect();
+        assert_eq!(pitches, [60, 62, 62, 60, 62, 62, 64]);
+        assert_eq!(track.ticks(10), 6 * 5 + 10);
+    }
+
+    #[test]
+    fn errors_point_at_the_byte() {
+        let error = |text| {
+            let MmlError { kind, offset } = MmlTrack::parse(text).unwrap_err();
+            (kind, offset)
+        };
+        use MmlErrorKind::*;
+        assert_eq!(error("c x"), (UnknownCommand, 2));
+        assert_eq!(error("co"), (MissingNumber, 2));
+        assert_eq!(error("lc"), (MissingNumber, 1));
+        assert_eq!(error("o9"), (BadOctave, 1));
+        assert_eq!(error("c65"), (BadLength, 1));
+        assert_eq!(error("t0"), (BadTempo, 1));
+        assert_eq!(error("t901"), (BadTempo, 1));
+        assert_eq!(error("v16"), (BadVolume, 1));
+        assert_eq!(error("[c]0"), (BadCount, 3));
+        assert_eq!(error("c]"), (UnmatchedBracket, 1));
+        assert_eq!(error("c [d"), (UnmatchedBracket, 2));
+        assert_eq!(error("[[[[[c]]]]]"), (LoopTooDeep, 4));
+        assert_eq!(error("c&d"), (BadTie, 2));
+        assert_eq!(error("c& o5c"), (BadTie, 3));
+        assert_eq!(error("r&c"), (BadTie, 2));
+        // 64,000 notes, or as many passes over nothing, and a command or
+        // two more.
+        assert_eq!(error("[[[c]40]40]40"), (TooLong, 10));
+        assert_eq!(error("[[[]40]40]40"), (TooLong, 9));
+        assert_eq!(error("[c]32768"), (TooLong, 2));
+        assert!(MmlTrack::parse("[c]32767 c").is_ok());
+        assert!(MmlTrack::parse("").is_ok());
+    }
+
+    #[test]
+    fn damaged_tracks_fail_cleanly() {
+        let track = b"t150 v12 l8 [o4 c e g >c<]3 r4 [[c&c d]2 e]2 a+8.";
+        assert!(MmlTrack::parse(core::str::from_utf8(track).unwrap()).is_ok());
+        for data in mangled(track) {
+            let Ok(text) = core::str::from_utf8(&data) else {
+                continue;
+            };
+            let track = match MmlTrack::parse(text) {
+                Ok(track) => track,
+                Err(error) => {
+                    assert!(error.offset <= text.len());
+                    continue;
+                }
+            };
+            assert!(track.notes(50).count() <= MAX_COMMANDS as usize);
+            let mut psg = FakePsg::new();
+            let mut player = MmlPlayer::new([Some(track), None, Some(track)], 50);
+            for _ in 0..100 {
+                player.tick(&mut psg).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn plays_three_tracks_in_lockstep() {
+        static SWELL: Instrument = Instrument {
+            volume: Table::new(&[5, 15]),
+            ..Instrument::DEFAULT
+        };
+        // Quarter notes at 120 BPM are 5 ticks at 10 Hz. C is half as long.
+        let tracks = ["c c", "v9 l2 e", "g"].map(|text| Some(MmlTrack::parse(text).unwrap()));
+        let play = |mut player: MmlPlayer<'_>| {
+            let mut psg = FakePsg::new();
+            let mut levels = Vec::new();
+            while !player.tick(&mut psg).unwrap() {
+                levels.push([0x8, 0x9, 0xA].map(|address| psg.registers().value(address)));
+            }
+            assert!(psg.registers().values()[0x8..=0xA]
+                .iter()
+                .all(|&level| level == 0));
+            levels
+        };
+
+        let levels = play(MmlPlayer::new(tracks, 10).with_instrument(Channel::B, &SWELL));
+        assert_eq!(levels.len(), 10);
+        assert_eq!(levels[0], [15, 3, 15]);
+        assert_eq!(levels[1], [15, 9, 15]);
+        // A's repeat is cut a tick short, and C stops after its note.
+        assert_eq!(levels[4], [0, 9, 0]);
+        assert_eq!(levels[5], [15, 9, 0]);
+        assert_eq!(levels[9], [0, 0, 0]);
+
+        let levels = play(MmlPlayer::new(tracks, 10).with_looping_tracks());
+        assert_eq!(levels.len(), 10);
+        assert_eq!(levels[5], [15, 9, 15]);
+        assert_eq!(levels[8], [15, 9, 15]);
+
+        // A track that lasts no time at all doesn't loop forever.
+        let empty = [None, Some(MmlTrack::parse("t900 c64").unwrap()), tracks[2]];
+        assert_eq!(
+            play(MmlPlayer::new(empty, 1).with_looping_tracks()).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Channels kept quiet while the rest of a song plays on, for picking a
+//! tune apart or playing along with it.
+//!
+//! A muted channel's level register is written as 0, and with
+//! [`Mute::with_mixer_off`] its tone and noise are switched off as well.
+//! Everything else, tone periods, the noise period and the envelope, is
+//! written as the song asks, so a channel unmuted mid-song comes back in
+//! tune and in time with the very next frame. Muting happens in
+//! [`MutedPsg`], between a player and whatever it writes to, so it stacks
+//! with the `arbiter::SfxArbiter`: a channel the user has muted that an
+//! effect borrows plays the effect, and is muted again once the effect
+//! hands it back.
+
+use bitflags::bitflags;
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::FrequencyPolicy;
+use crate::{Channel, ChipKind};
+
+bitflags! {
+    /// A set of channels.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+    pub struct ChannelMask: u8 {
+        const A = 0b001;
+        const B = 0b010;
+        const C = 0b100;
+    }
+}
+
+impl ChannelMask {
+    /// The mask holding just `channel`.
+    pub const fn channel(channel: Channel) -> ChannelMask {
+        ChannelMask::from_bits_truncate(1 << channel.index())
+    }
+
+    pub const fn has(self, channel: Channel) -> bool {
+        self.contains(ChannelMask::channel(channel))
+    }
+}
+
+/// Which channels to mute, and how.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Mute {
+    channels: ChannelMask,
+    mixer_off: bool,
+}
+
+impl Mute {
+    /// Nothing muted.
+    pub const NONE: Mute = Mute::new(ChannelMask::empty());
+
+    /// Mutes `channels` by their levels alone.
+    pub const fn new(channels: ChannelMask) -> Mute {
+        Mute {
+            channels,
+            mixer_off: false,
+        }
+    }
+
+    /// Switches the muted channels' tone and noise off too, for chips that
+    /// leak a little at level 0.
+    pub const fn with_mixer_off(mut self) -> Mute {
+        self.mixer_off = true;
+        self
+    }
+
+    pub const fn channels(&self) -> ChannelMask {
+        self.channels
+    }
+
+    pub const fn is_muted(&self, channel: Channel) -> bool {
+        self.channels.has(channel)
+    }
+
+    /// The channels' bits, and the mixer switch in bit 3.
+    pub(crate) const fn bits(&self) -> u8 {
+        self.channels.bits() | (self.mixer_off as u8) << 3
+    }
+
+    /// The mute [`Mute::bits`] gave, if `bits` could be one.
+    pub(crate) const fn from_bits(bits: u8) -> Option<Mute> {
+        if bits & !0xF != 0 {
+            return None;
+        }
+        Some(Mute {
+            channels: ChannelMask::from_bits_truncate(bits),
+            mixer_off: bits & 0x8 != 0,
+        })
+    }
+
+    /// What is written in place of `data` at `address`.
+    fn replace(&self, address: u8, data: u8) -> u8 {
+        match address {
+            0x8..=0xA => match Channel::for_register(address) {
+                Some(channel) if self.is_muted(channel) => 0,
+                _ => data,
+            },
+            0x7 if self.mixer_off => {
+                let bits = self.channels.bits();
+                data | bits | bits << 3
+            }
+            _ => data,
+        }
+    }
+}
+
+/// A [`Psg`] writing to `P` with some channels muted.
+///
+/// Its registers are `P`'s, so they hold what is sounding: 0 for a muted
+/// channel's level.
+pub struct MutedPsg<'a, P> {
+    psg: &'a mut P,
+    mute: Mute,
+}
+
+impl<'a, P: Psg> MutedPsg<'a, P> {
+    pub fn new(psg: &'a mut P, mute: Mute) -> MutedPsg<'a, P> {
+        MutedPsg { psg, mute }
+    }
+}
+
+impl<P: Psg> Psg for MutedPsg<'_, P> {
+    type Error = P::Error;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), P::Error> {
+        let data = self.mute.replace(address, data);
+        self.psg.set_register_value(address, data)
+    }
+
+    // What the song asks for isn't what the chip holds: compare what would
+    // be written instead.
+    fn update_register(&mut self, address: u8, data: u8) -> Result<bool, P::Error> {
+        let data = self.mute.replace(address, data);
+        self.psg.update_register(address, data)
+    }
+
+    fn registers(&self) -> &Registers {
+        self.psg.registers()
+    }
+
+    fn register_mask(&self, address: u8) -> u8 {
+        self.psg.register_mask(address)
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.psg.master_clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.psg.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.psg.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::ChannelLevel;
+
+    #[test]
+    fn only_muted_levels_and_mixer_bits_change() {
+        let mut psg = FakePsg::new();
+        let mute = Mute::new(ChannelMask::B);
+        let mut muted = MutedPsg::new(&mut psg, mute);
+        muted.set_channel_period(Channel::B, 0x123).unwrap();
+        muted
+            .update_channel_level(Channel::B, ChannelLevel::Envelope)
+            .unwrap();
+        muted
+            .update_channel_level(Channel::A, ChannelLevel::Fixed(12))
+            .unwrap();
+        muted.set_tone_enabled(Channel::B, true).unwrap();
+        // Asking again finds the muted level already there.
+        muted
+            .update_channel_level(Channel::B, ChannelLevel::Fixed(9))
+            .unwrap();
+        assert_eq!(
+            psg.take_writes(),
+            [
+                (0x2, 0x23),
+                (0x3, 0x01),
+                (0x9, 0),
+                (0x8, 12),
+                (0x7, 0b11_1101)
+            ]
+        );
+
+        let mut muted = MutedPsg::new(&mut psg, mute.with_mixer_off());
+        muted.set_tone_enabled(Channel::A, true).unwrap();
+        assert_eq!(psg.take_writes(), [(0x7, 0b11_1110)]);
+        assert!(ChannelMask::channel(Channel::C).has(Channel::C));
+        assert!(!Mute::NONE.is_muted(Channel::A));
+    }
+}
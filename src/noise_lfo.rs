@@ -0,0 +1,149 @@
+//! Slow sweeps of the shared noise period, for wind, surf and engines.
+
+use crate::lfo::{Lfo, LfoWaveform, LFO_SCALE};
+use crate::psg::Psg;
+use crate::tuning::MAX_NOISE_PERIOD;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoiseLfoMode {
+    /// Sweep between the bounds with an LFO, one cycle every `rate` ticks.
+    Periodic(LfoWaveform),
+    /// Every `rate` ticks, step up or down by at most `max_step`, staying
+    /// within the bounds. Less regular than a sweep, so it reads as natural.
+    RandomWalk { max_step: u8 },
+}
+
+/// Modulates the noise period (R6) between `min` and `max`.
+///
+/// The noise period is shared by all channels, but this only writes R6, so
+/// channels that have noise disabled in the mixer keep playing melody
+/// undisturbed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoiseLfo {
+    mode: NoiseLfoMode,
+    lfo: Lfo,
+    rate: u16,
+    min: u8,
+    max: u8,
+    current: u8,
+    countdown: u16,
+    seed: u32,
+}
+
+impl NoiseLfo {
+    /// Bounds are clamped to the 5-bit register and swapped if reversed.
+    pub const fn new(mode: NoiseLfoMode, rate: u16, min: u8, max: u8) -> NoiseLfo {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        let min = if min > MAX_NOISE_PERIOD {
+            MAX_NOISE_PERIOD
+        } else {
+            min
+        };
+        let max = if max > MAX_NOISE_PERIOD {
+            MAX_NOISE_PERIOD
+        } else {
+            max
+        };
+        let waveform = match mode {
+            NoiseLfoMode::Periodic(waveform) => waveform,
+            NoiseLfoMode::RandomWalk { .. } => LfoWaveform::Triangle,
+        };
+        NoiseLfo {
+            mode,
+            lfo: Lfo::new(waveform, rate),
+            rate,
+            min,
+            max,
+            current: (min + max) / 2,
+            countdown: 0,
+            seed: 0x2545_F491,
+        }
+    }
+
+    /// Seeds the random walk so different instances wander differently.
+    /// A zero seed is replaced by a fixed non-zero one.
+    pub const fn with_seed(mut self, seed: u32) -> NoiseLfo {
+        self.seed = if seed == 0 { 0x2545_F491 } else { seed };
+        self
+    }
+
+    /// The noise period for this tick.
+    pub fn next_period(&mut self) -> u8 {
+        match self.mode {
+            NoiseLfoMode::Periodic(_) => {
+                let span = (self.max - self.min) as i32;
+                let value = self.lfo.step() + LFO_SCALE;
+                self.current = self.min + ((span * value + LFO_SCALE) / (2 * LFO_SCALE)) as u8;
+            }
+            NoiseLfoMode::RandomWalk { max_step } => {
+                if self.countdown == 0 {
+                    self.countdown = self.rate.max(1);
+                    let range = 2 * max_step as u32 + 1;
+                    let step = (self.random() % range) as i16 - max_step as i16;
+                    let next = self.current as i16 + step;
+                    self.current = next.clamp(self.min as i16, self.max as i16) as u8;
+                }
+                self.countdown -= 1;
+            }
+        }
+        self.current
+    }
+
+    /// Writes this tick's noise period if it changed.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let period = self.next_period();
+        psg.update_register(0x6, period)?;
+        Ok(())
+    }
+
+    /// xorshift32: cheap, deterministic, and plenty random for wind noise.
+    fn random(&mut self) -> u32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+
+    #[test]
+    fn periodic_sweep_spans_the_bounds() {
+        let mut lfo = NoiseLfo::new(NoiseLfoMode::Periodic(LfoWaveform::Triangle), 4, 4, 20);
+        let periods: [u8; 4] = core::array::from_fn(|_| lfo.next_period());
+        assert_eq!(periods, [12, 20, 12, 4]);
+    }
+
+    #[test]
+    fn random_walk_stays_bounded_and_moves_slowly() {
+        let mut lfo = NoiseLfo::new(NoiseLfoMode::RandomWalk { max_step: 2 }, 3, 10, 16);
+        let mut previous = lfo.next_period();
+        let mut changes = 0;
+        for tick in 1..300 {
+            let period = lfo.next_period();
+            assert!((10..=16).contains(&period));
+            assert!(period.abs_diff(previous) <= 2);
+            if period != previous {
+                assert_eq!(tick % 3, 0, "changed off-beat at {tick}");
+                changes += 1;
+            }
+            previous = period;
+        }
+        assert!(changes > 20);
+    }
+
+    #[test]
+    fn only_changed_periods_are_written() {
+        let mut psg = FakePsg::new();
+        let mut lfo = NoiseLfo::new(NoiseLfoMode::Periodic(LfoWaveform::Square), 4, 3, 9);
+        for _ in 0..4 {
+            lfo.tick(&mut psg).unwrap();
+        }
+        assert_eq!(psg.take_writes(), [(0x6, 9), (0x6, 3)]);
+    }
+}
@@ -0,0 +1,23 @@
+//! The error every file and text parser gives: what is wrong, and where.
+//!
+//! The files come from anywhere, SD cards and downloads included, so every
+//! parser treats its input as hostile. Lengths, counts and offsets read
+//! from a file are checked against the data before they are used, nothing
+//! is read that isn't there, and nothing can make a parser or player go
+//! round forever. Each format has its own kind of error; the offset says
+//! how far the data was good, and where a format can still be partly used,
+//! its kind says how much.
+
+/// What is wrong with some data, and the byte it was found at, counted from
+/// the start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseError<K> {
+    pub kind: K,
+    pub offset: usize,
+}
+
+impl<K> ParseError<K> {
+    pub const fn new(kind: K, offset: usize) -> ParseError<K> {
+        ParseError { kind, offset }
+    }
+}
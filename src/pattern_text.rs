@@ -0,0 +1,339 @@
+//! Patterns written as a tracker shows them, turned into [`Pattern`]s and
+//! [`Song`]s at compile time by the [`pattern!`](crate::pattern) and
+//! [`song!`](crate::song) macros.
+//!
+//! A row is a string of up to three cells, for channels A, B and C, split by
+//! `|`; cells left out change nothing. A cell is up to four fields split by
+//! spaces, in this order, with any left off the end taken as empty:
+//!
+//! | Field | Written | Empty |
+//! |---|---|---|
+//! | Note | `C-4`, `C#4`: the name, `-` or `#`, the octave 0 to 9; `===` releases | `---` or `...` |
+//! | Instrument | Two hex digits, the index into the bank, so `00` is the first | `..` |
+//! | Volume | Two hex digits, `00` to `0F` | `..` |
+//! | Effect | A tracker's effect, as [`Command::from_code`] reads it: `A0F`, `F03` | `...` |
+//!
+//! ```
+//! use ym2149::sequencer::{Cell, Command, Pattern, Row, Song};
+//! use ym2149::pitch::{Note, Pitch};
+//! use ym2149::{pattern, song};
+//!
+//! const INTRO: Pattern = pattern! {
+//!     "C-4 00 .. F03 | C-3 01 0C     | ---"
+//!     ""
+//!     "G-4           | ===           | E-4 00 .. 047"
+//!     "..."
+//! };
+//!
+//! const C4: Pitch = Pitch::new(Note::C, 4);
+//! const BY_HAND: Pattern = Pattern::new(&[
+//!     Row::new(
+//!         Cell::note(C4).instrument(0).command(Command::Speed(3)),
+//!         Cell::note(Pitch::new(Note::C, 3)).instrument(1).volume(12),
+//!         Cell::EMPTY,
+//!     ),
+//!     Row::EMPTY,
+//!     Row::new(
+//!         Cell::note(Pitch::new(Note::G, 4)),
+//!         Cell::OFF,
+//!         Cell::note(Pitch::new(Note::E, 4))
+//!             .instrument(0)
+//!             .command(Command::Arpeggio(4, 7)),
+//!     ),
+//!     Row::EMPTY,
+//! ]);
+//! assert_eq!(INTRO, BY_HAND);
+//!
+//! // Twice through, then a fifth up, looping back to the second entry.
+//! // With `instruments`, a cell naming one past the end of the bank fails
+//! // to compile.
+//! static SONG: Song = song! {
+//!     patterns: [INTRO],
+//!     order: [0, 0, 0 @ 7],
+//!     loop: 1,
+//!     instruments: 2,
+//! };
+//! ```
+//!
+//! Anything that doesn't read is a compile error when the text is in a
+//! const or a static, as it always is through the macros:
+//!
+//! ```compile_fail
+//! // There is no H.
+//! const BAD: ym2149::sequencer::Pattern = ym2149::pattern! { "H-4 00" };
+//! ```
+//!
+//! ```compile_fail
+//! // Instrument 2 of a bank of two.
+//! const BAD: ym2149::sequencer::Song = ym2149::song! {
+//!     patterns: [ym2149::pattern! { "C-4 02" }],
+//!     order: [0],
+//!     instruments: 2,
+//! };
+//! ```
+
+use crate::pitch::{Note, Pitch};
+use crate::sequencer::{Cell, Command, Row, CHANNELS};
+#[cfg(doc)]
+use crate::sequencer::{Pattern, Song};
+
+/// The [`Row`] `text` describes. Panics, at compile time in const contexts,
+/// if it doesn't follow the syntax above.
+pub const fn row(text: &str) -> Row {
+    let bytes = text.as_bytes();
+    let mut cells = [Cell::EMPTY; CHANNELS];
+    let mut channel = 0;
+    let mut start = 0;
+    loop {
+        let mut end = start;
+        while end < bytes.len() && bytes[end] != b'|' {
+            end += 1;
+        }
+        if channel == CHANNELS {
+            panic!("more than three cells in a row");
+        }
+        cells[channel] = cell(bytes, start, end);
+        channel += 1;
+        if end == bytes.len() {
+            return Row { cells };
+        }
+        start = end + 1;
+    }
+}
+
+/// The cell in `bytes[start..end]`.
+const fn cell(bytes: &[u8], start: usize, end: usize) -> Cell {
+    let mut cell = Cell::EMPTY;
+    let mut field = 0;
+    let mut at = start;
+    loop {
+        while at < end && bytes[at] == b' ' {
+            at += 1;
+        }
+        if at == end {
+            return cell;
+        }
+        let from = at;
+        while at < end && bytes[at] != b' ' {
+            at += 1;
+        }
+        let token = (from, at - from);
+        match field {
+            0 => {
+                if is(bytes, token, b"===") {
+                    cell.off = true;
+                } else if !is(bytes, token, b"---") && !is(bytes, token, b"...") {
+                    cell.pitch = Some(pitch(bytes, token));
+                }
+            }
+            1 => {
+                if !is(bytes, token, b"..") {
+                    cell.instrument = Some(hex(bytes, token, 2, "bad instrument") as u8);
+                }
+            }
+            2 => {
+                if !is(bytes, token, b"..") {
+                    let volume = hex(bytes, token, 2, "bad volume");
+                    if volume > 15 {
+                        panic!("volume above 0F");
+                    }
+                    cell.volume = Some(volume as u8);
+                }
+            }
+            3 => {
+                if !is(bytes, token, b"...") {
+                    let code = hex(bytes, (token.0, 1), 1, "bad effect");
+                    let parameter = hex(bytes, (token.0 + 1, token.1 - 1), 2, "bad effect");
+                    cell.command = match Command::from_code(code as u8, parameter as u8) {
+                        Some(command) => Some(command),
+                        None => panic!("unsupported effect"),
+                    };
+                }
+            }
+            _ => panic!("more than four fields in a cell"),
+        }
+        field += 1;
+    }
+}
+
+/// Whether the token at `(start, length)` is `text`.
+const fn is(bytes: &[u8], (start, length): (usize, usize), text: &[u8]) -> bool {
+    if length != text.len() {
+        return false;
+    }
+    let mut n = 0;
+    while n < length {
+        if bytes[start + n] != text[n] {
+            return false;
+        }
+        n += 1;
+    }
+    true
+}
+
+const fn pitch(bytes: &[u8], (start, length): (usize, usize)) -> Pitch {
+    if length != 3 {
+        panic!("bad note");
+    }
+    let natural = match bytes[start] {
+        b'C' => Note::C,
+        b'D' => Note::D,
+        b'E' => Note::E,
+        b'F' => Note::F,
+        b'G' => Note::G,
+        b'A' => Note::A,
+        b'B' => Note::B,
+        _ => panic!("bad note"),
+    };
+    let note = match (bytes[start + 1], natural) {
+        (b'-', _) => natural,
+        (b'#', Note::E | Note::B) => panic!("bad note"),
+        (b'#', _) => Note::from_semitone(natural.semitone() + 1),
+        _ => panic!("bad note"),
+    };
+    let octave = bytes[start + 2];
+    if !octave.is_ascii_digit() {
+        panic!("bad octave");
+    }
+    Pitch::new(note, octave - b'0')
+}
+
+/// The `digits` hex digits at `(start, length)`.
+const fn hex(bytes: &[u8], (start, length): (usize, usize), digits: usize, error: &str) -> u16 {
+    if length != digits {
+        panic!("{}", error);
+    }
+    let mut value = 0;
+    let mut n = 0;
+    while n < length {
+        let digit = match bytes[start + n] {
+            byte @ b'0'..=b'9' => byte - b'0',
+            byte @ b'A'..=b'F' => byte - b'A' + 10,
+            byte @ b'a'..=b'f' => byte - b'a' + 10,
+            _ => panic!("{}", error),
+        };
+        value = value << 4 | digit as u16;
+        n += 1;
+    }
+    value
+}
+
+/// A [`Pattern`](crate::sequencer::Pattern) from rows of tracker text, one
+/// string a row, as described in [`crate::pattern_text`]. The rows are read
+/// at compile time.
+#[macro_export]
+macro_rules! pattern {
+    ($($row:literal $(,)?)*) => {{
+        const ROWS: &[$crate::sequencer::Row] = &[$($crate::pattern_text::row($row)),*];
+        $crate::sequencer::Pattern::new(ROWS)
+    }};
+}
+
+/// A [`Song`](crate::sequencer::Song) from const patterns and an order list
+/// of pattern indices, each optionally transposed with `@` and a number of
+/// semitones. `loop` makes the song loop back to that order entry. An entry
+/// naming a pattern that isn't there fails to compile, and with
+/// `instruments`, the size of the bank, so does a cell naming an instrument
+/// past its end.
+#[macro_export]
+macro_rules! song {
+    (
+        patterns: [$($pattern:expr),* $(,)?],
+        order: [$($entry:literal $(@ $transpose:literal)?),* $(,)?]
+        $(, loop: $loop:expr)?
+        $(, instruments: $instruments:expr)?
+        $(,)?
+    ) => {{
+        const PATTERNS: &[$crate::sequencer::Pattern] = &[$($pattern),*];
+        const ORDER: &[$crate::sequencer::OrderEntry] = &[
+            $($crate::sequencer::OrderEntry::new($entry) $(.transposed($transpose))?),*
+        ];
+        #[allow(unused_mut, unused_assignments)]
+        const SONG: $crate::sequencer::Song = {
+            let mut instruments = usize::MAX;
+            $(instruments = $instruments;)?
+            $crate::sequencer::Song::new(PATTERNS, ORDER)
+                $(.looping($loop))?
+                .checked(instruments)
+        };
+        SONG
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequencer::{OrderEntry, Pattern, Song};
+
+    const C4: Pitch = Pitch::new(Note::C, 4);
+
+    #[test]
+    fn cells_read_as_written() {
+        assert_eq!(row(""), Row::EMPTY);
+        assert_eq!(row("--- .. .. ... | ... | ..."), Row::EMPTY);
+        assert_eq!(
+            row("C-4 00 0C A0F"),
+            Row::new(
+                Cell::note(C4)
+                    .instrument(0)
+                    .volume(12)
+                    .command(Command::VolumeSlide { up: 0, down: 15 }),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            )
+        );
+        assert_eq!(
+            row("=== | A#8 1f | G-0 .. .. D12"),
+            Row::new(
+                Cell::OFF,
+                Cell::note(Pitch::new(Note::ASharp, 8)).instrument(31),
+                Cell::note(Pitch::new(Note::G, 0)).command(Command::PatternBreak(12)),
+            )
+        );
+        // Spacing is free, as long as the fields are in order.
+        assert_eq!(row("  C-4   00 |"), row("C-4 00"));
+    }
+
+    #[test]
+    fn macros_match_hand_written_data() {
+        const WRITTEN: Pattern = crate::pattern! {
+            "C-4 00 .. F02"
+            "..."
+            "E-4 .. 08 | C-3 01"
+        };
+        const BY_HAND: Pattern = Pattern::new(&[
+            Row::new(
+                Cell::note(C4).instrument(0).command(Command::Speed(2)),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            ),
+            Row::EMPTY,
+            Row::new(
+                Cell::note(Pitch::new(Note::E, 4)).volume(8),
+                Cell::note(Pitch::new(Note::C, 3)).instrument(1),
+                Cell::EMPTY,
+            ),
+        ]);
+        assert_eq!(WRITTEN, BY_HAND);
+
+        const SONG: Song = crate::song! {
+            patterns: [WRITTEN, crate::pattern! { "G-4" }],
+            order: [0, 1 @ -12, 0 @ 5],
+            loop: 2,
+            instruments: 2,
+        };
+        const G4: Pattern = Pattern::new(&[row("G-4")]);
+        assert_eq!(SONG.patterns, [BY_HAND, G4]);
+        assert_eq!(
+            SONG.order,
+            [
+                OrderEntry::new(0),
+                OrderEntry::new(1).transposed(-12),
+                OrderEntry::new(0).transposed(5),
+            ]
+        );
+        assert_eq!(SONG, Song::new(SONG.patterns, SONG.order).looping(2));
+        const ONCE: Song = crate::song! { patterns: [WRITTEN], order: [] };
+        assert_eq!(ONCE, Song::new(ONCE.patterns, &[]));
+    }
+}
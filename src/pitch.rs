@@ -0,0 +1,152 @@
+//! Musical pitches in twelve-tone equal temperament.
+
+/// A note name within an octave.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Note {
+    C,
+    CSharp,
+    D,
+    DSharp,
+    E,
+    F,
+    FSharp,
+    G,
+    GSharp,
+    A,
+    ASharp,
+    B,
+}
+
+impl Note {
+    /// All twelve notes, starting from C.
+    pub const ALL: [Note; 12] = [
+        Note::C,
+        Note::CSharp,
+        Note::D,
+        Note::DSharp,
+        Note::E,
+        Note::F,
+        Note::FSharp,
+        Note::G,
+        Note::GSharp,
+        Note::A,
+        Note::ASharp,
+        Note::B,
+    ];
+
+    /// Semitones above C.
+    pub const fn semitone(self) -> u8 {
+        self as u8
+    }
+
+    /// The note `semitone` semitones above C, wrapping every octave.
+    pub const fn from_semitone(semitone: u8) -> Note {
+        Note::ALL[(semitone % 12) as usize]
+    }
+}
+
+/// A pitch, stored as its MIDI note number (C4 = 60, A4 = 69).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pitch(u8);
+
+impl Pitch {
+    /// Highest representable pitch, G9 (MIDI 127).
+    pub const MAX: Pitch = Pitch(127);
+
+    /// Pitch of `note` in scientific octave `octave` (so `Pitch::new(Note::A, 4)`
+    /// is concert A). Panics, at compile time in const contexts, above G9.
+    pub const fn new(note: Note, octave: u8) -> Pitch {
+        let midi = (octave as u16 + 1) * 12 + note as u16;
+        assert!(midi <= 127, "pitch above G9");
+        Pitch(midi as u8)
+    }
+
+    /// Pitch for a MIDI note number, or `None` above 127.
+    pub const fn from_midi(midi: u8) -> Option<Pitch> {
+        if midi <= 127 {
+            Some(Pitch(midi))
+        } else {
+            None
+        }
+    }
+
+    /// MIDI note number.
+    pub const fn midi(self) -> u8 {
+        self.0
+    }
+
+    /// Note name.
+    pub const fn note(self) -> Note {
+        Note::from_semitone(self.0)
+    }
+
+    /// Scientific octave. MIDI notes 0..=11 sit in octave -1.
+    pub const fn octave(self) -> i8 {
+        (self.0 / 12) as i8 - 1
+    }
+
+    /// This pitch moved by `semitones`, or `None` if that leaves the MIDI range.
+    pub const fn transpose(self, semitones: i8) -> Option<Pitch> {
+        let midi = self.0 as i16 + semitones as i16;
+        if midi < 0 || midi > 127 {
+            None
+        } else {
+            Some(Pitch(midi as u8))
+        }
+    }
+
+    /// This pitch moved by `semitones`, pulled back by octaves into the MIDI
+    /// range if it would leave it.
+    pub const fn transpose_folded(self, semitones: i8) -> Pitch {
+        let mut midi = self.0 as i16 + semitones as i16;
+        while midi > 127 {
+            midi -= 12;
+        }
+        while midi < 0 {
+            midi += 12;
+        }
+        Pitch(midi as u8)
+    }
+}
+
+/// Written as its MIDI note number, and turned away past 127 when read.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Pitch {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Pitch {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Pitch, D::Error> {
+        let midi = <u8 as serde::Deserialize>::deserialize(deserializer)?;
+        Pitch::from_midi(midi).ok_or_else(|| serde::de::Error::custom("MIDI note above 127"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_and_octave_round_trip() {
+        let pitch = Pitch::new(Note::A, 4);
+        assert_eq!(pitch.midi(), 69);
+        assert_eq!(pitch.note(), Note::A);
+        assert_eq!(pitch.octave(), 4);
+        assert_eq!(Pitch::from_midi(0).unwrap().octave(), -1);
+        assert_eq!(Pitch::from_midi(128), None);
+    }
+
+    #[test]
+    fn transpose_stays_in_range() {
+        let c4 = Pitch::new(Note::C, 4);
+        assert_eq!(c4.transpose(7), Some(Pitch::new(Note::G, 4)));
+        assert_eq!(c4.transpose(-61), None);
+        assert_eq!(Pitch::MAX.transpose(1), None);
+        assert_eq!(Pitch::MAX.transpose_folded(1).midi(), 116);
+        assert_eq!(c4.transpose_folded(-70).midi(), 2);
+    }
+}
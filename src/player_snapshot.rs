@@ -0,0 +1,135 @@
+//! Where a [`FramePlayer`](crate::frame_player::FramePlayer) has got to, in
+//! a few fixed bytes that outlive a deep sleep in backup registers or flash.
+//!
+//! A [`PlayerSnapshot`] is plain numbers and a [`Mute`], the same size
+//! whatever the song, so it can go wherever a project keeps its state;
+//! [`PlayerSnapshot::to_bytes`] lays it out for storage with nothing else.
+
+use crate::mute::Mute;
+
+/// How many bytes [`PlayerSnapshot::to_bytes`] makes.
+pub const SNAPSHOT_SIZE: usize = 36;
+
+/// The first byte of the bytes, which changes if their layout does.
+const FORMAT: u8 = 1;
+
+/// A player's place in a song, from
+/// [`FramePlayer::snapshot`](crate::frame_player::FramePlayer::snapshot).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PlayerSnapshot {
+    /// The caller's name for the song.
+    pub source_id: u32,
+    /// The song's length in frames, if its source could tell.
+    pub frame_count: Option<u32>,
+    /// The next frame to play.
+    pub position: u32,
+    /// Times round the loop.
+    pub loops: u32,
+    /// Frames played in those loops, for the time played.
+    pub looped_frames: u64,
+    /// How far the rate has got towards the next frame.
+    pub phase: u64,
+    pub mute: Mute,
+}
+
+impl PlayerSnapshot {
+    /// The snapshot as bytes, little-endian, with a format byte in front and
+    /// a check byte at the end, so blank or worn storage isn't taken for a
+    /// snapshot.
+    pub fn to_bytes(&self) -> [u8; SNAPSHOT_SIZE] {
+        let mut bytes = [0; SNAPSHOT_SIZE];
+        bytes[0] = FORMAT;
+        bytes[1..5].copy_from_slice(&self.source_id.to_le_bytes());
+        bytes[5] = self.frame_count.is_some() as u8;
+        bytes[6..10].copy_from_slice(&self.frame_count.unwrap_or(0).to_le_bytes());
+        bytes[10..14].copy_from_slice(&self.position.to_le_bytes());
+        bytes[14..18].copy_from_slice(&self.loops.to_le_bytes());
+        bytes[18..26].copy_from_slice(&self.looped_frames.to_le_bytes());
+        bytes[26..34].copy_from_slice(&self.phase.to_le_bytes());
+        bytes[34] = self.mute.bits();
+        bytes[35] = check(&bytes[..35]);
+        bytes
+    }
+
+    /// The snapshot [`PlayerSnapshot::to_bytes`] made, or `None` if `bytes`
+    /// aren't one.
+    pub fn from_bytes(bytes: &[u8; SNAPSHOT_SIZE]) -> Option<PlayerSnapshot> {
+        if bytes[0] != FORMAT || bytes[35] != check(&bytes[..35]) || bytes[5] > 1 {
+            return None;
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(core::array::from_fn(|n| bytes[at + n]));
+        let u64_at = |at: usize| u64::from_le_bytes(core::array::from_fn(|n| bytes[at + n]));
+        Some(PlayerSnapshot {
+            source_id: u32_at(1),
+            frame_count: (bytes[5] == 1).then(|| u32_at(6)),
+            position: u32_at(10),
+            loops: u32_at(14),
+            looped_frames: u64_at(18),
+            phase: u64_at(26),
+            mute: Mute::from_bits(bytes[34])?,
+        })
+    }
+}
+
+/// A sum of `bytes` that neither all zeros nor all ones pass.
+fn check(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0xA5, |sum, &byte| sum.wrapping_add(byte).rotate_left(1))
+}
+
+/// Why [`FramePlayer::resume_from`](crate::frame_player::FramePlayer::resume_from)
+/// couldn't carry on from a snapshot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResumeError<E> {
+    /// The snapshot was taken of another song.
+    OtherSong,
+    /// The song's length in frames isn't what it was when the snapshot was
+    /// taken, so it isn't the same song any more.
+    LengthChanged { was: Option<u32>, now: Option<u32> },
+    /// Writing to the chip failed.
+    Psg(E),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mute::ChannelMask;
+
+    #[test]
+    fn bytes_round_trip_and_damage_is_caught() {
+        let snapshots = [
+            PlayerSnapshot {
+                source_id: 0xDEAD_BEEF,
+                frame_count: Some(3000),
+                position: 1234,
+                loops: 2,
+                looped_frames: 5000,
+                phase: 199_999,
+                mute: Mute::new(ChannelMask::A | ChannelMask::C).with_mixer_off(),
+            },
+            PlayerSnapshot {
+                source_id: u32::MAX,
+                frame_count: None,
+                position: u32::MAX,
+                loops: u32::MAX,
+                looped_frames: u64::MAX,
+                phase: u64::MAX,
+                mute: Mute::NONE,
+            },
+        ];
+        for snapshot in snapshots {
+            let bytes = snapshot.to_bytes();
+            assert_eq!(PlayerSnapshot::from_bytes(&bytes), Some(snapshot));
+            for bit in 0..SNAPSHOT_SIZE * 8 {
+                let mut damaged = bytes;
+                damaged[bit / 8] ^= 1 << (bit % 8);
+                assert_eq!(PlayerSnapshot::from_bytes(&damaged), None, "bit {bit}");
+            }
+        }
+        // Storage never written.
+        assert_eq!(PlayerSnapshot::from_bytes(&[0; SNAPSHOT_SIZE]), None);
+        assert_eq!(PlayerSnapshot::from_bytes(&[0xFF; SNAPSHOT_SIZE]), None);
+    }
+}
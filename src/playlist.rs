@@ -0,0 +1,425 @@
+//! Several songs played one after another by a single
+//! [`FramePlayer`](crate::frame_player::FramePlayer).
+
+use crate::frame_player::{Frame, FrameSource, FrameStatus, SourceError, R13_UNCHANGED};
+use crate::Channel;
+
+/// What happens when a song ends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Repeat {
+    /// The next song plays, and the playlist ends after the last.
+    #[default]
+    Off,
+    /// The next song plays, and the first after the last.
+    All,
+    /// The same song plays again, until skipped.
+    One,
+}
+
+/// How one song gives way to the next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Transition {
+    /// The next song's first frame straight after the last one's.
+    #[default]
+    Gapless,
+    /// This many frames of silence in between.
+    Gap(u8),
+    /// The end of each song fades out over this many frames and the start of
+    /// the next fades in; the ends of songs that can't tell their length
+    /// don't fade.
+    Fade(u8),
+}
+
+/// A track change to show, from [`Playlist::take_event`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlaylistEvent {
+    /// The song at this place in the playlist has started.
+    Started(usize),
+    /// The last song has ended and the playlist has run out.
+    Finished,
+}
+
+/// Returned with the song when the playlist has no room for it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PlaylistFull<S>(pub S);
+
+/// Up to `N` songs, played in turn as one [`FrameSource`].
+///
+/// Songs are kept as given, so a playlist of `&'static [Frame]` holds
+/// references to songs in flash rather than the songs themselves. Because
+/// the player sees one long song, the next song's first frame goes out on
+/// the tick after the last song's last, with nothing in between unless a
+/// [`Transition`] asks for it.
+///
+/// The player's position carries on across songs. Each song is read from
+/// its own first frame on, and a seek back past the start of the current
+/// song lands at that start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Playlist<S, const N: usize> {
+    songs: [Option<S>; N],
+    len: usize,
+    /// The song playing, or `len` once past the end.
+    current: usize,
+    /// The player's index for the current song's first frame, once started.
+    start: Option<u32>,
+    /// Where the next song starts, if in a gap.
+    gap_end: Option<u32>,
+    fade_in: bool,
+    skip: bool,
+    repeat: Repeat,
+    transition: Transition,
+    /// The last frame handed out, held through gaps.
+    held: Frame,
+    event: Option<PlaylistEvent>,
+}
+
+impl<S, const N: usize> Default for Playlist<S, N> {
+    fn default() -> Playlist<S, N> {
+        Playlist::new()
+    }
+}
+
+impl<S, const N: usize> Playlist<S, N> {
+    /// An empty playlist, gapless and not repeating.
+    pub const fn new() -> Playlist<S, N> {
+        Playlist {
+            songs: [const { None }; N],
+            len: 0,
+            current: 0,
+            start: None,
+            gap_end: None,
+            fade_in: false,
+            skip: false,
+            repeat: Repeat::Off,
+            transition: Transition::Gapless,
+            held: [0; 16],
+            event: None,
+        }
+    }
+
+    pub const fn with_repeat(mut self, repeat: Repeat) -> Playlist<S, N> {
+        self.repeat = repeat;
+        self
+    }
+
+    pub const fn with_transition(mut self, transition: Transition) -> Playlist<S, N> {
+        self.transition = transition;
+        self
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds `song` to the end. Once the playlist has run out, the next frame
+    /// read starts it.
+    pub fn enqueue(&mut self, song: S) -> Result<(), PlaylistFull<S>> {
+        if self.len == N {
+            return Err(PlaylistFull(song));
+        }
+        self.songs[self.len] = Some(song);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The place in the playlist of the song playing, if any.
+    pub fn current(&self) -> Option<usize> {
+        (self.start.is_some() && self.current < self.len).then_some(self.current)
+    }
+
+    /// Moves on to the next song from the next frame read, even when
+    /// repeating one, and skipping any gap or fade.
+    pub fn skip_next(&mut self) {
+        self.skip = true;
+    }
+
+    /// Goes back to the first song, for the player to start again.
+    pub fn rewind(&mut self) {
+        self.current = 0;
+        self.start = None;
+        self.gap_end = None;
+        self.skip = false;
+    }
+
+    /// The latest track change since the last call. Changes closer together
+    /// than the calls only report the last, so call once a tick.
+    pub fn take_event(&mut self) -> Option<PlaylistEvent> {
+        self.event.take()
+    }
+
+    /// Moves to the song after the current one, as the repeat mode says,
+    /// starting it at player index `index`.
+    fn advance(&mut self, index: u32, skipped: bool) {
+        self.current = match self.repeat {
+            Repeat::One if !skipped => self.current,
+            Repeat::All if self.current + 1 >= self.len => 0,
+            _ => self.current + 1,
+        };
+        self.gap_end = None;
+        self.fade_in = !skipped && matches!(self.transition, Transition::Fade(_));
+        self.start = Some(index);
+        self.event = Some(if self.current < self.len {
+            PlaylistEvent::Started(self.current)
+        } else {
+            PlaylistEvent::Finished
+        });
+    }
+
+    /// Whether a song follows the current one when it ends.
+    fn has_next(&self) -> bool {
+        self.repeat != Repeat::Off || self.current + 1 < self.len
+    }
+}
+
+/// A level register value turned down `step` of `steps + 1` of the way to
+/// silence.
+fn fade_level(value: u8, step: u32, steps: u32) -> u8 {
+    if value & 0x10 != 0 {
+        // The envelope can't be faded.
+        return value;
+    }
+    value.saturating_sub((15 * step / (steps + 1)) as u8)
+}
+
+impl<S: FrameSource, const N: usize> FrameSource for Playlist<S, N> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        // Songs found empty this call, so a playlist of them can't spin.
+        let mut empty = 0;
+        loop {
+            if self.current >= self.len || empty > self.len {
+                // Run out, until something is enqueued.
+                self.start = None;
+                return Ok(FrameStatus::End);
+            }
+            let Some(start) = self.start else {
+                self.start = Some(index);
+                self.event = Some(PlaylistEvent::Started(self.current));
+                continue;
+            };
+            if core::mem::take(&mut self.skip) {
+                self.advance(index, true);
+                continue;
+            }
+            if let Some(end) = self.gap_end {
+                if index >= end {
+                    self.advance(end.max(start), false);
+                    continue;
+                }
+                *frame = self.held;
+                for channel in Channel::ALL {
+                    frame[channel.level_register() as usize] = 0;
+                }
+                frame[0xD] = R13_UNCHANGED;
+                return Ok(FrameStatus::Ready);
+            }
+            let local = index.saturating_sub(start);
+            let Some(song) = self.songs[self.current].as_mut() else {
+                return Ok(FrameStatus::End);
+            };
+            match song.frame(local, frame)? {
+                FrameStatus::Ready => {}
+                FrameStatus::NotReady => return Ok(FrameStatus::NotReady),
+                FrameStatus::End if local == 0 => {
+                    empty += 1;
+                    self.advance(index, true);
+                    continue;
+                }
+                FrameStatus::End => {
+                    match self.transition {
+                        Transition::Gap(frames) if frames > 0 && self.has_next() => {
+                            self.gap_end = Some(index + frames as u32);
+                        }
+                        _ => self.advance(index, false),
+                    }
+                    continue;
+                }
+            }
+            if let Transition::Fade(frames) = self.transition {
+                let frames = frames as u32;
+                let left = song.frame_count().map(|count| count.saturating_sub(local));
+                let out = left.map_or(0, |left| (frames + 1).saturating_sub(left));
+                let into = if self.fade_in {
+                    frames.saturating_sub(local)
+                } else {
+                    0
+                };
+                let step = out.max(into);
+                for channel in Channel::ALL {
+                    let register = &mut frame[channel.level_register() as usize];
+                    *register = fade_level(*register, step, frames);
+                }
+            }
+            self.held = *frame;
+            return Ok(FrameStatus::Ready);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::frame_player::{FramePlayer, PlayStatus};
+    use crate::psg::Psg;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    const fn frame(period: u8) -> Frame {
+        [
+            period,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0x3E,
+            15,
+            0,
+            0,
+            0,
+            0,
+            R13_UNCHANGED,
+            0,
+            0,
+        ]
+    }
+
+    static FIRST: [Frame; 3] = [frame(10), frame(11), frame(12)];
+    static SECOND: [Frame; 2] = [frame(20), frame(21)];
+
+    fn playlist<const N: usize>(songs: &[&'static [Frame]]) -> Playlist<&'static [Frame], N> {
+        let mut playlist = Playlist::new();
+        for &song in songs {
+            playlist.enqueue(song).unwrap();
+        }
+        playlist
+    }
+
+    /// Period, level and any track change after each tick.
+    fn run<const N: usize>(
+        player: &mut FramePlayer<Playlist<&'static [Frame], N>>,
+        psg: &mut FakePsg,
+        ticks: usize,
+    ) -> Vec<(u8, u8, Option<PlaylistEvent>)> {
+        (0..ticks)
+            .map(|_| {
+                player.tick(psg).unwrap();
+                let event = player.source_mut().take_event();
+                (
+                    psg.registers().value(0x0),
+                    psg.registers().value(0x8),
+                    event,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plays_gaplessly_to_the_end() {
+        use PlaylistEvent::{Finished, Started};
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(playlist::<3>(&[&FIRST, &SECOND]));
+        player.play();
+        assert_eq!(
+            run(&mut player, &mut psg, 5),
+            [
+                (10, 15, Some(Started(0))),
+                (11, 15, None),
+                (12, 15, None),
+                (20, 15, Some(Started(1))),
+                (21, 15, None),
+            ]
+        );
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Finished));
+        assert_eq!(player.source_mut().take_event(), Some(Finished));
+        assert_eq!(player.source().current(), None);
+
+        // Something new queued after the end plays from the start of it.
+        player.source_mut().enqueue(&FIRST).unwrap();
+        player.play();
+        assert_eq!(run(&mut player, &mut psg, 1), [(10, 15, Some(Started(2)))]);
+        assert_eq!(
+            player.source_mut().enqueue(&FIRST),
+            Err(PlaylistFull(&FIRST[..]))
+        );
+    }
+
+    #[test]
+    fn repeats_one_or_all() {
+        let mut psg = FakePsg::new();
+        let list = playlist::<2>(&[&SECOND, &FIRST]).with_repeat(Repeat::One);
+        let mut player = FramePlayer::new(list);
+        player.play();
+        let periods: Vec<u8> = run(&mut player, &mut psg, 5).iter().map(|t| t.0).collect();
+        assert_eq!(periods, [20, 21, 20, 21, 20]);
+        // Skipping still moves on.
+        player.source_mut().skip_next();
+        player.source_mut().set_repeat(Repeat::All);
+        let periods: Vec<u8> = run(&mut player, &mut psg, 6).iter().map(|t| t.0).collect();
+        assert_eq!(periods, [10, 11, 12, 20, 21, 10]);
+    }
+
+    #[test]
+    fn skipping_in_the_last_frames_moves_on_once() {
+        use PlaylistEvent::Started;
+        static THIRD: [Frame; 1] = [frame(30)];
+        let mut psg = FakePsg::new();
+        let mut player = FramePlayer::new(playlist::<4>(&[&FIRST, &SECOND, &THIRD]));
+        player.play();
+        run(&mut player, &mut psg, 2);
+        // One frame of the first song left.
+        player.source_mut().skip_next();
+        assert_eq!(
+            run(&mut player, &mut psg, 3),
+            [
+                (20, 15, Some(Started(1))),
+                (21, 15, None),
+                (30, 15, Some(Started(2))),
+            ]
+        );
+        // On the last frame of the playlist, a skip ends it.
+        player.source_mut().skip_next();
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Finished));
+    }
+
+    #[test]
+    fn gaps_hold_the_pitch_and_fades_ramp_the_level() {
+        let mut psg = FakePsg::new();
+        let list = playlist::<2>(&[&FIRST, &SECOND]).with_transition(Transition::Gap(2));
+        let mut player = FramePlayer::new(list);
+        player.play();
+        let ticks: Vec<(u8, u8)> = run(&mut player, &mut psg, 6)
+            .iter()
+            .map(|t| (t.0, t.1))
+            .collect();
+        assert_eq!(
+            ticks,
+            [(10, 15), (11, 15), (12, 15), (12, 0), (12, 0), (20, 15)]
+        );
+
+        // Out over the last two frames and in over the first two; the
+        // first song starts at full level.
+        let list = playlist::<2>(&[&FIRST, &FIRST]).with_transition(Transition::Fade(2));
+        let mut player = FramePlayer::new(list);
+        player.play();
+        let levels: Vec<u8> = run(&mut player, &mut psg, 6).iter().map(|t| t.1).collect();
+        assert_eq!(levels, [15, 10, 5, 5, 10, 5]);
+
+        // Empty songs are passed over, even repeating.
+        static NOTHING: [Frame; 0] = [];
+        let list = playlist::<2>(&[&NOTHING, &NOTHING]).with_repeat(Repeat::All);
+        let mut player = FramePlayer::new(list);
+        player.play();
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Finished));
+    }
+}
@@ -0,0 +1,214 @@
+//! Mono-synth style portamento: each new note glides from the last one.
+
+use crate::effect::{ChannelCtx, Effect};
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning;
+use crate::Channel;
+
+/// How fast a glide moves towards its target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GlideRate {
+    /// No glide: new notes jump straight to their pitch.
+    Off,
+    /// Tone period units per tick. Sounds faster at low pitches, where
+    /// periods are long, which is how many tracker players behave.
+    Period(u16),
+    /// Cents per tick, the same musical speed in every octave.
+    Cents(u16),
+}
+
+/// Per-channel portamento.
+///
+/// Other pitch layers (vibrato, bends) build on [`Portamento::advance`],
+/// which returns the gliding period before modulation without writing
+/// anything; [`Portamento::tick`] is the shortcut that writes it directly.
+/// Either way there is one period write per tick at most.
+///
+/// Portamento only handles pitch: `note_off` matters for legato decisions,
+/// while silencing the channel is up to the caller or an envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Portamento {
+    channel: Channel,
+    rate: GlideRate,
+    legato: bool,
+    target: Option<Pitch>,
+    /// Current period in 1/256ths, so cent-sized steps at high pitches
+    /// still make progress.
+    current: Option<u32>,
+    held: bool,
+    jump: bool,
+}
+
+impl Portamento {
+    pub const fn new(channel: Channel, rate: GlideRate) -> Portamento {
+        Portamento {
+            channel,
+            rate,
+            legato: false,
+            target: None,
+            current: None,
+            held: false,
+            jump: false,
+        }
+    }
+
+    /// In legato mode a note only glides if the previous one is still held;
+    /// otherwise it starts at its own pitch.
+    pub const fn legato(mut self, legato: bool) -> Portamento {
+        self.legato = legato;
+        self
+    }
+
+    pub fn set_glide(&mut self, rate: GlideRate) {
+        self.rate = rate;
+    }
+
+    pub fn note_on(&mut self, pitch: Pitch) {
+        self.jump = self.current.is_none() || (self.legato && !self.held);
+        self.target = Some(pitch);
+        self.held = true;
+    }
+
+    pub fn note_off(&mut self) {
+        self.held = false;
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Moves one tick's worth towards the target and returns the resulting
+    /// period, or `None` before the first note.
+    pub fn advance(&mut self, clock: u32) -> Option<u16> {
+        let target = (tuning::fold_pitch_period(clock, self.target?).period as u32) << 8;
+        let current = match self.current {
+            Some(current) if !self.jump => current,
+            _ => target,
+        };
+        self.jump = false;
+        let next = match self.rate {
+            GlideRate::Off => target,
+            GlideRate::Period(step) => {
+                let step = (step as u32) << 8;
+                if current < target {
+                    (current + step).min(target)
+                } else {
+                    current.saturating_sub(step).max(target)
+                }
+            }
+            GlideRate::Cents(cents) => {
+                if current < target {
+                    tuning::detune_period(current, -(cents as i32)).clamp(current + 1, target)
+                } else if current > target {
+                    tuning::detune_period(current, cents as i32).clamp(target, current - 1)
+                } else {
+                    target
+                }
+            }
+        };
+        self.current = Some(next);
+        Some(((next + 128) >> 8) as u16)
+    }
+
+    /// The unmodulated period as of the last [`Portamento::advance`].
+    pub fn current_period(&self) -> Option<u16> {
+        self.current.map(|current| ((current + 128) >> 8) as u16)
+    }
+
+    /// Advances and writes the period to the channel.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        if let Some(period) = self.advance(psg.master_clock()) {
+            psg.set_channel_period(self.channel, period)?;
+        }
+        Ok(())
+    }
+}
+
+/// Contributes the distance between the glide and its target note in cents.
+impl Effect for Portamento {
+    fn tick(&mut self, ctx: &mut ChannelCtx) {
+        let clock = ctx.master_clock();
+        let (Some(period), Some(target)) = (self.advance(clock), self.target) else {
+            return;
+        };
+        let target = tuning::fold_pitch_period(clock, target).period;
+        ctx.add_cents(tuning::period_cents(target, period));
+    }
+
+    fn note_on(&mut self, pitch: Pitch) {
+        Portamento::note_on(self, pitch);
+    }
+
+    fn note_off(&mut self) {
+        Portamento::note_off(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    const CLOCK: u32 = 2_000_000;
+    const A3: Pitch = Pitch::new(Note::A, 3); // period 568
+    const A4: Pitch = Pitch::new(Note::A, 4); // period 284
+
+    fn periods(portamento: &mut Portamento, ticks: usize) -> Vec<u16> {
+        (0..ticks)
+            .map(|_| portamento.advance(CLOCK).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn first_note_starts_in_place_then_glides() {
+        let mut portamento = Portamento::new(Channel::A, GlideRate::Period(100));
+        assert_eq!(portamento.advance(CLOCK), None);
+        portamento.note_on(A3);
+        assert_eq!(periods(&mut portamento, 2), [568, 568]);
+        portamento.note_on(A4);
+        assert_eq!(periods(&mut portamento, 4), [468, 368, 284, 284]);
+        assert_eq!(portamento.current_period(), Some(284));
+    }
+
+    #[test]
+    fn cents_glide_takes_the_same_ticks_in_any_octave() {
+        let mut low = Portamento::new(Channel::A, GlideRate::Cents(300));
+        low.note_on(Pitch::new(Note::A, 2));
+        low.advance(CLOCK);
+        low.note_on(A3);
+        let mut high = Portamento::new(Channel::B, GlideRate::Cents(300));
+        high.note_on(A3);
+        high.advance(CLOCK);
+        high.note_on(A4);
+        let low_ticks = (1..).find(|_| low.advance(CLOCK) == Some(568)).unwrap();
+        let high_ticks = (1..).find(|_| high.advance(CLOCK) == Some(284)).unwrap();
+        assert_eq!(low_ticks, 4);
+        assert_eq!(high_ticks, 4);
+    }
+
+    #[test]
+    fn legato_only_glides_between_held_notes() {
+        let mut portamento = Portamento::new(Channel::A, GlideRate::Period(100)).legato(true);
+        portamento.note_on(A3);
+        portamento.advance(CLOCK);
+        portamento.note_off();
+        portamento.note_on(A4);
+        assert_eq!(portamento.advance(CLOCK), Some(284));
+        portamento.note_on(A3);
+        assert_eq!(portamento.advance(CLOCK), Some(384));
+    }
+
+    #[test]
+    fn tick_writes_the_period() {
+        let mut psg = FakePsg::new();
+        let mut portamento = Portamento::new(Channel::C, GlideRate::Off);
+        portamento.note_on(A4);
+        portamento.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::C), 284);
+    }
+}
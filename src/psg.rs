@@ -0,0 +1,390 @@
+//! The register-level interface shared by the hardware driver and anything
+//! that stands in for it.
+//!
+//! Higher-level helpers are provided methods on [`Psg`], so they work the same
+//! on every implementation.
+
+use crate::channel_editor::ChannelEditor;
+#[cfg(feature = "music")]
+use crate::chord::{self, Chord, ChordReport};
+#[cfg(feature = "music")]
+use crate::pitch::Pitch;
+use crate::registers::{self, Frame, Registers, R13_UNCHANGED};
+#[cfg(feature = "music")]
+use crate::state::ChannelState;
+use crate::state::{EnvelopeState, NoiseState};
+use crate::tuning::{self, FoldedPeriod, FrequencyError, FrequencyPolicy, MAX_TONE_PERIOD};
+#[cfg(feature = "music")]
+use crate::unison::Unison;
+use crate::{Channel, ChannelLevel, ChipKind};
+
+pub trait Psg {
+    type Error;
+
+    /// Writes `data` to register `address` unconditionally and records it in
+    /// the shadow copy returned by [`Psg::registers`].
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error>;
+
+    /// The last value written to each register.
+    fn registers(&self) -> &Registers;
+
+    /// Frequency of the chip's master clock in hertz.
+    fn master_clock(&self) -> u32;
+
+    /// Which chip it is, for helpers whose results depend on it. A YM2149
+    /// unless the backend says otherwise.
+    fn chip_kind(&self) -> ChipKind {
+        ChipKind::Ym2149
+    }
+
+    /// The bits of register `address` the backend keeps, so that writes
+    /// differing only in the others count as no change: those in
+    /// [`REGISTER_MASKS`](registers::REGISTER_MASKS) unless it keeps fewer,
+    /// and none of a register it drops writes to.
+    fn register_mask(&self, address: u8) -> u8 {
+        registers::REGISTER_MASKS[address as usize & 0xF]
+    }
+
+    /// Writes `data` only if it differs from the cached value (or nothing has
+    /// been written there yet). Returns whether a write happened, which it
+    /// never does to a register the backend drops.
+    fn update_register(&mut self, address: u8, data: u8) -> Result<bool, Self::Error> {
+        let mask = self.register_mask(address);
+        if mask == 0 {
+            return Ok(false);
+        }
+        if self.registers().get(address) == Some(data & mask) {
+            return Ok(false);
+        }
+        self.set_register_value(address, data)?;
+        Ok(true)
+    }
+
+    /// Sets a channel's 12-bit tone period, writing only the bytes that
+    /// changed.
+    ///
+    /// When both bytes change, the chip briefly plays the period formed by the
+    /// first byte written and the old value of the other. The write order is
+    /// chosen so that transient strays the least, in pitch terms, outside the
+    /// old and new periods, which keeps slides from clicking as they cross a
+    /// rough-byte boundary.
+    fn set_channel_period(&mut self, channel: Channel, period: u16) -> Result<(), Self::Error> {
+        let period = period.min(MAX_TONE_PERIOD);
+        let (fine_register, rough_register) = channel.period_registers();
+        let fine = period as u8;
+        let rough = (period >> 8) as u8;
+        let registers = self.registers();
+        if let (Some(old_fine), Some(old_rough)) =
+            (registers.get(fine_register), registers.get(rough_register))
+        {
+            if old_fine != fine && old_rough != rough {
+                let old = (old_rough as u16) << 8 | old_fine as u16;
+                let (low, high) = (old.min(period), old.max(period));
+                // Frequency ratio between the transient and the nearest end
+                // of the old..new range, in Q16.
+                let overshoot = |transient: u16| -> u32 {
+                    if transient < low {
+                        ((low as u32) << 16) / transient.max(1) as u32
+                    } else if transient > high {
+                        ((transient as u32) << 16) / high as u32
+                    } else {
+                        1 << 16
+                    }
+                };
+                let fine_first = (old_rough as u16) << 8 | fine as u16;
+                let rough_first = (rough as u16) << 8 | old_fine as u16;
+                if overshoot(rough_first) < overshoot(fine_first) {
+                    self.set_register_value(rough_register, rough)?;
+                    self.set_register_value(fine_register, fine)?;
+                    return Ok(());
+                }
+            }
+        }
+        self.update_register(fine_register, fine)?;
+        self.update_register(rough_register, rough)?;
+        Ok(())
+    }
+
+    /// How pitches and frequencies become periods when a call doesn't say:
+    /// [`FrequencyPolicy::default`] unless the backend is told otherwise.
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        FrequencyPolicy::default()
+    }
+
+    /// Tunes a channel to `pitch` under the [`Psg::frequency_policy`]: by
+    /// default, moving it by octaves if it falls outside the period range at
+    /// the current master clock.
+    #[cfg(feature = "music")]
+    fn set_channel_pitch(
+        &mut self,
+        channel: Channel,
+        pitch: Pitch,
+    ) -> Result<FoldedPeriod, FrequencyError<Self::Error>> {
+        self.set_channel_pitch_with(channel, pitch, self.frequency_policy())
+    }
+
+    /// [`Psg::set_channel_pitch`] under `policy`. Nothing is written if the
+    /// policy refuses the pitch.
+    #[cfg(feature = "music")]
+    fn set_channel_pitch_with(
+        &mut self,
+        channel: Channel,
+        pitch: Pitch,
+        policy: FrequencyPolicy,
+    ) -> Result<FoldedPeriod, FrequencyError<Self::Error>> {
+        let tone = tuning::pitch_tone(self.master_clock(), pitch, policy);
+        let tone = tone.ok_or(FrequencyError::OutOfRange)?;
+        self.set_channel_period(channel, tone.period)
+            .map_err(FrequencyError::Psg)?;
+        Ok(tone)
+    }
+
+    /// Plays `hz` on a channel under the [`Psg::frequency_policy`], returning
+    /// the period written and the frequency it plays.
+    fn set_channel_tone_hz(
+        &mut self,
+        channel: Channel,
+        hz: u32,
+    ) -> Result<FoldedPeriod, FrequencyError<Self::Error>> {
+        self.set_channel_tone_hz_with(channel, hz, self.frequency_policy())
+    }
+
+    /// [`Psg::set_channel_tone_hz`] under `policy`. Nothing is written if the
+    /// policy refuses the frequency.
+    fn set_channel_tone_hz_with(
+        &mut self,
+        channel: Channel,
+        hz: u32,
+        policy: FrequencyPolicy,
+    ) -> Result<FoldedPeriod, FrequencyError<Self::Error>> {
+        let millihertz = hz.saturating_mul(1000);
+        let tone = tuning::tone_period(self.master_clock(), millihertz, policy);
+        let tone = tone.ok_or(FrequencyError::OutOfRange)?;
+        self.set_channel_period(channel, tone.period)
+            .map_err(FrequencyError::Psg)?;
+        Ok(tone)
+    }
+
+    /// Sets a channel's level register, skipping the write if unchanged.
+    fn update_channel_level(
+        &mut self,
+        channel: Channel,
+        level: ChannelLevel,
+    ) -> Result<(), Self::Error> {
+        self.update_register(channel.level_register(), level.register_value())?;
+        Ok(())
+    }
+
+    /// Enables or disables the tone generator of one channel, leaving the rest
+    /// of the mixer untouched.
+    fn set_tone_enabled(&mut self, channel: Channel, enabled: bool) -> Result<(), Self::Error> {
+        let bit = 1 << channel.index();
+        update_mixer_bit(self, bit, enabled)
+    }
+
+    /// Enables or disables noise on one channel, leaving the rest of the mixer
+    /// untouched.
+    fn set_noise_enabled(&mut self, channel: Channel, enabled: bool) -> Result<(), Self::Error> {
+        let bit = 8 << channel.index();
+        update_mixer_bit(self, bit, enabled)
+    }
+
+    /// Sets both of a channel's mixer bits in one write, leaving the other
+    /// channels untouched.
+    fn set_channel_mixer(
+        &mut self,
+        channel: Channel,
+        tone: bool,
+        noise: bool,
+    ) -> Result<(), Self::Error> {
+        let bits = (1 | 8) << channel.index();
+        let mut mixer = self.registers().mixer() | bits;
+        if tone {
+            mixer &= !(1 << channel.index());
+        }
+        if noise {
+            mixer &= !(8 << channel.index());
+        }
+        self.update_register(0x7, mixer)?;
+        Ok(())
+    }
+
+    /// An editor for `channel`'s period, level and mixer bits, writing them
+    /// together once it is committed or dropped.
+    fn channel(&mut self, channel: Channel) -> ChannelEditor<'_, Self>
+    where
+        Self: Sized,
+    {
+        ChannelEditor::new(self, channel)
+    }
+
+    /// Writes one frame of a register dump, skipping registers that haven't
+    /// changed.
+    ///
+    /// R13 is the exception: writing it restarts the envelope, so a dump
+    /// writes it every frame it wants a restart and puts [`R13_UNCHANGED`]
+    /// there otherwise. The I/O ports, R14 and R15, are left alone, and so
+    /// are the port direction bits of the mixer, so a dump can't turn a port
+    /// the board uses as an input into an output.
+    fn write_frame(&mut self, frame: &Frame) -> Result<(), Self::Error> {
+        for address in 0..0xD {
+            let value = match address {
+                0x7 => registers::frame_mixer(frame[0x7], self.registers().mixer()),
+                _ => frame[address as usize],
+            };
+            self.update_register(address, value)?;
+        }
+        if frame[0xD] != R13_UNCHANGED {
+            self.set_register_value(0xD, frame[0xD])?;
+        }
+        Ok(())
+    }
+
+    /// Turns every channel's level down to 0 and its tone and noise off.
+    fn silence(&mut self) -> Result<(), Self::Error> {
+        for channel in Channel::ALL {
+            self.update_channel_level(channel, ChannelLevel::Fixed(0))?;
+        }
+        let mixer = self.registers().mixer() | 0x3F;
+        self.update_register(0x7, mixer)?;
+        Ok(())
+    }
+
+    /// Plays a chord across channels A, B and C at a fixed `level`, enabling
+    /// tone on all three, under the [`Psg::frequency_policy`]. By default,
+    /// notes that don't fit the period range are moved by octaves, which the
+    /// returned report records.
+    #[cfg(feature = "music")]
+    fn play_chord(
+        &mut self,
+        root: Pitch,
+        chord: impl Into<Chord>,
+        level: u8,
+    ) -> Result<ChordReport, FrequencyError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        let policy = self.frequency_policy();
+        chord::play(self, root, chord.into(), level, policy)
+    }
+
+    /// [`Psg::play_chord`] under `policy`. Nothing is written if the policy
+    /// refuses any of the notes.
+    #[cfg(feature = "music")]
+    fn play_chord_with(
+        &mut self,
+        root: Pitch,
+        chord: impl Into<Chord>,
+        level: u8,
+        policy: FrequencyPolicy,
+    ) -> Result<ChordReport, FrequencyError<Self::Error>>
+    where
+        Self: Sized,
+    {
+        chord::play(self, root, chord.into(), level, policy)
+    }
+
+    /// Silences the three channels used by [`Psg::play_chord`].
+    #[cfg(feature = "music")]
+    fn stop_chord(&mut self) -> Result<(), Self::Error>
+    where
+        Self: Sized,
+    {
+        chord::stop(self)
+    }
+
+    /// Plays `pitch` on two channels, the secondary detuned by
+    /// `detune_cents`. See [`Unison`] for keeping them in step afterwards.
+    #[cfg(feature = "music")]
+    fn play_unison(
+        &mut self,
+        primary: Channel,
+        secondary: Channel,
+        pitch: Pitch,
+        level: u8,
+        detune_cents: i8,
+    ) -> Result<Unison, Self::Error>
+    where
+        Self: Sized,
+    {
+        Unison::play(self, primary, secondary, pitch, level, detune_cents)
+    }
+
+    /// What `channel` is playing, as the shadow registers have it: its
+    /// period, the note nearest it, its level and its mixer bits.
+    #[cfg(feature = "music")]
+    fn channel_state(&self, channel: Channel) -> ChannelState {
+        ChannelState::new(self.registers(), self.master_clock(), channel)
+    }
+
+    /// The noise period and rate, as the shadow registers have them.
+    fn noise_state(&self) -> NoiseState {
+        NoiseState::new(self.registers(), self.master_clock())
+    }
+
+    /// The envelope's period, rate and shape, as the shadow registers have
+    /// them.
+    fn envelope_state(&self) -> EnvelopeState {
+        EnvelopeState::new(self.registers(), self.master_clock())
+    }
+}
+
+/// Mixer bits are active-low: a set bit disables the source.
+fn update_mixer_bit<P: Psg + ?Sized>(psg: &mut P, bit: u8, enabled: bool) -> Result<(), P::Error> {
+    let mixer = psg.registers().mixer();
+    let mixer = if enabled { mixer & !bit } else { mixer | bit };
+    psg.update_register(0x7, mixer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+
+    #[test]
+    fn unchanged_registers_are_not_rewritten() {
+        let mut psg = FakePsg::new();
+        assert!(psg.update_register(0x6, 0x10).unwrap());
+        assert!(!psg.update_register(0x6, 0x10).unwrap());
+        // Bits the register doesn't implement don't count as a change.
+        assert!(!psg.update_register(0x6, 0xF0).unwrap());
+        assert_eq!(psg.take_writes(), [(0x6, 0x10)]);
+    }
+
+    #[test]
+    fn period_writes_only_changed_bytes() {
+        let mut psg = FakePsg::new();
+        psg.set_channel_period(Channel::B, 0x123).unwrap();
+        assert_eq!(psg.take_writes(), [(0x2, 0x23), (0x3, 0x01)]);
+        psg.set_channel_period(Channel::B, 0x124).unwrap();
+        assert_eq!(psg.take_writes(), [(0x2, 0x24)]);
+    }
+
+    #[test]
+    fn period_write_order_keeps_transient_between_old_and_new() {
+        let mut psg = FakePsg::new();
+        psg.set_channel_period(Channel::A, 0x1F0).unwrap();
+        psg.take_writes();
+        // Fine first would pass through 0x110 (most of an octave sharp),
+        // rough first through 0x2F0 (about half an octave flat).
+        psg.set_channel_period(Channel::A, 0x210).unwrap();
+        assert_eq!(psg.take_writes(), [(0x1, 0x02), (0x0, 0x10)]);
+        // Going back down the same reasoning favours fine first.
+        psg.set_channel_period(Channel::A, 0x1F0).unwrap();
+        assert_eq!(psg.take_writes(), [(0x0, 0xF0), (0x1, 0x01)]);
+    }
+
+    #[test]
+    fn mixer_toggles_preserve_other_bits() {
+        let mut psg = FakePsg::new();
+        psg.set_tone_enabled(Channel::B, true).unwrap();
+        assert_eq!(psg.registers().mixer(), 0b0011_1101);
+        psg.set_noise_enabled(Channel::C, true).unwrap();
+        psg.set_tone_enabled(Channel::B, false).unwrap();
+        assert_eq!(psg.registers().mixer(), 0b0001_1111);
+        psg.take_writes();
+        psg.set_channel_mixer(Channel::A, true, true).unwrap();
+        assert_eq!(psg.take_writes(), [(0x7, 0b0001_0110)]);
+    }
+}
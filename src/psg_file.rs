@@ -0,0 +1,449 @@
+//! ZX Spectrum `.psg` register dumps, played as a stream.
+//!
+//! A file is a 16-byte header, `PSG`, `0x1A`, a format version and, from
+//! version 10, the interrupt rate in hertz, with the rest unused. Commands
+//! follow:
+//!
+//! - a register number, 0 to 15, then the value written to it
+//! - `0xFF`: the end of a frame
+//! - `0xFE` then a count: the end of a frame, and of `4 × count` frames in
+//!   all, the rest of them writing nothing
+//! - `0xFD`, or the end of the data: the end of the song
+//!
+//! Only the registers written change from frame to frame, so the stream
+//! can't be read from the middle. [`PsgStream`] reads it a byte at a time
+//! from any [`ByteSource`], a slice in flash or a file on an SD card alike,
+//! keeps the registers as they stand and hands them out frame by frame as a
+//! [`FrameSource`]. Going back, to a loop start or in a seek, reads the
+//! stream again from the start.
+
+use crate::data_source::DataSource;
+use crate::frame_player::{
+    Frame, FramePlayer, FrameSource, FrameStatus, SourceError, R13_UNCHANGED,
+};
+use crate::parse_error::ParseError;
+
+const MAGIC: &[u8; 4] = b"PSG\x1A";
+const HEADER: usize = 16;
+const END_OF_FRAME: u8 = 0xFF;
+const SKIP: u8 = 0xFE;
+const END_OF_SONG: u8 = 0xFD;
+
+/// Spectrum players run at the 50 Hz frame interrupt unless the header
+/// says otherwise.
+const DEFAULT_HERTZ: u8 = 50;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PsgErrorKind {
+    /// There is no `PSG` header.
+    NotPsg,
+    /// The header, or a register's value, runs past the end of the data.
+    Truncated,
+    /// A command that is neither a register below 16 nor a marker.
+    BadRegister(u8),
+    /// The storage behind the stream failed.
+    Read,
+}
+
+/// What is wrong with a `.psg` stream, and the byte it was found at.
+pub type PsgError = ParseError<PsgErrorKind>;
+
+/// Bytes pulled one at a time, in order.
+pub trait ByteSource {
+    /// The next byte, or `None` at the end of the data.
+    fn next_byte(&mut self) -> Result<Option<u8>, SourceError>;
+
+    /// Goes back to the first byte.
+    fn rewind(&mut self) -> Result<(), SourceError>;
+}
+
+/// A [`ByteSource`] reading a [`DataSource`], such as a slice or
+/// [`ProgMem`](crate::data_source).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DataBytes<D> {
+    data: D,
+    position: usize,
+}
+
+/// A [`ByteSource`] reading a slice.
+pub type SliceBytes<'a> = DataBytes<&'a [u8]>;
+
+impl<D: DataSource> DataBytes<D> {
+    pub const fn new(data: D) -> DataBytes<D> {
+        DataBytes { data, position: 0 }
+    }
+}
+
+impl<D: DataSource> ByteSource for DataBytes<D> {
+    fn next_byte(&mut self) -> Result<Option<u8>, SourceError> {
+        let byte = self.data.byte(self.position);
+        self.position += byte.is_some() as usize;
+        Ok(byte)
+    }
+
+    fn rewind(&mut self) -> Result<(), SourceError> {
+        self.position = 0;
+        Ok(())
+    }
+}
+
+/// A `.psg` song's frames, rebuilt as the stream is read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsgStream<B> {
+    bytes: B,
+    version: u8,
+    frame_hertz: u8,
+    registers: Frame,
+    /// Bytes read since the start.
+    position: usize,
+    /// Frames built so far; `registers` holds the last.
+    built: u32,
+    /// Frames still to come that write nothing.
+    held: u32,
+    ended: bool,
+    error: Option<PsgError>,
+}
+
+/// A [`FramePlayer`] of a `.psg` stream.
+pub type PsgPlayer<B> = FramePlayer<PsgStream<B>>;
+
+impl<'a> PsgStream<SliceBytes<'a>> {
+    /// The stream in `data`.
+    pub fn from_slice(data: &'a [u8]) -> Result<PsgStream<SliceBytes<'a>>, PsgError> {
+        PsgStream::new(SliceBytes::new(data))
+    }
+}
+
+impl<B: ByteSource> PsgStream<B> {
+    /// Checks the header at the start of `bytes`.
+    pub fn new(bytes: B) -> Result<PsgStream<B>, PsgError> {
+        let mut stream = PsgStream {
+            bytes,
+            version: 0,
+            frame_hertz: DEFAULT_HERTZ,
+            registers: [0; 16],
+            position: 0,
+            built: 0,
+            held: 0,
+            ended: false,
+            error: None,
+        };
+        stream.start()?;
+        Ok(stream)
+    }
+
+    /// Reads the header and clears the registers, leaving the stream at the
+    /// first command.
+    fn start(&mut self) -> Result<(), PsgError> {
+        self.position = 0;
+        let mut header = [0; HEADER];
+        for byte in &mut header {
+            *byte = self.required_byte()?;
+        }
+        if header[..4] != *MAGIC {
+            return Err(PsgError::new(PsgErrorKind::NotPsg, 0));
+        }
+        self.version = header[4];
+        self.frame_hertz = match header[5] {
+            0 => DEFAULT_HERTZ,
+            _ if self.version < 10 => DEFAULT_HERTZ,
+            hertz => hertz,
+        };
+        self.registers = [0; 16];
+        self.registers[0x7] = 0x3F;
+        self.registers[0xD] = R13_UNCHANGED;
+        self.built = 0;
+        self.held = 0;
+        self.ended = false;
+        self.error = None;
+        Ok(())
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Frames a second: the header's rate from version 10, otherwise 50.
+    pub fn frame_hertz(&self) -> u8 {
+        self.frame_hertz
+    }
+
+    /// Why the last frame asked for came back as an error.
+    pub fn error(&self) -> Option<PsgError> {
+        self.error
+    }
+
+    pub fn bytes(&self) -> &B {
+        &self.bytes
+    }
+
+    /// A player for the stream, ticked `tick_hertz` times a second.
+    pub fn into_player(self, tick_hertz: u32) -> PsgPlayer<B> {
+        let rate = self.frame_hertz as u32 * 1000;
+        FramePlayer::new(self).with_rate(rate, tick_hertz)
+    }
+
+    fn byte(&mut self) -> Result<Option<u8>, PsgError> {
+        let byte = self
+            .bytes
+            .next_byte()
+            .map_err(|_| PsgError::new(PsgErrorKind::Read, self.position))?;
+        self.position += byte.is_some() as usize;
+        Ok(byte)
+    }
+
+    /// The next byte, which the command before it needs.
+    fn required_byte(&mut self) -> Result<u8, PsgError> {
+        self.byte()?
+            .ok_or(PsgError::new(PsgErrorKind::Truncated, self.position))
+    }
+
+    /// Builds the next frame into `registers`, or says there isn't one.
+    fn advance(&mut self) -> Result<bool, PsgError> {
+        self.registers[0xD] = R13_UNCHANGED;
+        if self.held > 0 {
+            self.held -= 1;
+            return Ok(true);
+        }
+        let mut written = false;
+        while !self.ended {
+            match self.byte()? {
+                None | Some(END_OF_SONG) => self.ended = true,
+                Some(END_OF_FRAME) => return Ok(true),
+                Some(SKIP) => {
+                    let count = self.required_byte()?;
+                    if count > 0 {
+                        self.held = 4 * count as u32 - 1;
+                        return Ok(true);
+                    }
+                }
+                Some(register) if register < 16 => {
+                    let value = self.required_byte()?;
+                    self.registers[register as usize] = value;
+                    written = true;
+                }
+                Some(command) => {
+                    let kind = PsgErrorKind::BadRegister(command);
+                    return Err(PsgError::new(kind, self.position - 1));
+                }
+            }
+        }
+        // Writes with no end-of-frame after them still make a frame.
+        Ok(written)
+    }
+
+    fn seek(&mut self, index: u32) -> Result<FrameStatus, PsgError> {
+        if index.saturating_add(1) < self.built {
+            self.bytes
+                .rewind()
+                .map_err(|_| PsgError::new(PsgErrorKind::Read, 0))?;
+            self.start()?;
+        }
+        while self.built <= index {
+            // Held frames are all alike, so those before `index` are passed
+            // over together rather than built one by one.
+            let passed = self.held.min(index - self.built);
+            if passed > 0 {
+                self.held -= passed;
+                self.built += passed;
+                self.registers[0xD] = R13_UNCHANGED;
+            }
+            if !self.advance()? {
+                return Ok(FrameStatus::End);
+            }
+            self.built += 1;
+        }
+        Ok(FrameStatus::Ready)
+    }
+}
+
+impl<B: ByteSource> FrameSource for PsgStream<B> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        match self.seek(index) {
+            Ok(status) => {
+                *frame = self.registers;
+                Ok(status)
+            }
+            Err(error) => {
+                self.error = Some(error);
+                Err(match error.kind {
+                    PsgErrorKind::Read => SourceError::Read,
+                    _ => SourceError::Corrupt,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::frame_player::PlayStatus;
+    use crate::psg::Psg;
+    use crate::test_support::{mangled, FakePsg, Inverted};
+    use std::vec::Vec;
+
+    /// Two frames, then a third held for eight, then a last one without an
+    /// end-of-frame, at 25 Hz.
+    const SONG: &[u8] = b"PSG\x1A\x0A\x19\0\0\0\0\0\0\0\0\0\0\
+        \x00\x10\x07\x3E\x08\x0F\x0D\x0E\xFF\
+        \x00\x20\xFF\
+        \x00\x30\x08\x0C\xFE\x02\
+        \x00\x40";
+
+    fn frames<B: ByteSource>(stream: &mut PsgStream<B>, count: u32) -> Vec<(u8, u8, u8)> {
+        let mut frame = [0; 16];
+        (0..count)
+            .map_while(|index| match stream.frame(index, &mut frame) {
+                Ok(FrameStatus::Ready) => Some((frame[0x0], frame[0x8], frame[0xD])),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_hold_frames_and_the_end_ends() {
+        let mut stream = PsgStream::from_slice(SONG).unwrap();
+        assert_eq!((stream.version(), stream.frame_hertz()), (10, 25));
+        let held = (0x30, 12, R13_UNCHANGED);
+        let mut expected = Vec::from([(0x10, 15, 0x0E), (0x20, 15, R13_UNCHANGED)]);
+        expected.extend([held; 8]);
+        expected.push((0x40, 12, R13_UNCHANGED));
+        assert_eq!(frames(&mut stream, 20), expected);
+        let mut frame = [0; 16];
+        assert_eq!(stream.frame(11, &mut frame), Ok(FrameStatus::End));
+        assert_eq!(stream.frame(12, &mut frame), Ok(FrameStatus::End));
+        // Going back reads the stream again.
+        assert_eq!(stream.frame(1, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!((frame[0x0], frame[0x7]), (0x20, 0x3E));
+        assert_eq!(frames(&mut stream, 20), expected);
+    }
+
+    #[test]
+    fn data_sources_stream_as_slices_do() {
+        let inverted = Inverted::new(SONG);
+        let mut stream = PsgStream::new(DataBytes::new(&inverted)).unwrap();
+        let mut expected = PsgStream::from_slice(SONG).unwrap();
+        assert_eq!(frames(&mut stream, 20), frames(&mut expected, 20));
+        assert_eq!(frames(&mut stream, 3), frames(&mut expected, 3));
+    }
+
+    #[test]
+    fn plays_at_the_header_rate() {
+        let mut psg = FakePsg::new();
+        let mut player = PsgStream::from_slice(SONG).unwrap().into_player(50);
+        player.play();
+        let mut periods = Vec::new();
+        loop {
+            match player.tick(&mut psg).unwrap() {
+                PlayStatus::Finished => break,
+                _ => periods.push(psg.registers().value(0x0)),
+            }
+        }
+        // 25 Hz frames for a 50 Hz tick: every frame lasts two ticks.
+        assert_eq!(periods.len(), 22);
+        assert_eq!(&periods[..5], [0x10, 0x10, 0x20, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn damaged_streams_fail_cleanly() {
+        for data in mangled(SONG) {
+            let stream = match PsgStream::from_slice(&data) {
+                Ok(stream) => stream,
+                Err(error) => {
+                    assert!(error.offset <= data.len());
+                    continue;
+                }
+            };
+            let mut psg = FakePsg::new();
+            let mut player = stream.into_player(50);
+            player.play();
+            for _ in 0..100 {
+                player.tick(&mut psg).unwrap();
+            }
+            if let Some(error) = player.source().error() {
+                assert!(error.offset <= data.len());
+            }
+        }
+        // A long hold is passed over, not built a frame at a time.
+        let mut held = Vec::from(&SONG[..HEADER]);
+        held.extend_from_slice(b"\x08\x0F\xFE\xFF\x08\x01");
+        let mut stream = PsgStream::from_slice(&held).unwrap();
+        let mut frame = [0; 16];
+        assert_eq!(stream.frame(4 * 255, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(frame[0x8], 1);
+        assert_eq!(
+            stream.frame(4 * 255 - 1, &mut frame),
+            Ok(FrameStatus::Ready)
+        );
+        assert_eq!(frame[0x8], 15);
+    }
+
+    /// Storage handing out a byte at a time, counting rewinds.
+    struct Storage {
+        data: &'static [u8],
+        position: usize,
+        rewinds: u32,
+        fail_at: Option<usize>,
+    }
+
+    impl ByteSource for Storage {
+        fn next_byte(&mut self) -> Result<Option<u8>, SourceError> {
+            if self.fail_at == Some(self.position) {
+                return Err(SourceError::Read);
+            }
+            self.position += 1;
+            Ok(self.data.get(self.position - 1).copied())
+        }
+
+        fn rewind(&mut self) -> Result<(), SourceError> {
+            self.position = 0;
+            self.rewinds += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streams_and_reports_what_went_wrong() {
+        let storage = |data, fail_at| Storage {
+            data,
+            position: 0,
+            rewinds: 0,
+            fail_at,
+        };
+        let mut stream = PsgStream::new(storage(SONG, None)).unwrap();
+        assert_eq!(frames(&mut stream, 3).len(), 3);
+        assert_eq!(frames(&mut stream, 3).len(), 3);
+        assert_eq!(stream.bytes().rewinds, 1);
+
+        let mut frame = [0; 16];
+        let mut broken = Vec::from(&SONG[..HEADER]);
+        broken.extend_from_slice(b"\x00\x01\xFF\x10\x00");
+        let mut stream = PsgStream::from_slice(&broken).unwrap();
+        assert_eq!(stream.frame(0, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(stream.frame(1, &mut frame), Err(SourceError::Corrupt));
+        let kind = PsgErrorKind::BadRegister(0x10);
+        assert_eq!(stream.error(), Some(PsgError::new(kind, HEADER + 3)));
+
+        let mut stream = PsgStream::from_slice(&broken[..HEADER + 1]).unwrap();
+        assert_eq!(stream.frame(0, &mut frame), Err(SourceError::Corrupt));
+        let truncated = PsgError::new(PsgErrorKind::Truncated, HEADER + 1);
+        assert_eq!(stream.error(), Some(truncated));
+
+        let mut stream = PsgStream::new(storage(SONG, Some(HEADER + 2))).unwrap();
+        assert_eq!(stream.frame(0, &mut frame), Err(SourceError::Read));
+        let read = PsgError::new(PsgErrorKind::Read, HEADER + 2);
+        assert_eq!(stream.error(), Some(read));
+
+        assert_eq!(
+            PsgStream::from_slice(&SONG[..HEADER - 1]).err(),
+            Some(PsgError::new(PsgErrorKind::Truncated, HEADER - 1))
+        );
+        assert_eq!(
+            PsgStream::from_slice(b"PSG!\x0A\x19\0\0\0\0\0\0\0\0\0\0").err(),
+            Some(PsgError::new(PsgErrorKind::NotPsg, 0))
+        );
+    }
+}
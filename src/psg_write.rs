@@ -0,0 +1,170 @@
+//! [`PsgWrite`], all a backend has to do for this crate to drive it.
+//!
+//! A crate that only writes registers, such as a music engine of its own,
+//! can be generic over [`PsgWrite`] and drive the hardware driver, the
+//! emulator, `RecordingBus` and the wrappers here alike: everything that
+//! is a [`Psg`] is a [`PsgWrite`].
+//! The other way, a backend of its own, such as a register file in an
+//! FPGA, implements [`PsgWrite`] and is wrapped in a [`Shadowed`] to play
+//! everything in this crate, which keeps the shadow registers [`Psg`]
+//! needs for it.
+//!
+//! [`PsgWrite`] and [`Register`] are the point where other crates meet this
+//! one, so they are kept stable: neither changes, nor gains a method or a
+//! variant, short of a major version.
+//!
+//! ```
+//! use ym2149::psg_write::{PsgWrite, Shadowed};
+//! use ym2149::registers::Register;
+//! use ym2149::{Channel, ChannelLevel, Psg};
+//!
+//! /// Registers mapped into memory, as on an FPGA.
+//! struct RegisterFile([u8; 16]);
+//!
+//! impl PsgWrite for RegisterFile {
+//!     type Error = core::convert::Infallible;
+//!
+//!     fn write_register(&mut self, register: Register, value: u8) -> Result<(), Self::Error> {
+//!         self.0[register.address() as usize] = value;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut psg = Shadowed::new(RegisterFile([0; 16]));
+//! psg.set_channel_period(Channel::A, 284).unwrap();
+//! psg.update_channel_level(Channel::A, ChannelLevel::Fixed(12))
+//!     .unwrap();
+//! assert_eq!(psg.inner().0[Register::AFine.address() as usize], 0x1C);
+//! assert_eq!(psg.inner().0[Register::ALevel.address() as usize], 12);
+//! ```
+
+use crate::psg::Psg;
+use crate::registers::{Register, Registers};
+use crate::tuning::DEFAULT_MASTER_CLOCK;
+use crate::ChipKind;
+
+/// Something registers can be written to.
+pub trait PsgWrite {
+    type Error;
+
+    /// Writes `value` to `register`.
+    fn write_register(&mut self, register: Register, value: u8) -> Result<(), Self::Error>;
+}
+
+impl<P: Psg + ?Sized> PsgWrite for P {
+    type Error = P::Error;
+
+    fn write_register(&mut self, register: Register, value: u8) -> Result<(), P::Error> {
+        self.set_register_value(register.address(), value)
+    }
+}
+
+/// A [`Psg`] writing to `W`, keeping the shadow registers for it.
+#[derive(Debug, Clone)]
+pub struct Shadowed<W> {
+    writer: W,
+    registers: Registers,
+    master_clock: u32,
+    chip: ChipKind,
+}
+
+impl<W: PsgWrite> Shadowed<W> {
+    /// `writer`, taken to be a YM2149 at 2 MHz.
+    pub const fn new(writer: W) -> Shadowed<W> {
+        Shadowed {
+            writer,
+            registers: Registers::new(),
+            master_clock: DEFAULT_MASTER_CLOCK,
+            chip: ChipKind::Ym2149,
+        }
+    }
+
+    pub const fn with_master_clock(mut self, hz: u32) -> Shadowed<W> {
+        self.master_clock = hz;
+        self
+    }
+
+    pub const fn with_chip_kind(mut self, chip: ChipKind) -> Shadowed<W> {
+        self.chip = chip;
+        self
+    }
+
+    pub fn inner(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn inner_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: PsgWrite> Psg for Shadowed<W> {
+    type Error = W::Error;
+
+    /// Writes through to `W`. Addresses past R15 have no register, and are
+    /// dropped.
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), W::Error> {
+        let Some(register) = Register::from_address(address) else {
+            return Ok(());
+        };
+        self.writer.write_register(register, data)?;
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.chip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording_bus::RecordingBus;
+    use crate::{Channel, ChannelLevel};
+
+    /// A music engine of someone else's, knowing only [`PsgWrite`].
+    fn engine<W: PsgWrite>(psg: &mut W) -> Result<(), W::Error> {
+        psg.write_register(Register::AFine, 0x1C)?;
+        psg.write_register(Register::ALevel, 15)
+    }
+
+    #[test]
+    fn every_psg_takes_register_writes() {
+        let mut bus = RecordingBus::<8>::new();
+        engine(&mut bus).unwrap();
+        bus.assert_register_sequence(&[(0x0, 0x1C), (0x8, 15)]);
+        assert!(Register::ALL
+            .iter()
+            .enumerate()
+            .all(|(address, &register)| Register::from_address(address as u8) == Some(register)));
+        assert_eq!(Register::from_address(16), None);
+        assert_eq!(Register::EnvelopeShape.name(), "ENV_SHAPE");
+    }
+
+    #[test]
+    fn shadowed_writers_play_everything() {
+        let mut psg = Shadowed::new(RecordingBus::<16>::new()).with_master_clock(1_000_000);
+        psg.update_channel_level(Channel::B, ChannelLevel::Fixed(9))
+            .unwrap();
+        // The shadow skips what is already there.
+        psg.update_channel_level(Channel::B, ChannelLevel::Fixed(9))
+            .unwrap();
+        psg.set_register_value(0x10, 1).unwrap();
+        assert_eq!(psg.master_clock(), 1_000_000);
+        assert_eq!(psg.registers().value(0x9), 9);
+        psg.inner().assert_register_sequence(&[(0x9, 9)]);
+    }
+}
@@ -0,0 +1,1088 @@
+//! Pro Tracker 3 modules, as made with Vortex Tracker II, played through
+//! the module's own replay routine into register frames.
+//!
+//! A module is a fixed header, then a list of positions, each naming a
+//! pattern, and the patterns, samples and ornaments it points to, with every
+//! pointer a 16-bit offset from the start of the module:
+//!
+//! | Offset | Contents |
+//! |---|---|
+//! | `0x00` | `ProTracker 3.`*v*, *v* being the minor version |
+//! | `0x1E`, `0x42` | The name and author, 32 bytes each |
+//! | `0x63` | The tone table: PT, ST, ASM or REAL |
+//! | `0x64` | Ticks per pattern row |
+//! | `0x65`, `0x66` | The number of positions and the one to loop to |
+//! | `0x67` | The pattern table, three channel pointers a pattern |
+//! | `0x69` | 32 sample pointers |
+//! | `0xA9` | 16 ornament pointers |
+//! | `0xC9` | The positions, each the pattern number times 3 |
+//!
+//! A sample is per-tick tone, level, noise and envelope offsets with a loop;
+//! an ornament is per-tick note offsets with a loop. Patterns are a byte
+//! code of notes, sample and ornament changes, envelope settings and
+//! effects (tone slides, portamento, sample and ornament offsets, on-off
+//! gating, envelope slides and tempo), which [`Pt3Player`] runs a tick at
+//! a time, as the Z80 player does.
+//!
+//! The volume tables follow the player's own generator for 3.4 and earlier
+//! and for 3.5 and later. The tone tables are the 3.5 ones, which are used
+//! for every version; the slightly different 3.3 and 3.4 tables are not
+//! reproduced. Only a header, positions and pattern table that hold
+//! together are checked up front; pattern, sample and ornament data is read
+//! as it is met, with anything past the end of the module read as zeros.
+
+use crate::frame_player::{
+    Frame, FramePlayer, FrameSource, FrameStatus, SourceError, R13_UNCHANGED,
+};
+
+/// Frames a second in every PT3 module.
+pub const FRAME_HERTZ: u32 = 50;
+
+/// The most ticks [`Pt3Module::length`] plays through looking for the end,
+/// about six hours.
+pub const MAX_FRAMES: u32 = 1 << 20;
+
+const HEADER: usize = 0xC9;
+const MAGIC: &[u8; 13] = b"ProTracker 3.";
+const VORTEX: &[u8; 17] = b"Vortex Tracker II";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pt3Error {
+    /// There is no `ProTracker 3.` header.
+    NotPt3,
+    /// The header or position list runs past the end of the data.
+    Truncated,
+    /// No positions, or a loop position past the last.
+    BadPositions,
+    /// A position isn't a multiple of 3, or its pattern's pointers lie
+    /// outside the module.
+    BadPattern(u8),
+}
+
+/// Which period a note number plays at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneTable {
+    /// Pro Tracker's own, for a 1.77 MHz clock.
+    ProTracker,
+    /// Sound Tracker's.
+    SoundTracker,
+    /// ASM or PSC, for a 1.75 MHz clock.
+    Asm,
+    /// True equal temperament at 1.77 MHz.
+    Real,
+}
+
+impl ToneTable {
+    pub const fn from_header(value: u8) -> ToneTable {
+        match value & 3 {
+            0 => ToneTable::ProTracker,
+            1 => ToneTable::SoundTracker,
+            2 => ToneTable::Asm,
+            _ => ToneTable::Real,
+        }
+    }
+
+    /// The period of note `note`, 0 to 95, C1 upwards.
+    pub const fn period(self, note: u8) -> u16 {
+        let note = if note > 95 { 95 } else { note } as usize;
+        match self {
+            ToneTable::ProTracker => PT_TABLE[note],
+            ToneTable::SoundTracker => ST_TABLE[note],
+            ToneTable::Asm => ASM_TABLE[note],
+            ToneTable::Real => REAL_TABLE[note],
+        }
+    }
+}
+
+#[rustfmt::skip]
+const PT_TABLE: [u16; 96] = [
+    0x0C22, 0x0B73, 0x0ACF, 0x0A33, 0x09A1, 0x0917, 0x0894, 0x0819, 0x07A4, 0x0737, 0x06CF, 0x066D,
+    0x0611, 0x05BA, 0x0567, 0x051A, 0x04D0, 0x048B, 0x044A, 0x040C, 0x03D2, 0x039B, 0x0367, 0x0337,
+    0x0308, 0x02DD, 0x02B4, 0x028D, 0x0268, 0x0246, 0x0225, 0x0206, 0x01E9, 0x01CE, 0x01B4, 0x019B,
+    0x0184, 0x016E, 0x015A, 0x0146, 0x0134, 0x0123, 0x0112, 0x0103, 0x00F5, 0x00E7, 0x00DA, 0x00CE,
+    0x00C2, 0x00B7, 0x00AD, 0x00A3, 0x009A, 0x0091, 0x0089, 0x0082, 0x007A, 0x0073, 0x006D, 0x0067,
+    0x0061, 0x005C, 0x0056, 0x0052, 0x004D, 0x0049, 0x0045, 0x0041, 0x003D, 0x003A, 0x0036, 0x0033,
+    0x0031, 0x002E, 0x002B, 0x0029, 0x0027, 0x0024, 0x0022, 0x0020, 0x001F, 0x001D, 0x001B, 0x001A,
+    0x0018, 0x0017, 0x0016, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D, 0x000C,
+];
+
+#[rustfmt::skip]
+const ST_TABLE: [u16; 96] = [
+    0x0EF8, 0x0E10, 0x0D60, 0x0C80, 0x0BD8, 0x0B28, 0x0A88, 0x09F0, 0x0960, 0x08E0, 0x0858, 0x07E0,
+    0x077C, 0x0708, 0x06B0, 0x0640, 0x05EC, 0x0594, 0x0544, 0x04F8, 0x04B0, 0x0470, 0x042C, 0x03FD,
+    0x03BE, 0x0384, 0x0358, 0x0320, 0x02F6, 0x02CA, 0x02A2, 0x027C, 0x0258, 0x0238, 0x0216, 0x01F8,
+    0x01DF, 0x01C2, 0x01AC, 0x0190, 0x017B, 0x0165, 0x0151, 0x013E, 0x012C, 0x011C, 0x010A, 0x00FC,
+    0x00EF, 0x00E1, 0x00D6, 0x00C8, 0x00BD, 0x00B2, 0x00A8, 0x009F, 0x0096, 0x008E, 0x0085, 0x007E,
+    0x0077, 0x0070, 0x006B, 0x0064, 0x005E, 0x0059, 0x0054, 0x004F, 0x004B, 0x0047, 0x0042, 0x003F,
+    0x003B, 0x0038, 0x0035, 0x0032, 0x002F, 0x002C, 0x002A, 0x0027, 0x0025, 0x0023, 0x0021, 0x001F,
+    0x001D, 0x001C, 0x001A, 0x0019, 0x0017, 0x0016, 0x0015, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F,
+];
+
+#[rustfmt::skip]
+const ASM_TABLE: [u16; 96] = [
+    0x0D10, 0x0C55, 0x0BA4, 0x0AFC, 0x0A5F, 0x09CA, 0x093D, 0x08B8, 0x083B, 0x07C5, 0x0755, 0x06EC,
+    0x0688, 0x062A, 0x05D2, 0x057E, 0x052F, 0x04E5, 0x049E, 0x045C, 0x041D, 0x03E2, 0x03AB, 0x0376,
+    0x0344, 0x0315, 0x02E9, 0x02BF, 0x0298, 0x0272, 0x024F, 0x022E, 0x020F, 0x01F1, 0x01D5, 0x01BB,
+    0x01A2, 0x018B, 0x0174, 0x0160, 0x014C, 0x0139, 0x0128, 0x0117, 0x0107, 0x00F9, 0x00EB, 0x00DD,
+    0x00D1, 0x00C5, 0x00BA, 0x00B0, 0x00A6, 0x009D, 0x0094, 0x008C, 0x0084, 0x007C, 0x0075, 0x006F,
+    0x0069, 0x0063, 0x005D, 0x0058, 0x0053, 0x004E, 0x004A, 0x0046, 0x0042, 0x003E, 0x003B, 0x0037,
+    0x0034, 0x0031, 0x002F, 0x002C, 0x0029, 0x0027, 0x0025, 0x0023, 0x0021, 0x001F, 0x001D, 0x001C,
+    0x001A, 0x0019, 0x0017, 0x0016, 0x0015, 0x0014, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D,
+];
+
+#[rustfmt::skip]
+const REAL_TABLE: [u16; 96] = [
+    0x0CDA, 0x0C22, 0x0B73, 0x0ACF, 0x0A33, 0x09A1, 0x0917, 0x0894, 0x0819, 0x07A4, 0x0737, 0x06CF,
+    0x066D, 0x0611, 0x05BA, 0x0567, 0x051A, 0x04D0, 0x048B, 0x044A, 0x040C, 0x03D2, 0x039B, 0x0367,
+    0x0337, 0x0308, 0x02DD, 0x02B4, 0x028D, 0x0268, 0x0246, 0x0225, 0x0206, 0x01E9, 0x01CE, 0x01B4,
+    0x019B, 0x0184, 0x016E, 0x015A, 0x0146, 0x0134, 0x0123, 0x0112, 0x0103, 0x00F5, 0x00E7, 0x00DA,
+    0x00CE, 0x00C2, 0x00B7, 0x00AD, 0x00A3, 0x009A, 0x0091, 0x0089, 0x0082, 0x007A, 0x0073, 0x006D,
+    0x0067, 0x0061, 0x005C, 0x0056, 0x0052, 0x004D, 0x0049, 0x0045, 0x0041, 0x003D, 0x003A, 0x0036,
+    0x0033, 0x0031, 0x002E, 0x002B, 0x0029, 0x0027, 0x0024, 0x0022, 0x0020, 0x001F, 0x001D, 0x001B,
+    0x001A, 0x0018, 0x0017, 0x0016, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D,
+];
+
+/// The player's volume table generator: row `v` is `a * d / 256` for each
+/// level `a`, with `d` growing by `step` a row, rounded in 3.5 and later,
+/// which also pushes `d` past `0x77`.
+const fn volume_table(first: u16, step: u16, new: bool) -> [[u8; 16]; 16] {
+    let mut table = [[0; 16]; 16];
+    let mut d = first;
+    let mut v = 1;
+    while v < 16 {
+        d += step;
+        let mut product: u16 = 0;
+        let mut a = 0;
+        while a < 16 {
+            let round = if new { (product >> 7) & 1 } else { 0 };
+            table[v][a] = ((product >> 8) + round) as u8;
+            product += d;
+            a += 1;
+        }
+        if new && d & 0xFF == 0x77 {
+            d += 1;
+        }
+        v += 1;
+    }
+    table
+}
+
+const VOLUME_33_34: [[u8; 16]; 16] = volume_table(0x10, 0x10, false);
+const VOLUME_35: [[u8; 16]; 16] = volume_table(0, 0x11, true);
+
+fn word(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([
+        data.get(at).copied().unwrap_or(0),
+        data.get(at + 1).copied().unwrap_or(0),
+    ])
+}
+
+/// A checked PT3 module, borrowing its data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pt3Module<'a> {
+    data: &'a [u8],
+    version: u8,
+    positions: &'a [u8],
+}
+
+impl<'a> Pt3Module<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Pt3Module<'a>, Pt3Error> {
+        if !data.starts_with(MAGIC) && !data.starts_with(VORTEX) {
+            return Err(if data.len() < MAGIC.len() {
+                Pt3Error::Truncated
+            } else {
+                Pt3Error::NotPt3
+            });
+        }
+        if data.len() < HEADER {
+            return Err(Pt3Error::Truncated);
+        }
+        // Vortex Tracker's own header has no version digit; it writes 3.6.
+        let version = match data[13] {
+            digit @ b'0'..=b'9' => digit - b'0',
+            _ => 6,
+        };
+        let count = data[0x65] as usize;
+        let positions = data
+            .get(HEADER..HEADER + count)
+            .ok_or(Pt3Error::Truncated)?;
+        if count == 0 || data[0x66] as usize >= count {
+            return Err(Pt3Error::BadPositions);
+        }
+        let patterns = word(data, 0x67) as usize;
+        for &position in positions {
+            let table = patterns + position as usize * 2;
+            let fits = (0..3).all(|channel| {
+                table + channel * 2 + 2 <= data.len()
+                    && (word(data, table + channel * 2) as usize) < data.len()
+            });
+            if position % 3 != 0 || !fits {
+                return Err(Pt3Error::BadPattern(position));
+            }
+        }
+        Ok(Pt3Module {
+            data,
+            version,
+            positions,
+        })
+    }
+
+    /// The minor version, 5 for 3.5.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// The name, without the padding after it.
+    pub fn name(&self) -> &'a [u8] {
+        trim(&self.data[0x1E..0x3E])
+    }
+
+    pub fn author(&self) -> &'a [u8] {
+        trim(&self.data[0x42..0x62])
+    }
+
+    pub fn tone_table(&self) -> ToneTable {
+        ToneTable::from_header(self.data[0x63])
+    }
+
+    /// Ticks per pattern row at the start.
+    pub fn delay(&self) -> u8 {
+        self.data[0x64]
+    }
+
+    /// The patterns in playing order, each times 3.
+    pub fn positions(&self) -> &'a [u8] {
+        self.positions
+    }
+
+    pub fn loop_position(&self) -> u8 {
+        self.data[0x66]
+    }
+
+    /// How long the module plays before going back to its loop position,
+    /// and the frame it goes back to, found by playing it through.
+    pub fn length(&self) -> Pt3Length {
+        let mut player = Pt3Player::new(*self);
+        while player.wrapped_at.is_none() && player.frame < MAX_FRAMES {
+            player.next_frame();
+        }
+        Pt3Length {
+            frames: player.wrapped_at.unwrap_or(player.frame),
+            loop_frame: player.loop_frame.unwrap_or(0),
+        }
+    }
+
+    /// A [`FramePlayer`] playing the module at 50 Hz, looping as it does.
+    pub fn into_player(self, tick_hertz: u32) -> FramePlayer<Pt3Player<'a>> {
+        let length = self.length();
+        FramePlayer::new(Pt3Player::new(self))
+            .with_loop(length.loop_frame)
+            .with_rate(FRAME_HERTZ * 1000, tick_hertz)
+    }
+}
+
+/// Steps a sample or ornament position on, back to the loop at the end.
+fn advance(position: u8, length: u8, loop_to: u8) -> u8 {
+    match position.checked_add(1) {
+        Some(next) if next < length => next,
+        _ => loop_to,
+    }
+}
+
+fn trim(text: &[u8]) -> &[u8] {
+    let end = text
+        .iter()
+        .rposition(|&byte| byte != b' ' && byte != 0)
+        .map_or(0, |last| last + 1);
+    &text[..end]
+}
+
+/// A module's length in frames, from [`Pt3Module::length`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pt3Length {
+    /// Frames before the module first goes back to its loop position.
+    pub frames: u32,
+    /// The frame the loop position starts on.
+    pub loop_frame: u32,
+}
+
+/// One channel's replay state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+struct Voice {
+    pattern: usize,
+    sample: usize,
+    sample_loop: u8,
+    sample_length: u8,
+    sample_position: u8,
+    ornament: usize,
+    ornament_loop: u8,
+    ornament_length: u8,
+    ornament_position: u8,
+    tone: u16,
+    tone_accumulator: u16,
+    tone_sliding: i16,
+    slide_step: i16,
+    slide_delay: u8,
+    slide_count: u8,
+    /// How far a portamento has to go, and the note it ends on.
+    tone_delta: i16,
+    slide_to_note: u8,
+    simple_gliss: bool,
+    note: u8,
+    volume: u8,
+    amplitude: u8,
+    amplitude_sliding: i8,
+    noise_sliding: u8,
+    envelope_sliding: i8,
+    envelope_enabled: bool,
+    enabled: bool,
+    on_off: u8,
+    on_off_delay: u8,
+    off_on_delay: u8,
+    rows_per_note: u8,
+    rows_left: u8,
+}
+
+/// Plays a [`Pt3Module`], one 50 Hz frame at a time.
+///
+/// As a [`FrameSource`] it ends where the module first goes back to its
+/// loop position, as [`Pt3Module::length`] says, and going back to an
+/// earlier frame plays the module again from the start up to it.
+#[derive(Debug, Clone)]
+pub struct Pt3Player<'a> {
+    module: Pt3Module<'a>,
+    table: ToneTable,
+    volumes: &'static [[u8; 16]; 16],
+    voices: [Voice; 3],
+    position: usize,
+    delay: u8,
+    delay_left: u8,
+    noise_base: u8,
+    add_to_noise: u8,
+    envelope_base: u16,
+    envelope_slide: i16,
+    envelope_slide_add: i16,
+    envelope_delay: u8,
+    envelope_delay_left: u8,
+    /// The shape written this tick, if any.
+    shape: Option<u8>,
+    frame: u32,
+    loop_frame: Option<u32>,
+    wrapped_at: Option<u32>,
+}
+
+impl<'a> Pt3Player<'a> {
+    pub fn new(module: Pt3Module<'a>) -> Pt3Player<'a> {
+        let mut player = Pt3Player {
+            module,
+            table: module.tone_table(),
+            volumes: if module.version <= 4 {
+                &VOLUME_33_34
+            } else {
+                &VOLUME_35
+            },
+            voices: [Voice::default(); 3],
+            position: 0,
+            delay: module.delay(),
+            delay_left: 1,
+            noise_base: 0,
+            add_to_noise: 0,
+            envelope_base: 0,
+            envelope_slide: 0,
+            envelope_slide_add: 0,
+            envelope_delay: 0,
+            envelope_delay_left: 0,
+            shape: None,
+            frame: 0,
+            loop_frame: (module.loop_position() == 0).then_some(0),
+            wrapped_at: None,
+        };
+        player.enter_position(0);
+        for index in 0..3 {
+            player.set_ornament(index, 0);
+            player.set_sample(index, 1);
+            let voice = &mut player.voices[index];
+            voice.volume = 15;
+            voice.rows_per_note = 1;
+            voice.rows_left = 1;
+        }
+        player
+    }
+
+    pub fn module(&self) -> &Pt3Module<'a> {
+        &self.module
+    }
+
+    /// Frames played so far.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The index of the position playing.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Goes back to the start.
+    pub fn reset(&mut self) {
+        *self = Pt3Player::new(self.module);
+    }
+
+    fn byte(&self, at: usize) -> u8 {
+        self.module.data.get(at).copied().unwrap_or(0)
+    }
+
+    fn enter_position(&mut self, position: usize) {
+        self.position = position;
+        let patterns = word(self.module.data, 0x67) as usize;
+        let table = patterns + self.module.positions[position] as usize * 2;
+        for (channel, voice) in self.voices.iter_mut().enumerate() {
+            voice.pattern = word(self.module.data, table + channel * 2) as usize;
+        }
+    }
+
+    fn set_sample(&mut self, index: usize, sample: u8) {
+        let at = word(self.module.data, 0x69 + (sample as usize & 31) * 2) as usize;
+        let (sample_loop, sample_length) = (self.byte(at), self.byte(at + 1));
+        let voice = &mut self.voices[index];
+        voice.sample = at + 2;
+        voice.sample_loop = sample_loop;
+        voice.sample_length = sample_length;
+    }
+
+    fn set_ornament(&mut self, index: usize, ornament: u8) {
+        let at = word(self.module.data, 0xA9 + (ornament as usize & 15) * 2) as usize;
+        let (ornament_loop, ornament_length) = (self.byte(at), self.byte(at + 1));
+        let voice = &mut self.voices[index];
+        voice.ornament = at + 2;
+        voice.ornament_loop = ornament_loop;
+        voice.ornament_length = ornament_length;
+    }
+
+    /// Reads the envelope shape and base after an envelope command.
+    fn set_envelope(&mut self, shape: u8, at: &mut usize) {
+        self.shape = Some(shape);
+        self.envelope_base = (self.byte(*at + 1) as u16) << 8 | self.byte(*at + 2) as u16;
+        *at += 2;
+        self.envelope_slide = 0;
+        self.envelope_delay_left = 0;
+    }
+
+    /// Runs a channel's pattern up to its next row.
+    fn interpret(&mut self, index: usize) {
+        let mut at = self.voices[index].pattern;
+        let previous_note = self.voices[index].note;
+        let previous_sliding = self.voices[index].tone_sliding;
+        // The effects in the order they came, their parameters read once the
+        // row is over.
+        let mut effects = [0u8; 7];
+        let mut effect_count = 0;
+        loop {
+            // Anything past the end of the module is an empty row.
+            let command = self.module.data.get(at).copied().unwrap_or(0xD0);
+            match command {
+                0xF0..=0xFF => {
+                    self.set_ornament(index, command - 0xF0);
+                    at += 1;
+                    let sample = self.byte(at) / 2;
+                    self.set_sample(index, sample);
+                    let voice = &mut self.voices[index];
+                    voice.envelope_enabled = false;
+                    voice.ornament_position = 0;
+                }
+                0xD1..=0xEF => self.set_sample(index, command - 0xD0),
+                0xD0 => break,
+                0xC1..=0xCF => self.voices[index].volume = command - 0xC0,
+                0xC0 => {
+                    let voice = &mut self.voices[index];
+                    voice.restart();
+                    voice.enabled = false;
+                    break;
+                }
+                0xB2..=0xBF => {
+                    self.set_envelope(command - 0xB1, &mut at);
+                    let voice = &mut self.voices[index];
+                    voice.envelope_enabled = true;
+                    voice.ornament_position = 0;
+                }
+                0xB1 => {
+                    at += 1;
+                    self.voices[index].rows_per_note = self.byte(at);
+                }
+                0xB0 => {
+                    let voice = &mut self.voices[index];
+                    voice.envelope_enabled = false;
+                    voice.ornament_position = 0;
+                }
+                0x50..=0xAF => {
+                    let voice = &mut self.voices[index];
+                    voice.note = command - 0x50;
+                    voice.restart();
+                    voice.enabled = true;
+                    break;
+                }
+                0x40..=0x4F => {
+                    self.set_ornament(index, command - 0x40);
+                    self.voices[index].ornament_position = 0;
+                }
+                0x20..=0x3F => self.noise_base = command - 0x20,
+                0x10..=0x1F => {
+                    if command == 0x10 {
+                        self.voices[index].envelope_enabled = false;
+                    } else {
+                        self.set_envelope(command - 0x10, &mut at);
+                        self.voices[index].envelope_enabled = true;
+                    }
+                    at += 1;
+                    let sample = self.byte(at) / 2;
+                    self.set_sample(index, sample);
+                    self.voices[index].ornament_position = 0;
+                }
+                0x01..=0x05 | 0x08 | 0x09 => {
+                    // An effect given twice counts where it was last given.
+                    if let Some(earlier) =
+                        effects[..effect_count].iter().position(|&e| e == command)
+                    {
+                        effects.copy_within(earlier + 1..effect_count, earlier);
+                        effect_count -= 1;
+                    }
+                    effects[effect_count] = command;
+                    effect_count += 1;
+                }
+                _ => {}
+            }
+            at += 1;
+        }
+        at += 1;
+
+        // Parameters follow the row, last effect first.
+        for &effect in effects[..effect_count].iter().rev() {
+            match effect {
+                0x01 => {
+                    let delay = self.byte(at);
+                    let step = word(self.module.data, at + 1) as i16;
+                    at += 3;
+                    let version = self.module.version;
+                    let voice = &mut self.voices[index];
+                    voice.slide_delay = delay;
+                    voice.slide_count = delay;
+                    voice.slide_step = step;
+                    voice.simple_gliss = true;
+                    voice.on_off = 0;
+                    if voice.slide_count == 0 && version >= 7 {
+                        voice.slide_count = 1;
+                    }
+                }
+                0x02 => {
+                    let delay = self.byte(at);
+                    // Two bytes the player doesn't use, then the step.
+                    let step = (word(self.module.data, at + 3) as i16).wrapping_abs();
+                    at += 5;
+                    let (table, version) = (self.table, self.module.version);
+                    let voice = &mut self.voices[index];
+                    voice.simple_gliss = false;
+                    voice.on_off = 0;
+                    voice.slide_delay = delay;
+                    voice.slide_count = delay;
+                    voice.tone_delta =
+                        table.period(voice.note) as i16 - table.period(previous_note) as i16;
+                    voice.slide_to_note = voice.note;
+                    voice.note = previous_note;
+                    if version >= 6 {
+                        voice.tone_sliding = previous_sliding;
+                    }
+                    voice.slide_step = if voice.tone_delta.wrapping_sub(voice.tone_sliding) < 0 {
+                        step.wrapping_neg()
+                    } else {
+                        step
+                    };
+                }
+                0x03 => {
+                    self.voices[index].sample_position = self.byte(at);
+                    at += 1;
+                }
+                0x04 => {
+                    self.voices[index].ornament_position = self.byte(at);
+                    at += 1;
+                }
+                0x05 => {
+                    let (on_off, off_on) = (self.byte(at), self.byte(at + 1));
+                    at += 2;
+                    let voice = &mut self.voices[index];
+                    voice.on_off_delay = on_off;
+                    voice.off_on_delay = off_on;
+                    voice.on_off = on_off;
+                    voice.slide_count = 0;
+                    voice.tone_sliding = 0;
+                }
+                0x08 => {
+                    self.envelope_delay = self.byte(at);
+                    self.envelope_delay_left = self.envelope_delay;
+                    self.envelope_slide_add = word(self.module.data, at + 1) as i16;
+                    at += 3;
+                }
+                _ => {
+                    self.delay = self.byte(at);
+                    at += 1;
+                }
+            }
+        }
+        let voice = &mut self.voices[index];
+        voice.pattern = at;
+        voice.rows_left = voice.rows_per_note;
+    }
+
+    /// Plays a channel's sample and ornament for this tick, adding to the
+    /// envelope offset and mixer.
+    fn update_voice(&mut self, index: usize, add_to_envelope: &mut i16, mixer: &mut u8) {
+        let (table, volumes) = (self.table, self.volumes);
+        let line = self.voices[index].sample + self.voices[index].sample_position as usize * 4;
+        let (b0, b1) = (self.byte(line), self.byte(line + 1));
+        let sample_tone = word(self.module.data, line + 2);
+        let ornament = self
+            .byte(self.voices[index].ornament + self.voices[index].ornament_position as usize)
+            as i8;
+        let mut add_to_noise = None;
+        let voice = &mut self.voices[index];
+        if voice.enabled {
+            let tone = sample_tone.wrapping_add(voice.tone_accumulator);
+            if b1 & 0x40 != 0 {
+                voice.tone_accumulator = tone;
+            }
+            let note = (voice.note as i16 + ornament as i16).clamp(0, 95) as u8;
+            voice.tone = tone
+                .wrapping_add(voice.tone_sliding as u16)
+                .wrapping_add(table.period(note))
+                & 0xFFF;
+            if voice.slide_count > 0 {
+                voice.slide_count -= 1;
+                if voice.slide_count == 0 {
+                    voice.tone_sliding = voice.tone_sliding.wrapping_add(voice.slide_step);
+                    voice.slide_count = voice.slide_delay;
+                    let arrived = if voice.slide_step < 0 {
+                        voice.tone_sliding <= voice.tone_delta
+                    } else {
+                        voice.tone_sliding >= voice.tone_delta
+                    };
+                    if !voice.simple_gliss && arrived {
+                        voice.note = voice.slide_to_note;
+                        voice.slide_count = 0;
+                        voice.tone_sliding = 0;
+                    }
+                }
+            }
+
+            if b0 & 0x80 != 0 {
+                if b0 & 0x40 != 0 {
+                    voice.amplitude_sliding = (voice.amplitude_sliding + 1).min(15);
+                } else {
+                    voice.amplitude_sliding = (voice.amplitude_sliding - 1).max(-15);
+                }
+            }
+            let amplitude = ((b1 & 0xF) as i8 + voice.amplitude_sliding).clamp(0, 15);
+            voice.amplitude = volumes[voice.volume as usize & 15][amplitude as usize];
+            if b0 & 1 == 0 && voice.envelope_enabled {
+                voice.amplitude |= 0x10;
+            }
+
+            if b1 & 0x80 != 0 {
+                let offset = if b0 & 0x20 != 0 {
+                    (b0 >> 1 | 0xF0) as i8
+                } else {
+                    (b0 >> 1 & 0xF) as i8
+                };
+                let offset = offset.wrapping_add(voice.envelope_sliding);
+                if b1 & 0x20 != 0 {
+                    voice.envelope_sliding = offset;
+                }
+                *add_to_envelope = add_to_envelope.wrapping_add(offset as i16);
+            } else {
+                let noise = (b0 >> 1).wrapping_add(voice.noise_sliding);
+                if b1 & 0x20 != 0 {
+                    voice.noise_sliding = noise;
+                }
+                add_to_noise = Some(noise);
+            }
+            *mixer |= b1 >> 1 & 0x48;
+
+            // Effects 3 and 4 can put a position anywhere, 255 included.
+            voice.sample_position = advance(
+                voice.sample_position,
+                voice.sample_length,
+                voice.sample_loop,
+            );
+            voice.ornament_position = advance(
+                voice.ornament_position,
+                voice.ornament_length,
+                voice.ornament_loop,
+            );
+        } else {
+            voice.amplitude = 0;
+        }
+        *mixer >>= 1;
+
+        if voice.on_off > 0 {
+            voice.on_off -= 1;
+            if voice.on_off == 0 {
+                voice.enabled = !voice.enabled;
+                voice.on_off = if voice.enabled {
+                    voice.on_off_delay
+                } else {
+                    voice.off_on_delay
+                };
+            }
+        }
+        if let Some(noise) = add_to_noise {
+            self.add_to_noise = noise;
+        }
+    }
+
+    /// Plays the next tick and returns the registers it leaves.
+    pub fn next_frame(&mut self) -> Frame {
+        self.shape = None;
+        self.delay_left = self.delay_left.wrapping_sub(1);
+        if self.delay_left == 0 {
+            for index in 0..3 {
+                let voice = &mut self.voices[index];
+                voice.rows_left = voice.rows_left.wrapping_sub(1);
+                if voice.rows_left != 0 {
+                    continue;
+                }
+                if index == 0 && self.byte(self.voices[0].pattern) == 0 {
+                    let mut next = self.position + 1;
+                    if next == self.module.positions.len() {
+                        next = self.module.loop_position() as usize;
+                        self.wrapped_at.get_or_insert(self.frame);
+                    } else if next == self.module.loop_position() as usize {
+                        self.loop_frame.get_or_insert(self.frame);
+                    }
+                    self.enter_position(next);
+                    self.noise_base = 0;
+                }
+                self.interpret(index);
+            }
+            self.delay_left = self.delay;
+        }
+
+        let mut add_to_envelope = 0;
+        let mut mixer = 0;
+        for index in 0..3 {
+            self.update_voice(index, &mut add_to_envelope, &mut mixer);
+        }
+
+        let mut frame = [0; 16];
+        for (index, voice) in self.voices.iter().enumerate() {
+            frame[index * 2..index * 2 + 2].copy_from_slice(&voice.tone.to_le_bytes());
+            frame[0x8 + index] = voice.amplitude;
+        }
+        frame[0x6] = self.noise_base.wrapping_add(self.add_to_noise) & 0x1F;
+        frame[0x7] = mixer;
+        let envelope = self
+            .envelope_base
+            .wrapping_add(add_to_envelope as u16)
+            .wrapping_add(self.envelope_slide as u16);
+        frame[0xB..0xD].copy_from_slice(&envelope.to_le_bytes());
+        frame[0xD] = self.shape.unwrap_or(R13_UNCHANGED);
+
+        if self.envelope_delay_left > 0 {
+            self.envelope_delay_left -= 1;
+            if self.envelope_delay_left == 0 {
+                self.envelope_delay_left = self.envelope_delay;
+                self.envelope_slide = self.envelope_slide.wrapping_add(self.envelope_slide_add);
+            }
+        }
+        self.frame += 1;
+        frame
+    }
+}
+
+impl Voice {
+    /// What a new note or a note-off starts over.
+    fn restart(&mut self) {
+        self.sample_position = 0;
+        self.amplitude_sliding = 0;
+        self.noise_sliding = 0;
+        self.envelope_sliding = 0;
+        self.ornament_position = 0;
+        self.slide_count = 0;
+        self.tone_sliding = 0;
+        self.tone_accumulator = 0;
+        self.on_off = 0;
+    }
+}
+
+impl FrameSource for Pt3Player<'_> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        if index < self.frame {
+            self.reset();
+        }
+        while self.frame < index && self.wrapped_at.is_none() {
+            self.next_frame();
+        }
+        if self.wrapped_at.is_some_and(|end| index >= end) {
+            return Ok(FrameStatus::End);
+        }
+        *frame = self.next_frame();
+        if self.wrapped_at.is_some() {
+            return Ok(FrameStatus::End);
+        }
+        Ok(FrameStatus::Ready)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::psg::Psg;
+    use crate::test_support::{mangled, FakePsg};
+    use std::vec::Vec;
+
+    /// A module of one pattern, four rows at three ticks a row:
+    ///
+    /// - A: two rows a note, ornament 1 (an octave up every other tick) on a
+    ///   C-5 that fades through its sample
+    /// - B: envelope 0x0E at 0x0010 on a C-4, then a note-off on row 2
+    /// - C: four rows a note, C-3 with a tone slide of -2 every tick
+    fn module() -> Vec<u8> {
+        let mut data = Vec::from(&b"ProTracker 3.5 compilation of "[..]);
+        data.extend_from_slice(&[b' '; 32]);
+        data.extend_from_slice(b" by ");
+        data.extend_from_slice(b"Nobody");
+        data.resize(0x63, b' ');
+        // PT tone table, 3 ticks a row, 1 position, looping to it.
+        data.extend_from_slice(&[0, 3, 1, 0]);
+        let patterns = 0xCB;
+        let (a, b, c) = (0xD1, 0xD8, 0xE0);
+        let (sample, plain, octaves) = (0xE7, 0xF1, 0xF4);
+        data.extend_from_slice(&(patterns as u16).to_le_bytes());
+        let mut samples = [0u16; 32];
+        samples[1] = sample;
+        for pointer in samples {
+            data.extend_from_slice(&pointer.to_le_bytes());
+        }
+        let mut ornaments = [0u16; 16];
+        ornaments[0] = plain;
+        ornaments[1] = octaves;
+        for pointer in ornaments {
+            data.extend_from_slice(&pointer.to_le_bytes());
+        }
+        assert_eq!(data.len(), HEADER);
+        data.extend_from_slice(&[0x00, 0xFF]);
+        for pointer in [a, b, c] {
+            data.extend_from_slice(&(pointer as u16).to_le_bytes());
+        }
+        assert_eq!(data.len(), a);
+        data.extend_from_slice(&[0xB1, 0x02, 0xF1, 0x02, 0x80, 0xD0, 0x00]);
+        data.extend_from_slice(&[0x1E, 0x00, 0x10, 0x02, 0x74, 0xD0, 0xC0, 0xD0]);
+        data.extend_from_slice(&[0xB1, 0x04, 0x01, 0x68, 0x01, 0xFE, 0xFF]);
+        assert_eq!(data.len(), sample as usize);
+        // Full level with the noise off; then a tone offset of 1 and a fade.
+        data.extend_from_slice(&[0, 2, 0x00, 0x8F, 0, 0, 0x80, 0x8F, 1, 0]);
+        data.extend_from_slice(&[0, 1, 0]);
+        data.extend_from_slice(&[0, 2, 0, 12]);
+        data
+    }
+
+    /// What the replay routine gives for [`module`], worked through by hand
+    /// from the routine; no other player has checked these.
+    fn reference() -> Vec<Frame> {
+        let c_tones = [
+            0x308, 0x307, 0x304, 0x303, 0x300, 0x2FF, 0x2FC, 0x2FB, 0x2F8, 0x2F7, 0x2F4, 0x2F3,
+        ];
+        let levels = [15, 14, 14, 13, 13, 12, 12, 11, 11, 10, 10, 9];
+        (0..12)
+            .map(|tick| {
+                let a_tone: u16 = if tick % 2 == 0 { 0xC2 } else { 0x62 };
+                let b_tone: u16 = if tick % 2 == 0 && tick < 6 {
+                    0x184
+                } else {
+                    0x185
+                };
+                let mut frame = [0; 16];
+                frame[0..2].copy_from_slice(&a_tone.to_le_bytes());
+                frame[2..4].copy_from_slice(&b_tone.to_le_bytes());
+                frame[4..6].copy_from_slice(&(c_tones[tick] as u16).to_le_bytes());
+                frame[0x7] = if tick < 6 { 0x38 } else { 0x28 };
+                frame[0x8] = levels[tick];
+                frame[0x9] = if tick < 6 { 0x10 | levels[tick] } else { 0 };
+                frame[0xA] = levels[tick];
+                frame[0xB] = 0x10;
+                frame[0xD] = if tick == 0 { 0x0E } else { R13_UNCHANGED };
+                frame
+            })
+            .collect()
+    }
+
+    /// Checks a module's frames against the ones expected of it, frame by
+    /// frame. The ports, R14 and R15, are left out: players dump them as
+    /// they please.
+    fn assert_plays_as(module: &[u8], frames: &[Frame], name: impl core::fmt::Display) {
+        let mut player = Pt3Player::new(Pt3Module::parse(module).unwrap());
+        for (index, expected) in frames.iter().enumerate() {
+            let frame = player.next_frame();
+            for register in 0..14 {
+                assert_eq!(
+                    frame[register], expected[register],
+                    "{name}: R{register} in frame {index}: {frame:02X?}"
+                );
+            }
+        }
+    }
+
+    /// Where modules are kept with the `.psg` dumps other players made of
+    /// them: `name.pt3` beside `name.psg`.
+    const DUMPS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/dumps/pt3");
+
+    /// Every module in [`DUMPS`] with a dump beside it plays as the dump
+    /// does, for as long as the dump runs.
+    #[cfg(feature = "formats-ym")]
+    #[test]
+    #[ignore = "no dump from another player is checked in yet"]
+    fn plays_as_the_dumps_of_other_players() {
+        use crate::psg_file::PsgStream;
+
+        let mut checked = 0;
+        for entry in std::fs::read_dir(DUMPS).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("pt3".as_ref()) {
+                continue;
+            }
+            let Ok(dump) = std::fs::read(path.with_extension("psg")) else {
+                continue;
+            };
+            let mut stream = PsgStream::from_slice(&dump).unwrap();
+            let mut frame = [0; 16];
+            let frames: Vec<Frame> = (0..)
+                .map_while(|index| {
+                    (stream.frame(index, &mut frame) == Ok(FrameStatus::Ready)).then_some(frame)
+                })
+                .collect();
+            let module = std::fs::read(&path).unwrap();
+            assert_plays_as(&module, &frames, path.display());
+            checked += 1;
+        }
+        assert!(checked > 0, "no module in {DUMPS} has a dump beside it");
+    }
+
+    #[test]
+    fn plays_the_frames_worked_out_by_hand() {
+        // Kept as a file too, for other players to make a dump of.
+        let data = module();
+        assert_eq!(
+            std::fs::read(std::format!("{DUMPS}/worked.pt3")).unwrap(),
+            data
+        );
+        assert_plays_as(&data, &reference(), "worked.pt3");
+
+        let module = Pt3Module::parse(&data).unwrap();
+        assert_eq!(module.version(), 5);
+        assert_eq!(module.name(), b"");
+        assert_eq!(module.author(), b"Nobody");
+        assert_eq!(module.tone_table(), ToneTable::ProTracker);
+        assert_eq!(
+            module.length(),
+            Pt3Length {
+                frames: 12,
+                loop_frame: 0,
+            }
+        );
+
+        // Through the frame pipeline, looping back to the start.
+        let mut player = module.into_player(50);
+        player.play();
+        let mut psg = FakePsg::new();
+        let reference = reference();
+        for tick in 0..30 {
+            player.tick(&mut psg).unwrap();
+            let expected = &reference[tick % 12];
+            assert_eq!(
+                psg.registers().values()[..0xD],
+                expected[..0xD],
+                "tick {tick}"
+            );
+        }
+        assert_eq!(player.loops_completed(), 2);
+    }
+
+    #[test]
+    fn effects_at_their_limits_play_on() {
+        // Channel C on a pattern of its own after the module: a sample
+        // position of 255, then a portamento of step 0x8000 down to a note
+        // far above the last.
+        let mut data = module();
+        let pattern = data.len() as u16;
+        data[HEADER + 6..HEADER + 8].copy_from_slice(&pattern.to_le_bytes());
+        data.extend_from_slice(&[0xB1, 0x02, 0xD1, 0x03, 0x68, 0xFF]);
+        data.extend_from_slice(&[0x02, 0x90, 0x01, 0, 0, 0x00, 0x80, 0x00]);
+        let mut player = Pt3Player::new(Pt3Module::parse(&data).unwrap());
+        for _ in 0..24 {
+            player.next_frame();
+        }
+    }
+
+    #[test]
+    fn damaged_modules_fail_cleanly() {
+        for data in mangled(&module()) {
+            let Ok(module) = Pt3Module::parse(&data) else {
+                continue;
+            };
+            let mut player = Pt3Player::new(module);
+            for _ in 0..100 {
+                player.next_frame();
+            }
+        }
+    }
+
+    #[test]
+    fn volume_tables_follow_the_generator() {
+        let identity: [u8; 16] = core::array::from_fn(|level| level as u8);
+        assert_eq!(VOLUME_35[15], identity);
+        assert_eq!(VOLUME_33_34[15], identity);
+        assert_eq!(VOLUME_35[0], [0; 16]);
+        assert_eq!(
+            VOLUME_35[1],
+            [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1]
+        );
+        assert_eq!(
+            VOLUME_33_34[1],
+            [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1]
+        );
+        // The two differ where 3.5 rounds up.
+        assert_eq!(VOLUME_35[2][4], 1);
+        assert_eq!(VOLUME_33_34[2][4], 0);
+        for table in [&VOLUME_35, &VOLUME_33_34] {
+            for row in table {
+                assert!(row.windows(2).all(|pair| pair[0] <= pair[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_modules_that_dont_hold_together() {
+        let data = module();
+        assert_eq!(Pt3Module::parse(b"ProTracker"), Err(Pt3Error::Truncated));
+        assert_eq!(Pt3Module::parse(&[0; 0x100]), Err(Pt3Error::NotPt3));
+        assert_eq!(Pt3Module::parse(&data[..HEADER]), Err(Pt3Error::Truncated));
+        let mut bad = data.clone();
+        bad[0x66] = 1;
+        assert_eq!(Pt3Module::parse(&bad), Err(Pt3Error::BadPositions));
+        let mut bad = data.clone();
+        bad[HEADER] = 4;
+        assert_eq!(Pt3Module::parse(&bad), Err(Pt3Error::BadPattern(4)));
+        let mut bad = data.clone();
+        bad[0x67] = 0xF0;
+        assert_eq!(Pt3Module::parse(&bad), Err(Pt3Error::BadPattern(0)));
+
+        // Pattern data running off the end plays as empty rows.
+        let mut short = data.clone();
+        short[0xCB..0xCD].copy_from_slice(&(data.len() as u16 - 1).to_le_bytes());
+        let module = Pt3Module::parse(&short).unwrap();
+        let mut player = Pt3Player::new(module);
+        for _ in 0..100 {
+            player.next_frame();
+        }
+        assert!(module.length().frames > 0);
+    }
+}
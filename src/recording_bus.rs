@@ -0,0 +1,255 @@
+//! A [`Psg`] that writes to nothing and logs every bus cycle it would have
+//! made, for tests here and in crates built on this one.
+//!
+//! Each register write is logged as the chip sees it: an address latch
+//! selecting the register, then the data write. The log holds `N`
+//! transactions with no heap; once it is full, writes are turned away with
+//! [`LogFull`] rather than dropped unseen. Built for this crate's tests, and
+//! for others with the `test-utils` feature.
+//!
+//! ```
+//! use ym2149::recording_bus::RecordingBus;
+//! use ym2149::{Channel, Psg};
+//!
+//! let mut bus = RecordingBus::<16>::new();
+//! bus.set_channel_period(Channel::B, 0x123).unwrap();
+//! bus.assert_register_sequence(&[(0x2, 0x23), (0x3, 0x01)]);
+//! assert_eq!(bus.writes_to(0x2).collect::<Vec<_>>(), [0x23]);
+//! ```
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::DEFAULT_MASTER_CLOCK;
+use crate::ChipKind;
+
+/// Which bus cycle a [`Transaction`] was.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransactionKind {
+    /// Selecting the register the next data cycle goes to.
+    AddressLatch,
+    DataWrite,
+    Read,
+}
+
+/// One bus cycle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub kind: TransactionKind,
+    /// The byte on the bus: the address itself for a latch.
+    pub value: u8,
+    /// The register latched when the cycle happened.
+    pub register: u8,
+    /// What the timer said, if [`RecordingBus::with_timer`] gave one.
+    pub time: Option<u32>,
+}
+
+/// Returned once a [`RecordingBus`]'s log has no room for the cycles a
+/// write or read needs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LogFull;
+
+/// A fake chip logging up to `N` bus cycles.
+#[derive(Debug, Clone)]
+pub struct RecordingBus<const N: usize> {
+    log: [Transaction; N],
+    len: usize,
+    registers: Registers,
+    master_clock: u32,
+    chip: ChipKind,
+    timer: Option<fn() -> u32>,
+}
+
+impl<const N: usize> RecordingBus<N> {
+    /// An empty log for a chip at 2 MHz.
+    pub const fn new() -> RecordingBus<N> {
+        const EMPTY: Transaction = Transaction {
+            kind: TransactionKind::AddressLatch,
+            value: 0,
+            register: 0,
+            time: None,
+        };
+        RecordingBus {
+            log: [EMPTY; N],
+            len: 0,
+            registers: Registers::new(),
+            master_clock: DEFAULT_MASTER_CLOCK,
+            chip: ChipKind::Ym2149,
+            timer: None,
+        }
+    }
+
+    pub const fn with_master_clock(mut self, hz: u32) -> RecordingBus<N> {
+        self.master_clock = hz;
+        self
+    }
+
+    /// Stands in for `chip`: a YM2149 unless set otherwise.
+    pub const fn with_chip_kind(mut self, chip: ChipKind) -> RecordingBus<N> {
+        self.chip = chip;
+        self
+    }
+
+    /// Stamps each transaction with what `timer` says when it is logged,
+    /// such as a tick counter the test moves on.
+    pub const fn with_timer(mut self, timer: fn() -> u32) -> RecordingBus<N> {
+        self.timer = Some(timer);
+        self
+    }
+
+    /// Every transaction logged, oldest first.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.log[..self.len]
+    }
+
+    /// Empties the log, leaving the registers as they are.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Each register written and the value written to it, in order.
+    pub fn writes(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.transactions()
+            .iter()
+            .filter(|transaction| transaction.kind == TransactionKind::DataWrite)
+            .map(|transaction| (transaction.register, transaction.value))
+    }
+
+    /// The values written to `register`, in order.
+    pub fn writes_to(&self, register: u8) -> impl Iterator<Item = u8> + '_ {
+        self.writes()
+            .filter(move |&(written, _)| written == register)
+            .map(|(_, value)| value)
+    }
+
+    /// Panics unless the writes logged are exactly `expected`, saying where
+    /// they first differ.
+    #[track_caller]
+    pub fn assert_register_sequence(&self, expected: &[(u8, u8)]) {
+        let mut writes = self.writes();
+        for (index, &(register, value)) in expected.iter().enumerate() {
+            match writes.next() {
+                Some(write) if write == (register, value) => {}
+                Some((got, got_value)) => panic!(
+                    "write {index}: expected R{register:X} <- {value:#04x}, \
+                     got R{got:X} <- {got_value:#04x}"
+                ),
+                None => panic!(
+                    "write {index}: expected R{register:X} <- {value:#04x}, \
+                     got no more writes"
+                ),
+            }
+        }
+        let extra = writes.count();
+        assert!(
+            extra == 0,
+            "expected {} writes, got {} more",
+            expected.len(),
+            extra
+        );
+    }
+
+    /// Reads `address` back, as a chip wired with its bus bidirectional
+    /// could, logging the latch and the read. The value is the last one
+    /// written there, or 0.
+    pub fn read_register(&mut self, address: u8) -> Result<u8, LogFull> {
+        let value = self.registers.value(address);
+        self.cycle(address, TransactionKind::Read, value)?;
+        Ok(value)
+    }
+
+    /// Logs a latch of `address` and then a cycle of `kind`.
+    fn cycle(&mut self, address: u8, kind: TransactionKind, value: u8) -> Result<(), LogFull> {
+        if N - self.len < 2 {
+            return Err(LogFull);
+        }
+        let time = self.timer.map(|timer| timer());
+        for (kind, value) in [(TransactionKind::AddressLatch, address), (kind, value)] {
+            self.log[self.len] = Transaction {
+                kind,
+                value,
+                register: address,
+                time,
+            };
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for RecordingBus<N> {
+    fn default() -> RecordingBus<N> {
+        RecordingBus::new()
+    }
+}
+
+impl<const N: usize> Psg for RecordingBus<N> {
+    type Error = LogFull;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), LogFull> {
+        self.cycle(address, TransactionKind::DataWrite, data)?;
+        self.registers.set(address, data);
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.chip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static TICKS: AtomicU32 = AtomicU32::new(0);
+
+    fn tick() -> u32 {
+        TICKS.fetch_add(1, Ordering::Relaxed)
+    }
+
+    #[test]
+    fn writes_are_logged_as_a_latch_then_data() {
+        TICKS.store(7, Ordering::Relaxed);
+        let mut bus = RecordingBus::<4>::new().with_timer(tick);
+        bus.set_register_value(0x8, 15).unwrap();
+        assert_eq!(bus.read_register(0x8), Ok(15));
+        let transaction = |kind, value, time| Transaction {
+            kind,
+            value,
+            register: 0x8,
+            time: Some(time),
+        };
+        assert_eq!(
+            bus.transactions(),
+            [
+                transaction(TransactionKind::AddressLatch, 0x8, 7),
+                transaction(TransactionKind::DataWrite, 15, 7),
+                transaction(TransactionKind::AddressLatch, 0x8, 8),
+                transaction(TransactionKind::Read, 15, 8),
+            ]
+        );
+        // Full: turned away, and not taken into the registers either.
+        assert_eq!(bus.set_register_value(0x9, 1), Err(LogFull));
+        assert!(!bus.registers().is_written(0x9));
+        bus.clear();
+        bus.set_register_value(0x9, 1).unwrap();
+        bus.assert_register_sequence(&[(0x9, 1)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "write 1: expected R1 <- 0x02, got R2 <- 0x02")]
+    fn mismatched_sequences_say_where() {
+        let mut bus = RecordingBus::<8>::new();
+        bus.set_register_value(0x0, 1).unwrap();
+        bus.set_register_value(0x2, 2).unwrap();
+        bus.assert_register_sequence(&[(0x0, 1), (0x1, 2)]);
+    }
+}
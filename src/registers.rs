@@ -0,0 +1,187 @@
+//! A shadow copy of the chip's sixteen registers.
+//!
+//! The YM2149's registers can't be read back through the write-only bus
+//! wiring this crate drives, so the driver remembers what it last wrote.
+//! That lets helpers do read-modify-write on shared registers like the
+//! mixer, and lets repeated writes of an unchanged value be skipped.
+
+use crate::Channel;
+
+/// Number of registers on the chip.
+pub const REGISTER_COUNT: usize = 16;
+
+/// Bits each register actually implements; the chip ignores the rest.
+pub const REGISTER_MASKS: [u8; REGISTER_COUNT] = [
+    0xFF, 0x0F, 0xFF, 0x0F, 0xFF, 0x0F, 0x1F, 0xFF, 0x1F, 0x1F, 0x1F, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF,
+];
+
+/// Each register's name, for traces and messages: the channel and part
+/// for the tone and level registers, or what the shared ones control.
+pub const REGISTER_NAMES: [&str; REGISTER_COUNT] = [
+    "A_FINE",
+    "A_ROUGH",
+    "B_FINE",
+    "B_ROUGH",
+    "C_FINE",
+    "C_ROUGH",
+    "NOISE",
+    "MIXER",
+    "A_LEVEL",
+    "B_LEVEL",
+    "C_LEVEL",
+    "ENV_FINE",
+    "ENV_ROUGH",
+    "ENV_SHAPE",
+    "PORT_A",
+    "PORT_B",
+];
+
+/// One of the chip's registers, by what it does.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Register {
+    AFine,
+    ARough,
+    BFine,
+    BRough,
+    CFine,
+    CRough,
+    Noise,
+    Mixer,
+    ALevel,
+    BLevel,
+    CLevel,
+    EnvelopeFine,
+    EnvelopeRough,
+    EnvelopeShape,
+    PortA,
+    PortB,
+}
+
+impl Register {
+    /// Every register, in address order.
+    pub const ALL: [Register; REGISTER_COUNT] = [
+        Register::AFine,
+        Register::ARough,
+        Register::BFine,
+        Register::BRough,
+        Register::CFine,
+        Register::CRough,
+        Register::Noise,
+        Register::Mixer,
+        Register::ALevel,
+        Register::BLevel,
+        Register::CLevel,
+        Register::EnvelopeFine,
+        Register::EnvelopeRough,
+        Register::EnvelopeShape,
+        Register::PortA,
+        Register::PortB,
+    ];
+
+    pub const fn address(self) -> u8 {
+        self as u8
+    }
+
+    /// The register at `address`, or `None` past R15.
+    pub const fn from_address(address: u8) -> Option<Register> {
+        match address {
+            0..=15 => Some(Register::ALL[address as usize]),
+            _ => None,
+        }
+    }
+
+    /// Its name in [`REGISTER_NAMES`].
+    pub const fn name(self) -> &'static str {
+        REGISTER_NAMES[self as usize]
+    }
+}
+
+/// Mixer value with every tone and noise source disabled and both IO ports
+/// as inputs, assumed when the mixer has never been written.
+pub const MIXER_ALL_DISABLED: u8 = 0b0011_1111;
+
+/// The sixteen register values of one frame, R0 first.
+pub type Frame = [u8; REGISTER_COUNT];
+
+/// In a frame's R13, leaves the envelope running rather than restarting it.
+pub const R13_UNCHANGED: u8 = 0xFF;
+
+/// A frame's mixer value with the port direction bits kept from `current`.
+pub(crate) const fn frame_mixer(value: u8, current: u8) -> u8 {
+    value & 0x3F | current & 0xC0
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Registers {
+    values: [u8; REGISTER_COUNT],
+    written: u16,
+}
+
+impl Registers {
+    /// A snapshot where no register has been written yet.
+    pub const fn new() -> Registers {
+        Registers {
+            values: [0; REGISTER_COUNT],
+            written: 0,
+        }
+    }
+
+    /// A snapshot with every register known, e.g. from a register dump.
+    pub fn from_values(values: [u8; REGISTER_COUNT]) -> Registers {
+        let mut registers = Registers::new();
+        for (address, value) in values.iter().enumerate() {
+            registers.set(address as u8, *value);
+        }
+        registers
+    }
+
+    /// Last value written to `address`, or `None` if it is unknown.
+    pub fn get(&self, address: u8) -> Option<u8> {
+        if self.is_written(address) {
+            Some(self.values[address as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Last value written to `address`, treating unknown registers as 0.
+    pub fn value(&self, address: u8) -> u8 {
+        self.values[address as usize & 0xF]
+    }
+
+    pub fn is_written(&self, address: u8) -> bool {
+        address < REGISTER_COUNT as u8 && self.written & (1 << address) != 0
+    }
+
+    /// Records a write, keeping only the bits the register implements.
+    /// Addresses above 15 are ignored.
+    pub fn set(&mut self, address: u8, value: u8) {
+        if let Some(slot) = self.values.get_mut(address as usize) {
+            *slot = value & REGISTER_MASKS[address as usize];
+            self.written |= 1 << address;
+        }
+    }
+
+    /// Forgets every value, e.g. after the chip has been reset behind our back.
+    pub fn invalidate(&mut self) {
+        self.written = 0;
+    }
+
+    /// The raw register values, with unknown registers as 0.
+    pub fn values(&self) -> &[u8; REGISTER_COUNT] {
+        &self.values
+    }
+
+    /// The 12-bit tone period of `channel`.
+    pub fn tone_period(&self, channel: Channel) -> u16 {
+        let (fine, rough) = channel.period_registers();
+        self.value(fine) as u16 | (self.value(rough) as u16) << 8
+    }
+
+    /// The mixer register, assuming [`MIXER_ALL_DISABLED`] if never written.
+    pub fn mixer(&self) -> u8 {
+        self.get(0x7).unwrap_or(MIXER_ALL_DISABLED)
+    }
+}
@@ -0,0 +1,559 @@
+//! RTTTL, the Nokia ringtone format: a melody in one line of text.
+//!
+//! A tune is three sections split by colons: a name, defaults, and notes,
+//! as in `Beep:d=8,o=5,b=120:c,e,g,2c6`. The defaults give the duration
+//! (`d`, as a fraction of a whole note), octave (`o`) and tempo (`b`, in
+//! quarter notes a minute) for notes that don't say; any left out are 4, 6
+//! and 63. Each note is an optional duration, a letter `a` to `h` (`h` being
+//! the German B) or `p` for a rest, an optional `#`, an optional octave and
+//! an optional `.` to make it half as long again, which some tunes put
+//! before the octave instead. Case doesn't matter and spaces are ignored.
+//!
+//! Octaves are scientific, so `a4` is concert A.
+
+use crate::instrument::{apply_frame, Instrument, InstrumentPlayer};
+use crate::parse_error::ParseError;
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::{Channel, ChannelLevel};
+
+const DEFAULT_DURATION: u8 = 4;
+const DEFAULT_OCTAVE: u8 = 6;
+const DEFAULT_BPM: u16 = 63;
+
+/// The fastest tempo the format allows.
+pub const MAX_BPM: u16 = 900;
+
+/// The highest octave a note may be in.
+pub const MAX_OCTAVE: u8 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RtttlErrorKind {
+    /// Fewer than three sections.
+    MissingSection,
+    /// A default that isn't `d`, `o` or `b` followed by `=` and a number.
+    BadDefault,
+    /// A duration other than 1, 2, 4, 8, 16, 32 or 64.
+    BadDuration,
+    /// An octave above [`MAX_OCTAVE`].
+    BadOctave,
+    /// A tempo of 0 or above [`MAX_BPM`].
+    BadTempo,
+    /// Something other than a note where one should be.
+    BadNote,
+}
+
+/// What is wrong with a tune, and the byte it was found at.
+pub type RtttlError = ParseError<RtttlErrorKind>;
+
+fn skip_spaces(text: &[u8], position: &mut usize) {
+    while text.get(*position).is_some_and(u8::is_ascii_whitespace) {
+        *position += 1;
+    }
+}
+
+/// The decimal number at `position`, if there is one, saturating at
+/// `u16::MAX`.
+fn number(text: &[u8], position: &mut usize) -> Option<u16> {
+    let start = *position;
+    let mut value: u16 = 0;
+    while let Some(digit) = text.get(*position).filter(|byte| byte.is_ascii_digit()) {
+        value = value
+            .saturating_mul(10)
+            .saturating_add((digit - b'0') as u16);
+        *position += 1;
+    }
+    (*position > start).then_some(value)
+}
+
+fn duration(value: u16, offset: usize) -> Result<u8, RtttlError> {
+    match value {
+        1 | 2 | 4 | 8 | 16 | 32 | 64 => Ok(value as u8),
+        _ => Err(RtttlError::new(RtttlErrorKind::BadDuration, offset)),
+    }
+}
+
+fn octave(value: u16, offset: usize) -> Result<u8, RtttlError> {
+    if value <= MAX_OCTAVE as u16 {
+        Ok(value as u8)
+    } else {
+        Err(RtttlError::new(RtttlErrorKind::BadOctave, offset))
+    }
+}
+
+/// The defaults notes fall back on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Defaults {
+    pub duration: u8,
+    pub octave: u8,
+    pub bpm: u16,
+}
+
+impl Defaults {
+    fn parse(text: &[u8], start: usize, end: usize) -> Result<Defaults, RtttlError> {
+        let mut defaults = Defaults {
+            duration: DEFAULT_DURATION,
+            octave: DEFAULT_OCTAVE,
+            bpm: DEFAULT_BPM,
+        };
+        let text = &text[..end];
+        let mut position = start;
+        loop {
+            skip_spaces(text, &mut position);
+            let Some(&key) = text.get(position) else {
+                return Ok(defaults);
+            };
+            let at = position;
+            let bad = RtttlError::new(RtttlErrorKind::BadDefault, at);
+            position += 1;
+            skip_spaces(text, &mut position);
+            if text.get(position) != Some(&b'=') {
+                return Err(bad);
+            }
+            position += 1;
+            skip_spaces(text, &mut position);
+            let value_at = position;
+            let value = number(text, &mut position).ok_or(bad)?;
+            match key.to_ascii_lowercase() {
+                b'd' => defaults.duration = duration(value, value_at)?,
+                b'o' => defaults.octave = octave(value, value_at)?,
+                b'b' if (1..=MAX_BPM).contains(&value) => defaults.bpm = value,
+                b'b' => return Err(RtttlError::new(RtttlErrorKind::BadTempo, value_at)),
+                _ => return Err(bad),
+            }
+            skip_spaces(text, &mut position);
+            match text.get(position) {
+                None => return Ok(defaults),
+                Some(b',') => position += 1,
+                Some(_) => return Err(RtttlError::new(RtttlErrorKind::BadDefault, position)),
+            }
+        }
+    }
+}
+
+/// A note or rest, before it is timed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Item {
+    pitch: Option<Pitch>,
+    duration: u8,
+    dotted: bool,
+}
+
+impl Item {
+    /// How long it lasts at `bpm`, in microseconds.
+    fn micros(&self, bpm: u16) -> u64 {
+        let whole = 240_000_000 / bpm as u64;
+        let micros = whole / self.duration as u64;
+        if self.dotted {
+            micros * 3 / 2
+        } else {
+            micros
+        }
+    }
+}
+
+/// Reads the note at `position`, leaving it past the comma after it.
+/// Returns `None` at the end.
+fn read_item(
+    text: &[u8],
+    position: &mut usize,
+    defaults: &Defaults,
+) -> Result<Option<Item>, RtttlError> {
+    skip_spaces(text, position);
+    if *position >= text.len() {
+        return Ok(None);
+    }
+    let duration_at = *position;
+    let duration = match number(text, position) {
+        Some(value) => duration(value, duration_at)?,
+        None => defaults.duration,
+    };
+    skip_spaces(text, position);
+    let note_at = *position;
+    let bad_note = RtttlError::new(RtttlErrorKind::BadNote, note_at);
+    let semitone = match text.get(*position).map(u8::to_ascii_lowercase) {
+        Some(b'c') => Some(0),
+        Some(b'd') => Some(2),
+        Some(b'e') => Some(4),
+        Some(b'f') => Some(5),
+        Some(b'g') => Some(7),
+        Some(b'a') => Some(9),
+        Some(b'b' | b'h') => Some(11),
+        Some(b'p') => None,
+        _ => return Err(bad_note),
+    };
+    *position += 1;
+    let sharp = text.get(*position) == Some(&b'#');
+    if sharp {
+        *position += 1;
+    }
+    let mut dotted = text.get(*position) == Some(&b'.');
+    if dotted {
+        *position += 1;
+    }
+    let octave_at = *position;
+    let octave = match number(text, position) {
+        Some(value) => octave(value, octave_at)?,
+        None => defaults.octave,
+    };
+    if text.get(*position) == Some(&b'.') {
+        dotted = true;
+        *position += 1;
+    }
+    skip_spaces(text, position);
+    match text.get(*position) {
+        None => {}
+        Some(b',') => *position += 1,
+        Some(_) => return Err(RtttlError::new(RtttlErrorKind::BadNote, *position)),
+    }
+    let pitch = match semitone {
+        Some(semitone) => {
+            // B#8 is as high as it goes, well within range.
+            Pitch::from_midi((octave + 1) * 12 + semitone + sharp as u8)
+        }
+        None => None,
+    };
+    Ok(Some(Item {
+        pitch,
+        duration,
+        dotted,
+    }))
+}
+
+/// A parsed tune, borrowing its text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rtttl<'a> {
+    text: &'a [u8],
+    name: &'a str,
+    defaults: Defaults,
+    notes_at: usize,
+}
+
+impl<'a> Rtttl<'a> {
+    /// Checks the whole tune, so playing it can't fail.
+    pub fn parse(text: &'a str) -> Result<Rtttl<'a>, RtttlError> {
+        let bytes = text.as_bytes();
+        let missing = RtttlError::new(RtttlErrorKind::MissingSection, bytes.len());
+        let name_end = bytes.iter().position(|&byte| byte == b':').ok_or(missing)?;
+        let defaults_end = bytes[name_end + 1..]
+            .iter()
+            .position(|&byte| byte == b':')
+            .ok_or(missing)?
+            + name_end
+            + 1;
+        let defaults = Defaults::parse(bytes, name_end + 1, defaults_end)?;
+        let tune = Rtttl {
+            text: bytes,
+            name: text[..name_end].trim(),
+            defaults,
+            notes_at: defaults_end + 1,
+        };
+        let mut position = tune.notes_at;
+        while read_item(bytes, &mut position, &defaults)?.is_some() {}
+        Ok(tune)
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn defaults(&self) -> Defaults {
+        self.defaults
+    }
+
+    /// The notes and rests, timed in ticks at `tick_hertz`. Lengths are
+    /// rounded so they add up to the tune's true length rather than
+    /// drifting.
+    pub fn notes(&self, tick_hertz: u32) -> Notes<'a> {
+        Notes {
+            text: self.text,
+            position: self.notes_at,
+            defaults: self.defaults,
+            tick_hertz,
+            micros: 0,
+            ticks: 0,
+        }
+    }
+}
+
+/// A note or rest and how many ticks it lasts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RtttlNote {
+    /// `None` for a rest.
+    pub pitch: Option<Pitch>,
+    pub ticks: u32,
+}
+
+/// The notes of an [`Rtttl`] tune, from [`Rtttl::notes`].
+#[derive(Debug, Clone)]
+pub struct Notes<'a> {
+    text: &'a [u8],
+    position: usize,
+    defaults: Defaults,
+    tick_hertz: u32,
+    micros: u64,
+    ticks: u64,
+}
+
+impl Iterator for Notes<'_> {
+    type Item = RtttlNote;
+
+    fn next(&mut self) -> Option<RtttlNote> {
+        // Checked when the tune was parsed.
+        let item = read_item(self.text, &mut self.position, &self.defaults).ok()??;
+        self.micros += item.micros(self.defaults.bpm);
+        let end = (self.micros * self.tick_hertz as u64 + 500_000) / 1_000_000;
+        let ticks = end - self.ticks;
+        self.ticks = end;
+        Some(RtttlNote {
+            pitch: item.pitch,
+            ticks: ticks.min(u32::MAX as u64) as u32,
+        })
+    }
+}
+
+/// Plays an [`Rtttl`] tune on one channel, a tick at a time.
+///
+/// Each note is played with an [`Instrument`], a steady tone by default,
+/// scaled to a level. Its note-off comes a tick before the next note so
+/// repeated notes don't run together, unless it is only a tick long.
+#[derive(Debug, Clone)]
+pub struct RtttlPlayer<'a> {
+    tune: Rtttl<'a>,
+    notes: Notes<'a>,
+    tick_hertz: u32,
+    channel: Channel,
+    level: u8,
+    voice: InstrumentPlayer,
+    /// Ticks left of the current note.
+    left: u32,
+    finished: bool,
+}
+
+impl<'a> RtttlPlayer<'a> {
+    pub fn new(tune: Rtttl<'a>, channel: Channel, tick_hertz: u32) -> RtttlPlayer<'a> {
+        RtttlPlayer {
+            tune,
+            notes: tune.notes(tick_hertz),
+            tick_hertz,
+            channel,
+            level: 15,
+            voice: InstrumentPlayer::new(&Instrument::DEFAULT),
+            left: 0,
+            finished: false,
+        }
+    }
+
+    /// The loudest level notes play at, 0..=15; the instrument's levels are
+    /// scaled to it.
+    pub const fn with_level(mut self, level: u8) -> RtttlPlayer<'a> {
+        self.level = if level > 15 { 15 } else { level };
+        self
+    }
+
+    pub const fn with_instrument(mut self, instrument: &'static Instrument) -> RtttlPlayer<'a> {
+        self.voice = InstrumentPlayer::new(instrument);
+        self
+    }
+
+    pub fn tune(&self) -> &Rtttl<'a> {
+        &self.tune
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Goes back to the first note.
+    pub fn restart(&mut self) {
+        self.notes = self.tune.notes(self.tick_hertz);
+        self.voice.stop();
+        self.left = 0;
+        self.finished = false;
+    }
+
+    /// Plays the next tick. Returns `true` once the tune has finished, from
+    /// the tick that silences the channel.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        if self.finished {
+            return Ok(true);
+        }
+        while self.left == 0 {
+            let Some(note) = self.notes.next() else {
+                self.finished = true;
+                self.voice.stop();
+                psg.update_channel_level(self.channel, ChannelLevel::Fixed(0))?;
+                return Ok(true);
+            };
+            self.left = note.ticks;
+            match note.pitch {
+                Some(pitch) => self.voice.note_on(pitch),
+                None => self.voice.note_off(),
+            }
+        }
+        if self.left == 1 {
+            self.voice.note_off();
+        }
+        self.left -= 1;
+        match self.voice.next_frame() {
+            Some(mut frame) => {
+                frame.level = frame.level * self.level / 15;
+                apply_frame(psg, self.channel, &frame)?;
+            }
+            None => psg.update_channel_level(self.channel, ChannelLevel::Fixed(0))?,
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::{mangled, FakePsg};
+    use std::vec::Vec;
+
+    fn notes(text: &str, tick_hertz: u32) -> Vec<(Option<Pitch>, u32)> {
+        let tune = Rtttl::parse(text).unwrap();
+        tune.notes(tick_hertz)
+            .map(|note| (note.pitch, note.ticks))
+            .collect()
+    }
+
+    #[test]
+    fn reads_notes_durations_and_defaults() {
+        let tune = Rtttl::parse(" Beep :d=8,o=5,b=120:c,4e#,G.,2p,16a4.,h6,c#7").unwrap();
+        assert_eq!(tune.name(), "Beep");
+        assert_eq!(tune.defaults().bpm, 120);
+        // At 120 BPM a whole note is two seconds, 100 ticks at 50 Hz.
+        assert_eq!(
+            notes("x:d=8,o=5,b=120:c,4e#,G.,2p,16a4.,h6,c#7", 50),
+            [
+                (Some(Pitch::new(Note::C, 5)), 13),
+                (Some(Pitch::new(Note::F, 5)), 25),
+                (Some(Pitch::new(Note::G, 5)), 18),
+                (None, 50),
+                (Some(Pitch::new(Note::A, 4)), 10),
+                (Some(Pitch::new(Note::B, 6)), 12),
+                (Some(Pitch::new(Note::CSharp, 7)), 13),
+            ]
+        );
+        // Rounding doesn't drift: 32 32nds make a whole note.
+        let total: u32 = notes(
+            "x:d=32,b=120:c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c,c",
+            50,
+        )
+        .iter()
+        .map(|&(_, ticks)| ticks)
+        .sum();
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn takes_the_usual_quirks() {
+        // No defaults at all: d=4, o=6, b=63.
+        let bare = notes("Bare::a,p", 63);
+        assert_eq!(bare, [(Some(Pitch::new(Note::A, 6)), 60), (None, 60)]);
+        // Some defaults, upper case, spaces and a trailing comma.
+        assert_eq!(
+            notes("Loud: O=4 , B=63 :8P, 8A. , 8A.4,", 63),
+            [
+                (None, 30),
+                (Some(Pitch::new(Note::A, 4)), 45),
+                (Some(Pitch::new(Note::A, 4)), 45),
+            ]
+        );
+        assert_eq!(notes("Empty:d=4:", 50), []);
+        // 64ths and whole notes are durations too; 10ths are an error.
+        assert_eq!(
+            notes("x:b=60:64c,1c", 64),
+            [
+                (Some(Pitch::new(Note::C, 6)), 4),
+                (Some(Pitch::new(Note::C, 6)), 256)
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_byte() {
+        let error = |text| {
+            let RtttlError { kind, offset } = Rtttl::parse(text).unwrap_err();
+            (kind, offset)
+        };
+        use RtttlErrorKind::*;
+        assert_eq!(error("name"), (MissingSection, 4));
+        assert_eq!(error("name:d=4"), (MissingSection, 8));
+        assert_eq!(error("n:x=4:c"), (BadDefault, 2));
+        assert_eq!(error("n:d4:c"), (BadDefault, 2));
+        assert_eq!(error("n:d=4;o=5:c"), (BadDefault, 5));
+        assert_eq!(error("n:d=3:c"), (BadDuration, 4));
+        assert_eq!(error("n:o=9:c"), (BadOctave, 4));
+        assert_eq!(error("n:b=0:c"), (BadTempo, 4));
+        assert_eq!(error("n:b=901:c"), (BadTempo, 4));
+        assert_eq!(error("n::c,10e,g"), (BadDuration, 5));
+        assert_eq!(error("n::c, x"), (BadNote, 6));
+        assert_eq!(error("n::c,e9"), (BadOctave, 6));
+        assert_eq!(error("n::c e"), (BadNote, 5));
+        assert_eq!(error("n::c,,e"), (BadNote, 5));
+    }
+
+    #[test]
+    fn damaged_tunes_fail_cleanly() {
+        let tune = b" Beep :d=8,o=5,b=120:c,4e#,G.,2p,16a4.,h6,c#7";
+        assert!(Rtttl::parse(core::str::from_utf8(tune).unwrap()).is_ok());
+        for data in mangled(tune) {
+            let Ok(text) = core::str::from_utf8(&data) else {
+                continue;
+            };
+            let tune = match Rtttl::parse(text) {
+                Ok(tune) => tune,
+                Err(error) => {
+                    assert!(error.offset <= text.len());
+                    continue;
+                }
+            };
+            assert!(tune.notes(50).count() <= text.len());
+            let mut psg = FakePsg::new();
+            let mut player = RtttlPlayer::new(tune, Channel::A, 50);
+            for _ in 0..100 {
+                player.tick(&mut psg).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn plays_on_one_channel() {
+        static SWELL: Instrument = Instrument {
+            volume: crate::instrument::Table::new(&[5, 15]),
+            ..Instrument::DEFAULT
+        };
+        // Quarter notes at 150 BPM are 20 ticks at 50 Hz.
+        let tune = Rtttl::parse("x:d=4,o=4,b=150:a,a,p,c5").unwrap();
+        let mut player = RtttlPlayer::new(tune, Channel::B, 50)
+            .with_level(12)
+            .with_instrument(&SWELL);
+        let mut psg = FakePsg::new();
+        let mut levels = Vec::new();
+        let mut periods = Vec::new();
+        while !player.tick(&mut psg).unwrap() {
+            levels.push(psg.registers().value(0x9));
+            periods.push(psg.registers().tone_period(Channel::B));
+        }
+        assert_eq!(levels.len(), 80);
+        assert!(player.is_finished());
+        assert_eq!(psg.registers().value(0x9), 0);
+        assert_eq!(&levels[..3], [4, 12, 12]);
+        // A gap between the repeated notes, then the rest.
+        assert_eq!(&levels[18..22], [12, 0, 4, 12]);
+        assert!(levels[39..60].iter().all(|&level| level == 0));
+        assert_eq!(levels[60], 4);
+        assert_eq!(periods[0], 284);
+        assert!(periods[60] < periods[0]);
+
+        player.restart();
+        assert!(!player.tick(&mut psg).unwrap());
+        assert_eq!(psg.registers().value(0x9), 4);
+    }
+}
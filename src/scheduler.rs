@@ -0,0 +1,235 @@
+//! A fixed-capacity queue of "do this at tick T" events.
+
+use crate::psg::Psg;
+
+/// Names a scheduled event, to cancel it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Handle(u32);
+
+/// Returned with the event when the scheduler has no room for it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SchedulerFull<E>(pub E);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry<E> {
+    due: u32,
+    handle: Handle,
+    event: E,
+}
+
+/// Holds up to `N` events of a user type `E`, each due on a given tick, and
+/// hands them to a handler as their ticks come round.
+///
+/// Ticks are a wrapping `u32` count. Times are compared by their distance
+/// from the current tick rather than by value, so everything keeps working
+/// as the count wraps, as long as no event is scheduled more than 2^31 ticks
+/// ahead. The queue is kept sorted by due time, events due on the same tick
+/// firing in the order they were scheduled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheduler<E, const N: usize> {
+    entries: [Option<Entry<E>>; N],
+    len: usize,
+    now: u32,
+    next_handle: u32,
+}
+
+impl<E, const N: usize> Default for Scheduler<E, N> {
+    fn default() -> Scheduler<E, N> {
+        Scheduler::new()
+    }
+}
+
+impl<E, const N: usize> Scheduler<E, N> {
+    /// An empty scheduler at tick 0.
+    pub const fn new() -> Scheduler<E, N> {
+        Scheduler {
+            entries: [const { None }; N],
+            len: 0,
+            now: 0,
+            next_handle: 0,
+        }
+    }
+
+    /// Starts counting from `tick` rather than 0.
+    pub const fn with_start(mut self, tick: u32) -> Scheduler<E, N> {
+        self.now = tick;
+        self
+    }
+
+    /// The tick the next call to [`Scheduler::tick`] runs.
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Ticks from now until `tick`; anything already past counts as now.
+    fn until(&self, tick: u32) -> u32 {
+        (tick.wrapping_sub(self.now) as i32).max(0) as u32
+    }
+
+    /// Schedules `event` for `tick`, or for the next tick if `tick` has
+    /// already passed.
+    pub fn schedule_at(&mut self, tick: u32, event: E) -> Result<Handle, SchedulerFull<E>> {
+        self.schedule_in(self.until(tick), event)
+    }
+
+    /// Schedules `event` for `delta` ticks from now; 0 is the next tick run.
+    pub fn schedule_in(&mut self, delta: u32, event: E) -> Result<Handle, SchedulerFull<E>> {
+        if self.len == N {
+            return Err(SchedulerFull(event));
+        }
+        let due = self.now.wrapping_add(delta);
+        // After every event due at or before it.
+        let index = self.entries[..self.len]
+            .iter()
+            .position(|entry| {
+                entry
+                    .as_ref()
+                    .is_some_and(|entry| self.until(entry.due) > delta)
+            })
+            .unwrap_or(self.len);
+        let handle = Handle(self.next_handle);
+        self.next_handle = self.next_handle.wrapping_add(1);
+        self.entries[self.len] = Some(Entry { due, handle, event });
+        self.entries[index..=self.len].rotate_right(1);
+        self.len += 1;
+        Ok(handle)
+    }
+
+    fn remove(&mut self, index: usize) -> Option<E> {
+        self.entries[index..self.len].rotate_left(1);
+        self.len -= 1;
+        self.entries[self.len].take().map(|entry| entry.event)
+    }
+
+    /// Takes back an event that hasn't fired yet.
+    pub fn cancel(&mut self, handle: Handle) -> Option<E> {
+        let index = self.entries[..self.len]
+            .iter()
+            .position(|entry| entry.as_ref().is_some_and(|entry| entry.handle == handle))?;
+        self.remove(index)
+    }
+
+    pub fn is_pending(&self, handle: Handle) -> bool {
+        self.entries[..self.len]
+            .iter()
+            .flatten()
+            .any(|entry| entry.handle == handle)
+    }
+
+    /// Drops every pending event.
+    pub fn clear(&mut self) {
+        for entry in &mut self.entries[..self.len] {
+            *entry = None;
+        }
+        self.len = 0;
+    }
+
+    /// Passes every event due this tick to `handler`, in order, then moves
+    /// on a tick. If the handler fails, the events it hadn't reached stay due
+    /// and the tick is run again next time.
+    pub fn tick<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        mut handler: impl FnMut(&mut P, E) -> Result<(), P::Error>,
+    ) -> Result<(), P::Error> {
+        while self.entries[..self.len]
+            .first()
+            .and_then(Option::as_ref)
+            .is_some_and(|entry| entry.due == self.now)
+        {
+            if let Some(event) = self.remove(0) {
+                handler(psg, event)?;
+            }
+        }
+        self.now = self.now.wrapping_add(1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    fn run<const N: usize>(scheduler: &mut Scheduler<u8, N>, ticks: u32) -> Vec<(u32, u8)> {
+        let mut psg = FakePsg::new();
+        let mut fired = Vec::new();
+        for _ in 0..ticks {
+            let now = scheduler.now();
+            scheduler
+                .tick(&mut psg, |_, event| {
+                    fired.push((now, event));
+                    Ok(())
+                })
+                .unwrap();
+        }
+        fired
+    }
+
+    #[test]
+    fn fires_in_order_fifo_within_a_tick() {
+        let mut scheduler = Scheduler::<u8, 8>::new();
+        scheduler.schedule_at(5, 1).unwrap();
+        scheduler.schedule_in(2, 2).unwrap();
+        scheduler.schedule_at(5, 3).unwrap();
+        scheduler.schedule_in(0, 4).unwrap();
+        assert_eq!(run(&mut scheduler, 10), [(0, 4), (2, 2), (5, 1), (5, 3)]);
+        // A time in the past means straight away.
+        scheduler.schedule_at(3, 5).unwrap();
+        assert_eq!(run(&mut scheduler, 1), [(10, 5)]);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn cancel_and_overflow() {
+        let mut scheduler = Scheduler::<u8, 2>::new();
+        let first = scheduler.schedule_in(3, 1).unwrap();
+        scheduler.schedule_in(1, 2).unwrap();
+        assert_eq!(scheduler.schedule_in(2, 3), Err(SchedulerFull(3)));
+        assert_eq!(scheduler.cancel(first), Some(1));
+        assert!(!scheduler.is_pending(first));
+        assert_eq!(scheduler.cancel(first), None);
+        scheduler.schedule_in(2, 3).unwrap();
+        assert_eq!(run(&mut scheduler, 5), [(1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn handler_errors_leave_the_rest_due() {
+        let mut psg = FakePsg::new();
+        let mut scheduler = Scheduler::<u8, 4>::new();
+        scheduler.schedule_in(0, 1).unwrap();
+        scheduler.schedule_in(0, 2).unwrap();
+        assert_eq!(scheduler.tick(&mut psg, |_, _| Err(())), Err(()));
+        assert_eq!(scheduler.now(), 0);
+        assert_eq!(run(&mut scheduler, 1), [(0, 2)]);
+    }
+
+    #[test]
+    fn survives_the_tick_count_wrapping() {
+        let start = u32::MAX - 3;
+        let mut scheduler = Scheduler::<u8, 8>::new().with_start(start);
+        // Due after the wrap, scheduled both ways, and one just before it.
+        scheduler.schedule_at(2, 1).unwrap();
+        scheduler.schedule_in(10, 2).unwrap();
+        scheduler.schedule_at(u32::MAX, 3).unwrap();
+        scheduler.schedule_at(0, 4).unwrap();
+        assert_eq!(
+            run(&mut scheduler, 12),
+            [(u32::MAX, 3), (0, 4), (2, 1), (6, 2)]
+        );
+        // Ticks just before the wrap are in the past, not 4 billion ahead.
+        scheduler.schedule_at(u32::MAX - 1, 5).unwrap();
+        assert_eq!(run(&mut scheduler, 1), [(8, 5)]);
+    }
+}
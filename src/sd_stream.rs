@@ -0,0 +1,624 @@
+//! Songs streamed from a file on an SD card, read ahead of playback.
+//!
+//! A card read can take milliseconds, far too long to wait for in a 50 Hz
+//! tick. [`ReadAhead`] keeps a buffer of the bytes coming next, topped up by
+//! [`ReadAhead::fill`] from the main loop, so the tick only ever copies out
+//! of memory. Running dry is an underrun: it is counted, and a
+//! [`FrameFile`] answers [`FrameStatus::NotReady`] rather than wait.
+//!
+//! Nothing here knows about formats. [`ReadAhead`] is a [`ByteSource`], so a
+//! [`PsgStream`](crate::psg_file::PsgStream) reads `.psg` files through it as
+//! they are, and [`ReadAhead::frames`] plays the 16-byte frames laid out one
+//! after another at any offset, such as those of a YM file that isn't
+//! interleaved, once [`YmMetadata`](crate::ym_file::YmMetadata) has read its
+//! header. Interleaved YM files spread each frame across the whole file, so
+//! can't be streamed this way.
+//!
+//! Files are anything [`SeekableFile`], `embedded-sdmmc`'s `File` among
+//! them:
+//!
+//! ```
+//! use embedded_sdmmc::{BlockDevice, Directory, Error, File, Mode, TimeSource};
+//! use ym2149::sd_stream::ReadAhead;
+//!
+//! fn song<'a, D: BlockDevice, T: TimeSource>(
+//!     root: &Directory<'a, D, T, 4, 4, 1>,
+//! ) -> Result<ReadAhead<File<'a, D, T, 4, 4, 1>, 1024>, Error<D::Error>> {
+//!     Ok(ReadAhead::new(root.open_file_in_dir("SONG.YM", Mode::ReadOnly)?))
+//! }
+//! ```
+
+use crate::frame_player::{Frame, FrameSource, FrameStatus, SourceError};
+use crate::psg_file::ByteSource;
+
+/// A file read in order that can also jump to any offset.
+pub trait SeekableFile {
+    /// Reads the next bytes into `buffer`, giving how many there were: 0 at
+    /// the end of the file.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, SourceError>;
+
+    /// Makes `offset` bytes from the start the next to be read.
+    fn seek_from_start(&mut self, offset: u32) -> Result<(), SourceError>;
+}
+
+/// A file open on a FAT volume. Seeking past its end is
+/// [`SourceError::Corrupt`], as a header promising more than the file holds.
+impl<D, T, const DIRS: usize, const FILES: usize, const VOLUMES: usize> SeekableFile
+    for embedded_sdmmc::File<'_, D, T, DIRS, FILES, VOLUMES>
+where
+    D: embedded_sdmmc::BlockDevice,
+    T: embedded_sdmmc::TimeSource,
+{
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, SourceError> {
+        embedded_sdmmc::File::read(self, buffer).map_err(|_| SourceError::Read)
+    }
+
+    fn seek_from_start(&mut self, offset: u32) -> Result<(), SourceError> {
+        embedded_sdmmc::File::seek_from_start(self, offset).map_err(|error| match error {
+            embedded_sdmmc::Error::InvalidOffset => SourceError::Corrupt,
+            _ => SourceError::Read,
+        })
+    }
+}
+
+/// A file with the next `N` bytes of it kept in memory.
+///
+/// `N` is best a multiple of the card's 512-byte sectors, and big enough to
+/// cover the longest the main loop can go without calling
+/// [`ReadAhead::fill`]: a second of 50 Hz frames is 800 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadAhead<F, const N: usize> {
+    file: F,
+    buffer: [u8; N],
+    /// Where the next byte handed out is in `buffer`.
+    head: usize,
+    buffered: usize,
+    /// Bytes handed out that are still in `buffer`, just before `head`.
+    behind: usize,
+    /// The offset in the file of the next byte handed out.
+    position: u32,
+    /// Whether the file has been read to its end.
+    at_end: bool,
+    underruns: u32,
+}
+
+impl<F: SeekableFile, const N: usize> ReadAhead<F, N> {
+    /// Reads ahead in `file` from where it stands, taken as its start.
+    pub const fn new(file: F) -> ReadAhead<F, N> {
+        ReadAhead {
+            file,
+            buffer: [0; N],
+            head: 0,
+            buffered: 0,
+            behind: 0,
+            position: 0,
+            at_end: false,
+            underruns: 0,
+        }
+    }
+
+    /// Reads from the file until the buffer is full or the file over, giving
+    /// how many bytes that was. Call this from the main loop, away from the
+    /// tick.
+    pub fn fill(&mut self) -> Result<usize, SourceError> {
+        let mut read = 0;
+        while !self.at_end && self.buffered < N {
+            let tail = (self.head + self.buffered) % N;
+            let end = if tail < self.head { self.head } else { N };
+            let count = self.file.read(&mut self.buffer[tail..end])?;
+            if count == 0 {
+                self.at_end = true;
+            }
+            self.buffered += count;
+            self.behind = self.behind.min(N - self.buffered);
+            read += count;
+        }
+        Ok(read)
+    }
+
+    /// Bytes read ahead and not yet handed out.
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    /// Whether every byte of the file has been handed out.
+    pub fn is_finished(&self) -> bool {
+        self.at_end && self.buffered == 0
+    }
+
+    /// The offset in the file of the next byte handed out.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// How many times bytes were wanted before [`ReadAhead::fill`] had read
+    /// them.
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+
+    pub fn file(&self) -> &F {
+        &self.file
+    }
+
+    pub fn into_file(self) -> F {
+        self.file
+    }
+
+    /// Makes `offset` the next byte handed out. Within what is buffered, or
+    /// handed out and not yet overwritten, nothing is read again; anywhere
+    /// else the buffer starts over from there.
+    pub fn seek(&mut self, offset: u32) -> Result<(), SourceError> {
+        if let Some(skip) = offset.checked_sub(self.position) {
+            if skip as usize <= self.buffered {
+                self.consume(skip as usize);
+                return Ok(());
+            }
+        } else if ((self.position - offset) as usize) <= self.behind {
+            let back = (self.position - offset) as usize;
+            self.head = (self.head + N - back) % N;
+            self.buffered += back;
+            self.behind -= back;
+            self.position = offset;
+            return Ok(());
+        }
+        self.file.seek_from_start(offset)?;
+        self.head = 0;
+        self.buffered = 0;
+        self.behind = 0;
+        self.position = offset;
+        self.at_end = false;
+        Ok(())
+    }
+
+    /// Fills `out` from the buffer, reading the file when the buffer runs
+    /// short, which counts as an underrun. Gives how many bytes there were,
+    /// fewer than asked for only at the end of the file.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, SourceError> {
+        if self.buffered < out.len() && !self.at_end {
+            self.underruns += 1;
+        }
+        let mut done = 0;
+        while done < out.len() {
+            if self.buffered == 0 {
+                self.fill()?;
+                if self.buffered == 0 {
+                    break;
+                }
+            }
+            done += self.take(&mut out[done..]);
+        }
+        Ok(done)
+    }
+
+    /// Plays the file's 16-byte frames from `start`, `count` of them if
+    /// that's known, otherwise up to the end of the file.
+    pub fn frames(self, start: u32, count: Option<u32>) -> FrameFile<F, N> {
+        FrameFile {
+            bytes: self,
+            start,
+            count,
+            next: None,
+        }
+    }
+
+    /// Copies out what is buffered, as much as fits, giving how much that
+    /// was.
+    fn take(&mut self, out: &mut [u8]) -> usize {
+        let count = out.len().min(self.buffered).min(N - self.head);
+        out[..count].copy_from_slice(&self.buffer[self.head..self.head + count]);
+        self.consume(count);
+        count
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.head = (self.head + count) % N.max(1);
+        self.buffered -= count;
+        self.behind += count;
+        self.position += count as u32;
+    }
+}
+
+impl<F: SeekableFile, const N: usize> ByteSource for ReadAhead<F, N> {
+    fn next_byte(&mut self) -> Result<Option<u8>, SourceError> {
+        let mut byte = [0];
+        Ok((self.read(&mut byte)? == 1).then_some(byte[0]))
+    }
+
+    fn rewind(&mut self) -> Result<(), SourceError> {
+        self.seek(0)
+    }
+}
+
+/// Frames streamed from a file, from [`ReadAhead::frames`].
+///
+/// Going back to a loop start costs nothing while the loop's frames are
+/// still in the buffer. Further back, the buffer starts over, and the loop
+/// start is an underrun until the next [`ReadAhead::fill`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameFile<F, const N: usize> {
+    bytes: ReadAhead<F, N>,
+    start: u32,
+    count: Option<u32>,
+    /// The frame the buffer is at, if it's at one.
+    next: Option<u32>,
+}
+
+impl<F: SeekableFile, const N: usize> FrameFile<F, N> {
+    /// The buffer the frames come through, to [`ReadAhead::fill`] from the
+    /// main loop.
+    pub fn bytes(&self) -> &ReadAhead<F, N> {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut ReadAhead<F, N> {
+        &mut self.bytes
+    }
+
+    /// Tops up the buffer, as [`ReadAhead::fill`].
+    pub fn fill(&mut self) -> Result<usize, SourceError> {
+        self.bytes.fill()
+    }
+
+    pub fn into_bytes(self) -> ReadAhead<F, N> {
+        self.bytes
+    }
+}
+
+impl<F: SeekableFile, const N: usize> FrameSource for FrameFile<F, N> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        if self.count.is_some_and(|count| index >= count) {
+            return Ok(FrameStatus::End);
+        }
+        if self.next != Some(index) {
+            let offset = (index as u64 * 16 + self.start as u64).try_into();
+            self.bytes.seek(offset.map_err(|_| SourceError::Corrupt)?)?;
+            self.next = Some(index);
+        }
+        if self.bytes.buffered() < frame.len() {
+            if self.bytes.at_end {
+                // A partial frame at the end is ignored.
+                return Ok(FrameStatus::End);
+            }
+            self.bytes.underruns += 1;
+            return Ok(FrameStatus::NotReady);
+        }
+        let mut read = 0;
+        while read < frame.len() {
+            read += self.bytes.take(&mut frame[read..]);
+        }
+        self.next = Some(index + 1);
+        Ok(FrameStatus::Ready)
+    }
+
+    fn frame_count(&self) -> Option<u32> {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::frame_player::{FramePlayer, PlayStatus};
+    use crate::psg::Psg;
+    use crate::psg_file::PsgStream;
+    use crate::test_support::{ym_file, FakePsg};
+    use crate::ym_file::YmMetadata;
+    use std::vec::Vec;
+
+    const SECTOR: usize = 512;
+
+    /// A file on a card of `data`, read a sector at a time as a card is,
+    /// counting the sectors read.
+    struct FakeCard {
+        data: Vec<u8>,
+        offset: usize,
+        sectors_read: u32,
+        fail: bool,
+    }
+
+    impl FakeCard {
+        fn new(data: Vec<u8>) -> FakeCard {
+            FakeCard {
+                data,
+                offset: 0,
+                sectors_read: 0,
+                fail: false,
+            }
+        }
+    }
+
+    impl SeekableFile for FakeCard {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, SourceError> {
+            if self.fail {
+                return Err(SourceError::Read);
+            }
+            let sector_end = (self.offset / SECTOR + 1) * SECTOR;
+            let end = sector_end
+                .min(self.data.len())
+                .min(self.offset + buffer.len());
+            let Some(bytes) = self.data.get(self.offset..end) else {
+                return Ok(0);
+            };
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            self.offset = end;
+            self.sectors_read += !bytes.is_empty() as u32;
+            Ok(bytes.len())
+        }
+
+        fn seek_from_start(&mut self, offset: u32) -> Result<(), SourceError> {
+            self.offset = offset as usize;
+            Ok(())
+        }
+    }
+
+    /// `count` frames after a `header`-byte header, frame `n` with `n` in
+    /// R0 and R1 and its low bits in R8.
+    fn song(header: usize, count: u32) -> Vec<u8> {
+        let mut data = std::vec![0xAA; header];
+        for n in 0..count {
+            let mut frame = [0; 16];
+            frame[0] = n as u8;
+            frame[1] = (n >> 8) as u8;
+            frame[8] = n as u8 & 0x0F;
+            data.extend_from_slice(&frame);
+        }
+        data
+    }
+
+    #[test]
+    fn bytes_come_in_order_across_the_wrap() {
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut bytes = ReadAhead::<_, 96>::new(FakeCard::new(data.clone()));
+        assert_eq!(bytes.fill(), Ok(96));
+        let mut out = [0; 40];
+        let mut seen = Vec::new();
+        while !bytes.is_finished() {
+            bytes.fill().unwrap();
+            let read = bytes.read(&mut out).unwrap();
+            seen.extend_from_slice(&out[..read]);
+        }
+        assert_eq!(seen, data);
+        assert_eq!(bytes.underruns(), 0);
+        assert_eq!(bytes.position(), 1000);
+        assert_eq!(bytes.read(&mut out), Ok(0));
+
+        // Without a fill, reading waits on the card, and says so.
+        bytes.seek(10).unwrap();
+        assert_eq!(bytes.read(&mut out), Ok(40));
+        assert_eq!(out[0], 10);
+        assert_eq!(bytes.underruns(), 1);
+    }
+
+    #[test]
+    fn seeks_within_the_buffer_read_nothing_again() {
+        let mut bytes = ReadAhead::<_, 1024>::new(FakeCard::new(song(0, 100)));
+        bytes.fill().unwrap();
+        assert_eq!(bytes.file().sectors_read, 2);
+        bytes.seek(600).unwrap();
+        assert_eq!((bytes.buffered(), bytes.file().sectors_read), (424, 2));
+        // Back over bytes handed out, but not over those filled over since.
+        bytes.seek(16).unwrap();
+        assert_eq!(bytes.buffered(), 1008);
+        bytes.seek(600).unwrap();
+        bytes.fill().unwrap();
+        assert_eq!(bytes.file().sectors_read, 4);
+        bytes.seek(590).unwrap();
+        assert_eq!(bytes.buffered(), 1010);
+        bytes.seek(16).unwrap();
+        assert_eq!(bytes.buffered(), 0);
+        let mut out = [0; 16];
+        bytes.read(&mut out).unwrap();
+        assert_eq!(out[0], 1);
+    }
+
+    #[test]
+    fn frames_play_through_a_player_and_loop() {
+        let frames = ReadAhead::<_, 512>::new(FakeCard::new(song(40, 40))).frames(40, Some(40));
+        let mut player = FramePlayer::new(frames).with_loop(2);
+        let mut psg = FakePsg::new();
+        player.play();
+        // Nothing read yet: the first tick underruns rather than waits.
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Underrun));
+        assert_eq!(player.source().bytes().file().sectors_read, 0);
+        let mut played = Vec::new();
+        let mut underruns = 1;
+        while played.len() < 80 {
+            player.source_mut().fill().unwrap();
+            match player.tick(&mut psg).unwrap() {
+                PlayStatus::Underrun => underruns += 1,
+                _ => played.push(psg.registers().value(0x0)),
+            }
+        }
+        let mut expected: Vec<u8> = (0..40).collect();
+        expected.extend(2..40);
+        expected.extend(2..4);
+        assert_eq!(played, expected);
+        // Going back further than the buffer holds starts it over, once a
+        // loop.
+        assert_eq!(underruns, 3);
+        assert_eq!(player.source().bytes().underruns(), underruns);
+    }
+
+    #[test]
+    fn frames_end_with_the_file_and_report_errors() {
+        // One frame and a half, with no count given.
+        let mut data = song(0, 1);
+        data.extend_from_slice(&[0; 8]);
+        let mut frames = ReadAhead::<_, 64>::new(FakeCard::new(data)).frames(0, None);
+        frames.fill().unwrap();
+        let mut frame = [0; 16];
+        assert_eq!(frames.frame(0, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(frames.frame(1, &mut frame), Ok(FrameStatus::End));
+        assert_eq!(frames.frame_count(), None);
+
+        let mut card = FakeCard::new(song(0, 4));
+        card.fail = true;
+        let mut frames = ReadAhead::<_, 64>::new(card).frames(0, Some(4));
+        assert_eq!(frames.fill(), Err(SourceError::Read));
+        assert_eq!(frames.frame(0, &mut frame), Ok(FrameStatus::NotReady));
+        assert_eq!(frames.frame(4, &mut frame), Ok(FrameStatus::End));
+    }
+
+    #[test]
+    fn psg_files_stream_through_as_bytes() {
+        let data = Vec::from(&b"PSG\x1A\x0A\x32\0\0\0\0\0\0\0\0\0\0\x00\x10\xFF\x00\x20\xFF"[..]);
+        let mut stream = PsgStream::new(ReadAhead::<_, 8>::new(FakeCard::new(data))).unwrap();
+        let mut frame = [0; 16];
+        assert_eq!(stream.frame(1, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(frame[0], 0x20);
+        assert_eq!(stream.frame(0, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(frame[0], 0x10);
+        assert_eq!(stream.frame(2, &mut frame), Ok(FrameStatus::End));
+    }
+
+    #[test]
+    fn ym_songs_play_from_the_header_on() {
+        let frames: Vec<Frame> = (0..50).map(|n| [n as u8; 16]).collect();
+        let mut bytes =
+            ReadAhead::<_, 512>::new(FakeCard::new(ym_file(b"YM5!", &[b"drum"], &frames)));
+        bytes.fill().unwrap();
+        let mut header = [0; 64];
+        let read = bytes.read(&mut header).unwrap();
+        let metadata = YmMetadata::parse(&header[..read]).unwrap();
+        assert!(!metadata.is_interleaved());
+        let count = metadata.frame_count();
+        let mut frames = bytes.frames(metadata.frames_at() as u32, Some(count));
+        let mut frame = [0; 16];
+        assert_eq!(frames.frame(0, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(frame, [0; 16]);
+        assert_eq!(frames.frame(1, &mut frame), Ok(FrameStatus::Ready));
+        assert_eq!(frame, [1; 16]);
+        // The header's bytes were still buffered, so nothing waited.
+        assert_eq!(frames.bytes().underruns(), 0);
+        assert_eq!(frames.frame(count, &mut frame), Ok(FrameStatus::End));
+    }
+
+    /// A card holding a FAT16 volume with `data` as its one file,
+    /// `SONG.BIN`: the boot sector, one FAT, a root directory of a block and
+    /// clusters of a block, only the blocks up to the file's end stored.
+    struct FatCard(Vec<u8>);
+
+    /// Clusters enough that the volume isn't FAT12.
+    const CLUSTERS: u32 = 4100;
+    const FAT_BLOCKS: u32 = (CLUSTERS + 2).div_ceil(256);
+
+    impl FatCard {
+        fn new(data: &[u8]) -> FatCard {
+            let volume_blocks = 1 + FAT_BLOCKS + 1 + CLUSTERS;
+            let mut image = std::vec![0; SECTOR];
+            // The partition table, one FAT16 partition from block 1.
+            image[446 + 4] = 0x06;
+            image[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+            image[446 + 12..446 + 16].copy_from_slice(&volume_blocks.to_le_bytes());
+            image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+            let mut boot = [0; SECTOR];
+            boot[11..13].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+            boot[13] = 1;
+            boot[14..16].copy_from_slice(&1u16.to_le_bytes());
+            boot[16] = 1;
+            boot[17..19].copy_from_slice(&16u16.to_le_bytes());
+            boot[19..21].copy_from_slice(&(volume_blocks as u16).to_le_bytes());
+            boot[21] = 0xF8;
+            boot[22..24].copy_from_slice(&(FAT_BLOCKS as u16).to_le_bytes());
+            boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+            image.extend_from_slice(&boot);
+
+            // Clusters from 2 on, one after another.
+            let clusters = data.len().div_ceil(SECTOR) as u16;
+            let mut fat = std::vec![0; FAT_BLOCKS as usize * SECTOR];
+            fat[..4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]);
+            for cluster in 2..clusters + 2 {
+                let next = if cluster == clusters + 1 {
+                    0xFFFF
+                } else {
+                    cluster + 1
+                };
+                let at = cluster as usize * 2;
+                fat[at..at + 2].copy_from_slice(&next.to_le_bytes());
+            }
+            image.extend_from_slice(&fat);
+
+            let mut root = [0; SECTOR];
+            root[..11].copy_from_slice(b"SONG    BIN");
+            root[11] = 0x20;
+            root[26..28].copy_from_slice(&2u16.to_le_bytes());
+            root[28..32].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            image.extend_from_slice(&root);
+
+            image.extend_from_slice(data);
+            image.resize(image.len().div_ceil(SECTOR) * SECTOR, 0);
+            FatCard(image)
+        }
+    }
+
+    impl embedded_sdmmc::BlockDevice for FatCard {
+        type Error = core::convert::Infallible;
+
+        fn read(
+            &self,
+            blocks: &mut [embedded_sdmmc::Block],
+            start: embedded_sdmmc::BlockIdx,
+        ) -> Result<(), Self::Error> {
+            for (index, block) in blocks.iter_mut().enumerate() {
+                let at = (start.0 as usize + index) * SECTOR;
+                let stored = self.0.get(at..at + SECTOR).unwrap_or(&[0; SECTOR]);
+                block.contents.copy_from_slice(stored);
+            }
+            Ok(())
+        }
+
+        fn write(
+            &self,
+            _: &[embedded_sdmmc::Block],
+            _: embedded_sdmmc::BlockIdx,
+        ) -> Result<(), Self::Error> {
+            unreachable!("the song is only read")
+        }
+
+        fn num_blocks(&self) -> Result<embedded_sdmmc::BlockCount, Self::Error> {
+            Ok(embedded_sdmmc::BlockCount(
+                1 + 1 + FAT_BLOCKS + 1 + CLUSTERS,
+            ))
+        }
+    }
+
+    struct Midnight;
+
+    impl embedded_sdmmc::TimeSource for Midnight {
+        fn get_timestamp(&self) -> embedded_sdmmc::Timestamp {
+            embedded_sdmmc::Timestamp::from_fat(0, 0)
+        }
+    }
+
+    #[test]
+    fn frames_play_from_a_file_on_a_fat_card() {
+        let card = embedded_sdmmc::VolumeManager::<_, _, 4, 4, 1>::new(
+            FatCard::new(&song(40, 100)),
+            Midnight,
+        );
+        let volume = card.open_volume(embedded_sdmmc::VolumeIdx(0)).unwrap();
+        let root = volume.open_root_dir().unwrap();
+        let file = root
+            .open_file_in_dir("SONG.BIN", embedded_sdmmc::Mode::ReadOnly)
+            .unwrap();
+        assert_eq!(file.length(), 40 + 1600);
+
+        let mut frames = ReadAhead::<_, 1024>::new(file).frames(40, Some(100));
+        let mut frame = [0; 16];
+        for index in [0, 1, 2, 70, 99, 3] {
+            while frames.frame(index, &mut frame) == Ok(FrameStatus::NotReady) {
+                frames.fill().unwrap();
+            }
+            assert_eq!(frame[..2], (index as u16).to_le_bytes(), "frame {index}");
+        }
+        assert_eq!(frames.frame(100, &mut frame), Ok(FrameStatus::End));
+
+        // A count the file falls short of.
+        let mut frames = frames.into_bytes().frames(40, Some(200));
+        assert_eq!(frames.frame(150, &mut frame), Err(SourceError::Corrupt));
+    }
+}
@@ -0,0 +1,1842 @@
+//! A tracker-style pattern sequencer.
+//!
+//! A [`Pattern`] is a list of [`Row`]s, each holding a [`Cell`] for channels
+//! A, B and C. A cell can start a note, release one, pick an instrument from
+//! the bank, set the channel's volume and carry a [`Command`]. A [`Song`]
+//! lists the patterns to play, in an order that can repeat and transpose
+//! them, and can loop. The [`Sequencer`] plays it a row every `speed` ticks,
+//! driving an [`InstrumentPlayer`] per channel. Cells, rows, patterns and
+//! songs are const data, so a whole song can sit in flash:
+//!
+//! ```
+//! use ym2149::instrument::{presets, Instrument};
+//! use ym2149::pitch::{Note, Pitch};
+//! use ym2149::sequencer::{Cell, Command, OrderEntry, Pattern, Row, Sequencer, Song};
+//!
+//! const C4: Pitch = Pitch::new(Note::C, 4);
+//! const G4: Pitch = Pitch::new(Note::G, 4);
+//!
+//! static BANK: [Instrument; 2] = [presets::LEAD, presets::BASS];
+//! static INTRO: Pattern = Pattern::new(&[
+//!     Row::new(
+//!         Cell::note(C4).instrument(0).command(Command::Speed(3)),
+//!         Cell::note(C4).instrument(1).volume(12),
+//!         Cell::EMPTY,
+//!     ),
+//!     Row::EMPTY,
+//!     Row::new(Cell::note(G4), Cell::OFF, Cell::EMPTY),
+//!     Row::EMPTY,
+//! ]);
+//! static PATTERNS: [Pattern; 1] = [INTRO];
+//! // Twice as written, then a fifth up, then round again from the second.
+//! static SONG: Song = Song::new(
+//!     &PATTERNS,
+//!     &[
+//!         OrderEntry::new(0),
+//!         OrderEntry::new(0),
+//!         OrderEntry::new(0).transposed(7),
+//!     ],
+//! )
+//! .looping(1);
+//!
+//! let sequencer = Sequencer::new(&SONG, &BANK, 50);
+//! ```
+
+use core::borrow::Borrow;
+
+use crate::instrument::{apply_frame, Instrument, InstrumentPlayer};
+use crate::loop_policy::{Fader, LoopAction, LoopPolicy};
+use crate::mute::{Mute, MutedPsg};
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+#[cfg(feature = "alloc")]
+use crate::storage::Owned;
+use crate::storage::{Static, Storage};
+use crate::tempo_sync::TempoSync;
+use crate::tuning::{self, MAX_TONE_PERIOD};
+use crate::volume;
+use crate::{Channel, ChannelLevel};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Channels a row has a cell for.
+pub const CHANNELS: usize = 3;
+
+/// Ticks a row lasts unless a pattern says otherwise.
+pub const DEFAULT_SPEED: u8 = 6;
+
+/// Ticks a second unless a pattern says otherwise: the 50 Hz of a PAL
+/// frame, as most trackers assume.
+pub const DEFAULT_TEMPO: u16 = 50;
+
+/// What a cell's command column asks for, from the start of its row.
+///
+/// The rest are ProTracker's effects, in its order, and behave as they do
+/// there: most act on every tick of the row but the first, and those that
+/// remember their parameter take a 0 to mean the last one given. Slides
+/// move the tone period, as ProTracker's move the Amiga's, rather than
+/// working in cents like the `portamento` and `vibrato` modules, so
+/// ported songs keep their speeds; a lower period is a higher note.
+/// [`Command::from_code`] reads them as a tracker writes them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Ticks a row, from this row on. 0 is taken as 1.
+    Speed(u8),
+    /// Ticks a second, from this row on. 0 is taken as 1.
+    Tempo(u16),
+    /// `0xy`: the note, then `x` semitones up, then `y` up, a tick each,
+    /// counting from the first tick of the row.
+    Arpeggio(u8, u8),
+    /// `1xx`: takes this much off the period each tick.
+    SlideUp(u8),
+    /// `2xx`: adds this much to the period each tick.
+    SlideDown(u8),
+    /// `3xx`: slides this much a tick towards the cell's note, which starts
+    /// no note of its own, stopping there. Remembered.
+    TonePortamento(u8),
+    /// `4xy`: wobbles the period at speed `x` by depth `y` on ProTracker's
+    /// sine, which starts again from the middle with each new note. Each
+    /// half is remembered on its own.
+    Vibrato { speed: u8, depth: u8 },
+    /// `Axy`: raises the volume by `x` each tick, or if `x` is 0 lowers it
+    /// by `y`, staying within 0 to 15.
+    VolumeSlide { up: u8, down: u8 },
+    /// `Bxx`: goes on after this row from order entry `xx`.
+    PositionJump(u8),
+    /// `Dxx`: goes on after this row from row `xx` of the next order entry,
+    /// or of the entry a [`Command::PositionJump`] on the same row names.
+    PatternBreak(u8),
+}
+
+impl Command {
+    /// The command for a tracker's effect `code`, 0 to 0xF, with its
+    /// parameter byte, or `None` for one not supported here. `Fxx` sets the
+    /// speed below 0x20 and the tempo in beats a minute from there, turned
+    /// into ticks a second as ProTracker does, 125 being 50, and rounded;
+    /// `F00`, which stops ProTracker, isn't supported. `Dxx` takes its row in
+    /// decimal, so `D10` is row 10, as ProTracker does.
+    pub const fn from_code(code: u8, parameter: u8) -> Option<Command> {
+        let (x, y) = (parameter >> 4, parameter & 0xF);
+        Some(match code {
+            0x0 => Command::Arpeggio(x, y),
+            0x1 => Command::SlideUp(parameter),
+            0x2 => Command::SlideDown(parameter),
+            0x3 => Command::TonePortamento(parameter),
+            0x4 => Command::Vibrato { speed: x, depth: y },
+            0xA => Command::VolumeSlide { up: x, down: y },
+            0xB => Command::PositionJump(parameter),
+            0xD => Command::PatternBreak(x * 10 + y),
+            0xF if parameter == 0 => return None,
+            0xF if parameter < 0x20 => Command::Speed(parameter),
+            0xF => Command::Tempo((parameter as u16 * 2 + 2) / 5),
+            _ => return None,
+        })
+    }
+}
+
+/// One channel's part of a row. Anything left out leaves the channel as it
+/// was: the note sounding goes on, with the same instrument and volume.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cell {
+    /// A note to start.
+    pub pitch: Option<Pitch>,
+    /// Releases the note sounding, if there is no new one.
+    pub off: bool,
+    /// An index into the instrument bank, for this note and those after it.
+    /// An index past the end of the bank is ignored.
+    pub instrument: Option<u8>,
+    /// The channel's volume, 0 to 15, scaling the instrument's levels.
+    pub volume: Option<u8>,
+    pub command: Option<Command>,
+}
+
+impl Cell {
+    /// A cell that changes nothing.
+    pub const EMPTY: Cell = Cell {
+        pitch: None,
+        off: false,
+        instrument: None,
+        volume: None,
+        command: None,
+    };
+
+    /// A cell releasing the channel's note.
+    pub const OFF: Cell = Cell {
+        off: true,
+        ..Cell::EMPTY
+    };
+
+    /// A cell starting `pitch`.
+    pub const fn note(pitch: Pitch) -> Cell {
+        Cell {
+            pitch: Some(pitch),
+            ..Cell::EMPTY
+        }
+    }
+
+    pub const fn instrument(mut self, index: u8) -> Cell {
+        self.instrument = Some(index);
+        self
+    }
+
+    /// Sets the volume, clamped to 15.
+    pub const fn volume(mut self, volume: u8) -> Cell {
+        self.volume = Some(if volume > 15 { 15 } else { volume });
+        self
+    }
+
+    pub const fn command(mut self, command: Command) -> Cell {
+        self.command = Some(command);
+        self
+    }
+}
+
+/// A cell for each of channels A, B and C.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub cells: [Cell; CHANNELS],
+}
+
+impl Row {
+    /// A row that changes nothing.
+    pub const EMPTY: Row = Row {
+        cells: [Cell::EMPTY; CHANNELS],
+    };
+
+    pub const fn new(a: Cell, b: Cell, c: Cell) -> Row {
+        Row { cells: [a, b, c] }
+    }
+
+    /// The cell for `channel`.
+    pub const fn cell(&self, channel: Channel) -> &Cell {
+        &self.cells[channel.index()]
+    }
+}
+
+/// Rows played one after another.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pattern<S: Storage = Static> {
+    pub rows: S::Slice<Row>,
+}
+
+impl Pattern {
+    pub const fn new(rows: &'static [Row]) -> Pattern {
+        Pattern { rows }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Pattern<Owned> {
+    pub fn from_vec(rows: Vec<Row>) -> Pattern<Owned> {
+        Pattern { rows }
+    }
+
+    /// The pattern with its rows moved out of the heap for good.
+    pub fn leak(self) -> Pattern {
+        Pattern {
+            rows: self.rows.leak(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<Pattern> for Pattern<Owned> {
+    fn from(pattern: Pattern) -> Pattern<Owned> {
+        Pattern {
+            rows: pattern.rows.to_vec(),
+        }
+    }
+}
+
+impl<S: Storage> Pattern<S> {
+    pub fn rows(&self) -> &[Row] {
+        self.rows.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows().is_empty()
+    }
+}
+
+/// A place in a [`Song`]'s order list: a pattern, and how far to transpose
+/// its notes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OrderEntry {
+    /// An index into the song's patterns. An entry for a pattern that isn't
+    /// there plays as an empty one.
+    pub pattern: u8,
+    /// Semitones to move every note by, folded back by octaves into range
+    /// as [`Pitch::transpose_folded`] does.
+    pub transpose: i8,
+}
+
+impl OrderEntry {
+    pub const fn new(pattern: u8) -> OrderEntry {
+        OrderEntry {
+            pattern,
+            transpose: 0,
+        }
+    }
+
+    pub const fn transposed(mut self, semitones: i8) -> OrderEntry {
+        self.transpose = semitones;
+        self
+    }
+}
+
+/// What a [`Song`] does after its last order entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SongEnd {
+    /// Silences the chip and finishes.
+    Stop,
+    /// Goes on from the song's loop position.
+    Loop,
+}
+
+/// Patterns and the order they play in, which can name a pattern as often
+/// as it likes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Song<S: Storage = Static> {
+    pub patterns: S::Slice<Pattern<S>>,
+    pub order: S::Slice<OrderEntry>,
+    /// The order entry a looping song goes back to. A position past the end
+    /// goes back to the start, as in ProTracker.
+    pub loop_order: usize,
+    pub end: SongEnd,
+}
+
+impl Song {
+    /// A song playing `order` through once.
+    pub const fn new(patterns: &'static [Pattern], order: &'static [OrderEntry]) -> Song {
+        Song {
+            patterns,
+            order,
+            loop_order: 0,
+            end: SongEnd::Stop,
+        }
+    }
+
+    /// The song as it is, after checking that every order entry names a
+    /// pattern that is there and no cell names an instrument past the first
+    /// `instruments`. Panics, at compile time in const contexts, if not.
+    pub const fn checked(self, instruments: usize) -> Song {
+        let mut entry = 0;
+        while entry < self.order.len() {
+            if self.order[entry].pattern as usize >= self.patterns.len() {
+                panic!("order entry for a pattern that isn't there");
+            }
+            entry += 1;
+        }
+        let mut pattern = 0;
+        while pattern < self.patterns.len() {
+            let rows = self.patterns[pattern].rows;
+            let mut row = 0;
+            while row < rows.len() {
+                let mut channel = 0;
+                while channel < CHANNELS {
+                    if let Some(index) = rows[row].cells[channel].instrument {
+                        if index as usize >= instruments {
+                            panic!("cell for an instrument past the end of the bank");
+                        }
+                    }
+                    channel += 1;
+                }
+                row += 1;
+            }
+            pattern += 1;
+        }
+        self
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Song<Owned> {
+    /// As [`Song::new`], for patterns and an order list built at run time.
+    pub fn from_vecs(patterns: Vec<Pattern<Owned>>, order: Vec<OrderEntry>) -> Song<Owned> {
+        Song {
+            patterns,
+            order,
+            loop_order: 0,
+            end: SongEnd::Stop,
+        }
+    }
+
+    /// The song with its patterns and order list moved out of the heap for
+    /// good.
+    pub fn leak(self) -> Song {
+        let patterns: Vec<Pattern> = self.patterns.into_iter().map(Pattern::leak).collect();
+        Song {
+            patterns: patterns.leak(),
+            order: self.order.leak(),
+            loop_order: self.loop_order,
+            end: self.end,
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<Song> for Song<Owned> {
+    fn from(song: Song) -> Song<Owned> {
+        Song {
+            patterns: song
+                .patterns
+                .iter()
+                .map(|&pattern| pattern.into())
+                .collect(),
+            order: song.order.to_vec(),
+            loop_order: song.loop_order,
+            end: song.end,
+        }
+    }
+}
+
+impl<S: Storage> Song<S> {
+    /// Loops back to order entry `order` after the last.
+    pub const fn looping(mut self, order: usize) -> Song<S> {
+        self.loop_order = order;
+        self.end = SongEnd::Loop;
+        self
+    }
+}
+
+/// A note in a song that won't play as written at some clock, from
+/// [`validate_song`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// Where it is: the order entry, the row of its pattern, and the
+    /// channel.
+    pub order: usize,
+    pub row: usize,
+    pub channel: Channel,
+    /// The note as the cell has it, before the order entry transposes it.
+    pub pitch: Pitch,
+    pub problem: Problem,
+}
+
+/// What is wrong with a [`ValidationIssue`]'s note.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// Transposing it leaves the MIDI range, so it is moved back by octaves.
+    OutsideMidi,
+    /// The transposed note has no tone period at the clock, so it is moved
+    /// by octaves until it does.
+    NoPeriod(tuning::RangeError<Pitch>),
+}
+
+/// Every note in `song`, in the order its entries play, that a
+/// [`Sequencer`] at `clock` would have to move to play. Only the cells' own
+/// notes are checked, as their order entries transpose them: not the
+/// pitches instruments and arpeggios add. Entries for patterns that aren't
+/// there play nothing, so have nothing to report.
+pub fn validate_song<S: Storage>(
+    song: &Song<S>,
+    clock: u32,
+) -> impl Iterator<Item = ValidationIssue> + '_ {
+    let patterns = song.patterns.as_ref();
+    let order = song.order.as_ref().iter().enumerate();
+    order.flat_map(move |(order, entry)| {
+        let rows = patterns.get(entry.pattern as usize);
+        let rows = rows.map_or(&[][..], |pattern| pattern.rows());
+        rows.iter().enumerate().flat_map(move |(row, cells)| {
+            Channel::ALL.into_iter().filter_map(move |channel| {
+                let pitch = cells.cell(channel).pitch?;
+                let problem = match pitch.transpose(entry.transpose) {
+                    None => Problem::OutsideMidi,
+                    Some(played) => match tuning::checked_note_to_period(clock, played) {
+                        Ok(_) => return None,
+                        Err(error) => Problem::NoPeriod(error),
+                    },
+                };
+                Some(ValidationIssue {
+                    order,
+                    row,
+                    channel,
+                    pitch,
+                    problem,
+                })
+            })
+        })
+    })
+}
+
+/// Called by a [`Sequencer`] as its song plays, to keep a display in step
+/// say. Every method does nothing unless overridden.
+pub trait SequencerHooks {
+    /// Order entry `order`, playing pattern `pattern`, is about to play
+    /// its first row: at the start, at each pattern change, on a jump and
+    /// on a loop.
+    fn on_order(&mut self, order: usize, pattern: usize) {
+        let _ = (order, pattern);
+    }
+
+    /// The song has ended and the chip has been silenced.
+    fn on_end(&mut self) {}
+}
+
+impl SequencerHooks for () {}
+
+/// ProTracker's vibrato sine: half a cycle, from 0 up to 255 and back.
+/// [`Voice::vibrato`] makes the other half by negating it when bit 0x80 of
+/// the position is set.
+const VIBRATO_SINE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253, 255, 253, 250, 244,
+    235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+/// One channel of a [`Sequencer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Voice {
+    player: InstrumentPlayer,
+    /// The instrument from the bank, if one has been picked.
+    instrument: Option<u8>,
+    volume: u8,
+    /// The note last started, and the tone period slides have taken it to.
+    note: Option<(Pitch, u16)>,
+    /// The row's command, for those acting every tick.
+    command: Option<Command>,
+    /// Where a tone portamento is going.
+    target: Option<u16>,
+    portamento: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_position: u8,
+}
+
+impl Voice {
+    const fn new() -> Voice {
+        Voice {
+            player: InstrumentPlayer::new(&Instrument::DEFAULT),
+            instrument: None,
+            volume: 15,
+            note: None,
+            command: None,
+            target: None,
+            portamento: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_position: 0,
+        }
+    }
+}
+
+/// Plays a [`Song`] on channels A, B and C.
+///
+/// [`Sequencer::tick`] is called `tick_hertz` times a second, from a frame
+/// interrupt say, and plays the tempo's ticks as they come due, with the
+/// fraction carried so the tempo doesn't drift. Each tick advances every
+/// channel's instrument; every `speed` ticks a new row starts, the first row
+/// of the next pattern following the last of the one before as closely as
+/// any two rows. Speed and tempo start at [`DEFAULT_SPEED`] and
+/// [`DEFAULT_TEMPO`] and can be changed from pattern data by [`Command`]s,
+/// or the tempo can follow a clock from outside with
+/// [`Sequencer::with_tempo_sync`].
+///
+/// Channels start with [`Instrument::DEFAULT`], a steady tone, until a cell
+/// picks one from the bank.
+///
+/// The song and bank are `&'static` unless made with [`Sequencer::new_in`],
+/// which can take them `Owned`.
+///
+/// [`Sequencer::jump_to_order`] moves to another part of the song, to change
+/// the music with the game, on the next bar line so the beat carries on.
+/// Bars last a pattern unless set with [`Sequencer::with_bar_rows`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequencer<H = (), S: Storage = Static> {
+    song: S::Ref<Song<S>>,
+    instruments: S::Slice<Instrument<S>>,
+    voices: [Voice; CHANNELS],
+    tick_hertz: u32,
+    speed: u8,
+    tempo: u16,
+    /// Tempo ticks owed, in units of `1 / (1000 * tick_hertz)` of a tick.
+    due: u64,
+    /// The tempo to follow, and rows to a beat, in place of `tempo`.
+    sync: Option<(TempoSync, u8)>,
+    order: usize,
+    row: usize,
+    /// Ticks played of the current row.
+    row_tick: u8,
+    /// Whether the order entry has yet to be announced to the hooks.
+    entered: bool,
+    bar_rows: Option<u16>,
+    jump: Option<usize>,
+    /// Where a position jump or pattern break on this row goes.
+    break_order: Option<usize>,
+    break_row: Option<usize>,
+    loops: u32,
+    loop_policy: LoopPolicy,
+    /// The fade out at the end of the last loop, once begun.
+    fader: Option<Fader>,
+    mute: Mute,
+    /// Semitones on top of each order entry's own.
+    transpose: i8,
+    /// 0 to 127, as [`volume::scale_level`] takes it.
+    volume: u8,
+    finished: bool,
+    hooks: H,
+}
+
+impl Sequencer {
+    /// A sequencer for `song`, picking instruments from `instruments`,
+    /// ticked `tick_hertz` times a second.
+    pub const fn new(
+        song: &'static Song,
+        instruments: &'static [Instrument],
+        tick_hertz: u32,
+    ) -> Sequencer {
+        Sequencer::new_in(song, instruments, tick_hertz)
+    }
+}
+
+impl<S: Storage> Sequencer<(), S> {
+    /// As [`Sequencer::new`], for a song and bank held in `S`.
+    pub const fn new_in(
+        song: S::Ref<Song<S>>,
+        instruments: S::Slice<Instrument<S>>,
+        tick_hertz: u32,
+    ) -> Sequencer<(), S> {
+        let tick_hertz = if tick_hertz == 0 { 1 } else { tick_hertz };
+        Sequencer {
+            song,
+            instruments,
+            voices: [Voice::new(), Voice::new(), Voice::new()],
+            tick_hertz,
+            speed: DEFAULT_SPEED,
+            tempo: DEFAULT_TEMPO,
+            due: tick_hertz as u64 * 1000,
+            sync: None,
+            order: 0,
+            row: 0,
+            row_tick: 0,
+            entered: true,
+            bar_rows: None,
+            jump: None,
+            break_order: None,
+            break_row: None,
+            loops: 0,
+            loop_policy: LoopPolicy::Infinite,
+            fader: None,
+            mute: Mute::NONE,
+            transpose: 0,
+            volume: 127,
+            finished: false,
+            hooks: (),
+        }
+    }
+
+    /// Calls `hooks` as the song plays.
+    pub fn with_hooks<G: SequencerHooks>(self, hooks: G) -> Sequencer<G, S> {
+        Sequencer {
+            song: self.song,
+            instruments: self.instruments,
+            voices: self.voices,
+            tick_hertz: self.tick_hertz,
+            speed: self.speed,
+            tempo: self.tempo,
+            due: self.due,
+            sync: self.sync,
+            order: self.order,
+            row: self.row,
+            row_tick: self.row_tick,
+            entered: self.entered,
+            bar_rows: self.bar_rows,
+            jump: self.jump,
+            break_order: self.break_order,
+            break_row: self.break_row,
+            loops: self.loops,
+            loop_policy: self.loop_policy,
+            fader: self.fader,
+            mute: self.mute,
+            transpose: self.transpose,
+            volume: self.volume,
+            finished: self.finished,
+            hooks,
+        }
+    }
+}
+
+impl<H: SequencerHooks, S: Storage> Sequencer<H, S> {
+    /// Starts at `speed` ticks a row rather than [`DEFAULT_SPEED`].
+    pub const fn with_speed(mut self, speed: u8) -> Sequencer<H, S> {
+        self.speed = if speed == 0 { 1 } else { speed };
+        self
+    }
+
+    /// Starts at `tempo` ticks a second rather than [`DEFAULT_TEMPO`].
+    pub const fn with_tempo(mut self, tempo: u16) -> Sequencer<H, S> {
+        self.tempo = if tempo == 0 { 1 } else { tempo };
+        self
+    }
+
+    /// Puts a bar line every `rows` rows of a pattern, as well as at its
+    /// end, for [`Sequencer::jump_to_order`]. 0 leaves only the end.
+    pub const fn with_bar_rows(mut self, rows: u16) -> Sequencer<H, S> {
+        self.bar_rows = if rows == 0 { None } else { Some(rows) };
+        self
+    }
+
+    /// Goes round a looping song as `policy` says rather than for ever,
+    /// counting ticks as the frames of a fade.
+    pub const fn with_loop_policy(mut self, policy: LoopPolicy) -> Sequencer<H, S> {
+        self.loop_policy = policy;
+        self
+    }
+
+    /// Starts with channels muted, as [`Sequencer::set_mute`] does.
+    pub const fn with_mute(mut self, mute: Mute) -> Sequencer<H, S> {
+        self.mute = mute;
+        self
+    }
+
+    /// Plays at the tempo `sync` follows, from
+    /// [`Sequencer::sync_pulse`], rather than the song's: `rows_per_beat`
+    /// rows to a beat, however many ticks a row the song asks for. Until
+    /// the first pulses give a tempo, the song's own plays.
+    pub const fn with_tempo_sync(mut self, sync: TempoSync, rows_per_beat: u8) -> Sequencer<H, S> {
+        let rows_per_beat = if rows_per_beat == 0 { 1 } else { rows_per_beat };
+        self.sync = Some((sync, rows_per_beat));
+        self
+    }
+
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    pub fn hooks_mut(&mut self) -> &mut H {
+        &mut self.hooks
+    }
+
+    pub fn song(&self) -> &Song<S> {
+        self.song.borrow()
+    }
+
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: u8) {
+        self.speed = speed.max(1);
+    }
+
+    pub fn tempo(&self) -> u16 {
+        self.tempo
+    }
+
+    pub fn set_tempo(&mut self, tempo: u16) {
+        self.tempo = tempo.max(1);
+    }
+
+    /// A sync pulse came in at `micros`, as for [`TempoSync::pulse`]. Does
+    /// nothing without [`Sequencer::with_tempo_sync`].
+    pub fn sync_pulse(&mut self, micros: u32) {
+        if let Some((sync, _)) = &mut self.sync {
+            sync.pulse(micros);
+        }
+    }
+
+    pub fn tempo_sync(&self) -> Option<&TempoSync> {
+        self.sync.as_ref().map(|(sync, _)| sync)
+    }
+
+    /// The order entry playing, counted from 0.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// The pattern playing, or `None` once past the end of the order list.
+    pub fn pattern(&self) -> Option<usize> {
+        let entry = self.song().order.as_ref().get(self.order)?;
+        Some(entry.pattern as usize)
+    }
+
+    /// The row playing in the pattern, or the next to play between rows.
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    /// Times the song has gone back to its loop position.
+    pub fn loops_completed(&self) -> u32 {
+        self.loops
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn transpose(&self) -> i8 {
+        self.transpose
+    }
+
+    /// Moves the whole song `semitones` up, or down if negative, on top of
+    /// the order list's own transposes, from the next row on; notes already
+    /// sounding keep their pitch. Notes pushed off either end of the range
+    /// are moved back by octaves.
+    pub fn set_transpose(&mut self, semitones: i8) {
+        self.transpose = semitones;
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// Turns every level the song plays down by `volume` out of 127, on the
+    /// chip's 3 dB steps as [`volume::scale_level`] does, from the next tick
+    /// on. 127 and over is the song as written.
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(127);
+    }
+
+    pub fn mute(&self) -> Mute {
+        self.mute
+    }
+
+    /// Mutes the channels `mute` says, and unmutes the rest, from the next
+    /// tick on. Their notes play on underneath.
+    pub fn set_mute(&mut self, mute: Mute) {
+        self.mute = mute;
+    }
+
+    /// Moves to order entry `order` at the next bar line, returning
+    /// `false`, and changing nothing, if there is no such entry. A later
+    /// jump before the bar line replaces this one.
+    pub fn jump_to_order(&mut self, order: usize) -> bool {
+        if order >= self.song().order.as_ref().len() {
+            return false;
+        }
+        self.jump = Some(order);
+        true
+    }
+
+    /// Ends the song now, as running out of it does: cuts every voice,
+    /// silences its channels and tells the hooks. [`Sequencer::restart`]
+    /// plays it again.
+    pub fn stop<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        self.finish(psg)
+    }
+
+    /// Goes back to the first row of the first order entry, cutting off any
+    /// notes sounding. Speed and tempo stay as they are.
+    pub fn restart(&mut self) {
+        for voice in &mut self.voices {
+            voice.player.stop();
+        }
+        self.due = self.tick_hertz as u64 * 1000;
+        self.order = 0;
+        self.row = 0;
+        self.row_tick = 0;
+        self.entered = true;
+        self.jump = None;
+        self.break_order = None;
+        self.break_row = None;
+        self.loops = 0;
+        self.fader = None;
+        self.finished = false;
+    }
+
+    /// Plays any ticks due. Returns `true` once the song is over, from the
+    /// tick that silences every channel.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        let psg = &mut MutedPsg::new(psg, self.mute);
+        let tick = self.tick_hertz as u64 * 1000;
+        while !self.finished && self.due >= tick {
+            self.due -= tick;
+            self.play_tick(psg)?;
+        }
+        self.due += self.tick_millihertz();
+        if let Some((sync, _)) = &mut self.sync {
+            sync.advance(1_000_000 / self.tick_hertz);
+        }
+        Ok(self.finished)
+    }
+
+    /// Tempo ticks a second, in thousandths: from the sync once it has a
+    /// tempo, and otherwise the song's.
+    fn tick_millihertz(&self) -> u64 {
+        match self.sync {
+            Some((sync, rows_per_beat)) => match sync.millibpm() {
+                Some(millibpm) => millibpm as u64 * rows_per_beat as u64 * self.speed as u64 / 60,
+                None => self.tempo as u64 * 1000,
+            },
+            None => self.tempo as u64 * 1000,
+        }
+    }
+
+    fn play_tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        if self.row_tick == 0 {
+            let Some((row, transpose)) = self.locate() else {
+                return self.finish(psg);
+            };
+            let clock = psg.master_clock();
+            let transpose = transpose.saturating_add(self.transpose);
+            for (voice, cell) in self.voices.iter_mut().zip(&row.cells) {
+                match cell.command {
+                    Some(Command::Speed(speed)) => self.speed = speed.max(1),
+                    Some(Command::Tempo(tempo)) => self.tempo = tempo.max(1),
+                    Some(Command::PositionJump(order)) => self.break_order = Some(order as usize),
+                    Some(Command::PatternBreak(row)) => self.break_row = Some(row as usize),
+                    _ => {}
+                }
+                voice.start_row(cell, self.instruments.as_ref(), transpose, clock);
+            }
+        }
+        if self.fader.as_mut().is_some_and(Fader::step) {
+            return self.finish(psg);
+        }
+        let row_tick = self.row_tick;
+        for (channel, voice) in Channel::ALL.into_iter().zip(&mut self.voices) {
+            voice.tick(
+                psg,
+                channel,
+                row_tick,
+                self.instruments.as_ref(),
+                self.fader,
+                self.volume,
+            )?;
+        }
+        self.row_tick += 1;
+        if self.row_tick >= self.speed {
+            self.row_tick = 0;
+            let broke = self.break_order.is_some() || self.break_row.is_some();
+            if broke {
+                self.order = self.break_order.take().unwrap_or(self.order + 1);
+                self.row = self.break_row.take().unwrap_or(0);
+                self.entered = true;
+            } else {
+                self.row += 1;
+            }
+            if self.jump.is_some() && (broke || self.at_bar_line()) {
+                self.order = self.jump.take().unwrap_or(0);
+                self.row = 0;
+                self.entered = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the song: cuts every voice, silences the chip and tells the
+    /// hooks.
+    fn finish<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        self.finished = true;
+        for (channel, voice) in Channel::ALL.into_iter().zip(&mut self.voices) {
+            voice.player.stop();
+            psg.update_channel_level(channel, ChannelLevel::Fixed(0))?;
+        }
+        self.hooks.on_end();
+        Ok(())
+    }
+
+    /// Whether the row about to play starts a bar.
+    fn at_bar_line(&self) -> bool {
+        let length = self
+            .pattern()
+            .and_then(|pattern| self.song().patterns.as_ref().get(pattern));
+        self.row >= length.map_or(0, Pattern::len)
+            || self
+                .bar_rows
+                .is_some_and(|rows| self.row.is_multiple_of(rows as usize))
+    }
+
+    /// Moves on from the end of a pattern, or from order entries with
+    /// nothing in them, to the row to play, giving its pattern and
+    /// transposition, or `None` if the song is over.
+    fn locate(&mut self) -> Option<(Row, i8)> {
+        let song = self.song.borrow();
+        let order = song.order.as_ref();
+        // Enough tries to go round a looping song with no rows in it once.
+        for _ in 0..2 * order.len() + 1 {
+            if self.order >= order.len() {
+                if song.end == SongEnd::Stop || order.is_empty() {
+                    return None;
+                }
+                match self.loop_policy.at_end(self.loops, self.fader.is_some()) {
+                    LoopAction::Loop => {}
+                    LoopAction::Fade(fader) => self.fader = Some(fader),
+                    LoopAction::Finish => return None,
+                }
+                self.order = if song.loop_order < order.len() {
+                    song.loop_order
+                } else {
+                    0
+                };
+                self.row = 0;
+                self.entered = true;
+                self.loops += 1;
+            }
+            let entry = order[self.order];
+            match song.patterns.as_ref().get(entry.pattern as usize) {
+                Some(pattern) if self.row < pattern.len() => {
+                    if core::mem::take(&mut self.entered) {
+                        self.hooks.on_order(self.order, entry.pattern as usize);
+                    }
+                    return Some((pattern.rows()[self.row], entry.transpose));
+                }
+                _ => {
+                    self.order += 1;
+                    self.row = 0;
+                    self.entered = true;
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Voice {
+    fn start_row<S: Storage>(
+        &mut self,
+        cell: &Cell,
+        instruments: &[Instrument<S>],
+        transpose: i8,
+        clock: u32,
+    ) {
+        if let Some(index) = cell.instrument {
+            if (index as usize) < instruments.len() {
+                self.instrument = Some(index);
+            }
+        }
+        if let Some(volume) = cell.volume {
+            self.volume = volume.min(15);
+        }
+        self.command = cell.command;
+        match cell.command {
+            Some(Command::TonePortamento(speed)) if speed != 0 => self.portamento = speed,
+            Some(Command::Vibrato { speed, depth }) => {
+                if speed != 0 {
+                    self.vibrato_speed = speed;
+                }
+                if depth != 0 {
+                    self.vibrato_depth = depth;
+                }
+            }
+            _ => {}
+        }
+        let pitch = cell.pitch.map(|pitch| pitch.transpose_folded(transpose));
+        match (pitch, cell.command) {
+            // Already sounding: slide to the note rather than start it.
+            (Some(pitch), Some(Command::TonePortamento(_))) if self.player.is_playing() => {
+                self.target = Some(tuning::fold_pitch_period(clock, pitch).period);
+            }
+            (Some(pitch), _) => {
+                self.player.note_on(pitch);
+                self.note = Some((pitch, tuning::fold_pitch_period(clock, pitch).period));
+                self.target = None;
+                self.vibrato_position = 0;
+            }
+            (None, _) if cell.off => self.player.note_off(),
+            (None, _) => {}
+        }
+    }
+
+    /// Moves the note's period and the volume as the row's command asks,
+    /// on every tick but the first, giving the period to play before
+    /// vibrato.
+    fn run_command(&mut self, row_tick: u8) {
+        if row_tick == 0 {
+            return;
+        }
+        let Some((_, period)) = &mut self.note else {
+            return;
+        };
+        match self.command {
+            Some(Command::SlideUp(step)) => *period = period.saturating_sub(step as u16).max(1),
+            Some(Command::SlideDown(step)) => {
+                *period = period.saturating_add(step as u16).min(MAX_TONE_PERIOD)
+            }
+            Some(Command::TonePortamento(_)) => {
+                if let Some(target) = self.target {
+                    let step = self.portamento as u16;
+                    *period = if *period < target {
+                        period.saturating_add(step).min(target)
+                    } else {
+                        period.saturating_sub(step).max(target)
+                    };
+                    if *period == target {
+                        self.target = None;
+                    }
+                }
+            }
+            Some(Command::VolumeSlide { up, down }) => {
+                self.volume = if up != 0 {
+                    self.volume.saturating_add(up).min(15)
+                } else {
+                    self.volume.saturating_sub(down)
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// This tick's vibrato, to add to the period, moving on through the
+    /// cycle.
+    fn vibrato(&mut self, row_tick: u8) -> i32 {
+        if row_tick == 0 || !matches!(self.command, Some(Command::Vibrato { .. })) {
+            return 0;
+        }
+        let position = self.vibrato_position;
+        let delta =
+            (VIBRATO_SINE[(position >> 2 & 0x1F) as usize] as i32 * self.vibrato_depth as i32) >> 7;
+        self.vibrato_position = position.wrapping_add(self.vibrato_speed << 2);
+        if position & 0x80 == 0 {
+            delta
+        } else {
+            -delta
+        }
+    }
+
+    fn tick<P: Psg, S: Storage>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+        row_tick: u8,
+        instruments: &[Instrument<S>],
+        fader: Option<Fader>,
+        volume: u8,
+    ) -> Result<(), P::Error> {
+        self.run_command(row_tick);
+        let vibrato = self.vibrato(row_tick);
+        let frame = match self.instrument {
+            Some(index) => self.player.next_frame_with(&instruments[index as usize]),
+            None => self.player.next_frame(),
+        };
+        let Some(mut frame) = frame else {
+            return psg.update_channel_level(channel, ChannelLevel::Fixed(0));
+        };
+        frame.level = volume::scale_level(frame.level * self.volume / 15, volume);
+        if let Some(fader) = fader {
+            frame.level = fader.scale(frame.level);
+        }
+        let arpeggio = match self.command {
+            Some(Command::Arpeggio(x, y)) => [0, x, y][row_tick as usize % 3],
+            _ => 0,
+        };
+        let Some((note, period)) = self.note else {
+            return apply_frame(psg, channel, &frame);
+        };
+        if frame.tone {
+            // The instrument's pitch and the arpeggio as the distance
+            // they would move the note's own period, on top of slides.
+            let clock = psg.master_clock();
+            let pitch = frame.pitch.transpose_folded(arpeggio as i8);
+            let offset = tuning::fold_pitch_period(clock, pitch).period as i32
+                - tuning::fold_pitch_period(clock, note).period as i32;
+            let period = (period as i32 + offset + vibrato).clamp(1, MAX_TONE_PERIOD as i32);
+            psg.set_channel_period(channel, period as u16)?;
+        }
+        if let Some(period) = frame.noise {
+            psg.update_register(0x6, period)?;
+        }
+        psg.set_tone_enabled(channel, frame.tone)?;
+        psg.set_noise_enabled(channel, frame.noise.is_some())?;
+        psg.update_channel_level(channel, ChannelLevel::Fixed(frame.level))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::instrument::Table;
+    use crate::mute::ChannelMask;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use crate::tuning::fold_pitch_period;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// Level 9 on the first tick of a note and 15 after, so note-ons show
+    /// on the chip even when a note repeats.
+    static MARKED: Instrument = Instrument {
+        volume: Table::new(&[9, 15]).looping(1),
+        ..Instrument::DEFAULT
+    };
+    static BANK: [Instrument; 1] = [MARKED];
+
+    const C4: Pitch = Pitch::new(Note::C, 4);
+    const E4: Pitch = Pitch::new(Note::E, 4);
+    const G4: Pitch = Pitch::new(Note::G, 4);
+    const C3: Pitch = Pitch::new(Note::C, 3);
+
+    static PATTERNS: [Pattern; 2] = [
+        Pattern::new(&[
+            Row::new(
+                Cell::note(C4).instrument(0).command(Command::Speed(2)),
+                Cell::note(C3).instrument(0),
+                Cell::EMPTY,
+            ),
+            Row::new(Cell::note(E4), Cell::EMPTY, Cell::EMPTY),
+            Row::new(Cell::note(G4), Cell::note(C3), Cell::note(E4).instrument(0)),
+        ]),
+        Pattern::new(&[
+            Row::new(
+                Cell::note(G4),
+                Cell::OFF,
+                Cell::EMPTY.command(Command::Speed(3)),
+            ),
+            Row::new(Cell::OFF, Cell::note(C3).volume(5), Cell::note(G4)),
+        ]),
+    ];
+
+    static SONG: Song = Song::new(&PATTERNS, &[OrderEntry::new(0), OrderEntry::new(1)]);
+
+    #[test]
+    fn validation_finds_notes_that_would_move() {
+        // B0 is the lowest note with a period at 2 MHz.
+        const B0: Pitch = Pitch::new(Note::B, 0);
+        const G9: Pitch = Pitch::new(Note::G, 9);
+        static PATTERNS: [Pattern; 1] = [Pattern::new(&[
+            Row::new(Cell::note(B0), Cell::EMPTY, Cell::note(C4)),
+            Row::new(Cell::EMPTY, Cell::note(G9), Cell::OFF),
+        ])];
+        static SONG: Song = Song::new(
+            &PATTERNS,
+            &[
+                OrderEntry::new(0),
+                OrderEntry::new(1),
+                OrderEntry::new(0).transposed(-2),
+                OrderEntry::new(0).transposed(1),
+            ],
+        );
+        // Taken down, B0 has no period; taken up, G9 leaves the MIDI range.
+        // The missing pattern has nothing in it.
+        let issues: Vec<_> = validate_song(&SONG, 2_000_000).collect();
+        assert_eq!(
+            issues,
+            [
+                ValidationIssue {
+                    order: 2,
+                    row: 0,
+                    channel: Channel::A,
+                    pitch: B0,
+                    problem: Problem::NoPeriod(tuning::RangeError {
+                        requested: Pitch::new(Note::A, 0),
+                        range: Some((B0, G9)),
+                        nearest: Some(B0),
+                    }),
+                },
+                ValidationIssue {
+                    order: 3,
+                    row: 1,
+                    channel: Channel::B,
+                    pitch: G9,
+                    problem: Problem::OutsideMidi,
+                },
+            ]
+        );
+        // An octave down, A0 has one too.
+        assert_eq!(validate_song(&SONG, 1_000_000).count(), 1);
+    }
+
+    /// Ticks `sequencer` until it finishes, or `ticks` times, giving the
+    /// tick and pitch of each note-on per channel, and the level each
+    /// channel ended on.
+    fn note_ons<H: SequencerHooks>(
+        sequencer: &mut Sequencer<H>,
+        ticks: usize,
+    ) -> ([Vec<(usize, Pitch)>; 3], [u8; 3]) {
+        let mut psg = FakePsg::new();
+        let mut notes = [Vec::new(), Vec::new(), Vec::new()];
+        for tick in 0..ticks {
+            let finished = sequencer.tick(&mut psg).unwrap();
+            for channel in Channel::ALL {
+                let level = psg.registers().value(channel.level_register());
+                let period = psg.registers().tone_period(channel);
+                if psg.writes.contains(&(channel.level_register(), 9)) && level == 9 {
+                    // C2 to C7, well clear of where periods fold.
+                    let pitch = (36..=96)
+                        .filter_map(Pitch::from_midi)
+                        .find(|&pitch| {
+                            fold_pitch_period(psg.master_clock(), pitch).period == period
+                        })
+                        .unwrap();
+                    notes[channel.index()].push((tick, pitch));
+                }
+            }
+            psg.take_writes();
+            if finished {
+                break;
+            }
+        }
+        let levels = Channel::ALL.map(|channel| psg.registers().value(channel.level_register()));
+        (notes, levels)
+    }
+
+    #[test]
+    fn plays_patterns_in_order() {
+        let mut sequencer = Sequencer::new(&SONG, &BANK, 50);
+        let (notes, levels) = note_ons(&mut sequencer, 100);
+        assert!(sequencer.is_finished());
+        // Two ticks a row, then three from the second pattern.
+        assert_eq!(notes[0], [(0, C4), (2, E4), (4, G4), (6, G4)]);
+        // B's last note is at volume 5, so its first tick is at level 3.
+        assert_eq!(notes[1], [(0, C3), (4, C3)]);
+        assert_eq!(notes[2], [(4, E4), (9, G4)]);
+        assert_eq!(levels, [0; 3]);
+    }
+
+    #[test]
+    fn volume_and_release_come_from_cells() {
+        let mut psg = FakePsg::new();
+        let mut sequencer = Sequencer::new(&SONG, &BANK, 50);
+        for _ in 0..7 {
+            sequencer.tick(&mut psg).unwrap();
+        }
+        // The second pattern's first row: B released, C still holding E4.
+        assert_eq!((sequencer.pattern(), sequencer.row()), (Some(1), 0));
+        assert_eq!(psg.registers().value(0x9), 0);
+        assert_eq!(psg.registers().value(0xA), 15);
+        for _ in 0..4 {
+            sequencer.tick(&mut psg).unwrap();
+        }
+        // B at volume 5: 15 * 5 / 15.
+        assert_eq!(psg.registers().value(0x9), 5);
+        assert_eq!(psg.registers().value(0x8), 0);
+        // Channels without an instrument yet get the default one.
+        let mut sequencer = Sequencer::new(&SONG, &[], 50);
+        sequencer.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x8), 15);
+    }
+
+    #[test]
+    fn the_song_volume_scales_every_level_and_stop_silences() {
+        let mut psg = FakePsg::new();
+        let mut sequencer = Sequencer::new(&SONG, &BANK, 50);
+        sequencer.set_volume(64);
+        sequencer.tick(&mut psg).unwrap();
+        // Level 9 on the first tick, 4 steps down at half volume.
+        assert_eq!(psg.registers().value(0x8), 5);
+        sequencer.set_volume(200);
+        assert_eq!(sequencer.volume(), 127);
+        sequencer.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x8), 15);
+        sequencer.stop(&mut psg).unwrap();
+        assert!(sequencer.is_finished());
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(psg.registers().value(0x9), 0);
+        sequencer.restart();
+        assert!(!sequencer.is_finished());
+    }
+
+    #[test]
+    fn tempo_decouples_from_the_tick_rate() {
+        static SLOW: [Pattern; 1] = [Pattern::new(&[
+            Row::new(
+                Cell::note(C4).instrument(0).command(Command::Tempo(25)),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            ),
+            Row::new(
+                Cell::note(E4).command(Command::Tempo(100)),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            ),
+            Row::new(Cell::note(G4), Cell::EMPTY, Cell::EMPTY),
+        ])];
+        static SLOW_SONG: Song = Song::new(&SLOW, &[OrderEntry::new(0)]);
+        let sequencer = Sequencer::new(&SLOW_SONG, &BANK, 50).with_speed(2);
+        let (notes, _) = note_ons(&mut sequencer.clone(), 100);
+        // The first row ticks every other call; after that, twice a call.
+        assert_eq!(notes[0], [(0, C4), (4, E4), (5, G4)]);
+        let mut rows = Vec::new();
+        let mut psg = FakePsg::new();
+        let mut sequencer = sequencer;
+        while !sequencer.tick(&mut psg).unwrap() {
+            rows.push(sequencer.row());
+        }
+        assert_eq!(rows, [0, 0, 1, 1, 1, 2]);
+        assert_eq!(sequencer.tempo(), 100);
+        sequencer.restart();
+        assert!(!sequencer.is_finished());
+        assert_eq!(sequencer.row(), 0);
+    }
+
+    static PARTS: [Pattern; 3] = [
+        Pattern::new(&[
+            Row::new(Cell::note(C4).instrument(0), Cell::EMPTY, Cell::EMPTY),
+            Row::new(Cell::note(E4), Cell::EMPTY, Cell::EMPTY),
+        ]),
+        Pattern::new(&[Row::new(Cell::note(G4), Cell::EMPTY, Cell::EMPTY)]),
+        Pattern::new(&[Row::new(
+            Cell::note(Pitch::MAX).instrument(0),
+            Cell::EMPTY,
+            Cell::EMPTY,
+        )]),
+    ];
+
+    #[derive(Default)]
+    struct Log {
+        orders: Vec<(usize, usize)>,
+        ends: u32,
+    }
+
+    impl SequencerHooks for Log {
+        fn on_order(&mut self, order: usize, pattern: usize) {
+            self.orders.push((order, pattern));
+        }
+
+        fn on_end(&mut self) {
+            self.ends += 1;
+        }
+    }
+
+    #[test]
+    fn songs_repeat_transpose_and_loop() {
+        static LOOPING: Song = Song::new(
+            &PARTS,
+            &[
+                OrderEntry::new(0),
+                OrderEntry::new(0),
+                // Nothing there: skipped without a tick going by.
+                OrderEntry::new(9),
+                OrderEntry::new(1).transposed(12),
+                OrderEntry::new(0).transposed(-5),
+            ],
+        )
+        .looping(1);
+        let mut sequencer = Sequencer::new(&LOOPING, &BANK, 50)
+            .with_speed(2)
+            .with_hooks(Log::default());
+        let (notes, _) = note_ons(&mut sequencer, 22);
+        let g5 = Pitch::new(Note::G, 5);
+        let (g3, b3) = (Pitch::new(Note::G, 3), Pitch::new(Note::B, 3));
+        assert_eq!(
+            notes[0],
+            [
+                (0, C4),
+                (2, E4),
+                (4, C4),
+                (6, E4),
+                (8, g5),
+                (10, g3),
+                (12, b3),
+                // Round again from the second entry.
+                (14, C4),
+                (16, E4),
+                (18, g5),
+                (20, g3),
+            ]
+        );
+        assert_eq!(
+            sequencer.hooks().orders,
+            [(0, 0), (1, 0), (3, 1), (4, 0), (1, 0), (3, 1), (4, 0)]
+        );
+        assert_eq!(sequencer.loops_completed(), 1);
+        assert_eq!(sequencer.hooks().ends, 0);
+        assert!(!sequencer.is_finished());
+    }
+
+    #[test]
+    fn loop_policy_counts_loops_then_fades() {
+        static ONE_NOTE: [Pattern; 1] = [Pattern::new(&[Row::new(
+            Cell::note(C4),
+            Cell::EMPTY,
+            Cell::EMPTY,
+        )])];
+        static REPEATING: Song = Song::new(&ONE_NOTE, &[OrderEntry::new(0)]).looping(0);
+        let play = |policy| {
+            let mut psg = FakePsg::new();
+            let mut sequencer = Sequencer::new(&REPEATING, &[], 50)
+                .with_speed(1)
+                .with_loop_policy(policy)
+                .with_hooks(Log::default());
+            let mut levels = Vec::new();
+            while !sequencer.tick(&mut psg).unwrap() {
+                levels.push(psg.registers().value(0x8));
+            }
+            assert_eq!(sequencer.hooks().ends, 1);
+            levels.push(psg.registers().value(0x8));
+            levels
+        };
+        assert_eq!(play(LoopPolicy::Count(1)), [15, 15, 0]);
+        // Round twice, then fading from the third time back.
+        let levels = play(LoopPolicy::CountThenFade {
+            loops: 2,
+            fade_frames: 4,
+        });
+        assert_eq!(levels, [15, 15, 15, 11, 7, 3, 0]);
+
+        // A song that doesn't loop ends where it always did.
+        static ONCE: Song = Song::new(&ONE_NOTE, &[OrderEntry::new(0)]);
+        let mut sequencer = Sequencer::new(&ONCE, &[], 50)
+            .with_speed(1)
+            .with_loop_policy(LoopPolicy::Count(3));
+        let mut psg = FakePsg::new();
+        assert!(!sequencer.tick(&mut psg).unwrap());
+        assert!(sequencer.tick(&mut psg).unwrap());
+    }
+
+    static HELD: [Pattern; 1] = [Pattern::new(&[
+        Row::new(Cell::note(C4), Cell::note(E4), Cell::EMPTY),
+        Row::new(Cell::EMPTY, Cell::EMPTY, Cell::EMPTY),
+    ])];
+    static TWO_NOTES: Song = Song::new(&HELD, &[OrderEntry::new(0)]).looping(0);
+
+    #[test]
+    fn muted_channels_play_on_underneath() {
+        let mut psg = FakePsg::new();
+        let mut sequencer = Sequencer::new(&TWO_NOTES, &[], 50)
+            .with_speed(1)
+            .with_mute(Mute::new(ChannelMask::B));
+        sequencer.tick(&mut psg).unwrap();
+        let registers = psg.registers();
+        assert_eq!((registers.value(0x8), registers.value(0x9)), (15, 0));
+        assert_eq!(registers.tone_period(Channel::B), period(E4));
+
+        sequencer.set_mute(Mute::NONE);
+        sequencer.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().value(0x9), 15);
+    }
+
+    #[test]
+    fn transposing_moves_the_next_notes() {
+        let mut sequencer = Sequencer::new(&TWO_NOTES, &[], 50).with_speed(1);
+        let mut psg = FakePsg::new();
+        sequencer.set_transpose(3);
+        sequencer.tick(&mut psg).unwrap();
+        let registers = psg.registers();
+        assert_eq!(
+            registers.tone_period(Channel::A),
+            period(Pitch::new(Note::DSharp, 4))
+        );
+        assert_eq!(
+            registers.tone_period(Channel::B),
+            period(Pitch::new(Note::G, 4))
+        );
+        assert_eq!(sequencer.transpose(), 3);
+
+        // Down past the bottom of the range, folded back up by octaves.
+        sequencer.set_transpose(-100);
+        sequencer.tick(&mut psg).unwrap();
+        sequencer.tick(&mut psg).unwrap();
+        let folded = C4.transpose_folded(-100);
+        assert_eq!(psg.registers().tone_period(Channel::A), period(folded));
+    }
+
+    #[test]
+    fn synced_sequencers_play_rows_at_the_pulse_tempo() {
+        // 150 BPM at four rows a beat is 10 rows a second, not 8.3.
+        let mut sequencer =
+            Sequencer::new(&TWO_NOTES, &[], 50).with_tempo_sync(TempoSync::new(4), 4);
+        let mut psg = FakePsg::new();
+        for tick in 0..500u32 {
+            if tick % 5 == 0 {
+                sequencer.sync_pulse(tick * 20_000);
+            }
+            sequencer.tick(&mut psg).unwrap();
+        }
+        assert!(sequencer.tempo_sync().unwrap().is_locked());
+        // Two rows a loop.
+        assert!(sequencer.loops_completed().abs_diff(50) <= 1);
+        assert_eq!(sequencer.tempo(), DEFAULT_TEMPO);
+    }
+
+    #[test]
+    fn jumps_wait_for_the_bar_line() {
+        static ORDER: [OrderEntry; 3] =
+            [OrderEntry::new(0), OrderEntry::new(0), OrderEntry::new(1)];
+        static JUMPING: Song = Song::new(&PARTS, &ORDER);
+        // The jump comes a tick into the first row: at the end of the
+        // pattern, or with a bar a row, at the next row.
+        let expected: [&[(usize, Pitch)]; 2] = [&[(2, E4), (4, G4)], &[(2, G4)]];
+        for (bar_rows, expected) in [0, 1].into_iter().zip(expected) {
+            let mut psg = FakePsg::new();
+            let mut sequencer = Sequencer::new(&JUMPING, &BANK, 50)
+                .with_speed(2)
+                .with_bar_rows(bar_rows);
+            sequencer.tick(&mut psg).unwrap();
+            assert!(!sequencer.jump_to_order(3));
+            assert!(sequencer.jump_to_order(2));
+            let (notes, _) = note_ons(&mut sequencer, 5);
+            // One tick in already.
+            let notes: Vec<_> = notes[0]
+                .iter()
+                .map(|&(tick, pitch)| (tick + 1, pitch))
+                .collect();
+            assert_eq!(notes, expected);
+        }
+    }
+
+    #[test]
+    fn odd_songs_are_safe() {
+        let mut psg = FakePsg::new();
+        // An empty order list ends at once, looping or not.
+        static EMPTY: Song = Song::new(&PARTS, &[]);
+        static EMPTY_LOOP: Song = Song::new(&PARTS, &[]).looping(0);
+        static NOTHING_THERE: Song = Song::new(&PARTS, &[OrderEntry::new(7)]).looping(0);
+        for song in [&EMPTY, &EMPTY_LOOP, &NOTHING_THERE] {
+            let mut sequencer = Sequencer::new(song, &BANK, 50).with_hooks(Log::default());
+            assert!(sequencer.tick(&mut psg).unwrap());
+            assert_eq!(sequencer.hooks().ends, 1);
+            assert!(sequencer.hooks().orders.is_empty());
+            assert!(sequencer.tick(&mut psg).unwrap());
+            assert_eq!(sequencer.hooks().ends, 1);
+        }
+
+        // A loop position past the end goes back to the start.
+        static PAST_THE_END: Song =
+            Song::new(&PARTS, &[OrderEntry::new(1), OrderEntry::new(0)]).looping(5);
+        let mut sequencer = Sequencer::new(&PAST_THE_END, &BANK, 50).with_speed(1);
+        for _ in 0..3 {
+            sequencer.tick(&mut psg).unwrap();
+        }
+        sequencer.tick(&mut psg).unwrap();
+        assert_eq!((sequencer.order(), sequencer.loops_completed()), (0, 1));
+
+        // Transposing past G9 folds back down an octave.
+        static HIGH: Song = Song::new(&PARTS, &[OrderEntry::new(2).transposed(5)]);
+        let mut sequencer = Sequencer::new(&HIGH, &BANK, 50);
+        sequencer.tick(&mut psg).unwrap();
+        let c9 = fold_pitch_period(psg.master_clock(), Pitch::new(Note::C, 9)).period;
+        assert_eq!(psg.registers().tone_period(Channel::A), c9);
+    }
+
+    /// A song of one pattern of `rows`, played through once.
+    fn one_pattern(rows: &[Row]) -> &'static Song {
+        let rows = Box::leak(rows.to_vec().into_boxed_slice());
+        let patterns = Box::leak(Box::new([Pattern::new(rows)]));
+        static ORDER: [OrderEntry; 1] = [OrderEntry::new(0)];
+        Box::leak(Box::new(Song::new(patterns, &ORDER)))
+    }
+
+    /// Channel A's tone period and level after each of up to `ticks` ticks.
+    fn trace(song: &'static Song, speed: u8, ticks: usize) -> Vec<(u16, u8)> {
+        let mut psg = FakePsg::new();
+        let mut sequencer = Sequencer::new(song, &BANK, 50).with_speed(speed);
+        let mut trace = Vec::new();
+        for _ in 0..ticks {
+            if sequencer.tick(&mut psg).unwrap() {
+                break;
+            }
+            trace.push((
+                psg.registers().tone_period(Channel::A),
+                psg.registers().value(0x8),
+            ));
+        }
+        trace
+    }
+
+    fn periods(song: &'static Song, speed: u8, ticks: usize) -> Vec<u16> {
+        trace(song, speed, ticks)
+            .into_iter()
+            .map(|(period, _)| period)
+            .collect()
+    }
+
+    fn period(pitch: Pitch) -> u16 {
+        fold_pitch_period(FakePsg::new().master_clock(), pitch).period
+    }
+
+    fn commanded(pitch: Option<Pitch>, command: Command) -> Row {
+        let cell = match pitch {
+            Some(pitch) => Cell::note(pitch),
+            None => Cell::EMPTY,
+        };
+        Row::new(cell.command(command), Cell::EMPTY, Cell::EMPTY)
+    }
+
+    #[test]
+    fn arpeggio_cycles_from_the_first_tick() {
+        let song = one_pattern(&[commanded(Some(C4), Command::Arpeggio(4, 7)), Row::EMPTY]);
+        let (c, e, g) = (period(C4), period(E4), period(G4));
+        assert_eq!(periods(song, 6, 8), [c, e, g, c, e, g, c, c]);
+    }
+
+    #[test]
+    fn slide_up_lowers_the_period() {
+        let song = one_pattern(&[
+            commanded(Some(C4), Command::SlideUp(3)),
+            // No memory: 0 slides nothing.
+            commanded(None, Command::SlideUp(0)),
+            commanded(Some(Pitch::MAX), Command::SlideUp(0xFF)),
+        ]);
+        let (c, top) = (period(C4), period(Pitch::MAX));
+        let periods = periods(song, 4, 12);
+        assert_eq!(periods[..4], [c, c - 3, c - 6, c - 9]);
+        assert_eq!(periods[4..8], [c - 9; 4]);
+        assert_eq!(periods[8..], [top, 1, 1, 1]);
+    }
+
+    #[test]
+    fn slide_down_raises_the_period() {
+        let song = one_pattern(&[
+            commanded(Some(C4), Command::SlideDown(5)),
+            commanded(None, Command::SlideDown(2)),
+            commanded(Some(Pitch::new(Note::C, 0)), Command::SlideDown(0xFF)),
+        ]);
+        let (c, low) = (period(C4), period(Pitch::new(Note::C, 0)));
+        let periods = periods(song, 3, 9);
+        assert_eq!(periods[..3], [c, c + 5, c + 10]);
+        assert_eq!(periods[3..6], [c + 10, c + 12, c + 14]);
+        assert_eq!(periods[6..], [low, low + 255, MAX_TONE_PERIOD]);
+    }
+
+    #[test]
+    fn tone_portamento_slides_without_retriggering() {
+        let d4 = Pitch::new(Note::D, 4);
+        let song = one_pattern(&[
+            Row::new(Cell::note(C4).instrument(0), Cell::EMPTY, Cell::EMPTY),
+            commanded(Some(d4), Command::TonePortamento(20)),
+            // Back again at the remembered speed.
+            commanded(Some(C4), Command::TonePortamento(0)),
+        ]);
+        let (c, d) = (period(C4), period(d4));
+        assert!((41..=60).contains(&(c - d)));
+        let trace = trace(song, 4, 12);
+        let periods: Vec<_> = trace.iter().map(|&(period, _)| period).collect();
+        assert_eq!(periods[4..], [c, c - 20, c - 40, d, d, d + 20, d + 40, c]);
+        // The instrument's marked first tick comes only with the first note.
+        let levels: Vec<_> = trace.iter().map(|&(_, level)| level).collect();
+        assert_eq!(levels, [9, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn vibrato_follows_the_protracker_sine() {
+        let song = one_pattern(&[
+            commanded(Some(C4), Command::Vibrato { speed: 8, depth: 8 }),
+            // Both halves remembered, and the cycle carries on.
+            commanded(None, Command::Vibrato { speed: 0, depth: 0 }),
+        ]);
+        let c = period(C4);
+        // Depth 8 at the sine's 180 and 255: 11 and 15.
+        let periods = periods(song, 6, 12);
+        assert_eq!(periods[..6], [c, c, c + 11, c + 15, c + 11, c]);
+        assert_eq!(periods[6..], [c, c - 11, c - 15, c - 11, c, c + 11]);
+    }
+
+    #[test]
+    fn volume_slide_stays_in_range() {
+        let song = one_pattern(&[
+            Row::new(
+                Cell::note(C4)
+                    .volume(10)
+                    .command(Command::VolumeSlide { up: 2, down: 0 }),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            ),
+            commanded(None, Command::VolumeSlide { up: 0, down: 4 }),
+            commanded(None, Command::VolumeSlide { up: 0, down: 4 }),
+            commanded(None, Command::VolumeSlide { up: 0, down: 0 }),
+        ]);
+        let levels: Vec<_> = trace(song, 4, 16)
+            .into_iter()
+            .map(|(_, level)| level)
+            .collect();
+        assert_eq!(
+            levels,
+            [10, 12, 14, 15, 15, 11, 7, 3, 3, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    static JUMPS: [Pattern; 3] = [
+        Pattern::new(&[
+            Row::new(
+                Cell::note(C4).command(Command::PositionJump(1)),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            ),
+            Row::new(Cell::note(E4), Cell::EMPTY, Cell::EMPTY),
+        ]),
+        Pattern::new(&[
+            Row::new(
+                Cell::note(E4).command(Command::PatternBreak(1)),
+                Cell::EMPTY,
+                Cell::EMPTY,
+            ),
+            Row::new(Cell::note(G4), Cell::EMPTY, Cell::EMPTY),
+        ]),
+        Pattern::new(&[
+            Row::new(Cell::note(C3), Cell::EMPTY, Cell::EMPTY),
+            Row::new(
+                Cell::note(G4).command(Command::PositionJump(0)),
+                Cell::EMPTY,
+                Cell::EMPTY.command(Command::PatternBreak(1)),
+            ),
+        ]),
+    ];
+
+    #[test]
+    fn position_jump_skips_to_an_order_entry() {
+        static SONG: Song = Song::new(&JUMPS, &[OrderEntry::new(0), OrderEntry::new(2)]);
+        let mut sequencer = Sequencer::new(&SONG, &BANK, 50)
+            .with_speed(1)
+            .with_hooks(Log::default());
+        let mut psg = FakePsg::new();
+        let mut periods = Vec::new();
+        for _ in 0..5 {
+            sequencer.tick(&mut psg).unwrap();
+            periods.push(psg.registers().tone_period(Channel::A));
+        }
+        // Straight from C4 to order 1, skipping E4; then with a break as
+        // well, back to the second row of order 0, and round again.
+        assert_eq!(
+            periods,
+            [period(C4), period(C3), period(G4), period(E4), period(C3)]
+        );
+        assert_eq!(sequencer.hooks().orders, [(0, 0), (1, 2), (0, 0), (1, 2)]);
+    }
+
+    #[test]
+    fn pattern_break_starts_the_next_entry_part_way() {
+        static SONG: Song = Song::new(&JUMPS, &[OrderEntry::new(1), OrderEntry::new(1)]);
+        // From the first row of the first entry to the second row of the
+        // second, then a row past the end of the last.
+        assert_eq!(
+            periods(&SONG, 2, 10),
+            [period(E4), period(E4), period(G4), period(G4)]
+        );
+        assert_eq!(
+            Command::from_code(0xD, 0x10),
+            Some(Command::PatternBreak(10))
+        );
+        assert_eq!(
+            Command::from_code(0xB, 0x10),
+            Some(Command::PositionJump(16))
+        );
+    }
+
+    #[test]
+    fn set_speed_reads_like_a_tracker() {
+        assert_eq!(Command::from_code(0xF, 0x02), Some(Command::Speed(2)));
+        assert_eq!(Command::from_code(0xF, 0x1F), Some(Command::Speed(31)));
+        // 125 beats a minute is 50 Hz; 64 is 25.6, rounded.
+        assert_eq!(Command::from_code(0xF, 125), Some(Command::Tempo(50)));
+        assert_eq!(Command::from_code(0xF, 64), Some(Command::Tempo(26)));
+        assert_eq!(Command::from_code(0xF, 0), None);
+        assert_eq!(Command::from_code(0xC, 0x20), None);
+        let speed = Command::from_code(0xF, 0x02).unwrap();
+        let song = one_pattern(&[
+            commanded(Some(C4), speed),
+            Row::new(Cell::note(E4), Cell::EMPTY, Cell::EMPTY),
+        ]);
+        let (c, e) = (period(C4), period(E4));
+        assert_eq!(periods(song, 6, 10), [c, c, e, e]);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn owned_songs_play_as_static_ones_do() {
+        let mut owned = Sequencer::<(), Owned>::new_in(
+            Song::<Owned>::from(SONG),
+            BANK.iter().map(|&instrument| instrument.into()).collect(),
+            50,
+        );
+        let mut borrowed = Sequencer::new(&SONG, &BANK, 50);
+        let (mut a, mut b) = (FakePsg::new(), FakePsg::new());
+        while !borrowed.tick(&mut a).unwrap() {
+            assert!(!owned.tick(&mut b).unwrap());
+        }
+        assert!(owned.tick(&mut b).unwrap());
+        assert_eq!(a.writes, b.writes);
+
+        // Built at run time, then leaked for good.
+        let pattern = Pattern::from_vec(PATTERNS[1].rows.to_vec());
+        let song = Song::from_vecs(Vec::from([pattern]), Vec::from([OrderEntry::new(0)]));
+        let song: &'static Song = Box::leak(Box::new(song.leak()));
+        assert_eq!(song.patterns, &PATTERNS[1..]);
+        assert_eq!(Song::<Owned>::from(*song).leak(), *song);
+    }
+}
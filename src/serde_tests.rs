@@ -0,0 +1,213 @@
+//! Every type with the `serde` feature, through postcard and back, and the
+//! bytes of the ones kept in storage pinned down so a change to their
+//! layout can't go unnoticed.
+
+extern crate std;
+
+use core::fmt::Debug;
+use std::vec::Vec;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::registers::{Register, Registers};
+use crate::{Channel, ChannelLevel, ChipConfig, ChipKind, EnvelopeShape, MixerSettings};
+
+#[cfg(feature = "music")]
+use crate::instrument::{presets, Table};
+#[cfg(feature = "player")]
+use crate::mute::{ChannelMask, Mute};
+#[cfg(feature = "player")]
+use crate::player_snapshot::PlayerSnapshot;
+#[cfg(feature = "sfx")]
+use crate::sfx::{NoiseSweep, Sfx};
+#[cfg(feature = "sfx")]
+use crate::sweep::{Sweep, SweepCurve, SweepPoint};
+#[cfg(feature = "music")]
+use crate::{Note, Pitch};
+
+#[cfg(all(feature = "alloc", feature = "music"))]
+use crate::instrument::Instrument;
+#[cfg(all(feature = "alloc", feature = "music"))]
+use crate::storage::Owned;
+#[cfg(all(feature = "alloc", feature = "music"))]
+use std::vec;
+
+/// Writes `value` to a buffer, reads it back and returns what was written.
+#[track_caller]
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) -> Vec<u8> {
+    let mut buffer = [0; 256];
+    let bytes = postcard::to_slice(&value, &mut buffer).unwrap();
+    assert_eq!(postcard::from_bytes::<T>(bytes).unwrap(), value);
+    bytes.to_vec()
+}
+
+#[test]
+fn registers_round_trip_as_they_are_stored() {
+    round_trip(Registers::new());
+    round_trip(Registers::from_values([0xFF; 16]));
+    let mut registers = Registers::new();
+    registers.set(0x7, 0x38);
+    registers.set(0x8, 15);
+    let mut expected = [0; 18];
+    expected[0x7] = 0x38;
+    expected[0x8] = 15;
+    // Which registers are known, R7 and R8, as a varint.
+    expected[16..].copy_from_slice(&[0x80, 0x03]);
+    assert_eq!(round_trip(registers), expected);
+    for register in Register::ALL {
+        round_trip(register);
+    }
+}
+
+#[cfg(feature = "player")]
+#[test]
+fn snapshots_keep_their_layout() {
+    let snapshot = PlayerSnapshot {
+        source_id: 1,
+        frame_count: Some(300),
+        position: 200,
+        loops: 2,
+        looped_frames: 600,
+        phase: 0,
+        mute: Mute::new(ChannelMask::B),
+    };
+    assert_eq!(
+        round_trip(snapshot),
+        [1, 1, 0xAC, 0x02, 0xC8, 0x01, 2, 0xD8, 0x04, 0, 0b010, 0]
+    );
+    round_trip(PlayerSnapshot {
+        source_id: u32::MAX,
+        frame_count: None,
+        position: u32::MAX,
+        loops: u32::MAX,
+        looped_frames: u64::MAX,
+        phase: u64::MAX,
+        mute: Mute::new(ChannelMask::all()).with_mixer_off(),
+    });
+    round_trip(ChannelMask::empty());
+    round_trip(Mute::NONE);
+}
+
+#[test]
+fn chip_settings_round_trip() {
+    round_trip(MixerSettings::empty());
+    round_trip(MixerSettings::all());
+    round_trip(EnvelopeShape::empty());
+    round_trip(EnvelopeShape::all());
+    for channel in Channel::ALL {
+        round_trip(channel);
+    }
+    for level in [
+        ChannelLevel::Fixed(0),
+        ChannelLevel::Fixed(15),
+        ChannelLevel::Envelope,
+    ] {
+        round_trip(level);
+    }
+    for chip in [ChipKind::Ym2149, ChipKind::Ay8910, ChipKind::Ymz294] {
+        round_trip(chip);
+    }
+    round_trip(ChipConfig::default());
+    // Variants by index and clocks as varints.
+    let config = ChipConfig {
+        chip: ChipKind::Ymz294,
+        master_clock: u32::MAX,
+        sel_low: true,
+    };
+    assert_eq!(round_trip(config), [2, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 1]);
+}
+
+#[cfg(feature = "music")]
+#[test]
+fn pitches_past_127_are_turned_away() {
+    for note in Note::ALL {
+        round_trip(note);
+    }
+    assert_eq!(round_trip(Pitch::from_midi(0).unwrap()), [0]);
+    assert_eq!(round_trip(Pitch::MAX), [127]);
+    assert!(postcard::from_bytes::<Pitch>(&[128]).is_err());
+}
+
+#[cfg(feature = "sfx")]
+#[test]
+fn effects_round_trip() {
+    for sfx in Sfx::ALL {
+        assert_eq!(round_trip(sfx), [sfx as u8]);
+    }
+    round_trip(NoiseSweep {
+        from_hz: 0,
+        to_hz: u32::MAX,
+    });
+    round_trip(Sweep::new(
+        SweepPoint::Pitch(Pitch::MAX),
+        SweepPoint::Hertz(u32::MAX),
+        u16::MAX,
+        SweepCurve::Exponential,
+    ));
+    let mut sweep = Sweep::new(
+        SweepPoint::Period(0),
+        SweepPoint::Period(u16::MAX),
+        0,
+        SweepCurve::LinearPitch,
+    )
+    .repeat(u8::MAX)
+    .with_noise(true)
+    .with_level(0);
+    sweep.trigger();
+    round_trip(sweep);
+}
+
+#[cfg(feature = "music")]
+#[test]
+fn static_tables_are_written_as_their_values() {
+    let mut buffer = [0; 64];
+    let table = Table::<u8>::new(&[0, 15, 255]).looping(u16::MAX);
+    assert_eq!(
+        postcard::to_slice(&table, &mut buffer).unwrap(),
+        [3, 0, 15, 255, 1, 0xFF, 0xFF, 0x03]
+    );
+    // Each table its length, values and loop point, then the tone flag.
+    let bytes = postcard::to_slice(&presets::SNARE, &mut buffer).unwrap();
+    assert_eq!(bytes.len(), 13 + 2 + 5 + 7 + 1);
+}
+
+#[cfg(all(feature = "alloc", feature = "music"))]
+#[test]
+fn instruments_are_read_back_owned() {
+    for instrument in [
+        presets::LEAD,
+        presets::BASS,
+        presets::SNARE,
+        Instrument::DEFAULT,
+    ] {
+        let mut buffer = [0; 64];
+        let bytes = postcard::to_slice(&instrument, &mut buffer).unwrap();
+        let owned: Instrument<Owned> = postcard::from_bytes(bytes).unwrap();
+        assert_eq!(owned, Instrument::from(instrument));
+    }
+    round_trip(Instrument::<Owned> {
+        volume: Table::from_vec(vec![0, 15]).looping(0),
+        release: Table::from_vec(vec![]),
+        pitch: Table::from_vec(vec![i8::MIN, i8::MAX]).looping(u16::MAX),
+        noise: Table::from_vec(vec![31; 200]),
+        tone: false,
+    });
+}
+
+#[cfg(all(feature = "alloc", feature = "sfx"))]
+#[test]
+fn sfx_phases_are_read_back_as_their_parts() {
+    type Parts = (Sweep, bool, Option<NoiseSweep>, Table<u8, Owned>);
+    for sfx in Sfx::ALL {
+        for phase in sfx.phases() {
+            let mut buffer = [0; 64];
+            let bytes = postcard::to_slice(phase, &mut buffer).unwrap();
+            let (sweep, tone, noise, volume): Parts = postcard::from_bytes(bytes).unwrap();
+            assert_eq!(sweep, phase.sweep);
+            assert_eq!(tone, phase.tone);
+            assert_eq!(noise, phase.noise);
+            assert_eq!(volume, Table::from(phase.volume));
+        }
+    }
+}
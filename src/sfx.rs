@@ -0,0 +1,341 @@
+//! Classic game sound effects, built from sweeps, noise and volume tables.
+
+use crate::glissando::div_round;
+use crate::instrument::Table;
+use crate::pitch::{Note, Pitch};
+use crate::psg::Psg;
+use crate::sweep::{Sweep, SweepCurve, SweepPoint};
+use crate::tuning;
+use crate::{Channel, ChannelLevel};
+
+/// A noise burst swept in frequency, linearly in noise period.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoiseSweep {
+    pub from_hz: u32,
+    pub to_hz: u32,
+}
+
+/// One stretch of an effect.
+///
+/// The sweep sets the tone path and the phase's length; its own noise and
+/// level settings are not used. `volume` gives a level per tick (empty meaning
+/// a steady 15), and `tone: false` keeps the sweep silent so only the noise
+/// is heard.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SfxPhase {
+    pub sweep: Sweep,
+    pub tone: bool,
+    pub noise: Option<NoiseSweep>,
+    pub volume: Table<u8>,
+}
+
+impl SfxPhase {
+    const fn tone(sweep: Sweep, volume: &'static [u8]) -> SfxPhase {
+        SfxPhase {
+            sweep,
+            tone: true,
+            noise: None,
+            volume: Table::new(volume),
+        }
+    }
+
+    /// Noise period on tick `step` of the phase.
+    fn noise_period(&self, clock: u32, step: u16) -> Option<u8> {
+        let noise = self.noise?;
+        let from = tuning::noise_period(clock, noise.from_hz) as i32;
+        let to = tuning::noise_period(clock, noise.to_hz) as i32;
+        let last = self.sweep.ticks().saturating_sub(1).max(1) as i32;
+        let step = step.min(last as u16) as i32;
+        Some((from + div_round((to - from) * step, last)) as u8)
+    }
+}
+
+const fn steady(pitch: Pitch, ticks: u16) -> Sweep {
+    Sweep::new(
+        SweepPoint::Pitch(pitch),
+        SweepPoint::Pitch(pitch),
+        ticks,
+        SweepCurve::LinearPeriod,
+    )
+}
+
+const fn rise(from: Pitch, to: Pitch, ticks: u16) -> Sweep {
+    Sweep::new(
+        SweepPoint::Pitch(from),
+        SweepPoint::Pitch(to),
+        ticks,
+        SweepCurve::LinearPitch,
+    )
+}
+
+const COIN: &[SfxPhase] = &[
+    SfxPhase::tone(steady(Pitch::new(Note::B, 5), 4), &[12]),
+    SfxPhase::tone(
+        steady(Pitch::new(Note::E, 6), 12),
+        &[12, 12, 11, 10, 9, 8, 7, 6, 5, 4, 2, 1],
+    ),
+];
+
+const JUMP: &[SfxPhase] = &[SfxPhase::tone(
+    rise(Pitch::new(Note::C, 4), Pitch::new(Note::C, 5), 10),
+    &[13, 13, 12, 12, 11, 10, 9, 8, 6, 4],
+)];
+
+const LASER_SHOT: &[SfxPhase] = &[SfxPhase::tone(
+    Sweep::new(
+        SweepPoint::Hertz(2000),
+        SweepPoint::Hertz(200),
+        12,
+        SweepCurve::Exponential,
+    ),
+    &[15, 14, 13, 12, 11, 10, 9, 8, 6, 4, 2, 1],
+)];
+
+const EXPLOSION: &[SfxPhase] = &[SfxPhase {
+    sweep: steady(Pitch::new(Note::C, 2), 24),
+    tone: false,
+    noise: Some(NoiseSweep {
+        from_hz: 20_000,
+        to_hz: 4_000,
+    }),
+    volume: Table::new(&[
+        15, 15, 15, 14, 14, 13, 13, 12, 12, 11, 10, 10, 9, 8, 8, 7, 6, 5, 5, 4, 3, 2, 1, 1,
+    ]),
+}];
+
+const POWER_UP: &[SfxPhase] = &[
+    SfxPhase::tone(
+        rise(Pitch::new(Note::C, 4), Pitch::new(Note::G, 4), 8),
+        &[12],
+    ),
+    SfxPhase::tone(
+        rise(Pitch::new(Note::E, 4), Pitch::new(Note::B, 4), 8),
+        &[12],
+    ),
+    SfxPhase::tone(
+        rise(Pitch::new(Note::G, 4), Pitch::new(Note::D, 5), 8),
+        &[12, 12, 11, 10, 9, 7, 5, 3],
+    ),
+];
+
+const HIT: &[SfxPhase] = &[SfxPhase {
+    sweep: Sweep::new(
+        SweepPoint::Hertz(400),
+        SweepPoint::Hertz(100),
+        6,
+        SweepCurve::LinearPeriod,
+    ),
+    tone: true,
+    noise: Some(NoiseSweep {
+        from_hz: 10_000,
+        to_hz: 10_000,
+    }),
+    volume: Table::new(&[15, 13, 10, 7, 4, 1]),
+}];
+
+/// The built-in effects. Pitches and frequencies are given in musical or
+/// hertz terms, so every effect sounds the same at any master clock.
+///
+/// Each is numbered by its place here, as the C functions in `ffi` take
+/// them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Sfx {
+    Coin,
+    Jump,
+    LaserShot,
+    Explosion,
+    PowerUp,
+    Hit,
+}
+
+impl Sfx {
+    pub const ALL: [Sfx; 6] = [
+        Sfx::Coin,
+        Sfx::Jump,
+        Sfx::LaserShot,
+        Sfx::Explosion,
+        Sfx::PowerUp,
+        Sfx::Hit,
+    ];
+
+    pub const fn phases(self) -> &'static [SfxPhase] {
+        match self {
+            Sfx::Coin => COIN,
+            Sfx::Jump => JUMP,
+            Sfx::LaserShot => LASER_SHOT,
+            Sfx::Explosion => EXPLOSION,
+            Sfx::PowerUp => POWER_UP,
+            Sfx::Hit => HIT,
+        }
+    }
+}
+
+/// Plays one effect at a time on a chosen channel.
+///
+/// Noise-using effects write the shared noise period, so they affect any
+/// other channel that has noise enabled.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SfxPlayer {
+    playing: Option<(&'static [SfxPhase], Channel)>,
+    phase: usize,
+    elapsed: u16,
+}
+
+impl SfxPlayer {
+    pub const fn new() -> SfxPlayer {
+        SfxPlayer {
+            playing: None,
+            phase: 0,
+            elapsed: 0,
+        }
+    }
+
+    /// Starts `sfx` on `channel`, cutting off anything already playing.
+    pub fn trigger(&mut self, sfx: Sfx, channel: Channel) {
+        self.play_phases(sfx.phases(), channel);
+    }
+
+    /// Starts a custom effect made of `phases`.
+    pub fn play_phases(&mut self, phases: &'static [SfxPhase], channel: Channel) {
+        self.playing = Some((phases, channel));
+        self.phase = 0;
+        self.elapsed = 0;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some()
+    }
+
+    /// Plays the next tick. Returns `true` once the effect has finished, on
+    /// the tick that silences its channel and turns its noise off.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        let Some((phases, channel)) = self.playing else {
+            return Ok(true);
+        };
+        while let Some(phase) = phases.get(self.phase) {
+            if self.elapsed < phase.sweep.ticks() {
+                break;
+            }
+            self.phase += 1;
+            self.elapsed = 0;
+        }
+        let Some(phase) = phases.get(self.phase) else {
+            self.playing = None;
+            psg.update_channel_level(channel, ChannelLevel::Fixed(0))?;
+            psg.set_noise_enabled(channel, false)?;
+            return Ok(true);
+        };
+        let clock = psg.master_clock();
+        let step = self.elapsed;
+        if phase.tone {
+            psg.set_channel_period(channel, phase.sweep.period_at(clock, step))?;
+        }
+        if let Some(period) = phase.noise_period(clock, step) {
+            psg.update_register(0x6, period)?;
+        }
+        psg.set_tone_enabled(channel, phase.tone)?;
+        psg.set_noise_enabled(channel, phase.noise.is_some())?;
+        let level = phase.volume.at(step).unwrap_or(15);
+        psg.update_channel_level(channel, ChannelLevel::Fixed(level.min(15)))?;
+        self.elapsed += 1;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    fn record(sfx: Sfx, clock: u32) -> Vec<(u8, u8)> {
+        let mut psg = FakePsg::with_clock(clock);
+        let mut player = SfxPlayer::new();
+        player.trigger(sfx, Channel::B);
+        while !player.tick(&mut psg).unwrap() {}
+        psg.take_writes()
+    }
+
+    /// FNV-1a over the write log, so a golden value pins every write.
+    fn fingerprint(writes: &[(u8, u8)]) -> u32 {
+        writes.iter().fold(0x811C_9DC5, |hash, &(address, data)| {
+            let hash = (hash ^ address as u32).wrapping_mul(0x0100_0193);
+            (hash ^ data as u32).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    #[test]
+    fn coin_plays_two_notes_then_silences() {
+        let writes = record(Sfx::Coin, 2_000_000);
+        // B5 at level 12 with tone on and noise off.
+        assert_eq!(
+            writes[..4],
+            [(0x2, 0x7F), (0x3, 0x00), (0x7, 0x3D), (0x9, 12)]
+        );
+        // E6 a few ticks later.
+        assert_eq!(writes[4], (0x2, 0x5F));
+        assert_eq!(writes.last(), Some(&(0x9, 0)));
+    }
+
+    #[test]
+    fn presets_are_pinned() {
+        let golden: [(Sfx, usize, u32); 6] = [
+            (Sfx::Coin, 16, 0x4383_2637),
+            (Sfx::Jump, 22, 0x37DB_6A9D),
+            (Sfx::LaserShot, 29, 0xBF20_B503),
+            (Sfx::Explosion, 43, 0x3E61_9EFB),
+            (Sfx::PowerUp, 37, 0x3377_2144),
+            (Sfx::Hit, 21, 0x6E56_93DE),
+        ];
+        for (sfx, count, hash) in golden {
+            let writes = record(sfx, 2_000_000);
+            assert_eq!(
+                (writes.len(), fingerprint(&writes)),
+                (count, hash),
+                "{sfx:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn presets_scale_with_the_master_clock() {
+        // Tone period, noise period and level after each tick.
+        let trace = |sfx: Sfx, clock: u32| -> Vec<(u16, u8, u8)> {
+            let mut psg = FakePsg::with_clock(clock);
+            let mut player = SfxPlayer::new();
+            player.trigger(sfx, Channel::B);
+            let mut trace = Vec::new();
+            while !player.tick(&mut psg).unwrap() {
+                let registers = psg.registers();
+                trace.push((
+                    registers.tone_period(Channel::B),
+                    registers.value(0x6),
+                    registers.value(0x9),
+                ));
+            }
+            trace
+        };
+        for sfx in Sfx::ALL {
+            let fast = trace(sfx, 2_000_000);
+            let slow = trace(sfx, 1_000_000);
+            assert_eq!(fast.len(), slow.len());
+            for (fast, slow) in fast.iter().zip(slow.iter()) {
+                // Within 2%: short periods round coarsely at 1 MHz.
+                let tolerance = (fast.0 as i32 / 50).max(1);
+                assert!(
+                    (fast.0 as i32 - 2 * slow.0 as i32).abs() <= tolerance,
+                    "{sfx:?}"
+                );
+                // The 5-bit noise period is coarser still.
+                assert!((fast.1 as i32 - 2 * slow.1 as i32).abs() <= 2, "{sfx:?}");
+                assert_eq!(fast.2, slow.2, "{sfx:?}");
+            }
+        }
+    }
+}
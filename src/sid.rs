@@ -0,0 +1,210 @@
+//! The Atari ST "SID voice": a channel's level flipped between two values
+//! at an audio rate while its tone keeps playing the note.
+//!
+//! The tone square and the level square beat against each other into
+//! pulse-width-like timbres. The level has to change thousands of times a
+//! second, far faster than a frame tick, so the work is split: you run a
+//! hardware timer and call into [`SidVoice`] from its interrupt, and it
+//! does the arithmetic and the register write. There are two ways to drive
+//! it:
+//!
+//! - a free-running timer at a fixed rate, calling
+//!   [`SidVoice::tick_hi_res`] every interrupt. Edges land on the nearest
+//!   tick, so run the timer well above the SID frequency.
+//! - a one-shot timer reloaded from [`SidVoice::toggle`], which writes one
+//!   edge and says how many timer clocks to wait for the next. Edges are
+//!   exact, with two interrupts per cycle.
+
+use crate::math::{self, PhaseAccumulator};
+use crate::mfp::MfpTimer;
+use crate::psg::Psg;
+use crate::Channel;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SidVoice {
+    channel: Channel,
+    millihertz: u32,
+    duty: u8,
+    high: u8,
+    low: u8,
+    tick_rate: u32,
+    phase: PhaseAccumulator,
+    is_high: bool,
+}
+
+impl SidVoice {
+    /// A 50% duty square at `millihertz` cycles per second between levels
+    /// `high` and `low`.
+    pub const fn new(channel: Channel, millihertz: u32, high: u8, low: u8) -> SidVoice {
+        SidVoice {
+            channel,
+            millihertz,
+            duty: 50,
+            high: high & 0x0F,
+            low: low & 0x0F,
+            tick_rate: 0,
+            phase: PhaseAccumulator::STOPPED,
+            is_high: false,
+        }
+    }
+
+    /// The effect as a YM file describes it: the level flips between
+    /// `volume` and 0 on every interrupt of `timer`, so one cycle takes two
+    /// interrupts.
+    pub const fn from_mfp(channel: Channel, timer: MfpTimer, volume: u8) -> SidVoice {
+        SidVoice::new(channel, timer.millihertz() / 2, volume, 0)
+    }
+
+    /// Share of each cycle spent at the high level, in percent. Clamped to
+    /// 1..=99 so neither half vanishes.
+    pub const fn with_duty(mut self, percent: u8) -> SidVoice {
+        self.duty = if percent < 1 {
+            1
+        } else if percent > 99 {
+            99
+        } else {
+            percent
+        };
+        self
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn millihertz(&self) -> u32 {
+        self.millihertz
+    }
+
+    pub fn set_millihertz(&mut self, millihertz: u32) {
+        self.millihertz = millihertz;
+        self.set_tick_rate(self.tick_rate);
+    }
+
+    pub fn set_duty(&mut self, percent: u8) {
+        *self = self.clone().with_duty(percent);
+    }
+
+    pub fn set_levels(&mut self, high: u8, low: u8) {
+        self.high = high & 0x0F;
+        self.low = low & 0x0F;
+    }
+
+    /// Tells the voice how often [`SidVoice::tick_hi_res`] will be called.
+    pub fn set_tick_rate(&mut self, hertz: u32) {
+        self.tick_rate = hertz;
+        let step = math::q32_ratio(self.millihertz as u64, hertz as u64 * 1000);
+        self.phase.set_step(step);
+    }
+
+    /// Starts the next cycle from its high half.
+    pub fn reset(&mut self) {
+        self.phase.reset();
+        self.is_high = false;
+    }
+
+    fn threshold(&self) -> u32 {
+        ((self.duty as u64) << 32).div_ceil(100) as u32
+    }
+
+    /// Writes the level for this moment and advances by one tick at the
+    /// rate given to [`SidVoice::set_tick_rate`]. Without a rate the level
+    /// stays high. Only edges write to the chip.
+    pub fn tick_hi_res<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let level = if self.phase.phase() < self.threshold() {
+            self.high
+        } else {
+            self.low
+        };
+        self.phase.advance();
+        psg.update_register(self.channel.level_register(), level)?;
+        Ok(())
+    }
+
+    /// Writes the next edge and returns how many clocks of a
+    /// `timer_clock`-hertz timer to wait before calling again. At a
+    /// frequency of 0 the level is left high and 0 is returned: stop the
+    /// timer.
+    pub fn toggle<P: Psg>(&mut self, psg: &mut P, timer_clock: u32) -> Result<u32, P::Error> {
+        self.is_high = !self.is_high || self.millihertz == 0;
+        let level = if self.is_high { self.high } else { self.low };
+        psg.update_register(self.channel.level_register(), level)?;
+        let Some(cycle) = (timer_clock as u64 * 1000).checked_div(self.millihertz as u64) else {
+            return Ok(0);
+        };
+        let high = (cycle * self.duty as u64 + 50) / 100;
+        let clocks = if self.is_high { high } else { cycle - high };
+        Ok(clocks.clamp(1, u32::MAX as u64) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    fn levels(voice: &mut SidVoice, ticks: usize) -> Vec<u8> {
+        let mut psg = FakePsg::new();
+        (0..ticks)
+            .map(|_| {
+                voice.tick_hi_res(&mut psg).unwrap();
+                psg.registers().value(voice.channel().level_register())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tick_hi_res_squares_the_level() {
+        let mut voice = SidVoice::new(Channel::B, 1_000_000, 12, 3);
+        voice.set_tick_rate(8_000);
+        assert_eq!(levels(&mut voice, 10), [12, 12, 12, 12, 3, 3, 3, 3, 12, 12]);
+
+        let mut voice = voice.with_duty(25);
+        voice.reset();
+        assert_eq!(levels(&mut voice, 8), [12, 12, 3, 3, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn only_edges_reach_the_chip() {
+        let mut psg = FakePsg::new();
+        let mut voice = SidVoice::new(Channel::A, 2_000_000, 15, 0);
+        voice.set_tick_rate(16_000);
+        for _ in 0..16 {
+            voice.tick_hi_res(&mut psg).unwrap();
+        }
+        assert_eq!(
+            psg.take_writes(),
+            [(0x8, 15), (0x8, 0), (0x8, 15), (0x8, 0)]
+        );
+    }
+
+    #[test]
+    fn toggle_returns_timer_reloads() {
+        let mut psg = FakePsg::new();
+        // 500 Hz at 30% duty from a 1 MHz timer: 600 clocks high, 1400 low.
+        let mut voice = SidVoice::new(Channel::C, 500_000, 10, 2).with_duty(30);
+        assert_eq!(voice.toggle(&mut psg, 1_000_000), Ok(600));
+        assert_eq!(voice.toggle(&mut psg, 1_000_000), Ok(1400));
+        assert_eq!(voice.toggle(&mut psg, 1_000_000), Ok(600));
+        assert_eq!(psg.take_writes(), [(0xA, 10), (0xA, 2), (0xA, 10)]);
+
+        voice.set_millihertz(0);
+        assert_eq!(voice.toggle(&mut psg, 1_000_000), Ok(0));
+        assert_eq!(voice.toggle(&mut psg, 1_000_000), Ok(0));
+        assert_eq!(psg.registers().value(0xA), 10);
+    }
+
+    #[test]
+    fn ym_timers_flip_on_every_interrupt() {
+        // Two interrupts per cycle: 2457600 / (4 * 96) / 2 = 3200 Hz.
+        let voice = SidVoice::from_mfp(Channel::A, MfpTimer::new(1, 96), 0x1D);
+        assert_eq!(voice.millihertz(), 3_200_000);
+        let mut psg = FakePsg::new();
+        let mut voice = voice;
+        voice.toggle(&mut psg, 1).unwrap();
+        assert_eq!(psg.take_writes(), [(0x8, 0xD)]);
+    }
+}
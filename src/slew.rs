@@ -0,0 +1,95 @@
+//! Levels that move towards a target a step at a time rather than jumping,
+//! for fades that don't click.
+
+/// A fixed level heading for a target at up to `rate` steps a tick.
+///
+/// Each of the chip's levels is about 3 dB below the one above, so a steady
+/// rate in steps is a steady fade in decibels, which is what sounds even.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Slew {
+    level: u8,
+    target: u8,
+    rate: u8,
+}
+
+impl Slew {
+    /// Settled at `level`, moving a step a tick once given a target.
+    pub const fn new(level: u8) -> Slew {
+        Slew {
+            level: level & 0xF,
+            target: level & 0xF,
+            rate: 1,
+        }
+    }
+
+    /// Moves up to `rate` steps a tick, at least one.
+    pub const fn with_rate(mut self, rate: u8) -> Slew {
+        self.rate = if rate == 0 { 1 } else { rate };
+        self
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    pub fn target(&self) -> u8 {
+        self.target
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.level == self.target
+    }
+
+    /// Heads for `target` from wherever the level is now.
+    pub fn set_target(&mut self, target: u8) {
+        self.target = target & 0xF;
+    }
+
+    /// Sets the rate that gets from here to the target in `ticks` ticks,
+    /// or as near as whole steps allow; 0 gets there on the next tick.
+    pub fn reach_in(&mut self, ticks: u8) {
+        let distance = self.level.abs_diff(self.target);
+        self.rate = distance.div_ceil(ticks.max(1)).max(1);
+    }
+
+    /// Moves towards the target and returns the new level.
+    pub fn tick(&mut self) -> u8 {
+        self.level = if self.level < self.target {
+            self.target.min(self.level + self.rate)
+        } else {
+            self.target.max(self.level.saturating_sub(self.rate))
+        };
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn moves_at_the_rate_and_stops_at_the_target() {
+        let mut slew = Slew::new(13).with_rate(4);
+        assert!(slew.is_settled());
+        slew.set_target(0);
+        let levels: Vec<u8> = (0..5).map(|_| slew.tick()).collect();
+        assert_eq!(levels, [9, 5, 1, 0, 0]);
+
+        // Turned round part way, and timed rather than rated.
+        let mut slew = Slew::new(15);
+        slew.set_target(0);
+        slew.tick();
+        slew.set_target(12);
+        slew.reach_in(3);
+        let levels: Vec<u8> = (0..4).map(|_| slew.tick()).collect();
+        assert_eq!(levels, [13, 12, 12, 12]);
+        slew.set_target(0);
+        slew.reach_in(4);
+        let levels: Vec<u8> = (0..4).map(|_| slew.tick()).collect();
+        assert_eq!(levels, [9, 6, 3, 0]);
+        assert!(slew.is_settled());
+    }
+}
@@ -0,0 +1,503 @@
+//! Standard MIDI File playback straight from flash.
+//!
+//! A file is a `MThd` header chunk followed by `MTrk` track chunks. Each track
+//! is a run of events, each preceded by a variable-length delta time in the
+//! header's ticks per quarter note. Besides channel messages, with running
+//! status, tracks hold meta events (`0xFF`, of which only set tempo and end
+//! of track matter here) and system exclusive ones (`0xF0`, `0xF7`), which
+//! are skipped.
+
+use crate::midi::{self, MidiEvent};
+use crate::voices::{VoiceAllocator, VoiceEngine};
+
+/// The most tracks a type-1 file may have.
+pub const MAX_TRACKS: usize = 8;
+
+/// Microseconds per quarter note until a tempo event says otherwise: 120 BPM.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+const META: u8 = 0xFF;
+const SET_TEMPO: u8 = 0x51;
+const END_OF_TRACK: u8 = 0x2F;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SmfError {
+    /// There is no `MThd` header.
+    NotSmf,
+    /// A chunk or an event runs past the end of the data.
+    Truncated,
+    /// A data byte with no running status, or a header that doesn't add up.
+    Malformed,
+    /// Type 2 files hold unrelated sequences, which have no single playback
+    /// order.
+    UnsupportedFormat(u16),
+    /// Timing in SMPTE frames rather than ticks per quarter note.
+    SmpteTiming,
+    /// More tracks than [`MAX_TRACKS`] to merge.
+    TooManyTracks(u16),
+}
+
+/// What a track has next.
+enum Item {
+    Event(MidiEvent),
+    Tempo(u32),
+    EndOfTrack,
+    Skipped,
+}
+
+fn byte(data: &[u8], position: &mut usize, end: usize) -> Result<u8, SmfError> {
+    if *position >= end {
+        return Err(SmfError::Truncated);
+    }
+    *position += 1;
+    Ok(data[*position - 1])
+}
+
+/// A variable-length quantity: seven bits a byte, most significant first,
+/// the top bit set on all but the last of at most four bytes.
+fn varlen(data: &[u8], position: &mut usize, end: usize) -> Result<u32, SmfError> {
+    let mut value = 0;
+    for _ in 0..4 {
+        let byte = byte(data, position, end)?;
+        value = value << 7 | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(SmfError::Malformed)
+}
+
+fn skip(position: &mut usize, end: usize, length: u32) -> Result<(), SmfError> {
+    let length = length as usize;
+    if end - *position < length {
+        return Err(SmfError::Truncated);
+    }
+    *position += length;
+    Ok(())
+}
+
+/// Reads the event at `position`, just after its delta time.
+fn read_event(
+    data: &[u8],
+    position: &mut usize,
+    end: usize,
+    status: &mut Option<u8>,
+) -> Result<Item, SmfError> {
+    let first = byte(data, position, end)?;
+    match first {
+        META => {
+            let kind = byte(data, position, end)?;
+            let length = varlen(data, position, end)?;
+            let start = *position;
+            skip(position, end, length)?;
+            Ok(match (kind, length) {
+                (SET_TEMPO, 3) => Item::Tempo(
+                    (data[start] as u32) << 16
+                        | (data[start + 1] as u32) << 8
+                        | data[start + 2] as u32,
+                ),
+                (END_OF_TRACK, _) => Item::EndOfTrack,
+                _ => Item::Skipped,
+            })
+        }
+        0xF0 | 0xF7 => {
+            *status = None;
+            let length = varlen(data, position, end)?;
+            skip(position, end, length)?;
+            Ok(Item::Skipped)
+        }
+        0xF1..=0xFE => Err(SmfError::Malformed),
+        _ => {
+            let (status, data_byte) = if first >= 0x80 {
+                *status = Some(first);
+                (first, byte(data, position, end)?)
+            } else {
+                (status.ok_or(SmfError::Malformed)?, first)
+            };
+            let second = match midi::data_length(status) {
+                1 => 0,
+                _ => byte(data, position, end)?,
+            };
+            if data_byte >= 0x80 || second >= 0x80 {
+                return Err(SmfError::Malformed);
+            }
+            Ok(Item::Event(MidiEvent {
+                channel: status & 0x0F,
+                message: midi::decode(status, data_byte, second),
+            }))
+        }
+    }
+}
+
+/// Where one track has got to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Track {
+    start: usize,
+    end: usize,
+    position: usize,
+    status: Option<u8>,
+    /// When the next event is due, in file ticks, or `None` once the track
+    /// has ended.
+    next_at: Option<u32>,
+}
+
+impl Track {
+    const EMPTY: Track = Track {
+        start: 0,
+        end: 0,
+        position: 0,
+        status: None,
+        next_at: None,
+    };
+
+    fn rewind(&mut self, data: &[u8]) {
+        self.position = self.start;
+        self.status = None;
+        self.next_at = Some(0);
+        self.read_delta(data, 0);
+    }
+
+    /// Reads the delta time before the next event. The file was checked up
+    /// front, so running out here can only mean the track ended without an
+    /// end-of-track event.
+    fn read_delta(&mut self, data: &[u8], now: u32) {
+        self.next_at = varlen(data, &mut self.position, self.end)
+            .ok()
+            .map(|delta| now.saturating_add(delta));
+    }
+}
+
+/// Plays a type-0 or type-1 Standard MIDI File, ticked at a fixed rate.
+///
+/// The file is checked when the player is made, then read in place as it
+/// plays: the player holds only a position in each track, and type-1 tracks
+/// are merged as they go. Timing is counted exactly in integers, so
+/// delta times and tempo changes that don't fall on a tick are carried over
+/// rather than drifting; each event fires on the tick whose span it falls
+/// in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmfPlayer {
+    data: &'static [u8],
+    tracks: [Track; MAX_TRACKS],
+    track_count: usize,
+    division: u32,
+    tempo: u32,
+    tick_rate: u32,
+    looping: bool,
+    now: u32,
+    credit: u64,
+    finished: bool,
+}
+
+impl SmfPlayer {
+    /// Checks `data` and readies it to play once, ticked at 50 Hz.
+    pub fn new(data: &'static [u8]) -> Result<SmfPlayer, SmfError> {
+        if data.get(..4) != Some(b"MThd") {
+            return Err(SmfError::NotSmf);
+        }
+        let mut position = 4;
+        let header_length = u32_be(data, &mut position)? as usize;
+        if header_length < 6 || data.len() - position < header_length {
+            return Err(SmfError::Malformed);
+        }
+        let format = u16::from_be_bytes([data[8], data[9]]);
+        let count = u16::from_be_bytes([data[10], data[11]]);
+        let division = u16::from_be_bytes([data[12], data[13]]);
+        position += header_length;
+        match format {
+            0 if count != 1 => return Err(SmfError::Malformed),
+            0 | 1 => {}
+            _ => return Err(SmfError::UnsupportedFormat(format)),
+        }
+        if division & 0x8000 != 0 {
+            return Err(SmfError::SmpteTiming);
+        }
+        if division == 0 || count == 0 {
+            return Err(SmfError::Malformed);
+        }
+        if count as usize > MAX_TRACKS {
+            return Err(SmfError::TooManyTracks(count));
+        }
+
+        let mut tracks = [Track::EMPTY; MAX_TRACKS];
+        let mut track_count = 0;
+        while track_count < count as usize {
+            let id = data
+                .get(position..position + 4)
+                .ok_or(SmfError::Truncated)?;
+            position += 4;
+            let length = u32_be(data, &mut position)?;
+            let start = position;
+            skip(&mut position, data.len(), length)?;
+            // Unknown chunks are allowed, and skipped.
+            if id == b"MTrk" {
+                check_track(data, start, position)?;
+                tracks[track_count] = Track {
+                    start,
+                    end: position,
+                    ..Track::EMPTY
+                };
+                track_count += 1;
+            }
+        }
+
+        let mut player = SmfPlayer {
+            data,
+            tracks,
+            track_count,
+            division: division as u32,
+            tempo: DEFAULT_TEMPO,
+            tick_rate: 50,
+            looping: false,
+            now: 0,
+            credit: 0,
+            finished: false,
+        };
+        player.rewind();
+        Ok(player)
+    }
+
+    /// How many times a second [`SmfPlayer::advance`] will be called.
+    pub fn with_tick_rate(mut self, hertz: u32) -> SmfPlayer {
+        self.tick_rate = hertz.max(1);
+        self
+    }
+
+    /// Starts again from the top after the last track ends.
+    pub fn looping(mut self) -> SmfPlayer {
+        self.looping = true;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Goes back to the start, at the default tempo.
+    pub fn rewind(&mut self) {
+        for track in &mut self.tracks[..self.track_count] {
+            track.rewind(self.data);
+        }
+        self.tempo = DEFAULT_TEMPO;
+        self.now = 0;
+        self.credit = 0;
+        self.finished = false;
+    }
+
+    /// Moves on by one tick, passing every event due in it to `on_event` in
+    /// file order. Returns `true` once the file has finished, on every tick
+    /// from the one it ends in; a looping file never finishes.
+    pub fn advance(&mut self, mut on_event: impl FnMut(MidiEvent)) -> bool {
+        if self.finished {
+            return true;
+        }
+        // A file tick lasts tempo / division microseconds and a player tick
+        // 1_000_000 / tick_rate; both are counted here scaled up by
+        // division * tick_rate, so every length is a whole number.
+        self.credit += 1_000_000 * self.division as u64;
+        let mut rewound = false;
+        loop {
+            let Some(due) = self.next_due() else {
+                if !self.looping {
+                    self.finished = true;
+                    return true;
+                }
+                // Whatever time is left over runs on into the repeat, but a
+                // file with no length only plays once a tick.
+                if rewound {
+                    return false;
+                }
+                let credit = self.credit;
+                self.rewind();
+                self.credit = credit;
+                rewound = true;
+                continue;
+            };
+            if due <= self.now {
+                self.dispatch(&mut on_event);
+                continue;
+            }
+            // Only what lies strictly inside this tick's span is reached, so
+            // an event exactly on the next tick waits for it.
+            let cost = self.tempo as u64 * self.tick_rate as u64;
+            let ticks = ((due - self.now) as u64).min((self.credit - 1) / cost);
+            self.now += ticks as u32;
+            self.credit -= ticks * cost;
+            if self.now < due {
+                return false;
+            }
+        }
+    }
+
+    /// Plays the events due this tick on `voices`, as [`midi::apply`] does.
+    pub fn tick<E: VoiceEngine + Clone>(&mut self, voices: &mut VoiceAllocator<E>) -> bool {
+        let finished = self.advance(|event| midi::apply(event, voices));
+        if finished && !self.looping {
+            voices.all_notes_off();
+        }
+        finished
+    }
+
+    fn next_due(&self) -> Option<u32> {
+        self.tracks[..self.track_count]
+            .iter()
+            .filter_map(|track| track.next_at)
+            .min()
+    }
+
+    /// Runs the next event of the first track with one due now.
+    fn dispatch(&mut self, on_event: &mut impl FnMut(MidiEvent)) {
+        let now = self.now;
+        let data = self.data;
+        let Some(track) = self.tracks[..self.track_count]
+            .iter_mut()
+            .find(|track| track.next_at == Some(now))
+        else {
+            return;
+        };
+        match read_event(data, &mut track.position, track.end, &mut track.status) {
+            Ok(Item::Event(event)) => on_event(event),
+            Ok(Item::Tempo(tempo)) => self.tempo = tempo.max(1),
+            Ok(Item::Skipped) => {}
+            Ok(Item::EndOfTrack) | Err(_) => {
+                track.next_at = None;
+                return;
+            }
+        }
+        track.read_delta(data, now);
+    }
+}
+
+fn u32_be(data: &[u8], position: &mut usize) -> Result<u32, SmfError> {
+    let bytes = data
+        .get(*position..*position + 4)
+        .ok_or(SmfError::Truncated)?;
+    *position += 4;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads a whole track through once, so playback can't fail part way.
+fn check_track(data: &[u8], start: usize, end: usize) -> Result<(), SmfError> {
+    let mut position = start;
+    let mut status = None;
+    while position < end {
+        varlen(data, &mut position, end)?;
+        if let Item::EndOfTrack = read_event(data, &mut position, end, &mut status)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::midi::MidiMessage;
+    use std::vec::Vec;
+
+    /// C4 then an E4/G4 chord, an eighth note each at 96 ticks a quarter,
+    /// with running status and a note-on at velocity 0 for the chord's E.
+    #[rustfmt::skip]
+    static JINGLE: [u8; 54] = [
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 32,
+        0x00, 0xFF, 0x03, 0x02, b'h', b'i',   // track name
+        0x00, 0x90, 60, 100,
+        0x30, 0x80, 60, 0,
+        0x00, 0x90, 64, 90,
+        0x00, 67, 90,
+        0x30, 64, 0,
+        0x00, 0x80, 67, 0,
+        0x00, 0xFF, 0x2F, 0x00,
+    ];
+
+    /// A tempo track setting 240 BPM, and a note track with no end-of-track
+    /// event, as type 1.
+    #[rustfmt::skip]
+    static TWO_TRACKS: [u8; 48] = [
+        b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 96,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 11,
+        0x00, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90,   // 250,000 us a quarter
+        0x00, 0xFF, 0x2F, 0x00,
+        b'M', b'T', b'r', b'k', 0, 0, 0, 7,
+        0x60, 0x90, 72, 64,
+        0x60, 72, 0,
+    ];
+
+    fn on(key: u8, velocity: u8) -> MidiMessage {
+        MidiMessage::NoteOn { key, velocity }
+    }
+
+    fn off(key: u8) -> MidiMessage {
+        MidiMessage::NoteOff { key, velocity: 0 }
+    }
+
+    /// `(tick, message)` for every event, until the file ends or `limit`.
+    fn trace(player: &mut SmfPlayer, limit: u32) -> Vec<(u32, MidiMessage)> {
+        let mut events = Vec::new();
+        for tick in 0..limit {
+            if player.advance(|event| events.push((tick, event.message))) {
+                break;
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn type_0_decodes_with_running_status() {
+        // An eighth at 120 BPM is a quarter of a second: 12.5 ticks at 50 Hz.
+        let mut player = SmfPlayer::new(&JINGLE).unwrap();
+        assert_eq!(
+            trace(&mut player, 100),
+            [
+                (0, on(60, 100)),
+                (12, off(60)),
+                (12, on(64, 90)),
+                (12, on(67, 90)),
+                (25, off(64)),
+                (25, off(67)),
+            ]
+        );
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn type_1_tracks_merge_and_tempo_applies() {
+        let mut player = SmfPlayer::new(&TWO_TRACKS).unwrap().with_tick_rate(100);
+        // A quarter at 240 BPM is 25 ticks at 100 Hz.
+        assert_eq!(trace(&mut player, 100), [(25, on(72, 64)), (50, off(72))]);
+    }
+
+    #[test]
+    fn loops_without_drifting() {
+        let mut player = SmfPlayer::new(&JINGLE).unwrap().looping();
+        let starts: Vec<u32> = trace(&mut player, 130)
+            .into_iter()
+            .filter(|&(_, message)| message == on(60, 100))
+            .map(|(tick, _)| tick)
+            .collect();
+        // 25 ticks a time through, exactly.
+        assert_eq!(starts, [0, 25, 50, 75, 100, 125]);
+        assert!(!player.is_finished());
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        static FORMAT_2: [u8; 14] = [b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 2, 0, 1, 0, 96];
+        assert_eq!(SmfPlayer::new(&[]), Err(SmfError::NotSmf));
+        assert_eq!(
+            SmfPlayer::new(&FORMAT_2),
+            Err(SmfError::UnsupportedFormat(2))
+        );
+        assert_eq!(SmfPlayer::new(&JINGLE[..40]), Err(SmfError::Truncated));
+        static SMPTE: [u8; 14] = [b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0xE7, 40];
+        assert_eq!(SmfPlayer::new(&SMPTE), Err(SmfError::SmpteTiming));
+        static NO_STATUS: [u8; 26] = [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 96, b'M', b'T', b'r', b'k', 0, 0, 0,
+            4, 0x00, 60, 100, 0,
+        ];
+        assert_eq!(SmfPlayer::new(&NO_STATUS), Err(SmfError::Malformed));
+    }
+}
@@ -0,0 +1,226 @@
+//! What the chip is playing, read back from the shadow registers, for
+//! displays that show each channel's note and level.
+//!
+//! [`Psg`](crate::Psg)'s `channel_state`, with the `music` feature,
+//! `noise_state` and `envelope_state` build these from the registers and
+//! the master clock alone, without touching the bus. Anything resting on a
+//! register never written is `None`.
+
+#[cfg(feature = "music")]
+use crate::pitch::Pitch;
+use crate::registers::Registers;
+use crate::tuning;
+use crate::EnvelopeShape;
+#[cfg(feature = "music")]
+use crate::{Channel, ChannelLevel};
+
+/// One channel's tone, level and mixer settings.
+#[cfg(feature = "music")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelState {
+    /// The 12-bit tone period, once both its registers are written.
+    pub period: Option<u16>,
+    /// The equal-tempered note nearest the period.
+    pub approx_pitch: Option<Pitch>,
+    /// How many cents sharp of `approx_pitch` the period is, -50 to 50; 0
+    /// without a pitch.
+    pub cents_off: i8,
+    pub level: Option<ChannelLevel>,
+    /// Whether the mixer has the tone on, whatever the level.
+    pub tone_enabled: Option<bool>,
+    pub noise_enabled: Option<bool>,
+}
+
+#[cfg(feature = "music")]
+impl ChannelState {
+    /// `channel` as `registers` have it on a chip clocked at `clock`.
+    pub fn new(registers: &Registers, clock: u32, channel: Channel) -> ChannelState {
+        let (fine, rough) = channel.period_registers();
+        let period = (registers.is_written(fine) && registers.is_written(rough))
+            .then(|| registers.tone_period(channel));
+        let pitch = period.and_then(|period| tuning::nearest_pitch(clock, period));
+        let mixer = registers.get(0x7);
+        let bit = 1 << channel.index();
+        ChannelState {
+            period,
+            approx_pitch: pitch.map(|(pitch, _)| pitch),
+            cents_off: pitch.map_or(0, |(_, cents)| cents),
+            level: registers.get(channel.level_register()).map(level),
+            tone_enabled: mixer.map(|mixer| mixer & bit == 0),
+            noise_enabled: mixer.map(|mixer| mixer & bit << 3 == 0),
+        }
+    }
+}
+
+/// The noise generator's period and rate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NoiseState {
+    pub period: Option<u8>,
+    /// How often it picks a new value.
+    pub hertz: Option<u32>,
+}
+
+impl NoiseState {
+    pub fn new(registers: &Registers, clock: u32) -> NoiseState {
+        let period = registers.get(0x6);
+        NoiseState {
+            period,
+            hertz: period.map(|period| tuning::noise_hertz(clock, period)),
+        }
+    }
+}
+
+/// The envelope's period, how often its ramps play, and its shape.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EnvelopeState {
+    /// The 16-bit period, once both its registers are written.
+    pub period: Option<u16>,
+    /// Ramps a second, in millihertz.
+    pub millihertz: Option<u32>,
+    /// The shape last written to R13, which last restarted it.
+    pub shape: Option<EnvelopeShape>,
+}
+
+impl EnvelopeState {
+    pub fn new(registers: &Registers, clock: u32) -> EnvelopeState {
+        let period = match (registers.get(0xB), registers.get(0xC)) {
+            (Some(fine), Some(rough)) => Some(fine as u16 | (rough as u16) << 8),
+            _ => None,
+        };
+        EnvelopeState {
+            period,
+            millihertz: period.map(|period| tuning::envelope_millihertz(clock, period)),
+            shape: registers.get(0xD).map(EnvelopeShape::from_bits_truncate),
+        }
+    }
+}
+
+/// The level a level register's `value` sets.
+#[cfg(feature = "music")]
+fn level(value: u8) -> ChannelLevel {
+    match value & 0x10 {
+        0 => ChannelLevel::Fixed(value & 0xF),
+        _ => ChannelLevel::Envelope,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::Psg;
+
+    #[cfg(feature = "music")]
+    use crate::pitch::Note;
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn pitches_are_found_back_from_periods() {
+        let nearest = |clock, period| tuning::nearest_pitch(clock, period);
+        let a4 = Pitch::new(Note::A, 4);
+        assert_eq!(nearest(2_000_000, 284), Some((a4, 1)));
+        assert_eq!(nearest(1_000_000, 142), Some((a4, 1)));
+        // 2 MHz / (16 * 270) is 463 Hz, 11.9 cents flat of A#4's 466.2, and
+        // cents are counted to the one at or above.
+        let a_sharp_4 = Pitch::new(Note::ASharp, 4);
+        assert_eq!(nearest(2_000_000, 270), Some((a_sharp_4, -11)));
+        assert_eq!(nearest(1_000_000, 135), Some((a_sharp_4, -11)));
+        // The ZX Spectrum 128's clock: 1.7734 MHz / (16 * 252) is A4 to a
+        // cent, and period 240 is 16 cents flat of A#4.
+        assert_eq!(nearest(1_773_400, 252), Some((a4, 0)));
+        assert_eq!(nearest(1_773_400, 240), Some((a_sharp_4, -16)));
+        // Past G9, and at the bottom of the tone range of a fast clock.
+        assert_eq!(nearest(2_000_000, 1), None);
+        let (pitch, _) = nearest(4_000_000, 0xFFF).unwrap();
+        assert_eq!(pitch, Pitch::new(Note::B, 1));
+        // Every note comes back as itself at either clock.
+        for clock in [1_000_000, 2_000_000] {
+            for midi in 24..=100 {
+                let pitch = Pitch::from_midi(midi).unwrap();
+                let period = tuning::pitch_period(clock, pitch) as u16;
+                let (found, cents) = nearest(clock, period).unwrap();
+                assert_eq!(found, pitch, "{clock} Hz, {period}");
+                assert!(cents.abs() <= 30, "{clock} Hz, {period}: {cents}");
+            }
+        }
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn channels_read_back_what_was_written() {
+        let mut psg = FakePsg::new();
+        let unknown = ChannelState {
+            period: None,
+            approx_pitch: None,
+            cents_off: 0,
+            level: None,
+            tone_enabled: None,
+            noise_enabled: None,
+        };
+        assert_eq!(psg.channel_state(Channel::A), unknown);
+        psg.set_register_value(0x0, 0x1C).unwrap();
+        assert_eq!(psg.channel_state(Channel::A), unknown);
+        psg.set_register_value(0x1, 0x01).unwrap();
+        psg.set_noise_enabled(Channel::A, true).unwrap();
+        psg.update_channel_level(Channel::A, ChannelLevel::Envelope)
+            .unwrap();
+        assert_eq!(
+            psg.channel_state(Channel::A),
+            ChannelState {
+                period: Some(284),
+                approx_pitch: Some(Pitch::new(Note::A, 4)),
+                cents_off: 1,
+                level: Some(ChannelLevel::Envelope),
+                tone_enabled: Some(false),
+                noise_enabled: Some(true),
+            }
+        );
+        // The same period an octave down at half the clock.
+        let mut psg = FakePsg::with_clock(1_000_000);
+        psg.set_channel_period(Channel::B, 284).unwrap();
+        let state = psg.channel_state(Channel::B);
+        assert_eq!(state.approx_pitch, Some(Pitch::new(Note::A, 3)));
+        assert_eq!((state.level, state.tone_enabled), (None, None));
+    }
+
+    #[test]
+    fn noise_and_envelope_read_back_what_was_written() {
+        let mut psg = FakePsg::new();
+        assert_eq!(
+            psg.noise_state(),
+            NoiseState {
+                period: None,
+                hertz: None
+            }
+        );
+        assert_eq!(
+            psg.envelope_state(),
+            EnvelopeState {
+                period: None,
+                millihertz: None,
+                shape: None,
+            }
+        );
+        psg.set_register_value(0x6, 25).unwrap();
+        psg.set_register_value(0xB, 0xF4).unwrap();
+        assert_eq!(
+            psg.noise_state(),
+            NoiseState {
+                period: Some(25),
+                hertz: Some(5000)
+            }
+        );
+        assert_eq!(psg.envelope_state().period, None);
+        psg.set_register_value(0xC, 0x01).unwrap();
+        psg.set_register_value(0xD, 0x0E).unwrap();
+        // 2 MHz / (256 * 500): 15.625 ramps a second.
+        assert_eq!(
+            psg.envelope_state(),
+            EnvelopeState {
+                period: Some(500),
+                millihertz: Some(15_625),
+                shape: Some(EnvelopeShape::from_bits_truncate(0x0E)),
+            }
+        );
+    }
+}
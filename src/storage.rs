@@ -0,0 +1,44 @@
+//! Where music data lives.
+//!
+//! [`Table`](crate::instrument::Table)s,
+//! [`Instrument`](crate::instrument::Instrument)s, `sequencer::Pattern`s
+//! and `sequencer::Song`s take a [`Storage`] parameter saying what holds
+//! their lists. It defaults to [`Static`], `&'static` slices that can sit
+//! in flash and cost nothing to hold; with the `alloc` feature, `Owned`
+//! keeps them in `Vec`s instead, for music built or downloaded at run time.
+//! `From` turns the first kind into the second, and `leak` the second into
+//! the first.
+//!
+//! Parsed formats such as AYFX banks, YM files and frame buffers read
+//! bytes in place for any lifetime, so bytes in a `Vec` work with them as
+//! they are.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// What holds a list of `T`.
+pub trait Storage: 'static {
+    type Slice<T: 'static>: AsRef<[T]>;
+    /// What holds a single `T` a player keeps hold of.
+    type Ref<T: 'static>: core::borrow::Borrow<T>;
+}
+
+/// `&'static` slices, as const items and statics give.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Static {}
+
+impl Storage for Static {
+    type Slice<T: 'static> = &'static [T];
+    type Ref<T: 'static> = &'static T;
+}
+
+/// `Vec`s, and values held outright.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Owned {}
+
+#[cfg(feature = "alloc")]
+impl Storage for Owned {
+    type Slice<T: 'static> = Vec<T>;
+    type Ref<T: 'static> = T;
+}
@@ -0,0 +1,238 @@
+//! Frequency sweeps: the laser, zap and slide-whistle sound effect primitive.
+
+use crate::glissando::div_round;
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{self, MAX_TONE_PERIOD};
+use crate::{Channel, ChannelLevel};
+
+/// One end of a sweep.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SweepPoint {
+    /// Folded into range at the chip's clock like any other note.
+    Pitch(Pitch),
+    /// A frequency, so effects sound the same whatever the master clock.
+    Hertz(u32),
+    /// A raw tone period, for effects tuned by ear on one machine.
+    Period(u16),
+}
+
+impl SweepPoint {
+    pub fn period(self, clock: u32) -> u16 {
+        let period = match self {
+            SweepPoint::Pitch(pitch) => return tuning::fold_pitch_period(clock, pitch).period,
+            SweepPoint::Hertz(hz) => tuning::millihertz_to_period(clock, hz.saturating_mul(1000)),
+            SweepPoint::Period(period) => period as u32,
+        };
+        period.clamp(1, MAX_TONE_PERIOD as u32) as u16
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SweepCurve {
+    /// Equal steps of tone period. Sweeps up in pitch rush at the end.
+    LinearPeriod,
+    /// Equal steps in cents, the same musical speed all the way.
+    LinearPitch,
+    /// Pitch moves fast at first and settles into the end point, each tick
+    /// covering a quarter less than the one before.
+    Exponential,
+}
+
+/// Sweeps a channel's tone from one point to another over `ticks` ticks, at
+/// a fixed level, optionally with noise mixed in.
+///
+/// [`Sweep::trigger`] starts it; each [`Sweep::tick`] then sounds one step,
+/// the first sounding `from` and the last `to`. After the last repeat the
+/// channel is silenced and the sweep reports that it's done.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sweep {
+    from: SweepPoint,
+    to: SweepPoint,
+    ticks: u16,
+    curve: SweepCurve,
+    repeats: u8,
+    noise: bool,
+    level: u8,
+    elapsed: u16,
+    remaining: u8,
+    active: bool,
+}
+
+impl Sweep {
+    pub const fn new(from: SweepPoint, to: SweepPoint, ticks: u16, curve: SweepCurve) -> Sweep {
+        Sweep {
+            from,
+            to,
+            ticks,
+            curve,
+            repeats: 0,
+            noise: false,
+            level: 15,
+            elapsed: 0,
+            remaining: 0,
+            active: false,
+        }
+    }
+
+    /// Plays the sweep `repeats` more times after the first.
+    pub const fn repeat(mut self, repeats: u8) -> Sweep {
+        self.repeats = repeats;
+        self
+    }
+
+    pub const fn with_noise(mut self, noise: bool) -> Sweep {
+        self.noise = noise;
+        self
+    }
+
+    /// Clamped to 15. Defaults to 15.
+    pub const fn with_level(mut self, level: u8) -> Sweep {
+        self.level = if level > 15 { 15 } else { level };
+        self
+    }
+
+    /// Starts (or restarts) the sweep from the beginning.
+    pub fn trigger(&mut self) {
+        self.elapsed = 0;
+        self.remaining = self.repeats;
+        self.active = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Length of one pass.
+    pub const fn ticks(&self) -> u16 {
+        self.ticks
+    }
+
+    /// Sounds the next step on `channel`. Returns `true` once the sweep has
+    /// finished, which is also the tick that silences the channel.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P, channel: Channel) -> Result<bool, P::Error> {
+        if !self.active {
+            return Ok(true);
+        }
+        if self.elapsed >= self.ticks.max(1) {
+            if self.remaining == 0 {
+                self.active = false;
+                psg.update_channel_level(channel, ChannelLevel::Fixed(0))?;
+                return Ok(true);
+            }
+            self.remaining -= 1;
+            self.elapsed = 0;
+        }
+        if self.elapsed == 0 {
+            psg.set_tone_enabled(channel, true)?;
+            psg.set_noise_enabled(channel, self.noise)?;
+            psg.update_channel_level(channel, ChannelLevel::Fixed(self.level))?;
+        }
+        let period = self.period_at(psg.master_clock(), self.elapsed);
+        psg.set_channel_period(channel, period)?;
+        self.elapsed += 1;
+        Ok(false)
+    }
+
+    /// Tone period sounded on tick `step` of one pass.
+    pub fn period_at(&self, clock: u32, step: u16) -> u16 {
+        let from = self.from.period(clock);
+        let to = self.to.period(clock);
+        let last = self.ticks.saturating_sub(1) as i32;
+        if last == 0 {
+            return from;
+        }
+        let step = step.min(last as u16) as i32;
+        let fraction = match self.curve {
+            SweepCurve::LinearPeriod => {
+                let span = to as i32 - from as i32;
+                return (from as i32 + div_round(span * step, last)) as u16;
+            }
+            SweepCurve::LinearPitch => div_round(step << 16, last),
+            SweepCurve::Exponential => {
+                let covered = 65536 - decay_q16(step as u16);
+                let total = 65536 - decay_q16(last as u16);
+                ((covered as i64 * 65536 + total as i64 / 2) / total as i64) as i32
+            }
+        };
+        let cents = tuning::period_cents(from, to);
+        let cents = ((cents as i64 * fraction as i64 + (1 << 15)) >> 16) as i32;
+        tuning::detune_period(from as u32, cents).clamp(1, MAX_TONE_PERIOD as u32) as u16
+    }
+}
+
+/// `(3/4)^steps` in Q16, reaching 0 after a few dozen steps.
+fn decay_q16(steps: u16) -> i32 {
+    let mut value = 65536;
+    for _ in 0..steps {
+        value = value * 3 / 4;
+        if value == 0 {
+            break;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    const CLOCK: u32 = 2_000_000;
+
+    fn periods(curve: SweepCurve) -> Vec<u16> {
+        let sweep = Sweep::new(SweepPoint::Period(1000), SweepPoint::Period(500), 5, curve);
+        (0..5).map(|step| sweep.period_at(CLOCK, step)).collect()
+    }
+
+    #[test]
+    fn curves() {
+        assert_eq!(
+            periods(SweepCurve::LinearPeriod),
+            [1000, 875, 750, 625, 500]
+        );
+        // 0, 300, 600, 900 and 1200 cents up.
+        assert_eq!(periods(SweepCurve::LinearPitch), [1000, 841, 707, 595, 500]);
+        assert_eq!(periods(SweepCurve::Exponential), [1000, 776, 642, 556, 500]);
+    }
+
+    #[test]
+    fn points_scale_with_the_clock() {
+        assert_eq!(SweepPoint::Hertz(440).period(CLOCK), 284);
+        assert_eq!(SweepPoint::Hertz(440).period(1_000_000), 142);
+        assert_eq!(SweepPoint::Period(0x2000).period(CLOCK), MAX_TONE_PERIOD);
+    }
+
+    #[test]
+    fn repeats_then_silences() {
+        let mut psg = FakePsg::new();
+        let mut sweep = Sweep::new(
+            SweepPoint::Period(300),
+            SweepPoint::Period(200),
+            2,
+            SweepCurve::LinearPeriod,
+        )
+        .repeat(1)
+        .with_noise(true)
+        .with_level(12);
+        sweep.trigger();
+        let mut log = Vec::new();
+        loop {
+            let done = sweep.tick(&mut psg, Channel::A).unwrap();
+            log.push(psg.registers().tone_period(Channel::A));
+            if done {
+                break;
+            }
+        }
+        assert_eq!(log, [300, 200, 300, 200, 200]);
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(psg.registers().mixer() & 0b1001, 0);
+        assert!(!sweep.is_active());
+    }
+}
@@ -0,0 +1,230 @@
+//! The Atari ST "sync buzzer": the hardware envelope retriggered at an
+//! audio rate so that it becomes the oscillator.
+//!
+//! Every write to the envelope shape register (R13) restarts the envelope,
+//! even when the value doesn't change. Rewriting it from a timer interrupt
+//! at the note's frequency chops the envelope into a periodic wave: the
+//! retrigger rate sets the pitch, and the envelope period sets how much of
+//! the ramp is heard before the restart, and so the timbre. As with the
+//! [SID voice](crate::sid), the crate works out the numbers and makes the
+//! writes; the timer is yours. Call [`SyncBuzzer::on_timer`] from an
+//! interrupt firing every [`SyncBuzzer::timer_reload`] clocks.
+//!
+//! The envelope is shared by all three channels, so anything else playing
+//! the envelope while the buzzer runs gets buzzed too.
+
+use crate::mfp::MfpTimer;
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning;
+use crate::{Channel, ChannelLevel, EnvelopeShape};
+
+const ENVELOPE_FINE: u8 = 0xB;
+const ENVELOPE_ROUGH: u8 = 0xC;
+const ENVELOPE_SHAPE: u8 = 0xD;
+
+/// What the buzzer overwrites, put back by [`SyncBuzzer::stop`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Saved {
+    level: u8,
+    fine: u8,
+    rough: u8,
+    shape: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncBuzzer {
+    channel: Channel,
+    millihertz: u32,
+    shape: EnvelopeShape,
+    envelope_period: u16,
+    saved: Option<Saved>,
+}
+
+impl SyncBuzzer {
+    /// Retriggers `shape` at `millihertz` (thousandths of a hertz), with the
+    /// envelope running at `envelope_period`.
+    pub const fn new(
+        channel: Channel,
+        millihertz: u32,
+        shape: EnvelopeShape,
+        envelope_period: u16,
+    ) -> SyncBuzzer {
+        SyncBuzzer {
+            channel,
+            millihertz,
+            shape,
+            envelope_period,
+            saved: None,
+        }
+    }
+
+    /// Buzzes at `pitch`, with one envelope ramp lasting exactly one
+    /// retrigger period. Shorten the envelope period from there for
+    /// brighter timbres.
+    pub fn for_pitch(
+        clock: u32,
+        channel: Channel,
+        pitch: Pitch,
+        shape: EnvelopeShape,
+    ) -> SyncBuzzer {
+        let millihertz = tuning::pitch_millihertz(pitch);
+        let period = tuning::envelope_period(clock, millihertz);
+        SyncBuzzer::new(channel, millihertz, shape, period)
+    }
+
+    /// The effect as a YM file describes it: the shape is rewritten on every
+    /// interrupt of `timer`, and the envelope period comes from the frame's
+    /// R11 and R12.
+    pub const fn from_mfp(
+        channel: Channel,
+        timer: MfpTimer,
+        shape: EnvelopeShape,
+        envelope_period: u16,
+    ) -> SyncBuzzer {
+        SyncBuzzer::new(channel, timer.millihertz(), shape, envelope_period)
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn millihertz(&self) -> u32 {
+        self.millihertz
+    }
+
+    pub fn shape(&self) -> EnvelopeShape {
+        self.shape
+    }
+
+    pub fn envelope_period(&self) -> u16 {
+        self.envelope_period
+    }
+
+    pub fn set_millihertz(&mut self, millihertz: u32) {
+        self.millihertz = millihertz;
+    }
+
+    pub fn set_shape(&mut self, shape: EnvelopeShape) {
+        self.shape = shape;
+    }
+
+    /// Changes the envelope period, writing it straight away while running.
+    pub fn set_envelope_period<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        period: u16,
+    ) -> Result<(), P::Error> {
+        self.envelope_period = period;
+        if self.is_running() {
+            self.write_envelope_period(psg)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.saved.is_some()
+    }
+
+    /// Clocks of a `timer_clock`-hertz timer between retriggers, or 0 at a
+    /// frequency of 0.
+    pub fn timer_reload(&self, timer_clock: u32) -> u32 {
+        let clocks = (timer_clock as u64 * 1000 + self.millihertz as u64 / 2)
+            .checked_div(self.millihertz as u64)
+            .unwrap_or(0);
+        clocks.min(u32::MAX as u64) as u32
+    }
+
+    fn write_envelope_period<P: Psg>(&self, psg: &mut P) -> Result<(), P::Error> {
+        let [fine, rough] = self.envelope_period.to_le_bytes();
+        psg.update_register(ENVELOPE_FINE, fine)?;
+        psg.update_register(ENVELOPE_ROUGH, rough)?;
+        Ok(())
+    }
+
+    /// Saves the channel level and envelope registers, then sets the
+    /// envelope period, starts the envelope and switches the channel to it.
+    /// Start the timer after this.
+    pub fn start<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        if self.saved.is_none() {
+            let registers = psg.registers();
+            self.saved = Some(Saved {
+                level: registers.value(self.channel.level_register()),
+                fine: registers.value(ENVELOPE_FINE),
+                rough: registers.value(ENVELOPE_ROUGH),
+                shape: registers.get(ENVELOPE_SHAPE),
+            });
+        }
+        self.write_envelope_period(psg)?;
+        self.on_timer(psg)?;
+        psg.update_channel_level(self.channel, ChannelLevel::Envelope)
+    }
+
+    /// The timer interrupt's body: one forced write of the shape register.
+    pub fn on_timer<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        psg.set_register_value(ENVELOPE_SHAPE, self.shape.bits())
+    }
+
+    /// Restores what [`SyncBuzzer::start`] saved. Stop the timer first. The
+    /// shape register is only rewritten if something had set it before,
+    /// since writing it restarts the envelope.
+    pub fn stop<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let Some(saved) = self.saved.take() else {
+            return Ok(());
+        };
+        psg.update_register(self.channel.level_register(), saved.level)?;
+        psg.update_register(ENVELOPE_FINE, saved.fine)?;
+        psg.update_register(ENVELOPE_ROUGH, saved.rough)?;
+        if let Some(shape) = saved.shape {
+            psg.set_register_value(ENVELOPE_SHAPE, shape)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+
+    #[test]
+    fn pitch_sets_rate_and_envelope_period() {
+        let buzzer = SyncBuzzer::for_pitch(
+            2_000_000,
+            Channel::A,
+            Pitch::new(Note::A, 4),
+            EnvelopeShape::cont,
+        );
+        assert_eq!(buzzer.millihertz(), 440_000);
+        assert_eq!(buzzer.envelope_period(), 18);
+        // A 1 MHz timer: 1000000 / 440 = 2272.7
+        assert_eq!(buzzer.timer_reload(1_000_000), 2273);
+        let ym = SyncBuzzer::from_mfp(Channel::A, MfpTimer::new(5, 96), EnvelopeShape::cont, 7);
+        assert_eq!(ym.timer_reload(MfpTimer::new(5, 1).millihertz() / 1000), 96);
+    }
+
+    #[test]
+    fn retriggers_and_restores() {
+        let mut psg = FakePsg::new();
+        psg.update_channel_level(Channel::B, ChannelLevel::Fixed(9))
+            .unwrap();
+        psg.take_writes();
+
+        let shape = EnvelopeShape::cont | EnvelopeShape::Att;
+        let mut buzzer = SyncBuzzer::new(Channel::B, 220_000, shape, 0x0123);
+        buzzer.start(&mut psg).unwrap();
+        assert_eq!(
+            psg.take_writes(),
+            [(0xB, 0x23), (0xC, 0x01), (0xD, 0xC), (0x9, 0x10)]
+        );
+        // Retriggering always writes, even though the value is unchanged.
+        buzzer.on_timer(&mut psg).unwrap();
+        buzzer.on_timer(&mut psg).unwrap();
+        assert_eq!(psg.take_writes(), [(0xD, 0xC), (0xD, 0xC)]);
+
+        buzzer.stop(&mut psg).unwrap();
+        assert_eq!(psg.take_writes(), [(0x9, 9), (0xB, 0), (0xC, 0)]);
+        assert!(!buzzer.is_running());
+    }
+}
@@ -0,0 +1,182 @@
+//! A [`Psg`] writing to two others at once, such as the chip and the
+//! emulator, to compare what each makes of the same writes.
+//!
+//! Every write goes to the first backend and then the second. Helpers that
+//! read the shadow registers back, to skip unchanged writes or to keep the
+//! mixer's other bits, read the first's, so while neither fails the two
+//! are given exactly the same writes.
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::FrequencyPolicy;
+use crate::ChipKind;
+
+/// What a [`TeeBus`] does when a backend fails.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TeePolicy {
+    /// Stops at the first failure: a write the first backend fails is not
+    /// made to the second.
+    #[default]
+    FailFast,
+    /// Makes every write to both, reporting whichever failed.
+    Continue,
+}
+
+/// Why a write through a [`TeeBus`] failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TeeError<A, B> {
+    First(A),
+    Second(B),
+    /// Both failed, under [`TeePolicy::Continue`].
+    Both(A, B),
+}
+
+/// Two backends written as one.
+#[derive(Debug, Clone)]
+pub struct TeeBus<A, B> {
+    first: A,
+    second: B,
+    policy: TeePolicy,
+}
+
+impl<A: Psg, B: Psg> TeeBus<A, B> {
+    /// Writes to `first`, then `second`, stopping at the first failure.
+    pub const fn new(first: A, second: B) -> TeeBus<A, B> {
+        TeeBus {
+            first,
+            second,
+            policy: TeePolicy::FailFast,
+        }
+    }
+
+    pub const fn with_policy(mut self, policy: TeePolicy) -> TeeBus<A, B> {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> TeePolicy {
+        self.policy
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+
+    /// Both backends back, first and second.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Psg, B: Psg> Psg for TeeBus<A, B> {
+    type Error = TeeError<A::Error, B::Error>;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        let first = self.first.set_register_value(address, data);
+        if first.is_err() && self.policy == TeePolicy::FailFast {
+            return first.map_err(TeeError::First);
+        }
+        match (first, self.second.set_register_value(address, data)) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(first), Ok(())) => Err(TeeError::First(first)),
+            (Ok(()), Err(second)) => Err(TeeError::Second(second)),
+            (Err(first), Err(second)) => Err(TeeError::Both(first, second)),
+        }
+    }
+
+    /// The first backend's.
+    fn registers(&self) -> &Registers {
+        self.first.registers()
+    }
+
+    fn register_mask(&self, address: u8) -> u8 {
+        self.first.register_mask(address)
+    }
+
+    /// The first backend's.
+    fn master_clock(&self) -> u32 {
+        self.first.master_clock()
+    }
+
+    /// The first backend's.
+    fn chip_kind(&self) -> ChipKind {
+        self.first.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.first.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_player::FramePlayer;
+    use crate::recording_bus::{LogFull, RecordingBus};
+    use crate::{Channel, ChannelLevel};
+
+    #[test]
+    fn both_sides_get_the_same_writes() {
+        static SONG: [u8; 32] = [
+            0x1C, 0x01, 0, 0, 0, 0, 0, 0x3E, 15, 0, 0, 0, 0, 0xFF, 0, 0, //
+            0x1C, 0x01, 0, 0, 0, 0, 0, 0x3E, 10, 0, 0, 0, 0, 0xFF, 0, 0,
+        ];
+        let mut tee = TeeBus::new(RecordingBus::<64>::new(), RecordingBus::<64>::new());
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        for _ in 0..2 {
+            player.tick(&mut tee).unwrap();
+        }
+        tee.silence().unwrap();
+        let (first, second) = tee.into_inner();
+        assert_eq!(first.transactions(), second.transactions());
+        assert!(second.writes_to(0x8).eq([15, 10, 0]));
+    }
+
+    #[test]
+    fn failures_stop_or_carry_on_by_policy() {
+        let level =
+            |tee: &mut TeeBus<_, _>| tee.update_channel_level(Channel::A, ChannelLevel::Fixed(15));
+        let mut tee = TeeBus::new(RecordingBus::<0>::new(), RecordingBus::<2>::new());
+        assert_eq!(level(&mut tee), Err(TeeError::First(LogFull)));
+        assert!(tee.second().transactions().is_empty());
+
+        let mut tee = tee.with_policy(TeePolicy::Continue);
+        assert_eq!(level(&mut tee), Err(TeeError::First(LogFull)));
+        tee.second().assert_register_sequence(&[(0x8, 15)]);
+        // That filled the second too.
+        assert_eq!(
+            tee.set_register_value(0xA, 1),
+            Err(TeeError::Both(LogFull, LogFull))
+        );
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn the_emulator_hears_what_the_chip_is_sent() {
+        use crate::emulator::Ym2149Emulator;
+
+        let mut tee = TeeBus::new(RecordingBus::<64>::new(), Ym2149Emulator::new());
+        tee.set_channel_period(Channel::B, 0x100).unwrap();
+        tee.set_tone_enabled(Channel::B, true).unwrap();
+        tee.update_channel_level(Channel::B, ChannelLevel::Fixed(15))
+            .unwrap();
+        assert_eq!(tee.first().registers(), tee.second().registers());
+        let mut out = [0; 64];
+        tee.second_mut().render(&mut out, 44_100);
+        assert_eq!(tee.second().channel_levels()[0], 0);
+        assert_eq!(tee.second().peak_levels(64)[1], 31);
+    }
+}
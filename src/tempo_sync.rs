@@ -0,0 +1,182 @@
+//! Following a tempo from outside: a MIDI clock, or a sync pulse on a pin.
+//!
+//! Call [`TempoSync::pulse`] with a timestamp for each pulse as it comes
+//! in, from the UART's receive interrupt for a MIDI clock byte, or a pin
+//! change interrupt. The pulses are averaged into a tempo that
+//! [`Sequencer`](crate::sequencer::Sequencer) and
+//! `drum_machine::DrumMachine` play at in place of their own.
+//!
+//! Pulses are never quite on time, so each interval counts for an eighth of
+//! the average, and one more than a quarter longer or shorter than the
+//! average counts as a quarter: a single late, early or missing pulse moves
+//! the tempo by at most 1/32, about 3%, and steady jitter averages out. A
+//! real change of tempo is followed within a few beats.
+//!
+//! When the pulses stop for four of their intervals, the sync is lost and
+//! the music carries on at the last tempo it had. The next two pulses lock
+//! on again, at once, to whatever tempo they give. Only the tempo is
+//! followed, not where the beat falls.
+
+/// Pulses per quarter note of a MIDI clock.
+pub const MIDI_CLOCK_PPQN: u16 = 24;
+
+/// The longest time between two pulses that are taken as following each
+/// other, in microseconds: at one pulse a beat, 30 BPM.
+pub const MAX_PULSE_INTERVAL: u32 = 2_000_000;
+
+/// Intervals of silence after which the sync is lost.
+const LOST_AFTER: u32 = 4;
+
+/// A tempo taken from a train of pulses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TempoSync {
+    ppqn: u16,
+    last_pulse: Option<u32>,
+    /// The average interval between pulses, in sixteenths of a microsecond,
+    /// or 0 before there has been one.
+    interval: u32,
+    /// Microseconds since the last pulse, as counted by
+    /// [`TempoSync::advance`].
+    since_pulse: u32,
+    locked: bool,
+}
+
+impl TempoSync {
+    /// Follows pulses coming `ppqn` to a beat: [`MIDI_CLOCK_PPQN`] for a
+    /// MIDI clock, or 1, 2 or 4 for most sync pulses.
+    pub const fn new(ppqn: u16) -> TempoSync {
+        TempoSync {
+            ppqn: if ppqn == 0 { 1 } else { ppqn },
+            last_pulse: None,
+            interval: 0,
+            since_pulse: 0,
+            locked: false,
+        }
+    }
+
+    /// A pulse came in at `micros` microseconds from any point, on a clock
+    /// that may wrap round.
+    pub fn pulse(&mut self, micros: u32) {
+        if let Some(last) = self.last_pulse {
+            let sample = micros.wrapping_sub(last);
+            if sample > 0 && sample <= MAX_PULSE_INTERVAL {
+                let sample = sample * 16;
+                if self.locked {
+                    let sample = sample.clamp(self.interval / 4 * 3, self.interval / 4 * 5);
+                    self.interval = (self.interval / 8 * 7).saturating_add(sample / 8);
+                } else {
+                    self.interval = sample;
+                    self.locked = true;
+                }
+            }
+        }
+        self.last_pulse = Some(micros);
+        self.since_pulse = 0;
+    }
+
+    /// `micros` microseconds have gone by, from the player's own clock:
+    /// enough of them without a pulse and the sync is lost.
+    pub fn advance(&mut self, micros: u32) {
+        self.since_pulse = self.since_pulse.saturating_add(micros);
+        let lost_after = match self.locked {
+            true => self.interval / 16 * LOST_AFTER,
+            false => MAX_PULSE_INTERVAL,
+        };
+        if self.since_pulse > lost_after {
+            self.locked = false;
+            self.last_pulse = None;
+        }
+    }
+
+    /// Whether pulses are coming in and the tempo follows them.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// The tempo in thousandths of a beat a minute: the pulses' while
+    /// locked, and the last they gave since. `None` before the first lock.
+    pub fn millibpm(&self) -> Option<u32> {
+        if self.interval == 0 {
+            return None;
+        }
+        let per_minute = 60_000_000_000 * 16 / (self.interval as u64 * self.ppqn as u64);
+        Some(per_minute.min(u32::MAX as u64) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pulse train at `bpm`, each pulse up to `jitter` microseconds late
+    /// or early, pseudo-randomly.
+    fn pulses(bpm: u32, jitter: u32, count: usize, start: u32) -> impl Iterator<Item = u32> {
+        let interval = 60_000_000 / (bpm * MIDI_CLOCK_PPQN as u32);
+        let mut seed = 0x2545_F491u32;
+        (0..count as u32).map(move |n| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let offset = seed % (2 * jitter + 1);
+            start
+                .wrapping_add(n * interval + offset)
+                .wrapping_sub(jitter)
+        })
+    }
+
+    fn within(millibpm: Option<u32>, bpm: u32, percent: u32) -> bool {
+        let wanted = bpm * 1000;
+        millibpm.is_some_and(|got| got.abs_diff(wanted) <= wanted * percent / 100)
+    }
+
+    #[test]
+    fn jitter_is_smoothed_and_bounded() {
+        let mut sync = TempoSync::new(MIDI_CLOCK_PPQN);
+        assert_eq!(sync.millibpm(), None);
+        // 120 BPM is a pulse every 20.8 ms; jitter of 2 ms either way.
+        let mut worst = 0;
+        for (n, micros) in pulses(120, 2000, 500, 1000).enumerate() {
+            sync.pulse(micros);
+            if n > 48 {
+                worst = worst.max(sync.millibpm().unwrap().abs_diff(120_000));
+            }
+        }
+        assert!(sync.is_locked());
+        assert!(worst <= 120_000 * 3 / 100, "{worst}");
+
+        // A pulse lost, or one very late, moves the tempo 1/32 at most.
+        let steady = sync.millibpm().unwrap();
+        let last = sync.last_pulse.unwrap();
+        sync.pulse(last + 2 * 20_833);
+        assert!(sync.millibpm().unwrap().abs_diff(steady) <= steady / 32 + 1);
+    }
+
+    #[test]
+    fn dropouts_free_run_then_lock_on_again() {
+        let mut sync = TempoSync::new(MIDI_CLOCK_PPQN);
+        for micros in pulses(100, 500, 100, u32::MAX - 1_000_000) {
+            sync.pulse(micros);
+            sync.advance(20_000);
+        }
+        assert!(within(sync.millibpm(), 100, 1));
+        // Four intervals go by without a pulse: free running at 100.
+        for _ in 0..5 {
+            sync.advance(20_000);
+        }
+        assert!(!sync.is_locked());
+        assert!(within(sync.millibpm(), 100, 1));
+
+        // Back at 140 BPM, locked from the second pulse.
+        let mut restarted = pulses(140, 0, 50, 5_000_000);
+        sync.pulse(restarted.next().unwrap());
+        assert!(!sync.is_locked());
+        sync.pulse(restarted.next().unwrap());
+        assert!(sync.is_locked());
+        assert!(within(sync.millibpm(), 140, 1));
+        // Pulses too far apart to be a tempo are not taken as one.
+        let mut slow = TempoSync::new(1);
+        slow.pulse(0);
+        slow.pulse(MAX_PULSE_INTERVAL + 1);
+        assert_eq!(slow.millibpm(), None);
+    }
+}
@@ -0,0 +1,128 @@
+//! A `Psg` that only records what it is told, for unit tests.
+
+extern crate std;
+
+use std::vec::Vec;
+
+#[cfg(feature = "player")]
+use crate::data_source::DataSource;
+use crate::psg::Psg;
+#[cfg(feature = "formats-ym")]
+use crate::registers::Frame;
+use crate::registers::Registers;
+use crate::tuning::{FrequencyPolicy, DEFAULT_MASTER_CLOCK};
+
+pub struct FakePsg {
+    registers: Registers,
+    clock: u32,
+    pub writes: Vec<(u8, u8)>,
+    pub frequency_policy: FrequencyPolicy,
+}
+
+impl FakePsg {
+    pub fn new() -> FakePsg {
+        FakePsg::with_clock(DEFAULT_MASTER_CLOCK)
+    }
+
+    pub fn with_clock(clock: u32) -> FakePsg {
+        FakePsg {
+            registers: Registers::new(),
+            clock,
+            writes: Vec::new(),
+            frequency_policy: FrequencyPolicy::default(),
+        }
+    }
+
+    /// Writes made since the last call.
+    pub fn take_writes(&mut self) -> Vec<(u8, u8)> {
+        core::mem::take(&mut self.writes)
+    }
+}
+
+impl Psg for FakePsg {
+    type Error = ();
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), ()> {
+        self.registers.set(address, data);
+        self.writes.push((address, data));
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.clock
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.frequency_policy
+    }
+}
+
+/// A YM file of `version` (`b"YM5!"` or `b"YM6!"`) at 2 MHz and 50 Hz,
+/// frames one after another, looping to the start.
+#[cfg(feature = "formats-ym")]
+pub fn ym_file(version: &[u8; 4], drums: &[&[u8]], frames: &[Frame]) -> Vec<u8> {
+    let mut file = Vec::new();
+    file.extend_from_slice(version);
+    file.extend_from_slice(b"LeOnArD!");
+    file.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    file.extend_from_slice(&0u32.to_be_bytes());
+    file.extend_from_slice(&(drums.len() as u16).to_be_bytes());
+    file.extend_from_slice(&2_000_000u32.to_be_bytes());
+    file.extend_from_slice(&50u16.to_be_bytes());
+    file.extend_from_slice(&0u32.to_be_bytes());
+    file.extend_from_slice(&0u16.to_be_bytes());
+    for drum in drums {
+        file.extend_from_slice(&(drum.len() as u32).to_be_bytes());
+        file.extend_from_slice(drum);
+    }
+    file.extend_from_slice(b"\0\0\0");
+    for frame in frames {
+        file.extend_from_slice(frame);
+    }
+    file.extend_from_slice(b"End!");
+    file
+}
+
+/// Bytes stored inverted, so anything slicing them rather than going
+/// through [`DataSource`] reads nonsense.
+#[cfg(feature = "player")]
+#[derive(Debug, Clone)]
+pub struct Inverted(Vec<u8>);
+
+#[cfg(feature = "player")]
+impl Inverted {
+    pub fn new(data: &[u8]) -> Inverted {
+        Inverted(data.iter().map(|byte| !byte).collect())
+    }
+}
+
+#[cfg(feature = "player")]
+impl DataSource for Inverted {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) {
+        for (out, byte) in buffer.iter_mut().zip(&self.0[offset..]) {
+            *out = !byte;
+        }
+    }
+}
+
+/// Every way of cutting `data` short, then every way of flipping one bit of
+/// it: the damage a bad card or a botched download does, for feeding to
+/// parsers that must fail cleanly.
+#[cfg(feature = "player")]
+pub fn mangled(data: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    let truncated = (0..data.len()).map(|length| Vec::from(&data[..length]));
+    let flipped = (0..data.len() * 8).map(move |bit| {
+        let mut data = Vec::from(data);
+        data[bit / 8] ^= 1 << (bit % 8);
+        data
+    });
+    truncated.chain(flipped)
+}
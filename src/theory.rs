@@ -0,0 +1,208 @@
+//! Scales and keys for keeping generated music in tune.
+//!
+//! Everything here is `const fn` and allocation-free, so tables of keys can
+//! live in flash and feed the chord and arpeggio helpers directly.
+
+pub use crate::chord::ChordType;
+use crate::pitch::{Note, Pitch};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Scale {
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    Chromatic,
+}
+
+impl Scale {
+    /// Semitones above the root of each scale degree, ascending.
+    pub const fn intervals(self) -> &'static [u8] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+
+    /// Number of notes per octave.
+    pub const fn notes_per_octave(self) -> u8 {
+        self.intervals().len() as u8
+    }
+
+    /// The `n`th note of the scale starting from `root` (degree 0), continuing
+    /// into higher octaves past the last degree. Degrees that would land above
+    /// G9 come back down an octave at a time.
+    pub const fn degree(self, root: Pitch, n: u8) -> Pitch {
+        let intervals = self.intervals();
+        let len = intervals.len() as u16;
+        let octave = n as u16 / len;
+        let mut midi =
+            root.midi() as u16 + octave * 12 + intervals[(n as u16 % len) as usize] as u16;
+        while midi > 127 {
+            midi -= 12;
+        }
+        match Pitch::from_midi(midi as u8) {
+            Some(pitch) => pitch,
+            None => Pitch::MAX,
+        }
+    }
+
+    /// Whether `pitch` belongs to this scale built on `root`, in any octave.
+    pub const fn contains(self, root: Note, pitch: Pitch) -> bool {
+        let offset = (pitch.midi() + 12 - root.semitone()) % 12;
+        let intervals = self.intervals();
+        let mut i = 0;
+        while i < intervals.len() {
+            if intervals[i] == offset {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// Snaps `pitch` to the nearest note of this scale on `root`. Ties go
+    /// down, so a quantised melody never drifts sharp.
+    pub const fn quantize(self, root: Note, pitch: Pitch) -> Pitch {
+        let mut distance = 0u8;
+        while distance <= 6 {
+            if let Some(below) = pitch.transpose(-(distance as i8)) {
+                if self.contains(root, below) {
+                    return below;
+                }
+            }
+            if let Some(above) = pitch.transpose(distance as i8) {
+                if self.contains(root, above) {
+                    return above;
+                }
+            }
+            distance += 1;
+        }
+        // Every scale here has a note within a tritone of any pitch.
+        pitch
+    }
+}
+
+/// A scale on a particular root note.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub root: Note,
+    pub scale: Scale,
+}
+
+impl Key {
+    pub const fn new(root: Note, scale: Scale) -> Key {
+        Key { root, scale }
+    }
+
+    pub const fn contains(self, pitch: Pitch) -> bool {
+        self.scale.contains(self.root, pitch)
+    }
+
+    pub const fn quantize(self, pitch: Pitch) -> Pitch {
+        self.scale.quantize(self.root, pitch)
+    }
+
+    /// The `n`th scale degree counting up from the root in `octave`. A root
+    /// above G9 comes back down an octave at a time, as high degrees do.
+    pub const fn degree(self, octave: u8, n: u8) -> Pitch {
+        let mut midi = (octave as u16 + 1) * 12 + self.root as u16;
+        while midi > 127 {
+            midi -= 12;
+        }
+        let root = match Pitch::from_midi(midi as u8) {
+            Some(pitch) => pitch,
+            None => Pitch::MAX,
+        };
+        self.scale.degree(root, n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi_notes<const N: usize>(key: Key, octave: u8) -> [u8; N] {
+        core::array::from_fn(|n| key.degree(octave, n as u8).midi())
+    }
+
+    #[test]
+    fn d_major_degrees() {
+        let key = Key::new(Note::D, Scale::Major);
+        // D4 E4 F#4 G4 A4 B4 C#5 D5 E5
+        assert_eq!(
+            midi_notes::<9>(key, 4),
+            [62, 64, 66, 67, 69, 71, 73, 74, 76]
+        );
+        assert!(key.contains(Pitch::new(Note::FSharp, 2)));
+        assert!(!key.contains(Pitch::new(Note::F, 2)));
+    }
+
+    #[test]
+    fn a_minor_pentatonic_and_blues() {
+        let pentatonic = Key::new(Note::A, Scale::MinorPentatonic);
+        // A3 C4 D4 E4 G4 A4
+        assert_eq!(midi_notes::<6>(pentatonic, 3), [57, 60, 62, 64, 67, 69]);
+        let blues = Key::new(Note::A, Scale::Blues);
+        // A3 C4 D4 D#4 E4 G4 A4
+        assert_eq!(midi_notes::<7>(blues, 3), [57, 60, 62, 63, 64, 67, 69]);
+    }
+
+    #[test]
+    fn degrees_above_g9_fold_down() {
+        let key = Key::new(Note::C, Scale::Major);
+        assert_eq!(key.degree(9, 5), Pitch::new(Note::A, 8));
+    }
+
+    #[test]
+    fn roots_above_g9_fold_down() {
+        let a_major = Key::new(Note::A, Scale::Major);
+        assert_eq!(a_major.degree(9, 0), Pitch::new(Note::A, 8));
+        assert_eq!(a_major.degree(9, 2), Pitch::new(Note::CSharp, 9));
+        assert_eq!(a_major.degree(10, 0), Pitch::new(Note::A, 8));
+        assert_eq!(a_major.degree(8, 0), Pitch::new(Note::A, 8));
+        let b_chromatic = Key::new(Note::B, Scale::Chromatic);
+        for octave in [9, 10, 200, 255] {
+            assert_eq!(b_chromatic.degree(octave, 0), Pitch::new(Note::B, 8));
+            for n in [6, 7, 255] {
+                b_chromatic.degree(octave, n);
+            }
+        }
+        assert_eq!(
+            Key::new(Note::G, Scale::Major).degree(9, 0),
+            Pitch::new(Note::G, 9)
+        );
+    }
+
+    #[test]
+    fn quantize_snaps_to_nearest_in_scale_note() {
+        let c_major = Key::new(Note::C, Scale::Major);
+        let expected = [60, 60, 62, 62, 64, 65, 65, 67, 67, 69, 69, 71];
+        for (offset, expected) in expected.into_iter().enumerate() {
+            let pitch = Pitch::from_midi(60 + offset as u8).unwrap();
+            assert_eq!(c_major.quantize(pitch).midi(), expected, "offset {offset}");
+        }
+
+        let e_harmonic_minor = Key::new(Note::E, Scale::HarmonicMinor);
+        // E F# G A B C D#: the augmented second splits between C and D#.
+        assert_eq!(
+            e_harmonic_minor.quantize(Pitch::new(Note::CSharp, 4)),
+            Pitch::new(Note::C, 4)
+        );
+        assert_eq!(
+            e_harmonic_minor.quantize(Pitch::new(Note::D, 4)),
+            Pitch::new(Note::DSharp, 4)
+        );
+        assert_eq!(
+            Scale::Chromatic.quantize(Note::C, Pitch::new(Note::GSharp, 4)),
+            Pitch::new(Note::GSharp, 4)
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Audible diagnostic codes, "three long, two short" style, for devices
+//! without a display.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning;
+use crate::{Channel, ChannelLevel, Error, IoPorts, Ym2149};
+
+/// A stretch of sound or silence, in milliseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Step {
+    Tone(u16),
+    Rest(u16),
+}
+
+impl Step {
+    pub const fn millis(self) -> u16 {
+        match self {
+            Step::Tone(ms) | Step::Rest(ms) => ms,
+        }
+    }
+}
+
+/// Beep lengths for [`ToneCode::LongShort`], in milliseconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timing {
+    pub long: u16,
+    pub short: u16,
+    /// Between beeps in a group.
+    pub gap: u16,
+    /// Between the long and the short group.
+    pub group_gap: u16,
+}
+
+pub const DEFAULT_TIMING: Timing = Timing {
+    long: 600,
+    short: 150,
+    gap: 150,
+    group_gap: 600,
+};
+
+/// What to play. Both forms are const, so a table of error codes can live in
+/// flash.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneCode {
+    /// Exactly these steps.
+    Steps(&'static [Step]),
+    /// `long` long beeps, then `short` short ones.
+    LongShort { long: u8, short: u8 },
+}
+
+impl ToneCode {
+    /// Step `n` of one playthrough.
+    pub fn step(&self, n: usize, timing: &Timing) -> Option<Step> {
+        match *self {
+            ToneCode::Steps(steps) => steps.get(n).copied(),
+            ToneCode::LongShort { long, short } => {
+                // Beeps at even indices, the gaps after them at odd ones.
+                let (long, beeps) = (long as usize, long as usize + short as usize);
+                let beep = n / 2;
+                if n.is_multiple_of(2) {
+                    let length = if beep < long {
+                        timing.long
+                    } else {
+                        timing.short
+                    };
+                    (beep < beeps).then_some(Step::Tone(length))
+                } else if beep + 1 >= beeps {
+                    None
+                } else if beep + 1 == long {
+                    Some(Step::Rest(timing.group_gap))
+                } else {
+                    Some(Step::Rest(timing.gap))
+                }
+            }
+        }
+    }
+}
+
+/// Plays a [`ToneCode`] on one channel, either from a periodic tick or
+/// blocking with [`Ym2149::play_tone_code`].
+///
+/// When the code finishes, or is stopped early, the channel is silenced and
+/// its tone turned off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToneCodePlayer {
+    channel: Channel,
+    code: ToneCode,
+    pitch: Pitch,
+    level: u8,
+    timing: Timing,
+    repeats: u8,
+    repeat_gap: u16,
+    tick_rate: u32,
+    index: usize,
+    plays: u8,
+    remaining: i32,
+    running: bool,
+    prepared: bool,
+}
+
+impl ToneCodePlayer {
+    /// Plays `code` once at `pitch` and `level`, ticked at 50 Hz.
+    pub const fn new(channel: Channel, code: ToneCode, pitch: Pitch, level: u8) -> ToneCodePlayer {
+        ToneCodePlayer {
+            channel,
+            code,
+            pitch,
+            level,
+            timing: DEFAULT_TIMING,
+            repeats: 1,
+            repeat_gap: 0,
+            tick_rate: 50,
+            index: 0,
+            plays: 0,
+            remaining: 0,
+            running: false,
+            prepared: false,
+        }
+    }
+
+    pub const fn with_timing(mut self, timing: Timing) -> ToneCodePlayer {
+        self.timing = timing;
+        self
+    }
+
+    /// Plays the code `times` times, `gap` milliseconds apart. 0 repeats it
+    /// until stopped.
+    pub const fn repeat(mut self, times: u8, gap: u16) -> ToneCodePlayer {
+        self.repeats = times;
+        self.repeat_gap = gap;
+        self
+    }
+
+    /// How many times a second [`ToneCodePlayer::tick`] will be called.
+    pub const fn with_tick_rate(mut self, hertz: u32) -> ToneCodePlayer {
+        self.tick_rate = hertz;
+        self
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.running
+    }
+
+    /// Starts from the top on the next tick.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.index = 0;
+        self.plays = 0;
+        self.remaining = 0;
+        self.prepared = false;
+    }
+
+    /// Stops at once, silencing the channel.
+    pub fn stop<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        self.running = false;
+        psg.update_channel_level(self.channel, ChannelLevel::Fixed(0))?;
+        psg.set_tone_enabled(self.channel, false)
+    }
+
+    /// The next step, with the gaps between repeats included, or `None` when
+    /// every repeat has played.
+    fn next_step(&mut self) -> Option<Step> {
+        if let Some(step) = self.code.step(self.index, &self.timing) {
+            self.index += 1;
+            return Some(step);
+        }
+        self.plays = self.plays.saturating_add(1);
+        if self.repeats != 0 && self.plays >= self.repeats {
+            return None;
+        }
+        self.index = 0;
+        Some(Step::Rest(self.repeat_gap))
+    }
+
+    fn apply<P: Psg>(&self, psg: &mut P, step: Step) -> Result<(), P::Error> {
+        let level = match step {
+            Step::Tone(_) => self.level,
+            Step::Rest(_) => 0,
+        };
+        psg.update_channel_level(self.channel, ChannelLevel::Fixed(level))
+    }
+
+    fn prepare<P: Psg>(&self, psg: &mut P) -> Result<(), P::Error> {
+        let period = tuning::fold_pitch_period(psg.master_clock(), self.pitch).period;
+        psg.set_channel_period(self.channel, period)?;
+        psg.set_channel_mixer(self.channel, true, false)
+    }
+
+    /// Advances by one tick. Returns `true` once the code is over, on the
+    /// tick that cleans up.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<bool, P::Error> {
+        if !self.running {
+            return Ok(true);
+        }
+        if !self.prepared {
+            self.prepare(psg)?;
+            self.prepared = true;
+        }
+        // Step lengths are counted in thousandths of a tick, carrying any
+        // overshoot into the next step so the code keeps its overall length.
+        while self.remaining <= 0 {
+            let Some(step) = self.next_step() else {
+                self.stop(psg)?;
+                return Ok(true);
+            };
+            self.apply(psg, step)?;
+            // Zero-length steps take a millisecond, so a code of nothing but
+            // them can't spin forever.
+            self.remaining += step.millis().max(1) as i32 * self.tick_rate as i32;
+        }
+        self.remaining -= 1000;
+        Ok(false)
+    }
+}
+
+impl<P, Delay, Io> Ym2149<P, Delay, Io>
+where
+    P: OutputPin,
+    Delay: DelayNs,
+    Io: IoPorts,
+{
+    /// Plays `player`'s code to the end, blocking, unless `interrupted`
+    /// returns `true`. It is checked every 10 ms.
+    pub fn play_tone_code(
+        &mut self,
+        player: &mut ToneCodePlayer,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> Result<(), Error<P>> {
+        player.start();
+        player.prepare(self)?;
+        'steps: while let Some(step) = player.next_step() {
+            player.apply(self, step)?;
+            let mut remaining = step.millis() as u32;
+            while remaining > 0 {
+                if interrupted() {
+                    break 'steps;
+                }
+                let slice = remaining.min(10);
+                self.delay.delay_ms(slice);
+                remaining -= slice;
+            }
+        }
+        player.stop(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+    use std::vec::Vec;
+
+    const FAST: Timing = Timing {
+        long: 3,
+        short: 1,
+        gap: 1,
+        group_gap: 2,
+    };
+
+    /// Ticks at 1 kHz and records `(tick, level)` at each level change.
+    fn trace(mut player: ToneCodePlayer) -> Vec<(usize, u8)> {
+        let mut psg = FakePsg::new();
+        let mut changes = Vec::new();
+        let mut level = None;
+        player.start();
+        for tick in 0.. {
+            let done = player.tick(&mut psg).unwrap();
+            let now = psg.registers().value(0x8);
+            if level != Some(now) {
+                changes.push((tick, now));
+                level = Some(now);
+            }
+            if done {
+                break;
+            }
+        }
+        changes
+    }
+
+    #[test]
+    fn long_short_expands_into_groups() {
+        let code = ToneCode::LongShort { long: 3, short: 2 };
+        let player = ToneCodePlayer::new(Channel::A, code, Pitch::new(Note::A, 4), 13)
+            .with_timing(FAST)
+            .with_tick_rate(1000);
+        assert_eq!(
+            trace(player),
+            [
+                (0, 13), // long
+                (3, 0),
+                (4, 13), // long
+                (7, 0),
+                (8, 13),  // long
+                (11, 0),  // group gap
+                (13, 13), // short
+                (14, 0),
+                (15, 13), // short
+                (16, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn steps_repeat_with_a_gap_then_clean_up() {
+        const CODE: ToneCode = ToneCode::Steps(&[Step::Tone(2), Step::Rest(1), Step::Tone(1)]);
+        let mut psg = FakePsg::new();
+        let mut player = ToneCodePlayer::new(Channel::A, CODE, Pitch::new(Note::A, 4), 10)
+            .repeat(2, 5)
+            .with_tick_rate(1000);
+        assert_eq!(
+            trace(player.clone()),
+            [
+                (0, 10),
+                (2, 0),
+                (3, 10),
+                (4, 0),
+                (9, 10),
+                (11, 0),
+                (12, 10),
+                (13, 0)
+            ]
+        );
+
+        player.start();
+        player.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().mixer() & 0b1001, 0b1000);
+        // Stopping part way silences the channel straight away.
+        player.stop(&mut psg).unwrap();
+        assert!(!player.is_playing());
+        assert_eq!(psg.registers().value(0x8), 0);
+        assert_eq!(psg.registers().mixer() & 0b1001, 0b1001);
+    }
+
+    #[test]
+    fn durations_round_to_ticks_without_drift() {
+        // 150 ms at 50 Hz is 7.5 ticks: the beeps alternate 8 and 7 long.
+        let code = ToneCode::LongShort { long: 0, short: 4 };
+        let player = ToneCodePlayer::new(Channel::A, code, Pitch::new(Note::A, 4), 15);
+        let starts: Vec<_> = trace(player).into_iter().map(|(tick, _)| tick).collect();
+        assert_eq!(starts, [0, 8, 15, 23, 30, 38, 45, 53]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Amplitude tremolo layered over a channel's base level.
+
+use crate::effect::{ChannelCtx, Effect};
+use crate::lfo::{Lfo, LfoWaveform, LFO_SCALE};
+use crate::psg::Psg;
+use crate::{Channel, ChannelLevel};
+
+/// Periodic dips in a channel's level.
+///
+/// The level swings between the base level and `depth` levels below it,
+/// never above: that way tremolo can't undo a fade or un-mute a channel
+/// whose base level is 0, and a base of 15 doesn't clip. Feed it the level
+/// after any fading so the modulation rides on top of the fade. Channels
+/// playing the hardware envelope are left alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tremolo {
+    channel: Channel,
+    depth: u8,
+    lfo: Lfo,
+    base: Option<ChannelLevel>,
+}
+
+impl Tremolo {
+    pub const fn new(channel: Channel, depth: u8, rate: u16, waveform: LfoWaveform) -> Tremolo {
+        Tremolo {
+            channel,
+            depth,
+            lfo: Lfo::new(waveform, rate),
+            base: None,
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: u8) {
+        self.depth = depth;
+    }
+
+    pub fn set_rate(&mut self, rate: u16) {
+        self.lfo.set_period(rate);
+    }
+
+    pub fn set_base_level(&mut self, level: ChannelLevel) {
+        self.base = Some(level);
+    }
+
+    /// Restarts the LFO cycle.
+    pub fn retrigger(&mut self) {
+        self.lfo.reset();
+    }
+
+    /// Applies this tick's dip to a base level in 0..=15.
+    pub fn modulate(&mut self, base: u8) -> u8 {
+        let base = base.min(15) as i32;
+        // Map the LFO's -1..1 onto a dip of depth..0.
+        let dip = self.depth as i32 * (LFO_SCALE - self.lfo.step()) / (2 * LFO_SCALE);
+        (base - dip).max(0) as u8
+    }
+
+    /// Writes this tick's level. Unchanged levels are skipped by the
+    /// register cache.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        match self.base {
+            Some(ChannelLevel::Fixed(base)) => {
+                let level = self.modulate(base);
+                psg.update_channel_level(self.channel, ChannelLevel::Fixed(level))
+            }
+            Some(ChannelLevel::Envelope) | None => Ok(()),
+        }
+    }
+}
+
+/// Dips the chain's base level; envelope-mode channels are left alone.
+impl Effect for Tremolo {
+    fn tick(&mut self, ctx: &mut ChannelCtx) {
+        if let ChannelLevel::Fixed(base) = ctx.base_level() {
+            let level = self.modulate(base);
+            ctx.add_level(level as i8 - base.min(15) as i8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakePsg;
+
+    fn cycle(tremolo: &mut Tremolo, base: u8) -> [u8; 4] {
+        core::array::from_fn(|_| tremolo.modulate(base))
+    }
+
+    #[test]
+    fn depth_clamps_at_the_bottom_and_never_exceeds_the_base() {
+        let mut tremolo = Tremolo::new(Channel::A, 6, 4, LfoWaveform::Triangle);
+        assert_eq!(cycle(&mut tremolo, 15), [12, 15, 12, 9]);
+        assert_eq!(cycle(&mut tremolo, 1), [0, 1, 0, 0]);
+        assert_eq!(cycle(&mut tremolo, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn envelope_channels_are_skipped() {
+        let mut psg = FakePsg::new();
+        let mut tremolo = Tremolo::new(Channel::B, 4, 2, LfoWaveform::Square);
+        tremolo.set_base_level(ChannelLevel::Envelope);
+        tremolo.tick(&mut psg).unwrap();
+        assert!(psg.writes.is_empty());
+
+        tremolo.set_base_level(ChannelLevel::Fixed(10));
+        tremolo.tick(&mut psg).unwrap();
+        tremolo.tick(&mut psg).unwrap();
+        tremolo.tick(&mut psg).unwrap();
+        assert_eq!(psg.take_writes(), [(0x9, 10), (0x9, 6), (0x9, 10)]);
+    }
+}
@@ -0,0 +1,828 @@
+//! Integer tuning maths: converting between pitches, frequencies and the
+//! chip's tone periods.
+//!
+//! The tone generators divide the master clock by 16 and then by the 12-bit
+//! tone period, so `f = clock / (16 * period)`. Everything here works in
+//! integers (frequencies in millihertz, ratios in Q16) so it is usable on
+//! targets without an FPU.
+//!
+//! The conversions the setters use forgive values out of range, folding or
+//! clamping them into it. The `checked_` ones refuse them instead, with a
+//! [`RangeError`] saying what would have fitted, for checking data ahead of
+//! playing it.
+
+#[cfg(feature = "music")]
+use crate::pitch::Pitch;
+use crate::ChipKind;
+
+/// Master clock of the Atari ST's YM2149, used until the driver is told otherwise.
+pub const DEFAULT_MASTER_CLOCK: u32 = 2_000_000;
+
+/// Largest value the 12-bit tone period registers can hold.
+pub const MAX_TONE_PERIOD: u16 = 0x0FFF;
+
+/// Largest value the 5-bit noise period register can hold.
+pub const MAX_NOISE_PERIOD: u8 = 0x1F;
+
+/// Frequencies of MIDI notes 120..=131 in millihertz; lower octaves are
+/// derived by halving.
+#[cfg(feature = "music")]
+const TOP_OCTAVE_MILLIHERTZ: [u64; 12] = [
+    8_372_018, 8_869_844, 9_397_273, 9_956_063, 10_548_082, 11_175_303, 11_839_822, 12_543_854,
+    13_289_750, 14_080_000, 14_917_240, 15_804_266,
+];
+
+/// `2^(n/12)` in Q16 for n in 0..12.
+const SEMITONE_RATIO_Q16: [u64; 12] = [
+    65536, 69433, 73562, 77936, 82570, 87480, 92682, 98193, 104032, 110218, 116772, 123715,
+];
+
+/// `2^(n/1200)` in Q16 for n in 0..100.
+const CENT_RATIO_Q16: [u64; 100] = [
+    65536, 65574, 65612, 65650, 65688, 65726, 65764, 65802, 65840, 65878, 65916, 65954, 65992,
+    66030, 66068, 66106, 66144, 66183, 66221, 66259, 66297, 66336, 66374, 66412, 66451, 66489,
+    66528, 66566, 66605, 66643, 66682, 66720, 66759, 66797, 66836, 66874, 66913, 66952, 66990,
+    67029, 67068, 67107, 67145, 67184, 67223, 67262, 67301, 67340, 67378, 67417, 67456, 67495,
+    67534, 67573, 67612, 67651, 67691, 67730, 67769, 67808, 67847, 67886, 67926, 67965, 68004,
+    68043, 68083, 68122, 68161, 68201, 68240, 68280, 68319, 68359, 68398, 68438, 68477, 68517,
+    68556, 68596, 68635, 68675, 68715, 68755, 68794, 68834, 68874, 68914, 68953, 68993, 69033,
+    69073, 69113, 69153, 69193, 69233, 69273, 69313, 69353, 69393,
+];
+
+fn div_round(numerator: u64, denominator: u64) -> u64 {
+    (numerator + denominator / 2) / denominator
+}
+
+/// Frequency of an equal-tempered pitch (A4 = 440 Hz) in millihertz.
+#[cfg(feature = "music")]
+pub fn pitch_millihertz(pitch: Pitch) -> u32 {
+    let midi = pitch.midi() as u32;
+    let shift = 10 - midi / 12;
+    let top = TOP_OCTAVE_MILLIHERTZ[(midi % 12) as usize];
+    div_round(top, 1 << shift) as u32
+}
+
+/// Tone period for `pitch` at `clock`, rounded to nearest.
+///
+/// The result is not clamped: values above [`MAX_TONE_PERIOD`] mean the note
+/// is too low for this clock, and 0 means it is too high.
+#[cfg(feature = "music")]
+pub fn pitch_period(clock: u32, pitch: Pitch) -> u32 {
+    let midi = pitch.midi() as u32;
+    let shift = 10 - midi / 12;
+    let top = TOP_OCTAVE_MILLIHERTZ[(midi % 12) as usize];
+    div_round(((clock as u64) * 1000) << shift, 16 * top) as u32
+}
+
+/// Tone period producing `millihertz` at `clock`, rounded to nearest and
+/// unclamped like `pitch_period`. Returns `u32::MAX` for 0 Hz.
+pub fn millihertz_to_period(clock: u32, millihertz: u32) -> u32 {
+    if millihertz == 0 {
+        return u32::MAX;
+    }
+    div_round((clock as u64) * 1000, 16 * millihertz as u64).min(u32::MAX as u64) as u32
+}
+
+/// Frequency in millihertz actually produced by `period` at `clock`.
+pub fn period_to_millihertz(clock: u32, period: u16) -> u32 {
+    let period = period.max(1) as u64;
+    div_round((clock as u64) * 1000, 16 * period) as u32
+}
+
+/// The equal-tempered pitch nearest to what tone `period` plays at `clock`,
+/// and how many cents sharp of it that is, -50 to 50, rounded up to the
+/// cent. `None` more than half a semitone past either end of the MIDI
+/// range.
+#[cfg(feature = "music")]
+pub fn nearest_pitch(clock: u32, period: u16) -> Option<(Pitch, i8)> {
+    let period = period.max(1);
+    let pitch = |midi: u8| Pitch::from_midi(midi);
+    // The lowest note at least as sharp as the period, note periods falling
+    // as the notes rise; 128 if there is none.
+    let (mut low, mut high) = (0, 128);
+    while low < high {
+        let midi = low + (high - low) / 2;
+        match pitch_period(clock, pitch(midi)?) <= period as u32 {
+            true => high = midi,
+            false => low = midi + 1,
+        }
+    }
+    let sharp_of = |pitch: Pitch| {
+        // The note's period unrounded, in 1/256ths.
+        let midi = pitch.midi() as u32;
+        let top = TOP_OCTAVE_MILLIHERTZ[(midi % 12) as usize];
+        let note = div_round((clock as u64 * 1000) << (18 - midi / 12), 16 * top);
+        let note = note.clamp(1, u32::MAX as u64) as u32;
+        (pitch, cents_between(note, (period as u32) << 8))
+    };
+    let sharper = pitch(low).map(sharp_of);
+    let flatter = low.checked_sub(1).and_then(pitch).map(sharp_of);
+    [sharper, flatter]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(_, cents)| cents.abs())
+        .filter(|(_, cents)| cents.abs() <= 50)
+        .map(|(pitch, cents)| (pitch, cents as i8))
+}
+
+/// How often the noise generator picks a new value at `clock` with `period`,
+/// in hertz; period 0 runs as 1.
+pub fn noise_hertz(clock: u32, period: u8) -> u32 {
+    div_round(clock as u64, 16 * (period & MAX_NOISE_PERIOD).max(1) as u64) as u32
+}
+
+/// Noise period whose generator runs closest to `hertz` at `clock`, clamped
+/// to the 5-bit register. The noise generator divides the clock by 16 like
+/// the tone generators, so higher periods give a lower, rougher hiss.
+pub fn noise_period(clock: u32, hertz: u32) -> u8 {
+    let period = div_round(clock as u64, 16 * hertz.max(1) as u64);
+    period.clamp(1, MAX_NOISE_PERIOD as u64) as u8
+}
+
+/// Envelope period whose ramp repeats closest to `millihertz` at `clock`,
+/// clamped to 1..=0xFFFF. A ramp takes `256 * period` clock cycles: 32 steps
+/// on the YM2149, or 16 twice as long on the AY-3-8910.
+pub fn envelope_period(clock: u32, millihertz: u32) -> u16 {
+    let period = div_round((clock as u64) * 1000, 256 * millihertz.max(1) as u64);
+    period.clamp(1, u16::MAX as u64) as u16
+}
+
+/// How many times a second, in millihertz, an envelope ramp with `period`
+/// plays at `clock`, on any chip of the family.
+pub fn envelope_millihertz(clock: u32, period: u16) -> u32 {
+    div_round(clock as u64 * 1000, 256 * period.max(1) as u64) as u32
+}
+
+/// How many times a second, in millihertz, the envelope moves a step on
+/// `chip` at `clock` with `period`: the ramp takes as long on either chip,
+/// so the AY-3-8910's 16 steps come half as often as the YM2149's 32.
+pub fn envelope_step_millihertz(chip: ChipKind, clock: u32, period: u16) -> u32 {
+    let cycles = 256 * period.max(1) as u64;
+    div_round(clock as u64 * 1000 * chip.envelope_steps() as u64, cycles) as u32
+}
+
+/// `2^(cents/1200)` in Q16 for `cents` in 0..1200.
+fn octave_fraction_q16(cents: u32) -> u64 {
+    let semitones = (cents / 100) as usize;
+    let fine = (cents % 100) as usize;
+    (SEMITONE_RATIO_Q16[semitones] * CENT_RATIO_Q16[fine]) >> 16
+}
+
+/// Scales `period` so the resulting pitch moves by `cents` (positive is
+/// sharper, i.e. a shorter period). The result is rounded and unclamped.
+pub fn detune_period(period: u32, cents: i32) -> u32 {
+    let octaves = cents.div_euclid(1200);
+    let ratio = octave_fraction_q16(cents.rem_euclid(1200) as u32);
+    let mut scaled = (period as u64) << 16;
+    if octaves < 0 {
+        scaled <<= (-octaves).min(31) as u32;
+    } else {
+        scaled >>= octaves.min(63) as u32;
+    }
+    div_round(scaled, ratio).min(u32::MAX as u64) as u32
+}
+
+/// Interval between two periods in cents, positive when `to` is sharper than
+/// `from`. Accurate to about a cent, which is all the integer periods allow.
+pub fn period_cents(from: u16, to: u16) -> i32 {
+    cents_between((from.max(1) as u32) << 8, (to.max(1) as u32) << 8)
+}
+
+/// [`period_cents`] for periods in 1/256ths.
+fn cents_between(from: u32, to: u32) -> i32 {
+    if from == to {
+        return 0;
+    }
+    // Binary search in detune_period, which is monotonic in cents.
+    let (mut low, mut high) = (-12_000i32, 12_000i32);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if detune_period(from, mid) > to {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Which way a frequency between two tone periods goes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// To the nearest period, exact halves going to the longer one, as every
+    /// other conversion here does.
+    #[default]
+    Nearest,
+    /// To the highest frequency at or below the one asked for: the period
+    /// rounded up, so a note is never sharp.
+    Floor,
+    /// To the lowest frequency at or above the one asked for: the period
+    /// rounded down, so a note is never flat.
+    Ceil,
+}
+
+/// What to do with a frequency whose period, once rounded, is outside
+/// 1..=[`MAX_TONE_PERIOD`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OutOfRange {
+    /// Refuse it, writing nothing.
+    Error,
+    /// Move it by whole octaves until it fits, rounding again each time.
+    #[default]
+    FoldOctave,
+    /// Play the end of the range nearest it.
+    Clamp,
+}
+
+/// How pitches and frequencies are turned into tone periods. The default,
+/// to the nearest period and folded into range, is what the chip's
+/// pitch setters have always done.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct FrequencyPolicy {
+    pub rounding: Rounding,
+    pub out_of_range: OutOfRange,
+}
+
+impl FrequencyPolicy {
+    pub const fn new(rounding: Rounding, out_of_range: OutOfRange) -> FrequencyPolicy {
+        FrequencyPolicy {
+            rounding,
+            out_of_range,
+        }
+    }
+
+    pub const fn with_rounding(mut self, rounding: Rounding) -> FrequencyPolicy {
+        self.rounding = rounding;
+        self
+    }
+
+    pub const fn with_out_of_range(mut self, out_of_range: OutOfRange) -> FrequencyPolicy {
+        self.out_of_range = out_of_range;
+        self
+    }
+}
+
+/// Why a frequency or pitch wasn't played.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrequencyError<E> {
+    /// Its period is out of range, and the policy is [`OutOfRange::Error`].
+    OutOfRange,
+    /// Writing to the chip failed.
+    Psg(E),
+}
+
+/// A tone period known to fit the registers, how many octaves the requested
+/// pitch had to be moved to get there, and what it plays.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FoldedPeriod {
+    pub period: u16,
+    /// Octaves the pitch was moved: positive means raised.
+    pub octaves: i8,
+    /// The frequency the period plays, in millihertz, after any rounding,
+    /// folding or clamping.
+    pub millihertz: u32,
+}
+
+impl FoldedPeriod {
+    /// Whether the pitch had to be moved at all.
+    pub fn folded(&self) -> bool {
+        self.octaves != 0
+    }
+}
+
+/// Tone period for `pitch`, moving it by whole octaves until it fits the
+/// 12-bit period range. Only a clock of 0, which no octave fits, fails to
+/// fold; that is clamped instead.
+#[cfg(feature = "music")]
+pub fn fold_pitch_period(clock: u32, pitch: Pitch) -> FoldedPeriod {
+    let (numerator, denominator) = pitch_fraction(clock, pitch);
+    fold(clock, numerator, denominator, Rounding::Nearest)
+}
+
+/// Tone period for `pitch` at `clock` under `policy`, or `None` if it is out
+/// of range and the policy refuses it.
+#[cfg(feature = "music")]
+pub fn pitch_tone(clock: u32, pitch: Pitch, policy: FrequencyPolicy) -> Option<FoldedPeriod> {
+    let (numerator, denominator) = pitch_fraction(clock, pitch);
+    fit(clock, numerator, denominator, policy)
+}
+
+/// Tone period playing `millihertz` at `clock` under `policy`, or `None` if
+/// it is out of range and the policy refuses it. 0 Hz is below the range,
+/// and can't be folded into it: folding clamps it instead.
+pub fn tone_period(clock: u32, millihertz: u32, policy: FrequencyPolicy) -> Option<FoldedPeriod> {
+    fit(clock, clock as u64 * 1000, 16 * millihertz as u64, policy)
+}
+
+/// A value that has no register setting at the clock it was asked for,
+/// from the `checked_` conversions, in the unit it was asked in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RangeError<T> {
+    pub requested: T,
+    /// The lowest and highest values that do have one, or `None` if none
+    /// do at this clock.
+    pub range: Option<(T, T)>,
+    /// The end of the range nearest the value asked for.
+    pub nearest: Option<T>,
+}
+
+impl<T> RangeError<T> {
+    fn map<U>(self, f: impl Fn(T) -> U) -> RangeError<U> {
+        RangeError {
+            requested: f(self.requested),
+            range: self.range.map(|(low, high)| (f(low), f(high))),
+            nearest: self.nearest.map(f),
+        }
+    }
+}
+
+/// Tone period for `pitch` at `clock`, rounded to nearest, or an error if
+/// that is outside 1..=[`MAX_TONE_PERIOD`]. Unlike [`fold_pitch_period`],
+/// the pitch is never moved.
+#[cfg(feature = "music")]
+pub fn checked_note_to_period(clock: u32, pitch: Pitch) -> Result<u16, RangeError<Pitch>> {
+    let period = |midi| pitch_period(clock, Pitch::from_midi(midi as u8).unwrap()) as u64;
+    check(pitch.midi() as u64, 128, MAX_TONE_PERIOD as u64, period)
+        .map(|period| period as u16)
+        .map_err(|error| error.map(|midi| Pitch::from_midi(midi as u8).unwrap()))
+}
+
+/// Tone period for `hz` at `clock`, rounded to nearest, or an error if that
+/// is outside 1..=[`MAX_TONE_PERIOD`].
+pub fn checked_hz_to_period(clock: u32, hz: u32) -> Result<u16, RangeError<u32>> {
+    let period = |hz| divide(clock as u64, 16 * hz);
+    checked(hz, MAX_TONE_PERIOD as u64, period).map(|period| period as u16)
+}
+
+/// Envelope period for `millihertz` at `clock`, rounded to nearest, or an
+/// error if that is outside 1..=0xFFFF, where [`envelope_period`] clamps.
+pub fn checked_envelope_period(clock: u32, millihertz: u32) -> Result<u16, RangeError<u32>> {
+    let period = |millihertz| divide(clock as u64 * 1000, 256 * millihertz);
+    checked(millihertz, u16::MAX as u64, period).map(|period| period as u16)
+}
+
+/// Noise period for `hertz` at `clock`, rounded to nearest, or an error if
+/// that is outside 1..=[`MAX_NOISE_PERIOD`], where [`noise_period`] clamps.
+pub fn checked_noise_period(clock: u32, hertz: u32) -> Result<u8, RangeError<u32>> {
+    let period = |hertz| divide(clock as u64, 16 * hertz);
+    checked(hertz, MAX_NOISE_PERIOD as u64, period).map(|period| period as u8)
+}
+
+/// [`div_round`], with nothing at all taken as an endless period.
+fn divide(numerator: u64, denominator: u64) -> u64 {
+    match denominator {
+        0 => u64::MAX,
+        _ => div_round(numerator, denominator),
+    }
+}
+
+/// [`check`] over every `u32`.
+fn checked(value: u32, max: u64, period: impl Fn(u64) -> u64) -> Result<u64, RangeError<u32>> {
+    check(value as u64, 1 << 32, max, period).map_err(|error| error.map(|value| value as u32))
+}
+
+/// `value`'s period, if it is in 1..=`max`, for a conversion over
+/// `0..end` whose periods fall as the values rise.
+fn check(
+    value: u64,
+    end: u64,
+    max: u64,
+    period: impl Fn(u64) -> u64,
+) -> Result<u64, RangeError<u64>> {
+    match period(value) {
+        period if (1..=max).contains(&period) => Ok(period),
+        _ => {
+            let lowest = partition(end, |value| period(value) <= max);
+            let past = partition(end, |value| period(value) == 0);
+            let range = (lowest < past).then_some((lowest, past - 1));
+            Err(RangeError {
+                requested: value,
+                range,
+                nearest: range.map(|(low, high)| value.clamp(low, high)),
+            })
+        }
+    }
+}
+
+/// The first of `0..end` that `past` holds for, it holding for all after;
+/// `end` if none.
+fn partition(end: u64, past: impl Fn(u64) -> bool) -> u64 {
+    let (mut low, mut high) = (0, end);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match past(middle) {
+            true => high = middle,
+            false => low = middle + 1,
+        }
+    }
+    low
+}
+
+/// `pitch`'s period at `clock` unrounded, as a fraction.
+#[cfg(feature = "music")]
+fn pitch_fraction(clock: u32, pitch: Pitch) -> (u64, u64) {
+    let midi = pitch.midi() as u32;
+    let top = TOP_OCTAVE_MILLIHERTZ[(midi % 12) as usize];
+    ((clock as u64 * 1000) << (10 - midi / 12), 16 * top)
+}
+
+/// The period `numerator / denominator` fits in under `policy`.
+fn fit(
+    clock: u32,
+    numerator: u64,
+    denominator: u64,
+    policy: FrequencyPolicy,
+) -> Option<FoldedPeriod> {
+    let period = match denominator {
+        0 => u64::MAX,
+        _ => round(numerator, denominator, policy.rounding),
+    };
+    let tone = |period: u64, octaves| FoldedPeriod {
+        period: period as u16,
+        octaves,
+        millihertz: period_to_millihertz(clock, period as u16),
+    };
+    match policy.out_of_range {
+        _ if fits(period) => Some(tone(period, 0)),
+        OutOfRange::Error => None,
+        OutOfRange::FoldOctave => Some(fold(clock, numerator, denominator, policy.rounding)),
+        OutOfRange::Clamp => Some(tone(period.clamp(1, MAX_TONE_PERIOD as u64), 0)),
+    }
+}
+
+/// The period `numerator / denominator` folds to, halving or doubling it
+/// until it rounds into range. Periods of 0 or none at all can't be, so are
+/// clamped.
+fn fold(clock: u32, numerator: u64, denominator: u64, rounding: Rounding) -> FoldedPeriod {
+    let (mut numerator, mut denominator, mut octaves) = (numerator, denominator, 0i8);
+    let period = loop {
+        if numerator == 0 || denominator == 0 {
+            break match denominator {
+                0 => MAX_TONE_PERIOD as u64,
+                _ => 1,
+            };
+        }
+        match round(numerator, denominator, rounding) {
+            period if fits(period) => break period,
+            0 => {
+                numerator *= 2;
+                octaves -= 1;
+            }
+            _ => {
+                denominator *= 2;
+                octaves += 1;
+            }
+        }
+    };
+    FoldedPeriod {
+        period: period as u16,
+        octaves,
+        millihertz: period_to_millihertz(clock, period as u16),
+    }
+}
+
+/// Whether the registers hold `period`.
+fn fits(period: u64) -> bool {
+    (1..=MAX_TONE_PERIOD as u64).contains(&period)
+}
+
+/// `numerator / denominator` rounded as a period, the opposite way to the
+/// frequency it plays.
+fn round(numerator: u64, denominator: u64, rounding: Rounding) -> u64 {
+    match rounding {
+        Rounding::Nearest => div_round(numerator, denominator),
+        Rounding::Floor => numerator.div_ceil(denominator),
+        Rounding::Ceil => numerator / denominator,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "music")]
+    use crate::pitch::Note;
+
+    #[test]
+    fn periods_from_frequencies() {
+        // 2 MHz / (16 * 440 Hz) = 284.09
+        assert_eq!(millihertz_to_period(2_000_000, 440_000), 284);
+        assert_eq!(millihertz_to_period(1_000_000, 440_000), 142);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn a4_matches_closed_form() {
+        let a4 = Pitch::new(Note::A, 4);
+        assert_eq!(pitch_millihertz(a4), 440_000);
+        // 2 MHz / (16 * 440 Hz) = 284.09
+        assert_eq!(pitch_period(2_000_000, a4), 284);
+        assert_eq!(pitch_period(1_000_000, a4), 142);
+        assert_eq!(millihertz_to_period(2_000_000, 440_000), 284);
+    }
+
+    #[test]
+    fn envelope_periods() {
+        // 2 MHz / (256 * 440 Hz) = 17.76
+        assert_eq!(envelope_period(2_000_000, 440_000), 18);
+        assert_eq!(envelope_period(2_000_000, 0), u16::MAX);
+        assert_eq!(envelope_period(2_000_000, 100_000_000), 1);
+        // One period of 1 at 2 MHz: a ramp 7812.5 times a second.
+        let steps = |chip| envelope_step_millihertz(chip, 2_000_000, 1);
+        assert_eq!(steps(ChipKind::Ym2149), 250_000_000);
+        assert_eq!(steps(ChipKind::Ay8910), 125_000_000);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn low_notes_exceed_twelve_bits() {
+        assert!(pitch_period(2_000_000, Pitch::new(Note::C, 0)) > MAX_TONE_PERIOD as u32);
+        assert!(pitch_period(2_000_000, Pitch::new(Note::B, 0)) <= MAX_TONE_PERIOD as u32);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn folding_moves_low_notes_up() {
+        let folded = fold_pitch_period(2_000_000, Pitch::new(Note::C, 0));
+        assert_eq!(folded.octaves, 1);
+        assert_eq!(folded.period, 3822);
+        let unfolded = fold_pitch_period(1_000_000, Pitch::new(Note::C, 0));
+        assert!(!unfolded.folded());
+        assert_eq!(unfolded.period, 3822);
+    }
+
+    /// Every combination of policy on `millihertz` at `clock`, as the period
+    /// and octaves each gives, in the order Nearest, Floor, Ceil and within
+    /// that Error, FoldOctave, Clamp.
+    fn policies(clock: u32, millihertz: u32) -> [Option<(u16, i8)>; 9] {
+        let mut got = [None; 9];
+        let roundings = [Rounding::Nearest, Rounding::Floor, Rounding::Ceil];
+        let ranges = [OutOfRange::Error, OutOfRange::FoldOctave, OutOfRange::Clamp];
+        for (i, &rounding) in roundings.iter().enumerate() {
+            for (j, &out_of_range) in ranges.iter().enumerate() {
+                let policy = FrequencyPolicy::new(rounding, out_of_range);
+                let tone = tone_period(clock, millihertz, policy);
+                if let Some(tone) = tone {
+                    assert_eq!(tone.millihertz, period_to_millihertz(clock, tone.period));
+                }
+                got[i * 3 + j] = tone.map(|tone| (tone.period, tone.octaves));
+            }
+        }
+        got
+    }
+
+    #[test]
+    fn policies_round_exact_halves() {
+        // 2 MHz / (16 * 80 Hz) is 1562.5.
+        let both = |period| [Some((period, 0)); 3];
+        let expected = [both(1563), both(1563), both(1562)].concat();
+        assert_eq!(policies(2_000_000, 80_000), *expected);
+        let tone = |rounding| {
+            let policy = FrequencyPolicy::default().with_rounding(rounding);
+            tone_period(2_000_000, 80_000, policy).unwrap().millihertz
+        };
+        // Floor never plays sharp and Ceil never flat.
+        assert_eq!(tone(Rounding::Nearest), 79_974);
+        assert_eq!(tone(Rounding::Floor), 79_974);
+        assert_eq!(tone(Rounding::Ceil), 80_026);
+    }
+
+    #[test]
+    fn policies_at_the_bottom_of_the_range() {
+        // 4095.004: only rounding up leaves the registers.
+        assert_eq!(
+            policies(2_000_000, 30_525),
+            [
+                Some((4095, 0)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+                None,
+                Some((2048, 1)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+            ]
+        );
+        // 4096.076: out whichever way it goes, and 2048.04 an octave up.
+        assert_eq!(
+            policies(2_000_000, 30_517),
+            [
+                None,
+                Some((2048, 1)),
+                Some((4095, 0)),
+                None,
+                Some((2049, 1)),
+                Some((4095, 0)),
+                None,
+                Some((2048, 1)),
+                Some((4095, 0)),
+            ]
+        );
+        // 131.056 kHz / (16 * 2 Hz) is 4095.5, an exact half the nearest
+        // period of which is out of range.
+        assert_eq!(
+            policies(131_056, 2000),
+            [
+                None,
+                Some((2048, 1)),
+                Some((4095, 0)),
+                None,
+                Some((2048, 1)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+                Some((4095, 0)),
+            ]
+        );
+        // The clamped and folded frequencies are what they play.
+        let fold = FrequencyPolicy::default();
+        assert_eq!(tone_period(131_056, 2000, fold).unwrap().millihertz, 4000);
+        let clamp = fold.with_out_of_range(OutOfRange::Clamp);
+        assert_eq!(tone_period(131_056, 2000, clamp).unwrap().millihertz, 2000);
+        // 0 Hz can't be folded into range.
+        assert_eq!(
+            policies(2_000_000, 0),
+            [None, Some((4095, 0)), Some((4095, 0))].repeat(3)[..]
+        );
+    }
+
+    #[test]
+    fn policies_at_the_top_of_the_range() {
+        // 2 MHz / (16 * 250 kHz) is 0.5, which rounds to 1 unless rounded
+        // down; 200 kHz is 0.625.
+        for millihertz in [250_000_000, 200_000_000] {
+            assert_eq!(
+                policies(2_000_000, millihertz),
+                [
+                    Some((1, 0)),
+                    Some((1, 0)),
+                    Some((1, 0)),
+                    Some((1, 0)),
+                    Some((1, 0)),
+                    Some((1, 0)),
+                    None,
+                    Some((1, -1)),
+                    Some((1, 0)),
+                ]
+            );
+        }
+        // 0.25 rounds to 0 unless rounded up, and folds to 0.5, which rounds
+        // to 1, and then 1, which also rounds down to it.
+        assert_eq!(
+            policies(2_000_000, 500_000_000),
+            [
+                None,
+                Some((1, -1)),
+                Some((1, 0)),
+                Some((1, 0)),
+                Some((1, 0)),
+                Some((1, 0)),
+                None,
+                Some((1, -2)),
+                Some((1, 0)),
+            ]
+        );
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn pitches_follow_the_policy() {
+        let tone = |pitch, rounding, out_of_range| {
+            let policy = FrequencyPolicy::new(rounding, out_of_range);
+            pitch_tone(2_000_000, pitch, policy).map(|tone| (tone.period, tone.octaves))
+        };
+        // 284.09, and 4290.4 at the bottom.
+        let a4 = Pitch::new(Note::A, 4);
+        assert_eq!(
+            tone(a4, Rounding::Nearest, OutOfRange::Error),
+            Some((284, 0))
+        );
+        assert_eq!(tone(a4, Rounding::Floor, OutOfRange::Error), Some((285, 0)));
+        assert_eq!(tone(a4, Rounding::Ceil, OutOfRange::Error), Some((284, 0)));
+        let a_sharp_0 = Pitch::new(Note::ASharp, 0);
+        assert_eq!(tone(a_sharp_0, Rounding::Nearest, OutOfRange::Error), None);
+        assert_eq!(
+            tone(a_sharp_0, Rounding::Nearest, OutOfRange::Clamp),
+            Some((4095, 0))
+        );
+        assert_eq!(
+            tone(a_sharp_0, Rounding::Nearest, OutOfRange::FoldOctave),
+            Some((2145, 1))
+        );
+        let folded = fold_pitch_period(2_000_000, a_sharp_0);
+        assert_eq!((folded.period, folded.octaves), (2145, 1));
+        assert_eq!(folded.millihertz, period_to_millihertz(2_000_000, 2145));
+    }
+
+    /// Checks that `convert` takes exactly `range` at `clock`, the values
+    /// either side of it failing with it as their nearest.
+    fn edges<P: PartialEq + core::fmt::Debug>(
+        convert: impl Fn(u32, u32) -> Result<P, RangeError<u32>>,
+        clock: u32,
+        range: (u32, u32),
+    ) {
+        let (low, high) = range;
+        assert!(convert(clock, low).is_ok() && convert(clock, high).is_ok());
+        for (value, nearest) in [(0, low), (low - 1, low), (high + 1, high), (u32::MAX, high)] {
+            let error = RangeError {
+                requested: value,
+                range: Some(range),
+                nearest: Some(nearest),
+            };
+            assert_eq!(convert(clock, value), Err(error), "{value} at {clock}");
+        }
+    }
+
+    #[test]
+    fn checked_tones_at_their_edges() {
+        // 2 MHz / (16 * 31 Hz) is 4032.3 and / (16 * 30 Hz) 4166.7, and
+        // 250 kHz is 0.5, rounding up to 1.
+        assert_eq!(checked_hz_to_period(2_000_000, 31), Ok(4032));
+        assert_eq!(checked_hz_to_period(2_000_000, 250_000), Ok(1));
+        edges(checked_hz_to_period, 2_000_000, (31, 250_000));
+        edges(checked_hz_to_period, 1_000_000, (16, 125_000));
+        edges(checked_hz_to_period, 1_773_400, (28, 221_675));
+        // Nothing fits at all with no clock.
+        let none = RangeError {
+            requested: 440,
+            range: None,
+            nearest: None,
+        };
+        assert_eq!(checked_hz_to_period(0, 440), Err(none));
+        // The forgiving conversions still fold and clamp.
+        let fold = tone_period(2_000_000, 30_000, FrequencyPolicy::default()).unwrap();
+        assert_eq!((fold.period, fold.octaves), (2083, 1));
+    }
+
+    #[test]
+    fn checked_noise_and_envelopes_at_their_edges() {
+        // 2 MHz / (16 * 3969 Hz) is 31.49.
+        assert_eq!(checked_noise_period(2_000_000, 3969), Ok(31));
+        edges(checked_noise_period, 2_000_000, (3969, 250_000));
+        edges(checked_noise_period, 1_000_000, (1985, 125_000));
+        edges(checked_noise_period, 1_773_400, (3519, 221_675));
+        assert_eq!(noise_period(2_000_000, 3968), MAX_NOISE_PERIOD);
+        // 2 MHz / (256 * 0.12 Hz) is 65104.2 and / (256 * 0.119 Hz) 65651.
+        assert_eq!(checked_envelope_period(2_000_000, 120), Ok(65104));
+        edges(checked_envelope_period, 2_000_000, (120, 15_625_000));
+        edges(checked_envelope_period, 1_000_000, (60, 7_812_500));
+        edges(checked_envelope_period, 1_773_400, (106, 13_854_687));
+        assert_eq!(envelope_period(2_000_000, 119), u16::MAX);
+    }
+
+    #[cfg(feature = "music")]
+    #[test]
+    fn checked_notes_at_their_edges() {
+        let midi = |midi| Pitch::from_midi(midi).unwrap();
+        let error = |requested, range: (u8, u8), nearest| RangeError {
+            requested: midi(requested),
+            range: Some((midi(range.0), midi(range.1))),
+            nearest: Some(midi(nearest)),
+        };
+        // B0 is 4050.1 at 2 MHz, and A#0 4290.4; nothing is too high.
+        let b0 = Pitch::new(Note::B, 0);
+        assert_eq!(checked_note_to_period(2_000_000, b0), Ok(4050));
+        assert_eq!(
+            checked_note_to_period(2_000_000, midi(22)),
+            Err(error(22, (23, 127), 23))
+        );
+        assert_eq!(checked_note_to_period(2_000_000, midi(127)), Ok(10));
+        // An octave lower at 1 MHz.
+        assert_eq!(checked_note_to_period(1_000_000, midi(11)), Ok(4050));
+        assert_eq!(
+            checked_note_to_period(1_000_000, midi(10)),
+            Err(error(10, (11, 127), 11))
+        );
+        // At 100 kHz G9's 0.498 rounds to nothing, F#9's 0.528 to 1.
+        assert_eq!(checked_note_to_period(100_000, midi(126)), Ok(1));
+        assert_eq!(
+            checked_note_to_period(100_000, midi(127)),
+            Err(error(127, (0, 126), 126))
+        );
+        // At the Spectrum 128's 1.7734 MHz, A0 is 4030.5 and G#0 4269.1.
+        let range = checked_note_to_period(1_773_400, midi(0))
+            .unwrap_err()
+            .range;
+        assert_eq!(range, Some((midi(21), midi(127))));
+        // Where folding would have moved it.
+        assert_eq!(fold_pitch_period(2_000_000, midi(22)).octaves, 1);
+    }
+
+    #[test]
+    fn detune_by_octaves_and_cents() {
+        assert_eq!(detune_period(1000, 0), 1000);
+        assert_eq!(detune_period(1000, 1200), 500);
+        assert_eq!(detune_period(1000, -1200), 2000);
+        // One equal-tempered semitone up: 1000 / 1.05946
+        assert_eq!(detune_period(1000, 100), 944);
+        assert_eq!(detune_period(1000, -100), 1059);
+    }
+
+    #[test]
+    fn cents_between_periods() {
+        assert_eq!(period_cents(1000, 1000), 0);
+        assert!((period_cents(1000, 500) - 1200).abs() <= 1);
+        assert!((period_cents(1000, 944) - 100).abs() <= 1);
+        assert!((period_cents(944, 1000) + 100).abs() <= 1);
+    }
+}
@@ -0,0 +1,137 @@
+//! Two channels playing one note slightly apart, for a fatter lead.
+
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{self, MAX_TONE_PERIOD};
+use crate::{Channel, ChannelLevel};
+
+/// A note doubled across two channels, detuned by a few cents.
+///
+/// While a `Unison` is held, change the note with [`Unison::set_pitch`] so
+/// both channels move together. [`Unison::release`] hands the channels back
+/// for independent use; it writes nothing, so they keep sounding until
+/// something else silences or retunes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unison {
+    primary: Channel,
+    secondary: Channel,
+    detune_cents: i8,
+    achieved_cents: i32,
+}
+
+impl Unison {
+    /// Starts `pitch` on both channels at `level` with tone enabled. Positive
+    /// `detune_cents` puts the secondary channel sharp of the primary.
+    pub fn play<P: Psg>(
+        psg: &mut P,
+        primary: Channel,
+        secondary: Channel,
+        pitch: Pitch,
+        level: u8,
+        detune_cents: i8,
+    ) -> Result<Unison, P::Error> {
+        let mut unison = Unison {
+            primary,
+            secondary,
+            detune_cents,
+            achieved_cents: 0,
+        };
+        unison.set_pitch(psg, pitch)?;
+        for channel in [primary, secondary] {
+            psg.update_channel_level(channel, ChannelLevel::Fixed(level))?;
+            psg.set_tone_enabled(channel, true)?;
+        }
+        Ok(unison)
+    }
+
+    /// Moves both channels to `pitch`, keeping the configured detune.
+    pub fn set_pitch<P: Psg>(&mut self, psg: &mut P, pitch: Pitch) -> Result<(), P::Error> {
+        let primary = tuning::fold_pitch_period(psg.master_clock(), pitch).period;
+        let secondary = detuned_period(primary, self.detune_cents);
+        psg.set_channel_period(self.primary, primary)?;
+        psg.set_channel_period(self.secondary, secondary)?;
+        self.achieved_cents = tuning::period_cents(primary, secondary);
+        Ok(())
+    }
+
+    /// The detune actually produced by the last pitch, in cents. It differs
+    /// from the requested amount at high pitches, where periods are coarse.
+    pub fn achieved_cents(&self) -> i32 {
+        self.achieved_cents
+    }
+
+    pub fn channels(&self) -> (Channel, Channel) {
+        (self.primary, self.secondary)
+    }
+
+    /// Ends the pairing and returns the two channels.
+    pub fn release(self) -> (Channel, Channel) {
+        (self.primary, self.secondary)
+    }
+}
+
+/// Period for the secondary channel, at least one step from `primary`
+/// whenever any detune was requested. At the ends of the period range the
+/// step goes the other way rather than not at all.
+fn detuned_period(primary: u16, detune_cents: i8) -> u16 {
+    let period = tuning::detune_period(primary as u32, detune_cents as i32)
+        .clamp(1, MAX_TONE_PERIOD as u32) as u16;
+    if detune_cents == 0 || period != primary {
+        return period;
+    }
+    let sharper = detune_cents > 0;
+    match (sharper, primary) {
+        (true, 1) => 2,
+        (true, _) => primary - 1,
+        (false, MAX_TONE_PERIOD) => MAX_TONE_PERIOD - 1,
+        (false, _) => primary + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+
+    #[test]
+    fn small_detune_at_high_pitch_still_separates() {
+        let mut psg = FakePsg::new();
+        // C8 is period 30 at 2 MHz, where 3 cents is far less than one step.
+        let pitch = Pitch::new(Note::C, 8);
+        let unison = Unison::play(&mut psg, Channel::A, Channel::B, pitch, 13, 3).unwrap();
+        let registers = psg.registers();
+        assert_eq!(registers.tone_period(Channel::A), 30);
+        assert_eq!(registers.tone_period(Channel::B), 29);
+        assert!(unison.achieved_cents() > 50);
+        assert_eq!(registers.mixer() & 0b11, 0);
+    }
+
+    #[test]
+    fn detune_follows_pitch_changes() {
+        let mut psg = FakePsg::new();
+        let mut unison = Unison::play(
+            &mut psg,
+            Channel::B,
+            Channel::C,
+            Pitch::new(Note::A, 2),
+            10,
+            -10,
+        )
+        .unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::B), 1136);
+        assert_eq!(psg.registers().tone_period(Channel::C), 1143);
+        assert!((unison.achieved_cents() + 10).abs() <= 1);
+
+        unison.set_pitch(&mut psg, Pitch::new(Note::A, 3)).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::B), 568);
+        assert_eq!(psg.registers().tone_period(Channel::C), 571);
+    }
+
+    #[test]
+    fn zero_detune_is_exact_unison() {
+        assert_eq!(detuned_period(30, 0), 30);
+        assert_eq!(detuned_period(1, 5), 2);
+        assert_eq!(detuned_period(MAX_TONE_PERIOD, -5), MAX_TONE_PERIOD - 1);
+    }
+}
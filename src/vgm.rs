@@ -0,0 +1,664 @@
+//! VGM chip-music rips, playing the AY-3-8910 family's register writes.
+//!
+//! A file is a header, `Vgm ` and little-endian fields at fixed offsets,
+//! with any offsets in it counted from where they are stored, then a stream
+//! of commands, each a byte and its operands. Time is counted in samples at
+//! 44.1 kHz, and the stream says how long to wait between writes:
+//!
+//! - `0xA0` then a register and a value: a write to the AY chip, the second
+//!   chip if the register's top bit is set
+//! - `0x61` then a 16-bit count, `0x62`, `0x63`, and `0x70` to `0x7F`: waits
+//!   of that many samples, a 60 Hz frame, a 50 Hz frame and 1 to 16 samples
+//! - `0x66`: the end of the data, where a song with a loop goes back to it
+//!
+//! Commands for the many other chips VGM covers are [skipped or
+//! refused](OtherChips), as are writes to the second AY.
+
+use crate::frame_player::{self, PlayTime};
+use crate::gd3::Gd3;
+use crate::parse_error::ParseError;
+use crate::psg::Psg;
+
+/// Samples a second in every VGM file.
+pub const SAMPLE_RATE: u32 = 44_100;
+
+pub(crate) const MAGIC: &[u8; 4] = b"Vgm ";
+pub(crate) const AY_WRITE: u8 = 0xA0;
+pub(crate) const WAIT: u8 = 0x61;
+pub(crate) const WAIT_60HZ: u8 = 0x62;
+pub(crate) const WAIT_50HZ: u8 = 0x63;
+pub(crate) const END: u8 = 0x66;
+const DATA_BLOCK: u8 = 0x67;
+
+/// Commands a tick may carry out with no time passing. Real rips write a
+/// few dozen registers at once; a file of nothing but writes would hold
+/// the tick up for as long as it took to read it.
+pub const MAX_COMMANDS_WITHOUT_WAIT: u32 = 4096;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VgmErrorKind {
+    /// There is no `Vgm ` header.
+    NotVgm,
+    /// The header or a command runs past the end of the data.
+    Truncated,
+    /// The data or loop offset points outside the file.
+    BadOffset,
+    /// A command byte VGM doesn't define.
+    UnknownCommand(u8),
+    /// A command for a chip other than the first AY, refused by
+    /// [`OtherChips::Fail`].
+    OtherChip(u8),
+    /// A write to a register past R15.
+    BadRegister(u8),
+    /// More than [`MAX_COMMANDS_WITHOUT_WAIT`] commands in a row with no
+    /// wait between them.
+    NoWait,
+}
+
+/// What is wrong with a VGM file, and the byte it was found at.
+pub type VgmError = ParseError<VgmErrorKind>;
+
+/// What to do with commands for chips the player doesn't drive.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum OtherChips {
+    /// Step over them, playing the AY part of a multi-chip rip.
+    #[default]
+    Skip,
+    /// Stop with [`VgmErrorKind::OtherChip`].
+    Fail,
+}
+
+/// How many bytes `command` takes with its operands, where that is fixed.
+const fn command_length(command: u8) -> Option<usize> {
+    match command {
+        0x30..=0x3F | 0x4F | 0x50 | 0x94 => Some(2),
+        0x40..=0x4E | 0x51..=0x5F | 0x61 | 0xA0..=0xBF => Some(3),
+        0x62 | 0x63 | 0x66 | 0x70..=0x8F => Some(1),
+        0x90 | 0x91 | 0x95 => Some(5),
+        0x92 => Some(6),
+        0x93 => Some(11),
+        0x68 => Some(12),
+        0xC0..=0xDF => Some(4),
+        0xE0..=0xFF => Some(5),
+        _ => None,
+    }
+}
+
+fn le(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// An offset stored at `at`, counted from there, or `None` where it is 0.
+fn offset(data: &[u8], at: usize) -> Result<Option<usize>, VgmError> {
+    match le(data, at).ok_or(VgmError::new(VgmErrorKind::Truncated, at))? {
+        0 => Ok(None),
+        // Too far to count is as far outside the file as can be.
+        relative => Ok(Some(
+            usize::try_from(relative).map_or(usize::MAX, |relative| at.saturating_add(relative)),
+        )),
+    }
+}
+
+/// A VGM file, checked as far as its header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VgmSong<'a> {
+    data: &'a [u8],
+    version: u32,
+    start: usize,
+    loop_start: Option<usize>,
+    total_samples: u32,
+    loop_samples: u32,
+    gd3: Option<usize>,
+    ay_clock: u32,
+    ay_type: u8,
+}
+
+impl<'a> VgmSong<'a> {
+    pub fn new(data: &'a [u8]) -> Result<VgmSong<'a>, VgmError> {
+        match data.get(..4) {
+            Some(magic) if magic == MAGIC => {}
+            Some(_) => return Err(VgmError::new(VgmErrorKind::NotVgm, 0)),
+            None => return Err(VgmError::new(VgmErrorKind::Truncated, 0)),
+        }
+        let field = |at| le(data, at).ok_or(VgmError::new(VgmErrorKind::Truncated, at));
+        let end = match offset(data, 0x04)? {
+            Some(end) => end.min(data.len()),
+            None => data.len(),
+        };
+        let version = field(0x08)?;
+        let gd3 = offset(data, 0x14)?;
+        let total_samples = field(0x18)?;
+        let loop_start = offset(data, 0x1C)?;
+        let loop_samples = field(0x20)?;
+        // Before 1.50 the data always starts at 0x40.
+        let start = match version {
+            0x150.. => offset(data, 0x34)?.unwrap_or(0x40),
+            _ => 0x40,
+        };
+        if start > end {
+            return Err(VgmError::new(VgmErrorKind::BadOffset, 0x34));
+        }
+        if loop_start.is_some_and(|at| at < start || at >= end) {
+            return Err(VgmError::new(VgmErrorKind::BadOffset, 0x1C));
+        }
+        // The AY fields arrived in 1.51, in a header long enough to hold them.
+        let (ay_clock, ay_type) = match version {
+            0x151.. if start >= 0x7C => (field(0x74)? & 0x3FFF_FFFF, data[0x78]),
+            _ => (0, 0),
+        };
+        Ok(VgmSong {
+            data: &data[..end],
+            version,
+            start,
+            loop_start,
+            total_samples,
+            loop_samples,
+            gd3,
+            ay_clock,
+            ay_type,
+        })
+    }
+
+    /// The format version in BCD: `0x171` for 1.71.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The AY chip's clock in hertz, or 0 where the file has no AY part.
+    pub fn ay_clock(&self) -> u32 {
+        self.ay_clock
+    }
+
+    /// Which of the family the rip was made on: 0x00 for the AY-3-8910,
+    /// 0x10 for the YM2149, and so on.
+    pub fn ay_type(&self) -> u8 {
+        self.ay_type
+    }
+
+    /// The length of one pass, in samples.
+    pub fn total_samples(&self) -> u32 {
+        self.total_samples
+    }
+
+    /// The length of the looped part, in samples, or 0 without a loop.
+    pub fn loop_samples(&self) -> u32 {
+        self.loop_samples
+    }
+
+    pub fn has_loop(&self) -> bool {
+        self.loop_start.is_some()
+    }
+
+    /// Where the GD3 tag starts, counted from the start of the file.
+    pub fn gd3_offset(&self) -> Option<usize> {
+        self.gd3
+    }
+
+    /// The GD3 tag, if there is one that can be read; [`Gd3::parse`] with
+    /// [`VgmSong::gd3_offset`] says what is wrong with one that can't.
+    pub fn gd3(&self) -> Option<Gd3<'a>> {
+        Gd3::parse(self.data, self.gd3?).ok()
+    }
+
+    pub fn duration(&self) -> PlayTime {
+        PlayTime::from_millis(self.total_samples as u64 * 1000 / SAMPLE_RATE as u64)
+    }
+
+    /// A player for the song, ticked `tick_hertz` times a second.
+    pub fn into_player(self, tick_hertz: u32) -> VgmPlayer<'a> {
+        VgmPlayer::new(self, tick_hertz)
+    }
+}
+
+/// What a [`VgmPlayer::tick`] did.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VgmStatus {
+    Playing,
+    /// The song reached its end and went back to its loop.
+    Looped,
+    /// The song is over and the chip has been silenced.
+    Finished,
+    /// The stream is broken. The chip has been silenced.
+    Failed(VgmError),
+}
+
+/// What the next command asked for.
+enum Step {
+    Write,
+    Wait(u32),
+    End,
+}
+
+/// Plays a [`VgmSong`]'s writes as their time comes, from a tick far
+/// coarser than the sample rate.
+///
+/// Each tick moves the song on by the samples a tick lasts, keeping the
+/// fraction left over, so no time is lost however the tick and sample rates
+/// divide. A write lands on the first tick to reach its time, so it is at
+/// most a tick late.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VgmPlayer<'a> {
+    song: VgmSong<'a>,
+    tick_hertz: u32,
+    other_chips: OtherChips,
+    looping: bool,
+    position: usize,
+    /// Samples still to pass before the next command.
+    wait: u32,
+    /// Tick time not yet counted as a sample, in `1 / tick_hertz` samples.
+    phase: u32,
+    samples: u64,
+    loops: u32,
+    waited_in_pass: bool,
+    finished: bool,
+}
+
+impl<'a> VgmPlayer<'a> {
+    pub fn new(song: VgmSong<'a>, tick_hertz: u32) -> VgmPlayer<'a> {
+        VgmPlayer {
+            song,
+            tick_hertz: tick_hertz.max(1),
+            other_chips: OtherChips::Skip,
+            looping: true,
+            position: song.start,
+            wait: 0,
+            phase: 0,
+            samples: 0,
+            loops: 0,
+            waited_in_pass: false,
+            finished: false,
+        }
+    }
+
+    pub const fn with_other_chips(mut self, other_chips: OtherChips) -> VgmPlayer<'a> {
+        self.other_chips = other_chips;
+        self
+    }
+
+    /// Whether to go back to the song's loop at the end, as by default, or
+    /// finish.
+    pub const fn with_looping(mut self, looping: bool) -> VgmPlayer<'a> {
+        self.looping = looping;
+        self
+    }
+
+    pub fn song(&self) -> &VgmSong<'a> {
+        &self.song
+    }
+
+    /// Samples played so far, loops and all.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn elapsed(&self) -> PlayTime {
+        PlayTime::from_millis(self.samples * 1000 / SAMPLE_RATE as u64)
+    }
+
+    pub fn loops_completed(&self) -> u32 {
+        self.loops
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Goes back to the start of the song.
+    pub fn restart(&mut self) {
+        *self = VgmPlayer {
+            song: self.song,
+            other_chips: self.other_chips,
+            looping: self.looping,
+            ..VgmPlayer::new(self.song, self.tick_hertz)
+        };
+    }
+
+    /// Moves the song on by one tick's worth of samples, making the writes
+    /// that come due.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<VgmStatus, P::Error> {
+        if self.finished {
+            return Ok(VgmStatus::Finished);
+        }
+        self.phase += SAMPLE_RATE;
+        let mut samples = self.phase / self.tick_hertz;
+        self.phase %= self.tick_hertz;
+        let mut status = VgmStatus::Playing;
+        let mut commands = 0;
+        loop {
+            while self.wait == 0 {
+                commands += 1;
+                if commands > MAX_COMMANDS_WITHOUT_WAIT {
+                    let error = VgmError::new(VgmErrorKind::NoWait, self.position);
+                    return self.finish(psg, VgmStatus::Failed(error));
+                }
+                match self.step(psg)? {
+                    Ok(Step::Write) => {}
+                    Ok(Step::Wait(wait)) => {
+                        self.wait = wait;
+                        self.waited_in_pass |= wait > 0;
+                    }
+                    // A loop with no waits in it would never let the tick
+                    // end.
+                    Ok(Step::End) if self.looping && self.waited_in_pass => {
+                        if let Some(start) = self.song.loop_start {
+                            self.position = start;
+                            self.loops += 1;
+                            self.waited_in_pass = false;
+                            status = VgmStatus::Looped;
+                            continue;
+                        }
+                        return self.finish(psg, VgmStatus::Finished);
+                    }
+                    Ok(Step::End) => return self.finish(psg, VgmStatus::Finished),
+                    Err(error) => return self.finish(psg, VgmStatus::Failed(error)),
+                }
+            }
+            if samples == 0 {
+                return Ok(status);
+            }
+            commands = 0;
+            let passed = self.wait.min(samples);
+            self.wait -= passed;
+            samples -= passed;
+            self.samples += passed as u64;
+        }
+    }
+
+    fn finish<P: Psg>(&mut self, psg: &mut P, status: VgmStatus) -> Result<VgmStatus, P::Error> {
+        self.finished = true;
+        psg.silence()?;
+        Ok(status)
+    }
+
+    /// Carries out the command at the position and moves past it.
+    fn step<P: Psg>(&mut self, psg: &mut P) -> Result<Result<Step, VgmError>, P::Error> {
+        let data = self.song.data;
+        let Some(&command) = data.get(self.position) else {
+            // Running off the end is taken as the end command.
+            return Ok(Ok(Step::End));
+        };
+        let error = |kind| Ok(Err(VgmError::new(kind, self.position)));
+        let operand = |n: usize| data.get(self.position + n).copied();
+        let length = match command {
+            DATA_BLOCK => match le(data, self.position + 3) {
+                Some(size) => usize::try_from(size & 0x7FFF_FFFF)
+                    .ok()
+                    .and_then(|size| size.checked_add(7)),
+                None => None,
+            },
+            _ => match command_length(command) {
+                Some(length) => Some(length),
+                None => return error(VgmErrorKind::UnknownCommand(command)),
+            },
+        };
+        let end = length.and_then(|length| self.position.checked_add(length));
+        let Some(end) = end.filter(|&end| end <= data.len()) else {
+            return error(VgmErrorKind::Truncated);
+        };
+        let (first, second) = (operand(1).unwrap_or(0), operand(2).unwrap_or(0));
+        let at = self.position;
+        self.position = end;
+        let error = |kind| Ok(Err(VgmError::new(kind, at)));
+        let step = match command {
+            AY_WRITE if first & 0x80 == 0 => {
+                if first >= 16 {
+                    return error(VgmErrorKind::BadRegister(first));
+                }
+                write(psg, first, second)?;
+                Step::Write
+            }
+            WAIT => Step::Wait(u16::from_le_bytes([first, second]) as u32),
+            WAIT_60HZ => Step::Wait(SAMPLE_RATE / 60),
+            WAIT_50HZ => Step::Wait(SAMPLE_RATE / 50),
+            0x70..=0x7F => Step::Wait((command & 0x0F) as u32 + 1),
+            END => Step::End,
+            // Waits folded into YM2612 sample writes still pass time.
+            0x80..=0x8F => match self.other_chips {
+                OtherChips::Skip => Step::Wait((command & 0x0F) as u32),
+                OtherChips::Fail => return error(VgmErrorKind::OtherChip(command)),
+            },
+            // Other chips' writes, and data blocks for their sample memory.
+            _ => match self.other_chips {
+                OtherChips::Skip => Step::Write,
+                OtherChips::Fail => return error(VgmErrorKind::OtherChip(command)),
+            },
+        };
+        Ok(Ok(step))
+    }
+}
+
+/// Writes as a frame would: the mixer keeps the port directions, every R13
+/// write restarts the envelope, and the ports themselves are left alone.
+fn write<P: Psg>(psg: &mut P, register: u8, value: u8) -> Result<(), P::Error> {
+    match register {
+        0x7 => {
+            let mixer = frame_player::frame_mixer(value, psg.registers().mixer());
+            psg.update_register(0x7, mixer)?;
+        }
+        0xD => psg.set_register_value(0xD, value)?,
+        0xE | 0xF => {}
+        _ => {
+            psg.update_register(register, value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::{mangled, FakePsg};
+    use std::vec::Vec;
+
+    /// A 1.51 file with its data at 0x80, the AY at 2 MHz, looping to
+    /// `loop_at` bytes into `commands`.
+    fn vgm(commands: &[u8], loop_at: Option<usize>) -> Vec<u8> {
+        let mut file = std::vec![0; 0x80];
+        file[..4].copy_from_slice(MAGIC);
+        let eof = (0x80 + commands.len() - 4) as u32;
+        file[0x04..0x08].copy_from_slice(&eof.to_le_bytes());
+        file[0x08..0x0C].copy_from_slice(&0x151u32.to_le_bytes());
+        if let Some(at) = loop_at {
+            let relative = (0x80 + at - 0x1C) as u32;
+            file[0x1C..0x20].copy_from_slice(&relative.to_le_bytes());
+        }
+        file[0x34..0x38].copy_from_slice(&(0x80u32 - 0x34).to_le_bytes());
+        file[0x74..0x78].copy_from_slice(&2_000_000u32.to_le_bytes());
+        file[0x78] = 0x10;
+        file.extend_from_slice(commands);
+        file
+    }
+
+    /// The ticks (counting from 1) on which R0 changed, with its new value,
+    /// and the status that ended play.
+    fn timeline(player: &mut VgmPlayer, ticks: u32) -> (Vec<(u32, u8)>, VgmStatus) {
+        let mut psg = FakePsg::new();
+        let mut changes = Vec::new();
+        for tick in 1..=ticks {
+            let status = player.tick(&mut psg).unwrap();
+            for (register, value) in psg.take_writes() {
+                if register == 0x0 {
+                    changes.push((tick, value));
+                }
+            }
+            if status != VgmStatus::Playing {
+                return (changes, status);
+            }
+        }
+        (changes, VgmStatus::Playing)
+    }
+
+    const WAITS: &[u8] = &[
+        0xA0, 0x00, 0x10, 0x61, 0x0A, 0x00, // 10 samples
+        0xA0, 0x00, 0x20, 0x62, // 735
+        0xA0, 0x00, 0x30, 0x63, 0x7F, // 882 and 16
+        0xA0, 0x00, 0x40, 0x66,
+    ];
+
+    #[test]
+    fn reads_the_header() {
+        let data = vgm(WAITS, None);
+        let song = VgmSong::new(&data).unwrap();
+        assert_eq!(
+            (song.version(), song.ay_clock(), song.ay_type()),
+            (0x151, 2_000_000, 0x10)
+        );
+        assert!(!song.has_loop());
+        assert_eq!(song.gd3(), None);
+        let truncated = VgmError::new(VgmErrorKind::Truncated, 0x34);
+        assert_eq!(VgmSong::new(&data[..0x30]), Err(truncated));
+        let mut broken = data.clone();
+        broken[0] = b'X';
+        let not_vgm = VgmError::new(VgmErrorKind::NotVgm, 0);
+        assert_eq!(VgmSong::new(&broken), Err(not_vgm));
+        let broken = vgm(WAITS, Some(WAITS.len() + 10));
+        let bad_offset = VgmError::new(VgmErrorKind::BadOffset, 0x1C);
+        assert_eq!(VgmSong::new(&broken), Err(bad_offset));
+    }
+
+    #[test]
+    fn waits_add_up_at_any_tick_rate() {
+        let data = vgm(WAITS, None);
+        let song = VgmSong::new(&data).unwrap();
+        // A tick a sample: writes land on the very sample.
+        let mut player = song.into_player(SAMPLE_RATE);
+        assert_eq!(
+            timeline(&mut player, 2000),
+            (
+                Vec::from([(1, 0x10), (10, 0x20), (745, 0x30), (1643, 0x40)]),
+                VgmStatus::Finished
+            )
+        );
+        assert_eq!(player.samples(), 1643);
+        // 50 Hz ticks of 882 samples, and 60 Hz ones of 735.
+        let mut player = song.into_player(50);
+        assert_eq!(
+            timeline(&mut player, 10),
+            (
+                Vec::from([(1, 0x10), (1, 0x20), (1, 0x30), (2, 0x40)]),
+                VgmStatus::Finished
+            )
+        );
+        let mut player = song.into_player(60);
+        assert_eq!(
+            timeline(&mut player, 10).0,
+            [(1, 0x10), (1, 0x20), (2, 0x30), (3, 0x40)]
+        );
+        // 7 kHz doesn't divide 44100 evenly into whole ticks: nothing is
+        // lost all the same.
+        let mut player = song.into_player(7000);
+        timeline(&mut player, 1000);
+        assert_eq!(player.samples(), 1643);
+    }
+
+    #[test]
+    fn loops_back_and_counts() {
+        let commands = [
+            0xA0, 0x08, 0x0F, 0x61, 0x64, 0x00, // 100 samples, then the loop
+            0xA0, 0x00, 0x55, 0x61, 0xC8, 0x00, // 200
+            0xA0, 0x00, 0x66, 0x61, 0xC8, 0x00, // 200
+            0x66,
+        ];
+        let data = vgm(&commands, Some(6));
+        let song = VgmSong::new(&data).unwrap();
+        assert!(song.has_loop());
+        // Ticks of 100 samples.
+        let mut player = song.into_player(441);
+        let mut psg = FakePsg::new();
+        let statuses: Vec<_> = (0..13).map(|_| player.tick(&mut psg).unwrap()).collect();
+        let looped: Vec<_> = (1..=13)
+            .filter(|&tick| statuses[tick - 1] == VgmStatus::Looped)
+            .collect();
+        assert_eq!(looped, [5, 9, 13]);
+        assert_eq!(player.loops_completed(), 3);
+        assert_eq!(psg.registers().value(0x8), 15);
+
+        let mut player = song.into_player(441).with_looping(false);
+        assert_eq!(timeline(&mut player, 20).1, VgmStatus::Finished);
+        assert_eq!(player.samples(), 500);
+
+        // A loop that takes no time ends rather than spinning.
+        let data = vgm(&[0x61, 0x01, 0x00, 0xA0, 0x00, 0x01, 0x66], Some(3));
+        let mut player = VgmSong::new(&data).unwrap().into_player(50);
+        assert_eq!(timeline(&mut player, 5).1, VgmStatus::Finished);
+
+        // Nor does a tick spin over a stream with no waits in it: the last
+        // command here is the end.
+        let writes = MAX_COMMANDS_WITHOUT_WAIT as usize - 1;
+        let mut commands = [0xA0, 0x00, 0x01].repeat(writes);
+        let data = vgm(&commands, None);
+        let mut player = VgmSong::new(&data).unwrap().into_player(50);
+        assert_eq!(timeline(&mut player, 1).1, VgmStatus::Finished);
+        commands.extend_from_slice(&[0xA0, 0x00, 0x02]);
+        let data = vgm(&commands, None);
+        let mut player = VgmSong::new(&data).unwrap().into_player(50);
+        let at = 0x80 + 3 * (writes + 1);
+        let error = VgmError::new(VgmErrorKind::NoWait, at);
+        assert_eq!(timeline(&mut player, 1).1, VgmStatus::Failed(error));
+    }
+
+    #[test]
+    fn damaged_files_fail_cleanly() {
+        let commands = [
+            0xA0, 0x08, 0x0F, 0x61, 0x64, 0x00, 0x67, 0x66, 0x00, 0x01, 0x00, 0x00, 0x00, 0xAA,
+            0xA0, 0x00, 0x55, 0x62, 0x66,
+        ];
+        for file in [vgm(WAITS, None), vgm(&commands, Some(6))] {
+            for data in mangled(&file) {
+                let song = match VgmSong::new(&data) {
+                    Ok(song) => song,
+                    // Fields missing from a short header are pointed at
+                    // where they should be.
+                    Err(error) => {
+                        assert!(error.offset < file.len());
+                        continue;
+                    }
+                };
+                song.gd3();
+                let mut player = song.into_player(50);
+                if let (_, VgmStatus::Failed(error)) = timeline(&mut player, 100) {
+                    assert!(error.offset <= data.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn other_chips_are_skipped_or_refused() {
+        // An SN76489 write, a YM2612 one, a YM2612 sample write waiting 2
+        // samples, a data block and a write to a second AY.
+        let commands = [
+            0x50, 0x9F, 0x52, 0x2B, 0x80, 0x82, 0x67, 0x66, 0x00, 0x02, 0x00, 0x00, 0x00, 0xAA,
+            0xBB, 0xA0, 0x80, 0x11, 0xA0, 0x00, 0x22, 0x66,
+        ];
+        let data = vgm(&commands, None);
+        let song = VgmSong::new(&data).unwrap();
+        let mut player = song.into_player(SAMPLE_RATE);
+        assert_eq!(
+            timeline(&mut player, 10),
+            (Vec::from([(2, 0x22)]), VgmStatus::Finished)
+        );
+        let mut player = song.into_player(50).with_other_chips(OtherChips::Fail);
+        assert_eq!(
+            timeline(&mut player, 1).1,
+            VgmStatus::Failed(VgmError::new(VgmErrorKind::OtherChip(0x50), 0x80))
+        );
+        for (commands, kind) in [
+            (&[0x20][..], VgmErrorKind::UnknownCommand(0x20)),
+            (&[0xA0, 0x10, 0x00], VgmErrorKind::BadRegister(0x10)),
+            (&[0x61, 0x01], VgmErrorKind::Truncated),
+            (
+                &[0x67, 0x66, 0x00, 0xFF, 0xFF, 0xFF, 0x7F],
+                VgmErrorKind::Truncated,
+            ),
+        ] {
+            let data = vgm(commands, None);
+            let mut player = VgmSong::new(&data).unwrap().into_player(50);
+            let error = VgmError::new(kind, 0x80);
+            assert_eq!(timeline(&mut player, 1).1, VgmStatus::Failed(error));
+        }
+    }
+}
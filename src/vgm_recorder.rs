@@ -0,0 +1,284 @@
+//! Recording what is written to the chip as a VGM file, to play back on a
+//! computer.
+//!
+//! [`VgmRecorder`] wraps any [`Psg`], passing every write through and
+//! noting it, with the time it happened, in a buffer of yours. Time comes
+//! from a counter you supply in samples at 44.1 kHz, from a hardware timer
+//! say, and the gaps between writes become wait commands. On
+//! [`VgmRecorder::finish`] the header is filled in and the buffer holds a
+//! complete file.
+
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::tuning::FrequencyPolicy;
+use crate::vgm::{AY_WRITE, END, MAGIC, WAIT, WAIT_50HZ, WAIT_60HZ};
+use crate::ChipKind;
+
+/// Where the commands start: room for a 1.51 header with the AY fields.
+const DATA_START: usize = 0x80;
+
+/// The header's chip type for a YM2149.
+const YM2149: u8 = 0x10;
+
+/// The buffer ran out. The recording stops there; the chip still gets every
+/// write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VgmFull;
+
+/// A [`Psg`] that records the writes made through it as a VGM file.
+pub struct VgmRecorder<'b, P, F> {
+    psg: P,
+    buffer: &'b mut [u8],
+    length: usize,
+    samples: F,
+    start: u64,
+    /// The time written up to, counted from `start`.
+    written: u64,
+    loop_point: Option<(usize, u64)>,
+    full: bool,
+    finished: bool,
+}
+
+impl<'b, P: Psg, F: FnMut() -> u64> VgmRecorder<'b, P, F> {
+    /// Starts recording, at the time `samples` gives now, with the registers
+    /// `psg` already knows written out first so the file starts from there.
+    pub fn new(psg: P, buffer: &'b mut [u8], samples: F) -> VgmRecorder<'b, P, F> {
+        let mut recorder = VgmRecorder {
+            psg,
+            buffer,
+            length: DATA_START,
+            samples,
+            start: 0,
+            written: 0,
+            loop_point: None,
+            full: false,
+            finished: false,
+        };
+        recorder.start = (recorder.samples)();
+        recorder.full = recorder.buffer.len() < DATA_START;
+        for address in 0..0xE {
+            if let Some(value) = recorder.psg.registers().get(address) {
+                recorder.push(&[AY_WRITE, address, value]);
+            }
+        }
+        recorder
+    }
+
+    pub fn inner(&self) -> &P {
+        &self.psg
+    }
+
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.psg
+    }
+
+    pub fn into_inner(self) -> P {
+        self.psg
+    }
+
+    /// Whether the buffer has run out, ending the recording early.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Samples recorded so far.
+    pub fn samples(&self) -> u64 {
+        self.written
+    }
+
+    /// Makes now the point players go back to at the end. The chip's state
+    /// here should match its state at the end for the loop to be seamless.
+    pub fn mark_loop(&mut self) {
+        self.catch_up();
+        self.loop_point = Some((self.length, self.written));
+    }
+
+    /// Writes waits up to now.
+    fn catch_up(&mut self) {
+        let now = (self.samples)().saturating_sub(self.start);
+        while self.written < now && !self.full {
+            let gap = now - self.written;
+            let (command, samples): (&[u8], u64) = match gap {
+                735 => (&[WAIT_60HZ], 735),
+                882 => (&[WAIT_50HZ], 882),
+                1..=16 => (&[0x70 | (gap - 1) as u8], gap),
+                _ => {
+                    let samples = gap.min(u16::MAX as u64);
+                    let [low, high] = (samples as u16).to_le_bytes();
+                    self.push(&[WAIT, low, high]);
+                    self.written += samples;
+                    continue;
+                }
+            };
+            self.push(command);
+            self.written += samples;
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.full || self.finished {
+            return;
+        }
+        // Leave room for the end command.
+        match self
+            .buffer
+            .get_mut(self.length..self.length + bytes.len() + 1)
+        {
+            Some(room) => {
+                room[..bytes.len()].copy_from_slice(bytes);
+                self.length += bytes.len();
+            }
+            None => self.full = true,
+        }
+    }
+
+    /// Ends the recording and fills in the header, returning the file. Later
+    /// writes still reach the chip but aren't recorded.
+    pub fn finish(&mut self) -> Result<&[u8], VgmFull> {
+        if !self.finished {
+            self.catch_up();
+            if self.full {
+                self.finished = true;
+                return Err(VgmFull);
+            }
+            self.buffer[self.length] = END;
+            self.length += 1;
+            self.finished = true;
+            self.write_header();
+        }
+        if self.full {
+            return Err(VgmFull);
+        }
+        Ok(&self.buffer[..self.length])
+    }
+
+    fn write_header(&mut self) {
+        self.buffer[..DATA_START].fill(0);
+        let mut field = |at: usize, value: u32| {
+            self.buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        field(0x00, u32::from_le_bytes(*MAGIC));
+        field(0x04, (self.length - 0x04) as u32);
+        field(0x08, 0x151);
+        field(0x18, self.written.min(u32::MAX as u64) as u32);
+        if let Some((at, samples)) = self.loop_point {
+            field(0x1C, (at - 0x1C) as u32);
+            field(0x20, (self.written - samples).min(u32::MAX as u64) as u32);
+        }
+        field(0x34, (DATA_START - 0x34) as u32);
+        let clock = self.psg.master_clock();
+        field(0x74, clock);
+        self.buffer[0x78] = YM2149;
+    }
+}
+
+impl<P: Psg, F: FnMut() -> u64> Psg for VgmRecorder<'_, P, F> {
+    type Error = P::Error;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), P::Error> {
+        self.psg.set_register_value(address, data)?;
+        if address < 16 {
+            self.catch_up();
+            self.push(&[AY_WRITE, address, data]);
+        }
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        self.psg.registers()
+    }
+
+    fn register_mask(&self, address: u8) -> u8 {
+        self.psg.register_mask(address)
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.psg.master_clock()
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        self.psg.chip_kind()
+    }
+
+    fn frequency_policy(&self) -> FrequencyPolicy {
+        self.psg.frequency_policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test_support::FakePsg;
+    use crate::vgm::{VgmSong, VgmStatus, SAMPLE_RATE};
+    use std::cell::Cell;
+    use std::vec::Vec;
+
+    /// Writes at times that need every kind of wait.
+    const SCRIPT: [(u64, u8, u8); 7] = [
+        (5, 0x0, 0x10),
+        (5, 0x8, 0x0F),
+        (13, 0x0, 0x20),
+        (748, 0x7, 0x38),
+        (1630, 0x1, 0x02),
+        (71_000, 0xD, 0x0E),
+        (71_010, 0x0, 0x30),
+    ];
+
+    fn record(buffer: &mut [u8], loop_at: Option<u64>) -> Result<Vec<u8>, VgmFull> {
+        let now = Cell::new(1000);
+        let mut recorder = VgmRecorder::new(FakePsg::new(), buffer, || now.get());
+        for (time, address, value) in SCRIPT {
+            now.set(1000 + time);
+            if loop_at == Some(time) {
+                recorder.mark_loop();
+            }
+            recorder.set_register_value(address, value).unwrap();
+        }
+        now.set(1000 + 72_000);
+        assert_eq!(recorder.inner().writes.len(), SCRIPT.len());
+        recorder.finish().map(Vec::from)
+    }
+
+    #[test]
+    fn plays_back_as_it_was_written() {
+        let file = record(&mut [0; 256], Some(748)).unwrap();
+        let song = VgmSong::new(&file).unwrap();
+        assert_eq!(song.total_samples(), 72_000);
+        assert_eq!(song.loop_samples(), 72_000 - 748);
+        assert_eq!(song.ay_clock(), FakePsg::new().master_clock());
+
+        let mut player = song.into_player(SAMPLE_RATE).with_looping(false);
+        let mut psg = FakePsg::new();
+        let mut timeline = Vec::new();
+        loop {
+            let status = player.tick(&mut psg).unwrap();
+            for (address, value) in psg.take_writes() {
+                timeline.push((player.samples(), address, value));
+            }
+            if status != VgmStatus::Playing {
+                assert_eq!(status, VgmStatus::Finished);
+                break;
+            }
+        }
+        // The silencing at the end aside.
+        assert_eq!(&timeline[..SCRIPT.len()], SCRIPT);
+        assert_eq!(player.samples(), 72_000);
+    }
+
+    #[test]
+    fn a_full_buffer_is_reported() {
+        assert_eq!(record(&mut [0; 0x40], None), Err(VgmFull));
+        let length = record(&mut [0; 256], None).unwrap().len();
+        assert_eq!(record(&mut [0; 256][..length - 1], None), Err(VgmFull));
+
+        // What the chip already holds is written out first.
+        let mut psg = FakePsg::new();
+        psg.set_register_value(0x9, 7).unwrap();
+        let mut buffer = [0; 256];
+        let mut recorder = VgmRecorder::new(psg, &mut buffer, || 0);
+        let file = recorder.finish().unwrap();
+        assert_eq!(file[DATA_START..], [AY_WRITE, 0x9, 7, END]);
+    }
+}
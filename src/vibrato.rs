@@ -0,0 +1,159 @@
+//! Pitch vibrato layered over a channel's base note.
+
+use crate::effect::{ChannelCtx, Effect};
+use crate::lfo::{Lfo, LfoWaveform, LFO_SCALE};
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning::{self, MAX_TONE_PERIOD};
+use crate::Channel;
+
+/// Periodic pitch wobble around a base note.
+///
+/// Depth is in cents so the wobble sounds the same in every octave; the LFO
+/// completes one cycle every `rate` ticks. After [`Vibrato::retrigger`] the
+/// base note plays steady for `delay` ticks before the wobble starts, like a
+/// singer settling into a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vibrato {
+    channel: Channel,
+    depth_cents: u16,
+    lfo: Lfo,
+    delay: u16,
+    waited: u16,
+    base: Option<Pitch>,
+    last_period: Option<u16>,
+}
+
+impl Vibrato {
+    pub const fn new(
+        channel: Channel,
+        depth_cents: u16,
+        rate: u16,
+        waveform: LfoWaveform,
+    ) -> Vibrato {
+        Vibrato {
+            channel,
+            depth_cents,
+            lfo: Lfo::new(waveform, rate),
+            delay: 0,
+            waited: 0,
+            base: None,
+            last_period: None,
+        }
+    }
+
+    pub const fn with_delay(mut self, delay: u16) -> Vibrato {
+        self.delay = delay;
+        self
+    }
+
+    pub fn set_depth(&mut self, depth_cents: u16) {
+        self.depth_cents = depth_cents;
+    }
+
+    pub fn set_rate(&mut self, rate: u16) {
+        self.lfo.set_period(rate);
+    }
+
+    /// Sets the note [`Vibrato::tick`] wobbles around. Changing it doesn't
+    /// restart the onset delay; call [`Vibrato::retrigger`] for a new note.
+    pub fn set_base_pitch(&mut self, pitch: Pitch) {
+        self.base = Some(pitch);
+    }
+
+    /// Restarts the onset delay and the LFO cycle.
+    pub fn retrigger(&mut self) {
+        self.waited = 0;
+        self.lfo.reset();
+    }
+
+    /// Offset in cents for this tick, advancing the LFO once the onset delay
+    /// has passed.
+    pub fn next_cents(&mut self) -> i32 {
+        if self.waited < self.delay {
+            self.waited += 1;
+            return 0;
+        }
+        self.depth_cents as i32 * self.lfo.step() / LFO_SCALE
+    }
+
+    /// Applies this tick's offset to `base_period`, for layering over another
+    /// pitch source such as [`crate::portamento::Portamento`].
+    pub fn modulate(&mut self, base_period: u16) -> u16 {
+        let cents = self.next_cents();
+        tuning::detune_period(base_period as u32, cents).clamp(1, MAX_TONE_PERIOD as u32) as u16
+    }
+
+    /// Writes this tick's period for the base pitch, skipping the write when
+    /// it matches the last one.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        let Some(base) = self.base else {
+            return Ok(());
+        };
+        let base_period = tuning::fold_pitch_period(psg.master_clock(), base).period;
+        let period = self.modulate(base_period);
+        if self.last_period != Some(period) {
+            psg.set_channel_period(self.channel, period)?;
+            self.last_period = Some(period);
+        }
+        Ok(())
+    }
+}
+
+impl Effect for Vibrato {
+    fn tick(&mut self, ctx: &mut ChannelCtx) {
+        ctx.add_cents(self.next_cents());
+    }
+
+    fn note_on(&mut self, _pitch: Pitch) {
+        self.retrigger();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+
+    #[test]
+    fn triangle_cycle_at_a4() {
+        let mut psg = FakePsg::new();
+        let mut vibrato = Vibrato::new(Channel::A, 100, 8, LfoWaveform::Triangle);
+        vibrato.set_base_pitch(Pitch::new(Note::A, 4));
+        let mut periods = [0; 9];
+        for period in periods.iter_mut() {
+            vibrato.tick(&mut psg).unwrap();
+            *period = psg.registers().tone_period(Channel::A);
+        }
+        // 0, +50, +100, +50, 0, -50, -100, -50 cents around period 284.
+        assert_eq!(periods, [284, 276, 268, 276, 284, 292, 301, 292, 284]);
+    }
+
+    #[test]
+    fn unchanged_periods_are_not_rewritten() {
+        let mut psg = FakePsg::new();
+        let mut vibrato = Vibrato::new(Channel::B, 100, 4, LfoWaveform::Square).with_delay(2);
+        vibrato.set_base_pitch(Pitch::new(Note::A, 4));
+        for _ in 0..3 {
+            vibrato.tick(&mut psg).unwrap();
+        }
+        // Two steady delay ticks write the full period once, then the swing
+        // sharp only changes the fine byte.
+        assert_eq!(psg.take_writes(), [(0x2, 0x1C), (0x3, 0x01), (0x2, 0x0C)]);
+        vibrato.tick(&mut psg).unwrap();
+        assert!(psg.take_writes().is_empty());
+        vibrato.tick(&mut psg).unwrap();
+        assert_eq!(psg.registers().tone_period(Channel::B), 301);
+    }
+
+    #[test]
+    fn depth_is_consistent_across_octaves() {
+        let mut low = Vibrato::new(Channel::A, 100, 4, LfoWaveform::Triangle);
+        let mut high = low.clone();
+        low.next_cents();
+        high.next_cents();
+        assert_eq!(low.modulate(1136), 1072);
+        assert_eq!(high.modulate(284), 268);
+    }
+}
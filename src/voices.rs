@@ -0,0 +1,431 @@
+//! Playing notes from a keyboard or MIDI stream on the chip's three channels.
+
+use crate::adsr::Adsr;
+use crate::pitch::Pitch;
+use crate::psg::Psg;
+use crate::tuning;
+use crate::Channel;
+
+/// How long a voice taken over for a new note takes to fade, in ticks.
+pub const DEFAULT_STEAL_TICKS: u16 = 2;
+
+/// Which sounding note gives way when every channel is busy.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StealPolicy {
+    /// The note that started first.
+    Oldest,
+    /// The note whose envelope is lowest right now, the oldest breaking ties.
+    Quietest,
+    /// The note with the lowest priority, the oldest breaking ties. A new
+    /// note with a lower priority than every sounding one is dropped.
+    LowestPriority,
+}
+
+/// What plays a note on a voice the allocator has handed out.
+///
+/// [`Adsr`] is the simplest engine: a fixed tone shaped by an envelope.
+/// Richer ones, such as instruments driven from MIDI, implement this to reuse
+/// the allocator's stealing and sustain handling.
+pub trait VoiceEngine {
+    /// Starts `pitch` on `channel`, once any note before it has faded.
+    fn start<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+        pitch: Pitch,
+    ) -> Result<(), P::Error>;
+
+    /// Restarts the note already playing, for the same key pressed again.
+    fn retrigger(&mut self);
+
+    fn note_off(&mut self);
+
+    /// Fades out over `ticks`, for a voice that is being taken over.
+    fn cut(&mut self, ticks: u16);
+
+    /// Whether anything is still sounding.
+    fn is_active(&self) -> bool;
+
+    /// How loud the note is now, for [`StealPolicy::Quietest`].
+    fn level(&self) -> u8;
+
+    /// Advances by one tick and writes the channel.
+    fn tick<P: Psg>(&mut self, psg: &mut P, channel: Channel) -> Result<(), P::Error>;
+}
+
+impl VoiceEngine for Adsr {
+    fn start<P: Psg>(
+        &mut self,
+        psg: &mut P,
+        channel: Channel,
+        pitch: Pitch,
+    ) -> Result<(), P::Error> {
+        let period = tuning::fold_pitch_period(psg.master_clock(), pitch).period;
+        psg.set_channel_period(channel, period)?;
+        psg.set_channel_mixer(channel, true, false)?;
+        self.note_on();
+        Ok(())
+    }
+
+    fn retrigger(&mut self) {
+        self.note_on();
+    }
+
+    fn note_off(&mut self) {
+        Adsr::note_off(self);
+    }
+
+    fn cut(&mut self, ticks: u16) {
+        Adsr::cut(self, ticks);
+    }
+
+    fn is_active(&self) -> bool {
+        Adsr::is_active(self)
+    }
+
+    fn level(&self) -> u8 {
+        Adsr::level(self)
+    }
+
+    fn tick<P: Psg>(&mut self, psg: &mut P, channel: Channel) -> Result<(), P::Error> {
+        self.tick_channel(psg, channel)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Voice<E> {
+    /// The note this channel belongs to, until its release has finished.
+    pitch: Option<Pitch>,
+    /// The key is down.
+    held: bool,
+    /// The key is up but the sustain pedal is keeping the note going.
+    sustained: bool,
+    /// The note is waiting for the previous one's quick release to finish.
+    pending: bool,
+    priority: u8,
+    age: u32,
+    engine: E,
+}
+
+impl<E> Voice<E> {
+    fn is_free(&self) -> bool {
+        self.pitch.is_none()
+    }
+
+    fn is_releasing(&self) -> bool {
+        self.pitch.is_some() && !self.held && !self.sustained
+    }
+}
+
+/// Assigns notes to channels and plays each through its own engine, an
+/// [`Adsr`] unless another [`VoiceEngine`] is given.
+///
+/// A note goes to a free channel if there is one. Failing that it takes a
+/// channel whose note is already releasing, then one playing a held note as
+/// the [`StealPolicy`] decides. The note being replaced fades over a couple
+/// of ticks with [`VoiceEngine::cut`] before the new one starts, rather than
+/// clicking off.
+///
+/// A note-on for a pitch that is already sounding retriggers it on the same
+/// channel. A note-off for a note that has since been stolen does nothing,
+/// so it can't cut short whatever took its channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoiceAllocator<E = Adsr> {
+    voices: [Voice<E>; 3],
+    reserved: [bool; 3],
+    policy: StealPolicy,
+    steal_ticks: u16,
+    sustain: bool,
+    age: u32,
+}
+
+impl<E: VoiceEngine + Clone> VoiceAllocator<E> {
+    /// Every voice uses a copy of `engine`.
+    pub fn new(engine: E, policy: StealPolicy) -> VoiceAllocator<E> {
+        let voice = Voice {
+            pitch: None,
+            held: false,
+            sustained: false,
+            pending: false,
+            priority: 0,
+            age: 0,
+            engine,
+        };
+        VoiceAllocator {
+            voices: [voice.clone(), voice.clone(), voice],
+            reserved: [false; 3],
+            policy,
+            steal_ticks: DEFAULT_STEAL_TICKS,
+            sustain: false,
+            age: 0,
+        }
+    }
+
+    /// The engine playing on `channel`, to adjust it while it plays.
+    pub fn engine_mut(&mut self, channel: Channel) -> &mut E {
+        &mut self.voices[channel.index()].engine
+    }
+
+    pub fn engine(&self, channel: Channel) -> &E {
+        &self.voices[channel.index()].engine
+    }
+
+    pub fn set_policy(&mut self, policy: StealPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn set_steal_ticks(&mut self, ticks: u16) {
+        self.steal_ticks = ticks;
+    }
+
+    /// Keeps `channel` for something else, such as drums. A note playing
+    /// there fades out.
+    pub fn set_reserved(&mut self, channel: Channel, reserved: bool) {
+        self.reserved[channel.index()] = reserved;
+        if reserved {
+            let voice = &mut self.voices[channel.index()];
+            voice.engine.cut(self.steal_ticks);
+            voice.held = false;
+            voice.sustained = false;
+            voice.pending = false;
+        }
+    }
+
+    pub fn is_reserved(&self, channel: Channel) -> bool {
+        self.reserved[channel.index()]
+    }
+
+    /// The channel sounding `pitch`, if any.
+    pub fn channel_of(&self, pitch: Pitch) -> Option<Channel> {
+        self.usable()
+            .find(|channel| self.voices[channel.index()].pitch == Some(pitch))
+    }
+
+    fn usable(&self) -> impl Iterator<Item = Channel> + '_ {
+        Channel::ALL
+            .into_iter()
+            .filter(|channel| !self.reserved[channel.index()])
+    }
+
+    /// Starts `pitch`, returning the channel it went to, or `None` if it was
+    /// dropped.
+    pub fn note_on(&mut self, pitch: Pitch, priority: u8) -> Option<Channel> {
+        let channel = self.channel_of(pitch).or_else(|| self.pick(priority))?;
+        self.age = self.age.wrapping_add(1);
+        let steal_ticks = self.steal_ticks;
+        let voice = &mut self.voices[channel.index()];
+        if voice.pitch == Some(pitch) && !voice.pending {
+            // Same note again: retrigger from where it is.
+            voice.engine.retrigger();
+        } else {
+            voice.engine.cut(steal_ticks);
+            voice.pending = true;
+        }
+        voice.pitch = Some(pitch);
+        voice.held = true;
+        voice.sustained = false;
+        voice.priority = priority;
+        voice.age = self.age;
+        Some(channel)
+    }
+
+    fn pick(&self, priority: u8) -> Option<Channel> {
+        let voice = |channel: &Channel| &self.voices[channel.index()];
+        if let Some(free) = self.usable().find(|channel| voice(channel).is_free()) {
+            return Some(free);
+        }
+        let quietest = |channel: &Channel| (voice(channel).engine.level(), voice(channel).age);
+        if let Some(releasing) = self
+            .usable()
+            .filter(|channel| voice(channel).is_releasing())
+            .min_by_key(quietest)
+        {
+            return Some(releasing);
+        }
+        match self.policy {
+            StealPolicy::Oldest => self.usable().min_by_key(|channel| voice(channel).age),
+            StealPolicy::Quietest => self.usable().min_by_key(quietest),
+            StealPolicy::LowestPriority => self
+                .usable()
+                .min_by_key(|channel| (voice(channel).priority, voice(channel).age))
+                .filter(|channel| voice(channel).priority <= priority),
+        }
+    }
+
+    /// Releases `pitch`, or leaves it to the sustain pedal.
+    pub fn note_off(&mut self, pitch: Pitch) {
+        let Some(channel) = self.channel_of(pitch) else {
+            return;
+        };
+        let voice = &mut self.voices[channel.index()];
+        if !voice.held {
+            return;
+        }
+        voice.held = false;
+        if self.sustain {
+            voice.sustained = true;
+        } else if !voice.pending {
+            voice.engine.note_off();
+        }
+    }
+
+    /// Holds released notes while `down`; letting go releases them.
+    pub fn set_sustain(&mut self, down: bool) {
+        self.sustain = down;
+        if !down {
+            for voice in &mut self.voices {
+                if voice.sustained {
+                    voice.sustained = false;
+                    if !voice.pending {
+                        voice.engine.note_off();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Releases every note, sustained or not.
+    pub fn all_notes_off(&mut self) {
+        self.set_sustain(false);
+        for voice in &mut self.voices {
+            voice.held = false;
+            if !voice.pending {
+                voice.engine.note_off();
+            }
+        }
+    }
+
+    /// Advances every envelope and writes the results. Reserved channels are
+    /// left alone once their last note has faded.
+    pub fn tick<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        for channel in Channel::ALL {
+            let reserved = self.reserved[channel.index()];
+            let voice = &mut self.voices[channel.index()];
+            if voice.pending && !voice.engine.is_active() {
+                voice.pending = false;
+                if let Some(pitch) = voice.pitch {
+                    voice.engine.start(psg, channel, pitch)?;
+                    // Released while waiting: sound it briefly all the same.
+                    if !voice.held && !voice.sustained {
+                        voice.engine.note_off();
+                    }
+                }
+            }
+            if reserved && voice.pitch.is_none() {
+                continue;
+            }
+            voice.engine.tick(psg, channel)?;
+            if !voice.engine.is_active() && !voice.pending {
+                voice.pitch = None;
+                voice.held = false;
+                voice.sustained = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::Note;
+    use crate::test_support::FakePsg;
+
+    const ORGAN: Adsr = Adsr::new(0, 0, 12, 4);
+
+    fn pitch(midi: u8) -> Pitch {
+        Pitch::from_midi(midi).unwrap()
+    }
+
+    fn run(voices: &mut VoiceAllocator, psg: &mut FakePsg, ticks: usize) {
+        for _ in 0..ticks {
+            voices.tick(psg).unwrap();
+        }
+    }
+
+    #[test]
+    fn notes_fill_channels_and_release_their_own() {
+        let mut psg = FakePsg::new();
+        let mut voices = VoiceAllocator::new(ORGAN, StealPolicy::Oldest);
+        assert_eq!(voices.note_on(pitch(60), 0), Some(Channel::A));
+        assert_eq!(voices.note_on(pitch(64), 0), Some(Channel::B));
+        run(&mut voices, &mut psg, 2);
+        assert_eq!(psg.registers().value(0x9), 12);
+        voices.note_off(pitch(64));
+        run(&mut voices, &mut psg, 5);
+        assert_eq!(psg.registers().value(0x8), 12);
+        assert_eq!(psg.registers().value(0x9), 0);
+        assert_eq!(voices.channel_of(pitch(64)), None);
+        // A repeated key stays on its channel.
+        assert_eq!(voices.note_on(pitch(60), 0), Some(Channel::A));
+        assert_eq!(voices.note_on(pitch(67), 0), Some(Channel::B));
+    }
+
+    #[test]
+    fn oldest_is_stolen_with_a_quick_fade() {
+        let mut psg = FakePsg::new();
+        let mut voices = VoiceAllocator::new(ORGAN, StealPolicy::Oldest);
+        voices.set_reserved(Channel::C, true);
+        voices.note_on(pitch(60), 0);
+        voices.note_on(pitch(64), 0);
+        run(&mut voices, &mut psg, 3);
+        assert_eq!(voices.note_on(pitch(67), 0), Some(Channel::A));
+        // Two ticks down to silence, then the new note's attack.
+        let levels: [u8; 3] = core::array::from_fn(|_| {
+            voices.tick(&mut psg).unwrap();
+            psg.registers().value(0x8)
+        });
+        assert_eq!(levels, [6, 0, 15]);
+        let g4 = tuning::fold_pitch_period(psg.master_clock(), Pitch::new(Note::G, 4)).period;
+        assert_eq!(psg.registers().tone_period(Channel::A), g4);
+        assert!(psg.writes.iter().all(|&(address, _)| address != 0xA));
+        // The stolen note's key coming up doesn't touch the new note.
+        voices.note_off(pitch(60));
+        run(&mut voices, &mut psg, 2);
+        assert_eq!(psg.registers().value(0x8), 12);
+    }
+
+    #[test]
+    fn quietest_and_priority_policies() {
+        let mut psg = FakePsg::new();
+        let pad = Adsr::new(6, 0, 15, 4);
+        let mut voices = VoiceAllocator::new(pad, StealPolicy::Quietest);
+        voices.note_on(pitch(60), 5);
+        run(&mut voices, &mut psg, 3);
+        voices.note_on(pitch(64), 1);
+        run(&mut voices, &mut psg, 1);
+        voices.note_on(pitch(67), 9);
+        run(&mut voices, &mut psg, 2);
+        // C is newest but still the quietest in its attack.
+        assert_eq!(voices.note_on(pitch(71), 3), Some(Channel::C));
+
+        voices.set_policy(StealPolicy::LowestPriority);
+        assert_eq!(voices.note_on(pitch(72), 0), None);
+        assert_eq!(voices.note_on(pitch(74), 1), Some(Channel::B));
+        // A releasing note goes before any held one, whatever its priority.
+        voices.note_off(pitch(60));
+        assert_eq!(voices.note_on(pitch(76), 0), Some(Channel::A));
+    }
+
+    #[test]
+    fn sustain_pedal_holds_released_notes() {
+        let mut psg = FakePsg::new();
+        let mut voices = VoiceAllocator::new(ORGAN, StealPolicy::Oldest);
+        voices.note_on(pitch(60), 0);
+        voices.set_sustain(true);
+        voices.note_off(pitch(60));
+        run(&mut voices, &mut psg, 10);
+        assert_eq!(psg.registers().value(0x8), 12);
+        // Sustained notes are held as far as stealing goes.
+        voices.note_on(pitch(62), 0);
+        voices.note_on(pitch(64), 0);
+        assert_eq!(voices.note_on(pitch(65), 0), Some(Channel::A));
+        voices.set_sustain(false);
+        run(&mut voices, &mut psg, 2);
+        voices.all_notes_off();
+        run(&mut voices, &mut psg, 6);
+        assert_eq!(psg.registers().value(0x9), 0);
+        assert_eq!(voices.channel_of(pitch(62)), None);
+    }
+}
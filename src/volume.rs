@@ -0,0 +1,158 @@
+//! Mapping 0..=127 controls such as MIDI velocity and channel volume, and
+//! gains in percent, onto the chip's level steps, and what those steps
+//! measure at the output.
+
+use crate::ChipKind;
+
+/// The smallest control value for each attenuation, loudest first: values
+/// at least `THRESHOLDS[n]`, but below `THRESHOLDS[n - 1]`, lose `n` steps.
+///
+/// MIDI treats these controls as gains of `40 * log10(value / 127)` dB, and
+/// each of the chip's fixed levels is about 3 dB below the one above it, so
+/// the table is that curve cut into 3 dB slices and rounded.
+const THRESHOLDS: [u8; 16] = [
+    117, 99, 83, 70, 59, 50, 42, 35, 30, 25, 21, 18, 15, 13, 11, 0,
+];
+
+/// The smallest gain, in percent, for each attenuation, as [`THRESHOLDS`]
+/// are for controls.
+///
+/// A gain is how loud a channel sounds, and sounding half as loud takes
+/// about 10 dB, so a gain of `p` percent is `10 * log2(p / 100)` dB, cut into
+/// the same 3 dB slices and rounded: 50% is three steps down.
+const GAIN_THRESHOLDS: [u8; 16] = [91, 74, 60, 49, 40, 32, 26, 22, 18, 14, 12, 10, 8, 7, 5, 0];
+
+/// The YM2149's output at each of its 32 levels, as measured and used by
+/// the Ayumi emulator, full scale 0xFFFF. The envelope uses all 32; fixed
+/// level `n` is entry `2n + 1`.
+pub const YM2149_DAC: [u16; 32] = [
+    0, 0, 305, 506, 718, 915, 1114, 1312, 1597, 1946, 2298, 2647, 3181, 3823, 4460, 5097, 6063,
+    7280, 8503, 9731, 11578, 13864, 16147, 18422, 21871, 26242, 30630, 35024, 41626, 49676, 57666,
+    65535,
+];
+
+/// The AY-3-8910's output at each of its 16 levels, from the same source,
+/// full scale 0xFFFF. Fixed levels and the envelope share them.
+pub const AY8910_DAC: [u16; 16] = [
+    0, 655, 947, 1380, 2012, 2985, 4227, 7036, 8296, 13434, 19150, 24434, 32278, 41636, 52794,
+    65535,
+];
+
+/// The output of `chip` at fixed `level`, 0 to 15, full scale 0xFFFF.
+pub const fn fixed_output(chip: ChipKind, level: u8) -> u16 {
+    chip.profile().fixed_output(level)
+}
+
+/// The output of `chip` with the envelope at `step`, 0 to 31 in the
+/// YM2149's steps; the AY-3-8910 holds each of its 16 for two.
+pub const fn envelope_output(chip: ChipKind, step: u8) -> u16 {
+    chip.profile().envelope_output(step)
+}
+
+/// The level `chip`'s envelope plays at `step`, 0 to 31 in the YM2149's
+/// steps, counted in its own: 0 to 31 on the YM2149, 0 to 15 on the
+/// AY-3-8910.
+pub const fn envelope_level(chip: ChipKind, step: u8) -> u8 {
+    chip.profile().envelope_level(step)
+}
+
+/// How many level steps quieter than full `value` sounds. Very small values
+/// come out at 15, enough to silence any level.
+pub const fn attenuation(value: u8) -> u8 {
+    steps(&THRESHOLDS, value)
+}
+
+/// `level` turned down by `value` out of 127.
+pub const fn scale_level(level: u8, value: u8) -> u8 {
+    level.saturating_sub(attenuation(value))
+}
+
+/// How many level steps quieter a gain of `percent` sounds. 100 and over
+/// is none, and 0 is 15, enough to silence any level.
+pub const fn gain_attenuation(percent: u8) -> u8 {
+    steps(&GAIN_THRESHOLDS, percent)
+}
+
+/// `level` at a gain of `percent`.
+pub const fn scale_level_by_gain(level: u8, percent: u8) -> u8 {
+    level.saturating_sub(gain_attenuation(percent))
+}
+
+/// The steps `value` loses by `thresholds`.
+const fn steps(thresholds: &[u8; 16], value: u8) -> u8 {
+    let mut steps = 0;
+    while steps < 15 && value < thresholds[steps as usize] {
+        steps += 1;
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_is_perceptual_and_monotonic() {
+        assert_eq!(attenuation(127), 0);
+        assert_eq!(attenuation(100), 1);
+        // Half the control value is 12 dB: four steps.
+        assert_eq!(attenuation(64), 4);
+        assert_eq!(attenuation(32), 8);
+        assert_eq!(attenuation(0), 15);
+        for value in 1..=127 {
+            assert!(attenuation(value) <= attenuation(value - 1));
+        }
+        assert_eq!(scale_level(13, 64), 9);
+        assert_eq!(scale_level(3, 20), 0);
+    }
+
+    #[test]
+    fn gains_halve_loudness_at_50_percent() {
+        assert_eq!(gain_attenuation(100), 0);
+        assert_eq!(gain_attenuation(255), 0);
+        assert_eq!(gain_attenuation(91), 0);
+        // 75% is 4.2 dB down and 90% 1.5 dB: a step each.
+        assert_eq!(gain_attenuation(90), 1);
+        assert_eq!(gain_attenuation(75), 1);
+        // Half as loud is 10 dB, three steps; a quarter is 20 dB, seven.
+        assert_eq!(gain_attenuation(50), 3);
+        assert_eq!(gain_attenuation(25), 7);
+        assert_eq!(gain_attenuation(1), 15);
+        assert_eq!(gain_attenuation(0), 15);
+        for percent in 1..=100 {
+            assert!(gain_attenuation(percent) <= gain_attenuation(percent - 1));
+        }
+        assert_eq!(scale_level_by_gain(15, 50), 12);
+        assert_eq!(scale_level_by_gain(2, 50), 0);
+    }
+
+    #[test]
+    fn dac_tables_rise_from_silence_to_full_scale() {
+        assert_eq!((YM2149_DAC[0], YM2149_DAC[31]), (0, 0xFFFF));
+        assert_eq!((AY8910_DAC[0], AY8910_DAC[15]), (0, 0xFFFF));
+        // The YM's bottom step is as silent as 0, the rest all rise.
+        assert_eq!(YM2149_DAC[1], 0);
+        assert!(YM2149_DAC[1..].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(AY8910_DAC.windows(2).all(|pair| pair[0] < pair[1]));
+        for chip in [ChipKind::Ym2149, ChipKind::Ay8910] {
+            assert_eq!(fixed_output(chip, 0), 0);
+            assert_eq!(fixed_output(chip, 15), 0xFFFF);
+            for level in 0..16 {
+                assert_eq!(
+                    envelope_output(chip, level * 2 + 1),
+                    fixed_output(chip, level)
+                );
+            }
+        }
+        // The AY moves a level every other step.
+        let levels = |chip| [0, 1, 2, 30, 31].map(|step| envelope_level(chip, step));
+        assert_eq!(levels(ChipKind::Ym2149), [0, 1, 2, 30, 31]);
+        assert_eq!(levels(ChipKind::Ay8910), [0, 0, 1, 15, 15]);
+        assert_ne!(
+            envelope_output(ChipKind::Ym2149, 2),
+            envelope_output(ChipKind::Ay8910, 2)
+        );
+        // Quiet levels differ most between the two.
+        assert!(fixed_output(ChipKind::Ay8910, 1) > fixed_output(ChipKind::Ym2149, 1));
+    }
+}
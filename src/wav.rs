@@ -0,0 +1,482 @@
+//! Songs rendered to WAV files through the emulator, for previews on the
+//! host and listenable output from CI.
+//!
+//! [`render_wav`] ticks a player against a [`Ym2149Emulator`] at the rate it
+//! expects and writes what comes out as 16-bit PCM, mono or, if the
+//! emulator has a [`StereoMode`] other than [`StereoMode::Mono`], stereo.
+//! It is told how many ticks to render, so the header is right from the
+//! start and any writer will do; a song that ends sooner is followed by
+//! silence. [`render_wav_until_end`] renders until the song ends instead,
+//! writing the header with no length and going back to fill it in once it
+//! knows, so needs a writer that can [`Seek`].
+//!
+//! Anything [`Playback`] can be rendered: a [`FramePlayer`], playing a YM
+//! file, a `.psg` stream or any other [`FrameSource`], or a [`Sequencer`]
+//! playing a [`Song`](crate::sequencer::Song). A song going round its loop
+//! for ever is stopped after [`WavOptions::with_max_loops`] loops, or ends
+//! as its [`LoopPolicy`](crate::loop_policy::LoopPolicy) says, which can
+//! fade it out.
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::vec::Vec;
+
+use crate::emulator::{StereoMode, Ym2149Emulator};
+use crate::frame_player::{FramePlayer, FrameSource, PlayStatus, PlayerHooks, PlayerState};
+use crate::psg::Psg;
+use crate::sequencer::{Sequencer, SequencerHooks};
+use crate::storage::Storage;
+
+/// Length of the RIFF header written before the samples.
+pub const HEADER_LEN: usize = 44;
+
+/// A player [`render_wav`] can tick.
+pub trait Playback {
+    /// Plays a tick into `emulator`, saying whether the song is over.
+    fn play_tick(&mut self, emulator: &mut Ym2149Emulator) -> bool;
+
+    /// How many times the song has gone back to its loop start.
+    fn loops_completed(&self) -> u32;
+}
+
+impl<S: FrameSource, H: PlayerHooks> Playback for FramePlayer<S, H> {
+    fn play_tick(&mut self, emulator: &mut Ym2149Emulator) -> bool {
+        match self.tick(emulator) {
+            Ok(PlayStatus::Finished | PlayStatus::Failed(_)) => true,
+            Ok(PlayStatus::Idle) => self.state() == PlayerState::Stopped,
+            Ok(_) => false,
+            Err(never) => match never {},
+        }
+    }
+
+    fn loops_completed(&self) -> u32 {
+        FramePlayer::loops_completed(self)
+    }
+}
+
+impl<H: SequencerHooks, S: Storage> Playback for Sequencer<H, S> {
+    fn play_tick(&mut self, emulator: &mut Ym2149Emulator) -> bool {
+        match self.tick(emulator) {
+            Ok(finished) => finished,
+            Err(never) => match never {},
+        }
+    }
+
+    fn loops_completed(&self) -> u32 {
+        Sequencer::loops_completed(self)
+    }
+}
+
+/// How [`render_wav`] plays a song.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WavOptions {
+    sample_rate: u32,
+    tick_hertz: u32,
+    max_loops: Option<u32>,
+}
+
+impl WavOptions {
+    /// Samples at `sample_rate`, ticking the player `tick_hertz` times a
+    /// second: the frame rate of a YM file, or the rate a sequencer was
+    /// made for.
+    pub const fn new(sample_rate: u32, tick_hertz: u32) -> WavOptions {
+        WavOptions {
+            sample_rate: if sample_rate == 0 { 1 } else { sample_rate },
+            tick_hertz: if tick_hertz == 0 { 1 } else { tick_hertz },
+            max_loops: None,
+        }
+    }
+
+    /// Ends the song as it goes back to its loop start for the `loops + 1`th
+    /// time, so `loops` times round; 0 plays it through once.
+    pub const fn with_max_loops(mut self, loops: u32) -> WavOptions {
+        self.max_loops = Some(loops);
+        self
+    }
+
+    pub const fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub const fn tick_hertz(&self) -> u32 {
+        self.tick_hertz
+    }
+
+    /// Samples in the first `ticks` ticks, a sample for each side in stereo
+    /// counted once.
+    pub const fn samples(&self, ticks: u32) -> u64 {
+        ticks as u64 * self.sample_rate as u64 / self.tick_hertz as u64
+    }
+}
+
+/// What [`render_wav`] and [`render_wav_until_end`] wrote.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WavSummary {
+    /// Ticks rendered, including any silence after the song.
+    pub ticks: u32,
+    /// Samples written, a sample for each side in stereo counted once.
+    pub samples: u64,
+    /// Whether the song ended, or ran past its loop limit, in that time.
+    pub ended: bool,
+    /// Bytes written, header and all.
+    pub bytes: u64,
+}
+
+/// The RIFF header for `data_bytes` bytes of 16-bit samples, `channels`
+/// interleaved at `sample_rate`. Lengths too long for RIFF's 32 bits are
+/// written as the most they can hold.
+pub fn header(channels: u16, sample_rate: u32, data_bytes: u64) -> [u8; HEADER_LEN] {
+    let data = data_bytes.min((u32::MAX - 36) as u64) as u32;
+    let block = channels * 2;
+    let mut header = [0; HEADER_LEN];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&(36 + data).to_le_bytes());
+    header[8..16].copy_from_slice(b"WAVEfmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    // PCM.
+    header[20..22].copy_from_slice(&1u16.to_le_bytes());
+    header[22..24].copy_from_slice(&channels.to_le_bytes());
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    header[28..32].copy_from_slice(&(sample_rate * block as u32).to_le_bytes());
+    header[32..34].copy_from_slice(&block.to_le_bytes());
+    header[34..36].copy_from_slice(&16u16.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&data.to_le_bytes());
+    header
+}
+
+/// Renders `ticks` ticks of `player` into `emulator` and writes them to
+/// `out` as a WAV file. Once the song is over the chip is silenced and the
+/// rest is silence.
+pub fn render_wav<P: Playback, W: Write>(
+    player: &mut P,
+    emulator: &mut Ym2149Emulator,
+    options: WavOptions,
+    ticks: u32,
+    mut out: W,
+) -> io::Result<WavSummary> {
+    let channels = channels(emulator);
+    let data_bytes = options.samples(ticks) * channels as u64 * 2;
+    out.write_all(&header(channels, options.sample_rate, data_bytes))?;
+    let mut renderer = Renderer::new(options);
+    for _ in 0..ticks {
+        renderer.tick(player, emulator, &mut out)?;
+    }
+    Ok(renderer.summary(channels))
+}
+
+/// Renders `player` into `emulator` until the song is over, or for
+/// `max_ticks` ticks if it goes on longer, and writes it to `out` as a WAV
+/// file, filling in the header's lengths at the end.
+pub fn render_wav_until_end<P: Playback, W: Write + Seek>(
+    player: &mut P,
+    emulator: &mut Ym2149Emulator,
+    options: WavOptions,
+    max_ticks: u32,
+    mut out: W,
+) -> io::Result<WavSummary> {
+    let channels = channels(emulator);
+    let start = out.stream_position()?;
+    out.write_all(&header(channels, options.sample_rate, 0))?;
+    let mut renderer = Renderer::new(options);
+    while renderer.ticks < max_ticks && !renderer.ended {
+        renderer.tick(player, emulator, &mut out)?;
+    }
+    let summary = renderer.summary(channels);
+    let data_bytes = summary.bytes - HEADER_LEN as u64;
+    out.seek(SeekFrom::Start(start))?;
+    out.write_all(&header(channels, options.sample_rate, data_bytes))?;
+    out.seek(SeekFrom::Start(start + summary.bytes))?;
+    Ok(summary)
+}
+
+fn channels(emulator: &Ym2149Emulator) -> u16 {
+    match emulator.stereo_mode() {
+        StereoMode::Mono => 1,
+        _ => 2,
+    }
+}
+
+/// Where a render has got to.
+struct Renderer {
+    options: WavOptions,
+    ticks: u32,
+    samples: u64,
+    ended: bool,
+    /// The samples of a tick, as bytes.
+    buffer: Vec<u8>,
+}
+
+impl Renderer {
+    fn new(options: WavOptions) -> Renderer {
+        Renderer {
+            options,
+            ticks: 0,
+            samples: 0,
+            ended: false,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Plays a tick, if the song isn't over, and writes its samples.
+    fn tick<P: Playback, W: Write>(
+        &mut self,
+        player: &mut P,
+        emulator: &mut Ym2149Emulator,
+        out: &mut W,
+    ) -> io::Result<()> {
+        if !self.ended {
+            let finished = player.play_tick(emulator);
+            let max_loops = self.options.max_loops;
+            self.ended =
+                finished || max_loops.is_some_and(|loops| player.loops_completed() > loops);
+            if self.ended {
+                match emulator.silence() {
+                    Ok(()) => {}
+                    Err(never) => match never {},
+                }
+            }
+        }
+        self.ticks += 1;
+        let samples = (self.options.samples(self.ticks) - self.samples) as usize;
+        self.samples += samples as u64;
+        self.buffer.clear();
+        let rate = self.options.sample_rate;
+        match emulator.stereo_mode() {
+            StereoMode::Mono => {
+                let mut rendered = std::vec![0; samples];
+                emulator.render(&mut rendered, rate);
+                self.buffer
+                    .extend(rendered.iter().flat_map(|sample| sample.to_le_bytes()));
+            }
+            _ => {
+                let mut rendered = std::vec![[0; 2]; samples];
+                emulator.render_stereo(&mut rendered, rate);
+                self.buffer.extend(
+                    rendered
+                        .iter()
+                        .flatten()
+                        .flat_map(|sample| sample.to_le_bytes()),
+                );
+            }
+        }
+        out.write_all(&self.buffer)
+    }
+
+    fn summary(&self, channels: u16) -> WavSummary {
+        WavSummary {
+            ticks: self.ticks,
+            samples: self.samples,
+            ended: self.ended,
+            bytes: HEADER_LEN as u64 + self.samples * channels as u64 * 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_player::{Frame, R13_UNCHANGED};
+    use crate::instrument::Instrument;
+    use crate::pitch::{Note, Pitch};
+    use crate::sequencer::{Cell, OrderEntry, Pattern, Row, Song};
+    use std::io::Cursor;
+
+    /// A falling note on A with the envelope on B.
+    static SONG: [Frame; 4] = [
+        [
+            200, 0, 0, 1, 0, 0, 0, 0x3C, 15, 0x10, 0, 0x80, 0, 0x0E, 0, 0,
+        ],
+        [
+            180,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0x3C,
+            13,
+            0x10,
+            0,
+            0x80,
+            0,
+            R13_UNCHANGED,
+            0,
+            0,
+        ],
+        [
+            160,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0x3C,
+            11,
+            0x10,
+            0,
+            0x80,
+            0,
+            R13_UNCHANGED,
+            0,
+            0,
+        ],
+        [
+            140,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            0x3C,
+            9,
+            0x10,
+            0,
+            0x80,
+            0,
+            R13_UNCHANGED,
+            0,
+            0,
+        ],
+    ];
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    /// FNV-1a, enough to notice a render changing.
+    fn checksum(bytes: &[u8]) -> u32 {
+        bytes.iter().fold(0x811C_9DC5, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    #[test]
+    fn known_lengths_write_the_header_first_and_pad_with_silence() {
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        let mut emulator = Ym2149Emulator::new();
+        let mut out = Vec::new();
+        let options = WavOptions::new(44_100, 50);
+        let summary = render_wav(&mut player, &mut emulator, options, 10, &mut out).unwrap();
+        assert_eq!(
+            summary,
+            WavSummary {
+                ticks: 10,
+                samples: 8820,
+                ended: true,
+                bytes: 44 + 8820 * 2,
+            }
+        );
+        assert_eq!(out.len() as u64, summary.bytes);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32_at(&out, 4), out.len() as u32 - 8);
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&out, 24), 44_100);
+        assert_eq!(u32_at(&out, 28), 88_200);
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(u32_at(&out, 40), 8820 * 2);
+        // Four frames of song, then silence.
+        let samples = &out[44..];
+        assert!(samples[..4 * 882 * 2].iter().any(|&byte| byte != 0));
+        assert!(samples[5 * 882 * 2..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn streamed_lengths_are_filled_in_at_the_end() {
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        let mut emulator = Ym2149Emulator::new();
+        emulator.set_stereo_mode(StereoMode::StereoAbc);
+        // Somewhere into a file already being written.
+        let mut out = Cursor::new(std::vec![0xAA; 3]);
+        out.seek(SeekFrom::End(0)).unwrap();
+        let options = WavOptions::new(8_000, 50);
+        let summary =
+            render_wav_until_end(&mut player, &mut emulator, options, 1000, &mut out).unwrap();
+        // The four frames, and the tick that found the end.
+        assert_eq!((summary.ticks, summary.samples), (5, 800));
+        assert!(summary.ended);
+        let out = out.into_inner();
+        assert_eq!(out.len() as u64, 3 + summary.bytes);
+        let wav = &out[3..];
+        assert_eq!(u32_at(wav, 4), wav.len() as u32 - 8);
+        assert_eq!(u32_at(wav, 40), 800 * 4);
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 2);
+        assert_eq!(u16::from_le_bytes([wav[32], wav[33]]), 4);
+    }
+
+    #[test]
+    fn endless_loops_stop_at_the_limit() {
+        let mut player = FramePlayer::new(&SONG[..]).with_loop(2);
+        player.play();
+        let mut emulator = Ym2149Emulator::new();
+        let options = WavOptions::new(8_000, 50).with_max_loops(3);
+        let summary = render_wav_until_end(
+            &mut player,
+            &mut emulator,
+            options,
+            1000,
+            Cursor::new(Vec::new()),
+        )
+        .unwrap();
+        // Through once, three times round the last two frames, then the
+        // tick going round again ends it.
+        assert_eq!(summary.ticks, 4 + 3 * 2 + 1);
+        assert!(summary.ended);
+        assert_eq!(player.loops_completed(), 4);
+
+        // Without a limit, only the tick count stops it.
+        let mut player = FramePlayer::new(&SONG[..]).with_loop(2);
+        player.play();
+        let options = WavOptions::new(8_000, 50);
+        let summary = render_wav_until_end(
+            &mut player,
+            &mut emulator,
+            options,
+            100,
+            Cursor::new(Vec::new()),
+        )
+        .unwrap();
+        assert_eq!(summary.ticks, 100);
+        assert!(!summary.ended);
+    }
+
+    #[test]
+    fn sequencers_render_too() {
+        static BANK: [Instrument; 0] = [];
+        static ROWS: [Row; 2] = [
+            Row::new(Cell::note(Pitch::new(Note::A, 4)), Cell::EMPTY, Cell::EMPTY),
+            Row::new(Cell::OFF, Cell::EMPTY, Cell::EMPTY),
+        ];
+        static PATTERNS: [Pattern; 1] = [Pattern::new(&ROWS)];
+        static ORDER: [OrderEntry; 1] = [OrderEntry::new(0)];
+        static TUNE: Song = Song::new(&PATTERNS, &ORDER);
+        let mut sequencer = Sequencer::new(&TUNE, &BANK, 50);
+        let mut emulator = Ym2149Emulator::new();
+        let options = WavOptions::new(8_000, 50);
+        let mut out = Cursor::new(Vec::new());
+        let summary =
+            render_wav_until_end(&mut sequencer, &mut emulator, options, 1000, &mut out).unwrap();
+        assert!(summary.ended && summary.ticks < 1000);
+        let wav = out.into_inner();
+        assert!(wav[44..].iter().any(|&byte| byte != 0));
+    }
+
+    #[test]
+    fn a_short_render_matches_its_golden_checksum() {
+        let mut player = FramePlayer::new(&SONG[..]);
+        player.play();
+        let mut emulator = Ym2149Emulator::new();
+        emulator.set_dc_filter(true);
+        let mut out = Vec::new();
+        let options = WavOptions::new(22_050, 50);
+        render_wav(&mut player, &mut emulator, options, 6, &mut out).unwrap();
+        assert_eq!(out.len(), 44 + 6 * 441 * 2);
+        // Changes here mean the emulator or a player now sounds different:
+        // listen before taking the new value.
+        assert_eq!(checksum(&out), 0xEE10_6851);
+    }
+}
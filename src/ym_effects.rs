@@ -0,0 +1,387 @@
+//! Playing YM6 songs' special effects along with their frames.
+//!
+//! [`YmEffects`] rides on a [`YmSong`]'s player as its
+//! [hooks](crate::frame_player::PlayerHooks), noting each frame as it is
+//! written. After every player tick, [`YmEffects::update`] starts, retunes
+//! or stops the frame's effects, each on its channel's [`DigidrumPlayer`],
+//! [`SidVoice`] or the [`SyncBuzzer`]. The effects themselves run much
+//! faster than the frames: call [`YmEffects::on_timer`] from a timer
+//! interrupt at the rate given to [`YmEffects::new`]. That one timer stands
+//! in for the ST's MFP timers, each effect stepping on the tick nearest each
+//! of its own timer's interrupts, so run it well above the effects' rates;
+//! 20 kHz or so does for most songs.
+//!
+//! As on the ST, SID voices and the buzzer stop at the first frame that
+//! doesn't ask for them again, while digidrums play to their end. Sinus-SID
+//! isn't played yet: [`YmEffects::update`] reports it as skipped, along with
+//! drums the file doesn't have. Songs without effects, YM5 songs among
+//! them, never get a write from here.
+//!
+//! ```
+//! use ym2149::frame_player::{FramePlayer, PlayStatus};
+//! use ym2149::psg::Psg;
+//! use ym2149::ym_effects::YmEffects;
+//! use ym2149::ym_file::YmSong;
+//!
+//! fn start(song: YmSong<'static>) -> FramePlayer<YmSong<'static>, YmEffects<'static>> {
+//!     let mut player = song.into_player(50).with_hooks(YmEffects::new(song, 25_000));
+//!     player.play();
+//!     player
+//! }
+//!
+//! // The 50 Hz interrupt.
+//! fn on_frame<P: Psg>(
+//!     player: &mut FramePlayer<YmSong<'static>, YmEffects<'static>>,
+//!     psg: &mut P,
+//! ) -> Result<PlayStatus, P::Error> {
+//!     let status = player.tick(psg)?;
+//!     player.hooks_mut().update(psg)?;
+//!     Ok(status)
+//! }
+//!
+//! // The 25 kHz interrupt.
+//! fn on_effect_timer<P: Psg>(
+//!     player: &mut FramePlayer<YmSong<'static>, YmEffects<'static>>,
+//!     psg: &mut P,
+//! ) -> Result<(), P::Error> {
+//!     player.hooks_mut().on_timer(psg)
+//! }
+//! ```
+
+use crate::digidrum::DigidrumPlayer;
+use crate::frame_player::PlayerHooks;
+use crate::math::{self, PhaseAccumulator};
+use crate::mfp::MfpTimer;
+use crate::psg::Psg;
+use crate::registers::Registers;
+use crate::sid::SidVoice;
+use crate::sync_buzzer::SyncBuzzer;
+use crate::ym_file::{Effect, EffectKind, YmSong};
+use crate::EnvelopeShape;
+
+/// Picks the ticks of a fast timer nearest to a slower timer's interrupts:
+/// the phase wraps during the ticks `timer` interrupts in.
+fn timer_divider(timer: MfpTimer, tick_hertz: u32) -> PhaseAccumulator {
+    PhaseAccumulator::new(math::q32_ratio(
+        timer.millihertz() as u64,
+        tick_hertz as u64 * 1000,
+    ))
+}
+
+/// The effects of a [`YmSong`], kept going between its frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YmEffects<'a> {
+    song: YmSong<'a>,
+    tick_hertz: u32,
+    written: Option<u32>,
+    ended: bool,
+    drums: [DigidrumPlayer<'a>; 3],
+    drum_timers: [PhaseAccumulator; 3],
+    sids: [Option<SidVoice>; 3],
+    buzzer: Option<(SyncBuzzer, PhaseAccumulator)>,
+}
+
+impl<'a> YmEffects<'a> {
+    /// Effects for `song`, with [`YmEffects::on_timer`] called `tick_hertz`
+    /// times a second.
+    pub fn new(song: YmSong<'a>, tick_hertz: u32) -> YmEffects<'a> {
+        let drum = DigidrumPlayer::new().with_dac_setup(false);
+        YmEffects {
+            song,
+            tick_hertz,
+            written: None,
+            ended: false,
+            drums: [drum.clone(), drum.clone(), drum],
+            drum_timers: [PhaseAccumulator::STOPPED; 3],
+            sids: [None, None, None],
+            buzzer: None,
+        }
+    }
+
+    pub fn song(&self) -> &YmSong<'a> {
+        &self.song
+    }
+
+    /// Whether any effect is running, and so whether
+    /// [`YmEffects::on_timer`] has anything to do.
+    pub fn is_active(&self) -> bool {
+        self.drums.iter().any(DigidrumPlayer::is_playing)
+            || self.sids.iter().any(Option::is_some)
+            || self.buzzer.is_some()
+    }
+
+    /// Sets up the effects of the frame written since the last call, and
+    /// returns those it skipped. Call it after each player tick.
+    pub fn update<P: Psg>(&mut self, psg: &mut P) -> Result<[Option<Effect>; 2], P::Error> {
+        if core::mem::take(&mut self.ended) {
+            self.stop();
+        }
+        let Some(index) = self.written.take() else {
+            return Ok([None, None]);
+        };
+        let mut skipped = [None, None];
+        let mut sids = [false; 3];
+        let mut buzzing = false;
+        for (effect, skip) in self.song.effects(index).into_iter().zip(&mut skipped) {
+            let Some(effect) = effect.filter(|effect| !effect.timer.is_stopped()) else {
+                continue;
+            };
+            let n = effect.channel.index();
+            match effect.kind {
+                EffectKind::Digidrum => match self.song.digidrum_sample(effect.value as u16) {
+                    Some(sample) => {
+                        self.drums[n].start(psg, effect.channel, sample)?;
+                        self.drum_timers[n] = timer_divider(effect.timer, self.tick_hertz);
+                    }
+                    None => *skip = Some(effect),
+                },
+                EffectKind::Sid => {
+                    sids[n] = true;
+                    let voice = SidVoice::from_mfp(effect.channel, effect.timer, effect.value);
+                    match &mut self.sids[n] {
+                        Some(playing) => {
+                            playing.set_millihertz(voice.millihertz());
+                            playing.set_levels(effect.value, 0);
+                        }
+                        idle => {
+                            let mut voice = voice;
+                            voice.set_tick_rate(self.tick_hertz);
+                            *idle = Some(voice);
+                        }
+                    }
+                }
+                EffectKind::SyncBuzzer => {
+                    buzzing = true;
+                    self.buzz(psg, effect)?;
+                }
+                EffectKind::SinusSid => *skip = Some(effect),
+            }
+        }
+        // The frame has just put the song's own levels back.
+        for (voice, asked) in self.sids.iter_mut().zip(sids) {
+            if !asked {
+                *voice = None;
+            }
+        }
+        if !buzzing {
+            self.buzzer = None;
+        }
+        Ok(skipped)
+    }
+
+    /// Starts or retunes the buzzer, using the envelope period and shape
+    /// the frame has just written.
+    fn buzz<P: Psg>(&mut self, psg: &mut P, effect: Effect) -> Result<(), P::Error> {
+        let registers = psg.registers();
+        let period = u16::from_le_bytes([registers.value(0xB), registers.value(0xC)]);
+        let shape = EnvelopeShape::from_bits_truncate(registers.value(0xD));
+        let divider = timer_divider(effect.timer, self.tick_hertz);
+        match &mut self.buzzer {
+            Some((buzzer, playing)) if buzzer.channel() == effect.channel => {
+                buzzer.set_millihertz(effect.timer.millihertz());
+                buzzer.set_shape(shape);
+                buzzer.set_envelope_period(psg, period)?;
+                playing.set_step(divider.step());
+            }
+            _ => {
+                let mut buzzer = SyncBuzzer::from_mfp(effect.channel, effect.timer, shape, period);
+                buzzer.start(psg)?;
+                self.buzzer = Some((buzzer, divider));
+            }
+        }
+        Ok(())
+    }
+
+    /// The fast timer's interrupt: steps every running effect.
+    pub fn on_timer<P: Psg>(&mut self, psg: &mut P) -> Result<(), P::Error> {
+        for (drum, timer) in self.drums.iter_mut().zip(&mut self.drum_timers) {
+            if drum.is_playing() && timer.advance() {
+                drum.tick_sample(psg)?;
+            }
+        }
+        for voice in self.sids.iter_mut().flatten() {
+            voice.tick_hi_res(psg)?;
+        }
+        if let Some((buzzer, timer)) = &mut self.buzzer {
+            if timer.advance() {
+                buzzer.on_timer(psg)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops every effect without touching the chip. Do this along with
+    /// pausing, stopping or seeking the player, which sees to the levels.
+    pub fn stop(&mut self) {
+        for drum in &mut self.drums {
+            *drum = DigidrumPlayer::new().with_dac_setup(false);
+        }
+        self.sids = [None, None, None];
+        self.buzzer = None;
+        self.written = None;
+    }
+}
+
+impl PlayerHooks for YmEffects<'_> {
+    fn on_frame(&mut self, index: u32, _: &Registers) {
+        self.written = Some(index);
+    }
+
+    /// The player has silenced the chip, so the effects stop with it.
+    fn on_end(&mut self) {
+        self.ended = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::frame_player::Frame;
+    use crate::test_support::{ym_file, FakePsg};
+    use std::vec::Vec;
+
+    /// With timers at 9600 Hz (R6 or R8 bits 7–5 of 1, a count of 64) and
+    /// the fast timer at 19200 Hz, effects step every other tick.
+    const TICK_HERTZ: u32 = 19_200;
+
+    fn frame(r1: u8, r3: u8, levels: [u8; 3]) -> Frame {
+        let mut frame = [0; 16];
+        frame[0x1] = r1;
+        frame[0x3] = r3;
+        frame[0x6] = 0x20;
+        frame[0x7] = 0x3F;
+        frame[0x8] = levels[0];
+        frame[0x9] = levels[1];
+        frame[0xA] = levels[2];
+        frame[0xB] = 0x40;
+        frame[0xD] = 0xFF;
+        frame[0xE] = 64;
+        frame[0xF] = 64;
+        frame
+    }
+
+    /// Plays `data` for `frames` frames, with `ticks` fast ticks after
+    /// each, returning all the writes and what was skipped.
+    fn play(data: &[u8], frames: usize, ticks: usize) -> (Vec<(u8, u8)>, Vec<Effect>) {
+        let song = YmSong::new(data).unwrap();
+        let mut player = song
+            .into_player(50)
+            .with_hooks(YmEffects::new(song, TICK_HERTZ));
+        player.play();
+        let mut psg = FakePsg::new();
+        let mut skipped = Vec::new();
+        for _ in 0..frames {
+            player.tick(&mut psg).unwrap();
+            skipped.extend(
+                player
+                    .hooks_mut()
+                    .update(&mut psg)
+                    .unwrap()
+                    .into_iter()
+                    .flatten(),
+            );
+            for _ in 0..ticks {
+                player.hooks_mut().on_timer(&mut psg).unwrap();
+            }
+        }
+        (psg.take_writes(), skipped)
+    }
+
+    #[test]
+    fn songs_without_effects_play_as_without_hooks() {
+        // Effect bits in a YM5 song are just masked off.
+        let with_bits = [frame(0x51, 0x20, [0, 12, 4]), frame(0, 0, [9, 12, 4])];
+        let without = [frame(0, 0, [0, 12, 4]), frame(0, 0, [9, 12, 4])];
+        for (version, frames) in [(b"YM5!", with_bits), (b"YM6!", without)] {
+            let data = ym_file(version, &[&[0xFF]], &frames);
+            let song = YmSong::new(&data).unwrap();
+            let mut plain = song.into_player(50);
+            plain.play();
+            let mut psg = FakePsg::new();
+            for _ in 0..4 {
+                plain.tick(&mut psg).unwrap();
+            }
+            assert_eq!(play(&data, 4, 10), (psg.take_writes(), Vec::new()));
+        }
+    }
+
+    #[test]
+    fn drums_play_at_their_rate_to_the_end() {
+        let frames = [frame(0x50, 0, [0, 0, 0]), frame(0, 0, [0, 0, 0])];
+        let data = ym_file(b"YM6!", &[&[255, 128, 0]], &frames);
+        let song = YmSong::new(&data).unwrap();
+        let mut effects = YmEffects::new(song, TICK_HERTZ);
+        let mut psg = FakePsg::new();
+        effects.on_frame(0, psg.registers());
+        assert_eq!(effects.update(&mut psg), Ok([None, None]));
+        assert!(effects.is_active());
+        for _ in 0..8 {
+            effects.on_timer(&mut psg).unwrap();
+        }
+        // Into the next frame, which doesn't ask for the drum again.
+        effects.on_frame(1, psg.registers());
+        effects.update(&mut psg).unwrap();
+        for _ in 0..8 {
+            effects.on_timer(&mut psg).unwrap();
+        }
+        assert_eq!(psg.take_writes(), [(0x8, 15), (0x8, 13), (0x8, 0)]);
+        assert!(!effects.is_active());
+    }
+
+    #[test]
+    fn sids_and_buzzers_last_a_frame_unless_asked_again() {
+        // A buzzer on C and a SID on B, both timers interrupting every fourth
+        // tick, then nothing.
+        let mut buzzing = frame(0xF0, 0x20, [0x20, 12, 0x10]);
+        buzzing[0xD] = 0x0C;
+        buzzing[0xE] = 128;
+        buzzing[0xF] = 128;
+        let frames = [buzzing, frame(0, 0, [0, 12, 0x10])];
+        let data = ym_file(b"YM6!", &[], &frames);
+        let song = YmSong::new(&data).unwrap();
+        let mut player = song
+            .into_player(50)
+            .with_hooks(YmEffects::new(song, TICK_HERTZ));
+        player.play();
+        let mut psg = FakePsg::new();
+        player.tick(&mut psg).unwrap();
+        psg.take_writes();
+        player.hooks_mut().update(&mut psg).unwrap();
+        // The buzzer starts by retriggering, then its timer takes over. The
+        // SID's level starts high, as the frame left it, and flips on each
+        // interrupt.
+        assert_eq!(psg.take_writes(), [(0xD, 0x0C)]);
+        for _ in 0..8 {
+            player.hooks_mut().on_timer(&mut psg).unwrap();
+        }
+        assert_eq!(psg.take_writes(), [(0xD, 0x0C), (0x9, 0), (0xD, 0x0C)]);
+        // The second frame puts B back, and everything stops.
+        player.tick(&mut psg).unwrap();
+        assert_eq!(player.hooks_mut().update(&mut psg), Ok([None, None]));
+        assert!(!player.hooks().is_active());
+        for _ in 0..8 {
+            player.hooks_mut().on_timer(&mut psg).unwrap();
+        }
+        assert_eq!(psg.take_writes(), [(0x9, 12)]);
+    }
+
+    #[test]
+    fn unplayable_effects_are_reported() {
+        // A sinus-SID on A and a drum the file hasn't got on C.
+        let frames = [frame(0x90, 0x70, [0x25, 0, 3])];
+        let data = ym_file(b"YM6!", &[], &frames);
+        let (_, skipped) = play(&data, 1, 0);
+        assert_eq!(
+            skipped
+                .iter()
+                .map(|effect| (effect.kind, effect.channel))
+                .collect::<Vec<_>>(),
+            [
+                (EffectKind::SinusSid, crate::Channel::A),
+                (EffectKind::Digidrum, crate::Channel::C),
+            ]
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! Songs written out as YM6 files on the host, for sharing with players
+//! that have never heard of this crate.
+//!
+//! A [`YmCapture`] is a chip that plays nothing: drive it as any other
+//! [`Psg`], or as a [`PsgWrite`](crate::psg_write::PsgWrite), and call
+//! [`YmCapture::capture_frame`] once a frame to keep the registers as they
+//! are. R13 is kept as [`R13_UNCHANGED`](crate::registers::R13_UNCHANGED)
+//! unless it was written in the frame, so players restart the envelope only
+//! where the song did.
+//! [`YmCapture::record_sequencer`] does the ticking for a [`Sequencer`], and
+//! finds the song's loop as it goes. [`YmCapture::write_ym6`] then writes
+//! the file, uncompressed, with its frames interleaved as YM6 players expect.
+//!
+//! ```
+//! use ym2149::sequencer::{Cell, OrderEntry, Pattern, Row, Sequencer, Song};
+//! use ym2149::pitch::{Note, Pitch};
+//! use ym2149::ym_export::{YmCapture, FRAME_HERTZ};
+//! use ym2149::ym_file::YmSong;
+//!
+//! static PATTERNS: [Pattern; 1] = [Pattern::new(&[
+//!     Row::new(Cell::note(Pitch::new(Note::A, 4)), Cell::EMPTY, Cell::EMPTY),
+//!     Row::new(Cell::OFF, Cell::EMPTY, Cell::EMPTY),
+//! ])];
+//! static SONG: Song = Song::new(&PATTERNS, &[OrderEntry::new(0)]).looping(0);
+//!
+//! let mut sequencer = Sequencer::new(&SONG, &[], FRAME_HERTZ as u32);
+//! let mut capture = YmCapture::new();
+//! assert!(capture.record_sequencer(&mut sequencer, 3000));
+//! let mut file = Vec::new();
+//! capture.write_ym6(&mut file, b"Beep", b"Me", b"").unwrap();
+//!
+//! let ym = YmSong::new(&file).unwrap();
+//! assert_eq!(ym.frame_count(), 12);
+//! assert_eq!(ym.name(), b"Beep");
+//! ```
+
+use std::io::{self, Write};
+use std::vec::Vec;
+
+use crate::frame_player::snapshot_frame;
+use crate::frame_recorder::YM_END;
+use crate::psg::Psg;
+use crate::registers::{Frame, Registers, REGISTER_COUNT};
+use crate::sequencer::{Sequencer, SequencerHooks, SongEnd};
+use crate::storage::Storage;
+use crate::tuning::DEFAULT_MASTER_CLOCK;
+
+/// Frames a second in the files written here, the 50 Hz of a PAL frame.
+/// Sequencers recorded need making for this rate.
+pub const FRAME_HERTZ: u16 = 50;
+
+/// Bit 0 of a YM file's attributes: frames stored register by register.
+const INTERLEAVED: u32 = 1;
+
+/// A [`Psg`] that records the state of the chip once a frame, for a YM6
+/// file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YmCapture {
+    registers: Registers,
+    master_clock: u32,
+    envelope_written: bool,
+    frames: Vec<Frame>,
+    loop_frame: Option<u32>,
+}
+
+impl Default for YmCapture {
+    fn default() -> YmCapture {
+        YmCapture::new()
+    }
+}
+
+impl YmCapture {
+    /// A capture of a YM2149 at 2 MHz, with no frames yet.
+    pub const fn new() -> YmCapture {
+        YmCapture {
+            registers: Registers::new(),
+            master_clock: DEFAULT_MASTER_CLOCK,
+            envelope_written: false,
+            frames: Vec::new(),
+            loop_frame: None,
+        }
+    }
+
+    /// Tunes what is recorded, and the file, for a chip at `hz`.
+    pub const fn with_master_clock(mut self, hz: u32) -> YmCapture {
+        self.master_clock = hz;
+        self
+    }
+
+    /// Records the registers as they are now as the next frame.
+    pub fn capture_frame(&mut self) {
+        let restart = core::mem::take(&mut self.envelope_written);
+        self.frames.push(snapshot_frame(&self.registers, restart));
+    }
+
+    /// Makes the next frame captured the one players go back to at the end.
+    pub fn mark_loop(&mut self) {
+        self.loop_frame = Some(self.frames.len() as u32);
+    }
+
+    /// The frame marked with [`YmCapture::mark_loop`], if any.
+    pub fn loop_frame(&self) -> Option<u32> {
+        self.loop_frame
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Ticks `sequencer`, made for [`FRAME_HERTZ`], a frame at a time until
+    /// its song ends, or until it goes back to its loop, marking the frame
+    /// the loop starts on. Returns whether it got there within `max_frames`
+    /// frames, which stops a song that jumps round for ever.
+    ///
+    /// The loop starts on the first frame the song's loop position plays,
+    /// so a song whose order jumps back past it isn't looped as it plays.
+    pub fn record_sequencer<H: SequencerHooks, S: Storage>(
+        &mut self,
+        sequencer: &mut Sequencer<H, S>,
+        max_frames: u32,
+    ) -> bool {
+        let (end, loop_order) = {
+            let song = sequencer.song();
+            (song.end, song.loop_order)
+        };
+        for _ in 0..max_frames {
+            let finished = match sequencer.tick(self) {
+                Ok(finished) => finished,
+                Err(never) => match never {},
+            };
+            if sequencer.loops_completed() > 0 {
+                // This frame is the loop played again: players go back.
+                return true;
+            }
+            if end == SongEnd::Loop && self.loop_frame.is_none() && sequencer.order() == loop_order
+            {
+                self.mark_loop();
+            }
+            self.capture_frame();
+            if finished {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Writes the frames as an uncompressed YM6 file, interleaved, at
+    /// [`FRAME_HERTZ`], looping to the start unless a loop was marked, and
+    /// returns its length. The strings can't hold a NUL; one that does is
+    /// [`io::ErrorKind::InvalidInput`], with nothing written.
+    pub fn write_ym6<W: Write>(
+        &self,
+        mut writer: W,
+        name: &[u8],
+        author: &[u8],
+        comment: &[u8],
+    ) -> io::Result<u64> {
+        let strings = [name, author, comment];
+        if strings.iter().any(|text| text.contains(&0)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "YM strings end at a NUL",
+            ));
+        }
+        let count = self.frames.len();
+        let mut file = Vec::with_capacity(34 + 3 + count * REGISTER_COUNT + 4);
+        file.extend_from_slice(b"YM6!LeOnArD!");
+        file.extend_from_slice(&(count as u32).to_be_bytes());
+        file.extend_from_slice(&INTERLEAVED.to_be_bytes());
+        // No digidrums.
+        file.extend_from_slice(&0u16.to_be_bytes());
+        file.extend_from_slice(&self.master_clock.to_be_bytes());
+        file.extend_from_slice(&FRAME_HERTZ.to_be_bytes());
+        file.extend_from_slice(&self.loop_frame.unwrap_or(0).to_be_bytes());
+        // No extra data.
+        file.extend_from_slice(&0u16.to_be_bytes());
+        for text in strings {
+            file.extend_from_slice(text);
+            file.push(0);
+        }
+        for register in 0..REGISTER_COUNT {
+            file.extend(self.frames.iter().map(|frame| frame[register]));
+        }
+        file.extend_from_slice(YM_END);
+        writer.write_all(&file)?;
+        Ok(file.len() as u64)
+    }
+}
+
+impl Psg for YmCapture {
+    type Error = core::convert::Infallible;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Self::Error> {
+        self.registers.set(address, data);
+        if address == 0xD {
+            self.envelope_written = true;
+        }
+        Ok(())
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_player::{FramePlayer, FrameSource, FrameStatus};
+    use crate::instrument::{presets, Instrument};
+    use crate::pitch::{Note, Pitch};
+    use crate::psg_write::PsgWrite;
+    use crate::registers::{Register, R13_UNCHANGED};
+    use crate::sequencer::{Cell, OrderEntry, Pattern, Row, Song};
+    use crate::test_support::FakePsg;
+    use crate::ym_file::YmSong;
+    use crate::Channel;
+
+    const C4: Pitch = Pitch::new(Note::C, 4);
+    const G4: Pitch = Pitch::new(Note::G, 4);
+
+    static BANK: [Instrument; 2] = [presets::LEAD, presets::BASS];
+    static PATTERNS: [Pattern; 2] = [
+        Pattern::new(&[
+            Row::new(Cell::note(C4).instrument(0), Cell::EMPTY, Cell::EMPTY),
+            Row::new(Cell::note(G4), Cell::note(C4).instrument(1), Cell::EMPTY),
+        ]),
+        Pattern::new(&[
+            Row::new(Cell::OFF, Cell::EMPTY, Cell::note(G4).instrument(0)),
+            Row::new(Cell::note(C4), Cell::OFF, Cell::EMPTY),
+        ]),
+    ];
+    static INTRO_THEN_LOOP: Song = Song::new(
+        &PATTERNS,
+        &[OrderEntry::new(0), OrderEntry::new(1), OrderEntry::new(0)],
+    )
+    .looping(1);
+
+    /// The registers after each frame, as `player` plays them onto a chip.
+    fn played<S: FrameSource>(mut player: FramePlayer<S>, frames: usize) -> Vec<[u8; 16]> {
+        let mut psg = FakePsg::new();
+        player.play();
+        (0..frames)
+            .map(|_| {
+                player.tick(&mut psg).unwrap();
+                *psg.registers().values()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn songs_round_trip_frame_for_frame() {
+        let mut sequencer = Sequencer::new(&INTRO_THEN_LOOP, &BANK, FRAME_HERTZ as u32);
+        let mut capture = YmCapture::new().with_master_clock(1_773_400);
+        assert!(capture.record_sequencer(&mut sequencer, 10_000));
+        // Six ticks a row, two rows a pattern, three order entries.
+        let frames = capture.frames().len();
+        assert_eq!(frames, 36);
+        // The loop is the second entry, twelve frames in.
+        assert_eq!(capture.loop_frame(), Some(12));
+
+        let mut file = Vec::new();
+        let length = capture
+            .write_ym6(&mut file, b"Loop", b"Someone", b"Made here")
+            .unwrap();
+        assert_eq!(length, file.len() as u64);
+        let ym = YmSong::new(&file).unwrap();
+        assert!(ym.is_interleaved());
+        assert_eq!(ym.frame_count(), 36);
+        assert_eq!(ym.master_clock(), 1_773_400);
+        assert_eq!(ym.frame_rate(), 50);
+        assert_eq!(ym.loop_frame(), 12);
+        assert_eq!(
+            (ym.name(), ym.author(), ym.comment()),
+            (&b"Loop"[..], &b"Someone"[..], &b"Made here"[..])
+        );
+
+        // Every frame as stored, sentinels and all.
+        let mut source = ym;
+        for (index, expected) in capture.frames().iter().enumerate() {
+            let mut frame = [0; 16];
+            assert_eq!(
+                source.frame(index as u32, &mut frame),
+                Ok(FrameStatus::Ready)
+            );
+            assert_eq!(&frame, expected, "frame {index}");
+        }
+        // And as played, against the sequencer driving a chip, round the
+        // loop and back.
+        let song = YmSong::new(&file).unwrap();
+        let mut sequencer = Sequencer::new(&INTRO_THEN_LOOP, &BANK, FRAME_HERTZ as u32);
+        let mut psg = FakePsg::with_clock(1_773_400);
+        let live: Vec<_> = (0..frames + 24)
+            .map(|_| {
+                sequencer.tick(&mut psg).unwrap();
+                *psg.registers().values()
+            })
+            .collect();
+        assert_eq!(played(song.into_player(50), frames + 24), live);
+    }
+
+    #[test]
+    fn the_envelope_restarts_only_where_it_was_written() {
+        /// Someone else's engine, knowing only [`PsgWrite`], restarting the
+        /// same envelope every other frame.
+        fn engine<W: PsgWrite>(psg: &mut W, frame: u8) -> Result<(), W::Error> {
+            psg.write_register(Register::AFine, frame)?;
+            psg.write_register(Register::ALevel, 0x10)?;
+            if frame.is_multiple_of(2) {
+                psg.write_register(Register::EnvelopeShape, 0x0E)?;
+            }
+            Ok(())
+        }
+
+        let mut capture = YmCapture::new();
+        for frame in 0..6 {
+            engine(&mut capture, frame).unwrap();
+            capture.capture_frame();
+        }
+        let shapes: Vec<_> = capture.frames().iter().map(|frame| frame[0xD]).collect();
+        assert_eq!(
+            shapes,
+            [
+                0x0E,
+                R13_UNCHANGED,
+                0x0E,
+                R13_UNCHANGED,
+                0x0E,
+                R13_UNCHANGED
+            ]
+        );
+
+        let mut file = Vec::new();
+        capture.write_ym6(&mut file, b"", b"", b"").unwrap();
+        let ym = YmSong::new(&file).unwrap();
+        // No loop marked: back to the start.
+        assert_eq!(ym.loop_frame(), 0);
+        let mut psg = FakePsg::new();
+        let mut player = ym.into_player(50);
+        player.play();
+        for frame in 0..6u8 {
+            player.tick(&mut psg).unwrap();
+            let restarts = psg.take_writes().iter().any(|&(address, _)| address == 0xD);
+            assert_eq!(restarts, frame.is_multiple_of(2), "frame {frame}");
+            assert_eq!(psg.registers().value(0x0), frame);
+        }
+    }
+
+    #[test]
+    fn songs_that_stop_end_on_silence() {
+        static ONCE: Song = Song::new(&PATTERNS, &[OrderEntry::new(0)]);
+        let mut sequencer = Sequencer::new(&ONCE, &BANK, FRAME_HERTZ as u32);
+        let mut capture = YmCapture::new();
+        assert!(capture.record_sequencer(&mut sequencer, 10_000));
+        assert_eq!(capture.loop_frame(), None);
+        let last = capture.frames().last().unwrap();
+        for channel in Channel::ALL {
+            assert_eq!(last[channel.level_register() as usize], 0);
+        }
+        // And a limit short of the end says so.
+        let mut sequencer = Sequencer::new(&ONCE, &BANK, FRAME_HERTZ as u32);
+        let mut capture = YmCapture::new();
+        assert!(!capture.record_sequencer(&mut sequencer, 5));
+        assert_eq!(capture.frames().len(), 5);
+    }
+
+    #[test]
+    fn strings_with_a_nul_are_refused() {
+        let capture = YmCapture::new();
+        let mut file = Vec::new();
+        let error = capture.write_ym6(&mut file, b"A\0B", b"", b"").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(file.is_empty());
+    }
+}
@@ -0,0 +1,742 @@
+//! Atari ST music rips in the YM5 and YM6 formats, played straight from
+//! flash.
+//!
+//! `.ym` files are usually LHA-compressed; this reads them once unpacked.
+//! [`YmMetadata`] reads just what comes before the frames, for listing songs
+//! without loading them, and [`YmSong`] the whole file. A file is:
+//!
+//! - `YM5!` or `YM6!`, then `LeOnArD!`
+//! - big-endian: the frame count (4 bytes), song attributes (4), digidrum
+//!   count (2), master clock in Hz (4), frame rate in Hz (2), loop frame (4)
+//!   and the size of any extra data to skip (2)
+//! - each digidrum sample, after its size (4 bytes)
+//! - the song name, author and comment, each ending in a NUL
+//! - sixteen register values per frame: frame by frame, or, with bit 0 of the
+//!   attributes set, interleaved register by register (every frame's R0,
+//!   then every frame's R1, and so on), which packs better
+//! - `End!`
+//!
+//! An R13 of `0xFF` leaves the envelope running, as
+//! [`R13_UNCHANGED`](crate::frame_player::R13_UNCHANGED) does.
+//!
+//! YM6 also uses spare bits for up to two special effects a frame, each
+//! timed by an [MFP timer](crate::mfp):
+//!
+//! | | effect | timer control | timer count |
+//! |---|---|---|---|
+//! | first | R1 bits 7–4 | R6 bits 7–5 | R14 |
+//! | second | R3 bits 7–4 | R8 bits 7–5 | R15 |
+//!
+//! Of an effect's four bits, the low two give the channel, 1 to 3 for A to C
+//! and 0 for no effect, and the high two the kind: SID, digidrum, sinus-SID
+//! or sync buzzer. The channel's level register gives a digidrum's number in
+//! its low five bits, or the level for the others in its low four.
+//! [`YmSong::effects`] picks them out, and
+//! [`YmEffects`](crate::ym_effects::YmEffects) plays them. The bits are
+//! masked off when a frame is written, so a plain player plays the rest of
+//! the song as if they weren't there.
+
+use crate::digidrum::Sample;
+use crate::frame_player::{Frame, FramePlayer, FrameSource, FrameStatus, PlayTime, SourceError};
+use crate::mfp::MfpTimer;
+use crate::parse_error::ParseError;
+use crate::Channel;
+
+const CHECK: &[u8; 8] = b"LeOnArD!";
+const INTERLEAVED: u32 = 1;
+const DRUMS_SIGNED: u32 = 2;
+const DRUMS_4_BIT: u32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YmErrorKind {
+    /// LHA-compressed, as `.ym` files usually are; unpack it first.
+    Compressed,
+    /// Not a YM5 or YM6 file.
+    NotYm,
+    /// The header, samples or strings run past the end of the data.
+    Truncated,
+    /// Everything before the frames reads, but the frames run past the end
+    /// of the data. `usable` of them are whole: those one after another
+    /// that fit, or none if they're interleaved.
+    FramesTruncated { usable: u32 },
+}
+
+/// What is wrong with a YM file, and the byte it was found at.
+pub type YmError = ParseError<YmErrorKind>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum YmVersion {
+    Ym5,
+    Ym6,
+}
+
+/// What a YM6 special effect does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectKind {
+    /// The channel's level squared between its value and 0, as
+    /// [`SidVoice`](crate::sid::SidVoice) plays.
+    Sid,
+    /// A sample played through the channel's level, one value per interrupt.
+    Digidrum,
+    /// The level swept through a sine rather than squared.
+    SinusSid,
+    /// The envelope retriggered on every interrupt, as
+    /// [`SyncBuzzer`](crate::sync_buzzer::SyncBuzzer) plays.
+    SyncBuzzer,
+}
+
+/// One of a YM6 frame's special effects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Effect {
+    pub kind: EffectKind,
+    pub channel: Channel,
+    pub timer: MfpTimer,
+    /// The digidrum's number, or the level for the other kinds.
+    pub value: u8,
+}
+
+/// The two special effects packed into a YM6 frame, with either missing where
+/// its channel bits are 0. Frames from other formats have none as long as
+/// their spare bits are clear.
+pub fn frame_effects(frame: &Frame) -> [Option<Effect>; 2] {
+    [(0x1, 0x6, 0xE), (0x3, 0x8, 0xF)].map(|(code, control, count)| {
+        let code = frame[code] >> 4;
+        let channel = match code & 0b11 {
+            0 => return None,
+            n => Channel::ALL[n as usize - 1],
+        };
+        let kind = match code >> 2 {
+            0 => EffectKind::Sid,
+            1 => EffectKind::Digidrum,
+            2 => EffectKind::SinusSid,
+            _ => EffectKind::SyncBuzzer,
+        };
+        let level = frame[channel.level_register() as usize];
+        Some(Effect {
+            kind,
+            channel,
+            timer: MfpTimer::new(frame[control] >> 5, frame[count]),
+            value: if kind == EffectKind::Digidrum {
+                level & 0x1F
+            } else {
+                level & 0x0F
+            },
+        })
+    })
+}
+
+/// Reads a `length`-byte big-endian number at `position` and moves past it.
+fn number(data: &[u8], position: &mut usize, length: usize) -> Result<u32, YmError> {
+    let bytes = data
+        .get(*position..*position + length)
+        .ok_or(YmError::new(YmErrorKind::Truncated, *position))?;
+    *position += length;
+    Ok(bytes
+        .iter()
+        .fold(0, |value, &byte| value << 8 | byte as u32))
+}
+
+/// Skips `length` bytes at `position`, returning them.
+fn bytes<'a>(data: &'a [u8], position: &mut usize, length: usize) -> Result<&'a [u8], YmError> {
+    let truncated = YmError::new(YmErrorKind::Truncated, *position);
+    let end = position.checked_add(length).ok_or(truncated)?;
+    let bytes = data.get(*position..end).ok_or(truncated)?;
+    *position = end;
+    Ok(bytes)
+}
+
+/// Reads a NUL-terminated string at `position`, without the NUL.
+fn string<'a>(data: &'a [u8], position: &mut usize) -> Result<&'a [u8], YmError> {
+    let truncated = YmError::new(YmErrorKind::Truncated, *position);
+    let rest = data.get(*position..).ok_or(truncated)?;
+    let length = rest.iter().position(|&byte| byte == 0).ok_or(truncated)?;
+    *position += length + 1;
+    Ok(&rest[..length])
+}
+
+/// What a YM5 or YM6 file says about itself, read without its frames.
+///
+/// Everything up to the frames is read and nothing after, so a song browser
+/// can list songs from the first few hundred bytes of each file, sample
+/// data allowing. Borrowed strings come straight from the input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct YmMetadata<'a> {
+    version: YmVersion,
+    frame_count: u32,
+    attributes: u32,
+    master_clock: u32,
+    frame_rate: u16,
+    loop_frame: u32,
+    digidrum_count: u16,
+    digidrums: &'a [u8],
+    name: &'a [u8],
+    author: &'a [u8],
+    comment: &'a [u8],
+    /// Where the frames start.
+    frames_at: usize,
+}
+
+impl<'a> YmMetadata<'a> {
+    /// Reads the header, steps over the digidrums and reads the strings,
+    /// each of which may be empty.
+    pub fn parse(data: &'a [u8]) -> Result<YmMetadata<'a>, YmError> {
+        // An LHA header has its method, such as `-lh5-`, two bytes in.
+        if data.get(2..5) == Some(b"-lh") {
+            return Err(YmError::new(YmErrorKind::Compressed, 0));
+        }
+        let version = match data.get(..4) {
+            Some(b"YM5!") => YmVersion::Ym5,
+            Some(b"YM6!") => YmVersion::Ym6,
+            Some(_) => return Err(YmError::new(YmErrorKind::NotYm, 0)),
+            None => return Err(YmError::new(YmErrorKind::Truncated, 0)),
+        };
+        let mut position = 4;
+        if bytes(data, &mut position, CHECK.len())? != CHECK {
+            return Err(YmError::new(YmErrorKind::NotYm, 4));
+        }
+        let frame_count = number(data, &mut position, 4)?;
+        let attributes = number(data, &mut position, 4)?;
+        let digidrum_count = number(data, &mut position, 2)? as u16;
+        let master_clock = number(data, &mut position, 4)?;
+        let frame_rate = number(data, &mut position, 2)? as u16;
+        // Players go back to the start from a loop frame past the end.
+        let loop_frame = match number(data, &mut position, 4)? {
+            frame if frame < frame_count => frame,
+            _ => 0,
+        };
+        let extra = number(data, &mut position, 2)?;
+        bytes(data, &mut position, extra as usize)?;
+
+        let digidrums_start = position;
+        for _ in 0..digidrum_count {
+            let size = number(data, &mut position, 4)?;
+            bytes(data, &mut position, size as usize)?;
+        }
+        let digidrums = &data[digidrums_start..position];
+
+        let name = string(data, &mut position)?;
+        let author = string(data, &mut position)?;
+        let comment = string(data, &mut position)?;
+        Ok(YmMetadata {
+            version,
+            frame_count,
+            attributes,
+            master_clock,
+            frame_rate,
+            loop_frame,
+            digidrum_count,
+            digidrums,
+            name,
+            author,
+            comment,
+            frames_at: position,
+        })
+    }
+
+    pub fn version(&self) -> YmVersion {
+        self.version
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    pub fn is_interleaved(&self) -> bool {
+        self.attributes & INTERLEAVED != 0
+    }
+
+    /// The clock the song was written for, usually 2 MHz on the ST, for the
+    /// driver's `set_master_clock`.
+    pub fn master_clock(&self) -> u32 {
+        self.master_clock
+    }
+
+    /// Frames a second, usually 50.
+    pub fn frame_rate(&self) -> u16 {
+        self.frame_rate
+    }
+
+    /// The frame rate in millihertz, for [`FramePlayer::with_rate`].
+    pub fn frame_millihertz(&self) -> u32 {
+        self.frame_rate as u32 * 1000
+    }
+
+    /// How long one pass through the song lasts.
+    pub fn duration(&self) -> PlayTime {
+        PlayTime::from_frames(self.frame_count as u64, self.frame_millihertz())
+    }
+
+    /// The frame to go back to at the end.
+    pub fn loop_frame(&self) -> u32 {
+        self.loop_frame
+    }
+
+    /// How far into the song the loop goes back to.
+    pub fn loop_start(&self) -> PlayTime {
+        PlayTime::from_frames(self.loop_frame as u64, self.frame_millihertz())
+    }
+
+    pub fn digidrum_count(&self) -> u16 {
+        self.digidrum_count
+    }
+
+    /// Where in the file the frames start, for reading them from storage.
+    pub fn frames_at(&self) -> usize {
+        self.frames_at
+    }
+
+    /// The song name, in the ST's character set, which is ASCII as far as it
+    /// goes.
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    pub fn author(&self) -> &'a [u8] {
+        self.author
+    }
+
+    pub fn comment(&self) -> &'a [u8] {
+        self.comment
+    }
+}
+
+/// A YM5 or YM6 file, checked and ready to play as a [`FrameSource`].
+///
+/// Nothing is copied: frames are read from the file as they play, picking
+/// interleaved registers out one by one, so no buffer is needed whatever the
+/// layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct YmSong<'a> {
+    metadata: YmMetadata<'a>,
+    frames: &'a [u8],
+}
+
+impl<'a> YmSong<'a> {
+    /// Checks the header and finds everything in `data`, so playback can't
+    /// fail later.
+    pub fn new(data: &'a [u8]) -> Result<YmSong<'a>, YmError> {
+        let metadata = YmMetadata::parse(data)?;
+        let mut position = metadata.frames_at;
+        let available = data.len().saturating_sub(position);
+        let frames = match (metadata.frame_count as usize).checked_mul(16) {
+            Some(length) if length <= available => bytes(data, &mut position, length)?,
+            _ => {
+                let usable = match metadata.is_interleaved() {
+                    true => 0,
+                    false => (available / 16) as u32,
+                };
+                let kind = YmErrorKind::FramesTruncated { usable };
+                return Err(YmError::new(kind, position));
+            }
+        };
+        Ok(YmSong { metadata, frames })
+    }
+
+    /// The header and strings, which the song's other accessors also read.
+    pub fn metadata(&self) -> &YmMetadata<'a> {
+        &self.metadata
+    }
+
+    pub fn version(&self) -> YmVersion {
+        self.metadata.version
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.metadata.frame_count
+    }
+
+    pub fn is_interleaved(&self) -> bool {
+        self.metadata.is_interleaved()
+    }
+
+    /// The clock the song was written for, usually 2 MHz on the ST, for the
+    /// driver's `set_master_clock`.
+    pub fn master_clock(&self) -> u32 {
+        self.metadata.master_clock
+    }
+
+    /// Frames a second, usually 50.
+    pub fn frame_rate(&self) -> u16 {
+        self.metadata.frame_rate
+    }
+
+    /// The frame rate in millihertz, for [`FramePlayer::with_rate`].
+    pub fn frame_millihertz(&self) -> u32 {
+        self.metadata.frame_millihertz()
+    }
+
+    /// The frame to go back to at the end.
+    pub fn loop_frame(&self) -> u32 {
+        self.metadata.loop_frame
+    }
+
+    pub fn digidrum_count(&self) -> u16 {
+        self.metadata.digidrum_count
+    }
+
+    /// The sample data of digidrum `index`.
+    pub fn digidrum(&self, index: u16) -> Option<&'a [u8]> {
+        if index >= self.metadata.digidrum_count {
+            return None;
+        }
+        let mut position = 0;
+        for _ in 0..index {
+            let size = number(self.metadata.digidrums, &mut position, 4).ok()?;
+            position += size as usize;
+        }
+        let size = number(self.metadata.digidrums, &mut position, 4).ok()?;
+        self.metadata
+            .digidrums
+            .get(position..position + size as usize)
+    }
+
+    /// Digidrum `index`, read as the song's attributes say its samples are
+    /// stored: 4-bit levels, or signed or unsigned 8-bit amplitudes.
+    pub fn digidrum_sample(&self, index: u16) -> Option<Sample<'a>> {
+        let data = self.digidrum(index)?;
+        Some(if self.metadata.attributes & DRUMS_4_BIT != 0 {
+            Sample::levels(data)
+        } else if self.metadata.attributes & DRUMS_SIGNED != 0 {
+            Sample::signed_pcm(data)
+        } else {
+            Sample::pcm(data)
+        })
+    }
+
+    /// Frame `index`'s special effects. YM5 songs have none.
+    pub fn effects(&self, index: u32) -> [Option<Effect>; 2] {
+        if self.metadata.version != YmVersion::Ym6 || index >= self.metadata.frame_count {
+            return [None, None];
+        }
+        let mut frame = [0; 16];
+        self.read(index, &mut frame);
+        frame_effects(&frame)
+    }
+
+    fn read(&self, index: u32, frame: &mut Frame) {
+        let (index, count) = (index as usize, self.metadata.frame_count as usize);
+        let interleaved = self.is_interleaved();
+        for (register, value) in frame.iter_mut().enumerate() {
+            *value = if interleaved {
+                self.frames[register * count + index]
+            } else {
+                self.frames[index * 16 + register]
+            };
+        }
+    }
+
+    /// The song name, in the ST's character set, which is ASCII as far as it
+    /// goes.
+    pub fn name(&self) -> &'a [u8] {
+        self.metadata.name
+    }
+
+    pub fn author(&self) -> &'a [u8] {
+        self.metadata.author
+    }
+
+    pub fn comment(&self) -> &'a [u8] {
+        self.metadata.comment
+    }
+
+    /// A player for the song, looping as YM songs do, ticked `tick_hertz`
+    /// times a second.
+    pub fn into_player(self, tick_hertz: u32) -> FramePlayer<YmSong<'a>> {
+        let (loop_frame, rate) = (self.loop_frame(), self.frame_millihertz());
+        FramePlayer::new(self)
+            .with_loop(loop_frame)
+            .with_rate(rate, tick_hertz)
+    }
+}
+
+impl FrameSource for YmSong<'_> {
+    fn frame(&mut self, index: u32, frame: &mut Frame) -> Result<FrameStatus, SourceError> {
+        if index >= self.metadata.frame_count {
+            return Ok(FrameStatus::End);
+        }
+        self.read(index, frame);
+        Ok(FrameStatus::Ready)
+    }
+
+    fn frame_count(&self) -> Option<u32> {
+        Some(self.metadata.frame_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_player::{PlayStatus, R13_UNCHANGED};
+    use crate::psg::Psg;
+    use crate::test_support::{mangled, FakePsg};
+
+    const HEADER: usize = 34;
+    const LENGTH: usize = HEADER + 6 + 15 + 3 * 16 + 4;
+
+    static FRAMES: [Frame; 3] = [
+        [100, 1, 0, 0, 0, 0, 0, 0x3E, 15, 0, 0, 0, 0, 0x0E, 0, 0],
+        [
+            90,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0x3E,
+            14,
+            0,
+            0,
+            0,
+            0,
+            R13_UNCHANGED,
+            0,
+            0,
+        ],
+        [
+            80,
+            1,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0x3E,
+            13,
+            0,
+            0,
+            0,
+            0,
+            R13_UNCHANGED,
+            0,
+            0,
+        ],
+    ];
+
+    /// A YM5 file of three frames at 50 Hz and 1 MHz, looping to the second,
+    /// with one two-byte digidrum.
+    const fn fixture(interleaved: bool) -> [u8; LENGTH] {
+        let mut file = [0; LENGTH];
+        let mut header: [u8; HEADER + 6 + 15] = *b"YM5!LeOnArD!\
+            \x00\x00\x00\x03\x00\x00\x00\x00\x00\x01\x00\x0F\x42\x40\x00\x32\
+            \x00\x00\x00\x01\x00\x00\
+            \x00\x00\x00\x02\x80\x7F\
+            Tiny\0Me\0ripped\0";
+        if interleaved {
+            header[19] = 1;
+        }
+        let mut i = 0;
+        while i < header.len() {
+            file[i] = header[i];
+            i += 1;
+        }
+        let mut n = 0;
+        while n < 48 {
+            let (frame, register) = (n / 16, n % 16);
+            let at = if interleaved { register * 3 + frame } else { n };
+            file[header.len() + at] = FRAMES[frame][register];
+            n += 1;
+        }
+        let end = *b"End!";
+        let mut i = 0;
+        while i < 4 {
+            file[LENGTH - 4 + i] = end[i];
+            i += 1;
+        }
+        file
+    }
+
+    static PLAIN: [u8; LENGTH] = fixture(false);
+    static INTERLEAVED_FILE: [u8; LENGTH] = fixture(true);
+
+    #[test]
+    fn reads_both_layouts_and_the_header() {
+        for (data, interleaved) in [(&PLAIN, false), (&INTERLEAVED_FILE, true)] {
+            let mut song = YmSong::new(data).unwrap();
+            assert_eq!(song.version(), YmVersion::Ym5);
+            assert_eq!(song.is_interleaved(), interleaved);
+            assert_eq!(
+                (song.frame_count(), song.frame_rate(), song.master_clock()),
+                (3, 50, 1_000_000)
+            );
+            assert_eq!(song.loop_frame(), 1);
+            assert_eq!(
+                (song.name(), song.author(), song.comment()),
+                (&b"Tiny"[..], &b"Me"[..], &b"ripped"[..])
+            );
+            assert_eq!(song.digidrum(0), Some(&[0x80, 0x7F][..]));
+            assert_eq!(song.digidrum(1), None);
+            let mut frame = [0; 16];
+            for (index, expected) in FRAMES.iter().enumerate() {
+                assert_eq!(song.frame(index as u32, &mut frame), Ok(FrameStatus::Ready));
+                assert_eq!(&frame, expected);
+            }
+            assert_eq!(song.frame(3, &mut frame), Ok(FrameStatus::End));
+        }
+    }
+
+    #[test]
+    fn plays_and_loops_to_the_loop_frame() {
+        let mut psg = FakePsg::new();
+        let mut player = YmSong::new(&INTERLEAVED_FILE).unwrap().into_player(50);
+        player.play();
+        let mut periods = [0; 5];
+        for period in &mut periods {
+            player.tick(&mut psg).unwrap();
+            *period = psg.registers().value(0x0);
+        }
+        assert_eq!(periods, [100, 90, 80, 90, 80]);
+        assert_eq!(player.tick(&mut psg), Ok(PlayStatus::Looped));
+        assert_eq!(player.loops_completed(), 2);
+    }
+
+    #[test]
+    fn metadata_needs_nothing_past_the_strings() {
+        // Three frames at 50 Hz, looping from the second.
+        for data in [&PLAIN, &INTERLEAVED_FILE] {
+            let metadata = YmMetadata::parse(&data[..HEADER + 6 + 15]).unwrap();
+            assert_eq!(metadata, *YmSong::new(data).unwrap().metadata());
+            assert_eq!(
+                (metadata.name(), metadata.author(), metadata.comment()),
+                (&b"Tiny"[..], &b"Me"[..], &b"ripped"[..])
+            );
+            assert_eq!(metadata.duration(), PlayTime::from_millis(60));
+            assert_eq!(metadata.loop_start(), PlayTime::from_millis(20));
+            for length in 0..HEADER + 6 + 15 {
+                let error = YmMetadata::parse(&data[..length]).unwrap_err();
+                assert_eq!(error.kind, YmErrorKind::Truncated);
+                assert!(error.offset <= length);
+            }
+        }
+        let data = crate::test_support::ym_file(b"YM6!", &[], &[[0; 16]; 100]);
+        let metadata = YmMetadata::parse(&data).unwrap();
+        assert_eq!(metadata.version(), YmVersion::Ym6);
+        assert_eq!(
+            (metadata.name(), metadata.author(), metadata.comment()),
+            (&b""[..], &b""[..], &b""[..])
+        );
+        assert_eq!(metadata.duration().seconds, 2);
+    }
+
+    #[test]
+    fn rejects_other_and_broken_files() {
+        const FRAMES_AT: usize = HEADER + 6 + 15;
+        for length in 0..FRAMES_AT {
+            let error = YmSong::new(&PLAIN[..length]).unwrap_err();
+            assert_eq!(error.kind, YmErrorKind::Truncated);
+            assert!(error.offset <= length);
+        }
+        for length in FRAMES_AT..LENGTH - 4 {
+            let usable = ((length - FRAMES_AT) / 16) as u32;
+            let error = YmError::new(YmErrorKind::FramesTruncated { usable }, FRAMES_AT);
+            assert_eq!(YmSong::new(&PLAIN[..length]), Err(error));
+            let error = YmError::new(YmErrorKind::FramesTruncated { usable: 0 }, FRAMES_AT);
+            assert_eq!(YmSong::new(&INTERLEAVED_FILE[..length]), Err(error));
+        }
+        // The end marker isn't needed.
+        assert!(YmSong::new(&PLAIN[..LENGTH - 4]).is_ok());
+        let mut data = PLAIN;
+        data[3] = b'?';
+        let not_ym = YmError::new(YmErrorKind::NotYm, 0);
+        assert_eq!(YmSong::new(&data), Err(not_ym));
+        data = PLAIN;
+        data[11] = b'?';
+        assert_eq!(YmSong::new(&data), Err(YmError::new(YmErrorKind::NotYm, 4)));
+        assert_eq!(
+            YmSong::new(b"\x1f\x8c-lh5-\x00\x00"),
+            Err(YmError::new(YmErrorKind::Compressed, 0))
+        );
+    }
+
+    #[test]
+    fn damaged_files_fail_cleanly() {
+        for file in [&PLAIN, &INTERLEAVED_FILE] {
+            for data in mangled(file) {
+                let song = match YmSong::new(&data) {
+                    Ok(song) => song,
+                    Err(error) => {
+                        assert!(error.offset <= data.len());
+                        continue;
+                    }
+                };
+                assert!(song.loop_frame() < song.frame_count().max(1));
+                for index in 0..song.frame_count() {
+                    song.effects(index);
+                }
+                for index in 0..song.digidrum_count() {
+                    song.digidrum_sample(index);
+                }
+                let mut psg = FakePsg::new();
+                let mut player = song.into_player(50);
+                player.play();
+                for _ in 0..10 {
+                    player.tick(&mut psg).unwrap();
+                }
+            }
+        }
+        // A loop past the end goes back to the start.
+        let mut data = PLAIN;
+        data[28..32].copy_from_slice(&3u32.to_be_bytes());
+        assert_eq!(YmSong::new(&data).unwrap().loop_frame(), 0);
+    }
+
+    #[test]
+    fn picks_out_both_effect_slots() {
+        let mut frame = [0; 16];
+        assert_eq!(frame_effects(&frame), [None, None]);
+        // A buzzer on C in the first slot and a SID on B in the second.
+        frame[0x1] = 0xF3;
+        frame[0x6] = 0xE0 | 0x1F;
+        frame[0xE] = 100;
+        frame[0x3] = 0x20;
+        frame[0x8] = 0x40 | 0x1E;
+        frame[0x9] = 0x17;
+        frame[0xA] = 0x1C;
+        assert_eq!(
+            frame_effects(&frame),
+            [
+                Some(Effect {
+                    kind: EffectKind::SyncBuzzer,
+                    channel: Channel::C,
+                    timer: MfpTimer::new(7, 100),
+                    value: 0x0C,
+                }),
+                Some(Effect {
+                    kind: EffectKind::Sid,
+                    channel: Channel::B,
+                    timer: MfpTimer::new(2, 0),
+                    value: 0x07,
+                }),
+            ]
+        );
+        // A digidrum takes five bits of the level for its number.
+        frame[0x1] = 0x50;
+        frame[0x3] = 0x90;
+        let [drum, sinus] = frame_effects(&frame);
+        assert_eq!(
+            drum.map(|effect| (effect.kind, effect.channel, effect.value)),
+            Some((EffectKind::Digidrum, Channel::A, 0x1E))
+        );
+        assert_eq!(sinus.map(|effect| effect.kind), Some(EffectKind::SinusSid));
+
+        // Only YM6 songs have effects.
+        let mut data = PLAIN;
+        data[HEADER + 6 + 15 + 16 + 1] = 0x51;
+        let song = YmSong::new(&data).unwrap();
+        assert_eq!(song.effects(1), [None, None]);
+        data[2] = b'6';
+        let song = YmSong::new(&data).unwrap();
+        assert_eq!(song.effects(0), [None, None]);
+        assert_eq!(
+            song.effects(1)[0],
+            Some(Effect {
+                kind: EffectKind::Digidrum,
+                channel: Channel::A,
+                timer: MfpTimer::new(0, 0),
+                value: 14,
+            })
+        );
+        assert_eq!(song.effects(3), [None, None]);
+        assert_eq!(song.digidrum_sample(0), Some(Sample::pcm(&[0x80, 0x7F])));
+    }
+}
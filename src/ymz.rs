@@ -0,0 +1,156 @@
+//! A driver for Yamaha's YMZ294 and YMZ284, the YM2149's sound generator
+//! behind a conventional microprocessor bus.
+//!
+//! Instead of BDIR and BC1 they take A0, /WR and /CS: a write with A0 low
+//! latches an address and one with A0 high writes the register latched,
+//! each latched as /WR rises. [`YmzBus`] strobes those and is a [`Psg`], so
+//! every player, effect and helper works on these chips as on the YM2149.
+//! Neither chip has the I/O ports, so R14 and R15 are never written.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{OutputPin, PinState};
+
+use crate::chip_profile::ChipProfile;
+use crate::psg::Psg;
+use crate::registers::{Registers, REGISTER_MASKS};
+use crate::{tuning, ChipKind, Error};
+
+/// A YMZ294 or YMZ284 on A0, /WR, an optional /CS, and D0 to D7.
+pub struct YmzBus<P, Delay> {
+    a0: P,
+    wr: P,
+    /// `None` with /CS tied low.
+    cs: Option<P>,
+    data: [P; 8],
+    delay: Delay,
+    registers: Registers,
+    master_clock: u32,
+    clock_halved: bool,
+}
+
+impl<P, Delay> YmzBus<P, Delay>
+where
+    P: OutputPin,
+    Delay: DelayNs,
+{
+    /// Takes the pins, leaving /WR and /CS high so the chip ignores the bus
+    /// until the first write. Pass `None` for `cs` with /CS tied low.
+    pub fn new(
+        a0: P,
+        wr: P,
+        cs: Option<P>,
+        data: [P; 8],
+        delay: Delay,
+    ) -> Result<YmzBus<P, Delay>, Error<P>> {
+        let mut bus = YmzBus {
+            a0,
+            wr,
+            cs,
+            data,
+            delay,
+            registers: Registers::new(),
+            master_clock: tuning::DEFAULT_MASTER_CLOCK,
+            clock_halved: false,
+        };
+        bus.wr.set_high().map_err(Error::PinError)?;
+        bus.select(false)?;
+        Ok(bus)
+    }
+
+    /// Tells the driver the frequency of the clock feeding the chip. Defaults
+    /// to 2 MHz.
+    pub fn set_master_clock(&mut self, hz: u32) {
+        self.master_clock = hz;
+    }
+
+    /// Tells the driver how the YMZ294's 4/6 pin is tied: set for the faster
+    /// of the two clocks it takes, the chip halves the clock it is fed, and
+    /// pitch conversions have to know.
+    pub fn set_clock_halved(&mut self, halved: bool) {
+        self.clock_halved = halved;
+    }
+
+    fn select(&mut self, selected: bool) -> Result<(), Error<P>> {
+        match &mut self.cs {
+            Some(cs) => cs
+                .set_state(PinState::from(!selected))
+                .map_err(Error::PinError),
+            None => Ok(()),
+        }
+    }
+
+    /// Puts `byte` on the bus with A0 at `a0`, then strobes /WR.
+    fn strobe(&mut self, a0: bool, byte: u8) -> Result<(), Error<P>> {
+        self.a0
+            .set_state(PinState::from(a0))
+            .map_err(Error::PinError)?;
+        for (bit, pin) in self.data.iter_mut().enumerate() {
+            let state = PinState::from(byte >> bit & 1 == 1);
+            pin.set_state(state).map_err(Error::PinError)?;
+        }
+        self.wr.set_low().map_err(Error::PinError)?;
+        self.delay.delay_us(1);
+        self.wr.set_high().map_err(Error::PinError)?;
+        self.delay.delay_us(1);
+        Ok(())
+    }
+
+    /// Writes `data` to `address`: the address with A0 low, then the data
+    /// with A0 high, with /CS low for both. Writes to R14 and R15 are dropped,
+    /// and the mixer's port direction bits kept clear.
+    pub fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Error<P>> {
+        const PROFILE: ChipProfile = ChipProfile::YMZ294;
+        if !PROFILE.has_register(address) {
+            return Ok(());
+        }
+        let data = match address {
+            0x7 => data & (0x3F | PROFILE.direction_bits()),
+            _ => data,
+        };
+        self.select(true)?;
+        self.strobe(false, address)?;
+        self.strobe(true, data)?;
+        self.select(false)?;
+        self.registers.set(address, data);
+        Ok(())
+    }
+}
+
+impl<P, Delay> Psg for YmzBus<P, Delay>
+where
+    P: OutputPin,
+    Delay: DelayNs,
+{
+    type Error = Error<P>;
+
+    fn set_register_value(&mut self, address: u8, data: u8) -> Result<(), Error<P>> {
+        YmzBus::set_register_value(self, address, data)
+    }
+
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    /// The ports' registers dropped and their direction bits kept clear, as
+    /// [`YmzBus::set_register_value`] does.
+    fn register_mask(&self, address: u8) -> u8 {
+        const PROFILE: ChipProfile = ChipProfile::YMZ294;
+        match address {
+            _ if !PROFILE.has_register(address) => 0,
+            0x7 => 0x3F | PROFILE.direction_bits(),
+            _ => REGISTER_MASKS[address as usize],
+        }
+    }
+
+    /// The clock the chip runs at: halved if the 4/6 pin says so.
+    fn master_clock(&self) -> u32 {
+        match self.clock_halved {
+            true => self.master_clock / 2,
+            false => self.master_clock,
+        }
+    }
+
+    fn chip_kind(&self) -> ChipKind {
+        ChipKind::Ymz294
+    }
+}